@@ -27,36 +27,60 @@ fn main() {
     // };
     // vec![sky_shader]
     //}
-    println!("cargo:rerun-if-changed=./rust_shader_builder");
+    let root = env!("CARGO_MANIFEST_DIR");
+    println!("cargo:rerun-if-changed=build.rs");
+    // Every shader depends on these, so a change to either rebuilds them all.
+    for lib in ["../shader_lib", "../../../crates/shader_objects"] {
+        println!("cargo:rerun-if-changed={root}/{lib}");
+    }
 
+    let mut failed = vec![];
     // TODO: just use WalkDir or parse Cargo.toml maybe?
     for shader in [
         "skybox_vertex",
         "skybox_fragment",
         "default_vertex",
+        "skinned_vertex",
         "default_fragment",
         "debug_mesh_vertex",
         "debug_mesh_fragment",
+        "egui_vertex",
+        "egui_fragment",
+        "gbuffer_fragment",
+        "fullscreen_vertex",
+        "deferred_lighting_fragment",
     ]
     .iter()
     {
-        println!("cargo:rerun-if-changed=./{}", shader);
-        let module_path = SpirvBuilder::new(
-            format!("{}/../shaders/{}", env!("CARGO_MANIFEST_DIR"), shader),
+        println!("cargo:rerun-if-changed={root}/../shaders/{shader}");
+        let spv_path = format!("{root}/../spv/{shader}.spv");
+        let error_path = format!("{root}/../spv/{shader}.err");
+        let built = SpirvBuilder::new(
+            format!("{root}/../shaders/{shader}"),
             "spirv-unknown-spv1.0",
         )
         .print_metadata(MetadataPrintout::Full)
-        .build()
-        .unwrap()
-        .module
-        .unwrap_single()
-        .to_path_buf();
-
-        println!("cargo-warning={:?}", module_path);
-        std::fs::copy(
-            &module_path,
-            format!("{}/../spv/{}.spv", env!("CARGO_MANIFEST_DIR"), shader),
-        )
-        .unwrap();
+        .build();
+        match built {
+            Ok(result) => {
+                let module_path = result.module.unwrap_single().to_path_buf();
+                std::fs::copy(&module_path, &spv_path).unwrap();
+                let _ = std::fs::remove_file(&error_path);
+            }
+            // The previous SPIR-V is left in place, and the error next to it
+            // for the asset loader and `nshell --diagnose` to report.
+            Err(err) => {
+                println!("cargo:warning={shader} failed to build: {err}");
+                std::fs::write(
+                    &error_path,
+                    format!("{err}, see the output of `cargo xtask build-shaders`\n"),
+                )
+                .unwrap();
+                failed.push(*shader);
+            }
+        }
+    }
+    if !failed.is_empty() {
+        panic!("shaders failed to build: {}", failed.join(", "));
     }
 }
@@ -0,0 +1,39 @@
+use spirv_std::glam::{Vec3, Vec4};
+#[cfg(target_arch = "spirv")]
+use spirv_std::num_traits::Float;
+
+fn linear_from_srgb_channel(c: f32) -> f32 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn srgb_from_linear_channel(c: f32) -> f32 {
+    if c <= 0.0031308 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Decode an sRGB color to linear, leaving alpha as it is.
+pub fn linear_from_srgb(color: Vec4) -> Vec4 {
+    Vec3::new(
+        linear_from_srgb_channel(color.x),
+        linear_from_srgb_channel(color.y),
+        linear_from_srgb_channel(color.z),
+    )
+    .extend(color.w)
+}
+
+/// Encode a linear color as sRGB, leaving alpha as it is.
+pub fn srgb_from_linear(color: Vec4) -> Vec4 {
+    Vec3::new(
+        srgb_from_linear_channel(color.x),
+        srgb_from_linear_channel(color.y),
+        srgb_from_linear_channel(color.z),
+    )
+    .extend(color.w)
+}
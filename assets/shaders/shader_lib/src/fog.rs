@@ -0,0 +1,13 @@
+use shader_objects::UniformBuffer;
+use spirv_std::glam::Vec4;
+
+/// How much of a fragment's own color is visible at `distance`, from 1 before
+/// the fog starts to 0 past its end.
+pub fn visibility(ubo: &UniformBuffer, distance: f32) -> f32 {
+    ((ubo.fog_end - distance) / (ubo.fog_end - ubo.fog_start)).clamp(0.0, 1.0)
+}
+
+/// Blend `color` into the fog color by `distance`.
+pub fn apply(ubo: &UniformBuffer, color: Vec4, distance: f32) -> Vec4 {
+    ubo.fog_color.lerp(color, visibility(ubo, distance))
+}
@@ -0,0 +1,17 @@
+//! Helpers shared by the shader crates, so sampling, lighting, reflections,
+//! shadows and fog are written once. Changes here rebuild every shader, see
+//! `rust_shader_builder`.
+//!
+//! Arrays of lights and probes are looped over by index rather than iterated,
+//! since slice iterators don't compile to SPIR-V.
+
+#![cfg_attr(target_arch = "spirv", no_std)]
+// HACK(eddyb) can't easily see warnings otherwise from `spirv-builder` builds.
+#![deny(warnings)]
+
+pub mod color;
+pub mod fog;
+pub mod lighting;
+pub mod reflection;
+pub mod sampling;
+pub mod shadow;
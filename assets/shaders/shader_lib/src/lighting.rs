@@ -0,0 +1,82 @@
+use shader_objects::{
+    ClusterGrid, ClusteredLight, ClusteredLights, Light, UniformBuffer, CLUSTERS_X, CLUSTERS_Y,
+    CLUSTERS_Z, LIGHT_SPOT, MAX_LIGHTS,
+};
+use spirv_std::glam::Vec4;
+#[cfg(target_arch = "spirv")]
+use spirv_std::num_traits::Float;
+
+/// Lambertian diffuse light from the directional or spot light `light` on a
+/// fragment at `world_pos` facing `normal`. Spot lights fade out to nothing
+/// at their radius, and towards the edge of their cone.
+pub fn diffuse(light: &Light, world_pos: Vec4, normal: Vec4) -> Vec4 {
+    let direction = light.direction.truncate();
+    if light.kind != LIGHT_SPOT {
+        let intensity = (-direction).dot(normal.truncate()).max(0.0);
+        return intensity * light.color;
+    }
+    let to_light = light.pos_radius.truncate() - world_pos.truncate();
+    let distance = to_light.length();
+    let to_light = to_light / distance;
+    let falloff = (1.0 - distance / light.pos_radius.w).clamp(0.0, 1.0);
+    let (inner, outer) = (light.cone.x, light.cone.y);
+    let cone = ((-to_light).dot(direction) - outer) / (inner - outer).max(1e-4);
+    let intensity = to_light.dot(normal.truncate()).max(0.0);
+    intensity * falloff * falloff * cone.clamp(0.0, 1.0) * light.color
+}
+
+/// Diffuse light from the directional and spot lights of `ubo` on a fragment.
+/// Only `shadow` of the light casting shadows reaches it, see
+/// `shadow::visibility`.
+pub fn diffuse_lights(ubo: &UniformBuffer, world_pos: Vec4, normal: Vec4, shadow: f32) -> Vec4 {
+    let mut color = Vec4::ZERO;
+    let count = (ubo.light_count as usize).min(MAX_LIGHTS);
+    for i in 0..count {
+        let light = &ubo.lights[i];
+        let lit = if light.shadowed != 0 { shadow } else { 1.0 };
+        color += lit * diffuse(light, world_pos, normal);
+    }
+    color
+}
+
+/// The cluster holding the fragment at `frag_coord`, whose `w` is one over
+/// its view depth.
+pub fn cluster_index(grid: &ClusterGrid, frag_coord: Vec4) -> usize {
+    let x = ((frag_coord.x - grid.viewport.x) / grid.viewport.z) as usize;
+    let y = ((frag_coord.y - grid.viewport.y) / grid.viewport.w) as usize;
+    let depth = 1.0 / frag_coord.w;
+    let slice = (depth.ln() * grid.depth.x + grid.depth.y).max(0.0) as usize;
+    (slice.min(CLUSTERS_Z - 1) * CLUSTERS_Y + y.min(CLUSTERS_Y - 1)) * CLUSTERS_X
+        + x.min(CLUSTERS_X - 1)
+}
+
+/// Diffuse light from the point light `light` on a fragment at `world_pos`
+/// facing `normal`, fading out to nothing at the light's radius.
+pub fn point(light: &ClusteredLight, world_pos: Vec4, normal: Vec4) -> Vec4 {
+    let to_light = light.pos_radius.truncate() - world_pos.truncate();
+    let distance = to_light.length();
+    let falloff = (1.0 - distance / light.pos_radius.w).clamp(0.0, 1.0);
+    let intensity = (to_light / distance).dot(normal.truncate()).max(0.0);
+    intensity * falloff * falloff * light.color
+}
+
+/// Diffuse light from the point lights in the cluster of a fragment.
+pub fn clustered_lights(
+    lights: &ClusteredLights,
+    frag_coord: Vec4,
+    world_pos: Vec4,
+    normal: Vec4,
+) -> Vec4 {
+    let cluster = lights.clusters[cluster_index(&lights.grid, frag_coord)];
+    let offset = (cluster & 0xffff) as usize;
+    let count = (cluster >> 16) as usize;
+    let mut color = Vec4::ZERO;
+    for i in offset..offset + count {
+        color += point(
+            &lights.lights[lights.indices[i] as usize],
+            world_pos,
+            normal,
+        );
+    }
+    color
+}
@@ -0,0 +1,39 @@
+use shader_objects::{cube_face_uv, ReflectionProbes, MAX_REFLECTION_PROBES};
+use spirv_std::glam::Vec4;
+
+use crate::sampling::{self, Texture2dArray};
+
+/// Blend what the nearest reflection probe sees reflected off a fragment at
+/// `world_pos` facing `normal` into its `color`, by `reflectivity`. The
+/// reflection fades out towards the edge of the probe's radius, and fragments
+/// outside every probe's radius keep their color.
+pub fn blend_nearest_probe(
+    probes: &ReflectionProbes,
+    faces: &Texture2dArray,
+    color: Vec4,
+    reflectivity: f32,
+    world_pos: Vec4,
+    normal: Vec4,
+) -> Vec4 {
+    let mut nearest = MAX_REFLECTION_PROBES;
+    let mut nearest_distance = f32::MAX;
+    for i in 0..MAX_REFLECTION_PROBES {
+        let probe = probes.probes[i];
+        let distance = (probe.truncate() - world_pos.truncate()).length();
+        if (i as u32) < probes.count && distance < probe.w && distance < nearest_distance {
+            nearest = i;
+            nearest_distance = distance;
+        }
+    }
+    if nearest == MAX_REFLECTION_PROBES || reflectivity <= 0.0 {
+        return color;
+    }
+
+    let incident = (world_pos - probes.eye).truncate().normalize();
+    let normal = normal.truncate().normalize();
+    let reflected = incident - 2.0 * incident.dot(normal) * normal;
+    let (face, uv) = cube_face_uv(reflected);
+    let reflection = sampling::sample_layer(faces, uv, (nearest * 6 + face) as u32);
+    let fade = 1.0 - nearest_distance / probes.probes[nearest].w;
+    color.lerp(reflection, (reflectivity * fade).clamp(0.0, 1.0))
+}
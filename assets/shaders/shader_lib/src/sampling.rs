@@ -0,0 +1,26 @@
+use spirv_std::glam::{Vec2, Vec4};
+use spirv_std::image::SampledImage;
+use spirv_std::Image;
+
+/// A 2D color texture and its sampler, as models' diffuse maps are bound.
+pub type Texture2d = SampledImage<Image!(2D, type=f32, sampled, depth=false)>;
+
+/// Sample `texture` at `uv`, with implicit level of detail.
+pub fn sample(texture: &Texture2d, uv: Vec2) -> Vec4 {
+    texture.sample(uv)
+}
+
+/// Sample `texture` at `uv` at level of detail `lod`, such as a shadow map,
+/// which has a single level.
+pub fn sample_lod(texture: &Texture2d, uv: Vec2, lod: f32) -> Vec4 {
+    texture.sample_by_lod(uv, lod)
+}
+
+/// Layers of 2D color images sampled together, as reflection probe faces are
+/// bound.
+pub type Texture2dArray = SampledImage<Image!(2D, type=f32, sampled, arrayed, depth=false)>;
+
+/// Sample `layer` of `texture` at `uv`, with implicit level of detail.
+pub fn sample_layer(texture: &Texture2dArray, uv: Vec2, layer: u32) -> Vec4 {
+    texture.sample(uv.extend(layer as f32))
+}
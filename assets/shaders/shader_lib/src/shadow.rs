@@ -0,0 +1,26 @@
+use shader_objects::{UniformBuffer, SHADOW_MAP_RESOLUTION};
+use spirv_std::glam::{Vec2, Vec4};
+
+use crate::sampling::{self, Texture2d};
+
+/// How much of the light casting shadows reaches a fragment at `world_pos`,
+/// from 0, where the shadow map sees something in front of it, to 1. Taken
+/// over the texels around the fragment, softening the edges of shadows.
+/// Fragments outside the shadow map are lit.
+pub fn visibility(ubo: &UniformBuffer, shadow_map: &Texture2d, world_pos: Vec4) -> f32 {
+    let clip = ubo.light_space * world_pos;
+    let ndc = clip.truncate() / clip.w;
+    if ndc.z > 1.0 {
+        return 1.0;
+    }
+    let uv = Vec2::new(ndc.x, ndc.y) * 0.5 + 0.5;
+    let texel = 1.0 / SHADOW_MAP_RESOLUTION as f32;
+    let mut lit = 0.0;
+    for i in 0..9 {
+        let offset = Vec2::new((i % 3) as f32 - 1.0, (i / 3) as f32 - 1.0) * texel;
+        if ndc.z <= sampling::sample_lod(shadow_map, uv + offset, 0.0).x {
+            lit += 1.0;
+        }
+    }
+    lit / 9.0
+}
@@ -2,65 +2,43 @@
 // HACK(eddyb) can't easily see warnings otherwise from `spirv-builder` builds.
 #![deny(warnings)]
 
-use shader_objects::{UniformBuffer, MAX_LIGHTS};
-use spirv_std::glam::{
-    Vec2,
-    Vec4,
-    //Vec4Swizzles
+use shader_lib::sampling::{self, Texture2d, Texture2dArray};
+use shader_lib::{fog, lighting, reflection, shadow};
+use shader_objects::{
+    ClusteredLights, PushConstants, ReflectionProbes, UniformBuffer, REFLECTIVITY_PARAM,
 };
-use spirv_std::image::SampledImage;
-// use spirv_std::num_traits::Pow;
-use spirv_std::{spirv, Image};
+use spirv_std::glam::{Vec2, Vec4};
+use spirv_std::spirv;
 
 #[spirv(fragment)]
 pub fn fragment_main(
     #[spirv(frag_coord)] in_frag_coord: Vec4,
     #[spirv(uniform, descriptor_set = 0, binding = 0)] ubo: &UniformBuffer,
-    #[spirv(descriptor_set = 0, binding = 1)] diffuse_sampler: &SampledImage<
-        Image!(2D, type=f32, sampled, depth=false),
-    >,
+    #[spirv(push_constant)] push_constants: &PushConstants,
+    #[spirv(descriptor_set = 0, binding = 1)] diffuse_sampler: &Texture2d,
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 2)] clustered_lights: &ClusteredLights,
     // #[spirv(descriptor_set = 0, binding = 3)] _specular_sampler: &sampler::Sampler2d,
     // #[spirv(descriptor_set = 0, binding = 4)] _bump_sampler: &sampler::Sampler2d,
+    #[spirv(uniform, descriptor_set = 0, binding = 5)] reflection_probes: &ReflectionProbes,
+    #[spirv(descriptor_set = 0, binding = 6)] reflection_probe_faces: &Texture2dArray,
+    #[spirv(descriptor_set = 0, binding = 8)] shadow_map: &Texture2d,
     normal: Vec4,
     uv: Vec2,
+    world_pos: Vec4,
     out_frag_color: &mut Vec4,
 ) {
-    let mut fog_factor = 0.0;
-    let mut diffuse_color = Vec4::ZERO;
-
-    let texture: Vec4 = diffuse_sampler.sample(uv);
-    // let bump_map: Vec4 = unsafe { bump_sampler.sample(uv) };
-    // let specular_map: Vec4 = unsafe { specular_sampler.sample(uv) };
-
-    for i in 0..MAX_LIGHTS {
-        let light = ubo.lights[i];
-        let light_direction = (light.pos - in_frag_coord).normalize();
-
-        //     // Transform the normal from the normal map to the [-1, 1] range and normalize it
-        //     let bumped_normal = (bump_map * 2.0 - 1.0).normalize();
-
-        // Calculate the view direction
-        // let view_direction = (-in_frag_coord.xyz()).normalize();
-
-        //     // Use bumped_normal instead of normal
-        //     let diffuse_intensity = light_direction.dot(bumped_normal).max(0.0);
-        let diffuse_intensity = light_direction.dot(normal).max(0.0);
-        //     let reflection_direction = -light_direction - 2.0 * bumped_normal.dot(-light_direction) * bumped_normal;
-        //     let shininess = 1.0;
-        //     let specular_intensity = view_direction
-        //         .dot(reflection_direction.xyz())
-        //         .max(0.0)
-        //         .pow(shininess);
-
-        //     let specular_color = light.color * specular_map.x * specular_intensity;
-        diffuse_color += diffuse_intensity * light.color; //  + specular_color;
-
-        // Fog calculations
-        let fog_distance = in_frag_coord.w;
-        fog_factor +=
-            ((ubo.fog_end - fog_distance) / (ubo.fog_end - ubo.fog_start)).clamp(0.0, 1.0);
-    }
-
-    // Apply fog, combine texture, diffuse, and specular colors
-    *out_frag_color = ubo.fog_color.lerp(texture * diffuse_color, fog_factor);
+    let texture = sampling::sample(diffuse_sampler, uv);
+    // TODO: specular and bump maps, as lighting functions in shader_lib.
+    let shadow = shadow::visibility(ubo, shadow_map, world_pos);
+    let diffuse_color = lighting::diffuse_lights(ubo, world_pos, normal, shadow)
+        + lighting::clustered_lights(clustered_lights, in_frag_coord, world_pos, normal);
+    let color = reflection::blend_nearest_probe(
+        reflection_probes,
+        reflection_probe_faces,
+        texture * diffuse_color,
+        push_constants.params[REFLECTIVITY_PARAM].x,
+        world_pos,
+        normal,
+    );
+    *out_frag_color = fog::apply(ubo, color, in_frag_coord.w);
 }
@@ -15,10 +15,12 @@ pub fn vertex_main(
     normal: Vec4,
     o_normal: &mut Vec4,
     o_uv: &mut Vec2,
+    o_world_pos: &mut Vec4,
     #[spirv(position)] o_pos: &mut Vec4,
 ) {
     let model_mat = push_constants.model_mat;
     *o_normal = model_mat.inverse().transpose() * normal;
     *o_uv = uv;
-    *o_pos = ubo.proj * model_mat * Vec4::new(pos.x, pos.y, pos.z, 1.0);
+    *o_world_pos = model_mat * Vec4::new(pos.x, pos.y, pos.z, 1.0);
+    *o_pos = ubo.proj * *o_world_pos;
 }
@@ -0,0 +1,57 @@
+#![cfg_attr(target_arch = "spirv", no_std)]
+// HACK(eddyb) can't easily see warnings otherwise from `spirv-builder` builds.
+#![deny(warnings)]
+
+use shader_lib::sampling::{self, Texture2d, Texture2dArray};
+use shader_lib::{fog, lighting, reflection, shadow};
+use shader_objects::{ClusteredLights, ReflectionProbes, UniformBuffer};
+use spirv_std::glam::{Vec2, Vec4};
+use spirv_std::spirv;
+
+/// Shades the G-buffer written by `gbuffer_fragment` as `default_fragment`
+/// would have, writing the depth of what was drawn so anything drawn forward
+/// afterwards is tested against it.
+#[spirv(fragment(depth_replacing))]
+pub fn fragment_main(
+    #[spirv(frag_coord)] in_frag_coord: Vec4,
+    #[spirv(uniform, descriptor_set = 0, binding = 0)] ubo: &UniformBuffer,
+    #[spirv(descriptor_set = 0, binding = 1)] albedo: &Texture2d,
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 2)] clustered_lights: &ClusteredLights,
+    #[spirv(descriptor_set = 0, binding = 3)] normals: &Texture2d,
+    #[spirv(descriptor_set = 0, binding = 4)] positions: &Texture2d,
+    #[spirv(uniform, descriptor_set = 0, binding = 5)] reflection_probes: &ReflectionProbes,
+    #[spirv(descriptor_set = 0, binding = 6)] reflection_probe_faces: &Texture2dArray,
+    #[spirv(descriptor_set = 0, binding = 8)] shadow_map: &Texture2d,
+    uv: Vec2,
+    out_frag_color: &mut Vec4,
+    #[spirv(frag_depth)] out_frag_depth: &mut f32,
+) {
+    let position = sampling::sample(positions, uv);
+    if position.w < 0.0 {
+        spirv_std::arch::kill();
+    }
+    let world_pos = position.truncate().extend(1.0);
+    let normal = sampling::sample(normals, uv);
+    // The fragment's coordinates as the G-buffer pass rasterized them, which
+    // the lights and fog depend on.
+    let clip = ubo.proj * world_pos;
+    let frag_coord = Vec4::new(
+        in_frag_coord.x,
+        in_frag_coord.y,
+        clip.z / clip.w,
+        1.0 / clip.w,
+    );
+    let shadow = shadow::visibility(ubo, shadow_map, world_pos);
+    let diffuse_color = lighting::diffuse_lights(ubo, world_pos, normal, shadow)
+        + lighting::clustered_lights(clustered_lights, frag_coord, world_pos, normal);
+    let color = reflection::blend_nearest_probe(
+        reflection_probes,
+        reflection_probe_faces,
+        sampling::sample(albedo, uv) * diffuse_color,
+        position.w,
+        world_pos,
+        normal,
+    );
+    *out_frag_color = fog::apply(ubo, color, frag_coord.w);
+    *out_frag_depth = frag_coord.z;
+}
@@ -0,0 +1,26 @@
+#![cfg_attr(target_arch = "spirv", no_std)]
+// HACK(eddyb) can't easily see warnings otherwise from `spirv-builder` builds.
+#![deny(warnings)]
+
+use shader_lib::color;
+use shader_lib::sampling::{self, Texture2d};
+use shader_objects::UiPushConstants;
+use spirv_std::glam::{Vec2, Vec4};
+use spirv_std::spirv;
+
+#[spirv(fragment)]
+pub fn fragment_main(
+    #[spirv(push_constant)] push_constants: &UiPushConstants,
+    #[spirv(descriptor_set = 0, binding = 0)] texture: &Texture2d,
+    uv: Vec2,
+    color: Vec4,
+    out_frag_color: &mut Vec4,
+) {
+    // Textures are sRGB, so they're sampled as linear color.
+    let color = color * sampling::sample(texture, uv);
+    *out_frag_color = if push_constants.srgb_target != 0 {
+        color
+    } else {
+        color::srgb_from_linear(color)
+    };
+}
@@ -0,0 +1,26 @@
+#![cfg_attr(target_arch = "spirv", no_std)]
+// HACK(eddyb) can't easily see warnings otherwise from `spirv-builder` builds.
+#![deny(warnings)]
+
+use shader_lib::color;
+use shader_objects::UiPushConstants;
+use spirv_std::glam::{Vec2, Vec4};
+use spirv_std::spirv;
+
+#[spirv(vertex)]
+pub fn vertex_main(
+    #[spirv(push_constant)] push_constants: &UiPushConstants,
+    pos: Vec2,
+    uv: Vec2,
+    color: Vec4,
+    o_uv: &mut Vec2,
+    o_color: &mut Vec4,
+    #[spirv(position)] o_pos: &mut Vec4,
+) {
+    // Points from the top left to clip space, which is y down in Vulkan.
+    let clip = 2.0 * pos / push_constants.screen_size - Vec2::ONE;
+    *o_pos = Vec4::new(clip.x, clip.y, 0.0, 1.0);
+    *o_uv = uv;
+    // egui's colors are premultiplied sRGB, they're blended in linear space.
+    *o_color = color::linear_from_srgb(color);
+}
@@ -0,0 +1,19 @@
+#![cfg_attr(target_arch = "spirv", no_std)]
+// HACK(eddyb) can't easily see warnings otherwise from `spirv-builder` builds.
+#![deny(warnings)]
+
+use spirv_std::glam::{Vec2, Vec4};
+use spirv_std::spirv;
+
+/// A triangle covering the viewport, drawn from three vertices without any
+/// buffers, with `o_uv` from 0 to 1 across the viewport.
+#[spirv(vertex)]
+pub fn vertex_main(
+    #[spirv(vertex_index)] vertex_index: i32,
+    o_uv: &mut Vec2,
+    #[spirv(position)] o_pos: &mut Vec4,
+) {
+    let uv = Vec2::new(((vertex_index << 1) & 2) as f32, (vertex_index & 2) as f32);
+    *o_pos = Vec4::new(uv.x * 2.0 - 1.0, uv.y * 2.0 - 1.0, 0.0, 1.0);
+    *o_uv = uv;
+}
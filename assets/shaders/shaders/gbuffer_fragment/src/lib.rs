@@ -0,0 +1,31 @@
+#![cfg_attr(target_arch = "spirv", no_std)]
+// HACK(eddyb) can't easily see warnings otherwise from `spirv-builder` builds.
+#![deny(warnings)]
+
+use shader_lib::sampling::{self, Texture2d};
+use shader_objects::{PushConstants, REFLECTIVITY_PARAM};
+use spirv_std::glam::{Vec2, Vec4};
+use spirv_std::spirv;
+
+/// Writes what `default_fragment` shades with into the G-buffer, for the
+/// deferred lighting pass to shade instead. Drawn with the same vertex shader,
+/// layout and descriptor sets.
+#[spirv(fragment)]
+pub fn fragment_main(
+    #[spirv(push_constant)] push_constants: &PushConstants,
+    #[spirv(descriptor_set = 0, binding = 1)] diffuse_sampler: &Texture2d,
+    normal: Vec4,
+    uv: Vec2,
+    world_pos: Vec4,
+    out_albedo: &mut Vec4,
+    out_normal: &mut Vec4,
+    out_position: &mut Vec4,
+) {
+    *out_albedo = sampling::sample(diffuse_sampler, uv);
+    *out_normal = normal;
+    // Positions are cleared to a negative w, telling pixels nothing was drawn
+    // to apart.
+    *out_position = world_pos
+        .truncate()
+        .extend(push_constants.params[REFLECTIVITY_PARAM].x.max(0.0));
+}
@@ -0,0 +1,43 @@
+#![cfg_attr(target_arch = "spirv", no_std)]
+// HACK(eddyb) can't easily see warnings otherwise from `spirv-builder` builds.
+#![deny(warnings)]
+
+use shader_objects::{JointMatrices, PushConstants, UniformBuffer, SKIN_PARAM};
+use spirv_std::glam::{Mat4, UVec4, Vec2, Vec4};
+use spirv_std::spirv;
+
+/// Like `default_vertex`, with each vertex moved by the joints of its skin
+/// first. Drawables that aren't skinned are drawn as they are.
+#[spirv(vertex)]
+pub fn vertex_main(
+    #[spirv(uniform, descriptor_set = 0, binding = 0)] ubo: &UniformBuffer,
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 7)] joint_matrices: &JointMatrices,
+    #[spirv(push_constant)] push_constants: &PushConstants,
+    pos: Vec4,
+    uv: Vec2,
+    normal: Vec4,
+    #[spirv(flat)] joints: UVec4,
+    weights: Vec4,
+    o_normal: &mut Vec4,
+    o_uv: &mut Vec2,
+    o_world_pos: &mut Vec4,
+    #[spirv(position)] o_pos: &mut Vec4,
+) {
+    let skin = push_constants.params[SKIN_PARAM];
+    let (first, count) = (skin.x as u32, skin.y as u32);
+    let skin_mat = if count == 0 {
+        Mat4::IDENTITY
+    } else {
+        let joint = |index: u32| joint_matrices.matrices[(first + index.min(count - 1)) as usize];
+        joint(joints.x) * weights.x
+            + joint(joints.y) * weights.y
+            + joint(joints.z) * weights.z
+            + joint(joints.w) * weights.w
+    };
+
+    let model_mat = push_constants.model_transform * skin_mat;
+    *o_normal = model_mat.inverse().transpose() * normal;
+    *o_uv = uv;
+    *o_world_pos = model_mat * Vec4::new(pos.x, pos.y, pos.z, 1.0);
+    *o_pos = ubo.proj * *o_world_pos;
+}
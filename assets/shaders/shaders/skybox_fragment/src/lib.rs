@@ -2,18 +2,15 @@
 // HACK(eddyb) can't easily see warnings otherwise from `spirv-builder` builds.
 #![deny(warnings)]
 
+use shader_lib::sampling::{self, Texture2d};
 use spirv_std::glam::{Vec2, Vec4};
-use spirv_std::image::SampledImage;
-use spirv_std::{spirv, Image};
+use spirv_std::spirv;
 
 #[spirv(fragment)]
 pub fn fragment_main(
-    #[spirv(descriptor_set = 0, binding = 1)] diffuse_sampler: &SampledImage<
-        Image!(2D, type=f32, sampled, depth=false),
-    >,
+    #[spirv(descriptor_set = 0, binding = 1)] diffuse_sampler: &Texture2d,
     uv: Vec2,
     out_frag_color: &mut Vec4,
 ) {
-    let texture: Vec4 = diffuse_sampler.sample(uv);
-    *out_frag_color = texture;
+    *out_frag_color = sampling::sample(diffuse_sampler, uv);
 }
@@ -1,25 +1,29 @@
 //! Implements a simple shell entrypoint for the engine.
 
 use std::fs::File;
-use std::io::{BufReader, Read};
-use std::net::SocketAddr;
+use std::io::{BufRead, BufReader, Read};
 use std::path::{Path, PathBuf};
-use std::sync::Arc;
-use std::time::{Duration, Instant};
-
-use async_lock::Mutex;
-use futures_lite::future;
-use histogram::Histogram;
-use input::wire::InputState;
-use input::{DeviceEvent, EngineEvent};
-use logger::{info, LogFilter, LogLevel, Logger};
-use render::{Presenter, RenderState};
+use std::time::Duration;
+
+use engine::{
+    AdminSocket, BuiltinSystem, DebugCategories, DynamicResolution, EngineBuilder, EngineError,
+    RenderScale, ReplicationPolicy, SoakConfig, TimelineConfig, WindowConfig, WorldLimits,
+};
+use input::{EngineEvent, InputEvent, MouseLook};
+use logger::{error, info, warn, LogFilter, LogLevel, Logger};
+use platform::audio::MixerSettings;
 use serde::Deserialize;
 use structopt::StructOpt;
 use structopt_yaml::StructOptYaml;
-use world::{AssetLoaderState, AssetLoaderStateAndWorldLock, WorldLockAndControllerState};
 
-const FRAME_LENGTH_MS: u64 = 8;
+/// Lowest scale --target-frame-ms lowers the render scale to.
+const MIN_DYNAMIC_RENDER_SCALE: f32 = 0.5;
+
+/// Environment variable holding the token --admin-port connections send.
+const ADMIN_TOKEN_VAR: &str = "NANACTYL_ADMIN_TOKEN";
+
+/// Function key that captures a screenshot into --screenshot-dir, F12.
+const SCREENSHOT_KEY: u8 = 12;
 
 #[derive(StructOpt, Debug, StructOptYaml, Deserialize)]
 #[serde(default)]
@@ -33,8 +37,19 @@ struct CliOpts {
     #[structopt(long)]
     enable_validation_layer: bool,
 
+    /// Server to connect to, as host:port. Host names are resolved, and the
+    /// connection is retried if it's lost.
     #[structopt(long)]
-    connect_to_server: Option<SocketAddr>,
+    connect_to_server: Option<String>,
+
+    /// Act as the server and run a client in the same process, connected over
+    /// loopback.
+    #[structopt(long)]
+    listen_and_connect_self: bool,
+
+    /// Simulated latency of the loopback connection.
+    #[structopt(long, default_value = "0")]
+    loopback_latency_ms: u64,
 
     #[structopt(long, default_value = "15")]
     check_system_interval: u64,
@@ -48,8 +63,251 @@ struct CliOpts {
     #[structopt(long)]
     log_tag_filter: Option<String>,
 
+    /// Log a plugin at its own level, as <plugin>=<level>, e.g. net_sync=trace.
+    #[structopt(long = "plugin-log-level")]
+    plugin_log_levels: Vec<String>,
+
     #[structopt(long)]
     net_disabled: bool,
+
+    /// Codec to compress updates with when serving: none, lz4, zstd or
+    /// zstd:<level>. Clients that don't support it get zstd. Also set with
+    /// the `net_compression` console variable.
+    #[structopt(long, default_value = "zstd:3")]
+    net_compression: String,
+
+    /// Trace every packet sent and received to this file, for debugging the
+    /// protocol. Print it with the network crate's trace_dump example.
+    #[structopt(long)]
+    net_trace: Option<PathBuf>,
+
+    /// Blend replicated entities between updates from the server, drawn a
+    /// little behind it, instead of jumping to each update.
+    #[structopt(long)]
+    interpolate_replicated: bool,
+
+    /// How far behind the server interpolated entities are drawn, otherwise
+    /// the delay adapts to the connection's quality.
+    #[structopt(long)]
+    interpolation_delay_ms: Option<u64>,
+
+    /// Fraction of the window's resolution to render the scene at, 0.25 to 2.
+    #[structopt(long, default_value = "1.0")]
+    render_scale: f32,
+
+    /// Filter to scale the scene to the window with: nearest or linear.
+    #[structopt(long, default_value = "linear")]
+    upscale_filter: String,
+
+    /// Adjust the render scale, up to --render-scale, to keep frames to this
+    /// many milliseconds.
+    #[structopt(long)]
+    target_frame_ms: Option<f32>,
+
+    /// How to fit the scene to the window: stretch, letterbox (16:9),
+    /// letterbox:<w>:<h> or vertical_fov.
+    #[structopt(long, default_value = "stretch")]
+    aspect_policy: String,
+
+    /// How to light the main window: forward, or deferred (experimental).
+    #[structopt(long, default_value = "forward")]
+    render_path: String,
+
+    /// Frames recorded while the GPU renders the ones before them, 1 to 3.
+    #[structopt(long, default_value = "2")]
+    frames_in_flight: u32,
+
+    /// Cap the frame rate at this many frames per second, waiting out frames
+    /// precisely rather than relying on presentation to pace them.
+    #[structopt(long)]
+    fps_cap: Option<f32>,
+
+    /// How frames are presented where supported: fifo or mailbox wait for
+    /// vertical blank, immediate tears. Also set with the `present_mode`
+    /// console variable.
+    #[structopt(long, default_value = "mailbox")]
+    present_mode: String,
+
+    /// Most entities in the world, spawning more fails.
+    #[structopt(long, default_value = "65536")]
+    max_entities: usize,
+
+    /// Most drawable entities in the world.
+    #[structopt(long, default_value = "16384")]
+    max_drawables: usize,
+
+    /// Most entities replicated to clients: physics bodies and projectiles.
+    #[structopt(long, default_value = "1024")]
+    max_replicated: usize,
+
+    /// Scene file declaring groups of models to preload by priority.
+    #[structopt(long)]
+    scene: Option<PathBuf>,
+
+    /// Megabytes of models preloaded groups may take, lower priority groups
+    /// are evicted past it.
+    #[structopt(long, default_value = "256")]
+    preload_budget_mb: usize,
+
+    /// Run without a window or renderer.
+    #[structopt(long)]
+    headless: bool,
+
+    /// Run as a dedicated server: headless, reading console commands from
+    /// stdin.
+    #[structopt(long)]
+    dedicated: bool,
+
+    /// Render headless runs anyway, offscreen at the window's size.
+    #[structopt(long)]
+    offscreen: bool,
+
+    /// Take console commands on this localhost port, from connections that
+    /// first send the token in the NANACTYL_ADMIN_TOKEN environment variable.
+    #[structopt(long)]
+    admin_port: Option<u16>,
+
+    /// Open a second window showing the scene, beside the first.
+    #[structopt(long)]
+    debug_window: bool,
+
+    /// Check vulkan, SDL subsystems, assets, shaders and networking, print a
+    /// report and exit instead of running the engine.
+    #[structopt(long)]
+    diagnose: bool,
+
+    /// Run a soak test for this many ticks and exit, failing if entities, GPU
+    /// memory or unacked packets kept growing. Add --headless to soak a
+    /// server.
+    #[structopt(long)]
+    soak_ticks: Option<u64>,
+
+    /// Entities a soak test keeps moving.
+    #[structopt(long, default_value = "1000")]
+    soak_entities: usize,
+
+    /// Entities a soak test replaces with new ones every tick.
+    #[structopt(long, default_value = "4")]
+    soak_respawns: usize,
+
+    /// Seed of a soak test's entities and motions, the same seed repeats a
+    /// run.
+    #[structopt(long, default_value = "0")]
+    soak_seed: u64,
+
+    /// Capture a frame timeline when a frame takes longer than this, see
+    /// also the timeline_capture and timeline_hitch_ms console commands.
+    #[structopt(long)]
+    timeline_hitch_ms: Option<f32>,
+
+    /// Format timelines are written in: chrome or binary.
+    #[structopt(long, default_value = "chrome")]
+    timeline_format: String,
+
+    #[structopt(long, default_value = "timelines")]
+    timeline_dir: PathBuf,
+
+    /// Read console commands from stdin, one per line. Type help to list
+    /// them.
+    #[structopt(long)]
+    console: bool,
+
+    /// Built-in systems not to load: world_update, asset_loader or net_sync.
+    #[structopt(long = "disable-system")]
+    disable_systems: Vec<String>,
+
+    /// Debug lines to draw: colliders, contacts, wheel_rays, velocities, grid,
+    /// axes or all.
+    #[structopt(long = "debug-draw")]
+    debug_draw: Vec<String>,
+
+    /// Show the debug UI, with world and connection stats. Also toggled with
+    /// the debug_ui console variable.
+    #[structopt(long)]
+    debug_ui: bool,
+
+    /// Capture the mouse for FPS-style look. Also toggled with the
+    /// relative_mouse console variable.
+    #[structopt(long)]
+    relative_mouse: bool,
+
+    /// Radians turned per count of mouse motion. Also set with the
+    /// mouse_sensitivity console variable.
+    #[structopt(long, default_value = "0.002")]
+    mouse_sensitivity: f32,
+
+    /// Look down when the mouse moves up. Also toggled with the invert_y
+    /// console variable.
+    #[structopt(long)]
+    invert_y: bool,
+
+    /// File gamepad calibration profiles are kept in, see the
+    /// gamepad_calibrate console command.
+    #[structopt(long, default_value = "gamepads.yaml")]
+    gamepad_profiles: PathBuf,
+
+    /// File remembering which gamepad joined as which local player, see the
+    /// player_slots console command.
+    #[structopt(long, default_value = "players.yaml")]
+    player_slots: PathBuf,
+
+    /// Play the local player's input back from a macro recorded with
+    /// --record-input-macro or the input_macro console command.
+    #[structopt(long)]
+    input_macro: Option<PathBuf>,
+
+    /// Record the local player's input a tick at a time, saved to this file on
+    /// exit.
+    #[structopt(long)]
+    record_input_macro: Option<PathBuf>,
+
+    /// Directory a report is written to when Vulkan fails unexpectedly, and
+    /// the journal of recent events when the engine panics.
+    #[structopt(long, default_value = "crash_reports")]
+    crash_report_dir: PathBuf,
+
+    /// Directory screenshots taken with F12 are saved to.
+    #[structopt(long, default_value = "screenshots")]
+    screenshot_dir: PathBuf,
+
+    /// Recent significant events kept for post-mortems, see also the journal
+    /// and journal_dump console commands.
+    #[structopt(long, default_value = "1024")]
+    journal_len: usize,
+
+    /// Directory game systems are loaded from as plugins, see also the
+    /// plugins and plugins_scan console commands.
+    #[structopt(long)]
+    plugins_dir: Option<PathBuf>,
+
+    /// Server rooms to host alongside the main world, as <name>:<port>, see
+    /// also the room_create and room_destroy console commands.
+    #[structopt(long = "room")]
+    rooms: Vec<String>,
+
+    /// Volume of every bus. This and the other audio settings are also set
+    /// with console variables of the same names.
+    #[structopt(long, default_value = "1.0")]
+    master_volume: f32,
+
+    #[structopt(long, default_value = "1.0")]
+    sfx_volume: f32,
+
+    #[structopt(long, default_value = "1.0")]
+    music_volume: f32,
+
+    #[structopt(long, default_value = "1.0")]
+    ui_volume: f32,
+
+    /// Gain applied to music while sound effects play, 1.0 disables ducking.
+    #[structopt(long, default_value = "0.5")]
+    music_duck_under_sfx: f32,
+
+    #[structopt(long, default_value = "150")]
+    music_duck_attack_ms: u64,
+
+    #[structopt(long, default_value = "600")]
+    music_duck_release_ms: u64,
 }
 
 impl CliOpts {
@@ -77,192 +335,270 @@ impl CliOpts {
         }
         opts
     }
+
+    fn window_config(&self) -> WindowConfig {
+        let (title, x) = if self.net_disabled {
+            ("nshell (net disabled)", 0)
+        } else if self.listen_and_connect_self {
+            ("nshell (server + loopback client)", 0)
+        } else if self.connect_to_server.is_some() {
+            ("nshell-client", 640)
+        } else {
+            ("nshell-server", 0)
+        };
+        WindowConfig {
+            title: title.to_string(),
+            x,
+            ..Default::default()
+        }
+    }
+
+    fn mixer_settings(&self) -> MixerSettings {
+        MixerSettings {
+            master_volume: self.master_volume,
+            sfx_volume: self.sfx_volume,
+            music_volume: self.music_volume,
+            ui_volume: self.ui_volume,
+            music_duck_under_sfx: self.music_duck_under_sfx,
+            music_duck_attack: Duration::from_millis(self.music_duck_attack_ms),
+            music_duck_release: Duration::from_millis(self.music_duck_release_ms),
+        }
+    }
 }
 
 fn main() {
     let logger = LogLevel::Info.logger().sub("nshell");
 
     let opts = CliOpts::load_with_overrides(&logger);
-    match (opts.log_level_filter, opts.log_tag_filter) {
-        (None, Some(tag)) => logger.set_filter(LogFilter::tag(&tag)),
+    match (
+        opts.log_level_filter.clone(),
+        opts.log_tag_filter.as_deref(),
+    ) {
+        (None, Some(tag)) => logger.set_filter(LogFilter::tag(tag)),
         (Some(level), None) => logger.set_filter(LogFilter::level(level)),
-        (Some(level), Some(prefix)) => logger.set_filter(LogFilter::level_and_tag(level, &prefix)),
+        (Some(level), Some(prefix)) => logger.set_filter(LogFilter::level_and_tag(level, prefix)),
         (None, None) => {}
     }
+    for plugin_level in &opts.plugin_log_levels {
+        match plugin_level.split_once('=') {
+            Some((name, level)) => match level.parse::<LogLevel>() {
+                Ok(level) => logger.set_plugin_level(name, Some(level)),
+                Err(err) => error!(logger, "{err}"),
+            },
+            None => error!(logger, "expected <plugin>=<level>, got {plugin_level:?}"),
+        }
+    }
 
-    let world = world::World::new(opts.connect_to_server, &logger, opts.net_disabled);
-    let world = Arc::new(Mutex::new(world));
-
-    let logger2 = logger.sub("main");
-    future::block_on(async move {
-        let mut frame_start;
-        let mut last_frame_complete = Instant::now();
-
-        let mut frame = 0u64;
-        let own_controllers: [InputState; 2] = Default::default();
-        let own_controllers = Arc::new(Mutex::new(own_controllers));
-        let logger = logger2;
-        let mut frame_histogram = Histogram::new();
-        let mut platform_context = platform::PlatformContext::new(&logger).unwrap();
-
-        let index = if opts.net_disabled {
-            platform_context
-                .add_vulkan_window("nshell (net disabled)", 0, 0, 640, 400)
-                .unwrap()
-        } else if opts.connect_to_server.is_some() {
-            platform_context
-                .add_vulkan_window("nshell-client", 640, 0, 640, 400)
-                .unwrap()
-        } else {
-            platform_context
-                .add_vulkan_window("nshell-server", 0, 0, 640, 400)
-                .unwrap()
-        };
-
-        let win_ptr = platform_context.get_raw_window_handle(index).unwrap();
+    if opts.diagnose {
+        let report = engine::diagnose(Path::new("assets"));
+        match serde_yaml::to_string(&report) {
+            Ok(yaml) => println!("{yaml}"),
+            Err(err) => error!(logger, "unable to format diagnostic report {err}"),
+        }
+        std::process::exit(if report.passed() { 0 } else { 1 });
+    }
 
-        let render_state = RenderState::new(
-            win_ptr,
-            opts.enable_validation_layer,
-            opts.connect_to_server.is_none(),
-            logger.sub("render_state"),
+    let window = opts.window_config();
+    let mut builder = EngineBuilder::new(&logger);
+    if opts.debug_window {
+        builder = builder.extra_window(WindowConfig {
+            title: format!("{} (debug view)", window.title),
+            x: window.x + window.width as i32,
+            ..window.clone()
+        });
+    }
+    if opts.offscreen {
+        builder = builder.offscreen(window.width, window.height);
+    }
+    let mut builder = builder
+        .window(window)
+        .headless(opts.headless || opts.dedicated)
+        .enable_validation_layer(opts.enable_validation_layer)
+        .connect_to_server(opts.connect_to_server.clone())
+        .listen_and_connect_self(
+            opts.listen_and_connect_self
+                .then(|| Duration::from_millis(opts.loopback_latency_ms)),
         )
-        .into_shared();
-
-        let mut ash_renderer_system = ash_renderer_system::VulkanRenderPluginState::default();
-        ash_renderer_system.load(&mut *render_state.lock().await);
-
-        let mut world_update_system = world_update_system::WorldUpdate::new();
-        world_update_system.load(&mut *world.lock().await);
-
-        let mut net_sync_system = if !opts.net_disabled {
-            let world = Arc::clone(&world);
-            let controller_state = Arc::clone(&own_controllers);
-            let mut net = net_sync_system::NetSyncState::new();
-            let mut state = WorldLockAndControllerState::lock(&world, &controller_state).await;
-            net.load(&mut state);
-            Some(net)
-        } else {
-            None
-        };
-
-        let asset_state = Arc::new(Mutex::new(AssetLoaderState::default()));
-        let mut asset_loader = asset_loader_system::AssetLoader::new();
-        asset_loader.load(&mut AssetLoaderStateAndWorldLock::lock(&world, &asset_state).await);
-
-        'frame_loop: loop {
-            frame_start = Instant::now();
-            platform_context.pump_events();
-
-            if let Some(EngineEvent::ExitToDesktop) = handle_input_events(
-                platform_context.peek_events(),
-                &mut *own_controllers.lock().await,
-                logger.sub("handle_input_events"),
-            ) {
-                break 'frame_loop;
-            }
-
-            let last_frame_elapsed = last_frame_complete.elapsed();
-
-            asset_loader.update(
-                &mut AssetLoaderStateAndWorldLock::lock(&world, &asset_state).await,
-                &last_frame_elapsed,
-            );
-
-            // This is a bit convoluted, but the renderer plugin allows us to fetch a
-            // pointer to it's "state" which in this case is a dyn Renderer + Presenter
-            // trait object
-            render_state.lock().await.upload_untracked_graphics_prefabs(
-                &*world.as_ref().lock().await,
-                &mut ash_renderer_system,
-            );
-
-            match net_sync_system.as_mut() {
-                Some(net_sync_system) => {
-                    net_sync_system.update(
-                        &mut WorldLockAndControllerState::lock(&world, &own_controllers).await,
-                        &last_frame_elapsed,
-                    );
-                }
-                // Net is not enabled, so just update the world with the controller state
-                None => {
-                    let controller_state = own_controllers.lock().await;
-                    let world = &mut *world.lock().await;
-                    world.set_server_controller_state(controller_state[0]);
-                    world.set_client_controller_state(controller_state[1]);
-                }
+        .net_disabled(opts.net_disabled);
+    match opts.net_compression.parse() {
+        Ok(codec) => builder = builder.net_compression(codec),
+        Err(err) => error!(logger, "{err}"),
+    }
+    builder = builder.net_trace(opts.net_trace.clone());
+    if opts.interpolate_replicated {
+        builder = builder.replication(ReplicationPolicy::Interpolate { delay: None });
+    }
+    builder = builder.interpolation_delay(opts.interpolation_delay_ms.map(Duration::from_millis));
+    let mut render_scale = RenderScale {
+        scale: opts.render_scale,
+        dynamic: opts.target_frame_ms.map(|ms| DynamicResolution {
+            target_frame_time: Duration::from_secs_f32(ms / 1000.0),
+            min_scale: MIN_DYNAMIC_RENDER_SCALE,
+            max_scale: opts.render_scale,
+        }),
+        ..Default::default()
+    };
+    match opts.upscale_filter.parse() {
+        Ok(filter) => render_scale.filter = filter,
+        Err(err) => error!(logger, "{err}"),
+    }
+    builder = builder.render_scale(render_scale);
+    match opts.aspect_policy.parse() {
+        Ok(policy) => builder = builder.aspect_policy(policy),
+        Err(err) => error!(logger, "{err}"),
+    }
+    match opts.render_path.parse() {
+        Ok(path) => builder = builder.render_path(path),
+        Err(err) => error!(logger, "{err}"),
+    }
+    builder = builder.frames_in_flight(opts.frames_in_flight);
+    match opts.fps_cap {
+        Some(fps) if !(fps.is_finite() && fps > 0.0) => {
+            error!(logger, "fps cap must be a positive number, got {fps}")
+        }
+        fps_cap => builder = builder.fps_cap(fps_cap),
+    }
+    match opts.present_mode.parse() {
+        Ok(mode) => builder = builder.present_mode(mode),
+        Err(err) => error!(logger, "{err}"),
+    }
+    builder = builder.world_limits(WorldLimits {
+        max_entities: opts.max_entities,
+        max_drawables: opts.max_drawables,
+        max_replicated: opts.max_replicated,
+    });
+    builder = builder.scene(opts.scene.clone());
+    builder = builder.preload_budget(opts.preload_budget_mb * 1024 * 1024);
+    for name in opts.disable_systems.iter() {
+        match name.parse::<BuiltinSystem>() {
+            Ok(system) if !system.is_compiled_in() => {
+                info!(logger, "{system} is not compiled in, nothing to disable");
             }
-
-            ash_renderer_system.present(&*world.as_ref().lock().await);
-
-            // update the renderer and the world simultaneously
-            ash_renderer_system.update(&mut *render_state.lock().await, &last_frame_elapsed);
-            world_update_system.update(&mut *world.lock().await, &last_frame_elapsed);
-
-            let elapsed = frame_start.elapsed();
-            let last_frame_elapsed_micros = elapsed.as_micros();
-
-            frame_histogram
-                .increment(last_frame_elapsed_micros as u64)
-                .unwrap();
-
-            if frame % 1000 == 0 {
-                info!(
-                    logger,
-                    "Frame time (µs): Min: {} Avg: {} Max: {} StdDev: {} 50%: {}, 90%: {}, 99%: {}, 99.9%:{}",
-                    frame_histogram.minimum().unwrap(),
-                    frame_histogram.mean().unwrap(),
-                    frame_histogram.maximum().unwrap(),
-                    frame_histogram.stddev().unwrap(),
-                    frame_histogram.percentile(50.0).unwrap(),
-                    frame_histogram.percentile(90.0).unwrap(),
-                    frame_histogram.percentile(99.0).unwrap(),
-                    frame_histogram.percentile(99.9).unwrap(),
-                );
-                frame_histogram.clear();
+            Ok(system) => {
+                info!(logger, "disabling built-in system {system}");
+                builder = builder.disable_system(system);
             }
-
-            let delay = Duration::from_millis(FRAME_LENGTH_MS).saturating_sub(elapsed);
-            last_frame_complete = Instant::now();
-
-            smol::Timer::after(delay).await;
-
-            frame += 1;
-        } // 'frame_loop
+            Err(err) => error!(logger, "{err}"),
+        }
+    }
+    let mut debug_draw = DebugCategories::NONE;
+    for name in opts.debug_draw.iter() {
+        match DebugCategories::from_name(name) {
+            Some(categories) => debug_draw.insert(categories),
+            None => error!(logger, "unknown debug draw category {name:?}"),
+        }
+    }
+    builder = builder.debug_draw(debug_draw);
+    builder = builder.debug_ui(opts.debug_ui);
+    builder = builder.relative_mouse(opts.relative_mouse);
+    builder = builder.mouse_look(MouseLook {
+        sensitivity: opts.mouse_sensitivity,
+        invert_y: opts.invert_y,
     });
+    builder = builder.mixer(opts.mixer_settings());
+    let mut timeline = TimelineConfig {
+        hitch_threshold: opts
+            .timeline_hitch_ms
+            .map(|ms| Duration::from_secs_f32(ms / 1000.0)),
+        dir: opts.timeline_dir.clone(),
+        ..Default::default()
+    };
+    match opts.timeline_format.parse() {
+        Ok(format) => timeline.format = format,
+        Err(err) => error!(logger, "{err}"),
+    }
+    builder = builder.timeline(timeline);
+    builder = builder.gamepad_profiles(opts.gamepad_profiles.clone());
+    builder = builder.player_slots(opts.player_slots.clone());
+    if let Some(path) = &opts.input_macro {
+        builder = builder.input_macro(path.clone());
+    }
+    if let Some(path) = &opts.record_input_macro {
+        builder = builder.record_input_macro(path.clone());
+    }
+    builder = builder.crash_report_dir(opts.crash_report_dir.clone());
+    builder = builder.journal_len(opts.journal_len);
+    builder = builder.dump_journal_on_panic(true);
+    builder = builder.plugins_dir(opts.plugins_dir.clone());
+    for room in opts.rooms.iter() {
+        match room.parse() {
+            Ok(room) => builder = builder.room(room),
+            Err(err) => error!(logger, "{err}"),
+        }
+    }
+    if let Some(ticks) = opts.soak_ticks {
+        builder = builder.soak(SoakConfig {
+            seed: opts.soak_seed,
+            entities: opts.soak_entities,
+            ticks,
+            respawns_per_tick: opts.soak_respawns,
+        });
+    }
 
-    info!(logger, "quitting.");
-}
+    if let Some(port) = opts.admin_port {
+        match std::env::var(ADMIN_TOKEN_VAR) {
+            Ok(token) if !token.is_empty() => {
+                builder = builder.admin_socket(Some(AdminSocket { port, token }));
+            }
+            _ => error!(
+                logger,
+                "not opening the admin socket, {ADMIN_TOKEN_VAR} isn't set"
+            ),
+        }
+    }
 
-fn handle_input_events(
-    events: &[EngineEvent],
-    controllers: &mut [InputState; 2],
-    logger: Logger,
-) -> Option<EngineEvent> {
-    if !events.is_empty() {
-        for event in events {
-            match event {
-                EngineEvent::Continue => {}
-                EngineEvent::InputDevice(DeviceEvent::GameControllerAdded(id)) => {
-                    info!(logger, "gamepad {id} added");
-                    controllers[*id as usize] = InputState::new(*id as u8);
+    let read_console = opts.console || opts.dedicated;
+    let screenshot_dir = opts.screenshot_dir.clone();
+    let screenshot_logger = logger.sub("screenshot");
+    let mut screenshots = Vec::new();
+    let engine = builder
+        .on_frame(move |frame| {
+            let pressed = frame.events().iter().any(|event| {
+                matches!(
+                    event,
+                    EngineEvent::Input(InputEvent::FunctionKey(SCREENSHOT_KEY, true))
+                )
+            });
+            if pressed {
+                match frame.capture_frame(&screenshot_dir) {
+                    Some(screenshot) => screenshots.push(screenshot),
+                    None => warn!(screenshot_logger, "nothing is rendered to capture"),
                 }
-                EngineEvent::InputDevice(DeviceEvent::GameControllerRemoved(id)) => {
-                    info!(logger, "gamepad {id} removed");
-                    controllers[*id as usize] = Default::default();
-                }
-                EngineEvent::InputDevice(input_device_event) => {
-                    info!(logger, "input device event {input_device_event:?}");
+            }
+            screenshots.retain_mut(|screenshot| match screenshot.try_take() {
+                Some(Ok(path)) => {
+                    info!(screenshot_logger, "saved screenshot {}", path.display());
+                    false
                 }
-                EngineEvent::Input(input_event) => {
-                    controllers[0].update_from_event(input_event);
+                Some(Err(err)) => {
+                    error!(screenshot_logger, "unable to save screenshot: {err}");
+                    false
                 }
-                ret @ EngineEvent::ExitToDesktop => {
-                    info!(logger, "Got exit with code {ret:?}");
-                    return Some(ret.clone());
+                None => true,
+            });
+        })
+        .build();
+
+    if let (true, Ok(engine)) = (read_console, engine.as_ref()) {
+        let sender = engine.console_sender();
+        std::thread::spawn(move || {
+            for line in std::io::stdin().lock().lines() {
+                match line {
+                    Ok(line) if sender.send(line).is_ok() => {}
+                    _ => break,
                 }
             }
+        });
+    }
+
+    match engine.and_then(|engine| engine.run()) {
+        Ok(()) => {}
+        Err(EngineError::SoakFailed(report)) => {
+            error!(logger, "soak test failed, {report}");
+            std::process::exit(1);
         }
+        Err(err) => error!(logger, "engine exited with an error {err:?}"),
     }
-    None
+
+    info!(logger, "quitting.");
 }
@@ -0,0 +1,341 @@
+//! Compact encodings for values sent over the wire (or written to disk):
+//! fixed-point quantization of floats within a range, zigzag varints for
+//! small signed deltas, and a bit-level writer/reader for packing fields that
+//! don't fill a whole byte.
+
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+pub enum CodecError {
+    #[error("unexpected end of input, wanted {wanted} more bits")]
+    UnexpectedEnd { wanted: u32 },
+    #[error("varint is longer than 10 bytes")]
+    VarintTooLong,
+}
+
+/// Quantize `value` to an unsigned integer of `bits` bits, spread evenly over
+/// `min..=max`. Values outside the range are clamped.
+pub fn quantize(value: f32, min: f32, max: f32, bits: u32) -> u32 {
+    debug_assert!((1..=32).contains(&bits));
+    debug_assert!(max > min);
+    let steps = max_value(bits);
+    let normalized = ((value as f64 - min as f64) / (max as f64 - min as f64)).clamp(0.0, 1.0);
+    (normalized * steps as f64).round() as u32
+}
+
+/// Inverse of `quantize`.
+pub fn dequantize(quantized: u32, min: f32, max: f32, bits: u32) -> f32 {
+    let steps = max_value(bits);
+    let normalized = quantized.min(steps) as f64 / steps as f64;
+    (min as f64 + normalized * (max as f64 - min as f64)) as f32
+}
+
+/// The largest error introduced by quantizing within `min..=max` at `bits`.
+pub fn quantization_error(min: f32, max: f32, bits: u32) -> f32 {
+    (max - min) / max_value(bits) as f32 / 2.0
+}
+
+fn max_value(bits: u32) -> u32 {
+    if bits >= 32 {
+        u32::MAX
+    } else {
+        (1 << bits) - 1
+    }
+}
+
+/// Map signed integers to unsigned so that values close to zero (of either
+/// sign) have a short varint encoding: 0, -1, 1, -2, 2 ... -> 0, 1, 2, 3, 4 ...
+pub fn zigzag_encode(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+pub fn zigzag_decode(value: u64) -> i64 {
+    ((value >> 1) as i64) ^ -((value & 1) as i64)
+}
+
+/// Append `value` as a LEB128 varint, 7 bits per byte.
+pub fn write_varint(buf: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            buf.push(byte);
+            return;
+        }
+        buf.push(byte | 0x80);
+    }
+}
+
+/// Read a varint from the front of `bytes`, returning it and the number of
+/// bytes consumed.
+pub fn read_varint(bytes: &[u8]) -> Result<(u64, usize), CodecError> {
+    let mut value = 0u64;
+    for (i, byte) in bytes.iter().enumerate() {
+        if i >= 10 {
+            return Err(CodecError::VarintTooLong);
+        }
+        value |= ((byte & 0x7f) as u64) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Ok((value, i + 1));
+        }
+    }
+    Err(CodecError::UnexpectedEnd { wanted: 8 })
+}
+
+/// Append the difference between `current` and `previous` as a zigzag varint.
+pub fn write_delta(buf: &mut Vec<u8>, previous: i64, current: i64) {
+    write_varint(buf, zigzag_encode(current.wrapping_sub(previous)));
+}
+
+/// Read a delta written by `write_delta`, returning the reconstructed value and
+/// the number of bytes consumed.
+pub fn read_delta(bytes: &[u8], previous: i64) -> Result<(i64, usize), CodecError> {
+    let (delta, len) = read_varint(bytes)?;
+    Ok((previous.wrapping_add(zigzag_decode(delta)), len))
+}
+
+/// Packs values of arbitrary bit width, least significant bits first.
+#[derive(Debug, Default)]
+pub struct BitWriter {
+    bytes: Vec<u8>,
+    scratch: u64,
+    scratch_bits: u32,
+}
+
+impl BitWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Write into `bytes`, cleared first, so its allocation is reused.
+    pub fn with_buffer(mut bytes: Vec<u8>) -> Self {
+        bytes.clear();
+        Self {
+            bytes,
+            ..Default::default()
+        }
+    }
+
+    /// Write the low `bits` bits of `value`.
+    pub fn write_bits(&mut self, value: u32, bits: u32) {
+        debug_assert!(bits <= 32);
+        let value = value as u64 & (max_value(bits) as u64);
+        self.scratch |= value << self.scratch_bits;
+        self.scratch_bits += bits;
+        while self.scratch_bits >= 8 {
+            self.bytes.push(self.scratch as u8);
+            self.scratch >>= 8;
+            self.scratch_bits -= 8;
+        }
+    }
+
+    pub fn write_bool(&mut self, value: bool) {
+        self.write_bits(value as u32, 1);
+    }
+
+    /// Number of bits written so far.
+    pub fn bits_written(&self) -> usize {
+        self.bytes.len() * 8 + self.scratch_bits as usize
+    }
+
+    /// Flush any partial byte, padding with zeros, and return the bytes.
+    pub fn finish(mut self) -> Vec<u8> {
+        if self.scratch_bits > 0 {
+            self.bytes.push(self.scratch as u8);
+        }
+        self.bytes
+    }
+}
+
+/// Reads values written by `BitWriter`.
+#[derive(Debug)]
+pub struct BitReader<'a> {
+    bytes: &'a [u8],
+    bit_pos: usize,
+}
+
+impl<'a> BitReader<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, bit_pos: 0 }
+    }
+
+    pub fn read_bits(&mut self, bits: u32) -> Result<u32, CodecError> {
+        debug_assert!(bits <= 32);
+        let remaining = self.bytes.len() * 8 - self.bit_pos;
+        if (bits as usize) > remaining {
+            return Err(CodecError::UnexpectedEnd {
+                wanted: bits - remaining as u32,
+            });
+        }
+        let mut value = 0u64;
+        let mut read = 0;
+        while read < bits {
+            let byte = self.bytes[self.bit_pos / 8] as u64;
+            let offset = (self.bit_pos % 8) as u32;
+            let take = (8 - offset).min(bits - read);
+            let chunk = (byte >> offset) & ((1 << take) - 1);
+            value |= chunk << read;
+            read += take;
+            self.bit_pos += take as usize;
+        }
+        Ok(value as u32)
+    }
+
+    pub fn read_bool(&mut self) -> Result<bool, CodecError> {
+        Ok(self.read_bits(1)? == 1)
+    }
+
+    /// Number of bits read so far.
+    pub fn bits_read(&self) -> usize {
+        self.bit_pos
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quantize_roundtrip_within_error() {
+        for bits in [1, 4, 8, 10, 12, 16, 24] {
+            let (min, max) = (-256.0, 256.0);
+            let error = quantization_error(min, max, bits);
+            for i in 0..=1000 {
+                let value = min + (max - min) * (i as f32 / 1000.0);
+                let roundtrip = dequantize(quantize(value, min, max, bits), min, max, bits);
+                assert!(
+                    (value - roundtrip).abs() <= error * 1.001,
+                    "{bits} bits: {value} -> {roundtrip}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn quantize_is_exact_at_every_step() {
+        for bits in 1..=12 {
+            for q in 0..=max_value(bits) {
+                let value = dequantize(q, -1.0, 1.0, bits);
+                assert_eq!(quantize(value, -1.0, 1.0, bits), q);
+            }
+        }
+    }
+
+    #[test]
+    fn quantize_clamps() {
+        assert_eq!(quantize(-10.0, 0.0, 1.0, 8), 0);
+        assert_eq!(quantize(10.0, 0.0, 1.0, 8), 255);
+        assert_eq!(quantize(10.0, 0.0, 1.0, 32), u32::MAX);
+    }
+
+    #[test]
+    fn zigzag_roundtrip() {
+        for value in (-1000..=1000).chain([i64::MIN, i64::MAX, i64::MIN + 1, i64::MAX - 1]) {
+            assert_eq!(zigzag_decode(zigzag_encode(value)), value);
+        }
+        assert_eq!(zigzag_encode(0), 0);
+        assert_eq!(zigzag_encode(-1), 1);
+        assert_eq!(zigzag_encode(1), 2);
+        assert_eq!(zigzag_encode(-2), 3);
+    }
+
+    #[test]
+    fn varint_roundtrip() {
+        let mut values = vec![0, 1, 127, 128, 255, 300, 16383, 16384, u64::MAX];
+        values.extend((0..64).map(|shift| 1u64 << shift));
+        values.extend((0..64).map(|shift| (1u64 << shift) - 1));
+        let mut buf = Vec::new();
+        for value in values.iter() {
+            write_varint(&mut buf, *value);
+        }
+        let mut offset = 0;
+        for value in values.iter() {
+            let (read, len) = read_varint(&buf[offset..]).unwrap();
+            assert_eq!(read, *value);
+            offset += len;
+        }
+        assert_eq!(offset, buf.len());
+
+        let mut buf = Vec::new();
+        write_varint(&mut buf, 127);
+        assert_eq!(buf.len(), 1);
+        write_varint(&mut buf, 128);
+        assert_eq!(buf.len(), 3);
+    }
+
+    #[test]
+    fn varint_errors() {
+        assert_eq!(
+            read_varint(&[0x80, 0x80]),
+            Err(CodecError::UnexpectedEnd { wanted: 8 })
+        );
+        assert_eq!(read_varint(&[0xff; 11]), Err(CodecError::VarintTooLong));
+    }
+
+    #[test]
+    fn delta_roundtrip() {
+        let values = [0i64, 5, 3, 3, -100, 100_000, i64::MIN, i64::MAX, 0];
+        let mut buf = Vec::new();
+        let mut previous = 0;
+        for value in values {
+            write_delta(&mut buf, previous, value);
+            previous = value;
+        }
+        let mut offset = 0;
+        let mut previous = 0;
+        for value in values {
+            let (read, len) = read_delta(&buf[offset..], previous).unwrap();
+            assert_eq!(read, value);
+            offset += len;
+            previous = read;
+        }
+        // small deltas are a single byte.
+        let mut buf = Vec::new();
+        write_delta(&mut buf, 1000, 990);
+        assert_eq!(buf.len(), 1);
+    }
+
+    #[test]
+    fn bits_roundtrip_every_width() {
+        let mut writer = BitWriter::new();
+        let mut expected = Vec::new();
+        for bits in 1..=32u32 {
+            for pattern in [0u32, u32::MAX, 0xa5a5_a5a5, 0x1234_5678] {
+                let value = pattern & max_value(bits);
+                writer.write_bits(value, bits);
+                expected.push((value, bits));
+            }
+        }
+        let total_bits: usize = expected.iter().map(|(_, bits)| *bits as usize).sum();
+        assert_eq!(writer.bits_written(), total_bits);
+        let bytes = writer.finish();
+        assert_eq!(bytes.len(), total_bits.div_ceil(8));
+
+        let mut reader = BitReader::new(&bytes);
+        for (value, bits) in expected {
+            assert_eq!(reader.read_bits(bits).unwrap(), value, "{bits} bits");
+        }
+        assert_eq!(reader.bits_read(), total_bits);
+    }
+
+    #[test]
+    fn bits_are_masked_and_reads_checked() {
+        let mut writer = BitWriter::new();
+        writer.write_bits(0xff, 3);
+        writer.write_bool(true);
+        let bytes = writer.finish();
+        assert_eq!(bytes, [0b1111]);
+
+        let mut reader = BitReader::new(&bytes);
+        assert_eq!(reader.read_bits(3).unwrap(), 0b111);
+        assert!(reader.read_bool().unwrap());
+        assert_eq!(reader.read_bits(4).unwrap(), 0);
+        assert_eq!(
+            reader.read_bits(1),
+            Err(CodecError::UnexpectedEnd { wanted: 1 })
+        );
+
+        // Reused buffers are cleared first.
+        let mut writer = BitWriter::with_buffer(bytes);
+        writer.write_bits(0b10, 2);
+        assert_eq!(writer.finish(), [0b10]);
+    }
+}
@@ -1,5 +1,7 @@
 use std::error::Error;
 use std::fmt::Debug;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 
 /// Errors possible from use of Bichannel.
 #[derive(thiserror::Error, Debug)]
@@ -241,6 +243,8 @@ pub struct TaskShutdownHandle {
 /// A task wrapper that supports graceful shutdown.
 pub struct TaskWithShutdown {
     kill_confirm: Hookshot<(), ()>,
+    /// Set when the task is cancelled through a `TaskRegistry`.
+    cancel: Arc<AtomicBool>,
 }
 
 impl TaskWithShutdown {
@@ -252,20 +256,29 @@ impl TaskWithShutdown {
     pub fn new() -> (TaskShutdownHandle, TaskWithShutdown) {
         let (kill_send, kill_confirm) = Hookshot::new();
         let handle = TaskShutdownHandle { kill_send };
-        let shutdown = TaskWithShutdown { kill_confirm };
+        let shutdown = TaskWithShutdown {
+            kill_confirm,
+            cancel: Arc::new(AtomicBool::new(false)),
+        };
         (handle, shutdown)
     }
 
+    /// The flag cancelling this task, for registering its progress.
+    pub(crate) fn cancel_flag(&self) -> Arc<AtomicBool> {
+        Arc::clone(&self.cancel)
+    }
+
     /// Checks if the task should exit gracefully.
     ///
     /// # Returns
     ///
-    /// A boolean indicating whether the task should exit.
+    /// A boolean indicating whether the task should exit, because it's
+    /// being shut down or was cancelled.
     pub fn should_exit(&mut self) -> bool {
         if let Ok(()) = self.kill_confirm.try_recv() {
             return true;
         }
-        false
+        self.cancel.load(Ordering::Relaxed)
     }
 }
 
@@ -277,7 +290,9 @@ impl TaskShutdownHandle {
     /// A Result indicating success or an error variant from the HookshotError
     /// enum.
     pub async fn shutdown(mut self) -> Result<(), HookshotError> {
-        self.kill_send.send_blocking(())?;
+        // Fails if the task already exited, such as after being cancelled. Its
+        // confirmation is still waiting.
+        let _ = self.kill_send.send_blocking(());
         self.kill_send.recv().await?;
         Ok(())
     }
@@ -289,7 +304,8 @@ impl TaskShutdownHandle {
     /// A `Result` indicating success or an error variant from the `Box<dyn
     /// Error>` type.
     pub fn shutdown_blocking(mut self) -> Result<(), Box<dyn Error>> {
-        self.kill_send.send_blocking(())?;
+        // As in `shutdown`, a task that already exited has confirmed.
+        let _ = self.kill_send.send_blocking(());
         self.kill_send.recv_blocking()?;
         Ok(())
     }
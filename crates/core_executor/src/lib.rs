@@ -4,19 +4,23 @@
 
 pub mod channel;
 mod enrich;
+pub mod progress;
+pub mod queue;
 pub mod scoped;
 pub mod scoped_future;
+pub mod spsc;
 
 use std::future::Future;
 use std::pin::Pin;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::thread::JoinHandle;
 
-use async_channel::Sender;
 use async_executor::LocalExecutor;
-use async_oneshot::Closed;
+use async_oneshot::{Closed, Receiver};
 use enrich::CoreFuture;
 use futures_lite::{future, FutureExt, StreamExt};
+use progress::{Progress, TaskId, TaskRegistry};
+use queue::{ExecutorClosed, QueueMetrics, TaskQueue};
 use scoped_future::Scope;
 
 /// ThreadPoolExecutor is a high-level struct that manages a set of
@@ -74,7 +78,7 @@ pub struct ThreadAffineExecutor {
 /// threads for relaying work to the underlying ThreadAffineExecutor.
 pub struct ThreadAffineSpawner {
     pub core_id: usize,
-    tx: Sender<ExecutorTask>,
+    queue: TaskQueue<ExecutorTask>,
     task_killers: Vec<channel::TaskShutdownHandle>,
 }
 
@@ -87,7 +91,13 @@ enum ExecutorTask {
 
 impl ThreadAffineExecutor {
     pub fn new(core_id: usize) -> Self {
-        let (tx, mut rx) = async_channel::bounded::<ExecutorTask>(100);
+        Self::with_capacity(core_id, queue::DEFAULT_CAPACITY)
+    }
+
+    /// Create an executor queueing up to `capacity` tasks before spawning
+    /// onto it waits, see `queue`.
+    pub fn with_capacity(core_id: usize, capacity: usize) -> Self {
+        let (queue, mut rx) = TaskQueue::<ExecutorTask>::new(core_id, capacity);
         let exec_thread_jh = std::thread::spawn(move || {
             core_affinity::set_for_current(core_affinity::CoreId { id: core_id });
             let local_exec = LocalExecutor::new();
@@ -110,7 +120,7 @@ impl ThreadAffineExecutor {
             _core_id: core_id,
             spawner: ThreadAffineSpawner {
                 core_id,
-                tx,
+                queue,
                 task_killers: Vec::new(),
             },
             exec_thread_jh,
@@ -121,7 +131,7 @@ impl ThreadAffineExecutor {
 impl Drop for ThreadAffineExecutor {
     fn drop(&mut self) {
         if let Some(thread_handle) = self.exec_thread_jh.take() {
-            self.spawner.tx.try_send(ExecutorTask::Exit).unwrap();
+            self.spawner.queue.exit(ExecutorTask::Exit);
             thread_handle.join().unwrap();
         }
     }
@@ -131,7 +141,7 @@ impl Clone for ThreadAffineSpawner {
     fn clone(&self) -> Self {
         Self {
             core_id: self.core_id,
-            tx: self.tx.clone(),
+            queue: self.queue.clone(),
             // We DON'T carry forward killers on clone, so only one spawner is responsible for
             // cleanup. This could be refactored into using Arc...
             task_killers: Vec::new(),
@@ -142,8 +152,14 @@ impl Clone for ThreadAffineSpawner {
 impl<'s> ThreadPoolExecutor {
     /// Create a new ThreadPoolExecutor with the specified number of cores.
     pub fn new(cores: usize) -> Self {
+        Self::with_capacity(cores, queue::DEFAULT_CAPACITY)
+    }
+
+    /// Create a new ThreadPoolExecutor with the specified number of cores,
+    /// each queueing up to `capacity` tasks.
+    pub fn with_capacity(cores: usize, capacity: usize) -> Self {
         let thread_executors = (0..cores)
-            .map(ThreadAffineExecutor::new)
+            .map(|core_id| ThreadAffineExecutor::with_capacity(core_id, capacity))
             .collect::<Vec<_>>();
         ThreadPoolExecutor {
             thread_executors,
@@ -151,6 +167,14 @@ impl<'s> ThreadPoolExecutor {
         }
     }
 
+    /// How full each core's queue is, and has been.
+    pub fn metrics(&self) -> Vec<QueueMetrics> {
+        self.thread_executors
+            .iter()
+            .map(|executor| executor.spawner.metrics())
+            .collect()
+    }
+
     pub fn spawn_on_core<F>(
         &mut self,
         core_id: usize,
@@ -267,31 +291,84 @@ impl ThreadAffineSpawner {
         self.task_killers.push(killer);
     }
 
+    /// Spawn a task with a shutdown guard, as `spawn_with_shutdown` does, and
+    /// a handle reporting its progress to `registry` under `name`. Cancelling
+    /// it through the registry has `shutdown.should_exit()` return true.
+    pub fn spawn_with_progress<T, F>(
+        &mut self,
+        registry: &TaskRegistry,
+        name: &str,
+        task_fn: T,
+    ) -> TaskId
+    where
+        F: Future<Output = ()> + Send + 'static,
+        T: FnOnce(channel::TaskWithShutdown, Progress) -> F,
+    {
+        let (killer, shutdown) = channel::TaskWithShutdown::new();
+        let progress = registry.register_with_cancel(name, shutdown.cancel_flag());
+        let id = progress.id();
+        self.fire(task_fn(shutdown, progress));
+        self.task_killers.push(killer);
+        id
+    }
+
+    /// Spawn a task, returning a future of its output. Blocks the thread
+    /// while the executor's queue is full, so tasks running on an executor
+    /// should spawn with `spawn_async` instead.
     pub fn spawn<F>(&mut self, task: F) -> impl Future<Output = Result<F::Output, Closed>>
     where
         F: Future + Send + 'static,
         F::Output: Send + Sync + 'static,
     {
-        let (mut spawned_tx, spawned_rx) = async_oneshot::oneshot();
-        self.tx
-            .try_send(ExecutorTask::Task(
-                async move {
-                    if let Err(err) = spawned_tx.send(task.await) {
-                        panic!("unable to send task result: {err:?}");
-                    }
-                }
-                .boxed(),
-            ))
+        let (task, spawned_rx) = with_output(task);
+        self.queue
+            .send_blocking(task)
             .expect("unable to execute task");
         spawned_rx
     }
 
+    /// Spawn a task as `spawn` does, waiting for room in the executor's
+    /// queue without blocking the thread.
+    pub async fn spawn_async<F>(&self, task: F) -> Result<Receiver<F::Output>, ExecutorClosed>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + Sync + 'static,
+    {
+        let (task, spawned_rx) = with_output(task);
+        self.queue.send(task).await?;
+        Ok(spawned_rx)
+    }
+
+    /// Spawn a task without its output. Blocks the thread while the
+    /// executor's queue is full, see `spawn`.
     pub fn fire(&mut self, task: impl Future<Output = ()> + Send + 'static) {
-        self.tx
-            .try_send(ExecutorTask::Task(task.boxed()))
+        self.queue
+            .send_blocking(ExecutorTask::Task(task.boxed()))
             .expect("unable to execute task");
     }
 
+    /// Spawn a task without its output, waiting for room in the executor's
+    /// queue without blocking the thread.
+    pub async fn fire_async(
+        &self,
+        task: impl Future<Output = ()> + Send + 'static,
+    ) -> Result<(), ExecutorClosed> {
+        self.queue.send(ExecutorTask::Task(task.boxed())).await
+    }
+
+    /// Spawn a task without its output if there's room in the executor's
+    /// queue, returning whether it was. Tasks that don't fit are counted in
+    /// `QueueMetrics::dropped`, for work that can be skipped under load.
+    pub fn fire_or_drop(&self, task: impl Future<Output = ()> + Send + 'static) -> bool {
+        self.queue.send_or_drop(ExecutorTask::Task(task.boxed()))
+    }
+
+    /// How full the executor's queue is, and has been, counted across every
+    /// clone of this spawner.
+    pub fn metrics(&self) -> QueueMetrics {
+        self.queue.metrics()
+    }
+
     fn block_and_kill_tasks(&mut self) {
         for kill_send in self.task_killers.drain(..) {
             kill_send.shutdown_blocking().expect("unable to kill task");
@@ -299,6 +376,21 @@ impl ThreadAffineSpawner {
     }
 }
 
+/// A task sending the output of `task` to the receiver returned with it.
+fn with_output<F>(task: F) -> (ExecutorTask, Receiver<F::Output>)
+where
+    F: Future + Send + 'static,
+    F::Output: Send + Sync + 'static,
+{
+    let (mut spawned_tx, spawned_rx) = async_oneshot::oneshot();
+    let task = async move {
+        if let Err(err) = spawned_tx.send(task.await) {
+            panic!("unable to send task result: {err:?}");
+        }
+    };
+    (ExecutorTask::Task(task.boxed()), spawned_rx)
+}
+
 impl Drop for ThreadAffineSpawner {
     fn drop(&mut self) {
         self.block_and_kill_tasks();
@@ -407,6 +499,35 @@ mod tests {
         println!("ending thread {:?}", std::thread::current().id());
     }
 
+    #[test]
+    fn saturated_queues_wait_or_drop() {
+        let executor = ThreadAffineExecutor::with_capacity(0, 1);
+        let spawner = executor.spawner.clone();
+        let (started_tx, started_rx) = async_channel::bounded::<()>(1);
+        let (release_tx, release_rx) = async_channel::bounded::<()>(1);
+        // Occupies the executor until released, so the next task fills its queue.
+        assert!(spawner.fire_or_drop(async move {
+            started_tx.send(()).await.unwrap();
+            release_rx.recv().await.unwrap();
+        }));
+        future::block_on(started_rx.recv()).unwrap();
+        assert!(spawner.fire_or_drop(async {}));
+        assert!(!spawner.fire_or_drop(async {}));
+        assert!(spawner.metrics().is_saturated());
+
+        let (spawned, released) = future::block_on(future::zip(
+            spawner.spawn_async(async { 42 }),
+            release_tx.send(()),
+        ));
+        released.unwrap();
+        assert_eq!(future::block_on(spawned.unwrap()), Ok(42));
+
+        let metrics = spawner.metrics();
+        assert_eq!(metrics.sent, 3);
+        assert_eq!(metrics.waited, 1);
+        assert_eq!(metrics.dropped, 1);
+    }
+
     #[test]
     fn test_core_executor_spawn_specific_core() {
         let cores = 4;
@@ -0,0 +1,254 @@
+//! Progress of long-running tasks, such as asset bakes or loads.
+//!
+//! A task reports through its `Progress` handle, and everything registered is
+//! listed by the `TaskRegistry` for a loading screen or debug UI to show.
+//! Cancelling a task through the registry makes its
+//! `TaskWithShutdown::should_exit` return true, so tasks already checking for
+//! shutdown can be cancelled without changes.
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TaskId(u64);
+
+impl std::fmt::Display for TaskId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::str::FromStr for TaskId {
+    type Err = std::num::ParseIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse().map(TaskId)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TaskState {
+    Running,
+    /// Asked to stop, but the task hasn't noticed yet.
+    Cancelling,
+    Finished,
+    Cancelled,
+}
+
+/// A task's progress at the time it was listed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TaskProgress {
+    pub id: TaskId,
+    pub name: String,
+    /// How much is done, from 0 to 1, if the task knows.
+    pub fraction: Option<f32>,
+    /// What the task is doing, such as the file being loaded.
+    pub message: String,
+    /// Time since the task was registered, until it finished.
+    pub elapsed: Duration,
+    pub state: TaskState,
+}
+
+struct Entry {
+    name: String,
+    fraction: Option<f32>,
+    message: String,
+    started: Instant,
+    finished: Option<Instant>,
+    cancel: Arc<AtomicBool>,
+}
+
+#[derive(Default)]
+struct Registry {
+    next_id: AtomicU64,
+    tasks: Mutex<BTreeMap<TaskId, Entry>>,
+}
+
+/// Tasks reporting progress, shared between the tasks and whatever displays
+/// them. Finished tasks are listed until `clear_finished`.
+#[derive(Clone, Default)]
+pub struct TaskRegistry {
+    registry: Arc<Registry>,
+}
+
+impl TaskRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn tasks(&self) -> MutexGuard<'_, BTreeMap<TaskId, Entry>> {
+        // A task that panicked while reporting leaves nothing half updated.
+        self.registry
+            .tasks
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Register a task, returning the handle it reports its progress with.
+    pub fn register(&self, name: &str) -> Progress {
+        self.register_with_cancel(name, Arc::new(AtomicBool::new(false)))
+    }
+
+    pub(crate) fn register_with_cancel(&self, name: &str, cancel: Arc<AtomicBool>) -> Progress {
+        let id = TaskId(self.registry.next_id.fetch_add(1, Ordering::Relaxed));
+        self.tasks().insert(
+            id,
+            Entry {
+                name: name.to_string(),
+                fraction: None,
+                message: String::new(),
+                started: Instant::now(),
+                finished: None,
+                cancel: Arc::clone(&cancel),
+            },
+        );
+        Progress {
+            id,
+            cancel,
+            registry: self.clone(),
+        }
+    }
+
+    /// Every registered task, oldest first.
+    pub fn list(&self) -> Vec<TaskProgress> {
+        let now = Instant::now();
+        self.tasks()
+            .iter()
+            .map(|(id, entry)| {
+                let cancelled = entry.cancel.load(Ordering::Relaxed);
+                let state = match (entry.finished, cancelled) {
+                    (None, false) => TaskState::Running,
+                    (None, true) => TaskState::Cancelling,
+                    (Some(_), false) => TaskState::Finished,
+                    (Some(_), true) => TaskState::Cancelled,
+                };
+                TaskProgress {
+                    id: *id,
+                    name: entry.name.clone(),
+                    fraction: entry.fraction,
+                    message: entry.message.clone(),
+                    elapsed: entry.finished.unwrap_or(now) - entry.started,
+                    state,
+                }
+            })
+            .collect()
+    }
+
+    /// Progress of the running tasks that report a fraction, together, for
+    /// a loading screen. None if none of them do.
+    pub fn overall(&self) -> Option<f32> {
+        let tasks = self.tasks();
+        let fractions = tasks
+            .values()
+            .filter(|entry| entry.finished.is_none())
+            .filter_map(|entry| entry.fraction)
+            .collect::<Vec<_>>();
+        (!fractions.is_empty()).then(|| fractions.iter().sum::<f32>() / fractions.len() as f32)
+    }
+
+    /// Ask a task to stop. Returns false if it isn't running.
+    pub fn cancel(&self, id: TaskId) -> bool {
+        match self.tasks().get(&id) {
+            Some(entry) if entry.finished.is_none() => {
+                entry.cancel.store(true, Ordering::Relaxed);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Stop listing tasks that have finished.
+    pub fn clear_finished(&self) {
+        self.tasks().retain(|_, entry| entry.finished.is_none());
+    }
+
+    fn update(&self, id: TaskId, update: impl FnOnce(&mut Entry)) {
+        if let Some(entry) = self.tasks().get_mut(&id) {
+            update(entry);
+        }
+    }
+}
+
+/// A task's handle for reporting its progress. The task is listed as finished
+/// once this is dropped.
+pub struct Progress {
+    id: TaskId,
+    cancel: Arc<AtomicBool>,
+    registry: TaskRegistry,
+}
+
+impl Progress {
+    pub fn id(&self) -> TaskId {
+        self.id
+    }
+
+    /// Report how much is done, from 0 to 1, and what's being done.
+    pub fn set(&self, fraction: f32, message: impl Into<String>) {
+        let message = message.into();
+        self.registry.update(self.id, |entry| {
+            entry.fraction = Some(fraction.clamp(0.0, 1.0));
+            entry.message = message;
+        });
+    }
+
+    pub fn set_fraction(&self, fraction: f32) {
+        self.registry.update(self.id, |entry| {
+            entry.fraction = Some(fraction.clamp(0.0, 1.0))
+        });
+    }
+
+    pub fn set_message(&self, message: impl Into<String>) {
+        let message = message.into();
+        self.registry
+            .update(self.id, |entry| entry.message = message);
+    }
+
+    /// Whether the task was cancelled through the registry.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for Progress {
+    fn drop(&mut self) {
+        self.registry
+            .update(self.id, |entry| entry.finished = Some(Instant::now()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lists_progress_and_cancels() {
+        let registry = TaskRegistry::new();
+        let bake = registry.register("bake");
+        let load = registry.register("load");
+        assert_eq!(registry.overall(), None);
+
+        bake.set(0.25, "lightmaps");
+        load.set_fraction(0.75);
+        assert_eq!(registry.overall(), Some(0.5));
+        let tasks = registry.list();
+        assert_eq!(tasks[0].name, "bake");
+        assert_eq!(tasks[0].message, "lightmaps");
+        assert_eq!(tasks[1].state, TaskState::Running);
+
+        assert!(registry.cancel(load.id()));
+        assert!(load.is_cancelled());
+        assert_eq!(registry.list()[1].state, TaskState::Cancelling);
+        let load_id = load.id();
+        drop(load);
+        assert_eq!(registry.list()[1].state, TaskState::Cancelled);
+        assert!(!registry.cancel(load_id));
+        assert_eq!(registry.overall(), Some(0.25));
+
+        drop(bake);
+        assert_eq!(registry.list()[0].state, TaskState::Finished);
+        registry.clear_finished();
+        assert!(registry.list().is_empty());
+    }
+}
@@ -0,0 +1,195 @@
+//! The bounded queues spawners send tasks to their executor's thread through,
+//! and how full they've been.
+//!
+//! An executor runs its tasks one at a time, so a burst of work, or one slow
+//! task, can fill its queue. Spawning then waits for room: `spawn` and `fire`
+//! block the calling thread, `spawn_async` and `fire_async` yield instead, and
+//! `fire_or_drop` gives up and counts the task as dropped. Each wait and drop
+//! is counted, so a saturated queue shows up in `QueueMetrics` rather than as
+//! a stall nobody can explain.
+
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use async_channel::{Receiver, Sender, TrySendError};
+
+/// Tasks an executor queues before spawning onto it waits, unless it's
+/// created with a capacity of its own.
+pub const DEFAULT_CAPACITY: usize = 100;
+
+#[derive(thiserror::Error, Debug, Clone, Copy, PartialEq, Eq)]
+#[error("the executor on core {0} has exited")]
+pub struct ExecutorClosed(pub usize);
+
+/// How full a queue has been, shared by every clone of its spawner.
+#[derive(Debug, Default)]
+struct Counters {
+    sent: AtomicU64,
+    waited: AtomicU64,
+    dropped: AtomicU64,
+    high_water: AtomicUsize,
+}
+
+/// A snapshot of a queue, see `ThreadAffineSpawner::metrics`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct QueueMetrics {
+    pub core_id: usize,
+    pub capacity: usize,
+    /// Tasks waiting to run now.
+    pub queued: usize,
+    /// Tasks queued since the executor was created.
+    pub sent: u64,
+    /// Tasks that found the queue full and waited for room.
+    pub waited: u64,
+    /// Tasks `fire_or_drop` dropped because the queue was full.
+    pub dropped: u64,
+    /// Most tasks waiting at once.
+    pub high_water: usize,
+}
+
+impl QueueMetrics {
+    /// Whether spawning now would have to wait.
+    pub fn is_saturated(&self) -> bool {
+        self.queued >= self.capacity
+    }
+}
+
+pub(crate) struct TaskQueue<T> {
+    core_id: usize,
+    tx: Sender<T>,
+    counters: Arc<Counters>,
+}
+
+impl<T> Clone for TaskQueue<T> {
+    fn clone(&self) -> Self {
+        Self {
+            core_id: self.core_id,
+            tx: self.tx.clone(),
+            counters: Arc::clone(&self.counters),
+        }
+    }
+}
+
+impl<T> TaskQueue<T> {
+    pub(crate) fn new(core_id: usize, capacity: usize) -> (Self, Receiver<T>) {
+        let (tx, rx) = async_channel::bounded(capacity);
+        let queue = Self {
+            core_id,
+            tx,
+            counters: Arc::default(),
+        };
+        (queue, rx)
+    }
+
+    /// Queue a task, blocking the thread while the queue is full.
+    pub(crate) fn send_blocking(&self, task: T) -> Result<(), ExecutorClosed> {
+        match self.tx.try_send(task) {
+            Ok(()) => {}
+            Err(TrySendError::Full(task)) => {
+                self.counters.waited.fetch_add(1, Ordering::Relaxed);
+                self.tx
+                    .send_blocking(task)
+                    .map_err(|_| ExecutorClosed(self.core_id))?;
+            }
+            Err(TrySendError::Closed(_)) => return Err(ExecutorClosed(self.core_id)),
+        }
+        self.record_sent();
+        Ok(())
+    }
+
+    /// Queue a task, waiting while the queue is full.
+    pub(crate) async fn send(&self, task: T) -> Result<(), ExecutorClosed> {
+        if self.tx.is_full() {
+            self.counters.waited.fetch_add(1, Ordering::Relaxed);
+        }
+        self.tx
+            .send(task)
+            .await
+            .map_err(|_| ExecutorClosed(self.core_id))?;
+        self.record_sent();
+        Ok(())
+    }
+
+    /// Queue a task if there's room, returning whether it was queued. Tasks
+    /// that don't fit are counted as dropped.
+    pub(crate) fn send_or_drop(&self, task: T) -> bool {
+        match self.tx.try_send(task) {
+            Ok(()) => {
+                self.record_sent();
+                true
+            }
+            Err(TrySendError::Full(_)) => {
+                self.counters.dropped.fetch_add(1, Ordering::Relaxed);
+                false
+            }
+            Err(TrySendError::Closed(_)) => false,
+        }
+    }
+
+    /// Ask the executor to exit once the tasks queued before it have run,
+    /// waiting for room, unless it's exited already.
+    pub(crate) fn exit(&self, exit: T) {
+        let _ = self.tx.send_blocking(exit);
+    }
+
+    pub(crate) fn metrics(&self) -> QueueMetrics {
+        QueueMetrics {
+            core_id: self.core_id,
+            capacity: self.tx.capacity().unwrap_or(usize::MAX),
+            queued: self.tx.len(),
+            sent: self.counters.sent.load(Ordering::Relaxed),
+            waited: self.counters.waited.load(Ordering::Relaxed),
+            dropped: self.counters.dropped.load(Ordering::Relaxed),
+            high_water: self.counters.high_water.load(Ordering::Relaxed),
+        }
+    }
+
+    fn record_sent(&self) {
+        self.counters.sent.fetch_add(1, Ordering::Relaxed);
+        self.counters
+            .high_water
+            .fetch_max(self.tx.len(), Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_lite::future;
+
+    use super::*;
+
+    #[test]
+    fn full_queues_drop_or_wait() {
+        let (queue, rx) = TaskQueue::new(3, 2);
+        assert!(queue.send_or_drop(1));
+        assert!(queue.send_or_drop(2));
+        assert!(!queue.send_or_drop(3));
+        assert!(queue.metrics().is_saturated());
+
+        let sender = queue.clone();
+        let waiting = std::thread::spawn(move || sender.send_blocking(4));
+        assert_eq!(future::block_on(rx.recv()), Ok(1));
+        assert_eq!(waiting.join().unwrap(), Ok(()));
+        assert_eq!(future::block_on(rx.recv()), Ok(2));
+        assert_eq!(future::block_on(rx.recv()), Ok(4));
+
+        let metrics = queue.metrics();
+        assert_eq!(metrics.core_id, 3);
+        assert_eq!(metrics.capacity, 2);
+        assert_eq!(metrics.queued, 0);
+        assert_eq!(metrics.sent, 3);
+        assert_eq!(metrics.dropped, 1);
+        assert_eq!(metrics.high_water, 2);
+        assert!(!metrics.is_saturated());
+    }
+
+    #[test]
+    fn sending_to_an_exited_executor_fails() {
+        let (queue, rx) = TaskQueue::new(0, 1);
+        drop(rx);
+        assert_eq!(queue.send_blocking(1), Err(ExecutorClosed(0)));
+        assert_eq!(future::block_on(queue.send(2)), Err(ExecutorClosed(0)));
+        assert!(!queue.send_or_drop(3));
+        assert_eq!(queue.metrics().dropped, 0);
+    }
+}
@@ -2,13 +2,13 @@ use std::pin::Pin;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::thread::{Scope, ScopedJoinHandle};
 
-use async_channel::Sender;
 use async_executor::LocalExecutor;
 use async_oneshot::Closed;
 use futures_lite::{future, Future, FutureExt, StreamExt};
 
 use crate::channel;
 use crate::enrich::CoreFuture;
+use crate::queue::{self, QueueMetrics, TaskQueue};
 
 type ScopedPinnedTask<'task> = Pin<Box<dyn Future<Output = ()> + Send + 'task>>;
 /// ExecutorTask represents a single task to be executed by a ThreadExecutor.
@@ -26,7 +26,7 @@ pub struct ScopedThreadAffineExecutor<'scope> {
 
 pub struct ScopedThreadAffineSpawner<'task> {
     pub core_id: usize,
-    tx: Sender<ScopedExecutorTask<'task>>,
+    queue: TaskQueue<ScopedExecutorTask<'task>>,
     task_killers: Vec<channel::TaskShutdownHandle>,
 }
 
@@ -34,7 +34,7 @@ impl<'task> Clone for ScopedThreadAffineSpawner<'task> {
     fn clone(&self) -> Self {
         Self {
             core_id: self.core_id,
-            tx: self.tx.clone(),
+            queue: self.queue.clone(),
             task_killers: Vec::new(),
         }
     }
@@ -43,7 +43,17 @@ impl<'task> Clone for ScopedThreadAffineSpawner<'task> {
 
 impl<'scope> ScopedThreadAffineExecutor<'scope> {
     pub fn new(core_id: usize, scope: &'scope Scope<'scope, '_>) -> Self {
-        let (tx, mut rx) = async_channel::bounded::<ScopedExecutorTask<'scope>>(100);
+        Self::with_capacity(core_id, queue::DEFAULT_CAPACITY, scope)
+    }
+
+    /// Create an executor queueing up to `capacity` tasks before spawning
+    /// onto it waits, see `queue`.
+    pub fn with_capacity(
+        core_id: usize,
+        capacity: usize,
+        scope: &'scope Scope<'scope, '_>,
+    ) -> Self {
+        let (queue, mut rx) = TaskQueue::<ScopedExecutorTask<'scope>>::new(core_id, capacity);
         let exec_thread_jh = scope.spawn(move || {
             core_affinity::set_for_current(core_affinity::CoreId { id: core_id });
             let local_exec = LocalExecutor::new();
@@ -67,7 +77,7 @@ impl<'scope> ScopedThreadAffineExecutor<'scope> {
             _core_id: core_id,
             spawner: ScopedThreadAffineSpawner {
                 core_id,
-                tx,
+                queue,
                 task_killers: Vec::new(),
             },
             exec_thread_jh,
@@ -78,21 +88,23 @@ impl<'scope> ScopedThreadAffineExecutor<'scope> {
 impl<'a> Drop for ScopedThreadAffineExecutor<'a> {
     fn drop(&mut self) {
         if let Some(thread_handle) = self.exec_thread_jh.take() {
-            self.spawner.tx.try_send(ScopedExecutorTask::Exit).unwrap();
+            self.spawner.queue.exit(ScopedExecutorTask::Exit);
             thread_handle.join().unwrap();
         }
     }
 }
 
 impl<'task> ScopedThreadAffineSpawner<'task> {
+    /// Spawn a task, returning a future of its output. Blocks the thread
+    /// while the executor's queue is full.
     pub fn spawn<F>(&mut self, task: F) -> impl Future<Output = Result<F::Output, Closed>> + 'task
     where
         F: Future + Send + 'task,
         F::Output: std::fmt::Debug + Send + Sync + 'task,
     {
         let (mut spawned_tx, spawned_rx) = async_oneshot::oneshot();
-        self.tx
-            .try_send(ScopedExecutorTask::Task(
+        self.queue
+            .send_blocking(ScopedExecutorTask::Task(
                 async move {
                     if let Err(err) = spawned_tx.send(task.await) {
                         panic!("unable to send task result: {err:?}");
@@ -103,6 +115,11 @@ impl<'task> ScopedThreadAffineSpawner<'task> {
             .expect("unable to execute task");
         spawned_rx
     }
+
+    /// How full the executor's queue is, and has been.
+    pub fn metrics(&self) -> QueueMetrics {
+        self.queue.metrics()
+    }
 }
 
 pub struct ScopedThreadPoolExecutor<'scope> {
@@ -0,0 +1,207 @@
+//! A bounded, lock-free queue between exactly one producer and one consumer.
+//!
+//! Neither end ever blocks: pushing to a full queue hands the value back, and
+//! popping an empty one returns None. This suits threads that mustn't wait on
+//! each other, such as a simulation handing snapshots to a network thread.
+
+use std::cell::UnsafeCell;
+use std::mem::MaybeUninit;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// Create a queue holding at least `capacity` values, rounded up to a power of
+/// two.
+pub fn bounded<T>(capacity: usize) -> (Producer<T>, Consumer<T>) {
+    let capacity = capacity.max(1).next_power_of_two();
+    let ring = Arc::new(Ring {
+        slots: (0..capacity)
+            .map(|_| UnsafeCell::new(MaybeUninit::uninit()))
+            .collect(),
+        mask: capacity - 1,
+        head: CachePadded(AtomicUsize::new(0)),
+        tail: CachePadded(AtomicUsize::new(0)),
+    });
+    (
+        Producer {
+            ring: Arc::clone(&ring),
+        },
+        Consumer { ring },
+    )
+}
+
+/// The value pushed to a full queue, handed back.
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+#[error("queue full")]
+pub struct Full<T>(pub T);
+
+// Keep the indices written by each end on their own cache line.
+#[repr(align(64))]
+struct CachePadded<T>(T);
+
+struct Ring<T> {
+    slots: Box<[UnsafeCell<MaybeUninit<T>>]>,
+    mask: usize,
+    /// Index of the next value to pop, only written by the consumer.
+    head: CachePadded<AtomicUsize>,
+    /// Index of the next value to push, only written by the producer.
+    tail: CachePadded<AtomicUsize>,
+}
+
+// Each slot is only accessed by one end at a time, handed over by the release
+// and acquire of `head` and `tail`.
+unsafe impl<T: Send> Send for Ring<T> {}
+unsafe impl<T: Send> Sync for Ring<T> {}
+
+impl<T> Ring<T> {
+    fn len(&self) -> usize {
+        let tail = self.tail.0.load(Ordering::Acquire);
+        let head = self.head.0.load(Ordering::Acquire);
+        tail.wrapping_sub(head)
+    }
+}
+
+impl<T> Drop for Ring<T> {
+    fn drop(&mut self) {
+        let tail = *self.tail.0.get_mut();
+        let mut head = *self.head.0.get_mut();
+        while head != tail {
+            unsafe { self.slots[head & self.mask].get_mut().assume_init_drop() };
+            head = head.wrapping_add(1);
+        }
+    }
+}
+
+/// The sending end of a queue, see `bounded`.
+pub struct Producer<T> {
+    ring: Arc<Ring<T>>,
+}
+
+impl<T> Producer<T> {
+    /// Push a value, or hand it back if the queue is full.
+    pub fn push(&mut self, value: T) -> Result<(), Full<T>> {
+        let ring = &*self.ring;
+        let tail = ring.tail.0.load(Ordering::Relaxed);
+        let head = ring.head.0.load(Ordering::Acquire);
+        if tail.wrapping_sub(head) == ring.slots.len() {
+            return Err(Full(value));
+        }
+        unsafe { (*ring.slots[tail & ring.mask].get()).write(value) };
+        ring.tail.0.store(tail.wrapping_add(1), Ordering::Release);
+        Ok(())
+    }
+
+    /// Values pushed that haven't been popped yet.
+    pub fn len(&self) -> usize {
+        self.ring.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Whether the consumer has been dropped, so nothing pushed will be seen.
+    pub fn is_closed(&self) -> bool {
+        Arc::strong_count(&self.ring) == 1
+    }
+}
+
+/// The receiving end of a queue, see `bounded`.
+pub struct Consumer<T> {
+    ring: Arc<Ring<T>>,
+}
+
+impl<T> Consumer<T> {
+    /// Pop the oldest value, if there is one.
+    pub fn pop(&mut self) -> Option<T> {
+        let ring = &*self.ring;
+        let head = ring.head.0.load(Ordering::Relaxed);
+        let tail = ring.tail.0.load(Ordering::Acquire);
+        if head == tail {
+            return None;
+        }
+        let value = unsafe { (*ring.slots[head & ring.mask].get()).assume_init_read() };
+        ring.head.0.store(head.wrapping_add(1), Ordering::Release);
+        Some(value)
+    }
+
+    /// Pop everything pushed so far.
+    pub fn drain(&mut self) -> impl Iterator<Item = T> + '_ {
+        std::iter::from_fn(|| self.pop())
+    }
+
+    /// Values pushed that haven't been popped yet.
+    pub fn len(&self) -> usize {
+        self.ring.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Whether the producer has been dropped, so nothing more will arrive
+    /// after what's queued.
+    pub fn is_closed(&self) -> bool {
+        Arc::strong_count(&self.ring) == 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use super::*;
+
+    #[test]
+    fn in_order_until_full() {
+        let (mut producer, mut consumer) = bounded(3);
+        for value in 0..4 {
+            producer.push(value).unwrap();
+        }
+        assert_eq!(producer.push(4), Err(Full(4)));
+        assert_eq!(consumer.pop(), Some(0));
+        producer.push(4).unwrap();
+        assert_eq!(consumer.drain().collect::<Vec<_>>(), vec![1, 2, 3, 4]);
+        assert_eq!(consumer.pop(), None);
+
+        assert!(!consumer.is_closed());
+        drop(producer);
+        assert!(consumer.is_closed());
+    }
+
+    #[test]
+    fn across_threads() {
+        let (mut producer, mut consumer) = bounded(16);
+        let pushing = std::thread::spawn(move || {
+            for mut value in 0..10_000u32 {
+                while let Err(Full(full)) = producer.push(value) {
+                    value = full;
+                    std::thread::yield_now();
+                }
+            }
+        });
+        let mut expected = 0;
+        while expected < 10_000 {
+            match consumer.pop() {
+                Some(value) => {
+                    assert_eq!(value, expected);
+                    expected += 1;
+                }
+                None => std::thread::yield_now(),
+            }
+        }
+        pushing.join().unwrap();
+    }
+
+    #[test]
+    fn drops_values_left_queued() {
+        let value = Rc::new(());
+        {
+            let (mut producer, mut consumer) = bounded(4);
+            for _ in 0..3 {
+                producer.push(Rc::clone(&value)).ok();
+            }
+            consumer.pop();
+        }
+        assert_eq!(Rc::strong_count(&value), 1);
+    }
+}
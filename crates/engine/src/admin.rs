@@ -0,0 +1,390 @@
+//! Administering a server, such as a dedicated one without a window: console
+//! commands for its players and scene, and an optional admin socket taking
+//! the same console commands as stdin from tools and remote shells.
+//!
+//! The socket only listens on localhost. A connection first sends the admin
+//! token on a line of its own, then a command per line. Each command's output
+//! is followed by an empty line, and errors start with `error: `.
+
+use std::cell::RefCell;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::sync::mpsc::RecvTimeoutError;
+use std::time::{Duration, SystemTime};
+use std::{fmt, thread};
+
+use logger::{info, warn, Logger};
+use world::components::spatial::SpatialHierarchyNode;
+use world::journal::JournalEvent;
+use world::notifications::Severity;
+use world::Client;
+
+use crate::console::{Console, ConsoleSender};
+
+/// Longest line read from an admin connection.
+const MAX_LINE: u64 = 4096;
+/// How long a connection has to send the token.
+const AUTH_TIMEOUT: Duration = Duration::from_secs(10);
+/// Pause before refusing a wrong token, to slow down guessing.
+const AUTH_FAILURE_DELAY: Duration = Duration::from_secs(1);
+/// How long to wait for a command to run, which it won't if the frame loop
+/// has stopped.
+const REPLY_TIMEOUT: Duration = Duration::from_secs(10);
+/// Journaled events the `journal` command shows unless told otherwise.
+const JOURNAL_LINES: usize = 20;
+
+/// Port the admin socket listens on, and the token connections authenticate
+/// with.
+#[derive(Clone, PartialEq, Eq)]
+pub struct AdminSocket {
+    pub port: u16,
+    pub token: String,
+}
+
+impl fmt::Debug for AdminSocket {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AdminSocket")
+            .field("port", &self.port)
+            .finish_non_exhaustive()
+    }
+}
+
+/// What admin commands ask of the frame loop, carried out before the next
+/// frame's systems update.
+#[derive(Debug, Default)]
+pub(crate) struct AdminRequests {
+    pub reload_scene: bool,
+    /// Where `journal_dump` writes the journal, the crash report directory.
+    pub journal_dir: PathBuf,
+}
+
+pub(crate) fn register_commands(console: &mut Console, requests: &Rc<RefCell<AdminRequests>>) {
+    console.register(
+        "players",
+        "list players, who controls them and where they are",
+        |world, _args| {
+            if world.players.is_empty() {
+                return Ok("no players".to_string());
+            }
+            let client = match world.clients.first() {
+                Some(client) => format!("client {}", client_name(client)),
+                None if world.connection.is_some() => "client, connected".to_string(),
+                None => "client, not connected".to_string(),
+            };
+            Ok(world
+                .players
+                .iter()
+                .enumerate()
+                .map(|(index, player)| {
+                    let controller = match index {
+                        0 => "server",
+                        1 => &client,
+                        _ => "nobody",
+                    };
+                    match world.hecs_world.get::<&SpatialHierarchyNode>(*player) {
+                        Ok(node) => {
+                            let pos = node.get_pos();
+                            format!(
+                                "player {index} ({controller}): {player:?} at {:.1} {:.1} {:.1}",
+                                pos.x, pos.y, pos.z
+                            )
+                        }
+                        Err(_) => format!("player {index} ({controller}): {player:?}, despawned"),
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join("\n"))
+        },
+    );
+    console.register(
+        "clients",
+        "list the clients connected, the first controls the client's player",
+        |world, _args| {
+            if world.clients.is_empty() {
+                return Ok("no clients".to_string());
+            }
+            Ok(world
+                .clients
+                .iter()
+                .map(|client| {
+                    let packets = client.connection.packet_counts();
+                    let quality = client.connection.quality_sample().map_or_else(
+                        || "rtt unknown".to_string(),
+                        |sample| format!("rtt {:?}, loss {:.1}%", sample.rtt, sample.loss * 100.0),
+                    );
+                    format!(
+                        "{}: {}, {quality}, {} sent, {} received",
+                        client_name(client),
+                        client.compression,
+                        packets.sent,
+                        packets.received
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join("\n"))
+        },
+    );
+    console.register(
+        "kick",
+        "disconnect a client, by [address] if there's more than one, giving a [reason]",
+        |world, args| {
+            if !world.is_server() {
+                return Err("only a server can kick".to_string());
+            }
+            let addr = args.first().and_then(|arg| arg.parse::<SocketAddr>().ok());
+            let (index, reason) = match addr {
+                Some(addr) => (
+                    world
+                        .clients
+                        .iter()
+                        .position(|client| client.addr == Some(addr))
+                        .ok_or_else(|| format!("no client is connected from {addr}"))?,
+                    &args[1..],
+                ),
+                None => match world.clients.len() {
+                    0 => return Err("no client is connected".to_string()),
+                    1 => (0, args),
+                    _ => return Err("give the address of the client to kick".to_string()),
+                },
+            };
+            let client = world.clients.remove(index);
+            let reason = if reason.is_empty() {
+                "no reason given".to_string()
+            } else {
+                reason.join(" ")
+            };
+            world.record(JournalEvent::ClientDisconnected {
+                addr: client.addr,
+                reason: format!("kicked, {reason}"),
+            });
+            let kicked = format!("kicked client {}: {reason}", client_name(&client));
+            world.notify(Severity::Info, "admin", kicked.clone());
+            Ok(kicked)
+        },
+    );
+    let reload = Rc::clone(requests);
+    console.register(
+        "scene_reload",
+        "unload the scene and load it again, as the asset loader does on start",
+        move |_world, _args| {
+            reload.borrow_mut().reload_scene = true;
+            Ok("reloading the scene before the next frame".to_string())
+        },
+    );
+    console.register(
+        "journal",
+        "show the last [count] significant events, such as spawns and connections",
+        |world, args| {
+            let count = match args.first() {
+                Some(count) => count
+                    .parse()
+                    .map_err(|_| format!("{count:?} isn't a number of events"))?,
+                None => JOURNAL_LINES,
+            };
+            let recent = world.journal.recent(count);
+            if recent.is_empty() {
+                return Ok("nothing journaled".to_string());
+            }
+            Ok(recent
+                .iter()
+                .map(|entry| entry.to_string())
+                .collect::<Vec<_>>()
+                .join("\n"))
+        },
+    );
+    let dump = Rc::clone(requests);
+    console.register(
+        "journal_dump",
+        "write the journal to a file in the crash report directory",
+        move |world, _args| {
+            let dir = dump.borrow().journal_dir.clone();
+            let path = world
+                .journal
+                .dump(&dir, SystemTime::now())
+                .map_err(|err| format!("unable to write the journal to {dir:?}: {err}"))?;
+            Ok(format!("journal written to {}", path.display()))
+        },
+    );
+}
+
+/// A client's address, or what it is if it has none.
+fn client_name(client: &Client) -> String {
+    client
+        .addr
+        .map_or_else(|| "(provided)".to_string(), |addr| addr.to_string())
+}
+
+/// Listen on localhost, running each line of an authenticated connection
+/// through the console. Returns the address listened on.
+pub(crate) fn serve(
+    socket: &AdminSocket,
+    sender: ConsoleSender,
+    logger: &Logger,
+) -> io::Result<SocketAddr> {
+    if socket.token.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "the admin socket needs a token",
+        ));
+    }
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, socket.port))?;
+    let addr = listener.local_addr()?;
+    info!(logger, "admin socket listening on {addr}");
+    let token = socket.token.clone();
+    let logger = logger.sub("admin");
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(err) => {
+                    warn!(logger, "unable to accept an admin connection: {err}");
+                    continue;
+                }
+            };
+            let (token, sender) = (token.clone(), sender.clone());
+            let logger = logger.sub("connection");
+            thread::spawn(move || {
+                let peer = stream
+                    .peer_addr()
+                    .map_or_else(|_| "unknown".to_string(), |peer| peer.to_string());
+                match handle_connection(stream, &token, &sender) {
+                    Ok(()) => info!(logger, "admin {peer} disconnected"),
+                    Err(err) => warn!(logger, "admin {peer}: {err}"),
+                }
+            });
+        }
+    });
+    Ok(addr)
+}
+
+fn handle_connection(stream: TcpStream, token: &str, sender: &ConsoleSender) -> io::Result<()> {
+    // Timeouts are set on the socket, so apply to both halves.
+    stream.set_read_timeout(Some(AUTH_TIMEOUT))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = stream;
+
+    let mut line = String::new();
+    read_line(&mut reader, &mut line)?;
+    if !token_matches(line.trim_end(), token) {
+        thread::sleep(AUTH_FAILURE_DELAY);
+        writer.write_all(b"error: wrong token\n\n")?;
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "wrong token",
+        ));
+    }
+    writer.set_read_timeout(None)?;
+    writer.write_all(b"ok\n\n")?;
+
+    loop {
+        line.clear();
+        if read_line(&mut reader, &mut line)? == 0 {
+            return Ok(());
+        }
+        let output = match sender.request(line.trim().to_string()) {
+            Ok(reply) => match reply.recv_timeout(REPLY_TIMEOUT) {
+                Ok(Ok(output)) => output,
+                Ok(Err(err)) => format!("error: {err}"),
+                Err(RecvTimeoutError::Timeout) => {
+                    "error: timed out waiting for the command to run".to_string()
+                }
+                Err(RecvTimeoutError::Disconnected) => "error: the engine has exited".to_string(),
+            },
+            Err(_) => "error: the engine has exited".to_string(),
+        };
+        if !output.is_empty() {
+            writer.write_all(output.as_bytes())?;
+            writer.write_all(b"\n")?;
+        }
+        writer.write_all(b"\n")?;
+    }
+}
+
+/// Read a line of at most `MAX_LINE` bytes, returning 0 at the end of the
+/// stream.
+fn read_line(reader: &mut impl BufRead, line: &mut String) -> io::Result<usize> {
+    let read = reader.take(MAX_LINE).read_line(line)?;
+    if read as u64 == MAX_LINE && !line.ends_with('\n') {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "line too long"));
+    }
+    Ok(read)
+}
+
+/// Compare tokens without stopping at the first difference, which would tell
+/// a guesser how much of theirs was right.
+fn token_matches(given: &str, token: &str) -> bool {
+    given.len() == token.len()
+        && given
+            .bytes()
+            .zip(token.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use logger::LogLevel;
+    use world::World;
+
+    use super::*;
+
+    #[test]
+    fn compares_whole_tokens() {
+        assert!(token_matches("hunter2", "hunter2"));
+        assert!(!token_matches("hunter", "hunter2"));
+        assert!(!token_matches("hunter3", "hunter2"));
+        assert!(!token_matches("", "hunter2"));
+    }
+
+    #[test]
+    fn runs_commands_from_authenticated_connections() {
+        let logger = LogLevel::Info.logger();
+        let mut world = World::new(None, &logger, true);
+        let mut console = Console::with_builtin_commands();
+        console.register("echo", "print the arguments", |_world, args| {
+            Ok(args.join(" "))
+        });
+        let socket = AdminSocket {
+            port: 0,
+            token: "hunter2".to_string(),
+        };
+        let addr = serve(&socket, console.sender(), &logger).unwrap();
+
+        let client = thread::spawn(move || {
+            let stream = TcpStream::connect(addr).unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut writer = stream;
+            writer
+                .write_all(b"hunter2\necho hi there\nplayers\n")
+                .unwrap();
+            let mut lines = Vec::new();
+            for _ in 0..6 {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                lines.push(line.trim_end().to_string());
+            }
+            lines
+        });
+        let started = Instant::now();
+        while !client.is_finished() && started.elapsed() < Duration::from_secs(5) {
+            console.run_pending(&mut world);
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(
+            client.join().unwrap(),
+            ["ok", "", "hi there", "", "no players", ""]
+        );
+
+        let stream = TcpStream::connect(addr).unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut writer = stream;
+        writer.write_all(b"hunter3\n").unwrap();
+        let mut reply = String::new();
+        reader.read_to_string(&mut reply).unwrap();
+        assert_eq!(reply, "error: wrong token\n\n");
+    }
+}
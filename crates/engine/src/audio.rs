@@ -0,0 +1,136 @@
+//! Plays the world's `AudioEmitter`s through the platform's sound mixer, on
+//! the sfx bus, attenuated by their distance to the `AudioListener` and muffled
+//! by their occlusion. Without a listener they play at their own gain.
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use logger::{warn, Logger};
+use platform::audio::{distance_attenuation, occlusion_filter, Bus};
+use platform::sound::{AudioMixer, PlayOptions, SoundHandle, VoiceId};
+use world::components::{AudioEmitter, AudioListener, WorldTransform};
+use world::{Entity, World};
+
+/// The voices playing for emitters, by entity.
+#[derive(Default)]
+pub(crate) struct EmitterVoices {
+    looping: HashMap<Entity, (PathBuf, VoiceId)>,
+    one_shots: Vec<(Entity, VoiceId)>,
+    // Sounds that failed to load, reported once.
+    failed: HashSet<PathBuf>,
+}
+
+impl EmitterVoices {
+    /// Start the sounds emitters asked for, attenuate what they're playing
+    /// by where they are now, and stop the loops of emitters that are gone.
+    pub fn update(&mut self, world: &mut World, sound: &mut AudioMixer, logger: &Logger) {
+        let listener = world
+            .hecs_world
+            .query_mut::<(&AudioListener, &WorldTransform)>()
+            .into_iter()
+            .next()
+            .map(|(_, (_, transform))| transform.get_pos());
+
+        let mut seen = HashSet::new();
+        for (entity, (emitter, transform)) in world
+            .hecs_world
+            .query_mut::<(&mut AudioEmitter, &WorldTransform)>()
+        {
+            seen.insert(entity);
+            let (occluded_gain, low_pass) = occlusion_filter(emitter.occlusion);
+            let gain = emitter.gain
+                * occluded_gain
+                * listener.map_or(1.0, |listener| {
+                    distance_attenuation(
+                        transform.get_pos().distance(listener),
+                        emitter.min_distance,
+                        emitter.max_distance,
+                    )
+                });
+
+            for (_, voice) in self.one_shots.iter().filter(|(of, _)| *of == entity) {
+                sound.set_gain(*voice, gain);
+                sound.set_low_pass(*voice, low_pass);
+            }
+            for path in emitter.take_one_shots() {
+                if let Some(handle) = self.load(sound, &path, logger) {
+                    let voice = sound.play(
+                        handle,
+                        PlayOptions {
+                            bus: Bus::Sfx,
+                            gain,
+                            looping: false,
+                            low_pass,
+                        },
+                    );
+                    self.one_shots.push((entity, voice));
+                }
+            }
+
+            let unchanged = match (&emitter.looping, self.looping.get(&entity)) {
+                (Some(path), Some((playing, _))) => path == playing,
+                (None, None) => true,
+                _ => false,
+            };
+            if unchanged {
+                if let Some((_, voice)) = self.looping.get(&entity) {
+                    sound.set_gain(*voice, gain);
+                    sound.set_low_pass(*voice, low_pass);
+                }
+                continue;
+            }
+            if let Some((_, voice)) = self.looping.remove(&entity) {
+                sound.stop(voice);
+            }
+            if let Some(path) = &emitter.looping {
+                if let Some(handle) = self.load(sound, path, logger) {
+                    let voice = sound.play(
+                        handle,
+                        PlayOptions {
+                            bus: Bus::Sfx,
+                            gain,
+                            looping: true,
+                            low_pass,
+                        },
+                    );
+                    self.looping.insert(entity, (path.clone(), voice));
+                }
+            }
+        }
+
+        self.looping.retain(|entity, (_, voice)| {
+            let keep = seen.contains(entity);
+            if !keep {
+                sound.stop(*voice);
+            }
+            keep
+        });
+        // One-shots follow their emitter until they end, and end with it.
+        self.one_shots.retain(|(entity, voice)| {
+            if !seen.contains(entity) {
+                sound.stop(*voice);
+                return false;
+            }
+            sound.is_playing(*voice)
+        });
+    }
+
+    fn load(
+        &mut self,
+        sound: &mut AudioMixer,
+        path: &Path,
+        logger: &Logger,
+    ) -> Option<SoundHandle> {
+        if self.failed.contains(path) {
+            return None;
+        }
+        match sound.load(path) {
+            Ok(handle) => Some(handle),
+            Err(err) => {
+                warn!(logger, "emitters won't play {}: {err}", path.display());
+                self.failed.insert(path.to_path_buf());
+                None
+            }
+        }
+    }
+}
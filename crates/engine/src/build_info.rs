@@ -0,0 +1,27 @@
+//! What this engine was built from, recorded by the build script, logged on
+//! startup, shown in the debug UI, and exchanged in the network handshake,
+//! see `network::build_info`.
+
+use network::build_info::BuildInfo;
+
+/// The engine's optional features this build has.
+const FEATURES: &[(&str, bool)] = &[
+    ("asset-loader", cfg!(feature = "asset-loader")),
+    ("net-sync", cfg!(feature = "net-sync")),
+    ("world-update", cfg!(feature = "world-update")),
+];
+
+/// This build of the engine.
+pub fn current() -> BuildInfo {
+    BuildInfo {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        git_hash: env!("NANACTYL_GIT_HASH").to_string(),
+        build_date: env!("NANACTYL_BUILD_DATE").to_string(),
+        profile: env!("NANACTYL_BUILD_PROFILE").to_string(),
+        features: FEATURES
+            .iter()
+            .filter(|(_, enabled)| *enabled)
+            .map(|(feature, _)| feature.to_string())
+            .collect(),
+    }
+}
@@ -0,0 +1,125 @@
+//! Systems that ship with the engine.
+//!
+//! Each built-in system is statically linked behind a cargo feature of the
+//! same name (all enabled by default), and can be switched off at runtime with
+//! `EngineBuilder::disable_system`. Built-in systems that only need the world
+//! implement `GameSystem` and are driven through a `SystemHandle` like any
+//! game system; the asset loader and net sync still need their own locked
+//! state, so the frame loop drives them directly.
+
+use std::fmt;
+use std::str::FromStr;
+#[cfg(feature = "world-update")]
+use std::time::Duration;
+
+#[cfg(feature = "world-update")]
+use logger::Logger;
+#[cfg(feature = "world-update")]
+use world::World;
+
+#[cfg(feature = "world-update")]
+use crate::phase::FramePhase;
+#[cfg(feature = "world-update")]
+use crate::system::{GameSystem, SystemError};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum BuiltinSystem {
+    WorldUpdate,
+    AssetLoader,
+    NetSync,
+}
+
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+#[error("unknown built-in system {0:?}, expected one of world_update, asset_loader, net_sync")]
+pub struct UnknownBuiltinSystem(pub String);
+
+impl BuiltinSystem {
+    pub const ALL: [BuiltinSystem; 3] = [
+        BuiltinSystem::WorldUpdate,
+        BuiltinSystem::AssetLoader,
+        BuiltinSystem::NetSync,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            BuiltinSystem::WorldUpdate => "world_update",
+            BuiltinSystem::AssetLoader => "asset_loader",
+            BuiltinSystem::NetSync => "net_sync",
+        }
+    }
+
+    /// Whether the cargo feature for this system was enabled.
+    pub fn is_compiled_in(&self) -> bool {
+        match self {
+            BuiltinSystem::WorldUpdate => cfg!(feature = "world-update"),
+            BuiltinSystem::AssetLoader => cfg!(feature = "asset-loader"),
+            BuiltinSystem::NetSync => cfg!(feature = "net-sync"),
+        }
+    }
+}
+
+impl fmt::Display for BuiltinSystem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for BuiltinSystem {
+    type Err = UnknownBuiltinSystem;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        BuiltinSystem::ALL
+            .into_iter()
+            .find(|system| system.name() == s || system.name().replace('_', "-") == s)
+            .ok_or_else(|| UnknownBuiltinSystem(s.to_string()))
+    }
+}
+
+#[cfg(feature = "world-update")]
+impl GameSystem for world_update_system::WorldUpdate {
+    fn name(&self) -> &str {
+        BuiltinSystem::WorldUpdate.name()
+    }
+
+    fn version(&self) -> &str {
+        world_update_system::VERSION
+    }
+
+    fn set_logger(&mut self, logger: Logger) {
+        world_update_system::WorldUpdate::set_logger(self, logger);
+    }
+
+    fn phase(&self) -> FramePhase {
+        FramePhase::Sim
+    }
+
+    fn load(&mut self, world: &mut World) -> Result<(), SystemError> {
+        world_update_system::WorldUpdate::load(self, world);
+        Ok(())
+    }
+
+    fn update(&mut self, world: &mut World, delta_time: &Duration) {
+        world_update_system::WorldUpdate::update(self, world, delta_time);
+    }
+
+    fn unload(&mut self, world: &mut World) {
+        world_update_system::WorldUpdate::unload(self, world);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builtin_system_names_round_trip() {
+        for system in BuiltinSystem::ALL {
+            assert_eq!(system.name().parse::<BuiltinSystem>(), Ok(system));
+        }
+        assert_eq!(
+            "net-sync".parse::<BuiltinSystem>(),
+            Ok(BuiltinSystem::NetSync)
+        );
+        assert!("renderer".parse::<BuiltinSystem>().is_err());
+    }
+}
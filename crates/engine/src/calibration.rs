@@ -0,0 +1,89 @@
+//! Console commands calibrating gamepads, see `input::calibration`.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use input::calibration::Calibration;
+
+use crate::console::Console;
+
+pub(crate) fn register_commands(console: &mut Console, calibration: &Rc<RefCell<Calibration>>) {
+    let list = Rc::clone(calibration);
+    console.register(
+        "gamepads",
+        "list connected gamepads, and the one being calibrated",
+        move |_world, _args| {
+            let calibration = list.borrow();
+            let mut devices = calibration.devices().collect::<Vec<_>>();
+            devices.sort();
+            if devices.is_empty() {
+                return Ok("no gamepads connected".to_string());
+            }
+            let calibrating = calibration.session().map(|session| session.device());
+            Ok(devices
+                .into_iter()
+                .map(|(device, name)| match calibrating {
+                    Some(calibrating) if calibrating == device => {
+                        format!("{device}: {name} (calibrating)")
+                    }
+                    _ => format!("{device}: {name}"),
+                })
+                .collect::<Vec<_>>()
+                .join("\n"))
+        },
+    );
+    let calibrate = Rc::clone(calibration);
+    console.register(
+        "gamepad_calibrate",
+        "calibrate gamepad <id>, then step through with next, or cancel",
+        move |_world, args| {
+            let mut calibration = calibrate.borrow_mut();
+            match args {
+                ["cancel"] => {
+                    calibration.cancel().map_err(|err| err.to_string())?;
+                    Ok("calibration cancelled".to_string())
+                }
+                ["next"] => match calibration.advance().map_err(|err| err.to_string())? {
+                    None => Ok("move the sticks and triggers through their full range, \
+                                then run gamepad_calibrate next"
+                        .to_string()),
+                    Some(_) => {
+                        calibration.save().map_err(|err| err.to_string())?;
+                        Ok("gamepad calibrated".to_string())
+                    }
+                },
+                [device] => {
+                    let device = device.parse::<u32>().map_err(|err| err.to_string())?;
+                    calibration.start(device).map_err(|err| err.to_string())?;
+                    Ok("leave the sticks and triggers at rest, \
+                        then run gamepad_calibrate next"
+                        .to_string())
+                }
+                _ => Err("expected a gamepad id, next or cancel".to_string()),
+            }
+        },
+    );
+    let dead_zone = Rc::clone(calibration);
+    console.register(
+        "gamepad_dead_zone",
+        "set the dead zone of <axis> of gamepad <id> to a <fraction> of its range",
+        move |_world, args| {
+            let (device, axis, fraction) = match args {
+                [device, axis, fraction] => (
+                    device.parse::<u32>().map_err(|err| err.to_string())?,
+                    axis.parse::<usize>().map_err(|err| err.to_string())?,
+                    fraction.parse::<f32>().map_err(|err| err.to_string())?,
+                ),
+                _ => return Err("expected a gamepad id, an axis and a fraction".to_string()),
+            };
+            let mut calibration = dead_zone.borrow_mut();
+            calibration
+                .set_dead_zone(device, axis, fraction)
+                .map_err(|err| err.to_string())?;
+            calibration.save().map_err(|err| err.to_string())?;
+            Ok(format!(
+                "dead zone of axis {axis} of gamepad {device} set to {fraction}"
+            ))
+        },
+    );
+}
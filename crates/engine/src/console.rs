@@ -0,0 +1,645 @@
+//! Console commands, lines of text run against the world at the end of a
+//! frame. A line is a command name followed by its arguments, separated by
+//! whitespace. The engine registers its own commands, games add theirs with
+//! `EngineBuilder::console_command`. Lines are sent from wherever they're
+//! typed through a `ConsoleSender`, or run directly with `Frame::run_command`.
+//!
+//! Settings that can be changed while running are cvars, shown and set with
+//! `set <name> [value]` and listed with `cvars`. Games add theirs with
+//! `EngineBuilder::cvar`.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::sync::mpsc::{self, Receiver, SendError, Sender};
+use std::time::Duration;
+
+use core_executor::progress::TaskId;
+use logger::LogLevel;
+use world::debug_draw::DebugCategories;
+use world::save::SaveFile;
+use world::snapshot::{self, SnapshotChecksums, SnapshotDiff, WorldSnapshot};
+use world::{Compression, World};
+
+/// What a line run by the console results in.
+pub type ConsoleResult = Result<String, ConsoleError>;
+
+/// A line sent to the console, and where to send its result, if anywhere.
+struct PendingLine {
+    line: String,
+    reply: Option<Sender<ConsoleResult>>,
+}
+
+/// Sends lines to the console, to run at the end of the next frame.
+#[derive(Clone)]
+pub struct ConsoleSender(Sender<PendingLine>);
+
+impl ConsoleSender {
+    /// Send a line, its result is only logged. Fails once the console is
+    /// gone.
+    pub fn send(&self, line: String) -> Result<(), SendError<String>> {
+        self.0
+            .send(PendingLine { line, reply: None })
+            .map_err(|SendError(pending)| SendError(pending.line))
+    }
+
+    /// Send a line, returning where its result is sent once it's run, such as
+    /// to answer a remote admin.
+    pub fn request(&self, line: String) -> Result<Receiver<ConsoleResult>, SendError<String>> {
+        let (reply, result) = mpsc::channel();
+        self.0
+            .send(PendingLine {
+                line,
+                reply: Some(reply),
+            })
+            .map_err(|SendError(pending)| SendError(pending.line))?;
+        Ok(result)
+    }
+}
+
+/// Runs a command with its arguments, returning what to print.
+pub type CommandFn = Box<dyn FnMut(&mut World, &[&str]) -> Result<String, String>>;
+
+/// Shows a cvar's value.
+pub type CvarGetFn = Box<dyn Fn(&World) -> String>;
+
+/// Sets a cvar from the value given to `set`.
+pub type CvarSetFn = Box<dyn FnMut(&mut World, &str) -> Result<(), String>>;
+
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+pub enum ConsoleError {
+    #[error("unknown command {0:?}, try help")]
+    UnknownCommand(String),
+    #[error("{command}: {error}")]
+    Failed { command: String, error: String },
+    #[error("unknown cvar {0:?}, try cvars")]
+    UnknownCvar(String),
+}
+
+struct Command {
+    help: String,
+    run: CommandFn,
+}
+
+struct Cvar {
+    help: String,
+    get: CvarGetFn,
+    set: CvarSetFn,
+}
+
+pub struct Console {
+    commands: BTreeMap<String, Command>,
+    cvars: BTreeMap<String, Cvar>,
+    sender: ConsoleSender,
+    receiver: Receiver<PendingLine>,
+}
+
+impl Default for Console {
+    fn default() -> Self {
+        let (sender, receiver) = mpsc::channel();
+        Self {
+            commands: BTreeMap::new(),
+            cvars: BTreeMap::new(),
+            sender: ConsoleSender(sender),
+            receiver,
+        }
+    }
+}
+
+impl Console {
+    /// A console with the engine's own commands.
+    pub fn with_builtin_commands() -> Self {
+        let mut console = Self::default();
+        console.register(
+            "recapture_probes",
+            "capture every reflection probe again, after editing the scene",
+            |world, _args| {
+                world.recapture_reflection_probes();
+                Ok("reflection probes will be captured next frame".to_string())
+            },
+        );
+        console.register(
+            "snapshot_save",
+            "write a snapshot of the world to <path>, or only its checksums",
+            |world, args| {
+                let snapshot = WorldSnapshot::capture(world);
+                let (path, yaml) = match args {
+                    [path] => (path, serde_yaml::to_string(&snapshot)),
+                    [path, "checksums"] => (path, serde_yaml::to_string(&snapshot.checksums())),
+                    _ => return Err("expected a path, and optionally checksums".to_string()),
+                };
+                fs::write(path, yaml.map_err(|err| err.to_string())?)
+                    .map_err(|err| err.to_string())?;
+                Ok(format!(
+                    "wrote {} entities at update {} to {path}",
+                    snapshot.entities.len(),
+                    snapshot.update
+                ))
+            },
+        );
+        console.register(
+            "snapshot_diff",
+            "diff the world, or a [local] snapshot, with a <remote> snapshot or checksums",
+            |world, args| {
+                let (remote, local) = match args {
+                    [remote] => (remote, WorldSnapshot::capture(world)),
+                    [remote, local] => (remote, read_snapshot(local)?),
+                    _ => return Err("expected a remote path, and maybe a local one".to_string()),
+                };
+                let yaml = fs::read_to_string(remote).map_err(|err| err.to_string())?;
+                let diff = match serde_yaml::from_str::<WorldSnapshot>(&yaml) {
+                    Ok(remote) => local.diff(&remote, SnapshotDiff::DEFAULT_TOLERANCE),
+                    Err(_) => {
+                        let remote = serde_yaml::from_str::<SnapshotChecksums>(&yaml)
+                            .map_err(|err| err.to_string())?;
+                        local.diff_checksums(&remote)
+                    }
+                };
+                Ok(if diff.is_empty() {
+                    "no differences".to_string()
+                } else {
+                    diff.to_string()
+                })
+            },
+        );
+        console.register("save", "save the world to <path>", |world, args| {
+            let path = match args {
+                [path] => path,
+                _ => return Err("expected a path".to_string()),
+            };
+            let save = world.save_schemas.save(world);
+            let yaml = serde_yaml::to_string(&save).map_err(|err| err.to_string())?;
+            fs::write(path, yaml).map_err(|err| err.to_string())?;
+            Ok(format!(
+                "saved {} entities at update {} to {path}",
+                save.snapshot.entities.len(),
+                save.snapshot.update
+            ))
+        });
+        console.register(
+            "load",
+            "restore the entities of the world saved to <path>, migrating older saves",
+            |world, args| {
+                let path = match args {
+                    [path] => path,
+                    _ => return Err("expected a path".to_string()),
+                };
+                let yaml = fs::read_to_string(path).map_err(|err| err.to_string())?;
+                let save: SaveFile = serde_yaml::from_str(&yaml).map_err(|err| err.to_string())?;
+                let save = world
+                    .save_schemas
+                    .migrate(save)
+                    .map_err(|err| err.to_string())?;
+                let stats = save.restore(world);
+                Ok(format!(
+                    "restored {} entities from {path}, {} no longer exist",
+                    stats.restored, stats.missing
+                ))
+            },
+        );
+        console.register(
+            "divergence",
+            "list groups of entities that disagree with the other end, or the entities of <group>",
+            |world, args| {
+                let divergence = &world.divergence;
+                let groups = divergence.groups();
+                match args {
+                    [] => {
+                        let mut lines = divergence
+                            .diverged()
+                            .map(|(group, check)| {
+                                format!(
+                                    "group {group}/{groups}: {} checks disagreed, since update {}",
+                                    check.mismatches,
+                                    check.mismatched_since.unwrap_or_default()
+                                )
+                            })
+                            .collect::<Vec<_>>();
+                        if lines.is_empty() {
+                            lines.push("no groups have diverged".to_string());
+                        }
+                        if !divergence.covered() {
+                            lines.push(format!("not every one of {groups} groups checked yet"));
+                        }
+                        Ok(lines.join("\n"))
+                    }
+                    [group] => {
+                        let group = group.parse::<u16>().map_err(|err| err.to_string())?;
+                        if group >= groups {
+                            return Err(format!("there are only {groups} groups"));
+                        }
+                        let entities = world
+                            .hecs_world
+                            .iter()
+                            .map(|entity| entity.entity().to_bits().get())
+                            .filter(|bits| snapshot::slice_group(*bits, groups) == group)
+                            .map(|bits| format!("{bits:x}"))
+                            .collect::<Vec<_>>();
+                        Ok(format!(
+                            "group {group}/{groups}, {} entities: {}",
+                            entities.len(),
+                            entities.join(" ")
+                        ))
+                    }
+                    _ => Err("expected at most a group".to_string()),
+                }
+            },
+        );
+        console.register(
+            "tasks",
+            "list long-running tasks and their progress, clear drops finished ones",
+            |world, args| {
+                if args == ["clear"] {
+                    world.tasks.clear_finished();
+                }
+                let tasks = world.tasks.list();
+                if tasks.is_empty() {
+                    return Ok("no tasks".to_string());
+                }
+                Ok(tasks
+                    .iter()
+                    .map(|task| {
+                        let percent = task
+                            .fraction
+                            .map_or_else(|| "?".to_string(), |f| format!("{:.0}%", f * 100.0));
+                        format!(
+                            "{}: {} {percent} {:?} after {:.1}s {}",
+                            task.id,
+                            task.name,
+                            task.state,
+                            task.elapsed.as_secs_f32(),
+                            task.message
+                        )
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n"))
+            },
+        );
+        console.register(
+            "debug_draw",
+            "list the debug line categories drawn, or turn <category> on or off",
+            |world, args| {
+                let (name, enabled) = match args {
+                    [] => {
+                        let names = world.debug_draw.enabled().names().collect::<Vec<_>>();
+                        return Ok(if names.is_empty() {
+                            "drawing no debug lines".to_string()
+                        } else {
+                            format!("drawing {}", names.join(", "))
+                        });
+                    }
+                    [name, "on"] => (*name, true),
+                    [name, "off"] => (*name, false),
+                    _ => return Err("expected a category then on or off".to_string()),
+                };
+                let categories = DebugCategories::from_name(name)
+                    .ok_or_else(|| format!("unknown debug line category {name:?}"))?;
+                world.debug_draw.set_enabled(categories, enabled);
+                if enabled {
+                    Ok(format!("drawing {name}"))
+                } else {
+                    Ok(format!("not drawing {name}"))
+                }
+            },
+        );
+        #[cfg(feature = "net-sync")]
+        console.register(
+            "net_schema",
+            "print the wire format's schema, or write it to [path]",
+            |_world, args| {
+                let schema = net_sync_system::schema::Schema::current();
+                match args {
+                    [] => Ok(schema.to_string()),
+                    [path] => {
+                        fs::write(path, schema.to_string()).map_err(|err| err.to_string())?;
+                        Ok(format!(
+                            "wrote wire protocol version {} to {path}",
+                            schema.version
+                        ))
+                    }
+                    _ => Err("expected at most a path".to_string()),
+                }
+            },
+        );
+        console.register(
+            "log_plugins",
+            "list what each plugin has logged, and at what level",
+            |world, _args| {
+                let stats = world.logger.plugin_stats();
+                if stats.is_empty() {
+                    return Ok("no plugins".to_string());
+                }
+                Ok(stats
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+                    .join("\n"))
+            },
+        );
+        console.register(
+            "log_level",
+            "log <plugin> at <level>, or at the engine's level with default",
+            |world, args| {
+                let (name, level) = match args {
+                    [name, "default"] => (*name, None),
+                    [name, level] => (*name, Some(level.parse::<LogLevel>()?)),
+                    _ => return Err("expected a plugin then a level or default".to_string()),
+                };
+                world.logger.set_plugin_level(name, level.clone());
+                Ok(match level {
+                    Some(level) => format!("logging {name} at {level}"),
+                    None => format!("logging {name} at the engine's level"),
+                })
+            },
+        );
+        console.register("task_cancel", "cancel task <id>", |world, args| {
+            let id = match args {
+                [id] => id.parse::<TaskId>().map_err(|err| err.to_string())?,
+                _ => return Err("expected a task id".to_string()),
+            };
+            if world.tasks.cancel(id) {
+                Ok(format!("cancelling task {id}"))
+            } else {
+                Err(format!("task {id} isn't running"))
+            }
+        });
+        console.register_cvar(
+            "net_compression",
+            "codec updates are compressed with, for clients connecting from now on",
+            |world| world.config.net_compression.to_string(),
+            |world, value| {
+                world.config.net_compression = value
+                    .parse::<Compression>()
+                    .map_err(|err| err.to_string())?;
+                Ok(())
+            },
+        );
+        console.register_cvar(
+            "interpolation_delay_ms",
+            "how far behind the server interpolated entities are drawn, or auto",
+            |world| match world.config.interpolation_delay {
+                Some(delay) => delay.as_millis().to_string(),
+                None => "auto".to_string(),
+            },
+            |world, value| {
+                world.config.interpolation_delay = match value {
+                    "auto" => None,
+                    ms => Some(Duration::from_millis(
+                        ms.parse()
+                            .map_err(|_| format!("expected ms or auto, got {ms:?}"))?,
+                    )),
+                };
+                Ok(())
+            },
+        );
+        console.register_cvar(
+            "debug_ui",
+            "show the debug UI, on or off",
+            |world| match world.debug_ui.is_enabled() {
+                true => "on".to_string(),
+                false => "off".to_string(),
+            },
+            |world, value| {
+                match value {
+                    "on" => world.debug_ui.set_enabled(true),
+                    "off" => world.debug_ui.set_enabled(false),
+                    _ => return Err(format!("expected on or off, got {value:?}")),
+                }
+                Ok(())
+            },
+        );
+        console.register_cvar(
+            "ui_navigation",
+            "navigate the shown debug UI with gamepads and the keyboard, on or off",
+            |world| match world.input_focus.ui_navigation() {
+                true => "on".to_string(),
+                false => "off".to_string(),
+            },
+            |world, value| {
+                match value {
+                    "on" => world.input_focus.set_ui_navigation(true),
+                    "off" => world.input_focus.set_ui_navigation(false),
+                    _ => return Err(format!("expected on or off, got {value:?}")),
+                }
+                Ok(())
+            },
+        );
+        console
+    }
+
+    /// Register a command, replacing any already registered with the name.
+    pub fn register(
+        &mut self,
+        name: &str,
+        help: &str,
+        run: impl FnMut(&mut World, &[&str]) -> Result<String, String> + 'static,
+    ) {
+        self.commands.insert(
+            name.to_string(),
+            Command {
+                help: help.to_string(),
+                run: Box::new(run),
+            },
+        );
+    }
+
+    /// Register a cvar, replacing any already registered with the name.
+    pub fn register_cvar(
+        &mut self,
+        name: &str,
+        help: &str,
+        get: impl Fn(&World) -> String + 'static,
+        set: impl FnMut(&mut World, &str) -> Result<(), String> + 'static,
+    ) {
+        self.cvars.insert(
+            name.to_string(),
+            Cvar {
+                help: help.to_string(),
+                get: Box::new(get),
+                set: Box::new(set),
+            },
+        );
+    }
+
+    /// Where to send lines from, such as a thread reading stdin.
+    pub fn sender(&self) -> ConsoleSender {
+        self.sender.clone()
+    }
+
+    /// Run a line, returning what the command printed. Blank lines do
+    /// nothing, `help` lists the commands, and `set` and `cvars` show and set
+    /// cvars.
+    pub fn run(&mut self, world: &mut World, line: &str) -> ConsoleResult {
+        let mut words = line.split_whitespace();
+        let name = match words.next() {
+            Some(name) => name,
+            None => return Ok(String::new()),
+        };
+        let args = words.collect::<Vec<_>>();
+        match name {
+            "help" => return Ok(self.help()),
+            "cvars" => return Ok(self.list_cvars(world)),
+            "set" => return self.set(world, &args),
+            _ => {}
+        }
+        let command = self
+            .commands
+            .get_mut(name)
+            .ok_or_else(|| ConsoleError::UnknownCommand(name.to_string()))?;
+        (command.run)(world, &args).map_err(|error| ConsoleError::Failed {
+            command: name.to_string(),
+            error,
+        })
+    }
+
+    /// Run every line sent since the last call, returning each with its
+    /// result, which is also sent to whoever requested it.
+    pub fn run_pending(&mut self, world: &mut World) -> Vec<(String, ConsoleResult)> {
+        let pending = self.receiver.try_iter().collect::<Vec<_>>();
+        pending
+            .into_iter()
+            .map(|PendingLine { line, reply }| {
+                let result = self.run(world, &line);
+                if let Some(reply) = reply {
+                    // The requester may have given up waiting.
+                    let _ = reply.send(result.clone());
+                }
+                (line, result)
+            })
+            .collect()
+    }
+
+    fn help(&self) -> String {
+        let mut help = "help: list commands\n\
+                        cvars: list cvars and their values\n\
+                        set: show <cvar>, or set it to [value]"
+            .to_string();
+        for (name, command) in self.commands.iter() {
+            help.push_str(&format!("\n{name}: {}", command.help));
+        }
+        help
+    }
+
+    fn list_cvars(&self, world: &World) -> String {
+        self.cvars
+            .iter()
+            .map(|(name, cvar)| format!("{name} = {} ({})", (cvar.get)(world), cvar.help))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    fn set(&mut self, world: &mut World, args: &[&str]) -> ConsoleResult {
+        let (name, value) = match args {
+            [name] => (*name, None),
+            [name, value @ ..] => (*name, Some(value.join(" "))),
+            [] => {
+                return Err(ConsoleError::Failed {
+                    command: "set".to_string(),
+                    error: "expected a cvar, and optionally a value".to_string(),
+                })
+            }
+        };
+        let cvar = self
+            .cvars
+            .get_mut(name)
+            .ok_or_else(|| ConsoleError::UnknownCvar(name.to_string()))?;
+        if let Some(value) = value {
+            (cvar.set)(world, &value).map_err(|error| ConsoleError::Failed {
+                command: format!("set {name}"),
+                error,
+            })?;
+        }
+        Ok(format!("{name} = {}", (cvar.get)(world)))
+    }
+}
+
+fn read_snapshot(path: &str) -> Result<WorldSnapshot, String> {
+    let yaml = fs::read_to_string(path).map_err(|err| err.to_string())?;
+    serde_yaml::from_str(&yaml).map_err(|err| err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use logger::LogLevel;
+
+    use super::*;
+
+    #[test]
+    fn runs_commands_with_their_arguments() {
+        let mut world = World::new(None, &LogLevel::Info.logger(), true);
+        let mut console = Console::with_builtin_commands();
+        console.register("echo", "print the arguments", |_world, args| {
+            if args.is_empty() {
+                Err("nothing to echo".to_string())
+            } else {
+                Ok(args.join(" "))
+            }
+        });
+
+        assert_eq!(console.run(&mut world, "  echo  a b ").unwrap(), "a b");
+        assert_eq!(console.run(&mut world, "").unwrap(), "");
+        assert_eq!(
+            console.run(&mut world, "echo"),
+            Err(ConsoleError::Failed {
+                command: "echo".to_string(),
+                error: "nothing to echo".to_string()
+            })
+        );
+        assert_eq!(
+            console.run(&mut world, "ecko a"),
+            Err(ConsoleError::UnknownCommand("ecko".to_string()))
+        );
+        let help = console.run(&mut world, "help").unwrap();
+        assert!(help.contains("echo: print the arguments"));
+        assert!(help.contains("recapture_probes: "));
+
+        let captures = world.reflection_probe_captures;
+        let sender = console.sender();
+        sender.send("recapture_probes".to_string()).unwrap();
+        sender.send("echo sent".to_string()).unwrap();
+        let results = console.run_pending(&mut world);
+        assert_eq!(results.len(), 2);
+        assert_eq!(
+            results[1],
+            ("echo sent".to_string(), Ok("sent".to_string()))
+        );
+        assert_eq!(world.reflection_probe_captures, captures + 1);
+        assert!(console.run_pending(&mut world).is_empty());
+
+        let reply = sender.request("echo back".to_string()).unwrap();
+        console.run_pending(&mut world);
+        assert_eq!(reply.try_recv(), Ok(Ok("back".to_string())));
+    }
+
+    #[test]
+    fn shows_and_sets_cvars() {
+        let mut world = World::new(None, &LogLevel::Info.logger(), true);
+        let mut console = Console::with_builtin_commands();
+        assert_eq!(
+            console
+                .run(&mut world, "set interpolation_delay_ms")
+                .unwrap(),
+            "interpolation_delay_ms = auto"
+        );
+        assert_eq!(
+            console
+                .run(&mut world, "set interpolation_delay_ms 120")
+                .unwrap(),
+            "interpolation_delay_ms = 120"
+        );
+        assert_eq!(
+            world.config.interpolation_delay,
+            Some(Duration::from_millis(120))
+        );
+        assert!(matches!(
+            console.run(&mut world, "set interpolation_delay_ms soon"),
+            Err(ConsoleError::Failed { .. })
+        ));
+        assert_eq!(
+            console.run(&mut world, "set gravity 1"),
+            Err(ConsoleError::UnknownCvar("gravity".to_string()))
+        );
+        let cvars = console.run(&mut world, "cvars").unwrap();
+        assert!(cvars.contains("interpolation_delay_ms = 120"), "{cvars}");
+        assert!(cvars.contains("net_compression = "), "{cvars}");
+    }
+}
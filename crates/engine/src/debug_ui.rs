@@ -0,0 +1,303 @@
+//! Feeds the world's debug UI input from the main window, and shows the
+//! engine's own window of stats in it, see `world::debug_ui`.
+
+use std::time::Duration;
+
+use egui::{Color32, Event, Key, Modifiers, PointerButton, Pos2, RawInput, Rect, Sense, Vec2};
+use input::{Button, EngineEvent, InputEvent, MouseButton};
+use platform::WindowSize;
+use world::ecs_stats::REPEATED_QUERY_RUNS;
+use world::World;
+
+/// Width of a bucket of the round trip time histogram.
+const RTT_BUCKET: Duration = Duration::from_millis(25);
+const RTT_BUCKETS: usize = 12;
+
+/// Most archetypes and queries listed, the rest are summed up in a last row.
+const ECS_STATS_ROWS: usize = 8;
+
+/// Points scrolled per step of the mouse wheel.
+const SCROLL_STEP: f32 = 50.0;
+
+/// Input for a frame of the UI, laid out on a window of `size`, from the
+/// events pumped this frame. `time` is since the UI was first shown, and
+/// `pointer` where the cursor was last reported, kept between frames to press
+/// buttons where it is.
+pub(crate) fn raw_input(
+    events: &[EngineEvent],
+    size: WindowSize,
+    time: Duration,
+    pointer: &mut Pos2,
+) -> RawInput {
+    let (width, height) = size.logical;
+    RawInput {
+        screen_rect: Some(Rect::from_min_size(
+            Pos2::ZERO,
+            Vec2::new(width as f32, height as f32),
+        )),
+        pixels_per_point: Some(size.scale_factor()),
+        time: Some(time.as_secs_f64()),
+        events: events
+            .iter()
+            .filter_map(|event| key_event(event).or_else(|| pointer_event(event, pointer)))
+            .collect(),
+        ..Default::default()
+    }
+}
+
+fn key_event(event: &EngineEvent) -> Option<Event> {
+    let (button, pressed) = match event {
+        EngineEvent::Input(InputEvent::KeyPressed(button)) => (button, true),
+        EngineEvent::Input(InputEvent::KeyReleased(button)) => (button, false),
+        // Only routed to the UI while gamepads navigate it.
+        EngineEvent::Input(InputEvent::ButtonPressed(_, button)) => (button, true),
+        EngineEvent::Input(InputEvent::ButtonReleased(_, button)) => (button, false),
+        _ => return None,
+    };
+    Some(Event::Key {
+        key: key(*button)?,
+        pressed,
+        repeat: false,
+        modifiers: Modifiers::NONE,
+    })
+}
+
+fn pointer_event(event: &EngineEvent, pointer: &mut Pos2) -> Option<Event> {
+    match event {
+        EngineEvent::Input(InputEvent::MouseMoved(x, y)) => {
+            *pointer = Pos2::new(*x as f32, *y as f32);
+            Some(Event::PointerMoved(*pointer))
+        }
+        EngineEvent::Input(InputEvent::MouseButton(button, pressed)) => {
+            Some(Event::PointerButton {
+                pos: *pointer,
+                button: pointer_button(*button),
+                pressed: *pressed,
+                modifiers: Modifiers::NONE,
+            })
+        }
+        EngineEvent::Input(InputEvent::MouseWheel(x, y)) => {
+            Some(Event::Scroll(Vec2::new(*x as f32, *y as f32) * SCROLL_STEP))
+        }
+        _ => None,
+    }
+}
+
+fn pointer_button(button: MouseButton) -> PointerButton {
+    match button {
+        MouseButton::Left => PointerButton::Primary,
+        MouseButton::Right => PointerButton::Secondary,
+        MouseButton::Middle => PointerButton::Middle,
+        MouseButton::X1 => PointerButton::Extra1,
+        MouseButton::X2 => PointerButton::Extra2,
+    }
+}
+
+/// The key a button is shown to the UI as, for moving focus between widgets
+/// and pressing them.
+fn key(button: Button) -> Option<Key> {
+    match button {
+        Button::Up => Some(Key::ArrowUp),
+        Button::Down => Some(Key::ArrowDown),
+        Button::Left => Some(Key::ArrowLeft),
+        Button::Right => Some(Key::ArrowRight),
+        Button::Ok => Some(Key::Enter),
+        Button::Cancel => Some(Key::Escape),
+        _ => None,
+    }
+}
+
+/// Show the engine's window of world, ECS and network stats, while a frame of
+/// the UI is being built.
+pub(crate) fn show_stats(world: &World) {
+    let context = match world.debug_ui.context() {
+        Some(context) => context,
+        None => return,
+    };
+    egui::Window::new("stats").show(context, |ui| {
+        ui.label(format!("build: {}", world.config.build_info));
+        ui.label(format!(
+            "updates: {}, run life: {:.1}s",
+            world.stats.updates,
+            world.stats.run_life.as_secs_f32()
+        ));
+        ui.label(format!("entities: {}", world.hecs_world.len()));
+        ui.label(format!(
+            "clients: {}, players: {}",
+            world.clients.len(),
+            world.players.len()
+        ));
+        for (player, slot) in world.player_slots.slots().iter().enumerate() {
+            ui.label(match slot.device {
+                Some(device) => format!("player {player}: gamepad {device}"),
+                None => format!("player {player}: press ok to join"),
+            });
+        }
+        ui.separator();
+        ui.label(format!(
+            "connection: {:?}",
+            world.connection_quality.quality()
+        ));
+        ui.label(format!(
+            "round trips by {}ms, slowest last",
+            RTT_BUCKET.as_millis()
+        ));
+        let histogram = world
+            .connection_quality
+            .rtt_histogram(RTT_BUCKET, RTT_BUCKETS);
+        let (rect, _) = ui.allocate_exact_size(Vec2::new(180.0, 48.0), Sense::hover());
+        let painter = ui.painter_at(rect);
+        let tallest = histogram.iter().copied().max().unwrap_or(0).max(1) as f32;
+        let width = rect.width() / RTT_BUCKETS as f32;
+        for (bucket, count) in histogram.into_iter().enumerate() {
+            let height = rect.height() * count as f32 / tallest;
+            let left = rect.left() + width * bucket as f32;
+            painter.rect_filled(
+                Rect::from_min_max(
+                    Pos2::new(left + 1.0, rect.bottom() - height),
+                    Pos2::new(left + width - 1.0, rect.bottom()),
+                ),
+                0.0,
+                Color32::LIGHT_GREEN,
+            );
+        }
+        ui.separator();
+        show_ecs_stats(ui, world);
+    });
+}
+
+/// The most populated archetypes and the slowest queries of the last frame,
+/// with queries likely run once per entity highlighted.
+fn show_ecs_stats(ui: &mut egui::Ui, world: &World) {
+    egui::CollapsingHeader::new("archetypes").show(ui, |ui| {
+        let archetypes = world.archetype_stats();
+        for archetype in archetypes.iter().take(ECS_STATS_ROWS) {
+            ui.label(format!(
+                "{}: [{}]",
+                archetype.entities,
+                archetype.components.join(", ")
+            ));
+        }
+        if archetypes.len() > ECS_STATS_ROWS {
+            let rest = &archetypes[ECS_STATS_ROWS..];
+            ui.label(format!(
+                "{} in {} more",
+                rest.iter().map(|archetype| archetype.entities).sum::<u32>(),
+                rest.len()
+            ));
+        }
+    });
+    egui::CollapsingHeader::new("queries").show(ui, |ui| {
+        let queries = world.query_stats.last_frame();
+        for (system, query, counter) in queries.iter().take(ECS_STATS_ROWS) {
+            let text = format!(
+                "{system}/{query}: {} runs, {} items, {:?}",
+                counter.runs, counter.items, counter.time
+            );
+            if counter.runs > REPEATED_QUERY_RUNS {
+                ui.colored_label(Color32::YELLOW, text)
+                    .on_hover_text("run more than once per frame, likely once per entity");
+            } else {
+                ui.label(text);
+            }
+        }
+        if queries.len() > ECS_STATS_ROWS {
+            ui.label(format!("{} faster queries", queries.len() - ECS_STATS_ROWS));
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buttons_are_shown_as_keys() {
+        let events = [
+            EngineEvent::Input(InputEvent::KeyPressed(Button::Down)),
+            EngineEvent::Input(InputEvent::MouseMotion(3, -2)),
+            EngineEvent::Input(InputEvent::KeyReleased(Button::Ok)),
+            EngineEvent::Input(InputEvent::KeyPressed(Button::Unmapped)),
+            EngineEvent::Input(InputEvent::ButtonPressed(1, Button::Cancel)),
+            EngineEvent::WindowResized(0),
+        ];
+        let size = WindowSize {
+            logical: (640, 480),
+            drawable: (1280, 960),
+        };
+        let input = raw_input(&events, size, Duration::from_secs(2), &mut Pos2::default());
+        assert_eq!(
+            input.events,
+            vec![
+                Event::Key {
+                    key: Key::ArrowDown,
+                    pressed: true,
+                    repeat: false,
+                    modifiers: Modifiers::NONE,
+                },
+                Event::Key {
+                    key: Key::Enter,
+                    pressed: false,
+                    repeat: false,
+                    modifiers: Modifiers::NONE,
+                },
+                Event::Key {
+                    key: Key::Escape,
+                    pressed: true,
+                    repeat: false,
+                    modifiers: Modifiers::NONE,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn screen_is_laid_out_in_points() {
+        let size = WindowSize {
+            logical: (640, 480),
+            drawable: (1280, 960),
+        };
+        let input = raw_input(&[], size, Duration::ZERO, &mut Pos2::default());
+        assert_eq!(
+            input.screen_rect,
+            Some(Rect::from_min_size(Pos2::ZERO, Vec2::new(640.0, 480.0)))
+        );
+        assert_eq!(input.pixels_per_point, Some(2.0));
+    }
+
+    #[test]
+    fn buttons_are_pressed_where_the_pointer_was_last() {
+        let size = WindowSize {
+            logical: (640, 480),
+            drawable: (640, 480),
+        };
+        let mut pointer = Pos2::ZERO;
+        let events = [
+            EngineEvent::Input(InputEvent::MouseMoved(10, 20)),
+            EngineEvent::Input(InputEvent::MouseWheel(0, -1)),
+        ];
+        let input = raw_input(&events, size, Duration::ZERO, &mut pointer);
+        assert_eq!(
+            input.events,
+            vec![
+                Event::PointerMoved(Pos2::new(10.0, 20.0)),
+                Event::Scroll(Vec2::new(0.0, -SCROLL_STEP)),
+            ]
+        );
+
+        let events = [EngineEvent::Input(InputEvent::MouseButton(
+            MouseButton::Right,
+            true,
+        ))];
+        let input = raw_input(&events, size, Duration::ZERO, &mut pointer);
+        assert_eq!(
+            input.events,
+            vec![Event::PointerButton {
+                pos: Pos2::new(10.0, 20.0),
+                button: PointerButton::Secondary,
+                pressed: true,
+                modifiers: Modifiers::NONE,
+            }]
+        );
+    }
+}
@@ -0,0 +1,235 @@
+//! Environment checks for `nshell --diagnose`, for working out why the engine
+//! won't start on a machine without starting it. Each check reports what it
+//! found, and whether that's fine, worth a look, or will stop the engine.
+
+use std::fs;
+use std::net::UdpSocket;
+use std::path::{Path, PathBuf};
+
+use ash_renderer_system::diagnose::{diagnose_vulkan, validate_spirv};
+use serde::Serialize;
+
+const VALIDATION_LAYER: &str = "VK_LAYER_KHRONOS_validation";
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckStatus {
+    Pass,
+    /// Works, but something is missing that some configurations need.
+    Warn,
+    Fail,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Check {
+    pub name: String,
+    pub status: CheckStatus,
+    pub details: Vec<String>,
+}
+
+impl Check {
+    fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            status: CheckStatus::Pass,
+            details: Vec::new(),
+        }
+    }
+
+    fn warn(&mut self, detail: String) {
+        if self.status == CheckStatus::Pass {
+            self.status = CheckStatus::Warn;
+        }
+        self.details.push(detail);
+    }
+
+    fn fail(&mut self, detail: String) {
+        self.status = CheckStatus::Fail;
+        self.details.push(detail);
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DiagnosticReport {
+    pub checks: Vec<Check>,
+}
+
+impl DiagnosticReport {
+    /// Whether nothing failed, warnings allowed.
+    pub fn passed(&self) -> bool {
+        self.checks
+            .iter()
+            .all(|check| check.status != CheckStatus::Fail)
+    }
+}
+
+/// Run every check, with assets expected under `asset_dir`.
+pub fn diagnose(asset_dir: &Path) -> DiagnosticReport {
+    DiagnosticReport {
+        checks: vec![
+            check_vulkan(),
+            check_platform(),
+            check_assets(asset_dir),
+            check_shaders(&asset_dir.join("shaders/spv")),
+            check_network(),
+        ],
+    }
+}
+
+fn check_vulkan() -> Check {
+    let mut check = Check::new("vulkan");
+    let vulkan = match diagnose_vulkan() {
+        Ok(vulkan) => vulkan,
+        Err(err) => {
+            check.fail(err);
+            return check;
+        }
+    };
+    check
+        .details
+        .push(format!("instance version {}", vulkan.api_version));
+    check
+        .details
+        .push(format!("layers: {}", vulkan.layers.join(", ")));
+    check.details.push(format!(
+        "instance extensions: {}",
+        vulkan.extensions.join(", ")
+    ));
+    if !vulkan.layers.iter().any(|layer| layer == VALIDATION_LAYER) {
+        check.warn(format!(
+            "{VALIDATION_LAYER} is not installed, --enable-validation-layer will fail"
+        ));
+    }
+
+    if vulkan.devices.is_empty() {
+        check.fail("no physical devices".to_string());
+    }
+    let mut usable = false;
+    for device in vulkan.devices.iter() {
+        let summary = format!(
+            "{} ({}, vulkan {})",
+            device.name, device.device_type, device.api_version
+        );
+        match (&device.created, device.supports_swapchain) {
+            (Ok(()), true) => {
+                usable = true;
+                check.details.push(format!("{summary}: ok"));
+            }
+            (Ok(()), false) => check
+                .details
+                .push(format!("{summary}: no swapchain support")),
+            (Err(err), _) => check.details.push(format!("{summary}: {err}")),
+        }
+    }
+    if !vulkan.devices.is_empty() && !usable {
+        check.fail("no device can create a swapchain".to_string());
+    }
+    check
+}
+
+fn check_platform() -> Check {
+    let mut check = Check::new("platform");
+    for (subsystem, result) in platform::diagnose_subsystems() {
+        match result {
+            Ok(description) => check.details.push(format!("{subsystem}: {description}")),
+            // Without video there's no window, the rest the engine runs without.
+            Err(err) if subsystem == "sdl" || subsystem == "video" => {
+                check.fail(format!("{subsystem}: {err}"))
+            }
+            Err(err) => check.warn(format!("{subsystem}: {err}")),
+        }
+    }
+    check
+}
+
+fn check_assets(asset_dir: &Path) -> Check {
+    let mut check = Check::new("assets");
+    for required in ["icon.png", "models", "shaders/spv", "fonts"] {
+        let path = asset_dir.join(required);
+        if !path.exists() {
+            check.fail(format!("{} is missing", path.display()));
+        } else if path.is_dir() {
+            let count = files_in(&path).len();
+            check
+                .details
+                .push(format!("{}: {count} file(s)", path.display()));
+            if count == 0 {
+                check.warn(format!("{} is empty", path.display()));
+            }
+        }
+    }
+    check
+}
+
+fn check_shaders(spv_dir: &Path) -> Check {
+    let mut check = Check::new("shaders");
+    let shaders = files_in(spv_dir)
+        .into_iter()
+        .filter(|path| path.extension().map_or(false, |ext| ext == "spv"))
+        .collect::<Vec<_>>();
+    if shaders.is_empty() {
+        check.fail(format!("no SPIR-V found in {}", spv_dir.display()));
+    }
+    for shader in shaders {
+        match validate_spirv(&shader) {
+            Ok(entry_points) => {
+                check
+                    .details
+                    .push(format!("{}: {}", shader.display(), entry_points.join(", ")))
+            }
+            Err(err) => check.fail(format!("{}: {err}", shader.display())),
+        }
+    }
+    // Left by the shader build next to the last SPIR-V that built.
+    for error_file in files_in(spv_dir)
+        .into_iter()
+        .filter(|path| path.extension().map_or(false, |ext| ext == "err"))
+    {
+        let error = std::fs::read_to_string(&error_file).unwrap_or_default();
+        check.fail(format!(
+            "{} failed to build: {}",
+            error_file.with_extension("spv").display(),
+            error.trim()
+        ));
+    }
+    check
+}
+
+fn check_network() -> Check {
+    let mut check = Check::new("network");
+    match UdpSocket::bind("0.0.0.0:0").and_then(|socket| socket.local_addr()) {
+        Ok(addr) => check.details.push(format!("udp bind ok on {addr}")),
+        Err(err) => check.fail(format!("unable to bind a udp socket: {err}")),
+    }
+    #[cfg(feature = "net-sync")]
+    {
+        // Where the server binds, rooms bind ports of their own.
+        let addr = net_sync_system::DEFAULT_LISTEN_ADDR;
+        match UdpSocket::bind(addr) {
+            Ok(_) => check
+                .details
+                .push(format!("net sync address {addr} available")),
+            Err(err) => check.warn(format!("net sync address {addr} unavailable: {err}")),
+        }
+    }
+    check
+}
+
+/// Files under `dir`, recursively. Unreadable directories count as empty.
+fn files_in(dir: &Path) -> Vec<PathBuf> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return vec![],
+    };
+    entries
+        .filter_map(Result::ok)
+        .flat_map(|entry| {
+            let path = entry.path();
+            if path.is_dir() {
+                files_in(&path)
+            } else {
+                vec![path]
+            }
+        })
+        .collect()
+}
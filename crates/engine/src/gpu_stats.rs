@@ -0,0 +1,45 @@
+//! Console commands capturing GPU statistics, see `render::gpu_stats`.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use render::gpu_stats::GpuStats;
+
+use crate::console::Console;
+
+/// Whether the renderer captures GPU statistics, and the last it captured.
+#[derive(Debug, Default)]
+pub(crate) struct GpuStatsCapture {
+    pub enabled: bool,
+    pub latest: Option<GpuStats>,
+}
+
+pub(crate) fn register_commands(console: &mut Console, capture: &Rc<RefCell<GpuStatsCapture>>) {
+    let stats = Rc::clone(capture);
+    console.register(
+        "gpu_stats",
+        "show the GPU work of each pass and the largest graphics, or turn capturing on or off",
+        move |_world, args| {
+            let mut capture = stats.borrow_mut();
+            match args {
+                ["on"] => {
+                    capture.enabled = true;
+                    Ok("capturing GPU statistics".to_string())
+                }
+                ["off"] => {
+                    capture.enabled = false;
+                    capture.latest = None;
+                    Ok("not capturing GPU statistics".to_string())
+                }
+                [] if !capture.enabled => {
+                    Err("not capturing GPU statistics, run gpu_stats on".to_string())
+                }
+                [] => match capture.latest.as_ref() {
+                    Some(latest) => Ok(latest.to_string()),
+                    None => Ok("nothing captured yet, is a renderer running?".to_string()),
+                },
+                _ => Err("expected on, off or nothing".to_string()),
+            }
+        },
+    );
+}
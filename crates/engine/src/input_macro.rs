@@ -0,0 +1,90 @@
+//! Console commands recording and playing input macros, see `input::macros`.
+
+use std::cell::RefCell;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use input::macros::InputMacro;
+use logger::{info, warn, Logger};
+use world::World;
+
+use crate::console::Console;
+
+/// Where the macro being recorded is saved once it's stopped.
+#[derive(Debug, Default)]
+pub(crate) struct MacroRecording {
+    pub path: Option<PathBuf>,
+}
+
+pub(crate) fn register_commands(console: &mut Console, recording: &Rc<RefCell<MacroRecording>>) {
+    let recording = Rc::clone(recording);
+    console.register(
+        "input_macro",
+        "record <file> or play <file> the local player's input a tick at a time, or stop",
+        move |world, args| match args {
+            ["record", path] => {
+                world.input_macros.record();
+                recording.borrow_mut().path = Some(PathBuf::from(path));
+                Ok(format!("recording input to {path}"))
+            }
+            ["play", path] => {
+                let input_macro =
+                    InputMacro::load(Path::new(path)).map_err(|err| err.to_string())?;
+                let ticks = input_macro.ticks().len();
+                world.input_macros.play(input_macro);
+                recording.borrow_mut().path = None;
+                Ok(format!("playing {ticks} ticks of input from {path}"))
+            }
+            ["stop"] => match stop(world, &recording)? {
+                Some((ticks, path)) => Ok(format!(
+                    "saved {ticks} ticks of input to {}",
+                    path.display()
+                )),
+                None => Ok("stopped playing input".to_string()),
+            },
+            [] if world.input_macros.is_recording() => Ok(format!(
+                "recorded {} ticks of input",
+                world.input_macros.remaining()
+            )),
+            [] if world.input_macros.is_playing() => Ok(format!(
+                "{} ticks of input left to play",
+                world.input_macros.remaining()
+            )),
+            [] => Ok("not recording or playing input".to_string()),
+            _ => Err("expected record <file>, play <file>, stop or nothing".to_string()),
+        },
+    );
+}
+
+/// Stop recording or playing, saving what was recorded. Returns the ticks
+/// saved and where.
+fn stop(
+    world: &mut World,
+    recording: &RefCell<MacroRecording>,
+) -> Result<Option<(usize, PathBuf)>, String> {
+    let path = recording.borrow_mut().path.take();
+    match (world.input_macros.stop(), path) {
+        (Some(input_macro), Some(path)) => {
+            input_macro
+                .save(&path)
+                .map_err(|err| format!("unable to save to {}: {err}", path.display()))?;
+            Ok(Some((input_macro.ticks().len(), path)))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Save the macro still being recorded as the engine exits.
+pub(crate) fn save_on_exit(
+    world: &mut World,
+    recording: &RefCell<MacroRecording>,
+    logger: &Logger,
+) {
+    match stop(world, recording) {
+        Ok(Some((ticks, path))) => {
+            info!(logger, "saved {ticks} ticks of input to {}", path.display())
+        }
+        Ok(None) => {}
+        Err(err) => warn!(logger, "input macro lost: {err}"),
+    }
+}
@@ -0,0 +1,1830 @@
+//! Embeddable engine runtime.
+//!
+//! Sets up the platform, renderer, world and built-in systems, then drives the
+//! frame loop through its phases, see `FramePhase`. A game embeds the engine by
+//! configuring an `EngineBuilder`, registering its own systems and callbacks,
+//! and calling `Engine::run`. The `nshell` binary is a thin shell over this
+//! crate.
+
+mod admin;
+mod audio;
+mod build_info;
+mod builtin;
+mod calibration;
+mod console;
+mod debug_ui;
+mod diagnose;
+mod gpu_stats;
+mod input_macro;
+#[cfg(feature = "net-sync")]
+mod loopback;
+mod mixer;
+mod mouse;
+mod pacing;
+mod phase;
+mod player_slots;
+mod plugins;
+mod render_path;
+mod rooms;
+mod soak;
+mod system;
+mod timeline;
+#[cfg(feature = "net-sync")]
+mod world_runner;
+
+use std::cell::RefCell;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use async_lock::Mutex;
+use futures_lite::future;
+use histogram::Histogram;
+use input::assignment::{PlayerSlots, DEFAULT_PLAYER_SLOTS};
+use input::calibration::Calibration;
+use input::macros::{InputMacro, MacroError};
+use input::wire::InputState;
+use input::{DeviceEvent, EngineEvent, MouseLook};
+use logger::{debug, error, info, warn, Logger};
+use platform::audio::MixerSettings;
+use platform::{PlatformContext, PlatformError};
+pub use render::aspect::AspectPolicy;
+use render::readback::{Readback, ReadbackImage};
+pub use render::render_path::RenderPath;
+pub use render::render_scale::{DynamicResolution, RenderScale, UpscaleFilter};
+pub use render::settings::{PresentMode, RenderSettings};
+use render::target::RenderTargetId;
+use render::{Presenter, RenderState, DEFAULT_FRAMES_IN_FLIGHT};
+pub use world::debug_draw::DebugCategories;
+use world::focus::{InputFocus, InputTarget};
+use world::journal::JournalEvent;
+pub use world::limits::WorldLimits;
+use world::notifications::Severity;
+use world::preload::DEFAULT_PRELOAD_BUDGET;
+pub use world::replication::ReplicationPolicy;
+pub use world::Compression;
+use world::World;
+
+use crate::admin::AdminRequests;
+pub use crate::admin::AdminSocket;
+use crate::audio::EmitterVoices;
+pub use crate::builtin::{BuiltinSystem, UnknownBuiltinSystem};
+pub use crate::console::{
+    CommandFn, Console, ConsoleError, ConsoleResult, ConsoleSender, CvarGetFn, CvarSetFn,
+};
+pub use crate::diagnose::{diagnose, Check, CheckStatus, DiagnosticReport};
+use crate::gpu_stats::GpuStatsCapture;
+use crate::input_macro::MacroRecording;
+use crate::phase::PhaseSchedule;
+pub use crate::phase::{FramePhase, ScheduleError};
+use crate::plugins::Plugins;
+#[doc(hidden)]
+pub use crate::plugins::ENGINE_VERSION_NUL;
+pub use crate::plugins::{
+    build_fingerprint, PluginDescriptor, PluginError, ENGINE_VERSION, PLUGIN_ABI_VERSION,
+    PLUGIN_DESCRIPTOR_SYMBOL,
+};
+use crate::rooms::RoomAdmin;
+pub use crate::rooms::{InvalidRoomConfig, RoomConfig};
+pub use crate::soak::{Motion, Scenario, SoakConfig, SoakLeak, SoakMetric, SoakReport, SoakSample};
+pub use crate::system::{
+    GameSystem, RetryPolicy, SystemError, SystemHandle, SystemState, SystemStateChange,
+};
+pub use crate::timeline::{
+    FrameRecord, Mark, Span, Timeline, TimelineConfig, TimelineFormat, UnknownTimelineFormat,
+};
+
+const DEFAULT_FRAME_LENGTH_MS: u64 = 8;
+/// Frames between checks for graphics the renderer kept after their prefab
+/// was despawned, in debug builds.
+const GRAPHICS_AUDIT_FRAMES: u64 = 600;
+
+#[derive(thiserror::Error, Debug)]
+pub enum EngineError {
+    #[error("platform error {0:?}")]
+    Platform(#[from] PlatformError),
+    #[error("no window handle for window {0}")]
+    NoWindowHandle(usize),
+    #[error("unable to order systems: {0}")]
+    Schedule(#[from] ScheduleError),
+    #[error("soak test failed: {0}")]
+    SoakFailed(SoakReport),
+    #[error("unable to open the admin socket: {0}")]
+    AdminSocket(std::io::Error),
+    #[error("unable to play the input macro: {0}")]
+    InputMacro(MacroError),
+}
+
+/// Title, position and size of a window.
+#[derive(Debug, Clone)]
+pub struct WindowConfig {
+    pub title: String,
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Default for WindowConfig {
+    fn default() -> Self {
+        Self {
+            title: "nanactyl".to_string(),
+            x: 0,
+            y: 0,
+            width: 640,
+            height: 400,
+        }
+    }
+}
+
+/// How the engine is set up, see `EngineBuilder`.
+#[derive(Debug, Clone)]
+pub struct EngineConfig {
+    pub window: WindowConfig,
+    /// More windows showing the scene, such as a debug view on a dedicated
+    /// server. They render from the world's camera, see `render::target`.
+    pub extra_windows: Vec<WindowConfig>,
+    /// Run without a window, renderer or input devices.
+    pub headless: bool,
+    /// Render headless runs offscreen into images this wide and high, read
+    /// back with `Frame::read_back_frame`, such as for golden image tests.
+    pub offscreen: Option<(u32, u32)>,
+    pub enable_validation_layer: bool,
+    /// Host name or address, with port, of a server to connect to as a
+    /// client, otherwise act as the server.
+    pub connect_to_server: Option<String>,
+    /// Act as the server, and also run a headless client in the same process
+    /// connected over loopback with this simulated latency.
+    pub listen_and_connect_self: Option<Duration>,
+    pub net_disabled: bool,
+    /// Codec updates are compressed with when serving, see `Compression`.
+    pub net_compression: Compression,
+    /// Trace the main world's packets to this file, for debugging the
+    /// protocol. Print one with the network crate's `trace_dump` example.
+    pub net_trace: Option<PathBuf>,
+    /// How replicated entities without a policy of their own move between
+    /// updates, see `ReplicationPolicy`.
+    pub replication: ReplicationPolicy,
+    /// How far behind the server interpolated entities are drawn, None to
+    /// adapt to the connection's quality.
+    pub interpolation_delay: Option<Duration>,
+    /// Minimum length of a frame, the loop waits out the remainder.
+    pub frame_length: Duration,
+    /// Frames per second the loop is capped at from the start, waiting out
+    /// each frame precisely rather than relying on presentation to pace it.
+    /// Also set with the `fps_cap` console command.
+    pub fps_cap: Option<f32>,
+    /// How frames are presented from the start, see `RenderSettings`. Also
+    /// set with the `present_mode` console variable.
+    pub present_mode: PresentMode,
+    /// Built-in systems that won't be loaded even though they're compiled in.
+    pub disabled_systems: Vec<BuiltinSystem>,
+    /// Debug line categories drawn from the start, see `World::debug_draw`.
+    pub debug_draw: DebugCategories,
+    /// Show the debug UI from the start, see `World::debug_ui`. Also toggled
+    /// with the `debug_ui` console variable.
+    pub debug_ui: bool,
+    /// Resolution the scene is rendered at relative to the window.
+    pub render_scale: RenderScale,
+    /// How the main window is lit, see `RenderPath`. Also set with the
+    /// `render_path` console variable.
+    pub render_path: RenderPath,
+    /// Capture the mouse for mouse look from the start. Also toggled with the
+    /// `relative_mouse` console variable.
+    pub relative_mouse: bool,
+    /// How mouse motion turns the camera. Also set with the
+    /// `mouse_sensitivity` and `invert_y` console variables.
+    pub mouse_look: MouseLook,
+    /// Bus volumes and music ducking. Also set with the audio console
+    /// variables, such as `music_volume` and `music_duck_under_sfx`.
+    pub mixer: MixerSettings,
+    /// How the scene is fit to windows of other aspect ratios.
+    pub aspect_policy: AspectPolicy,
+    /// Frames the renderer records ahead of the GPU, see
+    /// `RenderState::frames_in_flight`.
+    pub frames_in_flight: u32,
+    /// Caps on what can be spawned into the world.
+    pub world_limits: WorldLimits,
+    /// Scene file declaring groups of models to preload, see
+    /// `world::preload`.
+    pub scene: Option<PathBuf>,
+    /// Bytes of models preloaded groups may take.
+    pub preload_budget: usize,
+    /// Run a soak test, exiting once it's done, see `SoakConfig`.
+    pub soak: Option<SoakConfig>,
+    /// When frame timelines are captured, and where they're written.
+    pub timeline: TimelineConfig,
+    /// File gamepad calibration profiles are read from and saved to.
+    pub gamepad_profiles: PathBuf,
+    /// File remembering which gamepad joined as which local player, see
+    /// `input::assignment`.
+    pub player_slots: PathBuf,
+    /// Input macro played back in place of the local player's input from the
+    /// start, see `input::macros`.
+    pub input_macro: Option<PathBuf>,
+    /// File the local player's input is recorded to from the start, saved as
+    /// the engine exits. Ignored while playing `input_macro`.
+    pub record_input_macro: Option<PathBuf>,
+    /// Directory the renderer writes crash reports to, see
+    /// `RenderState::crash_report_dir`. The journal is written here too.
+    pub crash_report_dir: PathBuf,
+    /// Significant events the world's journal keeps, see `World::journal`.
+    pub journal_len: usize,
+    /// Write the world's journal to `crash_report_dir` when a thread panics.
+    pub dump_journal_on_panic: bool,
+    /// Directory game systems are loaded from as plugins, see the `plugins`
+    /// console command.
+    pub plugins_dir: Option<PathBuf>,
+    /// Localhost port taking console commands from connections that send its
+    /// token first, see the `admin` module.
+    pub admin_socket: Option<AdminSocket>,
+    /// Server rooms created on start, more can be created from the console.
+    pub rooms: Vec<RoomConfig>,
+}
+
+impl EngineConfig {
+    /// Whether a built-in system is compiled in and hasn't been disabled.
+    pub fn is_enabled(&self, system: BuiltinSystem) -> bool {
+        system.is_compiled_in() && !self.disabled_systems.contains(&system)
+    }
+
+    fn net_enabled(&self) -> bool {
+        !self.net_disabled && self.is_enabled(BuiltinSystem::NetSync)
+    }
+}
+
+impl Default for EngineConfig {
+    fn default() -> Self {
+        Self {
+            window: WindowConfig::default(),
+            extra_windows: Vec::new(),
+            headless: false,
+            offscreen: None,
+            enable_validation_layer: false,
+            connect_to_server: None,
+            listen_and_connect_self: None,
+            net_disabled: false,
+            net_compression: Compression::default(),
+            net_trace: None,
+            replication: ReplicationPolicy::default(),
+            interpolation_delay: None,
+            frame_length: Duration::from_millis(DEFAULT_FRAME_LENGTH_MS),
+            fps_cap: None,
+            present_mode: PresentMode::default(),
+            disabled_systems: Vec::new(),
+            debug_draw: DebugCategories::NONE,
+            debug_ui: false,
+            render_scale: RenderScale::default(),
+            render_path: RenderPath::default(),
+            relative_mouse: false,
+            mouse_look: MouseLook::default(),
+            mixer: MixerSettings::default(),
+            aspect_policy: AspectPolicy::default(),
+            frames_in_flight: DEFAULT_FRAMES_IN_FLIGHT,
+            world_limits: WorldLimits::default(),
+            scene: None,
+            preload_budget: DEFAULT_PRELOAD_BUDGET,
+            soak: None,
+            timeline: TimelineConfig::default(),
+            gamepad_profiles: PathBuf::from("gamepads.yaml"),
+            player_slots: PathBuf::from("players.yaml"),
+            input_macro: None,
+            record_input_macro: None,
+            crash_report_dir: PathBuf::from("crash_reports"),
+            journal_len: world::journal::DEFAULT_CAPACITY,
+            dump_journal_on_panic: false,
+            plugins_dir: None,
+            admin_socket: None,
+            rooms: Vec::new(),
+        }
+    }
+}
+
+/// What the start and frame callbacks get access to.
+pub struct Frame<'a> {
+    /// Number of the frame, the start callback sees frame 0.
+    pub number: u64,
+    /// Length of the previous frame.
+    pub delta_time: Duration,
+    pub world: &'a mut World,
+    /// None when running headless.
+    pub platform: Option<&'a mut PlatformContext>,
+    /// None when running headless, unless rendering offscreen.
+    renderer: Option<&'a mut ash_renderer_system::VulkanRenderPluginState>,
+    /// Game systems in the order they were registered, and their states.
+    pub systems: &'a mut [SystemHandle],
+    /// Systems that changed state this frame.
+    pub system_changes: &'a [SystemStateChange],
+    // Changes made by the callback, reported next frame.
+    pending_changes: &'a mut Vec<SystemStateChange>,
+    console: &'a mut Console,
+    exit_requested: bool,
+}
+
+impl<'a> Frame<'a> {
+    /// Platform events pumped this frame.
+    pub fn events(&self) -> &[EngineEvent] {
+        self.platform
+            .as_deref()
+            .map(PlatformContext::peek_events)
+            .unwrap_or(&[])
+    }
+
+    /// Ask for the named game system to be unloaded and loaded again next
+    /// frame, returning false if there is no such system.
+    pub fn reload_system(&mut self, name: &str) -> bool {
+        match self.systems.iter_mut().find(|system| system.name() == name) {
+            Some(system) => {
+                system.request_reload(self.pending_changes);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Read the next frame presented back, once the GPU has rendered it. None
+    /// without a renderer, see `EngineConfig::offscreen`.
+    pub fn read_back_frame(&mut self) -> Option<Readback<ReadbackImage>> {
+        self.renderer.as_deref_mut()?.read_back_frame()
+    }
+
+    /// Capture the next frame presented as a PNG screenshot in `dir`, see
+    /// `Presenter::capture_frame`. None without a renderer.
+    pub fn capture_frame(&mut self, dir: &Path) -> Option<Readback<PathBuf>> {
+        self.renderer.as_deref_mut()?.capture_frame(dir)
+    }
+
+    /// Run a console command now, see `EngineBuilder::console_command`.
+    pub fn run_command(&mut self, line: &str) -> Result<String, ConsoleError> {
+        self.console.run(self.world, line)
+    }
+
+    /// Leave the frame loop once this frame is complete.
+    pub fn exit(&mut self) {
+        self.exit_requested = true;
+    }
+}
+
+type StartCallback = Box<dyn FnOnce(&mut Frame)>;
+type FrameCallback = Box<dyn FnMut(&mut Frame)>;
+type ExitCallback = Box<dyn FnOnce(&mut World)>;
+
+#[derive(Default)]
+struct Callbacks {
+    on_start: Option<StartCallback>,
+    on_frame: Option<FrameCallback>,
+    on_exit: Option<ExitCallback>,
+}
+
+/// Configures and builds an `Engine`.
+pub struct EngineBuilder {
+    config: EngineConfig,
+    systems: Vec<Box<dyn GameSystem>>,
+    retry_policy: RetryPolicy,
+    callbacks: Callbacks,
+    console: Console,
+    timeline: Rc<RefCell<Timeline>>,
+    calibration: Rc<RefCell<Calibration>>,
+    rooms: Rc<RefCell<RoomAdmin>>,
+    gpu_stats: Rc<RefCell<GpuStatsCapture>>,
+    pacing: Rc<RefCell<RenderSettings>>,
+    plugins: Rc<RefCell<Plugins>>,
+    admin: Rc<RefCell<AdminRequests>>,
+    input_macro: Rc<RefCell<MacroRecording>>,
+    render_path: Rc<RefCell<RenderPath>>,
+    relative_mouse: Rc<RefCell<bool>>,
+    mouse_look: Rc<RefCell<MouseLook>>,
+    mixer: Rc<RefCell<MixerSettings>>,
+    logger: Logger,
+}
+
+impl EngineBuilder {
+    pub fn new(logger: &Logger) -> Self {
+        let mut console = Console::with_builtin_commands();
+        let timeline = Rc::new(RefCell::new(Timeline::new(TimelineConfig::default())));
+        timeline::register_commands(&mut console, &timeline);
+        let calibration = Rc::new(RefCell::new(Calibration::default()));
+        calibration::register_commands(&mut console, &calibration);
+        player_slots::register_commands(&mut console);
+        let rooms = Rc::new(RefCell::new(RoomAdmin::default()));
+        rooms::register_commands(&mut console, &rooms);
+        let gpu_stats = Rc::new(RefCell::new(GpuStatsCapture::default()));
+        gpu_stats::register_commands(&mut console, &gpu_stats);
+        let pacing = Rc::new(RefCell::new(RenderSettings::default()));
+        pacing::register_commands(&mut console, &pacing);
+        let plugins = Rc::new(RefCell::new(Plugins::default()));
+        plugins::register_commands(&mut console, &plugins);
+        let admin = Rc::new(RefCell::new(AdminRequests::default()));
+        admin::register_commands(&mut console, &admin);
+        let input_macro = Rc::new(RefCell::new(MacroRecording::default()));
+        input_macro::register_commands(&mut console, &input_macro);
+        let render_path = Rc::new(RefCell::new(RenderPath::default()));
+        render_path::register_commands(&mut console, &render_path);
+        let relative_mouse = Rc::new(RefCell::new(false));
+        let mouse_look = Rc::new(RefCell::new(MouseLook::default()));
+        mouse::register_commands(&mut console, &relative_mouse, &mouse_look);
+        let mixer = Rc::new(RefCell::new(MixerSettings::default()));
+        mixer::register_commands(&mut console, &mixer);
+        Self {
+            config: EngineConfig::default(),
+            systems: Vec::new(),
+            retry_policy: RetryPolicy::default(),
+            callbacks: Callbacks::default(),
+            console,
+            timeline,
+            calibration,
+            rooms,
+            gpu_stats,
+            pacing,
+            plugins,
+            admin,
+            input_macro,
+            render_path,
+            relative_mouse,
+            mouse_look,
+            mixer,
+            logger: logger.sub("engine"),
+        }
+    }
+
+    pub fn window(mut self, window: WindowConfig) -> Self {
+        self.config.window = window;
+        self
+    }
+
+    /// Open another window showing the scene, see
+    /// `EngineConfig::extra_windows`.
+    pub fn extra_window(mut self, window: WindowConfig) -> Self {
+        self.config.extra_windows.push(window);
+        self
+    }
+
+    pub fn headless(mut self, headless: bool) -> Self {
+        self.config.headless = headless;
+        self
+    }
+
+    /// Render offscreen when headless, see `EngineConfig::offscreen`.
+    pub fn offscreen(mut self, width: u32, height: u32) -> Self {
+        self.config.offscreen = Some((width, height));
+        self
+    }
+
+    pub fn enable_validation_layer(mut self, enable: bool) -> Self {
+        self.config.enable_validation_layer = enable;
+        self
+    }
+
+    pub fn connect_to_server(mut self, addr: Option<String>) -> Self {
+        self.config.connect_to_server = addr;
+        self
+    }
+
+    /// Run a client in this process too, connected over loopback with the
+    /// given simulated latency. Overrides `connect_to_server`.
+    pub fn listen_and_connect_self(mut self, latency: Option<Duration>) -> Self {
+        self.config.listen_and_connect_self = latency;
+        self
+    }
+
+    pub fn net_disabled(mut self, net_disabled: bool) -> Self {
+        self.config.net_disabled = net_disabled;
+        self
+    }
+
+    pub fn net_compression(mut self, codec: Compression) -> Self {
+        self.config.net_compression = codec;
+        self
+    }
+
+    pub fn net_trace(mut self, path: Option<PathBuf>) -> Self {
+        self.config.net_trace = path;
+        self
+    }
+
+    /// How replicated entities without a `ReplicationPolicy` move between
+    /// updates from the server, they jump to each one by default.
+    pub fn replication(mut self, policy: ReplicationPolicy) -> Self {
+        self.config.replication = policy;
+        self
+    }
+
+    /// Draw interpolated entities this far behind the server, rather than
+    /// adapting to the connection's quality.
+    pub fn interpolation_delay(mut self, delay: Option<Duration>) -> Self {
+        self.config.interpolation_delay = delay;
+        self
+    }
+
+    pub fn render_scale(mut self, render_scale: RenderScale) -> Self {
+        self.config.render_scale = render_scale;
+        self
+    }
+
+    pub fn render_path(mut self, render_path: RenderPath) -> Self {
+        self.config.render_path = render_path;
+        self
+    }
+
+    pub fn relative_mouse(mut self, relative_mouse: bool) -> Self {
+        self.config.relative_mouse = relative_mouse;
+        self
+    }
+
+    pub fn mouse_look(mut self, mouse_look: MouseLook) -> Self {
+        self.config.mouse_look = mouse_look;
+        self
+    }
+
+    pub fn mixer(mut self, mixer: MixerSettings) -> Self {
+        self.config.mixer = mixer;
+        self
+    }
+
+    pub fn aspect_policy(mut self, aspect_policy: AspectPolicy) -> Self {
+        self.config.aspect_policy = aspect_policy;
+        self
+    }
+
+    pub fn frames_in_flight(mut self, frames_in_flight: u32) -> Self {
+        self.config.frames_in_flight = frames_in_flight;
+        self
+    }
+
+    pub fn fps_cap(mut self, fps_cap: Option<f32>) -> Self {
+        self.config.fps_cap = fps_cap;
+        self
+    }
+
+    pub fn present_mode(mut self, present_mode: PresentMode) -> Self {
+        self.config.present_mode = present_mode;
+        self
+    }
+
+    pub fn world_limits(mut self, world_limits: WorldLimits) -> Self {
+        self.config.world_limits = world_limits;
+        self
+    }
+
+    pub fn scene(mut self, path: Option<PathBuf>) -> Self {
+        self.config.scene = path;
+        self
+    }
+
+    pub fn preload_budget(mut self, bytes: usize) -> Self {
+        self.config.preload_budget = bytes;
+        self
+    }
+
+    pub fn frame_length(mut self, frame_length: Duration) -> Self {
+        self.config.frame_length = frame_length;
+        self
+    }
+
+    /// Spawn and move a soak test's entities, and exit after its last tick,
+    /// failing with `EngineError::SoakFailed` if anything kept growing.
+    pub fn soak(mut self, soak: SoakConfig) -> Self {
+        self.config.soak = Some(soak);
+        self
+    }
+
+    /// When to capture frame timelines, see `Timeline`.
+    pub fn timeline(mut self, timeline: TimelineConfig) -> Self {
+        self.config.timeline = timeline;
+        self
+    }
+
+    /// Where gamepad calibration profiles are kept, see `Calibration`.
+    pub fn gamepad_profiles(mut self, path: PathBuf) -> Self {
+        self.config.gamepad_profiles = path;
+        self
+    }
+
+    /// Where gamepads are remembered by the player they joined as, see
+    /// `PlayerSlots`.
+    pub fn player_slots(mut self, path: PathBuf) -> Self {
+        self.config.player_slots = path;
+        self
+    }
+
+    /// Where the renderer writes crash reports, see `RenderState`.
+    pub fn crash_report_dir(mut self, dir: PathBuf) -> Self {
+        self.config.crash_report_dir = dir;
+        self
+    }
+
+    /// Keep this many of the world's most recent significant events, see
+    /// `World::journal`.
+    pub fn journal_len(mut self, len: usize) -> Self {
+        self.config.journal_len = len;
+        self
+    }
+
+    /// Write the world's journal to the crash report directory if a thread
+    /// panics. Panic hooks are process-wide, so only one engine in a process
+    /// should.
+    pub fn dump_journal_on_panic(mut self, dump: bool) -> Self {
+        self.config.dump_journal_on_panic = dump;
+        self
+    }
+
+    /// Load game systems from the plugin libraries in `dir`, see
+    /// `export_plugin!`.
+    pub fn plugins_dir(mut self, dir: Option<PathBuf>) -> Self {
+        self.config.plugins_dir = dir;
+        self
+    }
+
+    /// Take console commands on a localhost port, from connections that send
+    /// the socket's token first.
+    pub fn admin_socket(mut self, socket: Option<AdminSocket>) -> Self {
+        self.config.admin_socket = socket;
+        self
+    }
+
+    /// Run a server room alongside the engine's world from the start, see
+    /// the `rooms` console command.
+    pub fn room(mut self, room: RoomConfig) -> Self {
+        self.config.rooms.push(room);
+        self
+    }
+
+    /// Don't load a built-in system. Disabling net sync also disables
+    /// networking, as if `net_disabled` were set.
+    pub fn disable_system(mut self, system: BuiltinSystem) -> Self {
+        if !self.config.disabled_systems.contains(&system) {
+            self.config.disabled_systems.push(system);
+        }
+        self
+    }
+
+    /// Draw debug lines in these categories, they can be toggled later
+    /// through `World::debug_draw`.
+    pub fn debug_draw(mut self, categories: DebugCategories) -> Self {
+        self.config.debug_draw = categories;
+        self
+    }
+
+    /// Show the debug UI, with the engine's stats and any windows systems show
+    /// through `World::debug_ui`.
+    pub fn debug_ui(mut self, enabled: bool) -> Self {
+        self.config.debug_ui = enabled;
+        self
+    }
+
+    /// Play an input macro back in place of the local player's input, see
+    /// `input::macros`.
+    pub fn input_macro(mut self, path: impl Into<PathBuf>) -> Self {
+        self.config.input_macro = Some(path.into());
+        self
+    }
+
+    /// Record the local player's input to a file, saved as the engine exits
+    /// or with `input_macro stop`.
+    pub fn record_input_macro(mut self, path: impl Into<PathBuf>) -> Self {
+        self.config.record_input_macro = Some(path.into());
+        self
+    }
+
+    /// Register a game system, see `GameSystem`.
+    pub fn with_system(mut self, system: impl GameSystem + 'static) -> Self {
+        self.systems.push(Box::new(system));
+        self
+    }
+
+    /// How game systems that fail to load with a transient error are retried.
+    pub fn retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// Called once, after all systems are loaded and before the first frame.
+    pub fn on_start(mut self, callback: impl FnOnce(&mut Frame) + 'static) -> Self {
+        self.callbacks.on_start = Some(Box::new(callback));
+        self
+    }
+
+    /// Called at the end of every frame, after all systems are updated.
+    pub fn on_frame(mut self, callback: impl FnMut(&mut Frame) + 'static) -> Self {
+        self.callbacks.on_frame = Some(Box::new(callback));
+        self
+    }
+
+    /// Called once when the frame loop exits, after game systems are unloaded.
+    pub fn on_exit(mut self, callback: impl FnOnce(&mut World) + 'static) -> Self {
+        self.callbacks.on_exit = Some(Box::new(callback));
+        self
+    }
+
+    /// Register a console command, run with the words following its name.
+    /// Replaces a built-in command with the same name.
+    pub fn console_command(
+        mut self,
+        name: &str,
+        help: &str,
+        run: impl FnMut(&mut World, &[&str]) -> Result<String, String> + 'static,
+    ) -> Self {
+        self.console.register(name, help, run);
+        self
+    }
+
+    /// Register a console variable, shown with `set <name>` and changed with
+    /// `set <name> <value>`.
+    pub fn cvar(
+        mut self,
+        name: &str,
+        help: &str,
+        get: impl Fn(&World) -> String + 'static,
+        set: impl FnMut(&mut World, &str) -> Result<(), String> + 'static,
+    ) -> Self {
+        self.console.register_cvar(name, help, get, set);
+        self
+    }
+
+    /// Create the world and resolve the order systems run in. Nothing else is
+    /// set up until `Engine::run`.
+    pub fn build(mut self) -> Result<Engine, EngineError> {
+        if self.config.listen_and_connect_self.is_some() {
+            self.config.connect_to_server = None;
+        }
+        let mut world = World::new(
+            self.config.connect_to_server.clone(),
+            &self.logger,
+            !self.config.net_enabled(),
+        );
+        world.debug_draw.set_enabled(self.config.debug_draw, true);
+        world.debug_ui.set_enabled(self.config.debug_ui);
+        world.config.build_info = build_info::current();
+        info!(self.logger, "nanactyl {}", world.config.build_info);
+        world.config.net_compression = self.config.net_compression;
+        world.config.net_trace = self.config.net_trace.clone();
+        world.config.replication = self.config.replication;
+        world.config.interpolation_delay = self.config.interpolation_delay;
+        world.config.limits = self.config.world_limits;
+        world.config.scene = self.config.scene.clone();
+        world.config.preload_budget = self.config.preload_budget;
+
+        // Built-in systems come first, so they're loaded before game systems.
+        #[cfg_attr(not(feature = "world-update"), allow(unused_mut))]
+        let mut builtin: Vec<Box<dyn GameSystem>> = Vec::new();
+        #[cfg(feature = "world-update")]
+        if self.config.is_enabled(BuiltinSystem::WorldUpdate) {
+            builtin.push(Box::new(world_update_system::WorldUpdate::new()));
+        }
+        let builtin_systems = builtin.len();
+
+        let mut systems = builtin
+            .into_iter()
+            .chain(self.systems)
+            .map(|system| SystemHandle::new(system, self.retry_policy))
+            .collect::<Vec<_>>();
+        let mut schedule = PhaseSchedule::new(&systems)?;
+        // Plugins run after game systems, and don't stop the engine building
+        // when they can't be scheduled.
+        if self.config.plugins_dir.is_some() {
+            self.plugins
+                .borrow_mut()
+                .set_dir(self.config.plugins_dir.clone());
+            plugins::scan_and_log(&self.plugins, &self.logger);
+            let loaded = self.plugins.borrow_mut().take_loaded();
+            plugins::schedule_plugin_systems(
+                loaded,
+                &mut systems,
+                &mut schedule,
+                self.retry_policy,
+                &self.logger,
+            );
+        }
+        *self.timeline.borrow_mut() = Timeline::new(self.config.timeline.clone());
+        *self.pacing.borrow_mut() = RenderSettings {
+            present_mode: self.config.present_mode,
+            frame_cap: self.config.fps_cap,
+        };
+        *self.render_path.borrow_mut() = self.config.render_path;
+        *self.relative_mouse.borrow_mut() = self.config.relative_mouse;
+        *self.mouse_look.borrow_mut() = self.config.mouse_look;
+        *self.mixer.borrow_mut() = self.config.mixer;
+        // A malformed file is left alone, rather than overwritten by the next
+        // calibration.
+        match Calibration::load(&self.config.gamepad_profiles) {
+            Ok(calibration) => *self.calibration.borrow_mut() = calibration,
+            Err(err) => warn!(self.logger, "gamepads won't be calibrated: {err}"),
+        }
+        match PlayerSlots::load(&self.config.player_slots, DEFAULT_PLAYER_SLOTS) {
+            Ok(slots) => world.player_slots = slots,
+            Err(err) => warn!(self.logger, "gamepads won't rejoin their players: {err}"),
+        }
+        world.journal.set_capacity(self.config.journal_len);
+        if self.config.dump_journal_on_panic {
+            world
+                .journal
+                .dump_on_panic(self.config.crash_report_dir.clone());
+        }
+        self.admin.borrow_mut().journal_dir = self.config.crash_report_dir.clone();
+        if let Some(path) = &self.config.input_macro {
+            let input_macro = InputMacro::load(path).map_err(EngineError::InputMacro)?;
+            info!(
+                self.logger,
+                "playing {} ticks of input from {}",
+                input_macro.ticks().len(),
+                path.display()
+            );
+            world.input_macros.play(input_macro);
+        } else if let Some(path) = &self.config.record_input_macro {
+            world.input_macros.record();
+            self.input_macro.borrow_mut().path = Some(path.clone());
+        }
+        if let Some(socket) = &self.config.admin_socket {
+            admin::serve(socket, self.console.sender(), &self.logger)
+                .map_err(EngineError::AdminSocket)?;
+        }
+
+        Ok(Engine {
+            config: self.config,
+            world: Arc::new(Mutex::new(world)),
+            systems,
+            builtin_systems,
+            schedule,
+            retry_policy: self.retry_policy,
+            callbacks: self.callbacks,
+            console: self.console,
+            timeline: self.timeline,
+            calibration: self.calibration,
+            rooms: self.rooms,
+            gpu_stats: self.gpu_stats,
+            pacing: self.pacing,
+            plugins: self.plugins,
+            admin: self.admin,
+            input_macro: self.input_macro,
+            render_path: self.render_path,
+            relative_mouse: self.relative_mouse,
+            mouse_look: self.mouse_look,
+            mixer: self.mixer,
+            logger: self.logger,
+        })
+    }
+}
+
+pub struct Engine {
+    config: EngineConfig,
+    world: Arc<Mutex<World>>,
+    systems: Vec<SystemHandle>,
+    // Number of built-in systems at the front of `systems`.
+    builtin_systems: usize,
+    schedule: PhaseSchedule,
+    retry_policy: RetryPolicy,
+    callbacks: Callbacks,
+    console: Console,
+    // Shared with the console commands arming captures.
+    timeline: Rc<RefCell<Timeline>>,
+    // Shared with the platform and the console commands calibrating gamepads.
+    calibration: Rc<RefCell<Calibration>>,
+    // Shared with the console commands creating and destroying rooms.
+    rooms: Rc<RefCell<RoomAdmin>>,
+    // Shared with the console command showing GPU statistics.
+    gpu_stats: Rc<RefCell<GpuStatsCapture>>,
+    // Shared with the console commands capping the frame rate and choosing
+    // the present mode, and handed to the renderer each frame.
+    pacing: Rc<RefCell<RenderSettings>>,
+    // Shared with the console commands loading plugins.
+    plugins: Rc<RefCell<Plugins>>,
+    // Shared with the console commands reloading the scene and dumping the
+    // journal.
+    admin: Rc<RefCell<AdminRequests>>,
+    // Shared with the console commands recording input macros.
+    input_macro: Rc<RefCell<MacroRecording>>,
+    // Shared with the console variable switching render paths.
+    render_path: Rc<RefCell<RenderPath>>,
+    // Shared with the console variable capturing the mouse.
+    relative_mouse: Rc<RefCell<bool>>,
+    // Shared with the console variables for mouse look, and handed to the
+    // platform each frame.
+    mouse_look: Rc<RefCell<MouseLook>>,
+    // Shared with the audio console variables, and handed to the mixer each
+    // frame.
+    mixer: Rc<RefCell<MixerSettings>>,
+    logger: Logger,
+}
+
+impl Engine {
+    pub fn config(&self) -> &EngineConfig {
+        &self.config
+    }
+
+    pub fn world(&self) -> &Arc<Mutex<World>> {
+        &self.world
+    }
+
+    /// Built-in systems driven through `GameSystem`, followed by game systems,
+    /// in the order they were registered.
+    pub fn systems(&self) -> &[SystemHandle] {
+        &self.systems
+    }
+
+    /// Send console commands from another thread, they run at the end of the
+    /// next frame and their output is logged.
+    pub fn console_sender(&self) -> ConsoleSender {
+        self.console.sender()
+    }
+
+    /// Set up the platform, renderer and systems, and run the frame loop until
+    /// the window is closed or a callback asks to exit.
+    pub fn run(self) -> Result<(), EngineError> {
+        future::block_on(self.frame_loop())
+    }
+
+    async fn frame_loop(mut self) -> Result<(), EngineError> {
+        let logger = self.logger.sub("main");
+        let config = &self.config;
+        let world = Arc::clone(&self.world);
+
+        let own_controllers: [InputState; 2] = Default::default();
+        let own_controllers = Arc::new(Mutex::new(own_controllers));
+
+        let mut platform_context = if config.headless {
+            match config.offscreen {
+                Some((width, height)) => info!(
+                    logger,
+                    "running headless, rendering offscreen at {width}x{height}"
+                ),
+                None => info!(
+                    logger,
+                    "running headless, no window or renderer will be created"
+                ),
+            }
+            None
+        } else {
+            let mut platform_context = PlatformContext::new(&logger)?;
+            platform_context.set_calibration(Rc::clone(&self.calibration));
+            Some(platform_context)
+        };
+
+        let mut main_window = None;
+        // Platform window of each extra window's render target.
+        let mut target_windows: Vec<(usize, RenderTargetId)> = Vec::new();
+        let render_logger = logger
+            .plugin("ash_renderer", ash_renderer_system::VERSION)
+            .sub("render_state");
+        let render_state = match platform_context.as_mut() {
+            Some(platform_context) => {
+                let window = &config.window;
+                let index = platform_context.add_vulkan_window(
+                    &window.title,
+                    window.x,
+                    window.y,
+                    window.width,
+                    window.height,
+                )?;
+                let win_ptr = platform_context
+                    .get_raw_window_handle(index)
+                    .ok_or(EngineError::NoWindowHandle(index))?;
+                main_window = Some(index);
+
+                let mut render_state = RenderState::new(
+                    win_ptr,
+                    platform_context.window_size(index).unwrap_or_default(),
+                    config.enable_validation_layer,
+                    config.connect_to_server.is_none(),
+                    render_logger,
+                );
+                for window in &config.extra_windows {
+                    let index = platform_context.add_vulkan_window(
+                        &window.title,
+                        window.x,
+                        window.y,
+                        window.width,
+                        window.height,
+                    )?;
+                    let win_ptr = platform_context
+                        .get_raw_window_handle(index)
+                        .ok_or(EngineError::NoWindowHandle(index))?;
+                    let size = platform_context.window_size(index).unwrap_or_default();
+                    let id = render_state.targets.add(win_ptr, size, None);
+                    target_windows.push((index, id));
+                }
+                Some(render_state)
+            }
+            None => config.offscreen.map(|(width, height)| {
+                RenderState::offscreen(width, height, config.enable_validation_layer, render_logger)
+            }),
+        };
+        let mut renderer = match render_state {
+            Some(mut render_state) => {
+                render_state.render_scale = config.render_scale;
+                render_state.aspect_policy = config.aspect_policy;
+                render_state.frames_in_flight = config.frames_in_flight;
+                render_state.set_settings(RenderSettings {
+                    present_mode: config.present_mode,
+                    frame_cap: config.fps_cap,
+                });
+                render_state.render_path = config.render_path;
+                render_state.crash_report_dir = config.crash_report_dir.clone();
+                let render_state = render_state.into_shared();
+
+                let mut ash_renderer_system =
+                    ash_renderer_system::VulkanRenderPluginState::default();
+                ash_renderer_system.load(&mut *render_state.lock().await);
+                Some((render_state, ash_renderer_system))
+            }
+            None => None,
+        };
+
+        // Built-in systems load ahead of net sync and the asset loader, game
+        // systems after them. Loading an already loaded system does nothing.
+        let mut system_changes = Vec::new();
+        {
+            let world = &mut *world.lock().await;
+            let now = Instant::now();
+            for system in self.systems[..self.builtin_systems].iter_mut() {
+                system.load(world, now, &mut system_changes);
+            }
+        }
+
+        #[cfg(feature = "net-sync")]
+        let mut loopback_connection = None;
+        #[cfg(feature = "net-sync")]
+        let mut net_sync_system = if config.net_enabled() {
+            let mut net = match config.listen_and_connect_self {
+                Some(latency) => {
+                    info!(
+                        logger,
+                        "running a client in-process over loopback, latency {latency:?}"
+                    );
+                    let (server, client) = network::loopback::LoopbackConnection::pair(latency);
+                    loopback_connection = Some(client);
+                    net_sync_system::NetSyncState::with_connection(server)
+                }
+                None => net_sync_system::NetSyncState::new(),
+            };
+            net.set_logger(logger.plugin(BuiltinSystem::NetSync.name(), net_sync_system::VERSION));
+            let mut state =
+                world::WorldLockAndControllerState::lock(&world, &own_controllers).await;
+            net.load(&mut state);
+            Some(net)
+        } else {
+            None
+        };
+
+        #[cfg(feature = "asset-loader")]
+        let asset_state = Arc::new(Mutex::new(world::AssetLoaderState::default()));
+        #[cfg(feature = "asset-loader")]
+        let mut asset_loader = if config.is_enabled(BuiltinSystem::AssetLoader) {
+            let mut asset_loader = asset_loader_system::AssetLoader::new();
+            asset_loader.set_logger(logger.plugin(
+                BuiltinSystem::AssetLoader.name(),
+                asset_loader_system::VERSION,
+            ));
+            asset_loader
+                .load(&mut world::AssetLoaderStateAndWorldLock::lock(&world, &asset_state).await);
+            Some(asset_loader)
+        } else {
+            None
+        };
+
+        #[cfg(feature = "net-sync")]
+        let mut loopback_client = match loopback_connection {
+            Some(connection) => {
+                Some(loopback::load_client(config, connection, self.retry_policy, &logger).await)
+            }
+            None => None,
+        };
+        #[cfg(not(feature = "net-sync"))]
+        if config.listen_and_connect_self.is_some() {
+            logger::warn!(
+                logger,
+                "net sync is not compiled in, not running a loopback client"
+            );
+        }
+
+        #[cfg(feature = "net-sync")]
+        let mut rooms = {
+            let mut rooms = rooms::Rooms::new(self.retry_policy, &logger);
+            rooms.load(config).await;
+            Some(rooms)
+        };
+        #[cfg(not(feature = "net-sync"))]
+        if !config.rooms.is_empty() {
+            warn!(logger, "net sync is not compiled in, not running rooms");
+        }
+
+        let mut pending_changes = Vec::new();
+        let mut soak = config.soak.map(|soak| Scenario::new(soak, &logger));
+        let mut soak_report = None;
+        {
+            let world = &mut *world.lock().await;
+            let now = Instant::now();
+            for system in self.systems.iter_mut() {
+                system.load(world, now, &mut system_changes);
+            }
+            log_system_changes(&logger, &system_changes);
+            journal_system_changes(world, &system_changes);
+            post_system_failures(world, &system_changes);
+            if let Some(on_start) = self.callbacks.on_start.take() {
+                on_start(&mut Frame {
+                    number: 0,
+                    delta_time: Duration::ZERO,
+                    world,
+                    platform: platform_context.as_mut(),
+                    renderer: renderer.as_mut().map(|(_, renderer)| renderer),
+                    systems: &mut self.systems,
+                    system_changes: &system_changes,
+                    pending_changes: &mut pending_changes,
+                    console: &mut self.console,
+                    exit_requested: false,
+                });
+            }
+            if let Some(soak) = soak.as_mut() {
+                soak.spawn(world);
+            }
+        }
+
+        let mut frame_start;
+        let mut last_frame_complete = Instant::now();
+        let mut frame = 0u64;
+        let mut frame_histogram = Histogram::new();
+        // Handed to the renderer for a dynamic render scale to adjust to.
+        let mut last_frame_time = None;
+
+        // The debug UI is only fed input, and so only built, with a window.
+        let ui_epoch = Instant::now();
+        let mut ui_pointer = egui::Pos2::ZERO;
+        let mut cursor_captured_by_ui = false;
+        // Emitters are only heard with a window, too.
+        let mut emitter_voices = EmitterVoices::default();
+
+        let timeline = Rc::clone(&self.timeline);
+        let enter = |phase: FramePhase| timeline.borrow_mut().enter(phase.name(), Instant::now());
+
+        'frame_loop: loop {
+            frame_start = Instant::now();
+            timeline.borrow_mut().begin_frame(frame, frame_start);
+            let last_frame_elapsed = last_frame_complete.elapsed();
+            let mut system_changes = std::mem::take(&mut pending_changes);
+            let systems = &mut self.systems;
+            let schedule = &self.schedule;
+
+            // FramePhase::Input
+            enter(FramePhase::Input);
+            // Rumbles are dropped without controllers to play them on.
+            let (rumbles, haptics) = {
+                let world = &mut *world.lock().await;
+                (
+                    std::mem::take(&mut world.local_rumbles),
+                    world.haptics.take_requests(),
+                )
+            };
+            if let Some(platform_context) = platform_context.as_mut() {
+                let relative_mouse = *self.relative_mouse.borrow();
+                if platform_context.relative_mouse_mode() != relative_mouse {
+                    platform_context.set_relative_mouse_mode(relative_mouse);
+                }
+                platform_context.set_mouse_look(*self.mouse_look.borrow());
+                platform_context.pump_events();
+
+                let (game_events, ui_events) = {
+                    let world = &mut *world.lock().await;
+                    // The UI is only shown with a window to show it in.
+                    world.input_focus.update_from_ui(
+                        world.debug_ui.is_enabled() && main_window.is_some(),
+                        world.debug_ui.wants_keyboard_input(),
+                        world.debug_ui.wants_pointer_input(),
+                    );
+                    let (game_events, ui_events) =
+                        route_input_events(platform_context.peek_events(), &mut world.input_focus);
+                    // Relative motion is only reported while the game has the
+                    // mouse, see `PlatformContext::is_relative_mouse_active`.
+                    let (yaw, pitch) = platform_context.look_delta();
+                    world.pending_look.0 += yaw;
+                    world.pending_look.1 += pitch;
+                    player_slots::update(world, &self.calibration.borrow(), &game_events, &logger);
+                    (game_events, ui_events)
+                };
+
+                if let Some(EngineEvent::ExitToDesktop) = handle_input_events(
+                    &game_events,
+                    &mut *own_controllers.lock().await,
+                    logger.sub("handle_input_events"),
+                ) {
+                    break 'frame_loop;
+                }
+
+                if let Some((render_state, _)) = renderer.as_mut() {
+                    for event in platform_context.peek_events() {
+                        match (event, main_window) {
+                            (EngineEvent::WindowResized(index), Some(main_window))
+                                if *index == main_window =>
+                            {
+                                if let Some(size) = platform_context.window_size(main_window) {
+                                    render_state.lock().await.set_window_size(size);
+                                }
+                            }
+                            (EngineEvent::WindowResized(index), _) => {
+                                let target = target_windows
+                                    .iter()
+                                    .find(|(window, _)| window == index)
+                                    .map(|(_, id)| *id);
+                                if let (Some(id), Some(size)) =
+                                    (target, platform_context.window_size(*index))
+                                {
+                                    render_state.lock().await.set_target_window_size(id, size);
+                                }
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+
+                if let Some(size) =
+                    main_window.and_then(|index| platform_context.window_size(index))
+                {
+                    let world = &mut *world.lock().await;
+                    world.debug_ui.begin_frame(debug_ui::raw_input(
+                        &ui_events,
+                        size,
+                        ui_epoch.elapsed(),
+                        &mut ui_pointer,
+                    ));
+                    if world.debug_ui.is_enabled() != cursor_captured_by_ui {
+                        cursor_captured_by_ui = world.debug_ui.is_enabled();
+                        platform_context.set_cursor_captured_by_ui(cursor_captured_by_ui);
+                    }
+                }
+
+                emitter_voices.update(
+                    &mut *world.lock().await,
+                    platform_context.sound_mut(),
+                    &logger,
+                );
+                platform_context
+                    .audio_mixer_mut()
+                    .configure(&self.mixer.borrow());
+                platform_context.update_audio(&last_frame_elapsed);
+
+                for rumble in rumbles {
+                    platform_context.play_rumble(rumble);
+                }
+                for request in haptics {
+                    platform_context.play_haptics(request);
+                }
+                platform_context.update_rumble();
+            }
+            update_phase(
+                FramePhase::Input,
+                systems,
+                schedule,
+                &world,
+                &last_frame_elapsed,
+                &mut system_changes,
+            )
+            .await;
+
+            // FramePhase::PreSim
+            enter(FramePhase::PreSim);
+            let reload_scene = std::mem::take(&mut self.admin.borrow_mut().reload_scene);
+            #[cfg(feature = "asset-loader")]
+            let reload_scene = match asset_loader.as_mut() {
+                Some(asset_loader) => {
+                    let state =
+                        &mut world::AssetLoaderStateAndWorldLock::lock(&world, &asset_state).await;
+                    if reload_scene {
+                        info!(logger, "reloading the scene");
+                        asset_loader.unload(state);
+                        asset_loader.load(state);
+                    }
+                    asset_loader.update(state, &last_frame_elapsed);
+                    false
+                }
+                None => reload_scene,
+            };
+            if reload_scene {
+                warn!(
+                    logger,
+                    "the asset loader isn't running, not reloading the scene"
+                );
+            }
+            update_phase(
+                FramePhase::PreSim,
+                systems,
+                schedule,
+                &world,
+                &last_frame_elapsed,
+                &mut system_changes,
+            )
+            .await;
+
+            // FramePhase::Sim
+            enter(FramePhase::Sim);
+            #[cfg(feature = "net-sync")]
+            let net_synced = match net_sync_system.as_mut() {
+                Some(net_sync_system) => {
+                    net_sync_system.update(
+                        &mut world::WorldLockAndControllerState::lock(&world, &own_controllers)
+                            .await,
+                        &last_frame_elapsed,
+                    );
+                    true
+                }
+                None => false,
+            };
+            #[cfg(not(feature = "net-sync"))]
+            let net_synced = false;
+
+            #[cfg(feature = "net-sync")]
+            if let Some(loopback_client) = loopback_client.as_mut() {
+                loopback_client.update(&last_frame_elapsed).await;
+            }
+
+            #[cfg(feature = "net-sync")]
+            if let Some(rooms) = rooms.as_mut() {
+                rooms.update(&self.rooms, config, &last_frame_elapsed).await;
+            }
+            #[cfg(not(feature = "net-sync"))]
+            for request in self.rooms.borrow_mut().take_requests() {
+                warn!(logger, "net sync is not compiled in, ignoring {request:?}");
+            }
+
+            // Net is not enabled, so just update the world with the controller state
+            if !net_synced {
+                let controller_state = own_controllers.lock().await;
+                let world = &mut *world.lock().await;
+                world.set_server_controller_state(controller_state[0]);
+                world.set_client_controller_state(controller_state[1]);
+            }
+            update_phase(
+                FramePhase::Sim,
+                systems,
+                schedule,
+                &world,
+                &last_frame_elapsed,
+                &mut system_changes,
+            )
+            .await;
+
+            // FramePhase::PostSim
+            enter(FramePhase::PostSim);
+            update_phase(
+                FramePhase::PostSim,
+                systems,
+                schedule,
+                &world,
+                &last_frame_elapsed,
+                &mut system_changes,
+            )
+            .await;
+
+            // FramePhase::Extract
+            enter(FramePhase::Extract);
+            world.lock().await.draw_debug_reference();
+            // This is a bit convoluted, but the renderer plugin allows us to fetch a
+            // pointer to it's "state" which in this case is a dyn Renderer + Presenter
+            // trait object
+            if let Some((render_state, ash_renderer_system)) = renderer.as_mut() {
+                let world = &mut *world.lock().await;
+                let mut render_state = render_state.lock().await;
+                render_state.release_despawned_graphics(world, ash_renderer_system);
+                if cfg!(debug_assertions) && frame % GRAPHICS_AUDIT_FRAMES == 0 {
+                    let orphans = render_state.orphaned_graphics(world, ash_renderer_system);
+                    if !orphans.is_empty() {
+                        world.notify(
+                            Severity::Warning,
+                            "render",
+                            format!(
+                                "{} graphics tracked for despawned prefabs: {orphans:?}",
+                                orphans.len()
+                            ),
+                        );
+                    }
+                }
+                render_state.upload_untracked_graphics_prefabs(world, ash_renderer_system);
+                #[cfg(feature = "asset-loader")]
+                {
+                    let modified = asset_state.lock().await.take_modified_shaders();
+                    if !modified.is_empty() {
+                        info!(logger, "reloading shaders modified for {modified:?}");
+                        ash_renderer_system.update_resources();
+                    }
+                }
+            } else {
+                // Nothing was uploaded, so there's nothing to release or
+                // reload.
+                world.lock().await.despawned_graphics.clear();
+                #[cfg(feature = "asset-loader")]
+                asset_state.lock().await.modified_shaders.clear();
+            }
+            update_phase(
+                FramePhase::Extract,
+                systems,
+                schedule,
+                &world,
+                &last_frame_elapsed,
+                &mut system_changes,
+            )
+            .await;
+            {
+                let world = &mut *world.lock().await;
+                debug_ui::show_stats(world);
+                world.debug_ui.end_frame();
+            }
+
+            // FramePhase::Render
+            enter(FramePhase::Render);
+            if let Some((render_state, ash_renderer_system)) = renderer.as_mut() {
+                {
+                    let world = &*world.as_ref().lock().await;
+                    ash_renderer_system.present(world);
+                    for (_, target) in &target_windows {
+                        ash_renderer_system.present_to(*target, world);
+                    }
+                }
+                // Timings are left over from an earlier frame when nothing
+                // was presented.
+                if let Some(timings) = ash_renderer_system
+                    .present_timings()
+                    .filter(|timings| timings.submitted >= frame_start)
+                {
+                    let mut timeline = timeline.borrow_mut();
+                    timeline.mark("queue_submit", timings.submitted);
+                    timeline.mark("present", timings.presented);
+                }
+
+                let capture_gpu_stats = self.gpu_stats.borrow().enabled;
+                if capture_gpu_stats {
+                    self.gpu_stats.borrow_mut().latest = ash_renderer_system.gpu_stats();
+                }
+
+                // update the renderer and the world simultaneously
+                let render_state = &mut *render_state.lock().await;
+                render_state.capture_gpu_stats = capture_gpu_stats;
+                render_state.frame_time = last_frame_time.take();
+                render_state.set_settings(*self.pacing.borrow());
+                render_state.render_path = *self.render_path.borrow();
+                ash_renderer_system.update(render_state, &last_frame_elapsed);
+            }
+            update_phase(
+                FramePhase::Render,
+                systems,
+                schedule,
+                &world,
+                &last_frame_elapsed,
+                &mut system_changes,
+            )
+            .await;
+            timeline.borrow_mut().enter("end_of_frame", Instant::now());
+
+            log_system_changes(&logger, &system_changes);
+            {
+                let world = &mut *world.lock().await;
+                journal_system_changes(world, &system_changes);
+                post_system_failures(world, &system_changes);
+                for warning in world.query_stats.end_frame() {
+                    world.notify(Severity::Warning, "query_stats", warning);
+                }
+                world.notifications.expire(Instant::now());
+                for (line, result) in self.console.run_pending(world) {
+                    match result {
+                        Ok(output) if output.is_empty() => {}
+                        Ok(output) => info!(logger, "{line}: {output}"),
+                        Err(err) => error!(logger, "console: {err}"),
+                    }
+                }
+                if let Some(soak) = soak.as_mut() {
+                    let gpu_memory = renderer
+                        .as_ref()
+                        .and_then(|(_, renderer)| renderer.graphics_memory());
+                    soak_report = soak.tick(world, gpu_memory);
+                }
+            }
+            let exit_requested = match self.callbacks.on_frame.as_mut() {
+                Some(on_frame) => {
+                    let world = &mut *world.lock().await;
+                    let mut frame = Frame {
+                        number: frame,
+                        delta_time: last_frame_elapsed,
+                        world,
+                        platform: platform_context.as_mut(),
+                        renderer: renderer.as_mut().map(|(_, renderer)| renderer),
+                        systems: &mut self.systems,
+                        system_changes: &system_changes,
+                        pending_changes: &mut pending_changes,
+                        console: &mut self.console,
+                        exit_requested: false,
+                    };
+                    on_frame(&mut frame);
+                    frame.exit_requested
+                }
+                None => false,
+            };
+            // Plugins loaded from the console run from the next frame, and
+            // are loaded by their first update.
+            let plugin_systems = self.plugins.borrow_mut().take_loaded();
+            if !plugin_systems.is_empty() {
+                plugins::schedule_plugin_systems(
+                    plugin_systems,
+                    &mut self.systems,
+                    &mut self.schedule,
+                    self.retry_policy,
+                    &logger,
+                );
+            }
+
+            let packets = world.lock().await.connections().fold(
+                network::PacketCounts::default(),
+                |total, connection| {
+                    let packets = connection.packet_counts();
+                    network::PacketCounts {
+                        sent: total.sent + packets.sent,
+                        received: total.received + packets.received,
+                    }
+                },
+            );
+            let captured = timeline.borrow_mut().end_frame(Instant::now(), packets);
+            if let Some(frames) = captured {
+                match timeline::export(timeline.borrow().config(), &frames) {
+                    Ok(path) => info!(
+                        logger,
+                        "wrote a timeline of {} frames to {}",
+                        frames.len(),
+                        path.display()
+                    ),
+                    Err(err) => error!(logger, "unable to write a timeline: {err}"),
+                }
+            }
+
+            let elapsed = frame_start.elapsed();
+            let last_frame_elapsed_micros = elapsed.as_micros();
+
+            frame_histogram
+                .increment(last_frame_elapsed_micros as u64)
+                .unwrap();
+            last_frame_time = Some(elapsed);
+
+            if frame % 1000 == 0 {
+                info!(
+                    logger,
+                    "Frame time (µs): Min: {} Avg: {} Max: {} StdDev: {} 50%: {}, 90%: {}, 99%: {}, 99.9%:{}",
+                    frame_histogram.minimum().unwrap(),
+                    frame_histogram.mean().unwrap(),
+                    frame_histogram.maximum().unwrap(),
+                    frame_histogram.stddev().unwrap(),
+                    frame_histogram.percentile(50.0).unwrap(),
+                    frame_histogram.percentile(90.0).unwrap(),
+                    frame_histogram.percentile(99.0).unwrap(),
+                    frame_histogram.percentile(99.9).unwrap(),
+                );
+                frame_histogram.clear();
+                let world = &*world.lock().await;
+                log_ecs_stats(&logger, world);
+                log_compression_stats(&logger, world);
+                log_plugin_stats(&logger);
+            }
+
+            if exit_requested {
+                info!(logger, "exit requested by frame callback");
+                break 'frame_loop;
+            }
+            if let Some(report) = soak_report.as_ref() {
+                info!(logger, "soak test done, {report}");
+                break 'frame_loop;
+            }
+
+            let delay = config.frame_length.saturating_sub(elapsed);
+            let capped_frame_length = self.pacing.borrow().frame_length();
+            last_frame_complete = Instant::now();
+
+            // A cap is waited out precisely, so the frame rate doesn't depend
+            // on timer resolution or on presentation waiting for vertical
+            // blank.
+            match capped_frame_length {
+                Some(frame_length) => pacing::wait_until(frame_start + frame_length).await,
+                None => smol::Timer::after(delay).await,
+            }
+
+            frame += 1;
+        } // 'frame_loop
+
+        #[cfg(feature = "net-sync")]
+        if let Some(loopback_client) = loopback_client.take() {
+            loopback_client.unload().await;
+        }
+        #[cfg(feature = "net-sync")]
+        if let Some(rooms) = rooms.take() {
+            rooms.unload().await;
+        }
+
+        let world = &mut *world.lock().await;
+        let mut system_changes = Vec::new();
+        for system in self.systems.iter_mut().rev() {
+            system.unload(world, &mut system_changes);
+        }
+        log_system_changes(&logger, &system_changes);
+        journal_system_changes(world, &system_changes);
+        input_macro::save_on_exit(world, &self.input_macro, &logger);
+        if let Some(on_exit) = self.callbacks.on_exit.take() {
+            on_exit(world);
+        }
+        match soak_report {
+            Some(report) if !report.passed() => Err(EngineError::SoakFailed(report)),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Update the systems scheduled in `phase`, if there are any.
+async fn update_phase(
+    phase: FramePhase,
+    systems: &mut [SystemHandle],
+    schedule: &PhaseSchedule,
+    world: &Mutex<World>,
+    delta_time: &Duration,
+    changes: &mut Vec<SystemStateChange>,
+) {
+    let order = schedule.phase(phase);
+    if order.is_empty() {
+        return;
+    }
+    let world = &mut *world.lock().await;
+    let now = Instant::now();
+    for index in order {
+        systems[*index].update(world, delta_time, now, changes);
+    }
+}
+
+fn log_system_changes(logger: &Logger, changes: &[SystemStateChange]) {
+    for change in changes {
+        match &change.to {
+            SystemState::Failed {
+                error,
+                attempts,
+                retry_at,
+            } => error!(
+                logger,
+                "system {} failed to load after {attempts} attempt(s), {}: {error}",
+                change.system,
+                if retry_at.is_some() {
+                    "retrying"
+                } else {
+                    "giving up"
+                }
+            ),
+            to => info!(
+                logger,
+                "system {} {:?} -> {to:?}", change.system, change.from
+            ),
+        }
+    }
+}
+
+/// Log entity counts per archetype, pool usage and the slowest queries of the
+/// last frame.
+fn log_ecs_stats(logger: &Logger, world: &World) {
+    for archetype in world.archetype_stats() {
+        debug!(
+            logger,
+            "archetype [{}]: {} entities",
+            archetype.components.join(", "),
+            archetype.entities
+        );
+    }
+    for (pool, stats) in world.pools.stats() {
+        debug!(
+            logger,
+            "pool {pool}: {} of {} free, {:.0}% hit rate",
+            stats.free,
+            stats.capacity,
+            stats.hit_rate() * 100.0
+        );
+    }
+    for (system, query, counter) in world.query_stats.last_frame() {
+        debug!(
+            logger,
+            "query {system}/{query}: {} runs, {} items, {:?}",
+            counter.runs,
+            counter.items,
+            counter.time
+        );
+    }
+}
+
+/// Log ratio and throughput of each codec updates were compressed or
+/// decompressed with.
+fn log_compression_stats(logger: &Logger, world: &World) {
+    let stats = &world.compression_stats;
+    for (direction, codecs) in [
+        ("compressed", stats.compressed().collect::<Vec<_>>()),
+        ("decompressed", stats.decompressed().collect::<Vec<_>>()),
+    ] {
+        for (codec, stats) in codecs {
+            debug!(
+                logger,
+                "{codec} {direction} {} updates, {:.2}x ratio, {:.1} MB/s",
+                stats.payloads,
+                stats.ratio(),
+                stats.throughput_mb_s()
+            );
+        }
+    }
+}
+
+/// Log how much each plugin has logged, and at what level.
+fn log_plugin_stats(logger: &Logger) {
+    for stats in logger.plugin_stats() {
+        debug!(logger, "{stats}");
+    }
+}
+
+/// Journal systems loading, failing and reloading, they're already logged by
+/// `log_system_changes`.
+fn journal_system_changes(world: &World, changes: &[SystemStateChange]) {
+    for change in changes {
+        let description = match &change.to {
+            SystemState::Failed { error, .. } => format!("failed to load: {error}"),
+            to => format!("{:?} -> {to:?}", change.from),
+        };
+        world.record(JournalEvent::System {
+            name: change.system.clone(),
+            change: description,
+        });
+    }
+}
+
+/// Let the user know about systems that failed to load, they're already
+/// logged by `log_system_changes`.
+fn post_system_failures(world: &mut World, changes: &[SystemStateChange]) {
+    let now = Instant::now();
+    for change in changes {
+        if let SystemState::Failed { error, .. } = &change.to {
+            world.notifications.post(
+                Severity::Error,
+                &change.system,
+                format!("failed to load: {error}"),
+                now,
+            );
+        }
+    }
+}
+
+/// Split the events pumped this frame between gameplay and the UI, see
+/// `world::focus`. Gameplay first gets releases of whatever it lost focus of,
+/// and events other than input go to both.
+fn route_input_events(
+    events: &[EngineEvent],
+    focus: &mut InputFocus,
+) -> (Vec<EngineEvent>, Vec<EngineEvent>) {
+    let mut game = focus
+        .take_releases()
+        .into_iter()
+        .map(EngineEvent::Input)
+        .collect::<Vec<_>>();
+    let mut ui = Vec::new();
+    for event in events {
+        let target = match event {
+            EngineEvent::Input(input_event) => focus.route(input_event),
+            _ => InputTarget::Both,
+        };
+        if target.to_game() {
+            game.push(event.clone());
+        }
+        if target.to_ui() {
+            ui.push(event.clone());
+        }
+    }
+    (game, ui)
+}
+
+fn handle_input_events(
+    events: &[EngineEvent],
+    controllers: &mut [InputState; 2],
+    logger: Logger,
+) -> Option<EngineEvent> {
+    if !events.is_empty() {
+        for event in events {
+            match event {
+                EngineEvent::Continue => {}
+                EngineEvent::InputDevice(DeviceEvent::GameControllerAdded(id)) => {
+                    info!(logger, "gamepad {id} added");
+                    controllers[*id as usize] = InputState::new(*id as u8);
+                }
+                EngineEvent::InputDevice(DeviceEvent::GameControllerRemoved(id)) => {
+                    info!(logger, "gamepad {id} removed");
+                    controllers[*id as usize] = Default::default();
+                }
+                EngineEvent::InputDevice(input_device_event) => {
+                    info!(logger, "input device event {input_device_event:?}");
+                }
+                EngineEvent::Input(input_event) => {
+                    controllers[0].update_from_event(input_event);
+                }
+                // Handled by the frame loop, which owns the render state.
+                EngineEvent::WindowResized(_) => {}
+                // Played by the platform already.
+                EngineEvent::Haptics(_) => {}
+                ret @ EngineEvent::ExitToDesktop => {
+                    info!(logger, "Got exit with code {ret:?}");
+                    return Some(ret.clone());
+                }
+            }
+        }
+    }
+    None
+}
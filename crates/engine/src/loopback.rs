@@ -0,0 +1,42 @@
+//! Runs a client world in the same process as the server, connected to it over
+//! a `LoopbackConnection`, so that client and server can be exercised together
+//! without launching a second process.
+
+use std::net::SocketAddr;
+
+use logger::{info, Logger};
+use net_sync_system::NetSyncState;
+use network::loopback::LoopbackConnection;
+use world::World;
+
+use crate::world_runner::WorldRunner;
+use crate::{EngineConfig, RetryPolicy};
+
+// The client world never connects to this, it only marks the world as a client.
+const LOOPBACK_SERVER_ADDR: SocketAddr =
+    SocketAddr::new(std::net::IpAddr::V4(std::net::Ipv4Addr::LOCALHOST), 0);
+
+/// Load a headless client, with its own world and built-in systems, syncing
+/// over `connection`.
+pub(crate) async fn load_client(
+    config: &EngineConfig,
+    connection: LoopbackConnection,
+    retry_policy: RetryPolicy,
+    logger: &Logger,
+) -> WorldRunner {
+    let logger = logger.sub("loopback-client");
+    let mut world = World::new(Some(LOOPBACK_SERVER_ADDR.to_string()), &logger, false);
+    world.config.limits = config.world_limits;
+    let client = WorldRunner::load(
+        world,
+        NetSyncState::with_connection(connection),
+        config,
+        #[cfg(feature = "asset-loader")]
+        asset_loader_system::ModelCache::new(),
+        retry_policy,
+        logger,
+    )
+    .await;
+    info!(client.logger(), "client loaded, connected over loopback");
+    client
+}
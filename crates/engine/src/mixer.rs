@@ -0,0 +1,115 @@
+//! Console variables for the audio mixer's bus volumes and for how music is
+//! ducked under sound effects, see `MixerSettings`. The settings are handed to
+//! the mixer every frame, so changes are heard right away.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::Duration;
+
+use platform::audio::MixerSettings;
+
+use crate::console::Console;
+
+pub(crate) fn register_commands(console: &mut Console, mixer: &Rc<RefCell<MixerSettings>>) {
+    register_gain(
+        console,
+        mixer,
+        "master_volume",
+        "volume of every bus",
+        |settings| &mut settings.master_volume,
+    );
+    register_gain(
+        console,
+        mixer,
+        "sfx_volume",
+        "volume of sound effects",
+        |settings| &mut settings.sfx_volume,
+    );
+    register_gain(
+        console,
+        mixer,
+        "music_volume",
+        "volume of music",
+        |settings| &mut settings.music_volume,
+    );
+    register_gain(
+        console,
+        mixer,
+        "ui_volume",
+        "volume of UI sounds",
+        |settings| &mut settings.ui_volume,
+    );
+    register_gain(
+        console,
+        mixer,
+        "music_duck_under_sfx",
+        "gain applied to music while sound effects play, 1 to not duck it",
+        |settings| &mut settings.music_duck_under_sfx,
+    );
+    register_millis(
+        console,
+        mixer,
+        "music_duck_attack_ms",
+        "milliseconds music takes to duck under sound effects",
+        |settings| &mut settings.music_duck_attack,
+    );
+    register_millis(
+        console,
+        mixer,
+        "music_duck_release_ms",
+        "milliseconds music takes to recover once sound effects stop",
+        |settings| &mut settings.music_duck_release,
+    );
+}
+
+fn register_gain(
+    console: &mut Console,
+    mixer: &Rc<RefCell<MixerSettings>>,
+    name: &str,
+    help: &str,
+    field: fn(&mut MixerSettings) -> &mut f32,
+) {
+    let get = Rc::clone(mixer);
+    let set = Rc::clone(mixer);
+    console.register_cvar(
+        name,
+        &format!("{help}, from 0 to 1"),
+        move |_world| {
+            let mut settings = *get.borrow();
+            field(&mut settings).to_string()
+        },
+        move |_world, value| {
+            *field(&mut set.borrow_mut()) = match value.parse::<f32>() {
+                Ok(gain) if (0.0..=1.0).contains(&gain) => gain,
+                _ => return Err(format!("expected a number from 0 to 1, got {value:?}")),
+            };
+            Ok(())
+        },
+    );
+}
+
+fn register_millis(
+    console: &mut Console,
+    mixer: &Rc<RefCell<MixerSettings>>,
+    name: &str,
+    help: &str,
+    field: fn(&mut MixerSettings) -> &mut Duration,
+) {
+    let get = Rc::clone(mixer);
+    let set = Rc::clone(mixer);
+    console.register_cvar(
+        name,
+        help,
+        move |_world| {
+            let mut settings = *get.borrow();
+            field(&mut settings).as_millis().to_string()
+        },
+        move |_world, value| {
+            let millis = value
+                .parse()
+                .map_err(|_| format!("expected milliseconds, got {value:?}"))?;
+            *field(&mut set.borrow_mut()) = Duration::from_millis(millis);
+            Ok(())
+        },
+    );
+}
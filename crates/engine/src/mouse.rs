@@ -0,0 +1,69 @@
+//! Mouse look console variables: `relative_mouse`, capturing the mouse for
+//! mouse look while running, see `PlatformContext::set_relative_mouse_mode`,
+//! and `mouse_sensitivity` and `invert_y`, how its motion turns the camera,
+//! see `MouseLook`.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use input::MouseLook;
+
+use crate::console::Console;
+
+pub(crate) fn register_commands(
+    console: &mut Console,
+    relative_mouse: &Rc<RefCell<bool>>,
+    mouse_look: &Rc<RefCell<MouseLook>>,
+) {
+    let get = Rc::clone(relative_mouse);
+    let set = Rc::clone(relative_mouse);
+    console.register_cvar(
+        "relative_mouse",
+        "capture the mouse for mouse look, on or off",
+        move |_world| on_off(*get.borrow()),
+        move |_world, value| {
+            *set.borrow_mut() = parse_on_off(value)?;
+            Ok(())
+        },
+    );
+    let get = Rc::clone(mouse_look);
+    let set = Rc::clone(mouse_look);
+    console.register_cvar(
+        "mouse_sensitivity",
+        "radians mouse look turns per count of mouse motion",
+        move |_world| get.borrow().sensitivity.to_string(),
+        move |_world, value| {
+            set.borrow_mut().sensitivity = match value.parse::<f32>() {
+                Ok(sensitivity) if sensitivity.is_finite() && sensitivity > 0.0 => sensitivity,
+                _ => return Err(format!("expected a positive number, got {value:?}")),
+            };
+            Ok(())
+        },
+    );
+    let get = Rc::clone(mouse_look);
+    let set = Rc::clone(mouse_look);
+    console.register_cvar(
+        "invert_y",
+        "look down when the mouse moves up, on or off",
+        move |_world| on_off(get.borrow().invert_y),
+        move |_world, value| {
+            set.borrow_mut().invert_y = parse_on_off(value)?;
+            Ok(())
+        },
+    );
+}
+
+fn on_off(value: bool) -> String {
+    match value {
+        true => "on".to_string(),
+        false => "off".to_string(),
+    }
+}
+
+fn parse_on_off(value: &str) -> Result<bool, String> {
+    match value {
+        "on" => Ok(true),
+        "off" => Ok(false),
+        _ => Err(format!("expected on or off, got {value:?}")),
+    }
+}
@@ -0,0 +1,112 @@
+//! Frame pacing independent of how the renderer presents: an optional cap on
+//! frames per second, and the present mode asked of the renderer, see
+//! `RenderSettings`.
+//!
+//! Timers wake late by up to a millisecond or so depending on the platform,
+//! which is a large share of a frame at high frame rates. Capped frames sleep
+//! until shortly before their deadline and spin the rest of the way.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use render::settings::RenderSettings;
+
+use crate::console::Console;
+
+/// How long before a deadline waiting stops sleeping and starts spinning.
+const SPIN_MARGIN: Duration = Duration::from_millis(2);
+
+pub(crate) fn register_commands(console: &mut Console, pacing: &Rc<RefCell<RenderSettings>>) {
+    let cap = Rc::clone(pacing);
+    console.register(
+        "fps_cap",
+        "show or set the frames per second the loop is capped at, or turn the cap off",
+        move |_world, args| {
+            let mut pacing = cap.borrow_mut();
+            match args {
+                [] => Ok(match pacing.frame_cap {
+                    Some(fps) => format!("capped at {fps} fps"),
+                    None => "not capped".to_string(),
+                }),
+                ["off"] => {
+                    pacing.frame_cap = None;
+                    Ok("not capped".to_string())
+                }
+                [fps] => {
+                    let fps = parse_fps(fps)?;
+                    pacing.frame_cap = Some(fps);
+                    Ok(format!("capped at {fps} fps"))
+                }
+                _ => Err("expected frames per second, off or nothing".to_string()),
+            }
+        },
+    );
+    let get = Rc::clone(pacing);
+    let set = Rc::clone(pacing);
+    console.register_cvar(
+        "present_mode",
+        "how frames are presented where supported, fifo, mailbox or immediate (tearing)",
+        move |_world| get.borrow().present_mode.to_string(),
+        move |_world, value| {
+            set.borrow_mut().present_mode = value.parse().map_err(|err| format!("{err}"))?;
+            Ok(())
+        },
+    );
+}
+
+fn parse_fps(arg: &str) -> Result<f32, String> {
+    match arg.parse::<f32>() {
+        Ok(fps) if fps.is_finite() && fps > 0.0 => Ok(fps),
+        _ => Err(format!(
+            "expected a positive number of frames per second, got {arg}"
+        )),
+    }
+}
+
+/// When to stop sleeping and start spinning to reach `deadline` from `now`,
+/// `None` when it's too close to sleep at all.
+fn wake_before(now: Instant, deadline: Instant) -> Option<Instant> {
+    deadline.checked_sub(SPIN_MARGIN).filter(|&wake| wake > now)
+}
+
+/// Wait until `deadline`, sleeping most of the way and spinning the rest.
+pub(crate) async fn wait_until(deadline: Instant) {
+    if let Some(wake) = wake_before(Instant::now(), deadline) {
+        smol::Timer::at(wake).await;
+    }
+    while Instant::now() < deadline {
+        std::hint::spin_loop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sleeps_until_the_spin_margin() {
+        let now = Instant::now();
+        let deadline = now + Duration::from_millis(10);
+        assert_eq!(wake_before(now, deadline), Some(deadline - SPIN_MARGIN));
+    }
+
+    #[test]
+    fn spins_when_the_deadline_is_close_or_past() {
+        let now = Instant::now();
+        assert_eq!(wake_before(now, now + SPIN_MARGIN), None);
+        assert_eq!(wake_before(now, now + Duration::from_millis(1)), None);
+        assert_eq!(wake_before(now + Duration::from_millis(5), now), None);
+    }
+
+    #[test]
+    fn caps_at_positive_frame_rates() {
+        assert_eq!(parse_fps("144"), Ok(144.0));
+        assert_eq!(parse_fps("59.94"), Ok(59.94));
+        assert!(parse_fps("0").is_err());
+        assert!(parse_fps("-60").is_err());
+        assert!(parse_fps("inf").is_err());
+        assert!(parse_fps("NaN").is_err());
+        assert!(parse_fps("fast").is_err());
+    }
+}
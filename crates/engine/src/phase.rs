@@ -0,0 +1,257 @@
+//! Named phases of a frame, and the order systems run in within them.
+//!
+//! Every frame runs the phases in order. The engine's own work is pinned to a
+//! phase (pumping input, polling assets, syncing the network, presenting), and
+//! each `GameSystem` declares the phase it runs in along with systems it must
+//! run before or after. The order is resolved once, when the engine is built,
+//! so a missing system or a cycle is reported up front instead of showing up
+//! as a subtly wrong frame.
+
+use crate::system::SystemHandle;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum FramePhase {
+    /// Platform events are pumped and controller state updated.
+    Input,
+    /// Assets are polled and reloaded.
+    PreSim,
+    /// Network sync and the world simulation step.
+    Sim,
+    /// Game logic reacting to the simulated world.
+    PostSim,
+    /// New graphics are handed from the world to the renderer.
+    Extract,
+    /// The world is drawn.
+    Render,
+}
+
+impl FramePhase {
+    pub const ALL: [FramePhase; 6] = [
+        FramePhase::Input,
+        FramePhase::PreSim,
+        FramePhase::Sim,
+        FramePhase::PostSim,
+        FramePhase::Extract,
+        FramePhase::Render,
+    ];
+
+    /// Name of the phase in frame timelines.
+    pub fn name(&self) -> &'static str {
+        match self {
+            FramePhase::Input => "input",
+            FramePhase::PreSim => "pre_sim",
+            FramePhase::Sim => "sim",
+            FramePhase::PostSim => "post_sim",
+            FramePhase::Extract => "extract",
+            FramePhase::Render => "render",
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+pub enum ScheduleError {
+    #[error("system {system} is ordered relative to {other}, which isn't registered")]
+    UnknownSystem { system: String, other: String },
+    #[error(
+        "system {system} in {phase:?} must run {relation} {other}, which runs in {other_phase:?}"
+    )]
+    PhaseConflict {
+        system: String,
+        phase: FramePhase,
+        relation: &'static str,
+        other: String,
+        other_phase: FramePhase,
+    },
+    #[error("systems in {phase:?} are ordered in a cycle: {systems:?}")]
+    Cycle {
+        phase: FramePhase,
+        systems: Vec<String>,
+    },
+}
+
+/// Indices of systems in the order they run, for each phase.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct PhaseSchedule {
+    phases: [Vec<usize>; 6],
+}
+
+impl PhaseSchedule {
+    /// Resolve the order of `systems`. Within a phase, systems without a
+    /// constraint between them keep the order they were registered in.
+    pub fn new(systems: &[SystemHandle]) -> Result<Self, ScheduleError> {
+        // runs_before[i] holds the systems in the same phase that must run after i.
+        let mut runs_before = vec![Vec::new(); systems.len()];
+        let mut blocked_by = vec![0usize; systems.len()];
+
+        for (index, system) in systems.iter().enumerate() {
+            let constraints = system
+                .runs_after()
+                .iter()
+                .map(|other| (*other, true))
+                .chain(system.runs_before().iter().map(|other| (*other, false)));
+            for (other, after) in constraints {
+                let mut found = false;
+                for (other_index, other_system) in systems.iter().enumerate() {
+                    if other_system.name() != other || other_index == index {
+                        continue;
+                    }
+                    found = true;
+                    let (first, then) = if after {
+                        (other_index, index)
+                    } else {
+                        (index, other_index)
+                    };
+                    let (first_phase, then_phase) = (systems[first].phase(), systems[then].phase());
+                    if first_phase == then_phase {
+                        runs_before[first].push(then);
+                        blocked_by[then] += 1;
+                    } else if first_phase > then_phase {
+                        return Err(ScheduleError::PhaseConflict {
+                            system: system.name().to_string(),
+                            phase: system.phase(),
+                            relation: if after { "after" } else { "before" },
+                            other: other.to_string(),
+                            other_phase: other_system.phase(),
+                        });
+                    }
+                }
+                if !found {
+                    return Err(ScheduleError::UnknownSystem {
+                        system: system.name().to_string(),
+                        other: other.to_string(),
+                    });
+                }
+            }
+        }
+
+        let mut schedule = PhaseSchedule::default();
+        for phase in FramePhase::ALL {
+            let mut waiting = (0..systems.len())
+                .filter(|index| systems[*index].phase() == phase)
+                .collect::<Vec<_>>();
+            let order = &mut schedule.phases[phase as usize];
+            // Always take the earliest registered system that is ready.
+            while let Some(position) = waiting.iter().position(|index| blocked_by[*index] == 0) {
+                let index = waiting.remove(position);
+                for then in runs_before[index].iter() {
+                    blocked_by[*then] -= 1;
+                }
+                order.push(index);
+            }
+            if !waiting.is_empty() {
+                return Err(ScheduleError::Cycle {
+                    phase,
+                    systems: waiting
+                        .into_iter()
+                        .map(|index| systems[index].name().to_string())
+                        .collect(),
+                });
+            }
+        }
+        Ok(schedule)
+    }
+
+    /// Systems that run in `phase`, in order.
+    pub fn phase(&self, phase: FramePhase) -> &[usize] {
+        &self.phases[phase as usize]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use world::World;
+
+    use super::*;
+    use crate::system::{GameSystem, RetryPolicy};
+
+    struct Ordered {
+        name: &'static str,
+        phase: FramePhase,
+        after: &'static [&'static str],
+        before: &'static [&'static str],
+    }
+
+    impl GameSystem for Ordered {
+        fn name(&self) -> &str {
+            self.name
+        }
+
+        fn phase(&self) -> FramePhase {
+            self.phase
+        }
+
+        fn runs_after(&self) -> &[&str] {
+            self.after
+        }
+
+        fn runs_before(&self) -> &[&str] {
+            self.before
+        }
+
+        fn update(&mut self, _world: &mut World, _delta_time: &Duration) {}
+    }
+
+    fn system(
+        name: &'static str,
+        phase: FramePhase,
+        after: &'static [&'static str],
+        before: &'static [&'static str],
+    ) -> SystemHandle {
+        SystemHandle::new(
+            Box::new(Ordered {
+                name,
+                phase,
+                after,
+                before,
+            }),
+            RetryPolicy::default(),
+        )
+    }
+
+    #[test]
+    fn orders_within_phases() {
+        let systems = [
+            system("ai", FramePhase::PostSim, &["camera"], &[]),
+            system("physics", FramePhase::Sim, &[], &[]),
+            system("camera", FramePhase::PostSim, &["physics"], &[]),
+            system("hud", FramePhase::PostSim, &[], &["ai"]),
+        ];
+        let schedule = PhaseSchedule::new(&systems).unwrap();
+        assert_eq!(schedule.phase(FramePhase::Sim), &[1]);
+        assert_eq!(schedule.phase(FramePhase::PostSim), &[2, 3, 0]);
+        assert!(schedule.phase(FramePhase::Render).is_empty());
+    }
+
+    #[test]
+    fn rejects_invalid_constraints() {
+        let missing = [system("ai", FramePhase::PostSim, &["pathfinding"], &[])];
+        assert!(matches!(
+            PhaseSchedule::new(&missing),
+            Err(ScheduleError::UnknownSystem { .. })
+        ));
+
+        let backwards = [
+            system("physics", FramePhase::Sim, &["ai"], &[]),
+            system("ai", FramePhase::PostSim, &[], &[]),
+        ];
+        assert!(matches!(
+            PhaseSchedule::new(&backwards),
+            Err(ScheduleError::PhaseConflict { .. })
+        ));
+
+        let cycle = [
+            system("a", FramePhase::PostSim, &["b"], &[]),
+            system("b", FramePhase::PostSim, &["a"], &[]),
+            system("c", FramePhase::PostSim, &[], &[]),
+        ];
+        assert_eq!(
+            PhaseSchedule::new(&cycle),
+            Err(ScheduleError::Cycle {
+                phase: FramePhase::PostSim,
+                systems: vec!["a".to_string(), "b".to_string()],
+            })
+        );
+    }
+}
@@ -0,0 +1,87 @@
+//! Gamepads joining local players, see `input::assignment`, and the console
+//! commands listing and letting go of them.
+
+use input::assignment::SlotEvent;
+use input::calibration::Calibration;
+use input::{EngineEvent, InputEvent};
+use logger::{warn, Logger};
+use world::notifications::Severity;
+use world::World;
+
+use crate::console::Console;
+
+/// Start a frame of joins and leaves: connect and disconnect gamepads as the
+/// platform has, join those pressing the join button in gameplay's `events`,
+/// and tell the user who joined or left.
+pub(crate) fn update(
+    world: &mut World,
+    calibration: &Calibration,
+    events: &[EngineEvent],
+    logger: &Logger,
+) {
+    let slots = &mut world.player_slots;
+    slots.sync_devices(calibration.devices());
+    for event in events {
+        if let EngineEvent::Input(input_event @ InputEvent::ButtonPressed(..)) = event {
+            slots.handle(input_event);
+        }
+    }
+    slots.begin_frame();
+    if slots.events().is_empty() {
+        return;
+    }
+    if let Err(err) = slots.save() {
+        warn!(logger, "joined gamepads won't be remembered: {err}");
+    }
+    let messages: Vec<_> = slots
+        .events()
+        .iter()
+        .map(|event| match event {
+            SlotEvent::Joined { player, device } => {
+                format!("gamepad {device} joined as player {player}")
+            }
+            SlotEvent::Left { player, device } => format!("gamepad {device} left player {player}"),
+        })
+        .collect();
+    for message in messages {
+        world.notify(Severity::Info, "player_slots", message);
+    }
+}
+
+pub(crate) fn register_commands(console: &mut Console) {
+    console.register(
+        "player_slots",
+        "list local players and the gamepads playing them",
+        |world, _args| {
+            let slots = &world.player_slots;
+            Ok(slots
+                .slots()
+                .iter()
+                .enumerate()
+                .map(|(player, slot)| match (slot.device, &slot.remembered) {
+                    (Some(device), _) => format!(
+                        "{player}: gamepad {device}, {}",
+                        slots.device_name(device).unwrap_or("unknown")
+                    ),
+                    (None, Some(name)) => format!("{player}: waiting for a {name}"),
+                    (None, None) => format!("{player}: free, press ok on a gamepad to join"),
+                })
+                .collect::<Vec<_>>()
+                .join("\n"))
+        },
+    );
+    console.register(
+        "player_leave",
+        "let local player <index> go, freeing its slot for another gamepad",
+        |world, args| {
+            let player = match args {
+                [player] => player.parse::<usize>().map_err(|err| err.to_string())?,
+                _ => return Err("expected a player index".to_string()),
+            };
+            let slots = &mut world.player_slots;
+            slots.leave(player).map_err(|err| err.to_string())?;
+            slots.save().map_err(|err| err.to_string())?;
+            Ok(format!("player {player} left"))
+        },
+    );
+}
@@ -0,0 +1,427 @@
+//! Game systems loaded from dynamic libraries dropped into a plugins
+//! directory.
+//!
+//! Rust has no stable ABI, so a plugin only works with the exact engine build
+//! it was compiled against. Each library exports a `PluginDescriptor`, see
+//! `export_plugin!`, and is only loaded when its ABI version, engine version
+//! and build fingerprint all match the engine's; anything else is reported
+//! and left alone. The directory is scanned when the engine is built, and
+//! again with the `plugins_scan` console command, which loads libraries added
+//! since and retries rejected ones that changed. A loaded library stays
+//! loaded until the engine exits, replacing it takes a restart.
+
+use std::any::TypeId;
+use std::cell::RefCell;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::BTreeMap;
+use std::ffi::{c_char, CStr};
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::{Duration, SystemTime};
+
+use libloading::Library;
+use logger::{error, info, Logger};
+use world::World;
+
+use crate::console::Console;
+use crate::phase::{FramePhase, PhaseSchedule};
+use crate::system::{GameSystem, RetryPolicy, SystemError, SystemHandle};
+
+/// Version of `PluginDescriptor`'s layout, bumped whenever it changes.
+pub const PLUGIN_ABI_VERSION: u32 = 1;
+
+/// Version of the engine plugins have to be built against.
+pub const ENGINE_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Symbol of the `PluginDescriptor` every plugin library exports.
+pub const PLUGIN_DESCRIPTOR_SYMBOL: &str = "NANACTYL_PLUGIN";
+
+#[doc(hidden)]
+pub const ENGINE_VERSION_NUL: &str = concat!(env!("CARGO_PKG_VERSION"), "\0");
+
+/// What a plugin library tells the engine about itself, exported as
+/// `NANACTYL_PLUGIN` by `export_plugin!`.
+#[repr(C)]
+pub struct PluginDescriptor {
+    /// `PLUGIN_ABI_VERSION` the plugin was built with. Checked before
+    /// anything else is read.
+    pub abi_version: u32,
+    /// `build_fingerprint` of the engine the plugin was built against.
+    pub build: fn() -> u64,
+    /// Nul terminated `ENGINE_VERSION` the plugin was built against.
+    pub engine_version: *const c_char,
+    /// Nul terminated name and version of the plugin.
+    pub name: *const c_char,
+    pub version: *const c_char,
+    /// Create the plugin's system.
+    pub create: fn() -> Box<dyn GameSystem>,
+}
+
+// Only ever points to string literals.
+unsafe impl Sync for PluginDescriptor {}
+
+/// Export a plugin's descriptor from a `cdylib` or `dylib` crate, given its
+/// name and a function creating its system. The plugin's version is its
+/// crate's.
+///
+/// ```ignore
+/// engine::export_plugin!("spinner", || Box::new(Spinner::default()));
+/// ```
+#[macro_export]
+macro_rules! export_plugin {
+    ($name:literal, $create:expr) => {
+        #[no_mangle]
+        pub static NANACTYL_PLUGIN: $crate::PluginDescriptor = $crate::PluginDescriptor {
+            abi_version: $crate::PLUGIN_ABI_VERSION,
+            build: $crate::build_fingerprint,
+            engine_version: $crate::ENGINE_VERSION_NUL.as_ptr() as *const ::std::ffi::c_char,
+            name: concat!($name, "\0").as_ptr() as *const ::std::ffi::c_char,
+            version: concat!(env!("CARGO_PKG_VERSION"), "\0").as_ptr() as *const ::std::ffi::c_char,
+            create: $create,
+        };
+    };
+}
+
+/// Fingerprint of the engine build. It differs between compilers and between
+/// builds of the world with different features, either of which could lay out
+/// what's shared with plugins differently.
+pub fn build_fingerprint() -> u64 {
+    let mut hasher = DefaultHasher::new();
+    TypeId::of::<World>().hash(&mut hasher);
+    TypeId::of::<dyn GameSystem>().hash(&mut hasher);
+    hasher.finish()
+}
+
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+pub enum PluginError {
+    #[error("unable to load library: {0}")]
+    Open(String),
+    #[error("no {PLUGIN_DESCRIPTOR_SYMBOL} descriptor, see export_plugin!")]
+    NoDescriptor,
+    #[error("plugin ABI version {found}, the engine's is {expected}")]
+    AbiVersion { found: u32, expected: u32 },
+    #[error("built for engine {found}, this is {expected}")]
+    EngineVersion { found: String, expected: String },
+    #[error("built with a different compiler or engine features")]
+    Build,
+}
+
+/// A plugin's system, and the library its code is in.
+struct PluginSystem {
+    system: Box<dyn GameSystem>,
+    // Dropped after the system.
+    _library: Library,
+}
+
+impl GameSystem for PluginSystem {
+    fn name(&self) -> &str {
+        self.system.name()
+    }
+
+    fn version(&self) -> &str {
+        self.system.version()
+    }
+
+    fn set_logger(&mut self, logger: Logger) {
+        self.system.set_logger(logger)
+    }
+
+    fn phase(&self) -> FramePhase {
+        self.system.phase()
+    }
+
+    fn runs_after(&self) -> &[&str] {
+        self.system.runs_after()
+    }
+
+    fn runs_before(&self) -> &[&str] {
+        self.system.runs_before()
+    }
+
+    fn load(&mut self, world: &mut World) -> Result<(), SystemError> {
+        self.system.load(world)
+    }
+
+    fn update(&mut self, world: &mut World, delta_time: &Duration) {
+        self.system.update(world, delta_time)
+    }
+
+    fn unload(&mut self, world: &mut World) {
+        self.system.unload(world)
+    }
+}
+
+/// Check what a plugin was built against, so far as it's known.
+fn check_compatible(
+    abi_version: u32,
+    build: impl FnOnce() -> u64,
+    engine_version: impl FnOnce() -> String,
+) -> Result<(), PluginError> {
+    if abi_version != PLUGIN_ABI_VERSION {
+        return Err(PluginError::AbiVersion {
+            found: abi_version,
+            expected: PLUGIN_ABI_VERSION,
+        });
+    }
+    let engine_version = engine_version();
+    if engine_version != ENGINE_VERSION {
+        return Err(PluginError::EngineVersion {
+            found: engine_version,
+            expected: ENGINE_VERSION.to_string(),
+        });
+    }
+    if build() != build_fingerprint() {
+        return Err(PluginError::Build);
+    }
+    Ok(())
+}
+
+/// Load the library at `path` and create its system, if it was built for
+/// this engine.
+fn load_plugin(path: &Path) -> Result<(String, PluginSystem), PluginError> {
+    // Safety: running a library's initializers is what loading it means, a
+    // plugins directory is as trusted as the engine's own binary.
+    let library =
+        unsafe { Library::new(path) }.map_err(|err| PluginError::Open(err.to_string()))?;
+    let descriptor = unsafe {
+        library
+            .get::<*const PluginDescriptor>(PLUGIN_DESCRIPTOR_SYMBOL.as_bytes())
+            .map(|symbol| &**symbol)
+            .map_err(|_| PluginError::NoDescriptor)?
+    };
+    // Safety: the ABI version is the first field of every layout, and the
+    // strings are checked to be there before they're read.
+    let c_string = |string: *const c_char| {
+        if string.is_null() {
+            String::new()
+        } else {
+            unsafe { CStr::from_ptr(string) }
+                .to_string_lossy()
+                .into_owned()
+        }
+    };
+    check_compatible(descriptor.abi_version, descriptor.build, || {
+        c_string(descriptor.engine_version)
+    })?;
+    let name = format!(
+        "{} {}",
+        c_string(descriptor.name),
+        c_string(descriptor.version)
+    );
+    let system = (descriptor.create)();
+    Ok((
+        name,
+        PluginSystem {
+            system,
+            _library: library,
+        },
+    ))
+}
+
+/// What became of a library in the plugins directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum LibraryStatus {
+    /// Loaded as the plugin of this name and version.
+    Loaded(String),
+    /// Not loaded, until the file changes.
+    Rejected {
+        error: PluginError,
+        modified: Option<SystemTime>,
+    },
+}
+
+/// Libraries in the plugins directory, and the systems loaded from them that
+/// the frame loop hasn't scheduled yet.
+#[derive(Default)]
+pub(crate) struct Plugins {
+    dir: Option<PathBuf>,
+    libraries: BTreeMap<PathBuf, LibraryStatus>,
+    loaded: Vec<Box<dyn GameSystem>>,
+}
+
+impl Plugins {
+    pub fn set_dir(&mut self, dir: Option<PathBuf>) {
+        self.dir = dir;
+    }
+
+    /// Load libraries in the directory that haven't been, or were rejected
+    /// and have changed since. Returns a line about each one looked at.
+    pub fn scan(&mut self) -> Result<Vec<String>, String> {
+        let dir = self.dir.as_ref().ok_or("no plugins directory is set")?;
+        let entries = fs::read_dir(dir).map_err(|err| format!("unable to read {dir:?}: {err}"))?;
+        let mut paths = entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| {
+                path.extension()
+                    .is_some_and(|ext| ext == std::env::consts::DLL_EXTENSION)
+            })
+            .collect::<Vec<_>>();
+        paths.sort();
+
+        let mut report = Vec::new();
+        for path in paths {
+            let modified = fs::metadata(&path).and_then(|m| m.modified()).ok();
+            match self.libraries.get(&path) {
+                Some(LibraryStatus::Loaded(_)) => continue,
+                Some(LibraryStatus::Rejected { modified: seen, .. }) if *seen == modified => {
+                    continue
+                }
+                _ => {}
+            }
+            let status = match load_plugin(&path) {
+                Ok((name, system)) => {
+                    report.push(format!("loaded {name} from {}", path.display()));
+                    self.loaded.push(Box::new(system));
+                    LibraryStatus::Loaded(name)
+                }
+                Err(error) => {
+                    report.push(format!("rejected {}: {error}", path.display()));
+                    LibraryStatus::Rejected { error, modified }
+                }
+            };
+            self.libraries.insert(path, status);
+        }
+        Ok(report)
+    }
+
+    /// Systems loaded since this was last called.
+    pub fn take_loaded(&mut self) -> Vec<Box<dyn GameSystem>> {
+        std::mem::take(&mut self.loaded)
+    }
+}
+
+/// Schedule systems loaded from plugins after those already registered,
+/// leaving out any that can't be ordered. They're loaded on their first
+/// update.
+pub(crate) fn schedule_plugin_systems(
+    plugin_systems: Vec<Box<dyn GameSystem>>,
+    systems: &mut Vec<SystemHandle>,
+    schedule: &mut PhaseSchedule,
+    retry_policy: RetryPolicy,
+    logger: &Logger,
+) {
+    for system in plugin_systems {
+        systems.push(SystemHandle::new(system, retry_policy));
+        match PhaseSchedule::new(systems) {
+            Ok(rescheduled) => *schedule = rescheduled,
+            Err(err) => {
+                let system = systems.pop().expect("just pushed");
+                error!(logger, "not running plugin {}: {err}", system.name());
+            }
+        }
+    }
+}
+
+/// Scan the plugins directory, logging what was found.
+pub(crate) fn scan_and_log(plugins: &RefCell<Plugins>, logger: &Logger) {
+    match plugins.borrow_mut().scan() {
+        Ok(report) => {
+            for line in report {
+                info!(logger, "plugins: {line}");
+            }
+        }
+        Err(err) => error!(logger, "plugins: {err}"),
+    }
+}
+
+pub(crate) fn register_commands(console: &mut Console, plugins: &Rc<RefCell<Plugins>>) {
+    let list = Rc::clone(plugins);
+    console.register(
+        "plugins",
+        "list libraries in the plugins directory, and whether they're loaded",
+        move |_world, _args| {
+            let plugins = list.borrow();
+            if plugins.libraries.is_empty() {
+                return Ok("no plugin libraries".to_string());
+            }
+            Ok(plugins
+                .libraries
+                .iter()
+                .map(|(path, status)| match status {
+                    LibraryStatus::Loaded(name) => format!("{}: {name}", path.display()),
+                    LibraryStatus::Rejected { error, .. } => {
+                        format!("{}: rejected, {error}", path.display())
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join("\n"))
+        },
+    );
+    let scan = Rc::clone(plugins);
+    console.register(
+        "plugins_scan",
+        "load libraries added to the plugins directory, their systems run from the next frame",
+        move |_world, _args| {
+            let report = scan.borrow_mut().scan()?;
+            Ok(if report.is_empty() {
+                "no new plugins".to_string()
+            } else {
+                report.join("\n")
+            })
+        },
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_plugins_built_for_this_engine_are_compatible() {
+        let version = || ENGINE_VERSION.to_string();
+        assert_eq!(
+            check_compatible(PLUGIN_ABI_VERSION, build_fingerprint, version),
+            Ok(())
+        );
+        assert_eq!(
+            check_compatible(PLUGIN_ABI_VERSION + 1, build_fingerprint, version),
+            Err(PluginError::AbiVersion {
+                found: PLUGIN_ABI_VERSION + 1,
+                expected: PLUGIN_ABI_VERSION
+            })
+        );
+        assert!(matches!(
+            check_compatible(PLUGIN_ABI_VERSION, build_fingerprint, || "0.0.0-old"
+                .to_string()),
+            Err(PluginError::EngineVersion { .. })
+        ));
+        assert_eq!(
+            check_compatible(PLUGIN_ABI_VERSION, || build_fingerprint() ^ 1, version),
+            Err(PluginError::Build)
+        );
+    }
+
+    #[test]
+    fn scans_only_libraries_and_retries_them_once_changed() {
+        let dir = std::env::temp_dir().join(format!("nanactyl-plugins-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let library = dir.join(format!("broken.{}", std::env::consts::DLL_EXTENSION));
+        fs::write(&library, b"not a library").unwrap();
+        fs::write(dir.join("readme.txt"), b"not a library either").unwrap();
+
+        let mut plugins = Plugins::default();
+        assert!(plugins.scan().is_err(), "no directory set");
+        plugins.set_dir(Some(dir.clone()));
+        let report = plugins.scan().unwrap();
+        assert_eq!(report.len(), 1);
+        assert!(report[0].starts_with("rejected"), "{report:?}");
+        assert!(matches!(
+            plugins.libraries[&library],
+            LibraryStatus::Rejected {
+                error: PluginError::Open(_),
+                ..
+            }
+        ));
+        assert!(plugins.take_loaded().is_empty());
+        assert!(plugins.scan().unwrap().is_empty(), "unchanged, not retried");
+
+        if let Some(LibraryStatus::Rejected { modified, .. }) = plugins.libraries.get_mut(&library)
+        {
+            *modified = None;
+        }
+        assert_eq!(plugins.scan().unwrap().len(), 1, "changed, retried");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
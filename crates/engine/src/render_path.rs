@@ -0,0 +1,24 @@
+//! The `render_path` console variable, switching the main window between
+//! forward and the experimental deferred path while running so the two can be
+//! compared on the same scene.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use render::render_path::RenderPath;
+
+use crate::console::Console;
+
+pub(crate) fn register_commands(console: &mut Console, render_path: &Rc<RefCell<RenderPath>>) {
+    let get = Rc::clone(render_path);
+    let set = Rc::clone(render_path);
+    console.register_cvar(
+        "render_path",
+        "how the main window is lit, forward or deferred (experimental)",
+        move |_world| get.borrow().to_string(),
+        move |_world, value| {
+            *set.borrow_mut() = value.parse().map_err(|err| format!("{err}"))?;
+            Ok(())
+        },
+    );
+}
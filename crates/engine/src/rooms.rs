@@ -0,0 +1,414 @@
+//! Rooms: server worlds running alongside the engine's own in one process, so
+//! a dedicated server can host several matches. Each room has its own world,
+//! built-in systems and client connection, listening on its own port, and is
+//! ticked by the frame loop after the engine's world. Rooms share the frame
+//! loop's executor and, through a `ModelCache`, the models their asset
+//! loaders load.
+//!
+//! Rooms are created from `EngineConfig::rooms` on start, and created and
+//! destroyed at runtime with the `room_create` and `room_destroy` console
+//! commands.
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::rc::Rc;
+
+use crate::console::Console;
+
+/// First port given to a room created without one.
+const FIRST_ROOM_PORT: u16 = 12003;
+
+/// A room's name and the port its server listens for a client on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RoomConfig {
+    pub name: String,
+    pub port: u16,
+}
+
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+#[error("expected a room as <name>:<port>, got {0:?}")]
+pub struct InvalidRoomConfig(String);
+
+impl std::str::FromStr for RoomConfig {
+    type Err = InvalidRoomConfig;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.rsplit_once(':') {
+            Some((name, port)) if !name.is_empty() => Ok(Self {
+                name: name.to_string(),
+                port: port.parse().map_err(|_| InvalidRoomConfig(s.to_string()))?,
+            }),
+            _ => Err(InvalidRoomConfig(s.to_string())),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum RoomRequest {
+    Create(RoomConfig),
+    Destroy(String),
+}
+
+/// How a room is doing, as of the last frame.
+#[cfg_attr(not(feature = "net-sync"), allow(dead_code))]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct RoomStatus {
+    pub port: u16,
+    pub clients: usize,
+    pub updates: u64,
+}
+
+/// Rooms as the console sees them: their last status, and the requests made
+/// since, which the frame loop applies before ticking rooms.
+#[derive(Debug)]
+pub(crate) struct RoomAdmin {
+    rooms: BTreeMap<String, RoomStatus>,
+    requests: Vec<RoomRequest>,
+    /// The port the engine's own server listens on, which no room can take.
+    engine_port: Option<u16>,
+}
+
+impl Default for RoomAdmin {
+    fn default() -> Self {
+        #[cfg(feature = "net-sync")]
+        let engine_port = net_sync_system::DEFAULT_LISTEN_ADDR
+            .parse::<std::net::SocketAddr>()
+            .ok()
+            .map(|addr| addr.port());
+        #[cfg(not(feature = "net-sync"))]
+        let engine_port = None;
+        Self::new(engine_port)
+    }
+}
+
+impl RoomAdmin {
+    fn new(engine_port: Option<u16>) -> Self {
+        Self {
+            rooms: BTreeMap::new(),
+            requests: Vec::new(),
+            engine_port,
+        }
+    }
+
+    /// The ports of the rooms there will be once requests are applied, by
+    /// room name.
+    fn pending_ports(&self) -> BTreeMap<&str, u16> {
+        let mut ports: BTreeMap<&str, u16> = self
+            .rooms
+            .iter()
+            .map(|(name, status)| (name.as_str(), status.port))
+            .collect();
+        for request in self.requests.iter() {
+            match request {
+                RoomRequest::Create(room) => {
+                    ports.insert(&room.name, room.port);
+                }
+                RoomRequest::Destroy(name) => {
+                    ports.remove(name.as_str());
+                }
+            }
+        }
+        ports
+    }
+
+    /// Whether the room exists, or will once requests are applied.
+    fn exists(&self, name: &str) -> bool {
+        self.pending_ports().contains_key(name)
+    }
+
+    /// Whether the engine's server listens on the port, or a room will once
+    /// requests are applied.
+    fn port_taken(&self, port: u16) -> bool {
+        self.engine_port == Some(port) || self.pending_ports().values().any(|&taken| taken == port)
+    }
+
+    pub fn create(&mut self, name: &str, port: Option<u16>) -> Result<RoomConfig, String> {
+        if self.exists(name) {
+            return Err(format!("room {name} already exists"));
+        }
+        let port = match port {
+            Some(port) if self.port_taken(port) => {
+                return Err(format!("port {port} is taken by another room"))
+            }
+            Some(port) => port,
+            None => (FIRST_ROOM_PORT..=u16::MAX)
+                .find(|port| !self.port_taken(*port))
+                .ok_or("no ports left for rooms")?,
+        };
+        let room = RoomConfig {
+            name: name.to_string(),
+            port,
+        };
+        self.requests.push(RoomRequest::Create(room.clone()));
+        Ok(room)
+    }
+
+    pub fn destroy(&mut self, name: &str) -> Result<(), String> {
+        if !self.exists(name) {
+            return Err(format!("no room named {name}"));
+        }
+        self.requests.push(RoomRequest::Destroy(name.to_string()));
+        Ok(())
+    }
+
+    pub fn take_requests(&mut self) -> Vec<RoomRequest> {
+        std::mem::take(&mut self.requests)
+    }
+
+    #[cfg_attr(not(feature = "net-sync"), allow(dead_code))]
+    pub fn set_statuses(&mut self, rooms: BTreeMap<String, RoomStatus>) {
+        self.rooms = rooms;
+    }
+}
+
+pub(crate) fn register_commands(console: &mut Console, admin: &Rc<RefCell<RoomAdmin>>) {
+    let list = Rc::clone(admin);
+    console.register(
+        "rooms",
+        "list rooms and their clients",
+        move |_world, _args| {
+            let admin = list.borrow();
+            if admin.rooms.is_empty() {
+                return Ok("no rooms".to_string());
+            }
+            Ok(admin
+                .rooms
+                .iter()
+                .map(|(name, status)| {
+                    let client = match status.clients {
+                        0 => "waiting for a client".to_string(),
+                        1 => "1 client".to_string(),
+                        clients => format!("{clients} clients"),
+                    };
+                    format!(
+                        "{name}: port {}, {client}, {} updates",
+                        status.port, status.updates
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join("\n"))
+        },
+    );
+    let create = Rc::clone(admin);
+    console.register(
+        "room_create",
+        "create room <name>, listening on [port]",
+        move |_world, args| {
+            let (name, port) = match args {
+                [name] => (*name, None),
+                [name, port] => (*name, Some(port.parse::<u16>().map_err(|e| e.to_string())?)),
+                _ => return Err("expected a room name and optionally a port".to_string()),
+            };
+            let room = create.borrow_mut().create(name, port)?;
+            Ok(format!("creating room {} on port {}", room.name, room.port))
+        },
+    );
+    let destroy = Rc::clone(admin);
+    console.register(
+        "room_destroy",
+        "destroy room <name>",
+        move |_world, args| match args {
+            [name] => {
+                destroy.borrow_mut().destroy(name)?;
+                Ok(format!("destroying room {name}"))
+            }
+            _ => Err("expected a room name".to_string()),
+        },
+    );
+}
+
+#[cfg(feature = "net-sync")]
+pub(crate) use self::live::Rooms;
+
+#[cfg(feature = "net-sync")]
+mod live {
+    use std::cell::RefCell;
+    use std::collections::BTreeMap;
+    use std::time::Duration;
+
+    use logger::{info, warn, Logger};
+    use net_sync_system::NetSyncState;
+    use world::World;
+
+    use super::{RoomAdmin, RoomConfig, RoomRequest, RoomStatus};
+    use crate::world_runner::WorldRunner;
+    use crate::{EngineConfig, RetryPolicy};
+
+    /// A room's server world and the systems driving it.
+    struct Room {
+        port: u16,
+        runner: WorldRunner,
+    }
+
+    impl Room {
+        async fn load(
+            room: &RoomConfig,
+            config: &EngineConfig,
+            #[cfg(feature = "asset-loader")] models: &asset_loader_system::ModelCache,
+            retry_policy: RetryPolicy,
+            logger: &Logger,
+        ) -> Self {
+            let logger = logger.sub(&format!("room-{}", room.name));
+            let mut world = World::new(None, &logger, false);
+            world.config.net_compression = config.net_compression;
+            world.config.limits = config.world_limits;
+            let runner = WorldRunner::load(
+                world,
+                NetSyncState::listening_on(format!("0.0.0.0:{}", room.port)),
+                config,
+                #[cfg(feature = "asset-loader")]
+                models.clone(),
+                retry_policy,
+                logger,
+            )
+            .await;
+            info!(
+                runner.logger(),
+                "room loaded, listening on port {}", room.port
+            );
+            Self {
+                port: room.port,
+                runner,
+            }
+        }
+
+        async fn status(&self) -> RoomStatus {
+            let world = self.runner.world().lock().await;
+            RoomStatus {
+                port: self.port,
+                clients: world.clients.len(),
+                updates: world.stats.updates,
+            }
+        }
+    }
+
+    /// The rooms running in this process, by name.
+    pub(crate) struct Rooms {
+        rooms: BTreeMap<String, Room>,
+        #[cfg(feature = "asset-loader")]
+        models: asset_loader_system::ModelCache,
+        retry_policy: RetryPolicy,
+        logger: Logger,
+    }
+
+    impl Rooms {
+        pub fn new(retry_policy: RetryPolicy, logger: &Logger) -> Self {
+            Self {
+                rooms: BTreeMap::new(),
+                #[cfg(feature = "asset-loader")]
+                models: asset_loader_system::ModelCache::new(),
+                retry_policy,
+                logger: logger.sub("rooms"),
+            }
+        }
+
+        async fn create(&mut self, room: &RoomConfig, config: &EngineConfig) {
+            if self.rooms.contains_key(&room.name) {
+                warn!(self.logger, "room {} already exists", room.name);
+                return;
+            }
+            let loaded = Room::load(
+                room,
+                config,
+                #[cfg(feature = "asset-loader")]
+                &self.models,
+                self.retry_policy,
+                &self.logger,
+            )
+            .await;
+            self.rooms.insert(room.name.clone(), loaded);
+        }
+
+        async fn destroy(&mut self, name: &str) {
+            match self.rooms.remove(name) {
+                Some(room) => {
+                    room.runner.unload().await;
+                    info!(self.logger, "room {name} destroyed");
+                }
+                None => warn!(self.logger, "no room named {name} to destroy"),
+            }
+        }
+
+        /// Create the configured rooms, before the first frame.
+        pub async fn load(&mut self, config: &EngineConfig) {
+            for room in config.rooms.iter() {
+                self.create(room, config).await;
+            }
+        }
+
+        /// Apply the console's requests, tick every room and report how
+        /// they're doing back to the console.
+        pub async fn update(
+            &mut self,
+            admin: &RefCell<RoomAdmin>,
+            config: &EngineConfig,
+            delta_time: &Duration,
+        ) {
+            let requests = admin.borrow_mut().take_requests();
+            for request in requests {
+                match request {
+                    RoomRequest::Create(room) => self.create(&room, config).await,
+                    RoomRequest::Destroy(name) => self.destroy(&name).await,
+                }
+            }
+            let mut statuses = BTreeMap::new();
+            for (name, room) in self.rooms.iter_mut() {
+                room.runner.update(delta_time).await;
+                statuses.insert(name.clone(), room.status().await);
+            }
+            admin.borrow_mut().set_statuses(statuses);
+        }
+
+        pub async fn unload(self) {
+            for (_, room) in self.rooms {
+                room.runner.unload().await;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_room_configs() {
+        let room = "duel:12010".parse::<RoomConfig>().unwrap();
+        assert_eq!(room.name, "duel");
+        assert_eq!(room.port, 12010);
+        assert!("duel".parse::<RoomConfig>().is_err());
+        assert!(":12010".parse::<RoomConfig>().is_err());
+        assert!("duel:port".parse::<RoomConfig>().is_err());
+    }
+
+    #[test]
+    fn admin_tracks_pending_rooms() {
+        let mut admin = RoomAdmin::default();
+        let first = admin.create("a", None).unwrap();
+        assert_eq!(first.port, FIRST_ROOM_PORT);
+        assert_eq!(admin.create("b", None).unwrap().port, FIRST_ROOM_PORT + 1);
+        assert!(admin.create("a", None).is_err());
+        assert!(admin.create("c", Some(FIRST_ROOM_PORT)).is_err());
+        admin.destroy("a").unwrap();
+        assert!(admin.destroy("a").is_err());
+        assert!(admin.destroy("missing").is_err());
+        assert_eq!(admin.take_requests().len(), 3);
+    }
+
+    #[test]
+    fn admin_frees_ports_of_destroyed_rooms() {
+        let engine_port = FIRST_ROOM_PORT - 1;
+        let mut admin = RoomAdmin::new(Some(engine_port));
+        assert!(admin.create("a", Some(engine_port)).is_err());
+        admin.set_statuses(BTreeMap::from([(
+            "a".to_string(),
+            RoomStatus {
+                port: FIRST_ROOM_PORT,
+                ..RoomStatus::default()
+            },
+        )]));
+        assert_eq!(admin.create("b", None).unwrap().port, FIRST_ROOM_PORT + 1);
+        admin.destroy("a").unwrap();
+        assert_eq!(admin.create("c", None).unwrap().port, FIRST_ROOM_PORT);
+    }
+}
@@ -0,0 +1,526 @@
+//! Soak tests, for catching slow leaks that only show once a server has been
+//! up for a long time. A `Scenario` spawns entities with transforms and
+//! motions drawn from a seeded generator, so a run can be repeated exactly,
+//! moves them every tick and replaces a few of them with new ones. What the
+//! world holds is sampled every tick, and once the run has settled none of it
+//! should keep growing.
+
+use std::fmt;
+
+use gfx::{DebugMesh, Vertex};
+use glam::{Mat4, Quat, Vec3, Vec4};
+use logger::{info, Logger};
+use world::bundles::StaticObject;
+use world::components::spatial::SpatialHierarchyNode;
+use world::{Entity, World};
+
+/// Distance from the origin along each axis entities are spawned within.
+const SPAWN_EXTENT: f32 = 100.0;
+
+/// What a soak test spawns, and for how long it runs.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct SoakConfig {
+    /// Seeds the generator, the same seed spawns and moves the same entities.
+    pub seed: u64,
+    /// Entities kept in the world.
+    pub entities: usize,
+    /// Ticks to run for, the engine exits after the last.
+    pub ticks: u64,
+    /// Entities despawned and replaced with new ones every tick. Each new one
+    /// has a graphic of its own, so renderers upload and free graphics too.
+    pub respawns_per_tick: usize,
+}
+
+impl Default for SoakConfig {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            entities: 1000,
+            ticks: 10_000,
+            respawns_per_tick: 4,
+        }
+    }
+}
+
+/// How a soak test entity moves, given the time since the start of the run.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Motion {
+    Still {
+        position: Vec3,
+    },
+    /// Circles `center` in the XZ plane.
+    Orbit {
+        center: Vec3,
+        radius: f32,
+        angular_speed: f32,
+        phase: f32,
+    },
+    /// Moves up and down around `origin`.
+    Bob {
+        origin: Vec3,
+        amplitude: f32,
+        period: f32,
+    },
+    /// Turns around Y at `origin`.
+    Spin {
+        origin: Vec3,
+        angular_speed: f32,
+    },
+}
+
+impl Motion {
+    pub fn transform(&self, time: f32) -> Mat4 {
+        match *self {
+            Motion::Still { position } => Mat4::from_translation(position),
+            Motion::Orbit {
+                center,
+                radius,
+                angular_speed,
+                phase,
+            } => {
+                let angle = phase + angular_speed * time;
+                Mat4::from_translation(center + Vec3::new(angle.cos(), 0.0, angle.sin()) * radius)
+            }
+            Motion::Bob {
+                origin,
+                amplitude,
+                period,
+            } => {
+                let height = (time / period * std::f32::consts::TAU).sin() * amplitude;
+                Mat4::from_translation(origin + Vec3::Y * height)
+            }
+            Motion::Spin {
+                origin,
+                angular_speed,
+            } => {
+                Mat4::from_rotation_translation(Quat::from_rotation_y(angular_speed * time), origin)
+            }
+        }
+    }
+}
+
+/// Something sampled from the world every tick.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SoakMetric {
+    Entities,
+    /// Bytes held by the renderer's uploaded graphics, not sampled headless.
+    GpuMemory,
+    /// Packets sent on the world's connection that haven't been acked yet.
+    UnackedPackets,
+}
+
+impl SoakMetric {
+    pub const ALL: [SoakMetric; 3] = [
+        SoakMetric::Entities,
+        SoakMetric::GpuMemory,
+        SoakMetric::UnackedPackets,
+    ];
+}
+
+impl fmt::Display for SoakMetric {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SoakMetric::Entities => f.write_str("entities"),
+            SoakMetric::GpuMemory => f.write_str("gpu memory bytes"),
+            SoakMetric::UnackedPackets => f.write_str("unacked packets"),
+        }
+    }
+}
+
+/// What the world held at the end of a tick.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct SoakSample {
+    pub entities: u64,
+    pub gpu_memory: Option<u64>,
+    pub unacked_packets: u64,
+}
+
+impl SoakSample {
+    pub fn get(&self, metric: SoakMetric) -> Option<u64> {
+        match metric {
+            SoakMetric::Entities => Some(self.entities),
+            SoakMetric::GpuMemory => self.gpu_memory,
+            SoakMetric::UnackedPackets => Some(self.unacked_packets),
+        }
+    }
+}
+
+/// A metric that kept growing: even its lowest value over the end of the run
+/// was above the highest once the run had settled.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct SoakLeak {
+    pub metric: SoakMetric,
+    pub settled_max: u64,
+    pub end_min: u64,
+}
+
+impl fmt::Display for SoakLeak {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} grew from at most {} to at least {}",
+            self.metric, self.settled_max, self.end_min
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SoakReport {
+    pub config: SoakConfig,
+    /// Sampled at the end of the last tick.
+    pub last: SoakSample,
+    /// Entities that couldn't be spawned, such as for being over a cap.
+    pub spawn_failures: u64,
+    pub leaks: Vec<SoakLeak>,
+}
+
+impl SoakReport {
+    pub fn passed(&self) -> bool {
+        self.spawn_failures == 0 && self.leaks.is_empty()
+    }
+}
+
+impl fmt::Display for SoakReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "seed {} ran {} ticks, ending with {} entities and {} unacked packets",
+            self.config.seed, self.config.ticks, self.last.entities, self.last.unacked_packets
+        )?;
+        if let Some(gpu_memory) = self.last.gpu_memory {
+            write!(f, ", {gpu_memory} gpu memory bytes")?;
+        }
+        if self.spawn_failures > 0 {
+            write!(f, ", {} spawns failed", self.spawn_failures)?;
+        }
+        for leak in self.leaks.iter() {
+            write!(f, ", {leak}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Lowest and highest value of each metric over part of a run.
+#[derive(Debug, Default, Copy, Clone)]
+struct Extremes([Option<(u64, u64)>; SoakMetric::ALL.len()]);
+
+impl Extremes {
+    fn record(&mut self, sample: &SoakSample) {
+        for (metric, extremes) in SoakMetric::ALL.iter().zip(self.0.iter_mut()) {
+            if let Some(value) = sample.get(*metric) {
+                *extremes = Some(extremes.map_or((value, value), |(min, max)| {
+                    (min.min(value), max.max(value))
+                }));
+            }
+        }
+    }
+}
+
+struct SoakEntity {
+    entity: Entity,
+    prefab: Entity,
+    motion: Motion,
+}
+
+/// Spawns and moves a soak test's entities, and samples the world. Runs are
+/// judged on two windows, each a tenth of the run: the second tenth, once
+/// the entities are spawned and everything has settled, and the last.
+pub struct Scenario {
+    config: SoakConfig,
+    rng: SoakRng,
+    entities: Vec<SoakEntity>,
+    tick: u64,
+    settled: Extremes,
+    end: Extremes,
+    last: SoakSample,
+    spawn_failures: u64,
+    logger: Logger,
+}
+
+impl Scenario {
+    pub fn new(config: SoakConfig, logger: &Logger) -> Self {
+        Self {
+            config,
+            rng: SoakRng::new(config.seed),
+            entities: Vec::with_capacity(config.entities),
+            tick: 0,
+            settled: Extremes::default(),
+            end: Extremes::default(),
+            last: SoakSample::default(),
+            spawn_failures: 0,
+            logger: logger.sub("soak"),
+        }
+    }
+
+    /// Spawn the entities, before the first tick.
+    pub fn spawn(&mut self, world: &mut World) {
+        info!(
+            self.logger,
+            "soak test with seed {} spawning {} entities for {} ticks",
+            self.config.seed,
+            self.config.entities,
+            self.config.ticks
+        );
+        for _ in 0..self.config.entities {
+            self.spawn_one(world);
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.tick >= self.config.ticks
+    }
+
+    /// Respawn and move entities, then sample the world along with
+    /// `gpu_memory`, as reported by the renderer. Returns the report after
+    /// the last tick.
+    pub fn tick(&mut self, world: &mut World, gpu_memory: Option<u64>) -> Option<SoakReport> {
+        if self.is_finished() {
+            return None;
+        }
+        for _ in 0..self.config.respawns_per_tick.min(self.entities.len()) {
+            let index = self.rng.index(self.entities.len());
+            let replaced = self.entities.swap_remove(index);
+            // Whatever else despawned them has nothing left to leak.
+            let _ = world.despawn(replaced.entity);
+            let _ = world.despawn(replaced.prefab);
+            self.spawn_one(world);
+        }
+
+        let time = self.time();
+        for soak_entity in self.entities.iter() {
+            if let Ok(mut node) = world
+                .hecs_world
+                .get::<&mut SpatialHierarchyNode>(soak_entity.entity)
+            {
+                node.transform = soak_entity.motion.transform(time);
+                node.mark_updated();
+            }
+        }
+
+        self.last = SoakSample {
+            entities: world.hecs_world.len() as u64,
+            gpu_memory,
+            unacked_packets: world
+                .connections()
+                .map(|connection| connection.unacked_packets() as u64)
+                .sum(),
+        };
+        let window = (self.config.ticks / 10).max(1);
+        if (window..window * 2).contains(&self.tick) {
+            self.settled.record(&self.last);
+        }
+        if self.tick >= self.config.ticks.saturating_sub(window) {
+            self.end.record(&self.last);
+        }
+        self.tick += 1;
+        if self.tick % window == 0 {
+            info!(
+                self.logger,
+                "soak tick {}/{}: {:?}", self.tick, self.config.ticks, self.last
+            );
+        }
+        self.is_finished().then(|| self.report())
+    }
+
+    pub fn report(&self) -> SoakReport {
+        let leaks = SoakMetric::ALL
+            .iter()
+            .zip(self.settled.0.iter().zip(self.end.0.iter()))
+            .filter_map(|(metric, (settled, end))| {
+                let (_, settled_max) = (*settled)?;
+                let (end_min, _) = (*end)?;
+                (end_min > settled_max).then_some(SoakLeak {
+                    metric: *metric,
+                    settled_max,
+                    end_min,
+                })
+            })
+            .collect();
+        SoakReport {
+            config: self.config,
+            last: self.last,
+            spawn_failures: self.spawn_failures,
+            leaks,
+        }
+    }
+
+    fn time(&self) -> f32 {
+        self.tick as f32 * World::SIM_TICK_DELAY.as_secs_f32()
+    }
+
+    fn spawn_one(&mut self, world: &mut World) {
+        let motion = self.random_motion();
+        let color = Vec4::new(self.rng.unit(), self.rng.unit(), self.rng.unit(), 1.0);
+        let prefab = world.add_debug_mesh(marker(color));
+        let mut spatial = SpatialHierarchyNode::new(world.root.expect("world has no root"));
+        spatial.transform = motion.transform(self.time());
+        match world.add_object(StaticObject::new(prefab, spatial)) {
+            Ok(entity) => self.entities.push(SoakEntity {
+                entity,
+                prefab,
+                motion,
+            }),
+            Err(_) => {
+                // The world has already told the user why.
+                let _ = world.despawn(prefab);
+                self.spawn_failures += 1;
+            }
+        }
+    }
+
+    fn random_motion(&mut self) -> Motion {
+        let position = self.rng.point(SPAWN_EXTENT);
+        match self.rng.index(4) {
+            0 => Motion::Still { position },
+            1 => Motion::Orbit {
+                center: position,
+                radius: self.rng.range(1.0, 20.0),
+                angular_speed: self.rng.range(-2.0, 2.0),
+                phase: self.rng.range(0.0, std::f32::consts::TAU),
+            },
+            2 => Motion::Bob {
+                origin: position,
+                amplitude: self.rng.range(0.5, 5.0),
+                period: self.rng.range(0.5, 4.0),
+            },
+            _ => Motion::Spin {
+                origin: position,
+                angular_speed: self.rng.range(-4.0, 4.0),
+            },
+        }
+    }
+}
+
+/// A small cross, drawn for each soak test entity.
+fn marker(color: Vec4) -> DebugMesh {
+    let vertices = vec![
+        Vertex::pos(-0.5, 0.0, 0.0),
+        Vertex::pos(0.5, 0.0, 0.0),
+        Vertex::pos(0.0, -0.5, 0.0),
+        Vertex::pos(0.0, 0.5, 0.0),
+        Vertex::pos(0.0, 0.0, -0.5),
+        Vertex::pos(0.0, 0.0, 0.5),
+    ];
+    DebugMesh::line_list(vertices, (0..6).collect(), color)
+}
+
+/// xorshift64*, seeded through splitmix64 so nearby seeds start far apart.
+/// Implemented here so runs repeat exactly whatever the platform.
+struct SoakRng(u64);
+
+impl SoakRng {
+    fn new(seed: u64) -> Self {
+        let mut z = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        // xorshift never leaves zero.
+        Self((z ^ (z >> 31)) | 1)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    /// Uniform in [0, 1).
+    fn unit(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    fn range(&mut self, min: f32, max: f32) -> f32 {
+        min + (max - min) * self.unit()
+    }
+
+    fn index(&mut self, len: usize) -> usize {
+        (self.next_u64() % len as u64) as usize
+    }
+
+    fn point(&mut self, extent: f32) -> Vec3 {
+        Vec3::new(
+            self.range(-extent, extent),
+            self.range(-extent, extent),
+            self.range(-extent, extent),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use logger::LogLevel;
+
+    use super::*;
+
+    fn run(config: SoakConfig, mut leak: impl FnMut(&mut World)) -> (World, SoakReport) {
+        let logger = LogLevel::Info.logger();
+        let mut world = World::new(None, &logger, true);
+        let mut scenario = Scenario::new(config, &logger);
+        scenario.spawn(&mut world);
+        loop {
+            leak(&mut world);
+            if let Some(report) = scenario.tick(&mut world, None) {
+                assert!(scenario.is_finished());
+                return (world, report);
+            }
+        }
+    }
+
+    fn transforms(world: &World) -> Vec<Mat4> {
+        let mut query = world.hecs_world.query::<&SpatialHierarchyNode>();
+        query.iter().map(|(_, node)| node.transform).collect()
+    }
+
+    #[test]
+    fn steady_scenarios_pass() {
+        let config = SoakConfig {
+            entities: 50,
+            ticks: 200,
+            respawns_per_tick: 3,
+            ..Default::default()
+        };
+        let (world, report) = run(config, |_| {});
+        assert!(report.passed(), "{report}");
+        // The root, and a prefab for each entity.
+        assert_eq!(report.last.entities, 101);
+        assert_eq!(world.hecs_world.len(), 101);
+    }
+
+    #[test]
+    fn seeds_repeat_exactly() {
+        let config = SoakConfig {
+            seed: 7,
+            entities: 20,
+            ticks: 30,
+            respawns_per_tick: 2,
+        };
+        let (a, _) = run(config, |_| {});
+        let (b, _) = run(config, |_| {});
+        assert_eq!(transforms(&a), transforms(&b));
+
+        let (c, _) = run(SoakConfig { seed: 8, ..config }, |_| {});
+        assert_ne!(transforms(&a), transforms(&c));
+    }
+
+    #[test]
+    fn growing_entity_counts_are_leaks() {
+        let config = SoakConfig {
+            entities: 10,
+            ticks: 100,
+            respawns_per_tick: 1,
+            ..Default::default()
+        };
+        let (_, report) = run(config, |world| {
+            world.hecs_world.spawn((Vec3::ZERO,));
+        });
+        assert!(!report.passed());
+        assert_eq!(
+            report.leaks,
+            [SoakLeak {
+                metric: SoakMetric::Entities,
+                settled_max: 21 + 20,
+                end_min: 21 + 91,
+            }]
+        );
+    }
+}
@@ -0,0 +1,520 @@
+//! Lifecycle of game systems registered with the engine.
+//!
+//! Systems are statically linked, or loaded from a plugins directory built
+//! against the exact same engine (see `plugins`), and loading one can still
+//! fail: a missing asset, a device that isn't there yet. Each registered system
+//! is wrapped in a `SystemHandle` that tracks its `SystemState`, retries
+//! transient load failures with backoff, and records every state change so
+//! frame callbacks (and a debug UI) can observe them, instead of the failure
+//! taking the whole engine down.
+
+use std::fmt::Display;
+use std::time::{Duration, Instant};
+
+use logger::Logger;
+use world::World;
+
+use crate::phase::FramePhase;
+
+/// A system supplied by the embedding game. Systems are loaded in the order
+/// they were registered, updated every frame in their phase, and unloaded in
+/// reverse order when the frame loop exits.
+pub trait GameSystem {
+    /// Name used in logs and status reports, and by other systems' ordering
+    /// constraints.
+    fn name(&self) -> &str {
+        std::any::type_name::<Self>()
+    }
+
+    /// Version shown with the name in everything the system logs.
+    fn version(&self) -> &str {
+        "0.0.0"
+    }
+
+    /// Called before every load with the system's own root logger, tagged
+    /// with its name and version. Its level can be set apart from the rest
+    /// of the engine's, see `Logger::set_plugin_level`.
+    fn set_logger(&mut self, _logger: Logger) {}
+
+    /// Phase of the frame the system is updated in.
+    fn phase(&self) -> FramePhase {
+        FramePhase::PostSim
+    }
+
+    /// Names of systems that must be updated before this one. Systems in an
+    /// earlier phase always are.
+    fn runs_after(&self) -> &[&str] {
+        &[]
+    }
+
+    /// Names of systems that must be updated after this one. Systems in a
+    /// later phase always are.
+    fn runs_before(&self) -> &[&str] {
+        &[]
+    }
+
+    fn load(&mut self, _world: &mut World) -> Result<(), SystemError> {
+        Ok(())
+    }
+
+    fn update(&mut self, world: &mut World, delta_time: &Duration);
+
+    fn unload(&mut self, _world: &mut World) {}
+}
+
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+pub enum SystemError {
+    /// Loading may succeed if tried again later.
+    #[error("transient load failure: {0}")]
+    Transient(String),
+    /// Loading will not succeed without intervention.
+    #[error("load failure: {0}")]
+    Fatal(String),
+}
+
+impl SystemError {
+    pub fn transient(err: impl Display) -> Self {
+        SystemError::Transient(err.to_string())
+    }
+
+    pub fn fatal(err: impl Display) -> Self {
+        SystemError::Fatal(err.to_string())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SystemState {
+    Unloaded,
+    Loaded,
+    /// The last load attempt failed. `retry_at` is set while a transient
+    /// failure still has attempts left.
+    Failed {
+        error: SystemError,
+        attempts: u32,
+        retry_at: Option<Instant>,
+    },
+    /// Loaded, and will be unloaded and loaded again on the next frame.
+    Reloading,
+}
+
+/// A change in a system's state, published to frame callbacks.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SystemStateChange {
+    pub system: String,
+    pub from: SystemState,
+    pub to: SystemState,
+}
+
+/// How transient load failures are retried. The delay doubles after every
+/// failed attempt, up to `max_backoff`.
+#[derive(Debug, Copy, Clone)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            initial_backoff: Duration::from_millis(250),
+            max_backoff: Duration::from_secs(8),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Delay before the attempt following `attempts` failed ones.
+    pub fn backoff(&self, attempts: u32) -> Duration {
+        let factor = 1u32
+            .checked_shl(attempts.saturating_sub(1))
+            .unwrap_or(u32::MAX);
+        self.initial_backoff
+            .saturating_mul(factor)
+            .min(self.max_backoff)
+    }
+}
+
+/// A registered system and its lifecycle state.
+pub struct SystemHandle {
+    system: Box<dyn GameSystem>,
+    state: SystemState,
+    policy: RetryPolicy,
+}
+
+impl SystemHandle {
+    pub fn new(system: Box<dyn GameSystem>, policy: RetryPolicy) -> Self {
+        Self {
+            system,
+            state: SystemState::Unloaded,
+            policy,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        self.system.name()
+    }
+
+    pub fn phase(&self) -> FramePhase {
+        self.system.phase()
+    }
+
+    pub fn runs_after(&self) -> &[&str] {
+        self.system.runs_after()
+    }
+
+    pub fn runs_before(&self) -> &[&str] {
+        self.system.runs_before()
+    }
+
+    pub fn state(&self) -> &SystemState {
+        &self.state
+    }
+
+    pub fn is_loaded(&self) -> bool {
+        self.state == SystemState::Loaded
+    }
+
+    /// Ask for the system to be unloaded and loaded again on the next frame. A
+    /// failed system starts over with a fresh set of attempts.
+    pub fn request_reload(&mut self, changes: &mut Vec<SystemStateChange>) {
+        match self.state {
+            SystemState::Loaded => self.transition(SystemState::Reloading, changes),
+            SystemState::Failed { .. } => self.transition(SystemState::Unloaded, changes),
+            SystemState::Unloaded | SystemState::Reloading => {}
+        }
+    }
+
+    /// Load the system if it hasn't been yet.
+    pub fn load(&mut self, world: &mut World, now: Instant, changes: &mut Vec<SystemStateChange>) {
+        if self.state == SystemState::Unloaded {
+            self.try_load(world, 0, now, changes);
+        }
+    }
+
+    /// Drive the state machine: load unloaded systems, retry failed ones whose
+    /// backoff has elapsed, and reload those that asked for it. Loaded systems
+    /// are updated.
+    pub fn update(
+        &mut self,
+        world: &mut World,
+        delta_time: &Duration,
+        now: Instant,
+        changes: &mut Vec<SystemStateChange>,
+    ) {
+        match &self.state {
+            SystemState::Unloaded => self.try_load(world, 0, now, changes),
+            SystemState::Loaded => self.system.update(world, delta_time),
+            SystemState::Failed {
+                attempts,
+                retry_at: Some(retry_at),
+                ..
+            } if *retry_at <= now => {
+                let attempts = *attempts;
+                self.try_load(world, attempts, now, changes)
+            }
+            SystemState::Failed { .. } => {}
+            SystemState::Reloading => {
+                self.system.unload(world);
+                self.try_load(world, 0, now, changes)
+            }
+        }
+    }
+
+    /// Unload the system if it is loaded.
+    pub fn unload(&mut self, world: &mut World, changes: &mut Vec<SystemStateChange>) {
+        if matches!(self.state, SystemState::Loaded | SystemState::Reloading) {
+            self.system.unload(world);
+        }
+        self.transition(SystemState::Unloaded, changes);
+    }
+
+    fn try_load(
+        &mut self,
+        world: &mut World,
+        previous_attempts: u32,
+        now: Instant,
+        changes: &mut Vec<SystemStateChange>,
+    ) {
+        let logger = world
+            .logger
+            .plugin(self.system.name(), self.system.version());
+        self.system.set_logger(logger);
+        let next = match self.system.load(world) {
+            Ok(()) => SystemState::Loaded,
+            Err(error) => {
+                let attempts = previous_attempts + 1;
+                let retry_at = match error {
+                    SystemError::Transient(_) if attempts < self.policy.max_attempts => {
+                        Some(now + self.policy.backoff(attempts))
+                    }
+                    _ => None,
+                };
+                SystemState::Failed {
+                    error,
+                    attempts,
+                    retry_at,
+                }
+            }
+        };
+        self.transition(next, changes);
+    }
+
+    fn transition(&mut self, to: SystemState, changes: &mut Vec<SystemStateChange>) {
+        if self.state == to {
+            return;
+        }
+        let from = std::mem::replace(&mut self.state, to.clone());
+        changes.push(SystemStateChange {
+            system: self.name().to_string(),
+            from,
+            to,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use logger::LogLevel;
+
+    use super::*;
+
+    /// How often each of a `Flaky` system's methods was called.
+    #[derive(Default, Debug, PartialEq, Eq)]
+    struct Calls {
+        loads: u32,
+        updates: u32,
+        unloads: u32,
+    }
+
+    /// Fails to load with each of `failures` in turn, then loads.
+    struct Flaky {
+        failures: Vec<SystemError>,
+        calls: Rc<RefCell<Calls>>,
+    }
+
+    impl Flaky {
+        fn handle(failures: Vec<SystemError>) -> (SystemHandle, Rc<RefCell<Calls>>) {
+            let calls = Rc::new(RefCell::new(Calls::default()));
+            let policy = RetryPolicy {
+                max_attempts: 3,
+                initial_backoff: Duration::from_millis(100),
+                max_backoff: Duration::from_secs(1),
+            };
+            let flaky = Flaky {
+                failures: failures.into_iter().rev().collect(),
+                calls: calls.clone(),
+            };
+            (SystemHandle::new(Box::new(flaky), policy), calls)
+        }
+    }
+
+    impl GameSystem for Flaky {
+        fn name(&self) -> &str {
+            "flaky"
+        }
+
+        fn load(&mut self, _world: &mut World) -> Result<(), SystemError> {
+            self.calls.borrow_mut().loads += 1;
+            self.failures.pop().map_or(Ok(()), Err)
+        }
+
+        fn update(&mut self, _world: &mut World, _delta_time: &Duration) {
+            self.calls.borrow_mut().updates += 1;
+        }
+
+        fn unload(&mut self, _world: &mut World) {
+            self.calls.borrow_mut().unloads += 1;
+        }
+    }
+
+    fn counts(loads: u32, updates: u32, unloads: u32) -> Calls {
+        Calls {
+            loads,
+            updates,
+            unloads,
+        }
+    }
+
+    struct Versioned {
+        logger: Option<Logger>,
+    }
+
+    impl GameSystem for Versioned {
+        fn name(&self) -> &str {
+            "versioned"
+        }
+
+        fn version(&self) -> &str {
+            "1.2.3"
+        }
+
+        fn set_logger(&mut self, logger: Logger) {
+            self.logger = Some(logger);
+        }
+
+        fn load(&mut self, _world: &mut World) -> Result<(), SystemError> {
+            let logger = self
+                .logger
+                .as_ref()
+                .ok_or_else(|| SystemError::fatal("no logger"))?;
+            logger::info!(logger, "loaded");
+            Ok(())
+        }
+
+        fn update(&mut self, _world: &mut World, _delta_time: &Duration) {}
+    }
+
+    #[test]
+    fn systems_get_their_own_logger_on_load() {
+        let mut world = World::new(None, &LogLevel::Info.logger(), true);
+        let mut handle =
+            SystemHandle::new(Box::new(Versioned { logger: None }), RetryPolicy::default());
+        handle.load(&mut world, Instant::now(), &mut Vec::new());
+        assert!(handle.is_loaded());
+
+        let stats = world.logger.plugin_stats();
+        assert_eq!(stats.len(), 1);
+        assert_eq!(
+            (&*stats[0].name, &*stats[0].version),
+            ("versioned", "1.2.3")
+        );
+        assert_eq!(stats[0].total(), 1);
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_limit() {
+        let policy = RetryPolicy {
+            max_attempts: 100,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(1),
+        };
+        assert_eq!(policy.backoff(1), Duration::from_millis(100));
+        assert_eq!(policy.backoff(2), Duration::from_millis(200));
+        assert_eq!(policy.backoff(4), Duration::from_millis(800));
+        assert_eq!(policy.backoff(5), Duration::from_secs(1));
+        assert_eq!(policy.backoff(64), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn transient_failures_are_retried_until_attempts_run_out() {
+        let mut world = World::new(None, &LogLevel::Info.logger(), true);
+        let error = SystemError::transient("device not ready");
+        let (mut handle, calls) = Flaky::handle(vec![error.clone(); 3]);
+        let dt = Duration::from_millis(16);
+        let start = Instant::now();
+        let mut changes = Vec::new();
+
+        handle.load(&mut world, start, &mut changes);
+        assert_eq!(
+            handle.state(),
+            &SystemState::Failed {
+                error: error.clone(),
+                attempts: 1,
+                retry_at: Some(start + Duration::from_millis(100)),
+            }
+        );
+        // Not retried before the backoff has elapsed.
+        handle.update(
+            &mut world,
+            &dt,
+            start + Duration::from_millis(50),
+            &mut changes,
+        );
+        assert_eq!(*calls.borrow(), counts(1, 0, 0));
+
+        let retry = start + Duration::from_millis(100);
+        handle.update(&mut world, &dt, retry, &mut changes);
+        assert_eq!(
+            handle.state(),
+            &SystemState::Failed {
+                error: error.clone(),
+                attempts: 2,
+                retry_at: Some(retry + Duration::from_millis(200)),
+            }
+        );
+
+        // The last attempt fails, leaving the system disabled.
+        let last = retry + Duration::from_millis(200);
+        handle.update(&mut world, &dt, last, &mut changes);
+        let disabled = SystemState::Failed {
+            error,
+            attempts: 3,
+            retry_at: None,
+        };
+        assert_eq!(handle.state(), &disabled);
+        handle.update(
+            &mut world,
+            &dt,
+            last + Duration::from_secs(60),
+            &mut changes,
+        );
+        assert_eq!(handle.state(), &disabled);
+        assert_eq!(*calls.borrow(), counts(3, 0, 0));
+        assert_eq!(changes.len(), 3);
+        assert_eq!(changes[0].from, SystemState::Unloaded);
+        assert_eq!(changes[2].to, disabled);
+
+        // A reload starts over with a fresh set of attempts, and this time it
+        // loads.
+        handle.request_reload(&mut changes);
+        assert_eq!(handle.state(), &SystemState::Unloaded);
+        handle.update(&mut world, &dt, last, &mut changes);
+        assert!(handle.is_loaded());
+        handle.update(&mut world, &dt, last, &mut changes);
+        assert_eq!(*calls.borrow(), counts(4, 1, 0));
+    }
+
+    #[test]
+    fn fatal_failures_are_not_retried() {
+        let mut world = World::new(None, &LogLevel::Info.logger(), true);
+        let error = SystemError::fatal("missing asset");
+        let (mut handle, calls) = Flaky::handle(vec![error.clone()]);
+        let start = Instant::now();
+        let mut changes = Vec::new();
+
+        handle.load(&mut world, start, &mut changes);
+        let disabled = SystemState::Failed {
+            error,
+            attempts: 1,
+            retry_at: None,
+        };
+        assert_eq!(handle.state(), &disabled);
+        let later = start + Duration::from_secs(60);
+        handle.update(&mut world, &Duration::ZERO, later, &mut changes);
+        assert_eq!(handle.state(), &disabled);
+        assert_eq!(*calls.borrow(), counts(1, 0, 0));
+    }
+
+    #[test]
+    fn only_loaded_systems_are_unloaded() {
+        let mut world = World::new(None, &LogLevel::Info.logger(), true);
+        let now = Instant::now();
+        let mut changes = Vec::new();
+
+        // A disabled system never loaded, so there's nothing to unload.
+        let (mut disabled, disabled_calls) = Flaky::handle(vec![SystemError::fatal("missing")]);
+        disabled.load(&mut world, now, &mut changes);
+        disabled.unload(&mut world, &mut changes);
+        assert_eq!(disabled.state(), &SystemState::Unloaded);
+        assert_eq!(*disabled_calls.borrow(), counts(1, 0, 0));
+
+        let (mut loaded, loaded_calls) = Flaky::handle(Vec::new());
+        loaded.load(&mut world, now, &mut changes);
+        loaded.unload(&mut world, &mut changes);
+        assert_eq!(loaded.state(), &SystemState::Unloaded);
+        assert_eq!(*loaded_calls.borrow(), counts(1, 0, 1));
+
+        // Reloading unloads before loading again.
+        loaded.load(&mut world, now, &mut changes);
+        loaded.request_reload(&mut changes);
+        assert_eq!(loaded.state(), &SystemState::Reloading);
+        loaded.update(&mut world, &Duration::ZERO, now, &mut changes);
+        assert!(loaded.is_loaded());
+        assert_eq!(*loaded_calls.borrow(), counts(3, 0, 2));
+    }
+}
@@ -0,0 +1,475 @@
+//! Frame timelines: when each phase of a frame started and ended, when the
+//! frame was submitted to the GPU and presented, and how many packets were
+//! sent and received. Every frame is recorded, but only kept while a capture
+//! is armed, either from the console with `timeline_capture <frames>` or
+//! automatically when a frame takes longer than the hitch threshold, set with
+//! `timeline_hitch_ms <ms|off>`. Finished captures are written to
+//! `TimelineConfig::dir`, as a chrome trace (for chrome://tracing or
+//! Perfetto) or in a compact binary format, see `write_binary`.
+
+use std::cell::RefCell;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use network::PacketCounts;
+
+use crate::console::Console;
+
+/// Magic bytes and version at the start of binary timelines.
+pub const BINARY_MAGIC: &[u8; 4] = b"NTL1";
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum TimelineFormat {
+    #[default]
+    ChromeTrace,
+    Binary,
+}
+
+impl TimelineFormat {
+    fn extension(&self) -> &'static str {
+        match self {
+            TimelineFormat::ChromeTrace => "json",
+            TimelineFormat::Binary => "ntl",
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+#[error("unknown timeline format {0:?}, expected chrome or binary")]
+pub struct UnknownTimelineFormat(String);
+
+impl FromStr for TimelineFormat {
+    type Err = UnknownTimelineFormat;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "chrome" => Ok(TimelineFormat::ChromeTrace),
+            "binary" => Ok(TimelineFormat::Binary),
+            _ => Err(UnknownTimelineFormat(s.to_string())),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TimelineConfig {
+    /// Frames taking longer than this are captured, along with the frames
+    /// after them. None to only capture when asked.
+    pub hitch_threshold: Option<Duration>,
+    /// Frames captured after a hitch.
+    pub frames_after_hitch: u64,
+    pub format: TimelineFormat,
+    /// Where captures are written.
+    pub dir: PathBuf,
+}
+
+impl Default for TimelineConfig {
+    fn default() -> Self {
+        Self {
+            hitch_threshold: None,
+            frames_after_hitch: 30,
+            format: TimelineFormat::default(),
+            dir: PathBuf::from("timelines"),
+        }
+    }
+}
+
+/// Part of a frame, relative to the frame's start.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Span {
+    pub name: &'static str,
+    pub start: Duration,
+    pub end: Duration,
+}
+
+/// A point in a frame, relative to the frame's start.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Mark {
+    pub name: &'static str,
+    pub at: Duration,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct FrameRecord {
+    pub frame: u64,
+    /// When the frame started, relative to the timeline's creation.
+    pub start: Duration,
+    pub length: Duration,
+    pub spans: Vec<Span>,
+    pub marks: Vec<Mark>,
+    /// Packets sent and received on the world's connection during the frame.
+    pub packets: PacketCounts,
+}
+
+/// Records the current frame, and keeps frames while a capture is armed.
+pub struct Timeline {
+    config: TimelineConfig,
+    epoch: Instant,
+    frame_start: Instant,
+    current: FrameRecord,
+    open_span: Option<(&'static str, Instant)>,
+    // Packet totals at the end of the last frame.
+    packets: PacketCounts,
+    armed: u64,
+    captured: Vec<FrameRecord>,
+}
+
+impl Timeline {
+    pub fn new(config: TimelineConfig) -> Self {
+        let now = Instant::now();
+        Self {
+            config,
+            epoch: now,
+            frame_start: now,
+            current: FrameRecord::default(),
+            open_span: None,
+            packets: PacketCounts::default(),
+            armed: 0,
+            captured: Vec::new(),
+        }
+    }
+
+    pub fn config(&self) -> &TimelineConfig {
+        &self.config
+    }
+
+    /// Keep the next `frames` frames, on top of any already armed.
+    pub fn arm(&mut self, frames: u64) {
+        self.armed += frames;
+    }
+
+    pub fn set_hitch_threshold(&mut self, threshold: Option<Duration>) {
+        self.config.hitch_threshold = threshold;
+    }
+
+    pub fn is_capturing(&self) -> bool {
+        self.armed > 0
+    }
+
+    pub fn begin_frame(&mut self, frame: u64, now: Instant) {
+        self.frame_start = now;
+        self.open_span = None;
+        self.current.frame = frame;
+        self.current.start = now.saturating_duration_since(self.epoch);
+        self.current.spans.clear();
+        self.current.marks.clear();
+    }
+
+    /// End the open span, if there is one, and start `name`.
+    pub fn enter(&mut self, name: &'static str, now: Instant) {
+        self.close_span(now);
+        self.open_span = Some((name, now));
+    }
+
+    pub fn mark(&mut self, name: &'static str, at: Instant) {
+        self.current.marks.push(Mark {
+            name,
+            at: at.saturating_duration_since(self.frame_start),
+        });
+    }
+
+    /// End the frame, given the connection's packet totals. Returns the
+    /// captured frames once a capture is done.
+    pub fn end_frame(&mut self, now: Instant, packets: PacketCounts) -> Option<Vec<FrameRecord>> {
+        self.close_span(now);
+        self.current.length = now.saturating_duration_since(self.frame_start);
+        // Totals restart from zero when a connection is replaced.
+        self.current.packets = PacketCounts {
+            sent: packets.sent.saturating_sub(self.packets.sent),
+            received: packets.received.saturating_sub(self.packets.received),
+        };
+        self.packets = packets;
+
+        let hitch = self
+            .config
+            .hitch_threshold
+            .is_some_and(|threshold| self.current.length > threshold);
+        if self.armed == 0 && !hitch {
+            return None;
+        }
+        self.captured.push(self.current.clone());
+        if hitch {
+            self.armed = self.armed.max(self.config.frames_after_hitch + 1);
+        }
+        self.armed -= 1;
+        (self.armed == 0).then(|| std::mem::take(&mut self.captured))
+    }
+
+    fn close_span(&mut self, now: Instant) {
+        if let Some((name, start)) = self.open_span.take() {
+            self.current.spans.push(Span {
+                name,
+                start: start.saturating_duration_since(self.frame_start),
+                end: now.saturating_duration_since(self.frame_start),
+            });
+        }
+    }
+}
+
+/// Register the console commands controlling `timeline`.
+pub(crate) fn register_commands(console: &mut Console, timeline: &Rc<RefCell<Timeline>>) {
+    let capture = Rc::clone(timeline);
+    console.register(
+        "timeline_capture",
+        "capture a timeline of the next <frames> frames",
+        move |_world, args| {
+            let frames = match args {
+                [frames] => frames.parse::<u64>().map_err(|err| err.to_string())?,
+                _ => return Err("expected a number of frames".to_string()),
+            };
+            capture.borrow_mut().arm(frames);
+            Ok(format!("capturing the next {frames} frames"))
+        },
+    );
+    let hitch = Rc::clone(timeline);
+    console.register(
+        "timeline_hitch_ms",
+        "capture a timeline when a frame takes longer than <ms>, or off",
+        move |_world, args| {
+            let threshold = match args {
+                ["off"] => None,
+                [ms] => Some(parse_hitch_ms(ms)?),
+                _ => return Err("expected milliseconds or off".to_string()),
+            };
+            hitch.borrow_mut().set_hitch_threshold(threshold);
+            Ok(match threshold {
+                Some(threshold) => format!("capturing frames longer than {threshold:?}"),
+                None => "not capturing hitches".to_string(),
+            })
+        },
+    );
+}
+
+/// A hitch threshold given in milliseconds on the console.
+fn parse_hitch_ms(arg: &str) -> Result<Duration, String> {
+    arg.parse::<f64>()
+        .ok()
+        .filter(|ms| *ms > 0.0)
+        .and_then(|ms| Duration::try_from_secs_f64(ms / 1000.0).ok())
+        .ok_or_else(|| format!("expected a positive number of milliseconds, got {arg}"))
+}
+
+/// Write captured frames to a new file in `config.dir`, returning its path.
+pub fn export(config: &TimelineConfig, frames: &[FrameRecord]) -> io::Result<PathBuf> {
+    fs::create_dir_all(&config.dir)?;
+    let first = frames.first().map_or(0, |frame| frame.frame);
+    let path = config
+        .dir
+        .join(format!("timeline-{first}.{}", config.format.extension()));
+    let mut writer = BufWriter::new(File::create(&path)?);
+    match config.format {
+        TimelineFormat::ChromeTrace => write_chrome_trace(frames, &mut writer)?,
+        TimelineFormat::Binary => write_binary(frames, &mut writer)?,
+    }
+    writer.flush()?;
+    Ok(path)
+}
+
+/// Write frames as chrome trace events: a complete event for each frame and
+/// span, an instant event for each mark and a counter of packets.
+pub fn write_chrome_trace(frames: &[FrameRecord], writer: &mut impl Write) -> io::Result<()> {
+    let micros = |duration: Duration| duration.as_secs_f64() * 1e6;
+    write!(writer, "{{\"displayTimeUnit\":\"ms\",\"traceEvents\":[")?;
+    let mut separator = "";
+    for frame in frames {
+        write!(
+            writer,
+            "{separator}{{\"name\":\"frame\",\"cat\":\"frame\",\"ph\":\"X\",\"pid\":1,\"tid\":1,\
+             \"ts\":{:.3},\"dur\":{:.3},\"args\":{{\"frame\":{}}}}}",
+            micros(frame.start),
+            micros(frame.length),
+            frame.frame
+        )?;
+        separator = ",";
+        for span in frame.spans.iter() {
+            write!(
+                writer,
+                ",{{\"name\":\"{}\",\"cat\":\"phase\",\"ph\":\"X\",\"pid\":1,\"tid\":1,\
+                 \"ts\":{:.3},\"dur\":{:.3}}}",
+                span.name,
+                micros(frame.start + span.start),
+                micros(span.end.saturating_sub(span.start))
+            )?;
+        }
+        for mark in frame.marks.iter() {
+            write!(
+                writer,
+                ",{{\"name\":\"{}\",\"cat\":\"gpu\",\"ph\":\"i\",\"s\":\"t\",\"pid\":1,\"tid\":1,\
+                 \"ts\":{:.3}}}",
+                mark.name,
+                micros(frame.start + mark.at)
+            )?;
+        }
+        write!(
+            writer,
+            ",{{\"name\":\"packets\",\"ph\":\"C\",\"pid\":1,\"ts\":{:.3},\
+             \"args\":{{\"sent\":{},\"received\":{}}}}}",
+            micros(frame.start),
+            frame.packets.sent,
+            frame.packets.received
+        )?;
+    }
+    write!(writer, "]}}")
+}
+
+/// Write frames in a compact binary format, all integers little endian:
+///
+/// - `BINARY_MAGIC`, then the number of frames as a u32
+/// - per frame: its number, start and length in nanoseconds, packets sent and
+///   received, all u64
+/// - then its spans, a u16 count of them, each a u8 name length, the name, and
+///   start and end nanoseconds from the frame's start as u64
+/// - then its marks, a u16 count of them, each a u8 name length, the name, and
+///   nanoseconds from the frame's start as u64
+pub fn write_binary(frames: &[FrameRecord], writer: &mut impl Write) -> io::Result<()> {
+    let nanos = |duration: Duration| duration.as_nanos() as u64;
+    let name = |writer: &mut dyn Write, name: &str| {
+        writer.write_all(&[name.len() as u8])?;
+        writer.write_all(name.as_bytes())
+    };
+    writer.write_all(BINARY_MAGIC)?;
+    writer.write_all(&(frames.len() as u32).to_le_bytes())?;
+    for frame in frames {
+        for value in [
+            frame.frame,
+            nanos(frame.start),
+            nanos(frame.length),
+            frame.packets.sent,
+            frame.packets.received,
+        ] {
+            writer.write_all(&value.to_le_bytes())?;
+        }
+        writer.write_all(&(frame.spans.len() as u16).to_le_bytes())?;
+        for span in frame.spans.iter() {
+            name(writer, span.name)?;
+            writer.write_all(&nanos(span.start).to_le_bytes())?;
+            writer.write_all(&nanos(span.end).to_le_bytes())?;
+        }
+        writer.write_all(&(frame.marks.len() as u16).to_le_bytes())?;
+        for mark in frame.marks.iter() {
+            name(writer, mark.name)?;
+            writer.write_all(&nanos(mark.at).to_le_bytes())?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record_frame(
+        timeline: &mut Timeline,
+        frame: u64,
+        start: Instant,
+        length: Duration,
+    ) -> Option<Vec<FrameRecord>> {
+        timeline.begin_frame(frame, start);
+        timeline.enter("input", start);
+        timeline.enter("sim", start + length / 2);
+        timeline.mark("present", start + length);
+        timeline.end_frame(
+            start + length,
+            PacketCounts {
+                sent: frame * 2,
+                received: frame,
+            },
+        )
+    }
+
+    #[test]
+    fn captures_armed_frames_and_hitches() {
+        let mut timeline = Timeline::new(TimelineConfig {
+            hitch_threshold: Some(Duration::from_millis(20)),
+            frames_after_hitch: 2,
+            ..Default::default()
+        });
+        let start = Instant::now();
+        let frame_length = Duration::from_millis(10);
+        let at = |frame: u64| start + frame_length * frame as u32;
+
+        assert_eq!(record_frame(&mut timeline, 0, at(0), frame_length), None);
+        timeline.arm(2);
+        assert_eq!(record_frame(&mut timeline, 1, at(1), frame_length), None);
+        let captured = record_frame(&mut timeline, 2, at(2), frame_length).unwrap();
+        assert_eq!(captured.len(), 2);
+        assert_eq!(captured[0].frame, 1);
+        assert_eq!(
+            captured[0].spans,
+            [
+                Span {
+                    name: "input",
+                    start: Duration::ZERO,
+                    end: Duration::from_millis(5),
+                },
+                Span {
+                    name: "sim",
+                    start: Duration::from_millis(5),
+                    end: frame_length,
+                },
+            ]
+        );
+        assert_eq!(captured[1].start - captured[0].start, frame_length);
+        assert_eq!(captured[1].marks[0].at, frame_length);
+        assert_eq!(
+            captured[1].packets,
+            PacketCounts {
+                sent: 2,
+                received: 1
+            }
+        );
+        assert!(!timeline.is_capturing());
+
+        // The hitch and the two frames after it.
+        assert!(record_frame(&mut timeline, 3, at(3), Duration::from_millis(25)).is_none());
+        assert!(record_frame(&mut timeline, 4, at(6), frame_length).is_none());
+        let captured = record_frame(&mut timeline, 5, at(7), frame_length).unwrap();
+        let frames = captured.iter().map(|frame| frame.frame).collect::<Vec<_>>();
+        assert_eq!(frames, [3, 4, 5]);
+    }
+
+    #[test]
+    fn hitch_thresholds_are_positive_milliseconds() {
+        assert_eq!(parse_hitch_ms("50"), Ok(Duration::from_millis(50)));
+        assert_eq!(parse_hitch_ms("0.5"), Ok(Duration::from_micros(500)));
+        for arg in ["0", "-5", "nan", "inf", "1e30", "slow"] {
+            assert!(parse_hitch_ms(arg).is_err(), "{arg} parsed");
+        }
+    }
+
+    #[test]
+    fn writes_every_event() {
+        let mut timeline = Timeline::new(TimelineConfig::default());
+        timeline.arm(1);
+        let frames =
+            record_frame(&mut timeline, 7, Instant::now(), Duration::from_millis(4)).unwrap();
+
+        let mut json = Vec::new();
+        write_chrome_trace(&frames, &mut json).unwrap();
+        let json = String::from_utf8(json).unwrap();
+        assert!(json.starts_with("{\"displayTimeUnit\""));
+        assert!(json.ends_with("]}"));
+        for name in [
+            "\"frame\"",
+            "\"input\"",
+            "\"sim\"",
+            "\"present\"",
+            "\"packets\"",
+        ] {
+            assert!(json.contains(name), "{name} missing from {json}");
+        }
+        assert!(json.contains("\"args\":{\"frame\":7}"));
+
+        let mut binary = Vec::new();
+        write_binary(&frames, &mut binary).unwrap();
+        assert_eq!(&binary[..4], BINARY_MAGIC);
+        assert_eq!(binary[4..8], 1u32.to_le_bytes());
+        assert_eq!(binary[8..16], 7u64.to_le_bytes());
+        let spans = 4 + 4 + 5 * 8 + 2 + (1 + 5 + 16) + (1 + 3 + 16);
+        let marks = 2 + (1 + 7 + 8);
+        assert_eq!(binary.len(), spans + marks);
+    }
+}
@@ -0,0 +1,139 @@
+//! Worlds run alongside the engine's own in one process, each with its own
+//! built-in systems and net sync, ticked by the frame loop after the engine's
+//! world. The loopback client and rooms are both run this way.
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use async_lock::Mutex;
+use input::wire::InputState;
+use logger::Logger;
+use net_sync_system::NetSyncState;
+use world::{World, WorldLockAndControllerState};
+
+use crate::{log_system_changes, BuiltinSystem, EngineConfig, RetryPolicy, SystemHandle};
+
+/// A world and the systems driving it.
+pub(crate) struct WorldRunner {
+    world: Arc<Mutex<World>>,
+    // Nobody is at the controls of a world run alongside the engine's.
+    controllers: Arc<Mutex<[InputState; 2]>>,
+    net_sync: NetSyncState,
+    systems: Vec<SystemHandle>,
+    #[cfg(feature = "asset-loader")]
+    asset_loader: Option<(
+        asset_loader_system::AssetLoader,
+        Arc<Mutex<world::AssetLoaderState>>,
+    )>,
+    logger: Logger,
+}
+
+impl WorldRunner {
+    /// Load the world's systems in the same order as the engine's, so that
+    /// worlds synced with each other spawn the same entities. `net_sync` is
+    /// loaded after the systems, and models are loaded through `models`.
+    #[cfg_attr(not(feature = "world-update"), allow(unused_variables))]
+    pub async fn load(
+        world: World,
+        mut net_sync: NetSyncState,
+        config: &EngineConfig,
+        #[cfg(feature = "asset-loader")] models: asset_loader_system::ModelCache,
+        retry_policy: RetryPolicy,
+        logger: Logger,
+    ) -> Self {
+        let world = Arc::new(Mutex::new(world));
+        let controllers = Arc::new(Mutex::new(<[InputState; 2]>::default()));
+
+        #[cfg_attr(not(feature = "world-update"), allow(unused_mut))]
+        let mut systems = Vec::new();
+        #[cfg(feature = "world-update")]
+        if config.is_enabled(BuiltinSystem::WorldUpdate) {
+            systems.push(SystemHandle::new(
+                Box::new(world_update_system::WorldUpdate::new()),
+                retry_policy,
+            ));
+        }
+        let mut system_changes = Vec::new();
+        {
+            let world = &mut *world.lock().await;
+            let now = Instant::now();
+            for system in systems.iter_mut() {
+                system.load(world, now, &mut system_changes);
+            }
+        }
+        log_system_changes(&logger, &system_changes);
+
+        net_sync.load(&mut WorldLockAndControllerState::lock(&world, &controllers).await);
+
+        #[cfg(feature = "asset-loader")]
+        let asset_loader = if config.is_enabled(BuiltinSystem::AssetLoader) {
+            let asset_state = Arc::new(Mutex::new(world::AssetLoaderState::default()));
+            let mut asset_loader = asset_loader_system::AssetLoader::with_cache(models);
+            asset_loader
+                .load(&mut world::AssetLoaderStateAndWorldLock::lock(&world, &asset_state).await);
+            Some((asset_loader, asset_state))
+        } else {
+            None
+        };
+
+        Self {
+            world,
+            controllers,
+            net_sync,
+            systems,
+            #[cfg(feature = "asset-loader")]
+            asset_loader,
+            logger,
+        }
+    }
+
+    pub fn world(&self) -> &Arc<Mutex<World>> {
+        &self.world
+    }
+
+    pub fn logger(&self) -> &Logger {
+        &self.logger
+    }
+
+    pub async fn update(&mut self, delta_time: &Duration) {
+        #[cfg(feature = "asset-loader")]
+        if let Some((asset_loader, asset_state)) = self.asset_loader.as_mut() {
+            asset_loader.update(
+                &mut world::AssetLoaderStateAndWorldLock::lock(&self.world, asset_state).await,
+                delta_time,
+            );
+        }
+
+        self.net_sync.update(
+            &mut WorldLockAndControllerState::lock(&self.world, &self.controllers).await,
+            delta_time,
+        );
+
+        let world = &mut *self.world.lock().await;
+        let mut system_changes = Vec::new();
+        let now = Instant::now();
+        for system in self.systems.iter_mut() {
+            system.update(world, delta_time, now, &mut system_changes);
+        }
+        log_system_changes(&self.logger, &system_changes);
+    }
+
+    pub async fn unload(mut self) {
+        let mut system_changes = Vec::new();
+        #[cfg(feature = "asset-loader")]
+        if let Some((asset_loader, asset_state)) = self.asset_loader.as_mut() {
+            asset_loader.unload(
+                &mut world::AssetLoaderStateAndWorldLock::lock(&self.world, asset_state).await,
+            );
+        }
+        {
+            let world = &mut *self.world.lock().await;
+            for system in self.systems.iter_mut().rev() {
+                system.unload(world, &mut system_changes);
+            }
+        }
+        log_system_changes(&self.logger, &system_changes);
+        self.net_sync
+            .unload(&mut WorldLockAndControllerState::lock(&self.world, &self.controllers).await);
+    }
+}
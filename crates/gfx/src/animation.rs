@@ -0,0 +1,230 @@
+//! Skinning and keyframe animation, as glTF describes them: the joints each
+//! vertex of a skinned mesh follows, the skins those joints make up, and
+//! clips of keyframes moving nodes such as joints.
+//!
+//! Clips only sample the translation, rotation or scale of a node; posing the
+//! nodes and working out the matrices a skinning shader needs is left to the
+//! world, which owns the nodes.
+
+use glam::{Mat4, Quat, Vec3};
+use interner::Symbol;
+
+/// The joints a vertex follows, and how much it follows each, for a skinning
+/// vertex shader. Kept beside the vertices of a mesh rather than in them, so
+/// meshes without a skin don't carry them. Weights add up to 1.
+#[derive(Debug, Default, Copy, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[repr(C)]
+pub struct SkinWeights {
+    /// Indices into the joints of the skin the mesh is drawn with.
+    pub joints: [u32; 4],
+    pub weights: [f32; 4],
+}
+
+/// The joints of a skin, as nodes of its glTF scene, and the inverse of each
+/// one's bind pose, taking the model space of the mesh to the joint's.
+#[derive(Debug, Clone)]
+pub struct Skin {
+    pub joints: Vec<usize>,
+    pub inverse_bind_matrices: Vec<Mat4>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Interpolation {
+    /// Hold each keyframe until the next.
+    Step,
+    Linear,
+}
+
+/// Values of the keyframes of a channel, one per time.
+#[derive(Debug, Clone)]
+pub enum Keyframes {
+    Translation(Vec<Vec3>),
+    Rotation(Vec<Quat>),
+    Scale(Vec<Vec3>),
+}
+
+/// A property of a node, as a channel has it at some time.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Sample {
+    Translation(Vec3),
+    Rotation(Quat),
+    Scale(Vec3),
+}
+
+impl Sample {
+    /// `transform` with this property replaced, and the others kept.
+    pub fn apply(self, transform: Mat4) -> Mat4 {
+        let (mut scale, mut rotation, mut translation) = transform.to_scale_rotation_translation();
+        match self {
+            Sample::Translation(sampled) => translation = sampled,
+            Sample::Rotation(sampled) => rotation = sampled,
+            Sample::Scale(sampled) => scale = sampled,
+        }
+        Mat4::from_scale_rotation_translation(scale, rotation, translation)
+    }
+}
+
+/// Keyframes of one property of one node.
+#[derive(Debug, Clone)]
+pub struct Channel {
+    /// Index of the node in the scene the clip was loaded from.
+    pub node: usize,
+    /// Name of the node, to play the clip on another scene with the same
+    /// skeleton.
+    pub node_name: Option<Symbol>,
+    pub interpolation: Interpolation,
+    /// Time of each keyframe in seconds, in increasing order.
+    pub times: Vec<f32>,
+    pub keyframes: Keyframes,
+}
+
+impl Channel {
+    /// The property at `time`, held at the first and last keyframes outside
+    /// of them. None without keyframes.
+    pub fn sample(&self, time: f32) -> Option<Sample> {
+        let last = self.times.len().checked_sub(1)?;
+        let next = self.times.partition_point(|&at| at <= time);
+        let (from, to, t) = if next == 0 {
+            (0, 0, 0.0)
+        } else if next > last {
+            (last, last, 0.0)
+        } else {
+            let (start, end) = (self.times[next - 1], self.times[next]);
+            let t = match self.interpolation {
+                Interpolation::Step => 0.0,
+                Interpolation::Linear => ((time - start) / (end - start)).clamp(0.0, 1.0),
+            };
+            (next - 1, next, t)
+        };
+        Some(match &self.keyframes {
+            Keyframes::Translation(values) => {
+                Sample::Translation(values.get(from)?.lerp(*values.get(to)?, t))
+            }
+            Keyframes::Rotation(values) => {
+                Sample::Rotation(values.get(from)?.slerp(*values.get(to)?, t).normalize())
+            }
+            Keyframes::Scale(values) => Sample::Scale(values.get(from)?.lerp(*values.get(to)?, t)),
+        })
+    }
+}
+
+/// Keyframes moving the nodes of a scene, such as the joints of a skin, see
+/// `GltfScene::animations` and `AnimationClip::load_gltf`.
+#[derive(Debug, Clone)]
+pub struct AnimationClip {
+    pub name: Option<Symbol>,
+    pub channels: Vec<Channel>,
+    /// Time of the last keyframe of any channel, in seconds.
+    pub duration: f32,
+}
+
+impl AnimationClip {
+    pub fn new(name: Option<Symbol>, channels: Vec<Channel>) -> Self {
+        let duration = channels
+            .iter()
+            .filter_map(|channel| channel.times.last().copied())
+            .fold(0.0, f32::max);
+        Self {
+            name,
+            channels,
+            duration,
+        }
+    }
+
+    /// Sample every channel at `time`, along with the channel.
+    pub fn sample(&self, time: f32) -> impl Iterator<Item = (&Channel, Sample)> + '_ {
+        self.channels
+            .iter()
+            .filter_map(move |channel| Some((channel, channel.sample(time)?)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::FRAC_PI_2;
+
+    use super::*;
+
+    fn channel(interpolation: Interpolation, keyframes: Keyframes) -> Channel {
+        Channel {
+            node: 0,
+            node_name: None,
+            interpolation,
+            times: vec![1.0, 2.0, 4.0],
+            keyframes,
+        }
+    }
+
+    #[test]
+    fn samples_between_keyframes() {
+        let translation = channel(
+            Interpolation::Linear,
+            Keyframes::Translation(vec![Vec3::ZERO, Vec3::X, Vec3::new(3.0, 0.0, 0.0)]),
+        );
+        assert_eq!(
+            translation.sample(0.0),
+            Some(Sample::Translation(Vec3::ZERO))
+        );
+        assert_eq!(
+            translation.sample(1.5),
+            Some(Sample::Translation(Vec3::X * 0.5))
+        );
+        assert_eq!(
+            translation.sample(3.0),
+            Some(Sample::Translation(Vec3::X * 2.0))
+        );
+        assert_eq!(
+            translation.sample(9.0),
+            Some(Sample::Translation(Vec3::X * 3.0))
+        );
+
+        let stepped = channel(
+            Interpolation::Step,
+            Keyframes::Scale(vec![Vec3::ONE, Vec3::splat(2.0), Vec3::splat(3.0)]),
+        );
+        assert_eq!(stepped.sample(1.9), Some(Sample::Scale(Vec3::ONE)));
+        assert_eq!(stepped.sample(2.0), Some(Sample::Scale(Vec3::splat(2.0))));
+
+        let rotation = channel(
+            Interpolation::Linear,
+            Keyframes::Rotation(vec![
+                Quat::IDENTITY,
+                Quat::from_rotation_y(FRAC_PI_2),
+                Quat::from_rotation_y(FRAC_PI_2),
+            ]),
+        );
+        match rotation.sample(1.5) {
+            Some(Sample::Rotation(rotation)) => {
+                assert!(rotation.abs_diff_eq(Quat::from_rotation_y(FRAC_PI_2 / 2.0), 1e-5))
+            }
+            other => panic!("sampled {other:?}"),
+        }
+    }
+
+    #[test]
+    fn samples_replace_one_property() {
+        let transform = Mat4::from_scale_rotation_translation(
+            Vec3::splat(2.0),
+            Quat::from_rotation_x(1.0),
+            Vec3::Y,
+        );
+        let moved = Sample::Translation(Vec3::Z).apply(transform);
+        let (scale, rotation, translation) = moved.to_scale_rotation_translation();
+        assert!(scale.abs_diff_eq(Vec3::splat(2.0), 1e-5));
+        assert!(rotation.abs_diff_eq(Quat::from_rotation_x(1.0), 1e-5));
+        assert!(translation.abs_diff_eq(Vec3::Z, 1e-5));
+    }
+
+    #[test]
+    fn clips_last_as_long_as_their_longest_channel() {
+        let mut short = channel(Interpolation::Step, Keyframes::Scale(vec![Vec3::ONE]));
+        short.times = vec![0.5];
+        let long = channel(
+            Interpolation::Linear,
+            Keyframes::Translation(vec![Vec3::ZERO; 3]),
+        );
+        let clip = AnimationClip::new(None, vec![short, long]);
+        assert_eq!(clip.duration, 4.0);
+        assert_eq!(clip.sample(1.0).count(), 2);
+    }
+}
@@ -1,10 +1,14 @@
+use std::collections::hash_map::DefaultHasher;
 use std::fmt::Debug;
+use std::hash::{Hash, Hasher};
 use std::path::{Path, PathBuf};
 
 use glam::Vec4;
 use image::GenericImageView;
 use obj_parser::model::{Interleaved, Mtl, MtlError, Obj, ObjError};
 
+use crate::animation::SkinWeights;
+
 #[derive(Debug, Clone)]
 pub struct Material {
     pub diffuse_map: Option<Image>,
@@ -52,14 +56,36 @@ pub enum LoadError {
         err: image::ImageError,
         path: PathBuf,
     },
+    #[error("unable to read {path:?} {err:?}")]
+    Io { err: std::io::Error, path: PathBuf },
+    #[error("gltf {path:?}: {reason}")]
+    Gltf { path: PathBuf, reason: String },
 }
 
 #[derive(Debug, Clone)]
 pub struct Model {
-    mesh: Mesh,
-    vertex_shader: PathBuf,
-    fragment_shader: PathBuf,
-    material: Material,
+    pub(crate) mesh: Mesh,
+    pub(crate) vertex_shader: PathBuf,
+    pub(crate) fragment_shader: PathBuf,
+    pub(crate) material: Material,
+    pub(crate) source: ModelSource,
+}
+
+/// The files a model was loaded from.
+#[derive(Debug, Clone)]
+pub(crate) enum ModelSource {
+    Obj {
+        obj_path: PathBuf,
+        material_path: PathBuf,
+    },
+    /// A primitive of a mesh of a glTF scene.
+    Gltf {
+        path: PathBuf,
+        /// Buffers and images referenced by the scene, if not embedded.
+        files: Vec<PathBuf>,
+        mesh: usize,
+        primitive: usize,
+    },
 }
 
 impl GpuNeeds for Model {
@@ -79,6 +105,10 @@ impl GpuNeeds for Model {
         self.mesh.indices.as_slice()
     }
 
+    fn skin_weights(&self) -> &[SkinWeights] {
+        self.mesh.skin.as_slice()
+    }
+
     fn diffuse_color(&self) -> Option<DiffuseColor<'_>> {
         self.material
             .diffuse_map
@@ -163,6 +193,12 @@ pub trait GpuNeeds {
     /// Return indices of this object.
     fn indices(&self) -> &[u32];
 
+    /// Return the joints and weights of each vertex, empty unless this object
+    /// is skinned.
+    fn skin_weights(&self) -> &[SkinWeights] {
+        &[]
+    }
+
     /// Return the diffuse color/map of this object.
     fn diffuse_color(&self) -> Option<DiffuseColor<'_>>;
 
@@ -178,8 +214,9 @@ pub trait GpuNeeds {
 /// Add new variants here.
 #[derive(Debug, Clone)]
 pub enum Graphic {
-    /// A model, with a texture and shaders.
-    Model(Model),
+    /// A model, with a texture and shaders. Boxed, as models are much larger
+    /// than the other graphics.
+    Model(Box<Model>),
 
     /// A debug mesh, color and primitive types.
     DebugMesh(DebugMesh),
@@ -191,7 +228,7 @@ pub enum Graphic {
 impl Graphic {
     /// Create a new graphic from a model.
     pub fn new_model(model: Model) -> Self {
-        Graphic::Model(model)
+        Graphic::Model(Box::new(model))
     }
 
     /// Create a new debug mesh with parts.
@@ -288,6 +325,14 @@ impl GpuNeeds for Graphic {
         }
     }
 
+    fn skin_weights(&self) -> &[SkinWeights] {
+        match self {
+            Graphic::Model(model) => model.skin_weights(),
+            Graphic::DebugMesh(mesh) => mesh.skin_weights(),
+            Graphic::ParticleSystem => todo!(),
+        }
+    }
+
     fn diffuse_color(&self) -> Option<DiffuseColor<'_>> {
         match self {
             Graphic::Model(model) => model.diffuse_color(),
@@ -297,6 +342,39 @@ impl GpuNeeds for Graphic {
     }
 }
 
+impl Graphic {
+    /// Hash of everything that ends up on the GPU for this graphic: vertices,
+    /// skin weights, indices, diffuse color or texture pixels, shader paths and
+    /// primitive. Graphics with the same hash can share a single upload.
+    pub fn content_hash(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        for vertex in self.vertices() {
+            for component in vertex.pos.iter().chain(&vertex.uv).chain(&vertex.normal) {
+                component.to_bits().hash(&mut hasher);
+            }
+        }
+        for skin in self.skin_weights() {
+            skin.joints.hash(&mut hasher);
+            skin.weights.map(f32::to_bits).hash(&mut hasher);
+        }
+        self.indices().hash(&mut hasher);
+        match self.diffuse_color() {
+            Some(DiffuseColor::Color(color)) => {
+                color.to_array().map(f32::to_bits).hash(&mut hasher)
+            }
+            Some(DiffuseColor::Texture(texture)) => {
+                texture.extent().hash(&mut hasher);
+                texture.image.as_bytes().hash(&mut hasher);
+            }
+            None => {}
+        }
+        self.vertex_shader_path().hash(&mut hasher);
+        self.fragment_shader_path().hash(&mut hasher);
+        (self.primitive() as u8).hash(&mut hasher);
+        hasher.finish()
+    }
+}
+
 impl Model {
     pub fn load_obj(
         filename: impl AsRef<Path>,
@@ -306,7 +384,7 @@ impl Model {
         let (mesh, obj) = Mesh::load(&filename)?;
         let obj = {
             obj.objects
-                .get(0)
+                .first()
                 .ok_or(LoadError::ObjHasMultipleModelsDefined)?
         };
 
@@ -341,8 +419,85 @@ impl Model {
             },
             vertex_shader: vertex_shader.as_ref().to_path_buf(),
             fragment_shader: fragment_shader.as_ref().to_path_buf(),
+            source: ModelSource::Obj {
+                obj_path: base_filename,
+                material_path,
+            },
         })
     }
+
+    /// Load this model again from the files it was originally loaded from.
+    pub fn reload(&self) -> Result<Self, LoadError> {
+        match &self.source {
+            ModelSource::Obj { obj_path, .. } => {
+                Model::load_obj(obj_path, &self.vertex_shader, &self.fragment_shader)
+            }
+            ModelSource::Gltf {
+                path,
+                mesh,
+                primitive,
+                ..
+            } => {
+                let mut scene = Model::load_gltf(path, &self.vertex_shader, &self.fragment_shader)?;
+                scene
+                    .meshes
+                    .get_mut(*mesh)
+                    .filter(|primitives| *primitive < primitives.len())
+                    .map(|primitives| primitives.swap_remove(*primitive))
+                    .ok_or_else(|| LoadError::Gltf {
+                        path: path.clone(),
+                        reason: format!("mesh {mesh} primitive {primitive} no longer exists"),
+                    })
+            }
+        }
+    }
+
+    /// Bytes of vertices, skin weights, indices and images the model holds,
+    /// roughly what it takes in memory and to upload.
+    pub fn memory_size(&self) -> usize {
+        let images = [
+            &self.material.diffuse_map,
+            &self.material.specular_map,
+            &self.material.bump_map,
+        ]
+        .into_iter()
+        .flatten()
+        .map(|image| image.image.as_bytes().len())
+        .sum::<usize>();
+        std::mem::size_of_val(self.mesh.vertices.as_slice())
+            + std::mem::size_of_val(self.mesh.skin.as_slice())
+            + std::mem::size_of_val(self.mesh.indices.as_slice())
+            + images
+    }
+
+    /// The files this model was loaded from: the obj, its material and any
+    /// images referenced by the material, or the glTF file and any buffers
+    /// and images it doesn't embed.
+    pub fn source_paths(&self) -> Vec<&Path> {
+        match &self.source {
+            ModelSource::Obj {
+                obj_path,
+                material_path,
+            } => {
+                let mut paths = vec![obj_path.as_path(), material_path.as_path()];
+                paths.extend(
+                    [
+                        &self.material.diffuse_map,
+                        &self.material.specular_map,
+                        &self.material.bump_map,
+                    ]
+                    .into_iter()
+                    .flatten()
+                    .map(|image| image.path.as_path()),
+                );
+                paths
+            }
+            ModelSource::Gltf { path, files, .. } => std::iter::once(path)
+                .chain(files)
+                .map(PathBuf::as_path)
+                .collect(),
+        }
+    }
 }
 
 fn load_image(stem: &str, base_path: &Path) -> Result<Image, LoadError> {
@@ -389,6 +544,9 @@ impl Vertex {
 pub struct Mesh {
     pub vertices: Vec<Vertex>,
     pub indices: Vec<u32>,
+    /// Joints and weights of each vertex of a skinned mesh, otherwise empty.
+    #[serde(default)]
+    pub skin: Vec<SkinWeights>,
 }
 
 impl Debug for Mesh {
@@ -396,13 +554,18 @@ impl Debug for Mesh {
         f.debug_struct("Mesh")
             .field("vertices", &self.vertices.len())
             .field("indices", &self.indices.len())
+            .field("skin", &self.skin.len())
             .finish()
     }
 }
 
 impl Mesh {
     pub fn new(vertices: Vec<Vertex>, indices: Vec<u32>) -> Self {
-        Mesh { vertices, indices }
+        Mesh {
+            vertices,
+            indices,
+            skin: Vec::new(),
+        }
     }
 
     /// Load a mesh from the given obj file at filename.
@@ -413,7 +576,7 @@ impl Mesh {
         let obj = Obj::load(&filename).map_err(LoadError::Obj)?;
         let object = {
             obj.objects
-                .get(0)
+                .first()
                 .ok_or(LoadError::ObjHasMultipleModelsDefined)?
         };
         let Interleaved { vertices, indices } = object.interleaved().map_err(LoadError::Obj)?;
@@ -427,3 +590,45 @@ impl Mesh {
         Ok((Self::new(verts, indices), obj))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn triangle(color: Vec4) -> Graphic {
+        Graphic::new_debug_mesh(
+            vec![
+                Vertex::pos(0.0, 0.0, 0.0),
+                Vertex::pos(1.0, 0.0, 0.0),
+                Vertex::pos(0.0, 1.0, 0.0),
+            ],
+            vec![0, 1, 2],
+            color,
+            Primitive::LineList,
+        )
+    }
+
+    #[test]
+    fn content_hash_matches_identical_graphics() {
+        let red = Vec4::new(1.0, 0.0, 0.0, 1.0);
+        assert_eq!(triangle(red).content_hash(), triangle(red).content_hash());
+        assert_ne!(
+            triangle(red).content_hash(),
+            triangle(Vec4::ONE).content_hash()
+        );
+
+        let mut moved = triangle(red);
+        if let Graphic::DebugMesh(mesh) = &mut moved {
+            mesh.vertices[1].pos[0] = 2.0;
+        }
+        assert_ne!(triangle(red).content_hash(), moved.content_hash());
+
+        let strip = match triangle(red) {
+            Graphic::DebugMesh(mesh) => {
+                Graphic::new_debug_mesh(mesh.vertices, mesh.indices, red, Primitive::LineStrip)
+            }
+            _ => unreachable!(),
+        };
+        assert_ne!(strip.content_hash(), triangle(red).content_hash());
+    }
+}
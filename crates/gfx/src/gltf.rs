@@ -0,0 +1,1264 @@
+//! glTF 2.0 scenes, from `.gltf` files with their buffers and images beside
+//! them or embedded as data URIs, and from binary `.glb` files.
+//!
+//! Each primitive of a mesh becomes a `Model`, textured with the base color
+//! of its material: its base color texture, or a single texel of its base
+//! color factor when it has none. Nodes keep their hierarchy and transforms,
+//! so the world can spawn them as children of one another.
+//!
+//! Skins keep the nodes that are their joints, and animations become
+//! `AnimationClip`s of the nodes they move. Cubic spline keyframes are
+//! interpolated linearly between their values, ignoring their tangents.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use glam::{Mat4, Quat, Vec3};
+use interner::Symbol;
+
+use crate::animation::{AnimationClip, Channel, Interpolation, Keyframes, Skin, SkinWeights};
+use crate::json::Json;
+use crate::{Image, LoadError, Material, Mesh, Model, ModelSource, Vertex};
+
+const GLB_MAGIC: &[u8; 4] = b"glTF";
+const GLB_JSON_CHUNK: &[u8; 4] = b"JSON";
+const GLB_BIN_CHUNK: &[u8; 4] = b"BIN\0";
+
+const TRIANGLES: usize = 4;
+
+/// A node of a glTF scene.
+#[derive(Debug, Clone)]
+pub struct GltfNode {
+    pub name: Option<Symbol>,
+    /// Transform relative to the parent node.
+    pub transform: Mat4,
+    /// Index of the mesh drawn at this node in `GltfScene::meshes`.
+    pub mesh: Option<usize>,
+    /// Index of the skin deforming the mesh in `GltfScene::skins`.
+    pub skin: Option<usize>,
+    /// Indices of the child nodes in `GltfScene::nodes`.
+    pub children: Vec<usize>,
+}
+
+/// The meshes and node hierarchy of a glTF file, see `Model::load_gltf`.
+#[derive(Debug, Clone)]
+pub struct GltfScene {
+    pub path: PathBuf,
+    /// A model for each primitive of each mesh.
+    pub meshes: Vec<Vec<Model>>,
+    pub nodes: Vec<GltfNode>,
+    /// Nodes at the top of the hierarchy of the file's default scene.
+    pub roots: Vec<usize>,
+    pub skins: Vec<Skin>,
+    pub animations: Vec<AnimationClip>,
+}
+
+impl Model {
+    /// Load the default scene of a `.gltf` or `.glb` file, drawing every
+    /// primitive with the given shaders.
+    pub fn load_gltf(
+        filename: impl AsRef<Path>,
+        vertex_shader: impl AsRef<Path>,
+        fragment_shader: impl AsRef<Path>,
+    ) -> Result<GltfScene, LoadError> {
+        let path = filename.as_ref();
+        let bytes = fs::read(path).map_err(|err| LoadError::Io {
+            err,
+            path: path.to_path_buf(),
+        })?;
+        GltfScene::parse(
+            path,
+            &bytes,
+            vertex_shader.as_ref(),
+            fragment_shader.as_ref(),
+        )
+    }
+}
+
+impl GltfScene {
+    fn parse(
+        path: &Path,
+        bytes: &[u8],
+        vertex_shader: &Path,
+        fragment_shader: &Path,
+    ) -> Result<Self, LoadError> {
+        let error = |reason: String| LoadError::Gltf {
+            path: path.to_path_buf(),
+            reason,
+        };
+        let mut document = Document::parse(path, bytes).map_err(error)?;
+        let materials = (0..document
+            .json
+            .get("materials")
+            .map_or(0, |m| m.elements().len()))
+            .map(|index| document.material(Some(index)))
+            .collect::<Result<Vec<_>, _>>()?;
+        let default_material = document.material(None)?;
+
+        let mut meshes = Vec::new();
+        for (mesh_index, mesh) in document.elements("meshes").iter().enumerate() {
+            let mut models = Vec::new();
+            for (primitive_index, primitive) in mesh
+                .get("primitives")
+                .map_or(&[][..], Json::elements)
+                .iter()
+                .enumerate()
+            {
+                let mesh = document
+                    .primitive_mesh(primitive)
+                    .map_err(|reason| error(format!("mesh {mesh_index}: {reason}")))?;
+                if mesh.vertices.is_empty() {
+                    return Err(LoadError::ModelHasNoVerts);
+                }
+                let material = match primitive.get("material").and_then(Json::as_usize) {
+                    Some(index) => materials
+                        .get(index)
+                        .cloned()
+                        .ok_or_else(|| error(format!("mesh {mesh_index}: no material {index}")))?,
+                    None => default_material.clone(),
+                };
+                models.push(Model {
+                    mesh,
+                    vertex_shader: vertex_shader.to_path_buf(),
+                    fragment_shader: fragment_shader.to_path_buf(),
+                    material,
+                    source: ModelSource::Gltf {
+                        path: path.to_path_buf(),
+                        files: document.files.clone(),
+                        mesh: mesh_index,
+                        primitive: primitive_index,
+                    },
+                });
+            }
+            meshes.push(models);
+        }
+
+        let skins = document.skins().map_err(error)?;
+        let nodes = document.nodes(meshes.len(), skins.len()).map_err(error)?;
+        let roots = document.roots(&nodes).map_err(error)?;
+        let animations = document.animations(&nodes).map_err(error)?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            meshes,
+            nodes,
+            roots,
+            skins,
+            animations,
+        })
+    }
+}
+
+impl AnimationClip {
+    /// Load the animations of a `.gltf` or `.glb` file without its meshes,
+    /// such as a file of clips shared by models with the same skeleton.
+    pub fn load_gltf(filename: impl AsRef<Path>) -> Result<Vec<Self>, LoadError> {
+        let path = filename.as_ref();
+        let bytes = fs::read(path).map_err(|err| LoadError::Io {
+            err,
+            path: path.to_path_buf(),
+        })?;
+        let error = |reason: String| LoadError::Gltf {
+            path: path.to_path_buf(),
+            reason,
+        };
+        let document = Document::parse(path, &bytes).map_err(error)?;
+        let skins = document.elements("skins").len();
+        let meshes = document.elements("meshes").len();
+        let nodes = document.nodes(meshes, skins).map_err(error)?;
+        document.animations(&nodes).map_err(error)
+    }
+}
+
+/// A glTF document and the buffers it references.
+struct Document<'a> {
+    path: &'a Path,
+    json: Json,
+    buffers: Vec<Vec<u8>>,
+    /// Files besides the document that it was loaded from.
+    files: Vec<PathBuf>,
+}
+
+/// The values of an accessor, flattened, and how many make up an element.
+struct Values {
+    values: Vec<f64>,
+    components: usize,
+}
+
+impl<'a> Document<'a> {
+    fn parse(path: &'a Path, bytes: &[u8]) -> Result<Self, String> {
+        let (text, bin) = if bytes.starts_with(GLB_MAGIC) {
+            split_glb(bytes)?
+        } else {
+            (bytes, None)
+        };
+        let text = std::str::from_utf8(text).map_err(|_| "document isn't utf-8".to_string())?;
+        let json = Json::parse(text)?;
+        let version = json
+            .get("asset")
+            .and_then(|asset| asset.get("version"))
+            .and_then(Json::as_str)
+            .unwrap_or_default();
+        if !version.starts_with("2.") {
+            return Err(format!("version {version:?} isn't supported, only 2.x"));
+        }
+
+        let mut document = Self {
+            path,
+            json,
+            buffers: Vec::new(),
+            files: Vec::new(),
+        };
+        let mut bin = bin;
+        let uris: Vec<Option<String>> = document
+            .elements("buffers")
+            .iter()
+            .map(|buffer| buffer.get("uri").and_then(Json::as_str).map(str::to_string))
+            .collect();
+        for (index, uri) in uris.into_iter().enumerate() {
+            let data = match uri {
+                Some(uri) => match Uri::resolve(path, &uri)? {
+                    Uri::Data(data) => data,
+                    Uri::File(path) => document.read_file(path)?,
+                },
+                // Only the first buffer of a glb may be its binary chunk.
+                None => bin
+                    .take()
+                    .filter(|_| index == 0)
+                    .ok_or_else(|| format!("buffer {index} has no data"))?
+                    .to_vec(),
+            };
+            document.buffers.push(data);
+        }
+        Ok(document)
+    }
+
+    fn elements(&self, key: &str) -> &[Json] {
+        self.json.get(key).map_or(&[], Json::elements)
+    }
+
+    fn element(&self, key: &str, index: usize) -> Result<&Json, String> {
+        self.elements(key)
+            .get(index)
+            .ok_or_else(|| format!("no {key} {index}"))
+    }
+
+    /// Read a file beside the document, remembering it as a source.
+    fn read_file(&mut self, path: PathBuf) -> Result<Vec<u8>, String> {
+        let data = fs::read(&path).map_err(|err| format!("unable to read {path:?} {err}"))?;
+        if !self.files.contains(&path) {
+            self.files.push(path);
+        }
+        Ok(data)
+    }
+
+    /// The bytes of a buffer view, and the stride between its elements if it
+    /// has one.
+    fn buffer_view(&self, index: usize) -> Result<(&[u8], Option<usize>), String> {
+        let view = self.element("bufferViews", index)?;
+        let buffer = view
+            .get("buffer")
+            .and_then(Json::as_usize)
+            .and_then(|buffer| self.buffers.get(buffer))
+            .ok_or_else(|| format!("buffer view {index} has no buffer"))?;
+        let offset = view.get("byteOffset").and_then(Json::as_usize).unwrap_or(0);
+        let length = view.get("byteLength").and_then(Json::as_usize).unwrap_or(0);
+        let bytes = offset
+            .checked_add(length)
+            .and_then(|end| buffer.get(offset..end))
+            .ok_or_else(|| format!("buffer view {index} is out of bounds"))?;
+        let stride = view.get("byteStride").and_then(Json::as_usize);
+        Ok((bytes, stride))
+    }
+
+    fn accessor(&self, index: usize) -> Result<Values, String> {
+        let accessor = self.element("accessors", index)?;
+        if accessor.get("sparse").is_some() {
+            return Err(format!("accessor {index} is sparse, which isn't supported"));
+        }
+        let components = match accessor.get("type").and_then(Json::as_str) {
+            Some("SCALAR") => 1,
+            Some("VEC2") => 2,
+            Some("VEC3") => 3,
+            Some("VEC4") => 4,
+            Some("MAT4") => 16,
+            other => return Err(format!("accessor {index} has unsupported type {other:?}")),
+        };
+        let component_type = accessor.get("componentType").and_then(Json::as_usize);
+        let size = match component_type {
+            Some(5120 | 5121) => 1,
+            Some(5122 | 5123) => 2,
+            Some(5125 | 5126) => 4,
+            other => return Err(format!("accessor {index} has component type {other:?}")),
+        };
+        let count = accessor.get("count").and_then(Json::as_usize).unwrap_or(0);
+        let normalized = matches!(accessor.get("normalized"), Some(Json::Bool(true)));
+        let element_len = size * components;
+
+        let view = match accessor.get("bufferView").and_then(Json::as_usize) {
+            Some(view) => view,
+            // Accessors without a view are all zeros. They're held to the size
+            // of the document's buffers, so a bogus count allocates no more
+            // than an accessor with a view could.
+            None => {
+                let data_len: usize = self.buffers.iter().map(Vec::len).sum();
+                if count
+                    .checked_mul(element_len)
+                    .filter(|&len| len <= data_len)
+                    .is_none()
+                {
+                    return Err(format!("accessor {index} is larger than the buffers"));
+                }
+                return Ok(Values {
+                    values: vec![0.0; count * components],
+                    components,
+                });
+            }
+        };
+        let (bytes, stride) = self.buffer_view(view)?;
+        let offset = accessor
+            .get("byteOffset")
+            .and_then(Json::as_usize)
+            .unwrap_or(0);
+        let stride = stride.unwrap_or(element_len);
+        // The count is checked against the view before anything is allocated
+        // for it, so a bogus one can't ask for more than the file holds.
+        let end = match count.checked_sub(1) {
+            Some(last) => last
+                .checked_mul(stride)
+                .and_then(|at| at.checked_add(offset))
+                .and_then(|at| at.checked_add(element_len)),
+            None => Some(offset),
+        };
+        if end.filter(|&end| end <= bytes.len()).is_none() {
+            return Err(format!("accessor {index} is out of bounds"));
+        }
+        let mut values = Vec::with_capacity(count * components);
+        for element in 0..count {
+            for component in 0..components {
+                let at = offset + element * stride + component * size;
+                let bytes = bytes
+                    .get(at..at + size)
+                    .ok_or_else(|| format!("accessor {index} is out of bounds"))?;
+                let value = match (component_type, bytes) {
+                    (Some(5120), &[b]) => (b as i8 as f64, 127.0),
+                    (Some(5121), &[b]) => (b as f64, 255.0),
+                    (Some(5122), &[a, b]) => (i16::from_le_bytes([a, b]) as f64, 32767.0),
+                    (Some(5123), &[a, b]) => (u16::from_le_bytes([a, b]) as f64, 65535.0),
+                    (Some(5125), &[a, b, c, d]) => (u32::from_le_bytes([a, b, c, d]) as f64, 1.0),
+                    (_, &[a, b, c, d]) => (f32::from_le_bytes([a, b, c, d]) as f64, 1.0),
+                    _ => unreachable!("component sizes match their types"),
+                };
+                values.push(if normalized {
+                    (value.0 / value.1).max(-1.0)
+                } else {
+                    value.0
+                });
+            }
+        }
+        Ok(Values { values, components })
+    }
+
+    /// Values of the attribute `name` of a primitive, None if it has none.
+    fn attribute(
+        &self,
+        primitive: &Json,
+        name: &str,
+        components: usize,
+    ) -> Result<Option<Vec<f64>>, String> {
+        let index = match primitive
+            .get("attributes")
+            .and_then(|attributes| attributes.get(name))
+            .and_then(Json::as_usize)
+        {
+            Some(index) => index,
+            None => return Ok(None),
+        };
+        let values = self.accessor(index)?;
+        if values.components != components {
+            return Err(format!("{name} has {} components", values.components));
+        }
+        Ok(Some(values.values))
+    }
+
+    fn primitive_mesh(&self, primitive: &Json) -> Result<Mesh, String> {
+        let mode = primitive
+            .get("mode")
+            .and_then(Json::as_usize)
+            .unwrap_or(TRIANGLES);
+        if mode != TRIANGLES {
+            return Err(format!(
+                "primitive mode {mode} isn't supported, only triangles"
+            ));
+        }
+        let positions = self
+            .attribute(primitive, "POSITION", 3)?
+            .ok_or_else(|| "primitive has no positions".to_string())?;
+        let count = positions.len() / 3;
+        let indices = match primitive.get("indices").and_then(Json::as_usize) {
+            Some(index) => self
+                .accessor(index)?
+                .values
+                .into_iter()
+                .map(|index| index as u32)
+                .collect(),
+            None => (0..count as u32).collect::<Vec<_>>(),
+        };
+        if let Some(index) = indices.iter().find(|&&index| index as usize >= count) {
+            return Err(format!("index {index} past the {count} vertices"));
+        }
+        let normals = match self.attribute(primitive, "NORMAL", 3)? {
+            Some(normals) => normals,
+            None => smooth_normals(&positions, &indices),
+        };
+        let uvs = self
+            .attribute(primitive, "TEXCOORD_0", 2)?
+            .unwrap_or_else(|| vec![0.0; count * 2]);
+        if normals.len() / 3 != count || uvs.len() / 2 != count {
+            return Err("attributes have different counts".to_string());
+        }
+
+        let skin = self.skin_weights(primitive, count)?;
+
+        let vertices = (0..count)
+            .map(|i| Vertex {
+                pos: [
+                    positions[i * 3] as f32,
+                    positions[i * 3 + 1] as f32,
+                    positions[i * 3 + 2] as f32,
+                    1.0,
+                ],
+                uv: [uvs[i * 2] as f32, uvs[i * 2 + 1] as f32],
+                normal: [
+                    normals[i * 3] as f32,
+                    normals[i * 3 + 1] as f32,
+                    normals[i * 3 + 2] as f32,
+                ],
+            })
+            .collect();
+        Ok(Mesh {
+            vertices,
+            indices,
+            skin,
+        })
+    }
+
+    /// The first set of joints and weights of each vertex of a primitive,
+    /// with weights scaled to add up to 1. Empty if it has none.
+    fn skin_weights(&self, primitive: &Json, count: usize) -> Result<Vec<SkinWeights>, String> {
+        let (joints, weights) = match (
+            self.attribute(primitive, "JOINTS_0", 4)?,
+            self.attribute(primitive, "WEIGHTS_0", 4)?,
+        ) {
+            (Some(joints), Some(weights)) => (joints, weights),
+            (None, None) => return Ok(Vec::new()),
+            _ => return Err("primitive has joints or weights without the other".to_string()),
+        };
+        if joints.len() / 4 != count || weights.len() / 4 != count {
+            return Err("attributes have different counts".to_string());
+        }
+        Ok(joints
+            .chunks_exact(4)
+            .zip(weights.chunks_exact(4))
+            .map(|(joints, weights)| {
+                let total = weights.iter().sum::<f64>();
+                let scale = if total > 0.0 { 1.0 / total } else { 0.0 };
+                SkinWeights {
+                    joints: [0, 1, 2, 3].map(|i| joints[i] as u32),
+                    weights: [0, 1, 2, 3].map(|i| (weights[i] * scale) as f32),
+                }
+            })
+            .collect())
+    }
+
+    /// The material at `index`, or glTF's default material.
+    fn material(&mut self, index: Option<usize>) -> Result<Material, LoadError> {
+        let path = self.path;
+        let error = |reason: String| LoadError::Gltf {
+            path: path.to_path_buf(),
+            reason,
+        };
+        let pbr = match index {
+            Some(index) => self
+                .element("materials", index)
+                .map_err(error)?
+                .get("pbrMetallicRoughness"),
+            None => None,
+        };
+        let texture = pbr
+            .and_then(|pbr| pbr.get("baseColorTexture"))
+            .and_then(|texture| texture.get("index"))
+            .and_then(Json::as_usize);
+        let diffuse_map = match texture {
+            Some(texture) => self.texture_image(texture)?,
+            None => {
+                let factor = pbr
+                    .and_then(|pbr| pbr.get("baseColorFactor"))
+                    .map_or(&[][..], Json::elements);
+                let mut rgba = [255u8; 4];
+                for (channel, value) in rgba.iter_mut().zip(factor) {
+                    *channel = (value.as_f64().unwrap_or(1.0).clamp(0.0, 1.0) * 255.0) as u8;
+                }
+                let name = match index {
+                    Some(index) => format!("material{index}"),
+                    None => "default_material".to_string(),
+                };
+                Image {
+                    path: self.embedded_path(&name),
+                    image: image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(
+                        1,
+                        1,
+                        image::Rgba(rgba),
+                    )),
+                }
+            }
+        };
+        Ok(Material {
+            diffuse_map: Some(diffuse_map),
+            specular_map: None,
+            bump_map: None,
+        })
+    }
+
+    /// The image of a texture, decoded from a buffer view or a URI.
+    fn texture_image(&mut self, texture: usize) -> Result<Image, LoadError> {
+        let path = self.path;
+        let error = |reason: String| LoadError::Gltf {
+            path: path.to_path_buf(),
+            reason,
+        };
+        let source = self
+            .element("textures", texture)
+            .map_err(error)?
+            .get("source")
+            .and_then(Json::as_usize)
+            .ok_or_else(|| error(format!("texture {texture} has no source")))?;
+        let image = self.element("images", source).map_err(error)?;
+        let bytes = match (
+            image.get("bufferView").and_then(Json::as_usize),
+            image.get("uri").and_then(Json::as_str),
+        ) {
+            (Some(view), _) => self.buffer_view(view).map_err(error)?.0.to_vec(),
+            (None, Some(uri)) => match Uri::resolve(path, uri).map_err(error)? {
+                Uri::Data(data) => data,
+                Uri::File(file) => {
+                    let image = image::open(&file).map_err(|err| LoadError::UnableToLoadImage {
+                        err,
+                        path: file.clone(),
+                    })?;
+                    if !self.files.contains(&file) {
+                        self.files.push(file.clone());
+                    }
+                    return Ok(Image { path: file, image });
+                }
+            },
+            (None, None) => return Err(error(format!("image {source} has no data"))),
+        };
+        let path = self.embedded_path(&format!("image{source}"));
+        let image =
+            image::load_from_memory(&bytes).map_err(|err| LoadError::UnableToLoadImage {
+                err,
+                path: path.clone(),
+            })?;
+        Ok(Image { path, image })
+    }
+
+    /// A name for data embedded in the document, which isn't a file.
+    fn embedded_path(&self, name: &str) -> PathBuf {
+        let mut path = self.path.as_os_str().to_owned();
+        path.push(format!("#{name}"));
+        PathBuf::from(path)
+    }
+
+    fn nodes(&self, meshes: usize, skins: usize) -> Result<Vec<GltfNode>, String> {
+        let nodes = self.elements("nodes");
+        nodes
+            .iter()
+            .enumerate()
+            .map(|(index, node)| {
+                let mesh = node.get("mesh").and_then(Json::as_usize);
+                if let Some(mesh) = mesh.filter(|&mesh| mesh >= meshes) {
+                    return Err(format!("node {index} has no mesh {mesh}"));
+                }
+                let skin = node.get("skin").and_then(Json::as_usize);
+                if let Some(skin) = skin.filter(|&skin| skin >= skins) {
+                    return Err(format!("node {index} has no skin {skin}"));
+                }
+                let children: Vec<usize> = node
+                    .get("children")
+                    .map_or(&[][..], Json::elements)
+                    .iter()
+                    .filter_map(Json::as_usize)
+                    .collect();
+                if let Some(child) = children.iter().find(|&&child| child >= nodes.len()) {
+                    return Err(format!("node {index} has no child {child}"));
+                }
+                Ok(GltfNode {
+                    name: node.get("name").and_then(Json::as_str).map(Symbol::intern),
+                    transform: node_transform(node),
+                    mesh,
+                    skin,
+                    children,
+                })
+            })
+            .collect()
+    }
+
+    fn skins(&self) -> Result<Vec<Skin>, String> {
+        let nodes = self.elements("nodes").len();
+        self.elements("skins")
+            .iter()
+            .enumerate()
+            .map(|(index, skin)| {
+                let joints: Vec<usize> = skin
+                    .get("joints")
+                    .map_or(&[][..], Json::elements)
+                    .iter()
+                    .filter_map(Json::as_usize)
+                    .collect();
+                if let Some(joint) = joints.iter().find(|&&joint| joint >= nodes) {
+                    return Err(format!("skin {index} has no joint node {joint}"));
+                }
+                // Without inverse bind matrices, joints are bound where the
+                // mesh is.
+                let inverse_bind_matrices = match skin
+                    .get("inverseBindMatrices")
+                    .and_then(Json::as_usize)
+                {
+                    Some(accessor) => {
+                        let values = self.accessor(accessor)?;
+                        if values.components != 16 {
+                            return Err(format!("skin {index} inverse bind matrices aren't MAT4"));
+                        }
+                        values
+                            .values
+                            .chunks_exact(16)
+                            .map(|matrix| Mat4::from_cols_array(&matrix_array(matrix)))
+                            .collect()
+                    }
+                    None => vec![Mat4::IDENTITY; joints.len()],
+                };
+                if inverse_bind_matrices.len() != joints.len() {
+                    return Err(format!(
+                        "skin {index} has {} joints but {} inverse bind matrices",
+                        joints.len(),
+                        inverse_bind_matrices.len()
+                    ));
+                }
+                Ok(Skin {
+                    joints,
+                    inverse_bind_matrices,
+                })
+            })
+            .collect()
+    }
+
+    /// Animations of node translations, rotations and scales. Channels of
+    /// morph target weights, which aren't supported, are left out.
+    fn animations(&self, nodes: &[GltfNode]) -> Result<Vec<AnimationClip>, String> {
+        let mut clips = Vec::new();
+        for (index, animation) in self.elements("animations").iter().enumerate() {
+            let error = |reason: String| format!("animation {index}: {reason}");
+            let samplers = animation.get("samplers").map_or(&[][..], Json::elements);
+            let mut channels = Vec::new();
+            for channel in animation.get("channels").map_or(&[][..], Json::elements) {
+                let target = channel.get("target");
+                let node = match target.and_then(|target| target.get("node")) {
+                    Some(node) => node
+                        .as_usize()
+                        .filter(|&node| node < nodes.len())
+                        .ok_or_else(|| error("channel targets no node".to_string()))?,
+                    // Targets other than nodes come from extensions.
+                    None => continue,
+                };
+                let path = target
+                    .and_then(|target| target.get("path"))
+                    .and_then(Json::as_str);
+                let sampler = channel
+                    .get("sampler")
+                    .and_then(Json::as_usize)
+                    .and_then(|sampler| samplers.get(sampler))
+                    .ok_or_else(|| error("channel has no sampler".to_string()))?;
+                let interpolation = match sampler.get("interpolation").and_then(Json::as_str) {
+                    Some("STEP") => Interpolation::Step,
+                    _ => Interpolation::Linear,
+                };
+                let times = match sampler.get("input").and_then(Json::as_usize) {
+                    Some(input) => self.accessor(input).map_err(error)?.values,
+                    None => return Err(error("sampler has no input".to_string())),
+                };
+                let output = match sampler.get("output").and_then(Json::as_usize) {
+                    Some(output) => self.accessor(output).map_err(error)?,
+                    None => return Err(error("sampler has no output".to_string())),
+                };
+                let components = match path {
+                    Some("translation" | "scale") => 3,
+                    Some("rotation") => 4,
+                    _ => continue,
+                };
+                if output.components != components {
+                    return Err(error(format!(
+                        "{path:?} has {} components",
+                        output.components
+                    )));
+                }
+                let mut values: Vec<&[f64]> = output.values.chunks_exact(components).collect();
+                // Cubic splines keep an in tangent, value and out tangent per
+                // keyframe, only values are kept.
+                if sampler.get("interpolation").and_then(Json::as_str) == Some("CUBICSPLINE") {
+                    values = values.into_iter().skip(1).step_by(3).collect();
+                }
+                if values.len() != times.len() {
+                    return Err(error(format!(
+                        "{} keyframe times but {} values",
+                        times.len(),
+                        values.len()
+                    )));
+                }
+                let vec3 =
+                    |value: &[f64]| Vec3::new(value[0] as f32, value[1] as f32, value[2] as f32);
+                let keyframes = match path {
+                    Some("translation") => {
+                        Keyframes::Translation(values.into_iter().map(vec3).collect())
+                    }
+                    Some("scale") => Keyframes::Scale(values.into_iter().map(vec3).collect()),
+                    _ => Keyframes::Rotation(
+                        values
+                            .into_iter()
+                            .map(|value| {
+                                Quat::from_xyzw(
+                                    value[0] as f32,
+                                    value[1] as f32,
+                                    value[2] as f32,
+                                    value[3] as f32,
+                                )
+                                .normalize()
+                            })
+                            .collect(),
+                    ),
+                };
+                channels.push(Channel {
+                    node,
+                    node_name: nodes[node].name,
+                    interpolation,
+                    times: times.into_iter().map(|time| time as f32).collect(),
+                    keyframes,
+                });
+            }
+            let name = animation
+                .get("name")
+                .and_then(Json::as_str)
+                .map(Symbol::intern);
+            clips.push(AnimationClip::new(name, channels));
+        }
+        Ok(clips)
+    }
+
+    /// The root nodes of the default scene, or every node without a parent
+    /// if there are no scenes. Fails if a node has more than one parent.
+    fn roots(&self, nodes: &[GltfNode]) -> Result<Vec<usize>, String> {
+        let mut parents = vec![None; nodes.len()];
+        for (index, node) in nodes.iter().enumerate() {
+            for &child in &node.children {
+                if parents[child].replace(index).is_some() || child == index {
+                    return Err(format!("node {child} has more than one parent"));
+                }
+            }
+        }
+        let scene = self.json.get("scene").and_then(Json::as_usize).unwrap_or(0);
+        let roots: Vec<usize> = match self.elements("scenes").get(scene) {
+            Some(scene) => scene
+                .get("nodes")
+                .map_or(&[][..], Json::elements)
+                .iter()
+                .filter_map(Json::as_usize)
+                .collect(),
+            None => (0..nodes.len())
+                .filter(|&node| parents[node].is_none())
+                .collect(),
+        };
+        match roots
+            .iter()
+            .find(|&&root| root >= nodes.len() || parents[root].is_some())
+        {
+            Some(root) => Err(format!("scene root {root} isn't a root node")),
+            None => Ok(roots),
+        }
+    }
+}
+
+/// Where a URI's data is: in the URI itself, or in a file beside the
+/// document.
+enum Uri {
+    Data(Vec<u8>),
+    File(PathBuf),
+}
+
+impl Uri {
+    fn resolve(document: &Path, uri: &str) -> Result<Self, String> {
+        match uri.strip_prefix("data:") {
+            Some(data) => {
+                let (_media_type, encoded) = data
+                    .split_once(";base64,")
+                    .ok_or_else(|| "data URIs must be base64".to_string())?;
+                base64_decode(encoded)
+                    .map(Uri::Data)
+                    .ok_or_else(|| "invalid base64 in data URI".to_string())
+            }
+            None => Ok(Uri::File(
+                document
+                    .parent()
+                    .unwrap_or(Path::new(""))
+                    .join(uri.replace("%20", " ")),
+            )),
+        }
+    }
+}
+
+/// The JSON and binary chunks of a glb.
+fn split_glb(bytes: &[u8]) -> Result<(&[u8], Option<&[u8]>), String> {
+    let u32_at = |at: usize| {
+        bytes
+            .get(at..at + 4)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as usize)
+    };
+    if u32_at(4) != Some(2) {
+        return Err("glb version isn't 2".to_string());
+    }
+    let length = u32_at(8).unwrap_or(0).min(bytes.len());
+    let mut chunks = Vec::new();
+    let mut at = 12;
+    while at + 8 <= length {
+        let chunk_length = u32_at(at).unwrap_or(0);
+        let kind = &bytes[at + 4..at + 8];
+        let data = bytes
+            .get(at + 8..at + 8 + chunk_length)
+            .ok_or_else(|| "glb chunk is out of bounds".to_string())?;
+        chunks.push((kind, data));
+        at += 8 + chunk_length;
+    }
+    let json = match chunks.first() {
+        Some((kind, json)) if kind == GLB_JSON_CHUNK => *json,
+        _ => return Err("glb doesn't start with a JSON chunk".to_string()),
+    };
+    let bin = chunks
+        .get(1)
+        .filter(|(kind, _)| kind == GLB_BIN_CHUNK)
+        .map(|(_, data)| *data);
+    Ok((json, bin))
+}
+
+fn node_transform(node: &Json) -> Mat4 {
+    let floats = |key: &str| -> Vec<f32> {
+        node.get(key)
+            .map_or(&[][..], Json::elements)
+            .iter()
+            .map(|value| value.as_f64().unwrap_or(0.0) as f32)
+            .collect()
+    };
+    if let Ok(matrix) = <[f32; 16]>::try_from(floats("matrix")) {
+        return Mat4::from_cols_array(&matrix);
+    }
+    let translation = <[f32; 3]>::try_from(floats("translation")).map_or(Vec3::ZERO, Vec3::from);
+    let rotation = <[f32; 4]>::try_from(floats("rotation")).map_or(Quat::IDENTITY, |rotation| {
+        Quat::from_array(rotation).normalize()
+    });
+    let scale = <[f32; 3]>::try_from(floats("scale")).map_or(Vec3::ONE, Vec3::from);
+    Mat4::from_scale_rotation_translation(scale, rotation, translation)
+}
+
+fn matrix_array(values: &[f64]) -> [f32; 16] {
+    let mut matrix = [0.0; 16];
+    for (to, from) in matrix.iter_mut().zip(values) {
+        *to = *from as f32;
+    }
+    matrix
+}
+
+/// Normals of each vertex, averaged from the triangles it's part of.
+fn smooth_normals(positions: &[f64], indices: &[u32]) -> Vec<f64> {
+    let position = |index: u32| {
+        let at = index as usize * 3;
+        Vec3::new(
+            positions[at] as f32,
+            positions[at + 1] as f32,
+            positions[at + 2] as f32,
+        )
+    };
+    let mut normals = vec![Vec3::ZERO; positions.len() / 3];
+    for triangle in indices.chunks_exact(3) {
+        let [a, b, c] = [triangle[0], triangle[1], triangle[2]];
+        let normal = (position(b) - position(a)).cross(position(c) - position(a));
+        for index in [a, b, c] {
+            normals[index as usize] += normal;
+        }
+    }
+    normals
+        .into_iter()
+        .flat_map(|normal| normal.normalize_or_zero().to_array())
+        .map(f64::from)
+        .collect()
+}
+
+fn base64_decode(encoded: &str) -> Option<Vec<u8>> {
+    let mut decoded = Vec::with_capacity(encoded.len() * 3 / 4);
+    let mut bits = 0u32;
+    let mut count = 0;
+    for byte in encoded.bytes().filter(|byte| !byte.is_ascii_whitespace()) {
+        let value = match byte {
+            b'A'..=b'Z' => byte - b'A',
+            b'a'..=b'z' => byte - b'a' + 26,
+            b'0'..=b'9' => byte - b'0' + 52,
+            b'+' | b'-' => 62,
+            b'/' | b'_' => 63,
+            b'=' => break,
+            _ => return None,
+        };
+        bits = bits << 6 | u32::from(value);
+        count += 6;
+        if count >= 8 {
+            count -= 8;
+            decoded.push((bits >> count) as u8);
+        }
+    }
+    Some(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::FRAC_PI_2;
+
+    use super::*;
+    use crate::{GpuNeeds, Sample};
+
+    fn f32_bytes(values: &[f32]) -> Vec<u8> {
+        values
+            .iter()
+            .flat_map(|value| value.to_le_bytes())
+            .collect()
+    }
+
+    /// A glb of a quad of two triangles, drawn by a child of a moved parent.
+    fn quad_glb() -> Vec<u8> {
+        let mut bin = f32_bytes(&[0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 1.0, 1.0, 0.0, 0.0, 1.0, 0.0]);
+        bin.extend([0u16, 1, 2, 0, 2, 3].iter().flat_map(|i| i.to_le_bytes()));
+        let json = format!(
+            r#"{{
+                "asset": {{"version": "2.0"}},
+                "scene": 0,
+                "scenes": [{{"nodes": [0]}}],
+                "nodes": [
+                    {{"name": "parent", "translation": [0, 5, 0], "children": [1]}},
+                    {{"name": "quad", "mesh": 0, "scale": [2, 2, 2]}}
+                ],
+                "meshes": [{{"primitives": [
+                    {{"attributes": {{"POSITION": 0}}, "indices": 1, "material": 0}}
+                ]}}],
+                "materials": [{{"pbrMetallicRoughness": {{"baseColorFactor": [1, 0, 0, 1]}}}}],
+                "buffers": [{{"byteLength": {}}}],
+                "bufferViews": [
+                    {{"buffer": 0, "byteLength": 48}},
+                    {{"buffer": 0, "byteOffset": 48, "byteLength": 12}}
+                ],
+                "accessors": [
+                    {{"bufferView": 0, "componentType": 5126, "count": 4, "type": "VEC3"}},
+                    {{"bufferView": 1, "componentType": 5123, "count": 6, "type": "SCALAR"}}
+                ]
+            }}"#,
+            bin.len()
+        );
+        glb(json, bin)
+    }
+
+    fn glb(json: String, mut bin: Vec<u8>) -> Vec<u8> {
+        let mut json = json.into_bytes();
+        json.resize(json.len().next_multiple_of(4), b' ');
+        bin.resize(bin.len().next_multiple_of(4), 0);
+
+        let mut glb = Vec::new();
+        glb.extend_from_slice(GLB_MAGIC);
+        glb.extend(2u32.to_le_bytes());
+        glb.extend(((12 + 8 + json.len() + 8 + bin.len()) as u32).to_le_bytes());
+        for (kind, data) in [(GLB_JSON_CHUNK, &json), (GLB_BIN_CHUNK, &bin)] {
+            glb.extend((data.len() as u32).to_le_bytes());
+            glb.extend_from_slice(kind);
+            glb.extend_from_slice(data);
+        }
+        glb
+    }
+
+    /// A glb of a triangle skinned to a root joint and a child joint, which
+    /// an animation turns and moves.
+    fn skinned_glb() -> Vec<u8> {
+        let mut bin = f32_bytes(&[0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0]);
+        bin.extend([0u8, 0, 0, 0, 0, 1, 0, 0, 1, 0, 0, 0]);
+        bin.extend(f32_bytes(&[
+            1.0, 0.0, 0.0, 0.0, 1.0, 1.0, 0.0, 0.0, 2.0, 0.0, 0.0, 0.0,
+        ]));
+        let mut inverse_bind = Mat4::IDENTITY.to_cols_array().to_vec();
+        inverse_bind.extend(Mat4::from_translation(Vec3::new(0.0, -1.0, 0.0)).to_cols_array());
+        bin.extend(f32_bytes(&inverse_bind));
+        bin.extend(f32_bytes(&[0.0, 1.0]));
+        let quarter_turn = Quat::from_rotation_z(FRAC_PI_2).to_array();
+        bin.extend(f32_bytes(&[0.0, 0.0, 0.0, 1.0]));
+        bin.extend(f32_bytes(&quarter_turn));
+        bin.extend(f32_bytes(&[0.0, 1.0, 0.0, 0.0, 3.0, 0.0]));
+        let json = format!(
+            r#"{{
+                "asset": {{"version": "2.0"}},
+                "scenes": [{{"nodes": [0, 1]}}],
+                "nodes": [
+                    {{"name": "body", "mesh": 0, "skin": 0}},
+                    {{"name": "hip", "children": [2]}},
+                    {{"name": "knee", "translation": [0, 1, 0]}}
+                ],
+                "meshes": [{{"primitives": [
+                    {{"attributes": {{"POSITION": 0, "JOINTS_0": 1, "WEIGHTS_0": 2}}}}
+                ]}}],
+                "skins": [{{"joints": [1, 2], "inverseBindMatrices": 3}}],
+                "animations": [{{
+                    "name": "kick",
+                    "channels": [
+                        {{"sampler": 0, "target": {{"node": 2, "path": "rotation"}}}},
+                        {{"sampler": 1, "target": {{"node": 1, "path": "translation"}}}},
+                        {{"sampler": 0, "target": {{"node": 0, "path": "weights"}}}}
+                    ],
+                    "samplers": [
+                        {{"input": 4, "output": 5}},
+                        {{"input": 4, "output": 6, "interpolation": "STEP"}}
+                    ]
+                }}],
+                "buffers": [{{"byteLength": {}}}],
+                "bufferViews": [
+                    {{"buffer": 0, "byteLength": 36}},
+                    {{"buffer": 0, "byteOffset": 36, "byteLength": 12}},
+                    {{"buffer": 0, "byteOffset": 48, "byteLength": 48}},
+                    {{"buffer": 0, "byteOffset": 96, "byteLength": 128}},
+                    {{"buffer": 0, "byteOffset": 224, "byteLength": 8}},
+                    {{"buffer": 0, "byteOffset": 232, "byteLength": 32}},
+                    {{"buffer": 0, "byteOffset": 264, "byteLength": 24}}
+                ],
+                "accessors": [
+                    {{"bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3"}},
+                    {{"bufferView": 1, "componentType": 5121, "count": 3, "type": "VEC4"}},
+                    {{"bufferView": 2, "componentType": 5126, "count": 3, "type": "VEC4"}},
+                    {{"bufferView": 3, "componentType": 5126, "count": 2, "type": "MAT4"}},
+                    {{"bufferView": 4, "componentType": 5126, "count": 2, "type": "SCALAR"}},
+                    {{"bufferView": 5, "componentType": 5126, "count": 2, "type": "VEC4"}},
+                    {{"bufferView": 6, "componentType": 5126, "count": 2, "type": "VEC3"}}
+                ]
+            }}"#,
+            bin.len()
+        );
+        glb(json, bin)
+    }
+
+    fn parse(path: &str, bytes: &[u8]) -> Result<GltfScene, LoadError> {
+        GltfScene::parse(
+            Path::new(path),
+            bytes,
+            Path::new("vertex.spv"),
+            Path::new("fragment.spv"),
+        )
+    }
+
+    #[test]
+    fn loads_meshes_materials_and_nodes_from_glb() {
+        let scene = parse("assets/quad.glb", &quad_glb()).unwrap();
+        assert_eq!(scene.roots, [0]);
+        assert_eq!(scene.nodes[0].name.map(Symbol::as_str), Some("parent"));
+        assert_eq!(scene.nodes[0].children, [1]);
+        assert_eq!(
+            scene.nodes[0].transform,
+            Mat4::from_translation(Vec3::new(0.0, 5.0, 0.0))
+        );
+        assert_eq!(scene.nodes[1].mesh, Some(0));
+        assert_eq!(scene.nodes[1].transform, Mat4::from_scale(Vec3::splat(2.0)));
+
+        let quad = &scene.meshes[0][0];
+        assert_eq!(quad.indices(), [0, 1, 2, 0, 2, 3]);
+        assert_eq!(quad.vertices()[2].pos, [1.0, 1.0, 0.0, 1.0]);
+        // Normals are worked out from the triangles when there are none.
+        assert_eq!(quad.vertices()[0].normal, [0.0, 0.0, 1.0]);
+        let diffuse = quad.material.diffuse_map.as_ref().unwrap();
+        assert_eq!(diffuse.image.to_rgba8().get_pixel(0, 0).0, [255, 0, 0, 255]);
+        assert_eq!(
+            quad.source_paths(),
+            [Path::new("assets/quad.glb")],
+            "embedded data has no files to watch"
+        );
+    }
+
+    #[test]
+    fn loads_skins_and_animations() {
+        let scene = parse("skinned.glb", &skinned_glb()).unwrap();
+        assert_eq!(scene.roots, [0, 1]);
+        assert_eq!(scene.nodes[0].skin, Some(0));
+        assert_eq!(scene.skins[0].joints, [1, 2]);
+        assert_eq!(
+            scene.skins[0].inverse_bind_matrices[1],
+            Mat4::from_translation(Vec3::new(0.0, -1.0, 0.0))
+        );
+
+        // Weights are scaled to add up to 1.
+        let weights = scene.meshes[0][0].skin_weights();
+        assert_eq!(weights[1].joints, [0, 1, 0, 0]);
+        assert_eq!(weights[1].weights, [0.5, 0.5, 0.0, 0.0]);
+        assert_eq!(weights[2].joints, [1, 0, 0, 0]);
+        assert_eq!(weights[2].weights, [1.0, 0.0, 0.0, 0.0]);
+
+        let kick = &scene.animations[0];
+        assert_eq!(kick.name.map(Symbol::as_str), Some("kick"));
+        assert_eq!(kick.duration, 1.0);
+        // Morph target weights aren't animated.
+        assert_eq!(kick.channels.len(), 2);
+        assert_eq!(kick.channels[0].node, 2);
+        assert_eq!(kick.channels[0].node_name.map(Symbol::as_str), Some("knee"));
+        assert_eq!(kick.channels[1].interpolation, Interpolation::Step);
+        match kick.channels[0].sample(1.0) {
+            Some(Sample::Rotation(rotation)) => {
+                assert!(rotation.abs_diff_eq(Quat::from_rotation_z(FRAC_PI_2), 1e-5))
+            }
+            other => panic!("sampled {other:?}"),
+        }
+        assert_eq!(
+            kick.channels[1].sample(0.5),
+            Some(Sample::Translation(Vec3::Y))
+        );
+    }
+
+    #[test]
+    fn loads_gltf_with_data_uris() {
+        let positions = f32_bytes(&[0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0]);
+        let encoded = base64_encode(&positions);
+        let json = format!(
+            r#"{{
+                "asset": {{"version": "2.0"}},
+                "nodes": [{{"mesh": 0}}],
+                "meshes": [{{"primitives": [{{"attributes": {{"POSITION": 0}}}}]}}],
+                "buffers": [{{
+                    "byteLength": 36,
+                    "uri": "data:application/octet-stream;base64,{encoded}"
+                }}],
+                "bufferViews": [{{"buffer": 0, "byteLength": 36}}],
+                "accessors": [
+                    {{"bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3"}}
+                ]
+            }}"#
+        );
+        let scene = parse("triangle.gltf", json.as_bytes()).unwrap();
+        assert_eq!(scene.roots, [0]);
+        let triangle = &scene.meshes[0][0];
+        assert_eq!(triangle.indices(), [0, 1, 2]);
+        assert_eq!(triangle.vertices()[1].pos, [1.0, 0.0, 0.0, 1.0]);
+        // The default material is white.
+        let diffuse = triangle.material.diffuse_map.as_ref().unwrap();
+        assert_eq!(diffuse.image.to_rgba8().get_pixel(0, 0).0, [255; 4]);
+    }
+
+    #[test]
+    fn rejects_invalid_documents() {
+        let invalid = [
+            r#"{"asset": {"version": "1.0"}}"#,
+            r#"{"asset": {"version": "2.0"}, "nodes": [{"children": [0]}]}"#,
+            r#"{"asset": {"version": "2.0"}, "nodes": [{"mesh": 0}]}"#,
+            r#"{"asset": {"version": "2.0"}, "meshes": [{"primitives": [{"attributes": {}}]}]}"#,
+        ];
+        for json in invalid {
+            assert!(
+                matches!(
+                    parse("bad.gltf", json.as_bytes()),
+                    Err(LoadError::Gltf { .. })
+                ),
+                "{json} loaded"
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_views_and_accessors_out_of_bounds() {
+        let bin = f32_bytes(&[0.0; 9]);
+        let view = r#"{"buffer": 0, "byteLength": 36}"#;
+        let cases = [
+            // An offset that overflows once the length is added.
+            (
+                r#"{"buffer": 0, "byteOffset": 18446744073709551615, "byteLength": 36}"#,
+                r#"{"bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3"}"#,
+            ),
+            // More elements than there's memory for, let alone in the view.
+            (
+                view,
+                r#"{"bufferView": 0, "componentType": 5126, "count": 4611686018427387904, "type": "VEC4"}"#,
+            ),
+            // One element more than the view holds.
+            (
+                view,
+                r#"{"bufferView": 0, "componentType": 5126, "count": 4, "type": "VEC3"}"#,
+            ),
+            // An offset that overflows once the elements are added.
+            (
+                view,
+                r#"{"bufferView": 0, "byteOffset": 18446744073709551615, "componentType": 5126, "count": 3, "type": "VEC3"}"#,
+            ),
+            // All zeros, but more of them than the buffers hold.
+            (
+                view,
+                r#"{"componentType": 5126, "count": 4611686018427387904, "type": "VEC3"}"#,
+            ),
+        ];
+        for (view, accessor) in cases {
+            let json = format!(
+                r#"{{
+                    "asset": {{"version": "2.0"}},
+                    "nodes": [{{"mesh": 0}}],
+                    "meshes": [{{"primitives": [{{"attributes": {{"POSITION": 0}}}}]}}],
+                    "buffers": [{{"byteLength": 36}}],
+                    "bufferViews": [{view}],
+                    "accessors": [{accessor}]
+                }}"#
+            );
+            assert!(
+                matches!(
+                    parse("bad.glb", &glb(json, bin.clone())),
+                    Err(LoadError::Gltf { .. })
+                ),
+                "{accessor} loaded from {view}"
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_documents_nested_too_deeply() {
+        let json = format!(
+            r#"{{"asset": {{"version": "2.0"}}, "extras": {}}}"#,
+            "[".repeat(100_000)
+        );
+        assert!(matches!(
+            parse("deep.gltf", json.as_bytes()),
+            Err(LoadError::Gltf { .. })
+        ));
+    }
+
+    fn base64_encode(bytes: &[u8]) -> String {
+        const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+        let mut encoded = String::new();
+        for chunk in bytes.chunks(3) {
+            let bits = chunk.iter().enumerate().fold(0u32, |bits, (i, &byte)| {
+                bits | u32::from(byte) << (16 - 8 * i)
+            });
+            for i in 0..=chunk.len() {
+                encoded.push(ALPHABET[(bits >> (18 - 6 * i) & 63) as usize] as char);
+            }
+        }
+        for _ in 0..(3 - bytes.len() % 3) % 3 {
+            encoded.push('=');
+        }
+        encoded
+    }
+
+    #[test]
+    fn decodes_base64() {
+        assert_eq!(base64_decode("aGVsbG8=").unwrap(), b"hello");
+        assert_eq!(base64_decode(&base64_encode(b"glTF!")).unwrap(), b"glTF!");
+        assert_eq!(base64_decode("a*"), None);
+    }
+}
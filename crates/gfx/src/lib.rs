@@ -1,4 +1,11 @@
-//! Implements model loading through obj-parser.
+//! Implements model loading through obj-parser, and of glTF scenes.
 
+mod animation;
 mod gfx;
+mod gltf;
+mod json;
+pub use crate::animation::{
+    AnimationClip, Channel, Interpolation, Keyframes, Sample, Skin, SkinWeights,
+};
 pub use crate::gfx::*;
+pub use crate::gltf::{GltfNode, GltfScene};
@@ -0,0 +1,236 @@
+//! Input accumulated over the interval between simulation ticks.
+//!
+//! Controller state is sampled once a frame, but the simulation ticks at a
+//! fixed rate, so reading whatever state is current at a tick makes the result
+//! depend on how frames and ticks line up. Instead each sample is weighted by
+//! how long it was held, and presses are counted, so a tick sees how much of
+//! its interval a button was down and how far an axis was pushed on average,
+//! at any frame rate.
+
+use std::time::{Duration, Instant};
+
+use crate::wire::InputState;
+use crate::Button;
+
+const BUTTONS: usize = 16;
+const AXES: usize = 7;
+
+/// Input over one interval, taken from an `InputAccumulator`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct HeldInput {
+    duration: Duration,
+    held: [Duration; BUTTONS],
+    presses: [u32; BUTTONS],
+    axes: [f32; AXES],
+    /// The state at the end of the interval.
+    last: InputState,
+}
+
+impl HeldInput {
+    /// Input of a state held over an interval, as if it was sampled once.
+    pub fn constant(state: InputState, duration: Duration) -> Self {
+        let mut accumulator = InputAccumulator::default();
+        let start = Instant::now();
+        accumulator.sample(state, start);
+        accumulator.take(start + duration)
+    }
+
+    pub fn duration(&self) -> Duration {
+        self.duration
+    }
+
+    /// Fraction of the interval the button was down, from 0 to 1. A button
+    /// held over an empty interval counts as fully down.
+    pub fn held_fraction(&self, button: Button) -> f32 {
+        let index = button as usize;
+        if self.duration.is_zero() {
+            return if self.last.is_button_pressed(button) {
+                1.0
+            } else {
+                0.0
+            };
+        }
+        (self.held[index].as_secs_f64() / self.duration.as_secs_f64()) as f32
+    }
+
+    /// Times the button went down during the interval.
+    pub fn presses(&self, button: Button) -> u32 {
+        self.presses[button as usize]
+    }
+
+    /// Value of an axis averaged over the interval, in the wire's units.
+    pub fn axis(&self, axis: usize) -> f32 {
+        self.axes[axis]
+    }
+
+    /// A single state standing for the interval, for whatever still reads
+    /// one. Buttons pressed at any point are down, so short taps aren't lost,
+    /// and axes are their averages.
+    pub fn state(&self) -> InputState {
+        let mut buttons = self.last.buttons();
+        for (index, presses) in self.presses.iter().enumerate() {
+            if *presses > 0 {
+                buttons |= 1 << index;
+            }
+        }
+        InputState::from_parts(
+            self.last.id(),
+            self.axes
+                .map(|value| value.round().clamp(-128.0, 127.0) as i8),
+            buttons,
+        )
+    }
+}
+
+/// Accumulates sampled controller state until it's taken for a tick.
+#[derive(Debug, Default, Clone)]
+pub struct InputAccumulator {
+    /// The latest state and when it was sampled.
+    current: Option<(InputState, Instant)>,
+    start: Option<Instant>,
+    held: [Duration; BUTTONS],
+    presses: [u32; BUTTONS],
+    /// Axis values multiplied by the seconds they were held.
+    axes: [f64; AXES],
+}
+
+impl InputAccumulator {
+    /// Record the state as of `now`, the previous state having been held
+    /// until then.
+    pub fn sample(&mut self, state: InputState, now: Instant) {
+        let previous = self.advance(now);
+        let went_down = state.buttons() & !previous.map_or(0, |state| state.buttons());
+        for (index, presses) in self.presses.iter_mut().enumerate() {
+            if went_down & (1 << index) != 0 {
+                *presses += 1;
+            }
+        }
+        self.start.get_or_insert(now);
+        self.current = Some((state, now));
+    }
+
+    /// The latest state, if any was sampled.
+    pub fn current(&self) -> Option<InputState> {
+        self.current.map(|(state, _)| state)
+    }
+
+    /// Take the input since the last time it was taken, up to `now`, and
+    /// start the next interval with the latest state still held. Before any
+    /// state is sampled, that's an empty interval of a default state.
+    pub fn take(&mut self, now: Instant) -> HeldInput {
+        let last = self.advance(now).unwrap_or_default();
+        let duration = self
+            .start
+            .map_or(Duration::ZERO, |start| now.saturating_duration_since(start));
+        let seconds = duration.as_secs_f64();
+        let axes = if seconds > 0.0 {
+            self.axes.map(|integral| (integral / seconds) as f32)
+        } else {
+            last.axis_values().map(f32::from)
+        };
+        let taken = HeldInput {
+            duration,
+            held: self.held,
+            presses: self.presses,
+            axes,
+            last,
+        };
+        self.held = [Duration::ZERO; BUTTONS];
+        self.presses = [0; BUTTONS];
+        self.axes = [0.0; AXES];
+        if self.current.is_some() {
+            self.start = Some(now);
+        }
+        taken
+    }
+
+    /// Add the current state held until `now`, returning it.
+    fn advance(&mut self, now: Instant) -> Option<InputState> {
+        let (state, since) = self.current?;
+        let held = now.saturating_duration_since(since);
+        let buttons = state.buttons();
+        for (index, total) in self.held.iter_mut().enumerate() {
+            if buttons & (1 << index) != 0 {
+                *total += held;
+            }
+        }
+        for (total, value) in self.axes.iter_mut().zip(state.axis_values()) {
+            *total += f64::from(value) * held.as_secs_f64();
+        }
+        self.current = Some((state, now));
+        Some(state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::InputEvent;
+
+    fn state(events: &[InputEvent]) -> InputState {
+        let mut state = InputState::new(0);
+        for event in events {
+            state.update_from_event(event);
+        }
+        state
+    }
+
+    #[test]
+    fn weights_samples_by_how_long_they_were_held() {
+        let start = Instant::now();
+        let ms = |ms: u64| start + Duration::from_millis(ms);
+        let up = state(&[
+            InputEvent::KeyPressed(Button::Up),
+            InputEvent::AxisMotion(0, 2, 100),
+        ]);
+        let released = state(&[]);
+
+        let mut accumulator = InputAccumulator::default();
+        accumulator.sample(up, ms(0));
+        accumulator.sample(released, ms(6));
+        // A tap between ticks still counts.
+        accumulator.sample(up, ms(7));
+        accumulator.sample(released, ms(8));
+        let held = accumulator.take(ms(10));
+
+        assert_eq!(held.duration(), Duration::from_millis(10));
+        assert!((held.held_fraction(Button::Up) - 0.7).abs() < 1e-6);
+        assert_eq!(held.presses(Button::Up), 2);
+        assert!((held.axis(2) - 70.0).abs() < 1e-4);
+        assert!(held.state().is_button_pressed(Button::Up));
+        assert_eq!(held.state().axis_values()[2], 70);
+
+        // The released state carries on into the next interval.
+        let held = accumulator.take(ms(18));
+        assert_eq!(held.held_fraction(Button::Up), 0.0);
+        assert_eq!(held.presses(Button::Up), 0);
+        assert!(!held.state().is_button_pressed(Button::Up));
+    }
+
+    #[test]
+    fn same_input_at_any_frame_rate() {
+        let start = Instant::now();
+        let held_for = |frame: Duration| {
+            let mut accumulator = InputAccumulator::default();
+            let mut at = start;
+            while at < start + Duration::from_millis(24) {
+                let down = at < start + Duration::from_millis(12);
+                let events = if down {
+                    vec![InputEvent::KeyPressed(Button::Left)]
+                } else {
+                    vec![]
+                };
+                accumulator.sample(state(&events), at);
+                at += frame;
+            }
+            accumulator.take(start + Duration::from_millis(24))
+        };
+        let fast = held_for(Duration::from_millis(1));
+        let slow = held_for(Duration::from_millis(4));
+        assert_eq!(fast.held_fraction(Button::Left), 0.5);
+        assert_eq!(fast, slow);
+
+        let constant = HeldInput::constant(state(&[]), Duration::ZERO);
+        assert_eq!(constant.held_fraction(Button::Left), 0.0);
+    }
+}
@@ -0,0 +1,340 @@
+//! Which gamepad plays which local player, for split-screen.
+//!
+//! A connected gamepad joins by pressing `Button::Ok`, claiming the first free
+//! player slot, and leaves when it's disconnected or its player is let go
+//! with `PlayerSlots::leave`. Slots remember, by name, the kind of gamepad
+//! that last joined them, in a file like calibration profiles, so when the
+//! same gamepads connect again, such as on the next run, they take their
+//! slots back without pressing anything.
+//!
+//! Joins and leaves are kept as `SlotEvent`s for a frame, for whatever
+//! creates and destroys players and their viewports to act on. Those in the
+//! middle of a frame, such as from the console, are kept for the next.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::{fs, io};
+
+use crate::{Button, InputEvent};
+
+/// Player slots when none are configured.
+pub const DEFAULT_PLAYER_SLOTS: usize = 4;
+
+#[derive(thiserror::Error, Debug)]
+pub enum AssignmentError {
+    #[error("unable to read or write player slots: {0}")]
+    Io(#[from] io::Error),
+    #[error("player slots are malformed: {0}")]
+    Format(#[from] serde_yaml::Error),
+    #[error("there's no player {0}")]
+    NoSuchPlayer(usize),
+    #[error("no gamepad plays player {0}")]
+    NoDevice(usize),
+}
+
+/// A gamepad joining or leaving a player slot.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SlotEvent {
+    Joined { player: usize, device: u32 },
+    Left { player: usize, device: u32 },
+}
+
+/// A player, and the gamepad playing it.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Slot {
+    /// Id of the connected gamepad playing the player, as its events carry.
+    pub device: Option<u32>,
+    /// Name of the kind of gamepad that last joined, which takes the slot
+    /// back when it connects.
+    pub remembered: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct PlayerSlots {
+    slots: Vec<Slot>,
+    /// Names of connected gamepads, by id.
+    devices: BTreeMap<u32, String>,
+    /// Joins and leaves of this frame, see `begin_frame`.
+    events: Vec<SlotEvent>,
+    /// Joins and leaves since `begin_frame`, kept for the next frame.
+    pending: Vec<SlotEvent>,
+    /// Whether what's remembered changed since it was saved.
+    changed: bool,
+    /// Where remembered gamepads are read from and saved to, if anywhere.
+    path: Option<PathBuf>,
+}
+
+impl Default for PlayerSlots {
+    fn default() -> Self {
+        Self::new(DEFAULT_PLAYER_SLOTS)
+    }
+}
+
+impl PlayerSlots {
+    pub fn new(players: usize) -> Self {
+        Self {
+            slots: vec![Slot::default(); players],
+            devices: BTreeMap::new(),
+            events: Vec::new(),
+            pending: Vec::new(),
+            changed: false,
+            path: None,
+        }
+    }
+
+    /// Read the gamepads slots remember from a file, saved to again by
+    /// `save`. A missing file remembers none.
+    pub fn load(path: &Path, players: usize) -> Result<Self, AssignmentError> {
+        let remembered: Vec<Option<String>> = match fs::read_to_string(path) {
+            Ok(yaml) => serde_yaml::from_str(&yaml)?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(err) => return Err(err.into()),
+        };
+        let mut slots = Self::new(players);
+        for (slot, remembered) in slots.slots.iter_mut().zip(remembered) {
+            slot.remembered = remembered;
+        }
+        slots.path = Some(path.to_path_buf());
+        Ok(slots)
+    }
+
+    /// Write the remembered gamepads back to the file they were loaded from,
+    /// if they changed. Does nothing for slots that weren't loaded from a
+    /// file.
+    pub fn save(&mut self) -> Result<(), AssignmentError> {
+        if let Some(path) = self.path.as_ref().filter(|_| self.changed) {
+            let remembered: Vec<_> = self.slots.iter().map(|slot| &slot.remembered).collect();
+            fs::write(path, serde_yaml::to_string(&remembered)?)?;
+        }
+        self.changed = false;
+        Ok(())
+    }
+
+    pub fn slots(&self) -> &[Slot] {
+        &self.slots
+    }
+
+    /// Name of a connected gamepad.
+    pub fn device_name(&self, device: u32) -> Option<&str> {
+        self.devices.get(&device).map(String::as_str)
+    }
+
+    /// The player a gamepad plays, if it joined.
+    pub fn player_of(&self, device: u32) -> Option<usize> {
+        self.slots
+            .iter()
+            .position(|slot| slot.device == Some(device))
+    }
+
+    /// A gamepad connected. It takes back a free slot that remembers its kind.
+    pub fn connect(&mut self, device: u32, name: &str) {
+        if self.devices.insert(device, name.to_string()).is_some() {
+            return;
+        }
+        let player = self
+            .slots
+            .iter()
+            .position(|slot| slot.device.is_none() && slot.remembered.as_deref() == Some(name));
+        if let Some(player) = player {
+            self.slots[player].device = Some(device);
+            self.pending.push(SlotEvent::Joined { player, device });
+        }
+    }
+
+    /// A gamepad disconnected, leaving its slot. The slot still remembers
+    /// it, to take back when it connects again.
+    pub fn disconnect(&mut self, device: u32) {
+        self.devices.remove(&device);
+        if let Some(player) = self.player_of(device) {
+            self.slots[player].device = None;
+            self.pending.push(SlotEvent::Left { player, device });
+        }
+    }
+
+    /// Connect and disconnect gamepads to match those connected now.
+    pub fn sync_devices<'a>(&mut self, connected: impl IntoIterator<Item = (u32, &'a str)>) {
+        let connected: BTreeMap<u32, &str> = connected.into_iter().collect();
+        let gone: Vec<u32> = self
+            .devices
+            .keys()
+            .filter(|device| !connected.contains_key(device))
+            .copied()
+            .collect();
+        for device in gone {
+            self.disconnect(device);
+        }
+        for (device, name) in connected {
+            self.connect(device, name);
+        }
+    }
+
+    /// Join a gamepad pressing `Button::Ok` to the first free slot, if it
+    /// hasn't joined already. Returns the player it joined as.
+    pub fn handle(&mut self, event: &InputEvent) -> Option<usize> {
+        let id = match event {
+            InputEvent::ButtonPressed(id, Button::Ok) => *id,
+            _ => return None,
+        };
+        // Events carry ids cut down to a byte, so gamepads with ids that
+        // don't fit one can't be told apart and never join this way.
+        let (device, name) = self
+            .devices
+            .iter()
+            .find(|(device, _)| u8::try_from(**device) == Ok(id))
+            .map(|(device, name)| (*device, name.clone()))?;
+        if self.player_of(device).is_some() {
+            return None;
+        }
+        let player = self.slots.iter().position(|slot| slot.device.is_none())?;
+        let slot = &mut self.slots[player];
+        slot.device = Some(device);
+        if slot.remembered.as_deref() != Some(name.as_str()) {
+            slot.remembered = Some(name);
+            self.changed = true;
+        }
+        self.pending.push(SlotEvent::Joined { player, device });
+        Some(player)
+    }
+
+    /// Let a player go, freeing its slot and forgetting its gamepad, which
+    /// has to join again.
+    pub fn leave(&mut self, player: usize) -> Result<(), AssignmentError> {
+        let slot = self
+            .slots
+            .get_mut(player)
+            .ok_or(AssignmentError::NoSuchPlayer(player))?;
+        let device = slot
+            .device
+            .take()
+            .ok_or(AssignmentError::NoDevice(player))?;
+        slot.remembered = None;
+        self.changed = true;
+        self.pending.push(SlotEvent::Left { player, device });
+        Ok(())
+    }
+
+    /// Joins and leaves of this frame, in the order they happened.
+    pub fn events(&self) -> &[SlotEvent] {
+        &self.events
+    }
+
+    /// Make the joins and leaves since the last call this frame's `events`.
+    pub fn begin_frame(&mut self) {
+        self.events.clear();
+        std::mem::swap(&mut self.events, &mut self.pending);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gamepads_join_free_slots_and_leave_them() {
+        let mut slots = PlayerSlots::new(2);
+        slots.connect(7, "pad");
+        slots.connect(9, "stick");
+        slots.connect(11, "pad");
+        slots.begin_frame();
+        assert!(slots.events().is_empty());
+
+        assert_eq!(
+            slots.handle(&InputEvent::ButtonPressed(9, Button::Cancel)),
+            None
+        );
+        assert_eq!(
+            slots.handle(&InputEvent::ButtonPressed(9, Button::Ok)),
+            Some(0)
+        );
+        assert_eq!(
+            slots.handle(&InputEvent::ButtonPressed(9, Button::Ok)),
+            None
+        );
+        assert_eq!(
+            slots.handle(&InputEvent::ButtonPressed(7, Button::Ok)),
+            Some(1)
+        );
+        // Every slot is taken.
+        assert_eq!(
+            slots.handle(&InputEvent::ButtonPressed(11, Button::Ok)),
+            None
+        );
+        assert_eq!(slots.player_of(7), Some(1));
+
+        slots.disconnect(9);
+        assert!(slots.leave(0).is_err());
+        slots.leave(1).unwrap();
+        assert!(slots.events().is_empty());
+        slots.begin_frame();
+        assert_eq!(
+            slots.events(),
+            [
+                SlotEvent::Joined {
+                    player: 0,
+                    device: 9
+                },
+                SlotEvent::Joined {
+                    player: 1,
+                    device: 7
+                },
+                SlotEvent::Left {
+                    player: 0,
+                    device: 9
+                },
+                SlotEvent::Left {
+                    player: 1,
+                    device: 7
+                },
+            ]
+        );
+        // Disconnecting kept the stick remembered, leaving forgot the pad.
+        assert_eq!(slots.slots()[0].remembered.as_deref(), Some("stick"));
+        assert_eq!(slots.slots()[1].remembered, None);
+    }
+
+    #[test]
+    fn gamepads_with_wide_ids_are_not_mistaken_for_others() {
+        let mut slots = PlayerSlots::new(2);
+        slots.connect(256 + 3, "pad");
+        assert_eq!(
+            slots.handle(&InputEvent::ButtonPressed(3, Button::Ok)),
+            None
+        );
+        slots.connect(3, "stick");
+        assert_eq!(
+            slots.handle(&InputEvent::ButtonPressed(3, Button::Ok)),
+            Some(0)
+        );
+        assert_eq!(slots.player_of(3), Some(0));
+        assert_eq!(slots.player_of(256 + 3), None);
+    }
+
+    #[test]
+    fn remembered_gamepads_take_their_slots_back() {
+        let path = std::env::temp_dir().join(format!("player_slots_{}.yaml", std::process::id()));
+        let mut slots = PlayerSlots::load(&path, 3).unwrap();
+        slots.sync_devices([(1, "pad"), (2, "stick")]);
+        slots.handle(&InputEvent::ButtonPressed(2, Button::Ok));
+        slots.handle(&InputEvent::ButtonPressed(1, Button::Ok));
+        slots.save().unwrap();
+
+        let mut slots = PlayerSlots::load(&path, 3).unwrap();
+        fs::remove_file(&path).unwrap();
+        // Ids differ from run to run, names don't.
+        slots.sync_devices([(5, "pad"), (6, "stick"), (8, "pad")]);
+        assert_eq!(slots.player_of(6), Some(0));
+        assert_eq!(slots.player_of(5), Some(1));
+        assert_eq!(slots.player_of(8), None);
+
+        slots.begin_frame();
+        slots.sync_devices([(5, "pad"), (8, "pad")]);
+        slots.begin_frame();
+        assert_eq!(
+            slots.events(),
+            [SlotEvent::Left {
+                player: 0,
+                device: 6
+            }]
+        );
+    }
+}
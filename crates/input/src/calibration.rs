@@ -0,0 +1,386 @@
+//! Gamepad axis calibration, with a profile per kind of device.
+//!
+//! Controllers report axes over different ranges, and sticks drift off center
+//! as they wear. A profile records where each axis rests, how far it reaches
+//! either way and how much around the center is read as centered. Axes are
+//! calibrated from raw values before they're scaled down for the wire.
+//!
+//! Profiles are measured by a `CalibrationSession`: the sticks are left at
+//! rest while the center is sampled, then moved through their full range.
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::{fs, io};
+
+use serde::{Deserialize, Serialize};
+
+/// Axes calibrated per device, as many as are sent on the wire.
+pub const AXES: usize = 7;
+
+/// Least distance from the center, in raw units, an axis has to be moved for
+/// its extent to be measured. Axes that weren't moved keep their old extents.
+const MIN_MEASURED_EXTENT: i32 = 8192;
+
+#[derive(thiserror::Error, Debug)]
+pub enum CalibrationError {
+    #[error("unable to read or write gamepad profiles: {0}")]
+    Io(#[from] io::Error),
+    #[error("gamepad profiles are malformed: {0}")]
+    Format(#[from] serde_yaml::Error),
+    #[error("no gamepad {0} is connected")]
+    NoSuchDevice(u32),
+    #[error("gamepads have {AXES} axes, there's no axis {0}")]
+    NoSuchAxis(usize),
+    #[error("a dead zone is a fraction from 0 to 1, not {0}")]
+    InvalidDeadZone(f32),
+    #[error("no gamepad is being calibrated")]
+    NotCalibrating,
+}
+
+/// Calibration of one axis, in the raw units the device reports.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AxisCalibration {
+    /// Value the axis rests at.
+    pub center: i16,
+    /// Furthest the axis reaches below the center.
+    pub min: i16,
+    /// Furthest the axis reaches above the center.
+    pub max: i16,
+    /// Fraction of the range either side of the center read as centered.
+    pub dead_zone: f32,
+}
+
+impl Default for AxisCalibration {
+    fn default() -> Self {
+        Self {
+            center: 0,
+            min: i16::MIN,
+            max: i16::MAX,
+            dead_zone: 0.08,
+        }
+    }
+}
+
+impl AxisCalibration {
+    /// Calibrate a raw value, scaled to the wire's range. Past the dead zone
+    /// the value is rescaled, so it still starts from 0 and reaches the end of
+    /// the range.
+    pub fn apply(&self, raw: i16) -> i8 {
+        let offset = i32::from(raw) - i32::from(self.center);
+        let extent = if offset >= 0 {
+            i32::from(self.max) - i32::from(self.center)
+        } else {
+            i32::from(self.center) - i32::from(self.min)
+        };
+        let value = (offset as f32 / extent.max(1) as f32).clamp(-1.0, 1.0);
+        let dead_zone = self.dead_zone.clamp(0.0, 1.0);
+        if value.abs() <= dead_zone {
+            return 0;
+        }
+        let scaled = (value.abs() - dead_zone) / (1.0 - dead_zone).max(f32::EPSILON);
+        (scaled.min(1.0) * value.signum() * i8::MAX as f32).round() as i8
+    }
+}
+
+/// Calibration of every axis of a kind of device.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DeviceProfile {
+    pub axes: [AxisCalibration; AXES],
+}
+
+/// Which step of calibration a session is at.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CalibrationStep {
+    /// Sticks and triggers are left at rest while their centers are sampled.
+    Center,
+    /// Sticks and triggers are moved through their full range.
+    Extents,
+}
+
+/// Measures a profile for one connected device.
+#[derive(Debug)]
+pub struct CalibrationSession {
+    device: u32,
+    step: CalibrationStep,
+    sums: [i64; AXES],
+    samples: [u32; AXES],
+    centers: [Option<i16>; AXES],
+    lowest: [i16; AXES],
+    highest: [i16; AXES],
+}
+
+impl CalibrationSession {
+    pub fn new(device: u32) -> Self {
+        Self {
+            device,
+            step: CalibrationStep::Center,
+            sums: [0; AXES],
+            samples: [0; AXES],
+            centers: [None; AXES],
+            lowest: [i16::MAX; AXES],
+            highest: [i16::MIN; AXES],
+        }
+    }
+
+    pub fn device(&self) -> u32 {
+        self.device
+    }
+
+    pub fn step(&self) -> CalibrationStep {
+        self.step
+    }
+
+    /// Record a raw value of an axis for the current step.
+    pub fn sample(&mut self, axis: usize, raw: i16) {
+        if axis >= AXES {
+            return;
+        }
+        match self.step {
+            CalibrationStep::Center => {
+                self.sums[axis] += i64::from(raw);
+                self.samples[axis] += 1;
+            }
+            CalibrationStep::Extents => {
+                self.lowest[axis] = self.lowest[axis].min(raw);
+                self.highest[axis] = self.highest[axis].max(raw);
+            }
+        }
+    }
+
+    /// Finish sampling centers and start measuring extents.
+    pub fn measure_extents(&mut self) {
+        for axis in 0..AXES {
+            if self.samples[axis] > 0 {
+                self.centers[axis] = Some((self.sums[axis] / i64::from(self.samples[axis])) as i16);
+            }
+        }
+        self.step = CalibrationStep::Extents;
+    }
+
+    /// The measured profile. Whatever wasn't measured, along with the dead
+    /// zones, is kept from `previous`. SDL only reports axes that moved, so an
+    /// axis resting exactly at its old center has no samples.
+    pub fn finish(&self, previous: &DeviceProfile) -> DeviceProfile {
+        let mut profile = *previous;
+        for (axis, calibration) in profile.axes.iter_mut().enumerate() {
+            if let Some(center) = self.centers[axis] {
+                calibration.center = center;
+            }
+            let center = i32::from(calibration.center);
+            if center - i32::from(self.lowest[axis]) >= MIN_MEASURED_EXTENT {
+                calibration.min = self.lowest[axis];
+            }
+            if i32::from(self.highest[axis]) - center >= MIN_MEASURED_EXTENT {
+                calibration.max = self.highest[axis];
+            }
+        }
+        profile
+    }
+}
+
+/// Profiles for every kind of device seen, by device name, and which
+/// connected device uses which.
+#[derive(Debug, Default)]
+pub struct Calibration {
+    profiles: BTreeMap<String, DeviceProfile>,
+    /// Names of connected devices, by the id their events carry.
+    devices: HashMap<u32, String>,
+    session: Option<CalibrationSession>,
+    /// Where profiles are read from and saved to, if anywhere.
+    path: Option<PathBuf>,
+}
+
+impl Calibration {
+    /// Read profiles from a file, saved to again by `save`. A missing file is
+    /// no profiles.
+    pub fn load(path: &Path) -> Result<Self, CalibrationError> {
+        let profiles = match fs::read_to_string(path) {
+            Ok(yaml) => serde_yaml::from_str(&yaml)?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(err) => return Err(err.into()),
+        };
+        Ok(Self {
+            profiles,
+            path: Some(path.to_path_buf()),
+            ..Self::default()
+        })
+    }
+
+    /// Write profiles back to the file they were loaded from. Does nothing
+    /// for profiles that weren't loaded from a file.
+    pub fn save(&self) -> Result<(), CalibrationError> {
+        if let Some(path) = &self.path {
+            fs::write(path, serde_yaml::to_string(&self.profiles)?)?;
+        }
+        Ok(())
+    }
+
+    pub fn connect(&mut self, device: u32, name: &str) {
+        self.devices.insert(device, name.to_string());
+    }
+
+    pub fn disconnect(&mut self, device: u32) {
+        self.devices.remove(&device);
+        if self.session.as_ref().map(CalibrationSession::device) == Some(device) {
+            self.session = None;
+        }
+    }
+
+    /// Connected devices with their names, by id.
+    pub fn devices(&self) -> impl Iterator<Item = (u32, &str)> {
+        self.devices
+            .iter()
+            .map(|(device, name)| (*device, name.as_str()))
+    }
+
+    /// Profile of a connected device, the default if it hasn't been
+    /// calibrated.
+    pub fn profile(&self, device: u32) -> DeviceProfile {
+        self.devices
+            .get(&device)
+            .and_then(|name| self.profiles.get(name))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Calibrate a raw axis value from a device. A device being calibrated
+    /// reads as centered, so waving its sticks around doesn't move anything.
+    pub fn apply(&mut self, device: u32, axis: u8, raw: i16) -> i8 {
+        let axis = axis as usize;
+        if let Some(session) = self.session.as_mut().filter(|s| s.device() == device) {
+            session.sample(axis, raw);
+            return 0;
+        }
+        match self
+            .devices
+            .get(&device)
+            .and_then(|name| self.profiles.get(name))
+        {
+            Some(profile) if axis < AXES => profile.axes[axis].apply(raw),
+            _ => AxisCalibration::default().apply(raw),
+        }
+    }
+
+    pub fn session(&self) -> Option<&CalibrationSession> {
+        self.session.as_ref()
+    }
+
+    /// Start calibrating a connected device, abandoning any other calibration.
+    pub fn start(&mut self, device: u32) -> Result<(), CalibrationError> {
+        if !self.devices.contains_key(&device) {
+            return Err(CalibrationError::NoSuchDevice(device));
+        }
+        self.session = Some(CalibrationSession::new(device));
+        Ok(())
+    }
+
+    /// Move calibration on a step. After the extents are measured, the
+    /// profile is stored and returned.
+    pub fn advance(&mut self) -> Result<Option<DeviceProfile>, CalibrationError> {
+        let session = self
+            .session
+            .as_mut()
+            .ok_or(CalibrationError::NotCalibrating)?;
+        if session.step() == CalibrationStep::Center {
+            session.measure_extents();
+            return Ok(None);
+        }
+        let session = self.session.take().unwrap();
+        let name = self
+            .devices
+            .get(&session.device())
+            .cloned()
+            .ok_or(CalibrationError::NoSuchDevice(session.device()))?;
+        let previous = self.profiles.get(&name).copied().unwrap_or_default();
+        let profile = session.finish(&previous);
+        self.profiles.insert(name, profile);
+        Ok(Some(profile))
+    }
+
+    pub fn cancel(&mut self) -> Result<(), CalibrationError> {
+        self.session
+            .take()
+            .map(|_| ())
+            .ok_or(CalibrationError::NotCalibrating)
+    }
+
+    /// Set the dead zone of an axis of a connected device's profile.
+    pub fn set_dead_zone(
+        &mut self,
+        device: u32,
+        axis: usize,
+        dead_zone: f32,
+    ) -> Result<(), CalibrationError> {
+        if axis >= AXES {
+            return Err(CalibrationError::NoSuchAxis(axis));
+        }
+        if !(0.0..1.0).contains(&dead_zone) {
+            return Err(CalibrationError::InvalidDeadZone(dead_zone));
+        }
+        let name = self
+            .devices
+            .get(&device)
+            .ok_or(CalibrationError::NoSuchDevice(device))?;
+        self.profiles.entry(name.clone()).or_default().axes[axis].dead_zone = dead_zone;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn applies_center_extents_and_dead_zone() {
+        let calibration = AxisCalibration {
+            center: 1000,
+            min: -20000,
+            max: 21000,
+            dead_zone: 0.1,
+        };
+        assert_eq!(calibration.apply(1000), 0);
+        assert_eq!(calibration.apply(2500), 0);
+        assert_eq!(calibration.apply(21000), 127);
+        assert_eq!(calibration.apply(i16::MAX), 127);
+        assert_eq!(calibration.apply(-20000), -127);
+        // Halfway between the dead zone and the end of the range.
+        assert_eq!(calibration.apply(1000 + 11000), 64);
+    }
+
+    #[test]
+    fn calibrates_a_drifting_device() {
+        let mut calibration = Calibration::default();
+        calibration.connect(3, "pad");
+        assert!(calibration.start(4).is_err());
+        calibration.start(3).unwrap();
+
+        for raw in [3000, 3200, 2800] {
+            assert_eq!(calibration.apply(3, 0, raw), 0);
+        }
+        assert_eq!(calibration.advance().unwrap(), None);
+        for raw in [-25000, 0, 24000] {
+            calibration.apply(3, 0, raw);
+        }
+        calibration.apply(3, 1, 100);
+        let profile = calibration.advance().unwrap().unwrap();
+        assert_eq!(profile.axes[0].center, 3000);
+        assert_eq!(profile.axes[0].min, -25000);
+        assert_eq!(profile.axes[0].max, 24000);
+        // Barely moved, so only the default extents are known.
+        assert_eq!(profile.axes[1], AxisCalibration::default());
+
+        assert_eq!(calibration.apply(3, 0, 3000), 0);
+        assert_eq!(calibration.apply(3, 0, 24000), 127);
+        calibration.set_dead_zone(3, 0, 0.5).unwrap();
+        assert_eq!(calibration.apply(3, 0, 12000), 0);
+        assert!(calibration.set_dead_zone(3, 0, 1.5).is_err());
+
+        // Another device of the same kind shares the profile.
+        calibration.connect(5, "pad");
+        assert_eq!(calibration.profile(5), calibration.profile(3));
+        calibration.disconnect(3);
+        assert!(calibration.advance().is_err());
+    }
+}
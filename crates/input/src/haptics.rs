@@ -0,0 +1,285 @@
+//! Rumble patterns for gamepads, the sequencer that plays them, and direct
+//! requests for a controller's motors.
+//!
+//! Patterns are a list of steps, each a motor strength held for a time. The
+//! sequencer plays one pattern at a time, and ignores patterns started too
+//! soon after the last, so a burst of hits doesn't turn into a constant buzz.
+//! `HapticsController` skips all that, for game code that wants to drive one
+//! controller's motors itself, such as rumbling with the force of a collision.
+
+use std::time::{Duration, Instant};
+
+/// Least time between the start of one pattern and the next.
+pub const MIN_RUMBLE_INTERVAL: Duration = Duration::from_millis(150);
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(u8)]
+pub enum RumblePattern {
+    /// A single short kick.
+    Pulse = 1,
+    /// Builds up to full strength.
+    Ramp,
+    /// Two beats, the second weaker.
+    Heartbeat,
+}
+
+/// Motor strength, from 0 to 1, held for a duration.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct RumbleStep {
+    pub strength: f32,
+    pub duration: Duration,
+}
+
+const fn step(strength: f32, millis: u64) -> RumbleStep {
+    RumbleStep {
+        strength,
+        duration: Duration::from_millis(millis),
+    }
+}
+
+const PULSE: &[RumbleStep] = &[step(1.0, 120)];
+const RAMP: &[RumbleStep] = &[
+    step(0.25, 80),
+    step(0.5, 80),
+    step(0.75, 80),
+    step(1.0, 120),
+];
+const HEARTBEAT: &[RumbleStep] = &[step(1.0, 90), step(0.0, 110), step(0.6, 90), step(0.0, 400)];
+
+impl RumblePattern {
+    pub fn steps(self) -> &'static [RumbleStep] {
+        match self {
+            RumblePattern::Pulse => PULSE,
+            RumblePattern::Ramp => RAMP,
+            RumblePattern::Heartbeat => HEARTBEAT,
+        }
+    }
+
+    pub fn duration(self) -> Duration {
+        self.steps().iter().map(|step| step.duration).sum()
+    }
+
+    /// Tag for the pattern on the wire.
+    pub fn id(self) -> u8 {
+        self as u8
+    }
+
+    pub fn from_id(id: u8) -> Option<Self> {
+        match id {
+            1 => Some(RumblePattern::Pulse),
+            2 => Some(RumblePattern::Ramp),
+            3 => Some(RumblePattern::Heartbeat),
+            _ => None,
+        }
+    }
+}
+
+/// A pattern played with its steps scaled by a strength, from 0 to 1.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Rumble {
+    pub pattern: RumblePattern,
+    pub strength: f32,
+}
+
+impl Rumble {
+    pub fn new(pattern: RumblePattern, strength: f32) -> Self {
+        Self {
+            pattern,
+            strength: strength.clamp(0.0, 1.0),
+        }
+    }
+}
+
+/// What to do with the motors, see `RumbleSequencer::update`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum RumbleCommand {
+    Play { strength: f32, duration: Duration },
+    Stop,
+}
+
+/// Something asked of one controller's motors, see `HapticsController`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum HapticsRequest {
+    /// Rumble at `strength`, from 0 to 1, for `duration`, replacing what the
+    /// controller is playing.
+    Rumble {
+        controller: u32,
+        strength: f32,
+        duration: Duration,
+    },
+    Stop {
+        controller: u32,
+    },
+}
+
+// Strengths are clamped when requested, so never NaN.
+impl Eq for HapticsRequest {}
+
+/// Requests for controllers' motors, by the id the controller was added with,
+/// see `DeviceEvent::GameControllerAdded`. Played by the platform on the next
+/// frame, which reports each as an `EngineEvent::Haptics`. Requests for
+/// controllers that aren't connected, or can't rumble, are ignored.
+#[derive(Debug, Default)]
+pub struct HapticsController {
+    requests: Vec<HapticsRequest>,
+}
+
+impl HapticsController {
+    pub fn play_rumble(&mut self, controller_id: u32, strength: f32, duration: Duration) {
+        self.requests.push(HapticsRequest::Rumble {
+            controller: controller_id,
+            strength: if strength.is_nan() {
+                0.0
+            } else {
+                strength.clamp(0.0, 1.0)
+            },
+            duration,
+        });
+    }
+
+    pub fn stop(&mut self, controller_id: u32) {
+        self.requests.push(HapticsRequest::Stop {
+            controller: controller_id,
+        });
+    }
+
+    /// The requests made since they were last taken, oldest first.
+    pub fn take_requests(&mut self) -> Vec<HapticsRequest> {
+        std::mem::take(&mut self.requests)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct RumbleSequencer {
+    playing: Option<Playing>,
+    last_started: Option<Instant>,
+}
+
+#[derive(Debug)]
+struct Playing {
+    rumble: Rumble,
+    step: usize,
+    step_started: Option<Instant>,
+}
+
+impl RumbleSequencer {
+    /// Start playing `rumble`, replacing anything playing, unless a pattern
+    /// was started less than `MIN_RUMBLE_INTERVAL` ago. Returns whether it
+    /// was started.
+    pub fn play(&mut self, rumble: Rumble, now: Instant) -> bool {
+        if matches!(self.last_started, Some(started) if now.duration_since(started) < MIN_RUMBLE_INTERVAL)
+        {
+            return false;
+        }
+        self.last_started = Some(now);
+        self.playing = Some(Playing {
+            rumble,
+            step: 0,
+            step_started: None,
+        });
+        true
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.playing.is_some()
+    }
+
+    /// Advance the pattern being played, returning a command when the
+    /// motors need to change.
+    pub fn update(&mut self, now: Instant) -> Option<RumbleCommand> {
+        let playing = self.playing.as_mut()?;
+        let steps = playing.rumble.pattern.steps();
+        if let Some(started) = playing.step_started {
+            if now.duration_since(started) < steps[playing.step].duration {
+                return None;
+            }
+            playing.step += 1;
+        }
+        match steps.get(playing.step) {
+            Some(step) => {
+                playing.step_started = Some(now);
+                Some(RumbleCommand::Play {
+                    strength: step.strength * playing.rumble.strength,
+                    duration: step.duration,
+                })
+            }
+            None => {
+                self.playing = None;
+                Some(RumbleCommand::Stop)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn haptics_requests_are_clamped_and_taken_in_order() {
+        let mut haptics = HapticsController::default();
+        haptics.play_rumble(1, 2.0, Duration::from_millis(100));
+        haptics.play_rumble(0, f32::NAN, Duration::from_millis(50));
+        haptics.stop(1);
+        assert_eq!(
+            haptics.take_requests(),
+            [
+                HapticsRequest::Rumble {
+                    controller: 1,
+                    strength: 1.0,
+                    duration: Duration::from_millis(100),
+                },
+                HapticsRequest::Rumble {
+                    controller: 0,
+                    strength: 0.0,
+                    duration: Duration::from_millis(50),
+                },
+                HapticsRequest::Stop { controller: 1 },
+            ]
+        );
+        assert!(haptics.take_requests().is_empty());
+    }
+
+    #[test]
+    fn sequencer_plays_steps_and_rate_limits() {
+        let start = Instant::now();
+        let at = |millis| start + Duration::from_millis(millis);
+        let mut sequencer = RumbleSequencer::default();
+        assert!(sequencer.play(Rumble::new(RumblePattern::Heartbeat, 0.5), start));
+        assert_eq!(
+            sequencer.update(at(0)),
+            Some(RumbleCommand::Play {
+                strength: 0.5,
+                duration: Duration::from_millis(90),
+            })
+        );
+        assert_eq!(sequencer.update(at(50)), None);
+        assert!(matches!(
+            sequencer.update(at(90)),
+            Some(RumbleCommand::Play { strength, .. }) if strength == 0.0
+        ));
+
+        // Too soon after the heartbeat started.
+        assert!(!sequencer.play(Rumble::new(RumblePattern::Pulse, 1.0), at(100)));
+        assert!(sequencer.play(Rumble::new(RumblePattern::Pulse, 1.0), at(200)));
+        assert!(matches!(
+            sequencer.update(at(200)),
+            Some(RumbleCommand::Play { strength, .. }) if strength == 1.0
+        ));
+        assert_eq!(sequencer.update(at(320)), Some(RumbleCommand::Stop));
+        assert!(!sequencer.is_playing());
+        assert_eq!(sequencer.update(at(400)), None);
+
+        for pattern in [
+            RumblePattern::Pulse,
+            RumblePattern::Ramp,
+            RumblePattern::Heartbeat,
+        ] {
+            assert_eq!(RumblePattern::from_id(pattern.id()), Some(pattern));
+        }
+        assert_eq!(
+            RumblePattern::Heartbeat.duration(),
+            Duration::from_millis(690)
+        );
+    }
+}
@@ -1,7 +1,13 @@
 //! Implements input and related events and errors.
 
+pub mod accumulate;
+pub mod assignment;
+pub mod calibration;
+pub mod haptics;
+pub mod macros;
+
 /// Input state descriptor.
-#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 #[repr(u8)]
 pub enum Button {
     Left = 0,
@@ -26,6 +32,59 @@ pub enum InputEvent {
     ButtonPressed(u8, Button),
     ButtonReleased(u8, Button),
     AxisMotion(u8, u8, i8),
+    /// Relative mouse motion in counts, summed over the events pumped in a
+    /// frame. Only produced while relative mouse mode is active.
+    MouseMotion(i32, i32),
+    /// Where the cursor moved to in the focused window, in its logical
+    /// coordinates from the top left, as of the last motion pumped in a frame.
+    /// Not produced while relative mouse mode is active.
+    MouseMoved(i32, i32),
+    /// A mouse button was pressed, or released if false.
+    MouseButton(MouseButton, bool),
+    /// Wheel steps scrolled, right and away from the user positive.
+    MouseWheel(i32, i32),
+    /// Function key F1 to F12, by number, was pressed, or released if false.
+    /// Shortcuts such as taking a screenshot, rather than game controls.
+    FunctionKey(u8, bool),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum MouseButton {
+    Left,
+    Middle,
+    Right,
+    /// The first and second side buttons.
+    X1,
+    X2,
+}
+
+/// Turns relative mouse motion into camera look.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct MouseLook {
+    /// Radians turned per count of mouse motion.
+    pub sensitivity: f32,
+    /// Look down when the mouse moves up.
+    pub invert_y: bool,
+}
+
+impl Default for MouseLook {
+    fn default() -> Self {
+        Self {
+            sensitivity: 0.002,
+            invert_y: false,
+        }
+    }
+}
+
+impl MouseLook {
+    /// Yaw and pitch in radians for mouse motion, positive pitch looking up.
+    pub fn look_delta(&self, dx: i32, dy: i32) -> (f32, f32) {
+        let pitch = if self.invert_y { dy } else { -dy };
+        (
+            dx as f32 * self.sensitivity,
+            pitch as f32 * self.sensitivity,
+        )
+    }
 }
 
 #[derive(Debug, PartialEq, Eq, Copy, Clone)]
@@ -49,6 +108,13 @@ pub enum EngineEvent {
     /// Input events
     Input(InputEvent),
 
+    /// The size of the window at this index changed.
+    WindowResized(usize),
+
+    /// Force feedback played on a controller, asked for through
+    /// `haptics::HapticsController`.
+    Haptics(haptics::HapticsRequest),
+
     /// Game loop should break and we should exit.
     ExitToDesktop,
 }
@@ -60,7 +126,7 @@ pub mod wire {
 
     use super::*;
 
-    #[derive(Debug, Default, Copy, Clone, Pod, Zeroable)]
+    #[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Pod, Zeroable)]
     #[repr(C)]
     pub struct Axis {
         value: i8,
@@ -68,7 +134,7 @@ pub mod wire {
 
     /// Wire representation of a controller with axes and buttons. Scaled down
     /// data types are used for compact representation.
-    #[derive(Debug, Default, Copy, Clone, Pod, Zeroable)]
+    #[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Pod, Zeroable)]
     #[repr(C)]
     pub struct InputState {
         id: u8,
@@ -87,6 +153,29 @@ pub mod wire {
             }
         }
 
+        /// Reassemble a state from its parts, see `id`, `axis_values` and
+        /// `buttons`.
+        pub fn from_parts(id: u8, axes: [i8; 7], buttons: u16) -> Self {
+            Self {
+                id,
+                axes: axes.map(|value| Axis { value }),
+                buttons,
+            }
+        }
+
+        pub fn id(&self) -> u8 {
+            self.id
+        }
+
+        pub fn axis_values(&self) -> [i8; 7] {
+            self.axes.map(|axis| axis.value)
+        }
+
+        /// Button states as bits, indexed by `Button`.
+        pub fn buttons(&self) -> u16 {
+            self.buttons
+        }
+
         /// Update the state from a given `InputEvent`.
         pub fn update_from_event(&mut self, event: &InputEvent) {
             match event {
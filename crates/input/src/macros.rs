@@ -0,0 +1,224 @@
+//! Input macros: the local player's input recorded a tick at a time and played
+//! back later, so a bug that needs a precise sequence of movements can be
+//! reproduced on demand.
+//!
+//! Each tick is recorded as a single state, see `HeldInput::state`. Input is
+//! fed to the simulation as that state while recording too, so a recording
+//! plays back exactly as it was recorded, as long as the world starts out the
+//! same.
+//!
+//! [`HeldInput::state`]: crate::accumulate::HeldInput::state
+
+use std::path::Path;
+use std::{fs, io};
+
+use crate::wire::InputState;
+
+/// Start of an input macro file, followed by a state per tick.
+const MAGIC: &[u8; 4] = b"NIM1";
+/// Bytes of a tick in a file: id, axes, then buttons little endian.
+const TICK_LEN: usize = 10;
+
+#[derive(thiserror::Error, Debug)]
+pub enum MacroError {
+    #[error("unable to read or write an input macro: {0}")]
+    Io(#[from] io::Error),
+    #[error("not an input macro")]
+    NotAMacro,
+    #[error("input macro is cut off in the middle of a tick")]
+    Truncated,
+}
+
+/// Input for consecutive ticks, oldest first.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct InputMacro {
+    ticks: Vec<InputState>,
+}
+
+impl InputMacro {
+    pub fn ticks(&self) -> &[InputState] {
+        &self.ticks
+    }
+
+    pub fn push(&mut self, state: InputState) {
+        self.ticks.push(state);
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(MAGIC.len() + self.ticks.len() * TICK_LEN);
+        bytes.extend_from_slice(MAGIC);
+        for state in self.ticks.iter() {
+            bytes.push(state.id());
+            bytes.extend(state.axis_values().map(|value| value as u8));
+            bytes.extend_from_slice(&state.buttons().to_le_bytes());
+        }
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, MacroError> {
+        let ticks = bytes.strip_prefix(MAGIC).ok_or(MacroError::NotAMacro)?;
+        if ticks.len() % TICK_LEN != 0 {
+            return Err(MacroError::Truncated);
+        }
+        let ticks = ticks
+            .chunks_exact(TICK_LEN)
+            .map(|tick| {
+                let mut axes = [0; 7];
+                for (axis, byte) in axes.iter_mut().zip(&tick[1..8]) {
+                    *axis = *byte as i8;
+                }
+                InputState::from_parts(tick[0], axes, u16::from_le_bytes([tick[8], tick[9]]))
+            })
+            .collect();
+        Ok(Self { ticks })
+    }
+
+    pub fn load(path: &Path) -> Result<Self, MacroError> {
+        Self::from_bytes(&fs::read(path)?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), MacroError> {
+        fs::write(path, self.to_bytes())?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default)]
+enum Mode {
+    #[default]
+    Idle,
+    Recording(InputMacro),
+    Playing {
+        input_macro: InputMacro,
+        next: usize,
+    },
+}
+
+/// Records the local player's input, or plays a macro back in its place, a
+/// tick at a time.
+#[derive(Debug, Default)]
+pub struct InputMacros {
+    mode: Mode,
+}
+
+impl InputMacros {
+    pub fn is_recording(&self) -> bool {
+        matches!(self.mode, Mode::Recording(_))
+    }
+
+    pub fn is_playing(&self) -> bool {
+        matches!(self.mode, Mode::Playing { .. })
+    }
+
+    /// Start recording, dropping anything being recorded or played.
+    pub fn record(&mut self) {
+        self.mode = Mode::Recording(InputMacro::default());
+    }
+
+    /// Start playing a macro from its first tick, dropping anything being
+    /// recorded or played.
+    pub fn play(&mut self, input_macro: InputMacro) {
+        self.mode = Mode::Playing {
+            input_macro,
+            next: 0,
+        };
+    }
+
+    /// Stop recording or playing, returning what was recorded.
+    pub fn stop(&mut self) -> Option<InputMacro> {
+        match std::mem::take(&mut self.mode) {
+            Mode::Recording(input_macro) => Some(input_macro),
+            _ => None,
+        }
+    }
+
+    /// Ticks recorded so far, or left to play.
+    pub fn remaining(&self) -> usize {
+        match &self.mode {
+            Mode::Idle => 0,
+            Mode::Recording(input_macro) => input_macro.ticks.len(),
+            Mode::Playing { input_macro, next } => input_macro.ticks.len() - next,
+        }
+    }
+
+    /// The input a tick should use in place of `state`, the local player's,
+    /// recording it if recording. None when neither recording nor playing,
+    /// or once the macro being played has ended.
+    pub fn tick(&mut self, state: InputState) -> Option<InputState> {
+        match &mut self.mode {
+            Mode::Idle => None,
+            Mode::Recording(input_macro) => {
+                input_macro.push(state);
+                Some(state)
+            }
+            Mode::Playing { input_macro, next } => match input_macro.ticks.get(*next) {
+                Some(state) => {
+                    *next += 1;
+                    Some(*state)
+                }
+                None => {
+                    self.mode = Mode::Idle;
+                    None
+                }
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Button, InputEvent};
+
+    fn pressed(button: Button) -> InputState {
+        let mut state = InputState::new(0);
+        state.update_from_event(&InputEvent::KeyPressed(button));
+        state
+    }
+
+    #[test]
+    fn macros_survive_a_round_trip_through_bytes() {
+        let mut input_macro = InputMacro::default();
+        input_macro.push(pressed(Button::Up));
+        input_macro.push(InputState::from_parts(
+            1,
+            [-128, -1, 0, 1, 2, 3, 127],
+            0x8001,
+        ));
+        let bytes = input_macro.to_bytes();
+        assert_eq!(InputMacro::from_bytes(&bytes).unwrap(), input_macro);
+
+        assert!(matches!(
+            InputMacro::from_bytes(b"macro"),
+            Err(MacroError::NotAMacro)
+        ));
+        assert!(matches!(
+            InputMacro::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(MacroError::Truncated)
+        ));
+    }
+
+    #[test]
+    fn recorded_ticks_play_back_in_order() {
+        let mut macros = InputMacros::default();
+        assert_eq!(macros.tick(pressed(Button::Up)), None);
+
+        macros.record();
+        for button in [Button::Up, Button::Left, Button::Down] {
+            assert_eq!(macros.tick(pressed(button)), Some(pressed(button)));
+        }
+        let recorded = macros.stop().expect("was recording");
+        assert_eq!(recorded.ticks().len(), 3);
+
+        macros.play(recorded);
+        assert!(macros.is_playing());
+        let idle = InputState::new(0);
+        assert_eq!(macros.tick(idle), Some(pressed(Button::Up)));
+        assert_eq!(macros.remaining(), 2);
+        assert_eq!(macros.tick(idle), Some(pressed(Button::Left)));
+        assert_eq!(macros.tick(idle), Some(pressed(Button::Down)));
+        assert_eq!(macros.tick(idle), None);
+        assert!(!macros.is_playing());
+        assert_eq!(macros.stop(), None);
+    }
+}
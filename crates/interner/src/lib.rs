@@ -0,0 +1,221 @@
+//! Strings interned for the life of the process, such as asset paths and
+//! logger tags, so that they're copied, compared and hashed as ids.
+//!
+//! Interned strings are never freed. Reading a symbol's string doesn't lock;
+//! interning takes a read lock, and a write lock the first time a string is
+//! seen. Symbol ids are stable for the life of the process, in the order
+//! strings were first interned, but mean nothing to another process.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+use std::sync::{OnceLock, PoisonError, RwLock, RwLockReadGuard};
+
+/// Slots in the first segment of interned strings, each segment after it has
+/// twice as many as the one before.
+const FIRST_SEGMENT_LEN: usize = 64;
+/// Enough segments for an id of every u32, bar the last `FIRST_SEGMENT_LEN`.
+const SEGMENTS: usize = 26;
+
+/// An interned string.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Symbol(u32);
+
+impl Symbol {
+    /// The symbol of `string`, interning it if it hasn't been yet.
+    pub fn intern(string: &str) -> Symbol {
+        interner().intern(string)
+    }
+
+    /// The symbol of a path, interned as its string, lossily for paths that
+    /// aren't UTF-8.
+    pub fn intern_path(path: impl AsRef<Path>) -> Symbol {
+        Symbol::intern(&path.as_ref().to_string_lossy())
+    }
+
+    /// The symbol of `string` if it's been interned, without interning it.
+    pub fn lookup(string: &str) -> Option<Symbol> {
+        interner().lookup(string)
+    }
+
+    pub fn as_str(self) -> &'static str {
+        interner().resolve(self)
+    }
+
+    pub fn as_path(self) -> &'static Path {
+        Path::new(self.as_str())
+    }
+
+    /// The id of this symbol, which is the number of strings interned before
+    /// it.
+    pub fn id(self) -> u32 {
+        self.0
+    }
+}
+
+impl From<&str> for Symbol {
+    fn from(string: &str) -> Self {
+        Symbol::intern(string)
+    }
+}
+
+impl AsRef<str> for Symbol {
+    fn as_ref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl AsRef<Path> for Symbol {
+    fn as_ref(&self) -> &Path {
+        self.as_path()
+    }
+}
+
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// The number of strings interned so far.
+pub fn interned_count() -> usize {
+    interner().ids().len()
+}
+
+struct Interner {
+    ids: RwLock<HashMap<&'static str, Symbol>>,
+    /// Interned strings by id, in segments that are allocated as they're
+    /// needed and never move, so they're read without locking.
+    segments: [OnceLock<Box<[OnceLock<&'static str>]>>; SEGMENTS],
+}
+
+fn interner() -> &'static Interner {
+    static INTERNER: OnceLock<Interner> = OnceLock::new();
+    INTERNER.get_or_init(|| Interner {
+        ids: RwLock::default(),
+        segments: std::array::from_fn(|_| OnceLock::new()),
+    })
+}
+
+impl Interner {
+    fn ids(&self) -> RwLockReadGuard<'_, HashMap<&'static str, Symbol>> {
+        // Strings are only ever inserted whole.
+        self.ids.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn lookup(&self, string: &str) -> Option<Symbol> {
+        self.ids().get(string).copied()
+    }
+
+    fn intern(&self, string: &str) -> Symbol {
+        if let Some(symbol) = self.lookup(string) {
+            return symbol;
+        }
+        let mut ids = self.ids.write().unwrap_or_else(PoisonError::into_inner);
+        // Another thread may have interned it between the locks.
+        if let Some(&symbol) = ids.get(string) {
+            return symbol;
+        }
+        let id = u32::try_from(ids.len())
+            .ok()
+            .filter(|&id| locate(id).0 < SEGMENTS)
+            .expect("interned more strings than there are symbols");
+        let string: &'static str = Box::leak(string.into());
+        let (segment, index) = locate(id);
+        let slots = self.segments[segment].get_or_init(|| {
+            (0..FIRST_SEGMENT_LEN << segment)
+                .map(|_| OnceLock::new())
+                .collect()
+        });
+        slots[index].get_or_init(|| string);
+        let symbol = Symbol(id);
+        ids.insert(string, symbol);
+        symbol
+    }
+
+    fn resolve(&self, symbol: Symbol) -> &'static str {
+        let (segment, index) = locate(symbol.0);
+        // Symbols are only made by interning, which stores the string first.
+        self.segments[segment]
+            .get()
+            .and_then(|slots| slots[index].get())
+            .expect("symbol without a string")
+    }
+}
+
+/// The segment of the string with `id`, and its index in the segment.
+fn locate(id: u32) -> (usize, usize) {
+    let n = id as u64 + FIRST_SEGMENT_LEN as u64;
+    let segment = n.ilog2() - FIRST_SEGMENT_LEN.ilog2();
+    (
+        segment as usize,
+        (n - ((FIRST_SEGMENT_LEN as u64) << segment)) as usize,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    fn locates_ids_in_segments() {
+        assert_eq!(locate(0), (0, 0));
+        assert_eq!(locate(63), (0, 63));
+        assert_eq!(locate(64), (1, 0));
+        assert_eq!(locate(191), (1, 127));
+        assert_eq!(locate(192), (2, 0));
+        assert_eq!(locate(u32::MAX - 64), (SEGMENTS - 1, (64 << 25) - 1));
+        assert_eq!(locate(u32::MAX).0, SEGMENTS);
+    }
+
+    #[test]
+    fn interns_strings_once() {
+        let a = Symbol::intern("interner.test.a");
+        assert_eq!(Symbol::intern("interner.test.a"), a);
+        assert_ne!(Symbol::intern("interner.test.b"), a);
+        assert_eq!(a.as_str(), "interner.test.a");
+        assert_eq!(a.to_string(), "interner.test.a");
+        assert_eq!(format!("{a:?}"), "\"interner.test.a\"");
+        assert_eq!(
+            Symbol::intern_path(Path::new("assets/models/ico.obj")).as_path(),
+            Path::new("assets/models/ico.obj")
+        );
+
+        assert_eq!(Symbol::lookup("interner.test.c"), None);
+        let c = Symbol::intern("interner.test.c");
+        assert_eq!(Symbol::lookup("interner.test.c"), Some(c));
+        assert!(interned_count() > c.id() as usize);
+    }
+
+    #[test]
+    fn interns_across_threads_and_segments() {
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                thread::spawn(|| {
+                    (0..300)
+                        .map(|i| Symbol::intern(&format!("interner.threads.{i}")))
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        let symbols: Vec<_> = threads
+            .into_iter()
+            .map(|thread| thread.join().unwrap())
+            .collect();
+        for other in &symbols[1..] {
+            assert_eq!(other, &symbols[0]);
+        }
+        for (i, symbol) in symbols[0].iter().enumerate() {
+            assert_eq!(symbol.as_str(), format!("interner.threads.{i}"));
+            assert_eq!(Symbol::lookup(symbol.as_str()), Some(*symbol));
+        }
+    }
+}
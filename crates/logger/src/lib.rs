@@ -1,7 +1,10 @@
+use std::collections::HashMap;
 use std::fmt;
 use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
 
+use interner::Symbol;
 use serde::{Deserialize, Serialize};
 
 // Define LogLevel enum
@@ -43,9 +46,21 @@ impl fmt::Display for LogLevel {
 }
 
 impl LogLevel {
+    pub const ALL: [LogLevel; 5] = [
+        LogLevel::Error,
+        LogLevel::Warn,
+        LogLevel::Info,
+        LogLevel::Debug,
+        LogLevel::Trace,
+    ];
+
     pub fn logger(self) -> Logger {
         Logger::new(self)
     }
+
+    fn index(&self) -> usize {
+        self.clone() as usize
+    }
 }
 
 /// Logger that logs to the console, implemented because the log crate doesn't
@@ -54,15 +69,68 @@ impl LogLevel {
 #[derive(Debug)]
 pub struct Logger {
     pub level: LogLevel,
-    path: Vec<String>,
+    /// Interned, as sub-loggers are made often and print rarely.
+    path: Vec<Symbol>,
     filter: Arc<Mutex<Option<LogFilter>>>,
+    /// Plugins logging through this logger's tree, and their level overrides.
+    plugins: Arc<Mutex<PluginTable>>,
+    /// The plugin this logger and its sub-loggers log for, see
+    /// `Logger::plugin`.
+    plugin: Option<Arc<PluginLog>>,
+}
+
+/// What a plugin has logged, and the level it logs at.
+#[derive(Debug)]
+struct PluginLog {
+    name: String,
+    version: String,
+    /// Overrides the filter and the logger's level for the plugin.
+    level: Mutex<Option<LogLevel>>,
+    /// Messages printed at each level.
+    counts: [AtomicU64; LogLevel::ALL.len()],
+}
+
+#[derive(Debug, Default)]
+struct PluginTable {
+    plugins: Vec<Arc<PluginLog>>,
+    /// Level overrides by plugin name, including plugins not loaded yet.
+    levels: HashMap<String, LogLevel>,
+}
+
+/// Messages a plugin has logged, see `Logger::plugin_stats`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PluginLogStats {
+    pub name: String,
+    pub version: String,
+    pub level: Option<LogLevel>,
+    /// Messages printed at each level, in the order of `LogLevel::ALL`.
+    pub counts: [u64; LogLevel::ALL.len()],
+}
+
+impl PluginLogStats {
+    pub fn total(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+}
+
+impl fmt::Display for PluginLogStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}@{}:", self.name, self.version)?;
+        for (level, count) in LogLevel::ALL.iter().zip(self.counts) {
+            write!(f, " {count} {level:?}")?;
+        }
+        if let Some(level) = &self.level {
+            write!(f, ", logging at {level:?}")?;
+        }
+        Ok(())
+    }
 }
 
 #[derive(Clone, Debug)]
 pub enum LogFilter {
     Level(LogLevel),
-    Tag(String),
-    LevelAndTag(LogLevel, String),
+    Tag(Symbol),
+    LevelAndTag(LogLevel, Symbol),
 }
 
 impl LogFilter {
@@ -71,11 +139,11 @@ impl LogFilter {
     }
 
     pub fn tag(prefix: &str) -> Self {
-        LogFilter::Tag(prefix.to_string())
+        LogFilter::Tag(Symbol::intern(prefix))
     }
 
     pub fn level_and_tag(level: LogLevel, prefix: &str) -> Self {
-        LogFilter::LevelAndTag(level, prefix.to_string())
+        LogFilter::LevelAndTag(level, Symbol::intern(prefix))
     }
 }
 
@@ -104,10 +172,88 @@ impl Logger {
             level,
             path: Vec::new(),
             filter: Arc::new(Mutex::new(None)),
+            plugins: Arc::new(Mutex::new(PluginTable::default())),
+            plugin: None,
         }
     }
 
+    /// A root logger for a plugin, attributing what it and its sub-loggers
+    /// log to it. Shares this logger's filter, and the plugin's messages are
+    /// counted in `plugin_stats`. A plugin loaded again keeps its counts.
+    pub fn plugin(&self, name: &str, version: &str) -> Self {
+        let mut table = self.plugins.lock().unwrap();
+        let plugin = match table.plugins.iter().find(|plugin| plugin.name == name) {
+            Some(plugin) => Arc::clone(plugin),
+            None => {
+                let plugin = Arc::new(PluginLog {
+                    name: name.to_string(),
+                    version: version.to_string(),
+                    level: Mutex::new(table.levels.get(name).cloned()),
+                    counts: Default::default(),
+                });
+                table.plugins.push(Arc::clone(&plugin));
+                plugin
+            }
+        };
+        Logger {
+            level: self.level.clone(),
+            path: vec![Symbol::intern(&format!("{name}@{version}"))],
+            filter: Arc::clone(&self.filter),
+            plugins: Arc::clone(&self.plugins),
+            plugin: Some(plugin),
+        }
+    }
+
+    /// Log a plugin's messages at `level`, regardless of the filter, or as
+    /// usual again with None. Applies to plugins loaded later too.
+    pub fn set_plugin_level(&self, name: &str, level: Option<LogLevel>) {
+        let mut table = self.plugins.lock().unwrap();
+        match &level {
+            Some(level) => table.levels.insert(name.to_string(), level.clone()),
+            None => table.levels.remove(name),
+        };
+        if let Some(plugin) = table.plugins.iter().find(|plugin| plugin.name == name) {
+            *plugin.level.lock().unwrap() = level;
+        }
+    }
+
+    /// What each plugin logging through this logger's tree has logged, in the
+    /// order they were first loaded.
+    pub fn plugin_stats(&self) -> Vec<PluginLogStats> {
+        self.plugins
+            .lock()
+            .unwrap()
+            .plugins
+            .iter()
+            .map(|plugin| PluginLogStats {
+                name: plugin.name.clone(),
+                version: plugin.version.clone(),
+                level: plugin.level.lock().unwrap().clone(),
+                counts: std::array::from_fn(|index| plugin.counts[index].load(Ordering::Relaxed)),
+            })
+            .collect()
+    }
+
+    fn has_tag(&self, tag: Symbol) -> bool {
+        self.path.contains(&tag)
+            || self
+                .plugin
+                .as_ref()
+                .is_some_and(|plugin| plugin.name == tag.as_str())
+    }
+
     pub fn log(&self, item_level: LogLevel, args: fmt::Arguments<'_>) {
+        let plugin_level = self
+            .plugin
+            .as_ref()
+            .and_then(|plugin| plugin.level.lock().unwrap().clone());
+        if let Some(level) = plugin_level {
+            if item_level <= level {
+                self.print(item_level, args);
+            }
+            return;
+        }
+
         if let Some(filter) = self.get_filter() {
             match filter {
                 LogFilter::Level(level) => {
@@ -116,12 +262,12 @@ impl Logger {
                     }
                 }
                 LogFilter::Tag(tag) => {
-                    if self.path.contains(&tag) {
+                    if self.has_tag(tag) {
                         self.print(item_level, args);
                     }
                 }
                 LogFilter::LevelAndTag(level, tag) => {
-                    if level == item_level && self.path.contains(&tag) {
+                    if level == item_level && self.has_tag(tag) {
                         self.print(item_level, args);
                     }
                 }
@@ -135,6 +281,9 @@ impl Logger {
     }
 
     fn print(&self, level: LogLevel, args: fmt::Arguments) {
+        if let Some(plugin) = &self.plugin {
+            plugin.counts[level.index()].fetch_add(1, Ordering::Relaxed);
+        }
         print!("{}(", level);
         for (i, item) in self.path.iter().enumerate() {
             print!("{}{item}", if i > 0 { "." } else { "" }, item = item);
@@ -145,11 +294,13 @@ impl Logger {
     /// Create a new logger with the given name as a sub-logger of this one.
     pub fn sub(&self, name: &str) -> Self {
         let mut path = self.path.clone();
-        path.push(name.to_string());
+        path.push(Symbol::intern(name));
         Logger {
             level: self.level.clone(),
             path,
             filter: Arc::clone(&self.filter),
+            plugins: Arc::clone(&self.plugins),
+            plugin: self.plugin.clone(),
         }
     }
 
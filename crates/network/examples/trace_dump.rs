@@ -0,0 +1,119 @@
+//! Print a message trace written by `network::trace::MessageTrace`, one
+//! message a line, flagging received messages that arrived out of order, twice
+//! or after a gap.
+//!
+//!     cargo run -p network --example trace_dump -- net.trace [connection]
+
+use std::collections::{HashMap, VecDeque};
+use std::process::ExitCode;
+
+use network::trace::{Direction, PayloadKind, TraceReader, TraceRecord};
+use network::{SequenceNumber, MAX_UNACKED_PACKETS};
+
+fn main() -> ExitCode {
+    let mut args = std::env::args().skip(1);
+    let path = match args.next() {
+        Some(path) => path,
+        None => {
+            eprintln!("usage: trace_dump <trace> [connection]");
+            return ExitCode::FAILURE;
+        }
+    };
+    let only = args
+        .next()
+        .and_then(|connection| connection.parse::<u16>().ok());
+
+    let reader = match TraceReader::open(&path) {
+        Ok(reader) => reader,
+        Err(err) => {
+            eprintln!("unable to read {path}: {err}");
+            return ExitCode::FAILURE;
+        }
+    };
+    println!(
+        "trace of {path}, started {:.6}s after the unix epoch",
+        reader.started.as_secs_f64()
+    );
+
+    let mut received = HashMap::new();
+    for record in reader {
+        let record = match record {
+            Ok(record) => record,
+            Err(err) => {
+                eprintln!("stopped reading {path}: {err}");
+                return ExitCode::FAILURE;
+            }
+        };
+        if only.is_some_and(|only| only != record.connection) {
+            continue;
+        }
+        let note = match record.direction {
+            Direction::Sent => String::new(),
+            Direction::Received => received_note(&mut received, &record),
+        };
+        println!("{}{note}", describe(&record));
+    }
+    ExitCode::SUCCESS
+}
+
+fn describe(record: &TraceRecord) -> String {
+    let direction = match record.direction {
+        Direction::Sent => "->",
+        Direction::Received => "<-",
+    };
+    let kind = match record.payload.kind {
+        PayloadKind::Unknown => "?",
+        PayloadKind::Input => "input",
+        PayloadKind::Update => "update",
+    };
+    let codec = record
+        .payload
+        .codec
+        .map_or(String::new(), |codec| format!(" ({codec})"));
+    format!(
+        "{:>14.6}ms #{:<3} {direction} seq {:>5} ack {:>5} bits {:032b} len {:>4} \
+         reliable {:>4} {kind}{codec}",
+        record.at.as_secs_f64() * 1000.0,
+        record.connection,
+        record.seq.0,
+        record.ack.0,
+        record.ack_bits,
+        record.len,
+        record.payload.reliable_len,
+    )
+}
+
+/// What's been received over a connection.
+#[derive(Default)]
+struct Received {
+    newest: Option<SequenceNumber>,
+    /// As far back as acks reach.
+    recent: VecDeque<SequenceNumber>,
+}
+
+fn received_note(received: &mut HashMap<u16, Received>, record: &TraceRecord) -> String {
+    let seq = record.seq;
+    let received = received.entry(record.connection).or_default();
+    if received.recent.contains(&seq) {
+        return "  DUPLICATE".to_string();
+    }
+    if received.recent.len() == MAX_UNACKED_PACKETS {
+        received.recent.pop_front();
+    }
+    received.recent.push_back(seq);
+    let newest = match received.newest {
+        Some(newest) if !seq.is_newer_than(newest) => {
+            return format!("  OUT OF ORDER, {} behind", newest.0.wrapping_sub(seq.0));
+        }
+        Some(newest) => newest,
+        None => {
+            received.newest = Some(seq);
+            return String::new();
+        }
+    };
+    received.newest = Some(seq);
+    match seq.0.wrapping_sub(newest.0) {
+        1 => String::new(),
+        ahead => format!("  GAP, {} missing", ahead - 1),
+    }
+}
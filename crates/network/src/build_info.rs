@@ -0,0 +1,150 @@
+//! What a peer was built from, exchanged in the handshake so a client and
+//! server built from different sources find out when they connect, rather
+//! than desyncing over subtly different wire formats or simulations.
+//!
+//! Builds are compatible when their versions and git hashes match. Their
+//! profiles and features are shown alongside, to tell the builds apart, but
+//! don't change what goes over the wire.
+
+use std::fmt;
+
+/// Stands for a git hash that wasn't known when building, such as outside a
+/// git checkout. Builds without one are only compared by version.
+pub const UNKNOWN: &str = "unknown";
+
+/// Separates the fields of an encoded `BuildInfo`, which can't contain it.
+const FIELD_SEPARATOR: u8 = b'\n';
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BuildInfo {
+    /// The engine's crate version.
+    pub version: String,
+    /// Short hash of the commit built, with `-dirty` appended when there
+    /// were uncommitted changes.
+    pub git_hash: String,
+    /// UTC date of the build, as `YYYY-MM-DD`.
+    pub build_date: String,
+    /// Cargo profile, such as `debug` or `release`.
+    pub profile: String,
+    /// Cargo features the engine was built with.
+    pub features: Vec<String>,
+}
+
+impl Default for BuildInfo {
+    fn default() -> Self {
+        Self {
+            version: UNKNOWN.to_string(),
+            git_hash: UNKNOWN.to_string(),
+            build_date: UNKNOWN.to_string(),
+            profile: UNKNOWN.to_string(),
+            features: Vec::new(),
+        }
+    }
+}
+
+impl BuildInfo {
+    /// Whether a peer built as `other` can sync with this one.
+    pub fn is_compatible(&self, other: &BuildInfo) -> bool {
+        self.version == other.version
+            && (self.git_hash == other.git_hash
+                || self.git_hash == UNKNOWN
+                || other.git_hash == UNKNOWN)
+    }
+
+    /// Append this build to a handshake in `buf`, read back by `decode`.
+    pub fn encode_into(&self, buf: &mut Vec<u8>) {
+        let fields = [
+            &self.version,
+            &self.git_hash,
+            &self.build_date,
+            &self.profile,
+            &self.features.join(","),
+        ];
+        for (i, field) in fields.into_iter().enumerate() {
+            if i > 0 {
+                buf.push(FIELD_SEPARATOR);
+            }
+            buf.extend(field.bytes().filter(|byte| *byte != FIELD_SEPARATOR));
+        }
+    }
+
+    /// Read a build written by `encode_into`. Trailing zeroes, padding the
+    /// rest of a payload, are ignored. None when malformed, such as from a
+    /// peer older than build info.
+    pub fn decode(bytes: &[u8]) -> Option<Self> {
+        let end = bytes
+            .iter()
+            .rposition(|byte| *byte != 0)
+            .map_or(0, |last| last + 1);
+        let encoded = std::str::from_utf8(&bytes[..end]).ok()?;
+        let mut fields = encoded.split(FIELD_SEPARATOR as char);
+        let mut next = || fields.next().map(str::to_string);
+        let build = Self {
+            version: next()?,
+            git_hash: next()?,
+            build_date: next()?,
+            profile: next()?,
+            features: next()?
+                .split(',')
+                .filter(|feature| !feature.is_empty())
+                .map(str::to_string)
+                .collect(),
+        };
+        (!build.version.is_empty() && next().is_none()).then_some(build)
+    }
+}
+
+impl fmt::Display for BuildInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} ({}, {} {})",
+            self.version, self.git_hash, self.profile, self.build_date
+        )?;
+        if !self.features.is_empty() {
+            write!(f, " with {}", self.features.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn build(version: &str, git_hash: &str) -> BuildInfo {
+        BuildInfo {
+            version: version.to_string(),
+            git_hash: git_hash.to_string(),
+            build_date: "2024-05-01".to_string(),
+            profile: "release".to_string(),
+            features: vec!["net-sync".to_string(), "world-update".to_string()],
+        }
+    }
+
+    #[test]
+    fn builds_roundtrip_and_are_compared() {
+        let server = build("0.1.0", "1cc5c27");
+        let mut handshake = b"magic".to_vec();
+        server.encode_into(&mut handshake);
+        handshake.resize(64, 0);
+        assert_eq!(BuildInfo::decode(&handshake[5..]), Some(server.clone()));
+        assert_eq!(
+            server.to_string(),
+            "0.1.0 (1cc5c27, release 2024-05-01) with net-sync, world-update"
+        );
+
+        let mut bare = BuildInfo::default();
+        bare.features.clear();
+        let mut encoded = Vec::new();
+        bare.encode_into(&mut encoded);
+        assert_eq!(BuildInfo::decode(&encoded), Some(bare));
+        assert_eq!(BuildInfo::decode(&[]), None);
+        assert_eq!(BuildInfo::decode(b"0.1.0\n1cc5c27"), None);
+
+        assert!(server.is_compatible(&build("0.1.0", "1cc5c27")));
+        assert!(server.is_compatible(&build("0.1.0", UNKNOWN)));
+        assert!(!server.is_compatible(&build("0.1.0", "bff3306")));
+        assert!(!server.is_compatible(&build("0.2.0", "1cc5c27")));
+    }
+}
@@ -0,0 +1,441 @@
+//! Compression codecs for payloads, chosen per deployment to trade CPU for
+//! bandwidth. zstd compresses best, LZ4 is much cheaper at high tick rates,
+//! and no compression costs nothing for peers on a fast link.
+//!
+//! Peers advertise the codecs they can decode in their handshake, and each
+//! compressed payload is tagged with the codec that made it, see
+//! `Compression::id`.
+
+use std::collections::HashMap;
+use std::io::Read;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+use std::{fmt, io};
+
+use crate::PAYLOAD_LEN;
+
+/// zstd level used unless another is given.
+pub const DEFAULT_ZSTD_LEVEL: i32 = 3;
+
+/// Most bytes a payload decompresses to. Payloads fit in a packet and
+/// compress far less than 64 to 1, and the sizes codecs record are read from
+/// the network, so they're checked before anything is allocated for them.
+pub const MAX_DECOMPRESSED_LEN: usize = 64 * PAYLOAD_LEN;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Compression {
+    None,
+    Lz4,
+    /// zstd at a compression level, 1 to 22.
+    Zstd(i32),
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Compression::Zstd(DEFAULT_ZSTD_LEVEL)
+    }
+}
+
+impl Compression {
+    /// Every codec this build can decode, as a mask of `Compression::bit`s.
+    pub const SUPPORTED: u8 = 0b111;
+
+    /// Tag for payloads made with this codec. Zero isn't used, so zeroed
+    /// padding never reads as a codec.
+    pub fn id(self) -> u8 {
+        match self {
+            Compression::None => 1,
+            Compression::Lz4 => 2,
+            Compression::Zstd(_) => 3,
+        }
+    }
+
+    /// The codec to decode a payload tagged with `id`.
+    pub fn from_id(id: u8) -> Option<Self> {
+        match id {
+            1 => Some(Compression::None),
+            2 => Some(Compression::Lz4),
+            3 => Some(Compression::default()),
+            _ => None,
+        }
+    }
+
+    /// This codec's bit in a mask of supported codecs.
+    pub fn bit(self) -> u8 {
+        1 << (self.id() - 1)
+    }
+
+    /// The codec to use with a peer that supports the codecs in `mask`: this
+    /// one if it can, otherwise the default.
+    pub fn negotiate(self, mask: u8) -> Self {
+        if mask & self.bit() != 0 {
+            self
+        } else {
+            Compression::default()
+        }
+    }
+
+    pub fn compress(self, bytes: &[u8]) -> io::Result<Vec<u8>> {
+        let mut compressed = Vec::new();
+        self.compress_into(bytes, &mut compressed, &mut ZstdContexts::default())?;
+        Ok(compressed)
+    }
+
+    pub fn decompress(self, bytes: &[u8]) -> io::Result<Vec<u8>> {
+        let mut decompressed = Vec::new();
+        self.decompress_into(bytes, &mut decompressed, &mut ZstdContexts::default())?;
+        Ok(decompressed)
+    }
+
+    /// Compress `bytes` onto the end of `out`, which only allocates if it
+    /// doesn't have the room.
+    fn compress_into(
+        self,
+        bytes: &[u8],
+        out: &mut Vec<u8>,
+        zstd: &mut ZstdContexts,
+    ) -> io::Result<()> {
+        match self {
+            Compression::None => out.extend_from_slice(bytes),
+            Compression::Lz4 => {
+                // The same framing as `lz4_flex::compress_prepend_size`.
+                let start = out.len();
+                out.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+                out.resize(
+                    start + 4 + lz4_flex::block::get_maximum_output_size(bytes.len()),
+                    0,
+                );
+                let len = lz4_flex::compress_into(bytes, &mut out[start + 4..])
+                    .map_err(io::Error::other)?;
+                out.truncate(start + 4 + len);
+            }
+            Compression::Zstd(level) => {
+                out.reserve(zstd::zstd_safe::compress_bound(bytes.len()));
+                let start = out.len() as u64;
+                let mut cursor = io::Cursor::new(out);
+                cursor.set_position(start);
+                zstd.compressor(level)?
+                    .compress_to_buffer(bytes, &mut cursor)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Decompress `bytes` onto the end of `out`, see `compress_into`.
+    fn decompress_into(
+        self,
+        bytes: &[u8],
+        out: &mut Vec<u8>,
+        zstd: &mut ZstdContexts,
+    ) -> io::Result<()> {
+        let invalid = |err| io::Error::new(io::ErrorKind::InvalidData, err);
+        match self {
+            Compression::None => out.extend_from_slice(bytes),
+            Compression::Lz4 => {
+                let (len, compressed) =
+                    lz4_flex::block::uncompressed_size(bytes).map_err(invalid)?;
+                check_decompressed_len(len)?;
+                let start = out.len();
+                out.resize(start + len, 0);
+                let len =
+                    lz4_flex::decompress_into(compressed, &mut out[start..]).map_err(invalid)?;
+                out.truncate(start + len);
+            }
+            Compression::Zstd(_) => match zstd::zstd_safe::get_frame_content_size(bytes) {
+                Ok(Some(len)) => {
+                    check_decompressed_len(usize::try_from(len).unwrap_or(usize::MAX))?;
+                    out.reserve(len as usize);
+                    let start = out.len() as u64;
+                    let mut cursor = io::Cursor::new(out);
+                    cursor.set_position(start);
+                    zstd.decompressor()?
+                        .decompress_to_buffer(bytes, &mut cursor)?;
+                }
+                // Streamed frames don't record their size, so they're cut off
+                // just past the most they may decompress to.
+                _ => {
+                    let start = out.len();
+                    zstd::stream::read::Decoder::new(bytes)?
+                        .take(MAX_DECOMPRESSED_LEN as u64 + 1)
+                        .read_to_end(out)?;
+                    if let Err(err) = check_decompressed_len(out.len() - start) {
+                        out.truncate(start);
+                        return Err(err);
+                    }
+                }
+            },
+        }
+        Ok(())
+    }
+}
+
+fn check_decompressed_len(len: usize) -> io::Result<()> {
+    if len > MAX_DECOMPRESSED_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "payload decompresses to {len} bytes, at most {MAX_DECOMPRESSED_LEN} are allowed"
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// zstd contexts, made when first needed and reused for every payload after,
+/// rather than made and freed for each.
+#[derive(Default)]
+struct ZstdContexts {
+    compressor: Option<(i32, zstd::bulk::Compressor<'static>)>,
+    decompressor: Option<zstd::bulk::Decompressor<'static>>,
+}
+
+impl ZstdContexts {
+    fn compressor(&mut self, level: i32) -> io::Result<&mut zstd::bulk::Compressor<'static>> {
+        match &mut self.compressor {
+            Some((current, _)) if *current == level => {}
+            Some((current, compressor)) => {
+                compressor.set_compression_level(level)?;
+                *current = level;
+            }
+            None => self.compressor = Some((level, zstd::bulk::Compressor::new(level)?)),
+        }
+        Ok(&mut self.compressor.as_mut().unwrap().1)
+    }
+
+    fn decompressor(&mut self) -> io::Result<&mut zstd::bulk::Decompressor<'static>> {
+        if self.decompressor.is_none() {
+            self.decompressor = Some(zstd::bulk::Decompressor::new()?);
+        }
+        Ok(self.decompressor.as_mut().unwrap())
+    }
+}
+
+impl fmt::Debug for ZstdContexts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ZstdContexts")
+            .field("level", &self.compressor.as_ref().map(|(level, _)| level))
+            .finish_non_exhaustive()
+    }
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Compression::None => write!(f, "none"),
+            Compression::Lz4 => write!(f, "lz4"),
+            Compression::Zstd(level) => write!(f, "zstd:{level}"),
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+#[error("unknown compression {0:?}, expected none, lz4, zstd or zstd:<level>")]
+pub struct ParseCompressionError(String);
+
+impl FromStr for Compression {
+    type Err = ParseCompressionError;
+
+    /// Parse "none", "lz4", "zstd" or "zstd:<level>".
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || ParseCompressionError(s.to_string());
+        match s.split_once(':') {
+            None if s == "none" => Ok(Compression::None),
+            None if s == "lz4" => Ok(Compression::Lz4),
+            None if s == "zstd" => Ok(Compression::default()),
+            Some(("zstd", level)) => match level.parse() {
+                Ok(level) if (1..=22).contains(&level) => Ok(Compression::Zstd(level)),
+                _ => Err(err()),
+            },
+            _ => Err(err()),
+        }
+    }
+}
+
+/// Bytes in and out of a codec, and the time it took.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct CodecStats {
+    pub payloads: u64,
+    /// Bytes before compression, or after decompression.
+    pub raw_bytes: u64,
+    pub compressed_bytes: u64,
+    pub time: Duration,
+}
+
+impl CodecStats {
+    /// Raw bytes per compressed byte, higher is better.
+    pub fn ratio(&self) -> f32 {
+        if self.compressed_bytes == 0 {
+            1.0
+        } else {
+            self.raw_bytes as f32 / self.compressed_bytes as f32
+        }
+    }
+
+    /// Raw megabytes processed per second of codec time.
+    pub fn throughput_mb_s(&self) -> f32 {
+        let secs = self.time.as_secs_f32();
+        if secs == 0.0 {
+            0.0
+        } else {
+            self.raw_bytes as f32 / secs / 1_000_000.0
+        }
+    }
+}
+
+/// Compression and decompression stats for each codec used, so the tradeoff
+/// between them can be measured on a deployment. Also keeps the codecs'
+/// contexts between payloads.
+#[derive(Debug, Default)]
+pub struct CompressionStats {
+    compress: HashMap<Compression, CodecStats>,
+    decompress: HashMap<Compression, CodecStats>,
+    zstd: ZstdContexts,
+}
+
+impl CompressionStats {
+    /// Compress `bytes`, recording how long it took.
+    pub fn compress(&mut self, codec: Compression, bytes: &[u8]) -> io::Result<Vec<u8>> {
+        let mut compressed = Vec::new();
+        self.compress_into(codec, bytes, &mut compressed)?;
+        Ok(compressed)
+    }
+
+    /// Decompress `bytes`, recording how long it took.
+    pub fn decompress(&mut self, codec: Compression, bytes: &[u8]) -> io::Result<Vec<u8>> {
+        let mut decompressed = Vec::new();
+        self.decompress_into(codec, bytes, &mut decompressed)?;
+        Ok(decompressed)
+    }
+
+    /// Compress `bytes` onto the end of `out`, recording how long it took.
+    /// Reusing `out` between payloads saves allocating for each.
+    pub fn compress_into(
+        &mut self,
+        codec: Compression,
+        bytes: &[u8],
+        out: &mut Vec<u8>,
+    ) -> io::Result<()> {
+        let start = Instant::now();
+        let len = out.len();
+        codec.compress_into(bytes, out, &mut self.zstd)?;
+        record(
+            self.compress.entry(codec).or_default(),
+            bytes.len(),
+            out.len() - len,
+            start.elapsed(),
+        );
+        Ok(())
+    }
+
+    /// Decompress `bytes` onto the end of `out`, see `compress_into`.
+    pub fn decompress_into(
+        &mut self,
+        codec: Compression,
+        bytes: &[u8],
+        out: &mut Vec<u8>,
+    ) -> io::Result<()> {
+        let start = Instant::now();
+        let len = out.len();
+        codec.decompress_into(bytes, out, &mut self.zstd)?;
+        record(
+            self.decompress.entry(codec).or_default(),
+            out.len() - len,
+            bytes.len(),
+            start.elapsed(),
+        );
+        Ok(())
+    }
+
+    pub fn compressed(&self) -> impl Iterator<Item = (Compression, CodecStats)> + '_ {
+        self.compress.iter().map(|(codec, stats)| (*codec, *stats))
+    }
+
+    pub fn decompressed(&self) -> impl Iterator<Item = (Compression, CodecStats)> + '_ {
+        self.decompress
+            .iter()
+            .map(|(codec, stats)| (*codec, *stats))
+    }
+}
+
+fn record(stats: &mut CodecStats, raw_bytes: usize, compressed_bytes: usize, time: Duration) {
+    stats.payloads += 1;
+    stats.raw_bytes += raw_bytes as u64;
+    stats.compressed_bytes += compressed_bytes as u64;
+    stats.time += time;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn codecs_roundtrip_and_are_negotiated() {
+        let payload = (0..1024).map(|i| (i % 7) as u8).collect::<Vec<_>>();
+        let mut stats = CompressionStats::default();
+        for codec in [Compression::None, Compression::Lz4, Compression::Zstd(9)] {
+            assert_eq!(codec.to_string().parse(), Ok(codec));
+            let compressed = stats.compress(codec, &payload).unwrap();
+            let decoder = Compression::from_id(codec.id()).unwrap();
+            assert_eq!(stats.decompress(decoder, &compressed).unwrap(), payload);
+            assert_eq!(codec.negotiate(Compression::SUPPORTED), codec);
+        }
+        let (_, lz4) = stats
+            .compressed()
+            .find(|(codec, _)| *codec == Compression::Lz4)
+            .unwrap();
+        assert_eq!((lz4.payloads, lz4.raw_bytes), (1, 1024));
+        assert!(lz4.ratio() > 1.0);
+
+        // Appended after what's already in the buffer, and readable by the
+        // codecs' own framing.
+        for codec in [Compression::Lz4, Compression::Zstd(9)] {
+            let mut buf = vec![0xff];
+            stats.compress_into(codec, &payload, &mut buf).unwrap();
+            assert_eq!(buf[0], 0xff);
+            let framed = match codec {
+                Compression::Lz4 => lz4_flex::decompress_size_prepended(&buf[1..]).unwrap(),
+                _ => zstd::decode_all(&buf[1..]).unwrap(),
+            };
+            assert_eq!(framed, payload);
+            let compressed = buf.split_off(1);
+            stats.decompress_into(codec, &compressed, &mut buf).unwrap();
+            assert_eq!(&buf[1..], payload);
+        }
+        let streamed = zstd::encode_all(&payload[..], 3).unwrap();
+        assert_eq!(
+            Compression::default().decompress(&streamed).unwrap(),
+            payload
+        );
+
+        assert_eq!(Compression::Lz4.negotiate(0), Compression::default());
+        assert_eq!(Compression::from_id(0), None);
+        assert!("zstd:0".parse::<Compression>().is_err());
+        assert!("brotli".parse::<Compression>().is_err());
+    }
+
+    #[test]
+    fn claimed_sizes_are_checked_before_allocating() {
+        let mut stats = CompressionStats::default();
+        // An LZ4 block claiming to decompress to 4GiB.
+        let mut lz4 = u32::MAX.to_le_bytes().to_vec();
+        lz4.extend([0x10, b'a']);
+        assert!(stats.decompress(Compression::Lz4, &lz4).is_err());
+
+        let too_large = vec![0; MAX_DECOMPRESSED_LEN + 1];
+        let sized = zstd::bulk::compress(&too_large, 3).unwrap();
+        assert!(stats.decompress(Compression::default(), &sized).is_err());
+        let streamed = zstd::encode_all(&too_large[..], 3).unwrap();
+        assert!(stats.decompress(Compression::default(), &streamed).is_err());
+
+        let largest = vec![0; MAX_DECOMPRESSED_LEN];
+        for codec in [Compression::Lz4, Compression::default()] {
+            let compressed = stats.compress(codec, &largest).unwrap();
+            assert_eq!(stats.decompress(codec, &compressed).unwrap(), largest);
+        }
+        let streamed = zstd::encode_all(&largest[..], 3).unwrap();
+        assert_eq!(
+            stats.decompress(Compression::default(), &streamed).unwrap(),
+            largest
+        );
+    }
+}
@@ -1,12 +1,24 @@
 //! Implements UDP networking for real-time game data sync. This is essentially
 //! an attempt to implement GafferOnGames' approach to game world sync.
 
+pub mod build_info;
+pub mod compression;
+pub mod loopback;
+pub mod manager;
+pub mod quality;
+pub mod reconnect;
+pub mod reliable;
+pub mod sequence;
+pub mod trace;
+
 use std::io;
 use std::marker::PhantomData;
 use std::mem::size_of;
 use std::time::Duration;
 
 use bytemuck::{AnyBitPattern, NoUninit, PodCastError};
+use quality::QualitySample;
+pub use sequence::SequenceNumber;
 pub const PAYLOAD_LEN: usize = 1024;
 pub const MSG_LEN: usize = size_of::<Message>();
 
@@ -37,6 +49,19 @@ pub enum RpcError {
     PayloadTooLarge(usize),
     #[error("not connected")]
     NotConnected,
+    #[error("malformed packet, {0}")]
+    Malformed(&'static str),
+    #[error("{0} reliable messages are already queued")]
+    ReliableBacklog(usize),
+    #[error("connection has no reliable channel")]
+    Unreliable,
+}
+
+/// Packets sent and received over a connection since it was opened.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct PacketCounts {
+    pub sent: u64,
+    pub received: u64,
 }
 
 #[async_trait::async_trait]
@@ -47,7 +72,49 @@ pub trait Connection {
         &mut self,
         timeout_duration: Duration,
     ) -> Result<Typed<Message>, RpcError>;
-    async fn send(&mut self, payload: &[u8]) -> Result<u16, RpcError>;
+    async fn send(&mut self, payload: &[u8]) -> Result<SequenceNumber, RpcError>;
+
+    /// Round trip time and packet loss measured so far, for connections that
+    /// measure them.
+    fn quality_sample(&self) -> Option<QualitySample> {
+        None
+    }
+
+    /// Packets sent that haven't been acked yet, for connections that track
+    /// acks.
+    fn unacked_packets(&self) -> usize {
+        0
+    }
+
+    /// Packets sent and received so far, for connections that count them.
+    fn packet_counts(&self) -> PacketCounts {
+        PacketCounts::default()
+    }
+
+    /// Sequence numbers of the packets sent that were acked since the last
+    /// call, for connections that track acks.
+    fn take_acked(&mut self) -> Vec<SequenceNumber> {
+        Vec::new()
+    }
+
+    /// The ack and ack bits of the last message sent, for connections that
+    /// number their own messages.
+    fn last_acks_sent(&self) -> Option<(SequenceNumber, u32)> {
+        None
+    }
+
+    /// Queue a message to arrive at the other end, after those sent before
+    /// it, for connections carrying a reliable channel such as
+    /// `reliable::Reliable`.
+    fn send_reliable(&mut self, _message: &[u8]) -> Result<(), RpcError> {
+        Err(RpcError::Unreliable)
+    }
+
+    /// The next message the other end sent reliably, in the order they were
+    /// sent.
+    fn recv_reliable(&mut self) -> Option<Vec<u8>> {
+        None
+    }
 }
 
 trait Tagged {
@@ -109,21 +176,14 @@ where
 #[derive(bytemuck::Pod, bytemuck::Zeroable, Copy, Clone, PartialEq, Debug)]
 #[repr(C)]
 pub struct Message {
-    pub seq: u16,
-    pub ack: u16,
+    pub seq: SequenceNumber,
+    pub ack: SequenceNumber,
     pub ack_bits: u32,
     pub payload: [u8; PAYLOAD_LEN],
 }
 
-pub fn next_seq(current: u16) -> u16 {
-    if current == std::u16::MAX {
-        return 0;
-    }
-    current + 1
-}
-
 impl Message {
-    pub fn new(seq: u16, ack: u16, ack_bits: u32, bytes: &[u8]) -> Self {
+    pub fn new(seq: SequenceNumber, ack: SequenceNumber, ack_bits: u32, bytes: &[u8]) -> Self {
         let mut payload = [0; PAYLOAD_LEN];
         payload[..bytes.len()].copy_from_slice(bytes);
         Self {
@@ -0,0 +1,198 @@
+//! An in-process connection, for running a server and client in one process
+//! without a socket between them, see `LoopbackConnection`.
+
+use std::collections::VecDeque;
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use async_io::Timer;
+
+use crate::quality::QualitySample;
+use crate::{
+    Connection, Message, PacketCounts, RpcError, SequenceNumber, Typed, MAX_UNACKED_PACKETS,
+    PAYLOAD_LEN,
+};
+
+// How often a blocked loopback receive checks for a newly sent message.
+const LOOPBACK_POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+type LoopbackQueue = Arc<Mutex<VecDeque<(Instant, Vec<u8>)>>>;
+
+/// An in-process `Connection`, linked to the other end of the pair created by
+/// `LoopbackConnection::pair`. Messages arrive in order once the simulated
+/// latency has passed, and are never dropped. Used to run a server and client
+/// in one process.
+pub struct LoopbackConnection {
+    seq: SequenceNumber,
+    remote_seq: SequenceNumber,
+    latency: Duration,
+    packets: PacketCounts,
+    /// Packets sent since acks were last taken, all of which arrive.
+    acked: Vec<SequenceNumber>,
+    outgoing: LoopbackQueue,
+    incoming: LoopbackQueue,
+}
+
+impl LoopbackConnection {
+    /// Create two linked ends, each delivering to the other after `latency`.
+    pub fn pair(latency: Duration) -> (Self, Self) {
+        let a_to_b = LoopbackQueue::default();
+        let b_to_a = LoopbackQueue::default();
+        let a = Self {
+            seq: SequenceNumber::ZERO,
+            remote_seq: SequenceNumber::ZERO,
+            latency,
+            packets: PacketCounts::default(),
+            acked: Vec::new(),
+            outgoing: Arc::clone(&a_to_b),
+            incoming: Arc::clone(&b_to_a),
+        };
+        let b = Self {
+            seq: SequenceNumber::ZERO,
+            remote_seq: SequenceNumber::ZERO,
+            latency,
+            packets: PacketCounts::default(),
+            acked: Vec::new(),
+            outgoing: b_to_a,
+            incoming: a_to_b,
+        };
+        (a, b)
+    }
+
+    pub async fn recv_with_optional_timeout(
+        &mut self,
+        maybe_timeout_duration: Option<Duration>,
+    ) -> Result<Typed<Message>, RpcError> {
+        let deadline = maybe_timeout_duration.map(|timeout| Instant::now() + timeout);
+        loop {
+            let now = Instant::now();
+            let next_delivery = {
+                let mut incoming = self.incoming.lock().unwrap();
+                match incoming.front() {
+                    Some((deliver_at, _)) if *deliver_at <= now => {
+                        let (_, bytes) = incoming.pop_front().unwrap();
+                        let msg_wrap = Typed::new(bytes);
+                        let msg: &Message = msg_wrap.try_ref()?;
+                        if msg.seq.is_newer_than(self.remote_seq) {
+                            self.remote_seq = msg.seq;
+                        }
+                        self.packets.received += 1;
+                        return Ok(msg_wrap);
+                    }
+                    Some((deliver_at, _)) => Some(*deliver_at),
+                    None => None,
+                }
+            };
+            if next_delivery.is_none() && !self.is_connected() {
+                return Err(RpcError::NotConnected);
+            }
+            if let Some(deadline) = deadline {
+                if deadline <= now {
+                    return Err(RpcError::Receive(io::ErrorKind::TimedOut.into()));
+                }
+            }
+            let wake_at = [next_delivery, deadline]
+                .into_iter()
+                .flatten()
+                .fold(now + LOOPBACK_POLL_INTERVAL, Instant::min);
+            Timer::at(wake_at).await;
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Connection for LoopbackConnection {
+    /// Connected for as long as the other end hasn't been dropped.
+    fn is_connected(&self) -> bool {
+        Arc::strong_count(&self.incoming) > 1
+    }
+
+    async fn recv(&mut self) -> Result<Typed<Message>, RpcError> {
+        self.recv_with_optional_timeout(None).await
+    }
+
+    async fn recv_with_timeout(
+        &mut self,
+        timeout_duration: Duration,
+    ) -> Result<Typed<Message>, RpcError> {
+        self.recv_with_optional_timeout(Some(timeout_duration))
+            .await
+    }
+
+    async fn send(&mut self, payload: &[u8]) -> Result<SequenceNumber, RpcError> {
+        if payload.len() > PAYLOAD_LEN {
+            return Err(RpcError::PayloadTooLarge(payload.len()));
+        }
+        if !self.is_connected() {
+            return Err(RpcError::NotConnected);
+        }
+        // Nothing is ever lost over loopback, so there are no ack bits to
+        // send, and every packet counts as acked.
+        let msg = Message::new(self.seq, self.remote_seq, 0, payload);
+        if self.acked.len() == MAX_UNACKED_PACKETS {
+            self.acked.remove(0);
+        }
+        self.acked.push(msg.seq);
+        self.seq = self.seq.next();
+        self.outgoing.lock().unwrap().push_back((
+            Instant::now() + self.latency,
+            bytemuck::bytes_of(&msg).to_vec(),
+        ));
+        self.packets.sent += 1;
+        Ok(msg.seq)
+    }
+
+    fn quality_sample(&self) -> Option<QualitySample> {
+        Some(QualitySample {
+            rtt: self.latency * 2,
+            loss: 0.0,
+        })
+    }
+
+    fn packet_counts(&self) -> PacketCounts {
+        self.packets
+    }
+
+    /// Every packet sent, as if the other end had acked it on arrival.
+    fn take_acked(&mut self) -> Vec<SequenceNumber> {
+        std::mem::take(&mut self.acked)
+    }
+
+    fn last_acks_sent(&self) -> Option<(SequenceNumber, u32)> {
+        (self.packets.sent > 0).then_some((self.remote_seq, 0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_lite::future::block_on;
+
+    use super::*;
+
+    #[test]
+    fn messages_arrive_after_the_latency() {
+        let (mut server, mut client) = LoopbackConnection::pair(Duration::from_millis(20));
+
+        block_on(client.send(b"stuff")).unwrap();
+        assert!(matches!(
+            block_on(server.recv_with_timeout(Duration::ZERO)),
+            Err(RpcError::Receive(err)) if err.kind() == io::ErrorKind::TimedOut
+        ));
+        let msg = block_on(server.recv_with_timeout(Duration::from_millis(200))).unwrap();
+        assert_eq!(&msg.try_ref().unwrap().payload[..5], b"stuff");
+
+        block_on(server.send(b"more")).unwrap();
+        assert_eq!(
+            block_on(client.recv()).unwrap().try_ref().unwrap().ack,
+            SequenceNumber::ZERO
+        );
+
+        drop(server);
+        assert!(!client.is_connected());
+        assert!(matches!(
+            block_on(client.recv()),
+            Err(RpcError::NotConnected)
+        ));
+    }
+}
@@ -0,0 +1,576 @@
+//! Serving many peers from one socket. Datagrams are told apart by the
+//! address they came from, and each address gets sequence numbers, acks and
+//! quality measurements of its own, see `ConnectionManager`.
+
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+use async_io::Timer;
+
+use crate::quality::QualitySample;
+use crate::{
+    Connection, Message, PacketCounts, RpcError, SequenceNumber, Typed, MAX_UNACKED_PACKETS,
+    MSG_LEN, PAYLOAD_LEN,
+};
+
+/// Messages kept for a peer until they're received, the oldest are dropped
+/// past this.
+const INBOX_LEN: usize = 64;
+
+/// How often a waiting receive checks the socket.
+const RECV_POLL: Duration = Duration::from_millis(1);
+
+// Weight of each new measurement in a peer's smoothed round trip time and
+// packet loss.
+const RTT_SMOOTHING: f64 = 0.125;
+const LOSS_SMOOTHING: f32 = 0.1;
+
+/// A socket serving up to a number of peers, keyed by their address. A peer
+/// is accepted when it's first heard from, and forgotten when it's
+/// disconnected.
+///
+/// Peers are sent to and received from with `send_to`, `broadcast` and
+/// `recv_from`, or through the `PeerConnection` that `accept` hands out for
+/// each, which is a `Connection` of its own. The socket doesn't block, and is
+/// read whenever either receives.
+pub struct ConnectionManager {
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    socket: UdpSocket,
+    state: Mutex<State>,
+}
+
+struct State {
+    peers: HashMap<SocketAddr, PeerState>,
+    /// Peers heard from for the first time, not yet taken by `accept`.
+    accepted: VecDeque<(SocketAddr, u64)>,
+    max_peers: usize,
+    /// Told apart from the peer accepted at the same address before it.
+    next_peer_id: u64,
+    /// Datagrams dropped for not being messages, or for coming from a peer
+    /// when there was no room for another.
+    refused: u64,
+}
+
+/// Sequence numbers, acks and quality of one peer, and the messages received
+/// from it.
+struct PeerState {
+    id: u64,
+    seq: SequenceNumber,
+    remote_seq: SequenceNumber,
+    packets: PacketCounts,
+    last_heard: Instant,
+    smoothed_rtt: Option<Duration>,
+    /// Fraction of sent packets that dropped out of the send queue unacked.
+    loss: f32,
+    send_queue: VecDeque<(SequenceNumber, Instant, bool)>,
+    recv_queue: VecDeque<SequenceNumber>,
+    acked: Vec<SequenceNumber>,
+    /// The ack and ack bits of the last message sent to the peer.
+    last_acks_sent: Option<(SequenceNumber, u32)>,
+    inbox: VecDeque<Typed<Message>>,
+}
+
+impl ConnectionManager {
+    /// Serve on `addr`, accepting up to `max_peers` peers at a time.
+    pub fn bind(addr: impl ToSocketAddrs, max_peers: usize) -> Result<Self, RpcError> {
+        let socket = UdpSocket::bind(addr).map_err(RpcError::Bind)?;
+        socket.set_nonblocking(true).map_err(RpcError::Bind)?;
+        Ok(Self {
+            shared: Arc::new(Shared {
+                socket,
+                state: Mutex::new(State {
+                    peers: HashMap::new(),
+                    accepted: VecDeque::new(),
+                    max_peers,
+                    next_peer_id: 0,
+                    refused: 0,
+                }),
+            }),
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.shared.socket.local_addr()
+    }
+
+    /// The next peer heard from since the last call, if any.
+    pub fn accept(&self) -> Result<Option<PeerConnection>, RpcError> {
+        let mut state = self.shared.poll()?;
+        while let Some((addr, id)) = state.accepted.pop_front() {
+            // It may have been disconnected before it was accepted.
+            if state.peers.get(&addr).is_some_and(|peer| peer.id == id) {
+                return Ok(Some(PeerConnection {
+                    addr,
+                    id,
+                    shared: Arc::clone(&self.shared),
+                }));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Addresses of the peers connected, in no particular order.
+    pub fn peers(&self) -> Vec<SocketAddr> {
+        self.shared.state().peers.keys().copied().collect()
+    }
+
+    /// Datagrams dropped so far, see `State::refused`.
+    pub fn refused(&self) -> u64 {
+        self.shared.state().refused
+    }
+
+    pub fn send_to(&self, addr: SocketAddr, payload: &[u8]) -> Result<SequenceNumber, RpcError> {
+        self.shared.send_to(addr, payload)
+    }
+
+    /// Send `payload` to every peer, each numbered in its own sequence.
+    /// Returns what became of each send.
+    pub fn broadcast(&self, payload: &[u8]) -> Vec<(SocketAddr, Result<SequenceNumber, RpcError>)> {
+        self.peers()
+            .into_iter()
+            .map(|addr| (addr, self.send_to(addr, payload)))
+            .collect()
+    }
+
+    /// The oldest message from `addr` not yet received, without waiting.
+    pub fn recv_from(&self, addr: SocketAddr) -> Result<Option<Typed<Message>>, RpcError> {
+        let mut state = self.shared.poll()?;
+        let peer = state.peers.get_mut(&addr).ok_or(RpcError::NotConnected)?;
+        Ok(peer.inbox.pop_front())
+    }
+
+    /// Forget a peer, returning whether it was connected. If it's heard from
+    /// again it's accepted as a new one.
+    pub fn disconnect(&self, addr: SocketAddr) -> bool {
+        self.shared.state().peers.remove(&addr).is_some()
+    }
+
+    /// Disconnect the peers not heard from in `timeout`, returning their
+    /// addresses.
+    pub fn disconnect_silent(&self, timeout: Duration) -> Vec<SocketAddr> {
+        let now = Instant::now();
+        let mut state = self.shared.state();
+        let silent: Vec<_> = state
+            .peers
+            .iter()
+            .filter(|(_, peer)| now.duration_since(peer.last_heard) >= timeout)
+            .map(|(addr, _)| *addr)
+            .collect();
+        for addr in &silent {
+            state.peers.remove(addr);
+        }
+        silent
+    }
+}
+
+impl Shared {
+    fn state(&self) -> MutexGuard<'_, State> {
+        // Peers are only ever updated whole.
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Read every datagram waiting on the socket, returning the state they
+    /// were read into.
+    fn poll(&self) -> Result<MutexGuard<'_, State>, RpcError> {
+        let mut state = self.state();
+        // One more byte than a message, to tell longer datagrams apart.
+        let mut buf = [0; MSG_LEN + 1];
+        loop {
+            match self.socket.recv_from(&mut buf) {
+                Ok((len, addr)) => state.receive(addr, &buf[..len], Instant::now()),
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => return Ok(state),
+                // Some platforms report a peer that's gone away on the next
+                // receive, which is no fault of the socket's.
+                Err(err) if err.kind() == io::ErrorKind::ConnectionReset => {}
+                Err(err) => return Err(RpcError::Receive(err)),
+            }
+        }
+    }
+
+    fn send_to(&self, addr: SocketAddr, payload: &[u8]) -> Result<SequenceNumber, RpcError> {
+        if payload.len() > PAYLOAD_LEN {
+            return Err(RpcError::PayloadTooLarge(payload.len()));
+        }
+        let mut state = self.state();
+        let peer = state.peers.get_mut(&addr).ok_or(RpcError::NotConnected)?;
+        let msg = peer.next_message(payload, Instant::now());
+        self.socket
+            .send_to(bytemuck::bytes_of(&msg), addr)
+            .map_err(RpcError::Send)?;
+        peer.packets.sent += 1;
+        Ok(msg.seq)
+    }
+}
+
+impl State {
+    fn receive(&mut self, addr: SocketAddr, bytes: &[u8], now: Instant) {
+        let msg = Typed::<Message>::new(bytes.to_vec());
+        if bytes.len() != MSG_LEN || msg.try_ref().is_err() {
+            self.refused += 1;
+            return;
+        }
+        if !self.peers.contains_key(&addr) {
+            if self.peers.len() >= self.max_peers {
+                self.refused += 1;
+                return;
+            }
+            let id = self.next_peer_id;
+            self.next_peer_id += 1;
+            self.peers.insert(addr, PeerState::new(id, now));
+            self.accepted.push_back((addr, id));
+        }
+        if let Some(peer) = self.peers.get_mut(&addr) {
+            peer.receive(msg, now);
+        }
+    }
+}
+
+impl PeerState {
+    fn new(id: u64, now: Instant) -> Self {
+        Self {
+            id,
+            seq: SequenceNumber::ZERO,
+            remote_seq: SequenceNumber::ZERO,
+            packets: PacketCounts::default(),
+            last_heard: now,
+            smoothed_rtt: None,
+            loss: 0.0,
+            send_queue: VecDeque::new(),
+            recv_queue: VecDeque::new(),
+            acked: Vec::new(),
+            last_acks_sent: None,
+            inbox: VecDeque::new(),
+        }
+    }
+
+    /// Number the next message to the peer, acking what it has sent.
+    fn next_message(&mut self, payload: &[u8], now: Instant) -> Message {
+        let msg = Message::new(self.seq, self.remote_seq, self.ack_bits(), payload);
+        if self.send_queue.len() == MAX_UNACKED_PACKETS {
+            if let Some((_, _, acked)) = self.send_queue.pop_front() {
+                let lost = if acked { 0.0 } else { 1.0 };
+                self.loss += (lost - self.loss) * LOSS_SMOOTHING;
+            }
+        }
+        self.send_queue.push_back((msg.seq, now, false));
+        self.seq = self.seq.next();
+        self.last_acks_sent = Some((msg.ack, msg.ack_bits));
+        msg
+    }
+
+    /// Bit n is set if the packet n behind the latest one was received.
+    fn ack_bits(&self) -> u32 {
+        (0..MAX_UNACKED_PACKETS)
+            .filter(|&n| self.recv_queue.contains(&self.remote_seq.behind(n as u16)))
+            .fold(0, |bits, n| bits | 1 << n)
+    }
+
+    fn receive(&mut self, msg: Typed<Message>, now: Instant) {
+        let (seq, ack, ack_bits) = match msg.try_ref() {
+            Ok(msg) => (msg.seq, msg.ack, msg.ack_bits),
+            Err(_) => return,
+        };
+        self.packets.received += 1;
+        self.last_heard = now;
+        if self.recv_queue.len() == MAX_UNACKED_PACKETS {
+            self.recv_queue.pop_front();
+        }
+        self.recv_queue.push_back(seq);
+        if seq.is_newer_than(self.remote_seq) {
+            self.remote_seq = seq;
+        }
+
+        for index in 0..MAX_UNACKED_PACKETS {
+            if ack_bits & 1 << index == 0 {
+                continue;
+            }
+            let acked = ack.behind(index as u16);
+            if let Some((seq, sent_at, acked @ false)) =
+                self.send_queue.iter_mut().find(|(seq, _, _)| *seq == acked)
+            {
+                *acked = true;
+                // Acks not taken by now are of no use to anyone.
+                if self.acked.len() == MAX_UNACKED_PACKETS {
+                    self.acked.remove(0);
+                }
+                self.acked.push(*seq);
+                let rtt = now.duration_since(*sent_at);
+                self.smoothed_rtt = Some(self.smoothed_rtt.map_or(rtt, |smoothed| {
+                    smoothed.mul_f64(1.0 - RTT_SMOOTHING) + rtt.mul_f64(RTT_SMOOTHING)
+                }));
+            }
+        }
+
+        if self.inbox.len() == INBOX_LEN {
+            self.inbox.pop_front();
+        }
+        self.inbox.push_back(msg);
+    }
+}
+
+/// One peer of a `ConnectionManager`, sending and receiving through the
+/// manager's socket. Dropping this disconnects the peer.
+pub struct PeerConnection {
+    addr: SocketAddr,
+    id: u64,
+    shared: Arc<Shared>,
+}
+
+impl PeerConnection {
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Read from the peer with this, waiting for up to `timeout` or forever.
+    pub async fn recv_with_optional_timeout(
+        &mut self,
+        timeout: Option<Duration>,
+    ) -> Result<Typed<Message>, RpcError> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        loop {
+            {
+                let mut state = self.shared.poll()?;
+                let peer = state
+                    .peers
+                    .get_mut(&self.addr)
+                    .filter(|peer| peer.id == self.id)
+                    .ok_or(RpcError::NotConnected)?;
+                if let Some(msg) = peer.inbox.pop_front() {
+                    return Ok(msg);
+                }
+            }
+            let now = Instant::now();
+            if deadline.is_some_and(|deadline| deadline <= now) {
+                return Err(RpcError::Receive(io::ErrorKind::TimedOut.into()));
+            }
+            let wake_at = now + RECV_POLL;
+            Timer::at(deadline.map_or(wake_at, |deadline| deadline.min(wake_at))).await;
+        }
+    }
+
+    fn peer<T>(&self, read: impl FnOnce(&mut PeerState) -> T) -> Option<T> {
+        self.shared
+            .state()
+            .peers
+            .get_mut(&self.addr)
+            .filter(|peer| peer.id == self.id)
+            .map(read)
+    }
+}
+
+impl Drop for PeerConnection {
+    fn drop(&mut self) {
+        let mut state = self.shared.state();
+        if state
+            .peers
+            .get(&self.addr)
+            .is_some_and(|peer| peer.id == self.id)
+        {
+            state.peers.remove(&self.addr);
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Connection for PeerConnection {
+    /// Connected until the manager disconnects the peer.
+    fn is_connected(&self) -> bool {
+        self.peer(|_| ()).is_some()
+    }
+
+    async fn recv(&mut self) -> Result<Typed<Message>, RpcError> {
+        self.recv_with_optional_timeout(None).await
+    }
+
+    async fn recv_with_timeout(
+        &mut self,
+        timeout_duration: Duration,
+    ) -> Result<Typed<Message>, RpcError> {
+        self.recv_with_optional_timeout(Some(timeout_duration))
+            .await
+    }
+
+    async fn send(&mut self, payload: &[u8]) -> Result<SequenceNumber, RpcError> {
+        // Not to another peer accepted at the same address since.
+        if !self.is_connected() {
+            return Err(RpcError::NotConnected);
+        }
+        self.shared.send_to(self.addr, payload)
+    }
+
+    fn quality_sample(&self) -> Option<QualitySample> {
+        self.peer(|peer| {
+            peer.smoothed_rtt.map(|rtt| QualitySample {
+                rtt,
+                loss: peer.loss,
+            })
+        })
+        .flatten()
+    }
+
+    fn unacked_packets(&self) -> usize {
+        self.peer(|peer| {
+            peer.send_queue
+                .iter()
+                .filter(|(_, _, acked)| !acked)
+                .count()
+        })
+        .unwrap_or(0)
+    }
+
+    fn packet_counts(&self) -> PacketCounts {
+        self.peer(|peer| peer.packets).unwrap_or_default()
+    }
+
+    fn take_acked(&mut self) -> Vec<SequenceNumber> {
+        self.peer(|peer| std::mem::take(&mut peer.acked))
+            .unwrap_or_default()
+    }
+
+    fn last_acks_sent(&self) -> Option<(SequenceNumber, u32)> {
+        self.peer(|peer| peer.last_acks_sent).flatten()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use futures_lite::future::block_on;
+
+    use super::*;
+
+    /// A peer's socket, sending numbered messages to `server`.
+    fn client(server: SocketAddr) -> UdpSocket {
+        let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        socket.connect(server).unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .unwrap();
+        socket
+    }
+
+    fn send(socket: &UdpSocket, seq: u16, ack: u16, ack_bits: u32, payload: &[u8]) {
+        let msg = Message::new(SequenceNumber(seq), SequenceNumber(ack), ack_bits, payload);
+        socket.send(bytemuck::bytes_of(&msg)).unwrap();
+    }
+
+    fn recv(socket: &UdpSocket) -> Message {
+        let mut buf = [0; MSG_LEN];
+        let len = socket.recv(&mut buf).unwrap();
+        *bytemuck::from_bytes(&buf[..len])
+    }
+
+    /// Accept the next peer, waiting for its datagram to arrive.
+    fn accept(manager: &ConnectionManager) -> PeerConnection {
+        let deadline = Instant::now() + Duration::from_secs(1);
+        loop {
+            if let Some(peer) = manager.accept().unwrap() {
+                return peer;
+            }
+            assert!(Instant::now() < deadline, "no peer was accepted");
+            std::thread::sleep(RECV_POLL);
+        }
+    }
+
+    fn localhost() -> ConnectionManager {
+        ConnectionManager::bind((Ipv4Addr::LOCALHOST, 0), 2).unwrap()
+    }
+
+    #[test]
+    fn serves_peers_by_address() {
+        let manager = localhost();
+        let server = manager.local_addr().unwrap();
+        let (a, b) = (client(server), client(server));
+
+        send(&a, 0, 0, 0, b"from a");
+        let mut peer_a = accept(&manager);
+        send(&b, 7, 0, 0, b"from b");
+        let mut peer_b = accept(&manager);
+        assert_eq!(peer_a.addr(), a.local_addr().unwrap());
+        assert_eq!(peer_b.addr(), b.local_addr().unwrap());
+
+        let received = block_on(peer_b.recv_with_timeout(Duration::from_secs(1))).unwrap();
+        assert_eq!(&received.try_ref().unwrap().payload[..6], b"from b");
+        let received = block_on(peer_a.recv_with_timeout(Duration::from_secs(1))).unwrap();
+        assert_eq!(&received.try_ref().unwrap().payload[..6], b"from a");
+        assert!(matches!(
+            block_on(peer_a.recv_with_timeout(Duration::ZERO)),
+            Err(RpcError::Receive(err)) if err.kind() == io::ErrorKind::TimedOut
+        ));
+
+        // Each peer is numbered and acked in its own sequence.
+        assert_eq!(block_on(peer_a.send(b"to a")).unwrap(), SequenceNumber(0));
+        let results = manager.broadcast(b"to all");
+        assert_eq!(results.len(), 2);
+        assert!(results.iter().all(|(_, sent)| sent.is_ok()));
+        let to_a = [recv(&a), recv(&a)];
+        assert_eq!(&to_a[0].payload[..4], b"to a");
+        assert_eq!(to_a[1].seq, SequenceNumber(1));
+        let to_b = recv(&b);
+        assert_eq!(&to_b.payload[..6], b"to all");
+        assert_eq!(
+            (to_b.seq, to_b.ack, to_b.ack_bits),
+            (SequenceNumber(0), SequenceNumber(7), 1)
+        );
+
+        send(&a, 1, 1, 0b11, b"");
+        block_on(peer_a.recv_with_timeout(Duration::from_secs(1))).unwrap();
+        assert_eq!(peer_a.take_acked(), [SequenceNumber(1), SequenceNumber(0)]);
+        assert_eq!(peer_a.unacked_packets(), 0);
+        assert_eq!(peer_b.unacked_packets(), 1);
+        assert_eq!(
+            peer_a.packet_counts(),
+            PacketCounts {
+                sent: 2,
+                received: 2
+            }
+        );
+        assert!(peer_a.quality_sample().is_some());
+    }
+
+    #[test]
+    fn refuses_peers_past_the_limit() {
+        let manager = localhost();
+        let server = manager.local_addr().unwrap();
+        let clients = [client(server), client(server), client(server)];
+
+        send(&clients[0], 0, 0, 0, b"");
+        let first = accept(&manager);
+        send(&clients[1], 0, 0, 0, b"");
+        let _second = accept(&manager);
+        send(&clients[2], 0, 0, 0, b"");
+        clients[2].send(b"not a message").unwrap();
+        while manager.refused() < 2 {
+            assert!(manager.accept().unwrap().is_none());
+        }
+        assert_eq!(manager.peers().len(), 2);
+
+        // Dropping a peer's connection makes room for another.
+        drop(first);
+        assert_eq!(manager.peers().len(), 1);
+        send(&clients[2], 1, 0, 0, b"again");
+        let third = accept(&manager);
+        assert_eq!(third.addr(), clients[2].local_addr().unwrap());
+        assert_eq!(
+            manager
+                .recv_from(third.addr())
+                .unwrap()
+                .map(|msg| msg.try_ref().unwrap().seq),
+            Some(SequenceNumber(1))
+        );
+
+        assert_eq!(manager.disconnect_silent(Duration::ZERO).len(), 2);
+        assert!(!third.is_connected());
+        assert!(matches!(
+            manager.send_to(third.addr(), b""),
+            Err(RpcError::NotConnected)
+        ));
+    }
+}
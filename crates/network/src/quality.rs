@@ -0,0 +1,246 @@
+//! Classifying connection quality from round trip time and packet loss, so
+//! senders and receivers can adapt to congestion. Quality drops as soon as a
+//! threshold is crossed, but only recovers once the connection has stayed
+//! comfortably below it for a while, so a connection hovering around a
+//! threshold doesn't flap between qualities.
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Samples a monitor keeps, for showing how a connection has been recently.
+pub const RECENT_SAMPLES: usize = 120;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ConnectionQuality {
+    Good,
+    Degraded,
+    Bad,
+}
+
+impl ConnectionQuality {
+    /// How long a receiver should buffer updates before interpolating between
+    /// them, longer when updates are more likely to be late or lost.
+    pub fn interpolation_delay(self) -> Duration {
+        match self {
+            ConnectionQuality::Good => Duration::from_millis(50),
+            ConnectionQuality::Degraded => Duration::from_millis(100),
+            ConnectionQuality::Bad => Duration::from_millis(200),
+        }
+    }
+
+    /// Minimum time between updates sent to a peer, to ease congestion.
+    pub fn send_interval(self) -> Duration {
+        match self {
+            ConnectionQuality::Good => Duration::ZERO,
+            ConnectionQuality::Degraded => Duration::from_millis(33),
+            ConnectionQuality::Bad => Duration::from_millis(100),
+        }
+    }
+}
+
+/// A measurement of a connection, see `Connection::quality_sample`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct QualitySample {
+    pub rtt: Duration,
+    /// Fraction of packets sent that were never acked, from 0 to 1.
+    pub loss: f32,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct QualityThresholds {
+    pub degraded_rtt: Duration,
+    pub bad_rtt: Duration,
+    pub degraded_loss: f32,
+    pub bad_loss: f32,
+    /// Fraction of a threshold samples must stay under to count as recovering.
+    pub recovery_margin: f32,
+    /// How long samples must keep recovering before quality improves.
+    pub recovery_time: Duration,
+}
+
+impl Default for QualityThresholds {
+    fn default() -> Self {
+        Self {
+            degraded_rtt: Duration::from_millis(150),
+            bad_rtt: Duration::from_millis(300),
+            degraded_loss: 0.05,
+            bad_loss: 0.15,
+            recovery_margin: 0.8,
+            recovery_time: Duration::from_secs(2),
+        }
+    }
+}
+
+impl QualityThresholds {
+    fn classify(&self, sample: QualitySample, scale: f32) -> ConnectionQuality {
+        if sample.rtt >= self.bad_rtt.mul_f32(scale) || sample.loss >= self.bad_loss * scale {
+            ConnectionQuality::Bad
+        } else if sample.rtt >= self.degraded_rtt.mul_f32(scale)
+            || sample.loss >= self.degraded_loss * scale
+        {
+            ConnectionQuality::Degraded
+        } else {
+            ConnectionQuality::Good
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct QualityChange {
+    pub from: ConnectionQuality,
+    pub to: ConnectionQuality,
+    /// The sample that caused the change.
+    pub sample: QualitySample,
+}
+
+/// Tracks the quality of a connection from samples taken over time.
+#[derive(Debug)]
+pub struct QualityMonitor {
+    thresholds: QualityThresholds,
+    quality: ConnectionQuality,
+    recovering_since: Option<Instant>,
+    /// The last `RECENT_SAMPLES` samples, oldest first.
+    recent: VecDeque<QualitySample>,
+}
+
+impl Default for QualityMonitor {
+    fn default() -> Self {
+        Self::new(QualityThresholds::default())
+    }
+}
+
+impl QualityMonitor {
+    pub fn new(thresholds: QualityThresholds) -> Self {
+        Self {
+            thresholds,
+            quality: ConnectionQuality::Good,
+            recovering_since: None,
+            recent: VecDeque::with_capacity(RECENT_SAMPLES),
+        }
+    }
+
+    pub fn quality(&self) -> ConnectionQuality {
+        self.quality
+    }
+
+    /// Classify a sample taken at `now`, returning the change in quality if
+    /// there was one.
+    pub fn update(&mut self, sample: QualitySample, now: Instant) -> Option<QualityChange> {
+        if self.recent.len() == RECENT_SAMPLES {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(sample);
+
+        let measured = self.thresholds.classify(sample, 1.0);
+        let to = if measured > self.quality {
+            self.recovering_since = None;
+            measured
+        } else {
+            let recovered = self
+                .thresholds
+                .classify(sample, self.thresholds.recovery_margin);
+            if recovered >= self.quality {
+                self.recovering_since = None;
+                return None;
+            }
+            let since = *self.recovering_since.get_or_insert(now);
+            if now.saturating_duration_since(since) < self.thresholds.recovery_time {
+                return None;
+            }
+            self.recovering_since = None;
+            recovered
+        };
+
+        let change = QualityChange {
+            from: self.quality,
+            to,
+            sample,
+        };
+        self.quality = to;
+        Some(change)
+    }
+
+    /// The most recent samples, oldest first.
+    pub fn recent_samples(&self) -> impl Iterator<Item = &QualitySample> + '_ {
+        self.recent.iter()
+    }
+
+    /// Counts of recent samples by round trip time, in `buckets` buckets of
+    /// `width` each. The last bucket also counts anything slower.
+    pub fn rtt_histogram(&self, width: Duration, buckets: usize) -> Vec<usize> {
+        let mut counts = vec![0; buckets];
+        if buckets == 0 || width.is_zero() {
+            return counts;
+        }
+        for sample in self.recent.iter() {
+            let bucket = (sample.rtt.as_nanos() / width.as_nanos()) as usize;
+            counts[bucket.min(buckets - 1)] += 1;
+        }
+        counts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(rtt_millis: u64, loss: f32) -> QualitySample {
+        QualitySample {
+            rtt: Duration::from_millis(rtt_millis),
+            loss,
+        }
+    }
+
+    #[test]
+    fn quality_drops_immediately_and_recovers_with_hysteresis() {
+        let start = Instant::now();
+        let at = |millis| start + Duration::from_millis(millis);
+        let mut monitor = QualityMonitor::default();
+
+        assert_eq!(monitor.update(sample(20, 0.0), at(0)), None);
+        let change = monitor.update(sample(20, 0.2), at(100)).unwrap();
+        assert_eq!(
+            (change.from, change.to),
+            (ConnectionQuality::Good, ConnectionQuality::Bad)
+        );
+
+        // Just under the threshold isn't enough to recover.
+        assert_eq!(monitor.update(sample(20, 0.14), at(5_000)), None);
+        assert_eq!(monitor.quality(), ConnectionQuality::Bad);
+
+        // Comfortably under it, recovery waits for the recovery time.
+        assert_eq!(monitor.update(sample(200, 0.0), at(6_000)), None);
+        assert_eq!(monitor.update(sample(200, 0.0), at(7_000)), None);
+        // A bad sample restarts recovery.
+        assert_eq!(monitor.update(sample(250, 0.0), at(7_500)), None);
+        assert_eq!(monitor.update(sample(200, 0.0), at(8_000)), None);
+        let change = monitor.update(sample(200, 0.0), at(10_000)).unwrap();
+        assert_eq!(
+            (change.from, change.to),
+            (ConnectionQuality::Bad, ConnectionQuality::Degraded)
+        );
+        assert_eq!(monitor.quality(), ConnectionQuality::Degraded);
+    }
+
+    #[test]
+    fn recent_round_trips_are_bucketed() {
+        let start = Instant::now();
+        let mut monitor = QualityMonitor::default();
+        for rtt in [5, 30, 45, 60, 500] {
+            monitor.update(sample(rtt, 0.0), start);
+        }
+        assert_eq!(
+            monitor.rtt_histogram(Duration::from_millis(25), 3),
+            vec![1, 2, 2]
+        );
+
+        for _ in 0..RECENT_SAMPLES {
+            monitor.update(sample(10, 0.0), start);
+        }
+        assert_eq!(monitor.recent_samples().count(), RECENT_SAMPLES);
+        assert_eq!(
+            monitor.rtt_histogram(Duration::from_millis(25), 3),
+            vec![RECENT_SAMPLES, 0, 0]
+        );
+    }
+}
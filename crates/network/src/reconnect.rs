@@ -0,0 +1,168 @@
+//! Connecting clients to a server by host name, and reconnecting when the
+//! connection is lost. Names are resolved on another thread so a slow lookup
+//! doesn't stall frames, and reconnect attempts back off exponentially so a
+//! server that's down isn't flooded.
+
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::sync::mpsc::{self, TryRecvError};
+use std::time::Duration;
+use std::{fmt, io, thread};
+
+/// State of a client's connection to its server, for showing to the user.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ConnectionState {
+    /// Looking up the server's address.
+    Resolving,
+    /// Sent a handshake, waiting to hear from the server.
+    Connecting,
+    Connected,
+    /// Lost or failed to make the connection, trying again after `retry_in`.
+    Reconnecting {
+        attempt: u32,
+        retry_in: Duration,
+    },
+    /// Turned away by a server built differently, see `build_info`. Not
+    /// tried again.
+    Refused,
+}
+
+impl fmt::Display for ConnectionState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConnectionState::Resolving => write!(f, "resolving server address..."),
+            ConnectionState::Connecting => write!(f, "connecting..."),
+            ConnectionState::Connected => write!(f, "connected"),
+            ConnectionState::Reconnecting { attempt, retry_in } => write!(
+                f,
+                "reconnecting in {:.1}s (attempt {attempt})...",
+                retry_in.as_secs_f32()
+            ),
+            ConnectionState::Refused => write!(f, "refused, the server runs a different build"),
+        }
+    }
+}
+
+/// Delays between reconnect attempts, doubling each attempt up to `max`.
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    pub initial: Duration,
+    pub max: Duration,
+    attempt: u32,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self::new(Duration::from_millis(500), Duration::from_secs(30))
+    }
+}
+
+impl Backoff {
+    pub fn new(initial: Duration, max: Duration) -> Self {
+        Self {
+            initial,
+            max,
+            attempt: 0,
+        }
+    }
+
+    /// Attempts made since the last reset.
+    pub fn attempt(&self) -> u32 {
+        self.attempt
+    }
+
+    /// Count an attempt, returning how long to wait before making it.
+    pub fn next_delay(&mut self) -> Duration {
+        let delay = self
+            .initial
+            .checked_mul(1 << self.attempt.min(16))
+            .map_or(self.max, |delay| delay.min(self.max));
+        self.attempt += 1;
+        delay
+    }
+
+    /// Start over from `initial`, once connected.
+    pub fn reset(&mut self) {
+        self.attempt = 0;
+    }
+}
+
+/// A host name being resolved on another thread.
+pub struct Resolve {
+    host: String,
+    result: mpsc::Receiver<io::Result<SocketAddr>>,
+}
+
+impl Resolve {
+    /// Start resolving `host`, a host name or address with a port, such as
+    /// "example.com:12002".
+    pub fn start(host: &str) -> Self {
+        let (sender, result) = mpsc::channel();
+        let lookup = host.to_string();
+        thread::spawn(move || {
+            let resolved = lookup.to_socket_addrs().and_then(|mut addrs| {
+                addrs
+                    .next()
+                    .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no addresses for host"))
+            });
+            // The resolve may have been dropped, nothing is waiting then.
+            let _ = sender.send(resolved);
+        });
+        Self {
+            host: host.to_string(),
+            result,
+        }
+    }
+
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    /// The address, or the error resolving it, once the lookup finishes.
+    pub fn poll(&self) -> Option<io::Result<SocketAddr>> {
+        match self.result.try_recv() {
+            Ok(result) => Some(result),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => {
+                Some(Err(io::Error::other("resolver thread exited")))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wait(resolve: &Resolve) -> io::Result<SocketAddr> {
+        loop {
+            if let Some(result) = resolve.poll() {
+                return result;
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_max_and_resets() {
+        let mut backoff = Backoff::new(Duration::from_millis(100), Duration::from_millis(500));
+        let delays = (0..5)
+            .map(|_| backoff.next_delay().as_millis())
+            .collect::<Vec<_>>();
+        assert_eq!(delays, vec![100, 200, 400, 500, 500]);
+        assert_eq!(backoff.attempt(), 5);
+        for _ in 0..100 {
+            assert_eq!(backoff.next_delay(), backoff.max);
+        }
+        backoff.reset();
+        assert_eq!(backoff.next_delay(), backoff.initial);
+    }
+
+    #[test]
+    fn resolves_off_thread() {
+        let resolve = Resolve::start("127.0.0.1:12002");
+        assert_eq!(resolve.host(), "127.0.0.1:12002");
+        assert_eq!(wait(&resolve).unwrap(), "127.0.0.1:12002".parse().unwrap());
+        // No port to connect to.
+        assert!(wait(&Resolve::start("127.0.0.1")).is_err());
+    }
+}
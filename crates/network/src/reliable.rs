@@ -0,0 +1,512 @@
+//! A reliable, ordered channel carried in the same packets as unreliable
+//! updates, for things that must arrive, such as chat, spawn events and asset
+//! manifests.
+//!
+//! Messages are split into fragments of at most `FRAGMENT_LEN` bytes, which
+//! ride along in whatever room packets have left. A fragment is sent again if
+//! the packet carrying it isn't acked in time, and the receiver reassembles
+//! messages and hands them over in the order they were sent.
+//!
+//! `Reliable` carries a channel over a connection: each packet's payload
+//! starts with the length of its fragments as a u16, then the fragments, then
+//! the unreliable payload. Received packets have the fragments stripped, so
+//! the unreliable payload starts the payload as it was sent.
+
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use crate::quality::QualitySample;
+use crate::{
+    Connection, Message, PacketCounts, RpcError, SequenceNumber, Typed, MAX_UNACKED_PACKETS,
+    PAYLOAD_LEN,
+};
+
+/// Most bytes of a message sent in one packet, leaving the rest of it for
+/// unreliable payloads.
+pub const FRAGMENT_LEN: usize = 512;
+
+/// Largest message that can be sent reliably.
+pub const MAX_MESSAGE_LEN: usize = MAX_FRAGMENTS * FRAGMENT_LEN;
+
+/// Messages being sent at once, the rest wait their turn. Far less than half
+/// the range of message ids, so the receiver can tell old ones from new.
+pub const MAX_MESSAGES_IN_FLIGHT: usize = 64;
+
+/// Messages queued, including those in flight, before sending more fails.
+pub const MAX_QUEUED_MESSAGES: usize = 1024;
+
+const MAX_FRAGMENTS: usize = 128;
+
+/// Message id, fragment index, fragment count and fragment length, as u16s.
+const FRAGMENT_HEADER_LEN: usize = 8;
+
+/// Bytes before a packet's fragments, giving their length.
+const SECTION_HEADER_LEN: usize = 2;
+
+/// How long a fragment's packet has to be acked before it's sent again, until
+/// the round trip time is known.
+const DEFAULT_RESEND_AFTER: Duration = Duration::from_millis(100);
+
+/// Shortest wait before sending a fragment again, however short the round
+/// trip.
+const MIN_RESEND_AFTER: Duration = Duration::from_millis(20);
+
+struct OutgoingMessage {
+    id: SequenceNumber,
+    bytes: Vec<u8>,
+    fragments: Vec<OutgoingFragment>,
+}
+
+#[derive(Default, Clone)]
+struct OutgoingFragment {
+    sent_at: Option<Instant>,
+    acked: bool,
+}
+
+struct IncomingMessage {
+    fragments: Vec<Option<Vec<u8>>>,
+    missing: usize,
+}
+
+/// Both ends of a reliable channel. The sending end writes fragments into
+/// packets and is told which packets were acked, the receiving end reads
+/// fragments from packets. Neither touches a socket, see `Reliable` for that.
+pub struct ReliableChannel {
+    next_id: SequenceNumber,
+    /// Messages waiting for room in flight.
+    waiting: VecDeque<Vec<u8>>,
+    /// Messages in flight, oldest first, until all their fragments are acked.
+    in_flight: VecDeque<OutgoingMessage>,
+    /// The fragments each recent packet carried, by message id and index.
+    carried: VecDeque<(SequenceNumber, Vec<(SequenceNumber, u16)>)>,
+    resend_after: Duration,
+    next_expected: SequenceNumber,
+    incoming: HashMap<SequenceNumber, IncomingMessage>,
+    received: VecDeque<Vec<u8>>,
+}
+
+impl Default for ReliableChannel {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ReliableChannel {
+    pub fn new() -> Self {
+        Self {
+            next_id: SequenceNumber::ZERO,
+            waiting: VecDeque::new(),
+            in_flight: VecDeque::new(),
+            carried: VecDeque::new(),
+            resend_after: DEFAULT_RESEND_AFTER,
+            next_expected: SequenceNumber::ZERO,
+            incoming: HashMap::new(),
+            received: VecDeque::new(),
+        }
+    }
+
+    /// Queue `message` to be sent, failing if it's larger than
+    /// `MAX_MESSAGE_LEN` or `MAX_QUEUED_MESSAGES` are already queued.
+    pub fn send(&mut self, message: &[u8]) -> Result<(), RpcError> {
+        if message.len() > MAX_MESSAGE_LEN {
+            return Err(RpcError::PayloadTooLarge(message.len()));
+        }
+        if self.queued() >= MAX_QUEUED_MESSAGES {
+            return Err(RpcError::ReliableBacklog(self.queued()));
+        }
+        self.waiting.push_back(message.to_vec());
+        Ok(())
+    }
+
+    /// Messages sent that haven't been acked in full yet.
+    pub fn queued(&self) -> usize {
+        self.waiting.len() + self.in_flight.len()
+    }
+
+    /// How long a fragment's packet has to be acked before it's sent again.
+    pub fn set_resend_after(&mut self, resend_after: Duration) {
+        self.resend_after = resend_after.max(MIN_RESEND_AFTER);
+    }
+
+    /// Append the fragments due to be sent at `now` to `out`, oldest message
+    /// first, in at most `budget` bytes. Returns which were written, to give
+    /// `carried_by` once the packet is sent.
+    pub fn write(
+        &mut self,
+        now: Instant,
+        mut budget: usize,
+        out: &mut Vec<u8>,
+    ) -> Vec<(SequenceNumber, u16)> {
+        while self.in_flight.len() < MAX_MESSAGES_IN_FLIGHT {
+            let bytes = match self.waiting.pop_front() {
+                Some(bytes) => bytes,
+                None => break,
+            };
+            let count = bytes.len().div_ceil(FRAGMENT_LEN).max(1);
+            self.in_flight.push_back(OutgoingMessage {
+                id: self.next_id,
+                bytes,
+                fragments: vec![OutgoingFragment::default(); count],
+            });
+            self.next_id = self.next_id.next();
+        }
+
+        let resend_after = self.resend_after;
+        let mut written = Vec::new();
+        'messages: for message in self.in_flight.iter_mut() {
+            let count = message.fragments.len();
+            for (index, fragment) in message.fragments.iter_mut().enumerate() {
+                let due = match fragment.sent_at {
+                    _ if fragment.acked => false,
+                    Some(sent_at) => now.saturating_duration_since(sent_at) >= resend_after,
+                    None => true,
+                };
+                if !due {
+                    continue;
+                }
+                let start = index * FRAGMENT_LEN;
+                let bytes = &message.bytes[start..message.bytes.len().min(start + FRAGMENT_LEN)];
+                if FRAGMENT_HEADER_LEN + bytes.len() > budget {
+                    break 'messages;
+                }
+                budget -= FRAGMENT_HEADER_LEN + bytes.len();
+                for field in [message.id.0, index as u16, count as u16, bytes.len() as u16] {
+                    out.extend_from_slice(&field.to_le_bytes());
+                }
+                out.extend_from_slice(bytes);
+                fragment.sent_at = Some(now);
+                written.push((message.id, index as u16));
+            }
+        }
+        written
+    }
+
+    /// Remember that packet `seq` carried `fragments`, as returned by `write`.
+    pub fn carried_by(&mut self, seq: SequenceNumber, fragments: Vec<(SequenceNumber, u16)>) {
+        if fragments.is_empty() {
+            return;
+        }
+        // Acks only come for recent packets, older ones are resent anyway.
+        if self.carried.len() == MAX_UNACKED_PACKETS {
+            self.carried.pop_front();
+        }
+        self.carried.push_back((seq, fragments));
+    }
+
+    /// Packet `seq` was acked, so the fragments it carried arrived.
+    pub fn acked(&mut self, seq: SequenceNumber) {
+        let at = match self.carried.iter().position(|(carrier, _)| *carrier == seq) {
+            Some(at) => at,
+            None => return,
+        };
+        let (_, fragments) = self.carried.remove(at).unwrap();
+        for (id, index) in fragments {
+            if let Some(message) = self.in_flight.iter_mut().find(|message| message.id == id) {
+                message.fragments[index as usize].acked = true;
+            }
+        }
+        // Messages leave in order, keeping the ids in flight contiguous.
+        while self
+            .in_flight
+            .front()
+            .is_some_and(|message| message.fragments.iter().all(|fragment| fragment.acked))
+        {
+            self.in_flight.pop_front();
+        }
+    }
+
+    /// Take in fragments written by the other end's `write`.
+    pub fn read(&mut self, mut bytes: &[u8]) -> Result<(), RpcError> {
+        while !bytes.is_empty() {
+            let header = bytes
+                .get(..FRAGMENT_HEADER_LEN)
+                .ok_or(RpcError::Malformed("truncated fragment header"))?;
+            let field = |at: usize| u16::from_le_bytes([header[at], header[at + 1]]);
+            let (id, index, count, len) = (
+                SequenceNumber(field(0)),
+                field(2) as usize,
+                field(4) as usize,
+                field(6) as usize,
+            );
+            let fragment = bytes
+                .get(FRAGMENT_HEADER_LEN..FRAGMENT_HEADER_LEN + len)
+                .ok_or(RpcError::Malformed("truncated fragment"))?;
+            bytes = &bytes[FRAGMENT_HEADER_LEN + len..];
+            if index >= count || count > MAX_FRAGMENTS || len > FRAGMENT_LEN {
+                return Err(RpcError::Malformed("invalid fragment header"));
+            }
+
+            let ahead = id.distance_from(self.next_expected);
+            if ahead < 0 {
+                // Already delivered, sent again because an ack was lost.
+                continue;
+            }
+            if ahead >= MAX_MESSAGES_IN_FLIGHT as i32 {
+                return Err(RpcError::Malformed("message id out of the window"));
+            }
+            let message = self.incoming.entry(id).or_insert_with(|| IncomingMessage {
+                fragments: vec![None; count],
+                missing: count,
+            });
+            if message.fragments.len() != count {
+                return Err(RpcError::Malformed("fragment count changed"));
+            }
+            if message.fragments[index].is_none() {
+                message.fragments[index] = Some(fragment.to_vec());
+                message.missing -= 1;
+            }
+        }
+
+        while self
+            .incoming
+            .get(&self.next_expected)
+            .is_some_and(|message| message.missing == 0)
+        {
+            let message = self.incoming.remove(&self.next_expected).unwrap();
+            self.received
+                .push_back(message.fragments.into_iter().flatten().flatten().collect());
+            self.next_expected = self.next_expected.next();
+        }
+        Ok(())
+    }
+
+    /// The next message received, in the order they were sent.
+    pub fn receive(&mut self) -> Option<Vec<u8>> {
+        self.received.pop_front()
+    }
+}
+
+/// Split the payload of a packet carrying a reliable channel into its
+/// fragments and the unreliable payload after them, for looking into packets
+/// below a `Reliable`, such as when tracing them.
+pub fn split_payload(payload: &[u8]) -> Option<(&[u8], &[u8])> {
+    let len = u16::from_le_bytes([*payload.first()?, *payload.get(1)?]) as usize;
+    let rest = &payload[SECTION_HEADER_LEN..];
+    (len <= rest.len()).then(|| rest.split_at(len))
+}
+
+/// A connection carrying a `ReliableChannel` in its packets, see the module
+/// docs. Both ends need to be wrapped.
+pub struct Reliable {
+    connection: Box<dyn Connection + Send + Sync + 'static>,
+    channel: ReliableChannel,
+    packet: Vec<u8>,
+}
+
+impl Reliable {
+    pub fn new(connection: Box<dyn Connection + Send + Sync + 'static>) -> Self {
+        Self {
+            connection,
+            channel: ReliableChannel::new(),
+            packet: Vec::with_capacity(PAYLOAD_LEN),
+        }
+    }
+
+    pub fn channel(&self) -> &ReliableChannel {
+        &self.channel
+    }
+
+    /// Tell the channel which packets were acked, and how long acks take.
+    fn take_acks(&mut self) {
+        for seq in self.connection.take_acked() {
+            self.channel.acked(seq);
+        }
+        if let Some(sample) = self.connection.quality_sample() {
+            self.channel.set_resend_after(sample.rtt.mul_f32(1.5));
+        }
+    }
+
+    /// Read the fragments from a received packet, moving its unreliable
+    /// payload to the start.
+    fn strip_fragments(&mut self, mut message: Typed<Message>) -> Result<Typed<Message>, RpcError> {
+        self.take_acks();
+        let payload = &mut message.try_mut()?.payload;
+        let len = u16::from_le_bytes([payload[0], payload[1]]) as usize;
+        let end = SECTION_HEADER_LEN + len;
+        if end > PAYLOAD_LEN {
+            return Err(RpcError::Malformed("fragments longer than the payload"));
+        }
+        self.channel.read(&payload[SECTION_HEADER_LEN..end])?;
+        payload.copy_within(end.., 0);
+        payload[PAYLOAD_LEN - end..].fill(0);
+        Ok(message)
+    }
+}
+
+#[async_trait::async_trait]
+impl Connection for Reliable {
+    fn is_connected(&self) -> bool {
+        self.connection.is_connected()
+    }
+
+    async fn recv(&mut self) -> Result<Typed<Message>, RpcError> {
+        let message = self.connection.recv().await?;
+        self.strip_fragments(message)
+    }
+
+    async fn recv_with_timeout(
+        &mut self,
+        timeout_duration: Duration,
+    ) -> Result<Typed<Message>, RpcError> {
+        let message = self.connection.recv_with_timeout(timeout_duration).await?;
+        self.strip_fragments(message)
+    }
+
+    /// Send `payload` unreliably, along with the fragments due to be sent
+    /// that fit in the room it leaves.
+    async fn send(&mut self, payload: &[u8]) -> Result<SequenceNumber, RpcError> {
+        let budget = PAYLOAD_LEN
+            .checked_sub(SECTION_HEADER_LEN + payload.len())
+            .ok_or(RpcError::PayloadTooLarge(payload.len()))?;
+        self.take_acks();
+        self.packet.clear();
+        self.packet.extend_from_slice(&[0; SECTION_HEADER_LEN]);
+        let carried = self.channel.write(Instant::now(), budget, &mut self.packet);
+        let len = (self.packet.len() - SECTION_HEADER_LEN) as u16;
+        self.packet[..SECTION_HEADER_LEN].copy_from_slice(&len.to_le_bytes());
+        self.packet.extend_from_slice(payload);
+        let seq = self.connection.send(&self.packet).await?;
+        self.channel.carried_by(seq, carried);
+        Ok(seq)
+    }
+
+    fn quality_sample(&self) -> Option<QualitySample> {
+        self.connection.quality_sample()
+    }
+
+    fn unacked_packets(&self) -> usize {
+        self.connection.unacked_packets()
+    }
+
+    fn packet_counts(&self) -> PacketCounts {
+        self.connection.packet_counts()
+    }
+
+    fn last_acks_sent(&self) -> Option<(SequenceNumber, u32)> {
+        self.connection.last_acks_sent()
+    }
+
+    fn send_reliable(&mut self, message: &[u8]) -> Result<(), RpcError> {
+        self.channel.send(message)
+    }
+
+    fn recv_reliable(&mut self) -> Option<Vec<u8>> {
+        self.channel.receive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Send packets from `sender` to `receiver` every 10ms until everything is
+    /// acked, losing those `lost` picks, and return what was received.
+    fn exchange(
+        sender: &mut ReliableChannel,
+        receiver: &mut ReliableChannel,
+        mut lost: impl FnMut(u16) -> bool,
+    ) -> Vec<Vec<u8>> {
+        let mut now = Instant::now();
+        let mut seq = SequenceNumber::ZERO;
+        let mut received = Vec::new();
+        for _ in 0..1000 {
+            if sender.queued() == 0 {
+                return received;
+            }
+            let mut packet = Vec::new();
+            let carried = sender.write(now, PAYLOAD_LEN - SECTION_HEADER_LEN, &mut packet);
+            sender.carried_by(seq, carried);
+            if !lost(seq.0) {
+                receiver.read(&packet).unwrap();
+                received.extend(std::iter::from_fn(|| receiver.receive()));
+                sender.acked(seq);
+            }
+            seq = seq.next();
+            now += Duration::from_millis(10);
+        }
+        panic!("{} messages were never acked", sender.queued());
+    }
+
+    #[test]
+    fn delivers_fragmented_messages_in_order_despite_loss() {
+        let (mut sender, mut receiver) = (ReliableChannel::new(), ReliableChannel::new());
+        let large = (0..3000).map(|i| i as u8).collect::<Vec<_>>();
+        let messages = [b"hello".to_vec(), large, Vec::new(), b"bye".to_vec()];
+        for message in &messages {
+            sender.send(message).unwrap();
+        }
+        let received = exchange(&mut sender, &mut receiver, |seq| seq % 3 == 2);
+        assert_eq!(received, messages);
+    }
+
+    #[test]
+    fn ignores_fragments_sent_again() {
+        let (mut sender, mut receiver) = (ReliableChannel::new(), ReliableChannel::new());
+        sender.send(b"once").unwrap();
+        let now = Instant::now();
+        let mut packet = Vec::new();
+        sender.write(now, PAYLOAD_LEN, &mut packet);
+        // Its ack was lost, so it's sent again.
+        let mut again = Vec::new();
+        sender.write(now + DEFAULT_RESEND_AFTER, PAYLOAD_LEN, &mut again);
+        assert_eq!(packet, again);
+
+        receiver.read(&packet).unwrap();
+        receiver.read(&again).unwrap();
+        assert_eq!(receiver.receive(), Some(b"once".to_vec()));
+        assert_eq!(receiver.receive(), None);
+    }
+
+    #[test]
+    fn limits_what_is_queued() {
+        let mut channel = ReliableChannel::new();
+        assert!(matches!(
+            channel.send(&vec![0; MAX_MESSAGE_LEN + 1]),
+            Err(RpcError::PayloadTooLarge(_))
+        ));
+        for _ in 0..MAX_QUEUED_MESSAGES {
+            channel.send(b"").unwrap();
+        }
+        assert!(matches!(
+            channel.send(b""),
+            Err(RpcError::ReliableBacklog(MAX_QUEUED_MESSAGES))
+        ));
+
+        // Only so many go in flight, however much room there is.
+        let mut packet = Vec::new();
+        let written = channel.write(Instant::now(), usize::MAX, &mut packet);
+        assert_eq!(written.len(), MAX_MESSAGES_IN_FLIGHT);
+    }
+
+    #[test]
+    fn rejects_malformed_fragments() {
+        let mut channel = ReliableChannel::new();
+        let fragment = |fields: [u16; 4], len: usize| {
+            let mut bytes = fields
+                .iter()
+                .flat_map(|field| field.to_le_bytes())
+                .collect::<Vec<_>>();
+            bytes.resize(FRAGMENT_HEADER_LEN + len, 0);
+            bytes
+        };
+        for bytes in [
+            vec![0; 3],
+            fragment([0, 0, 1, 4], 2),
+            fragment([0, 1, 1, 0], 0),
+            fragment([MAX_MESSAGES_IN_FLIGHT as u16, 0, 1, 0], 0),
+        ] {
+            assert!(channel.read(&bytes).is_err(), "{bytes:?} was read");
+        }
+    }
+
+    #[test]
+    fn splits_payloads_below_the_channel() {
+        assert_eq!(
+            split_payload(&[2, 0, 7, 8, 9]),
+            Some((&[7, 8][..], &[9][..]))
+        );
+        assert_eq!(split_payload(&[0, 0]), Some((&[][..], &[][..])));
+        assert_eq!(split_payload(&[3, 0, 7]), None);
+        assert_eq!(split_payload(&[1]), None);
+    }
+}
@@ -0,0 +1,105 @@
+//! Packet sequence numbers. These are u16 on the wire and wrap around, so
+//! ordering is only meaningful between numbers less than half the range apart:
+//! a number is newer than another if it is ahead of it by less than half.
+
+use std::cmp::Ordering;
+use std::fmt;
+
+const HALF_RANGE: u16 = u16::MAX / 2 + 1;
+
+#[derive(bytemuck::Pod, bytemuck::Zeroable, Copy, Clone, Default, PartialEq, Eq, Hash, Debug)]
+#[repr(transparent)]
+pub struct SequenceNumber(pub u16);
+
+impl SequenceNumber {
+    pub const ZERO: Self = Self(0);
+
+    /// The number after this one, wrapping back to zero.
+    pub fn next(self) -> Self {
+        Self(self.0.wrapping_add(1))
+    }
+
+    /// The number `n` before this one.
+    pub fn behind(self, n: u16) -> Self {
+        Self(self.0.wrapping_sub(n))
+    }
+
+    /// Order accounting for wrap-around. Numbers exactly half the range apart
+    /// are ambiguous, and the higher raw value is treated as newer.
+    pub fn wrapping_cmp(self, other: Self) -> Ordering {
+        match self.0.wrapping_sub(other.0) {
+            0 => Ordering::Equal,
+            ahead if ahead < HALF_RANGE => Ordering::Greater,
+            HALF_RANGE => self.0.cmp(&other.0),
+            _ => Ordering::Less,
+        }
+    }
+
+    pub fn is_newer_than(self, other: Self) -> bool {
+        self.wrapping_cmp(other) == Ordering::Greater
+    }
+
+    /// How far ahead of `other` this number is, negative if it's behind.
+    pub fn distance_from(self, other: Self) -> i32 {
+        match self.wrapping_cmp(other) {
+            Ordering::Less => -(other.0.wrapping_sub(self.0) as i32),
+            _ => self.0.wrapping_sub(other.0) as i32,
+        }
+    }
+
+    /// Whether this number is `latest` or one of the `len - 1` before it.
+    pub fn is_within_window(self, latest: Self, len: u16) -> bool {
+        latest.0.wrapping_sub(self.0) < len
+    }
+}
+
+impl fmt::Display for SequenceNumber {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl From<u16> for SequenceNumber {
+    fn from(seq: u16) -> Self {
+        Self(seq)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ordering_and_distance_across_wrap() {
+        let max = SequenceNumber(u16::MAX);
+        assert_eq!(max.next(), SequenceNumber::ZERO);
+        assert_eq!(SequenceNumber::ZERO.behind(1), max);
+
+        assert!(SequenceNumber(1).is_newer_than(SequenceNumber::ZERO));
+        assert!(SequenceNumber(2).is_newer_than(max));
+        assert!(!max.is_newer_than(SequenceNumber(2)));
+        assert!(!SequenceNumber(7).is_newer_than(SequenceNumber(7)));
+        assert!(SequenceNumber(HALF_RANGE - 1).is_newer_than(SequenceNumber::ZERO));
+        assert!(!SequenceNumber(HALF_RANGE + 1).is_newer_than(SequenceNumber::ZERO));
+
+        // Exactly half the range apart is ambiguous, but must still be
+        // antisymmetric.
+        let (a, b) = (SequenceNumber(HALF_RANGE), SequenceNumber::ZERO);
+        assert_eq!(a.wrapping_cmp(b), b.wrapping_cmp(a).reverse());
+
+        assert_eq!(SequenceNumber(2).distance_from(max), 3);
+        assert_eq!(max.distance_from(SequenceNumber(2)), -3);
+        assert_eq!(SequenceNumber(10).distance_from(SequenceNumber(10)), 0);
+    }
+
+    #[test]
+    fn window_inclusion_across_wrap() {
+        let latest = SequenceNumber(3);
+        assert!(latest.is_within_window(latest, 32));
+        assert!(SequenceNumber(0).is_within_window(latest, 32));
+        assert!(SequenceNumber(u16::MAX - 27).is_within_window(latest, 32));
+        assert!(!SequenceNumber(u16::MAX - 28).is_within_window(latest, 32));
+        assert!(!SequenceNumber(4).is_within_window(latest, 32));
+        assert!(!latest.is_within_window(latest, 0));
+    }
+}
@@ -0,0 +1,510 @@
+//! Tracing a connection's messages to a compact binary log, for working out
+//! ack and ordering bugs after the fact. See `examples/trace_dump.rs` for
+//! printing one.
+//!
+//! `Traced` wraps a connection, recording the header of every message sent
+//! and received, when, and what its payload carried, as told by a
+//! `Classify` function from whoever knows the payloads. Wrap the connection
+//! that numbers messages, such as a `manager::PeerConnection`, so the
+//! sequence numbers and acks recorded are those on the wire.
+//!
+//! A trace starts with `MAGIC`, its version and when it was opened in
+//! microseconds since the unix epoch, followed by `RECORD_LEN` byte records,
+//! all little endian.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::compression::Compression;
+use crate::quality::QualitySample;
+use crate::{Connection, Message, PacketCounts, RpcError, SequenceNumber, Typed};
+
+pub const MAGIC: &[u8; 8] = b"NETTRACE";
+pub const VERSION: u16 = 1;
+pub const RECORD_LEN: usize = 25;
+
+const HEADER_LEN: usize = MAGIC.len() + 2 + 8;
+
+/// How often buffered records are written out, so a trace is only missing
+/// the last moments of a process that dies.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Direction {
+    Sent,
+    Received,
+}
+
+/// What a message's payload carried.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PayloadKind {
+    Unknown,
+    /// A client's input for the server.
+    Input,
+    /// World updates from the server.
+    Update,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct PayloadType {
+    pub kind: PayloadKind,
+    /// The codec the payload was compressed with, if it was.
+    pub codec: Option<Compression>,
+    /// Bytes of reliable fragments carried along, see `reliable`.
+    pub reliable_len: u16,
+}
+
+impl PayloadType {
+    pub const UNKNOWN: Self = Self {
+        kind: PayloadKind::Unknown,
+        codec: None,
+        reliable_len: 0,
+    };
+}
+
+/// Tells what a payload carried, from the direction it went and its bytes.
+/// Received payloads are padded with zeroes to `PAYLOAD_LEN`.
+pub type Classify = fn(Direction, &[u8]) -> PayloadType;
+
+/// One message, as traced.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct TraceRecord {
+    /// Since the trace was opened.
+    pub at: Duration,
+    /// Which of the traced connections it went over, in the order they were
+    /// traced.
+    pub connection: u16,
+    pub direction: Direction,
+    pub seq: SequenceNumber,
+    pub ack: SequenceNumber,
+    pub ack_bits: u32,
+    /// Bytes of payload sent. Received payloads are padded with zeroes, so
+    /// they're counted up to their last non-zero byte.
+    pub len: u16,
+    pub payload: PayloadType,
+}
+
+impl TraceRecord {
+    pub fn to_bytes(&self) -> [u8; RECORD_LEN] {
+        let mut bytes = [0; RECORD_LEN];
+        let at = self.at.as_micros().min(u64::MAX as u128) as u64;
+        bytes[0..8].copy_from_slice(&at.to_le_bytes());
+        bytes[8..10].copy_from_slice(&self.connection.to_le_bytes());
+        bytes[10] = match self.direction {
+            Direction::Sent => 0,
+            Direction::Received => 1,
+        };
+        bytes[11] = match self.payload.kind {
+            PayloadKind::Unknown => 0,
+            PayloadKind::Input => 1,
+            PayloadKind::Update => 2,
+        };
+        bytes[12] = self.payload.codec.map_or(0, Compression::id);
+        bytes[13..15].copy_from_slice(&self.seq.0.to_le_bytes());
+        bytes[15..17].copy_from_slice(&self.ack.0.to_le_bytes());
+        bytes[17..21].copy_from_slice(&self.ack_bits.to_le_bytes());
+        bytes[21..23].copy_from_slice(&self.len.to_le_bytes());
+        bytes[23..25].copy_from_slice(&self.payload.reliable_len.to_le_bytes());
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8; RECORD_LEN]) -> Result<Self, RpcError> {
+        let u16_at = |at: usize| u16::from_le_bytes([bytes[at], bytes[at + 1]]);
+        let direction = match bytes[10] {
+            0 => Direction::Sent,
+            1 => Direction::Received,
+            _ => return Err(RpcError::Malformed("unknown trace direction")),
+        };
+        let kind = match bytes[11] {
+            1 => PayloadKind::Input,
+            2 => PayloadKind::Update,
+            _ => PayloadKind::Unknown,
+        };
+        Ok(Self {
+            at: Duration::from_micros(u64::from_le_bytes(bytes[0..8].try_into().unwrap())),
+            connection: u16_at(8),
+            direction,
+            seq: SequenceNumber(u16_at(13)),
+            ack: SequenceNumber(u16_at(15)),
+            ack_bits: u32::from_le_bytes(bytes[17..21].try_into().unwrap()),
+            len: u16_at(21),
+            payload: PayloadType {
+                kind,
+                codec: Compression::from_id(bytes[12]),
+                reliable_len: u16_at(23),
+            },
+        })
+    }
+}
+
+struct TraceWriter {
+    out: Box<dyn Write + Send>,
+    opened: Instant,
+    flushed: Instant,
+    connections: u16,
+    /// Set once writing fails, after which nothing more is traced.
+    failed: bool,
+}
+
+/// A trace being written, shared by the connections traced to it.
+#[derive(Clone)]
+pub struct MessageTrace {
+    writer: Arc<Mutex<TraceWriter>>,
+}
+
+impl MessageTrace {
+    /// Start a trace at `path`, replacing what's there.
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::new(BufWriter::new(File::create(path)?))
+    }
+
+    pub fn new(mut out: impl Write + Send + 'static) -> io::Result<Self> {
+        let started = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_micros() as u64;
+        out.write_all(MAGIC)?;
+        out.write_all(&VERSION.to_le_bytes())?;
+        out.write_all(&started.to_le_bytes())?;
+        let now = Instant::now();
+        Ok(Self {
+            writer: Arc::new(Mutex::new(TraceWriter {
+                out: Box::new(out),
+                opened: now,
+                flushed: now,
+                connections: 0,
+                failed: false,
+            })),
+        })
+    }
+
+    /// Trace `connection`, telling what its payloads carry with `classify`.
+    pub fn trace(
+        &self,
+        connection: Box<dyn Connection + Send + Sync + 'static>,
+        classify: Classify,
+    ) -> Traced {
+        let id = {
+            let mut writer = self.writer.lock().unwrap();
+            let id = writer.connections;
+            writer.connections = id.wrapping_add(1);
+            id
+        };
+        Traced {
+            connection,
+            trace: self.clone(),
+            id,
+            classify,
+        }
+    }
+
+    pub fn flush(&self) -> io::Result<()> {
+        self.writer.lock().unwrap().out.flush()
+    }
+
+    fn record(
+        &self,
+        connection: u16,
+        direction: Direction,
+        header: (SequenceNumber, SequenceNumber, u32),
+        len: usize,
+        payload: PayloadType,
+    ) {
+        let mut writer = self.writer.lock().unwrap();
+        if writer.failed {
+            return;
+        }
+        let now = Instant::now();
+        let (seq, ack, ack_bits) = header;
+        let record = TraceRecord {
+            at: now.duration_since(writer.opened),
+            connection,
+            direction,
+            seq,
+            ack,
+            ack_bits,
+            len: len as u16,
+            payload,
+        };
+        let mut written = writer.out.write_all(&record.to_bytes());
+        if written.is_ok() && now.duration_since(writer.flushed) >= FLUSH_INTERVAL {
+            writer.flushed = now;
+            written = writer.out.flush();
+        }
+        // A trace is a debugging aid, losing it mustn't fail the connection.
+        writer.failed = written.is_err();
+    }
+}
+
+impl Drop for TraceWriter {
+    fn drop(&mut self) {
+        let _ = self.out.flush();
+    }
+}
+
+/// A connection whose messages are recorded to a `MessageTrace`.
+pub struct Traced {
+    connection: Box<dyn Connection + Send + Sync + 'static>,
+    trace: MessageTrace,
+    id: u16,
+    classify: Classify,
+}
+
+impl Traced {
+    /// The id records of this connection's messages have.
+    pub fn id(&self) -> u16 {
+        self.id
+    }
+
+    fn received(&self, message: &Typed<Message>) {
+        if let Ok(msg) = message.try_ref() {
+            let len = msg
+                .payload
+                .iter()
+                .rposition(|&byte| byte != 0)
+                .map_or(0, |last| last + 1);
+            self.trace.record(
+                self.id,
+                Direction::Received,
+                (msg.seq, msg.ack, msg.ack_bits),
+                len,
+                (self.classify)(Direction::Received, &msg.payload),
+            );
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Connection for Traced {
+    fn is_connected(&self) -> bool {
+        self.connection.is_connected()
+    }
+
+    async fn recv(&mut self) -> Result<Typed<Message>, RpcError> {
+        let message = self.connection.recv().await?;
+        self.received(&message);
+        Ok(message)
+    }
+
+    async fn recv_with_timeout(
+        &mut self,
+        timeout_duration: Duration,
+    ) -> Result<Typed<Message>, RpcError> {
+        let message = self.connection.recv_with_timeout(timeout_duration).await?;
+        self.received(&message);
+        Ok(message)
+    }
+
+    async fn send(&mut self, payload: &[u8]) -> Result<SequenceNumber, RpcError> {
+        let seq = self.connection.send(payload).await?;
+        let (ack, ack_bits) = self.connection.last_acks_sent().unwrap_or_default();
+        self.trace.record(
+            self.id,
+            Direction::Sent,
+            (seq, ack, ack_bits),
+            payload.len(),
+            (self.classify)(Direction::Sent, payload),
+        );
+        Ok(seq)
+    }
+
+    fn quality_sample(&self) -> Option<QualitySample> {
+        self.connection.quality_sample()
+    }
+
+    fn unacked_packets(&self) -> usize {
+        self.connection.unacked_packets()
+    }
+
+    fn packet_counts(&self) -> PacketCounts {
+        self.connection.packet_counts()
+    }
+
+    fn take_acked(&mut self) -> Vec<SequenceNumber> {
+        self.connection.take_acked()
+    }
+
+    fn last_acks_sent(&self) -> Option<(SequenceNumber, u32)> {
+        self.connection.last_acks_sent()
+    }
+
+    fn send_reliable(&mut self, message: &[u8]) -> Result<(), RpcError> {
+        self.connection.send_reliable(message)
+    }
+
+    fn recv_reliable(&mut self) -> Option<Vec<u8>> {
+        self.connection.recv_reliable()
+    }
+}
+
+/// Reads the records of a trace written by `MessageTrace`.
+pub struct TraceReader<R> {
+    input: R,
+    /// When the trace was opened, since the unix epoch.
+    pub started: Duration,
+}
+
+impl TraceReader<io::BufReader<File>> {
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::new(io::BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read> TraceReader<R> {
+    pub fn new(mut input: R) -> io::Result<Self> {
+        let mut header = [0; HEADER_LEN];
+        input.read_exact(&mut header)?;
+        let version = u16::from_le_bytes([header[8], header[9]]);
+        if &header[..MAGIC.len()] != MAGIC || version != VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("not a version {VERSION} message trace"),
+            ));
+        }
+        let started = u64::from_le_bytes(header[10..].try_into().unwrap());
+        Ok(Self {
+            input,
+            started: Duration::from_micros(started),
+        })
+    }
+}
+
+impl<R: Read> Iterator for TraceReader<R> {
+    type Item = io::Result<TraceRecord>;
+
+    /// The next record, none at the end of the trace or of a record cut short
+    /// by the process dying.
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut bytes = [0; RECORD_LEN];
+        match self.input.read_exact(&mut bytes) {
+            Ok(()) => Some(
+                TraceRecord::from_bytes(&bytes)
+                    .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.to_string())),
+            ),
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => None,
+            Err(err) => Some(Err(err)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_lite::future;
+
+    use super::*;
+
+    /// Sends to and receives from itself, numbering messages like a peer.
+    #[derive(Default)]
+    struct Echo {
+        seq: SequenceNumber,
+        inbox: Vec<Message>,
+    }
+
+    #[async_trait::async_trait]
+    impl Connection for Echo {
+        fn is_connected(&self) -> bool {
+            true
+        }
+
+        async fn recv(&mut self) -> Result<Typed<Message>, RpcError> {
+            self.recv_with_timeout(Duration::ZERO).await
+        }
+
+        async fn recv_with_timeout(&mut self, _: Duration) -> Result<Typed<Message>, RpcError> {
+            let msg = self.inbox.pop().ok_or(RpcError::Timeout)?;
+            Ok(Typed::new(bytemuck::bytes_of(&msg).to_vec()))
+        }
+
+        async fn send(&mut self, payload: &[u8]) -> Result<SequenceNumber, RpcError> {
+            let msg = Message::new(self.seq, SequenceNumber(7), 0b101, payload);
+            self.inbox.push(msg);
+            self.seq = self.seq.next();
+            Ok(msg.seq)
+        }
+
+        fn last_acks_sent(&self) -> Option<(SequenceNumber, u32)> {
+            Some((SequenceNumber(7), 0b101))
+        }
+    }
+
+    /// Writes to a buffer the test can read back.
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn classify(direction: Direction, payload: &[u8]) -> PayloadType {
+        PayloadType {
+            kind: match direction {
+                Direction::Sent => PayloadKind::Input,
+                Direction::Received => PayloadKind::Update,
+            },
+            codec: Compression::from_id(payload[0]),
+            reliable_len: 12,
+        }
+    }
+
+    #[test]
+    fn records_messages_both_ways() {
+        let out = Shared::default();
+        let trace = MessageTrace::new(out.clone()).unwrap();
+        let mut first = trace.trace(Box::<Echo>::default(), classify);
+        let mut second = trace.trace(Box::<Echo>::default(), classify);
+        assert_eq!((first.id(), second.id()), (0, 1));
+
+        future::block_on(async {
+            first.send(&[2, 0, 9]).await.unwrap();
+            first.recv().await.unwrap();
+            second.send(&[3]).await.unwrap();
+        });
+        drop((first, second, trace));
+
+        let bytes = out.0.lock().unwrap().clone();
+        assert_eq!(bytes.len(), HEADER_LEN + 3 * RECORD_LEN);
+        let records = TraceReader::new(&bytes[..])
+            .unwrap()
+            .collect::<io::Result<Vec<_>>>()
+            .unwrap();
+        let summary = records
+            .iter()
+            .map(|r| (r.connection, r.direction, r.seq.0, r.len, r.payload.kind))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            [
+                (0, Direction::Sent, 0, 3, PayloadKind::Input),
+                (0, Direction::Received, 0, 3, PayloadKind::Update),
+                (1, Direction::Sent, 0, 1, PayloadKind::Input),
+            ]
+        );
+        for record in &records {
+            assert_eq!((record.ack, record.ack_bits), (SequenceNumber(7), 0b101));
+            assert_eq!(record.payload.reliable_len, 12);
+        }
+        assert_eq!(records[0].payload.codec, Some(Compression::Lz4));
+        assert!(records[0].at <= records[2].at);
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        assert!(TraceReader::new(&b"not a trace at all"[..]).is_err());
+        // A record cut short ends the trace.
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+        bytes.extend_from_slice(&0u64.to_le_bytes());
+        bytes.extend_from_slice(&[0; RECORD_LEN - 1]);
+        assert_eq!(TraceReader::new(&bytes[..]).unwrap().count(), 0);
+    }
+}
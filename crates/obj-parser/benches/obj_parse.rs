@@ -0,0 +1,31 @@
+use std::io::{BufReader, Cursor};
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use obj_parser::model::Obj;
+
+const MODELS: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/../../assets/models/static");
+
+fn obj_parse_benchmark(c: &mut Criterion) {
+    let mut group = c.benchmark_group("obj_parse");
+    // Read up front, so only parsing is measured.
+    for name in ["tank.obj", "tank_smooth.obj"] {
+        let source = std::fs::read(format!("{MODELS}/{name}")).unwrap();
+        group.throughput(Throughput::Bytes(source.len() as u64));
+        group.bench_function(format!("parse_{name}"), |b| {
+            b.iter(|| Obj::from_reader(BufReader::new(Cursor::new(black_box(&source)))).unwrap())
+        });
+        group.bench_function(format!("parse_and_interleave_{name}"), |b| {
+            b.iter(|| {
+                let obj =
+                    Obj::from_reader(BufReader::new(Cursor::new(black_box(&source)))).unwrap();
+                for object in obj.objects.iter() {
+                    black_box(object.interleaved().unwrap());
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, obj_parse_benchmark);
+criterion_main!(benches);
@@ -0,0 +1,534 @@
+//! Audio mixing: buses with per-bus volume, pause and ducking, positional
+//! attenuation and cross-faded looping music.
+//!
+//! The mixer only computes gains. Whatever is feeding samples to the SDL audio
+//! device, such as `sound::AudioMixer`, asks the mixer for the gain of a bus
+//! (or a music track) and scales its output accordingly.
+
+use std::time::Duration;
+
+/// A mixing bus. Every voice is routed through exactly one bus, and every bus
+/// is routed through `Master`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[repr(u8)]
+pub enum Bus {
+    Master = 0,
+    Sfx = 1,
+    Music = 2,
+    Ui = 3,
+}
+
+impl Bus {
+    pub const ALL: [Bus; 4] = [Bus::Master, Bus::Sfx, Bus::Music, Bus::Ui];
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// Lower the volume of `target` while anything is playing on `trigger`.
+///
+/// `amount` is the gain multiplier applied to `target` when fully ducked, so
+/// `0.25` means "drop to a quarter of the volume". `attack` is how long it
+/// takes to reach full ducking, `release` how long it takes to recover.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct DuckingRule {
+    pub trigger: Bus,
+    pub target: Bus,
+    pub amount: f32,
+    pub attack: Duration,
+    pub release: Duration,
+}
+
+/// Bus volumes, and how far and how quickly music is ducked while sound
+/// effects play, as set with the audio console variables.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct MixerSettings {
+    pub master_volume: f32,
+    pub sfx_volume: f32,
+    pub music_volume: f32,
+    pub ui_volume: f32,
+    /// Gain applied to music while sound effects play, 1.0 disables ducking.
+    pub music_duck_under_sfx: f32,
+    pub music_duck_attack: Duration,
+    pub music_duck_release: Duration,
+}
+
+impl Default for MixerSettings {
+    fn default() -> Self {
+        Self {
+            master_volume: 1.0,
+            sfx_volume: 1.0,
+            music_volume: 1.0,
+            ui_volume: 1.0,
+            music_duck_under_sfx: 0.5,
+            music_duck_attack: Duration::from_millis(150),
+            music_duck_release: Duration::from_millis(600),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+struct BusState {
+    volume: f32,
+    paused: bool,
+    active_voices: u32,
+}
+
+impl Default for BusState {
+    fn default() -> Self {
+        Self {
+            volume: 1.0,
+            paused: false,
+            active_voices: 0,
+        }
+    }
+}
+
+/// Identifies a music track for the `MusicPlayer`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct TrackId(pub u32);
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+struct Fade {
+    track: TrackId,
+    gain: f32,
+    // gain change per second, negative when fading out.
+    rate: f32,
+}
+
+impl Fade {
+    fn new(track: TrackId, gain: f32, target: f32, duration: Duration) -> Self {
+        let secs = duration.as_secs_f32();
+        if secs > 0.0 {
+            Self {
+                track,
+                gain,
+                rate: (target - gain) / secs,
+            }
+        } else {
+            // Instant fades jump straight to the target.
+            Self {
+                track,
+                gain: target,
+                rate: 0.0,
+            }
+        }
+    }
+
+    fn step(&mut self, dt: f32) {
+        self.gain = (self.gain + self.rate * dt).clamp(0.0, 1.0);
+    }
+}
+
+/// Looping music with cross-fades between tracks. The gains reported here are
+/// per-track and do not include the `Music` bus gain, see
+/// `Mixer::music_gains`.
+#[derive(Debug, Default)]
+pub struct MusicPlayer {
+    current: Option<Fade>,
+    fading_out: Vec<Fade>,
+}
+
+impl MusicPlayer {
+    /// Start looping `track`, cross-fading from whatever is currently playing
+    /// over `crossfade`. Playing the track that is already current is a no-op.
+    pub fn play_looping(&mut self, track: TrackId, crossfade: Duration) {
+        if let Some(current) = self.current {
+            if current.track == track {
+                return;
+            }
+        }
+        // If the new track is still fading out, pick it back up from its
+        // current gain rather than restarting from silence.
+        let start_gain = match self.fading_out.iter().position(|f| f.track == track) {
+            Some(index) => self.fading_out.remove(index).gain,
+            None => 0.0,
+        };
+        self.fade_out_current(crossfade);
+        self.current = Some(Fade::new(track, start_gain, 1.0, crossfade));
+    }
+
+    /// Fade out the current track over `fade`.
+    pub fn stop(&mut self, fade: Duration) {
+        self.fade_out_current(fade);
+    }
+
+    fn fade_out_current(&mut self, fade: Duration) {
+        if let Some(current) = self.current.take() {
+            self.fading_out
+                .push(Fade::new(current.track, current.gain, 0.0, fade));
+        }
+    }
+
+    pub fn current(&self) -> Option<TrackId> {
+        self.current.map(|f| f.track)
+    }
+
+    pub fn update(&mut self, dt: &Duration) {
+        let dt = dt.as_secs_f32();
+        if let Some(current) = self.current.as_mut() {
+            current.step(dt);
+        }
+        for fade in self.fading_out.iter_mut() {
+            fade.step(dt);
+        }
+        self.fading_out.retain(|f| f.gain > 0.0);
+    }
+
+    /// Every audible track and its fade gain.
+    pub fn gains(&self) -> impl Iterator<Item = (TrackId, f32)> + '_ {
+        self.fading_out
+            .iter()
+            .chain(self.current.iter())
+            .map(|f| (f.track, f.gain))
+    }
+}
+
+/// Bus volumes, pause state and ducking.
+#[derive(Debug)]
+pub struct Mixer {
+    buses: [BusState; 4],
+    // Each rule with its current gain multiplier, 1.0 being "not ducked".
+    ducking: Vec<(DuckingRule, f32)>,
+    music: MusicPlayer,
+}
+
+impl Default for Mixer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Mixer {
+    pub fn new() -> Self {
+        Self {
+            buses: [BusState::default(); 4],
+            ducking: Vec::new(),
+            music: MusicPlayer::default(),
+        }
+    }
+
+    /// Set the volume of a bus, clamped to `0.0..=1.0`.
+    pub fn set_volume(&mut self, bus: Bus, volume: f32) {
+        self.buses[bus.index()].volume = volume.clamp(0.0, 1.0);
+    }
+
+    pub fn volume(&self, bus: Bus) -> f32 {
+        self.buses[bus.index()].volume
+    }
+
+    pub fn pause(&mut self, bus: Bus) {
+        self.buses[bus.index()].paused = true;
+    }
+
+    pub fn resume(&mut self, bus: Bus) {
+        self.buses[bus.index()].paused = false;
+    }
+
+    /// A bus is paused if it, or `Master`, has been paused.
+    pub fn is_paused(&self, bus: Bus) -> bool {
+        self.buses[bus.index()].paused || self.buses[Bus::Master.index()].paused
+    }
+
+    pub fn add_ducking_rule(&mut self, rule: DuckingRule) {
+        self.ducking.push((rule, 1.0));
+    }
+
+    pub fn clear_ducking_rules(&mut self) {
+        self.ducking.clear();
+    }
+
+    /// Set the bus volumes and the rule ducking music under sound effects
+    /// from `settings`. A rule that's already ducking carries on from where
+    /// it is, so this can be called every frame.
+    pub fn configure(&mut self, settings: &MixerSettings) {
+        self.set_volume(Bus::Master, settings.master_volume);
+        self.set_volume(Bus::Sfx, settings.sfx_volume);
+        self.set_volume(Bus::Music, settings.music_volume);
+        self.set_volume(Bus::Ui, settings.ui_volume);
+        let existing = self
+            .ducking
+            .iter()
+            .position(|(rule, _)| rule.trigger == Bus::Sfx && rule.target == Bus::Music);
+        if settings.music_duck_under_sfx >= 1.0 {
+            if let Some(index) = existing {
+                self.ducking.remove(index);
+            }
+            return;
+        }
+        let rule = DuckingRule {
+            trigger: Bus::Sfx,
+            target: Bus::Music,
+            amount: settings.music_duck_under_sfx.max(0.0),
+            attack: settings.music_duck_attack,
+            release: settings.music_duck_release,
+        };
+        match existing {
+            Some(index) => self.ducking[index].0 = rule,
+            None => self.add_ducking_rule(rule),
+        }
+    }
+
+    /// Track a voice starting on `bus`, so ducking rules triggered by the bus
+    /// engage.
+    pub fn voice_started(&mut self, bus: Bus) {
+        self.buses[bus.index()].active_voices += 1;
+    }
+
+    pub fn voice_stopped(&mut self, bus: Bus) {
+        let state = &mut self.buses[bus.index()];
+        state.active_voices = state.active_voices.saturating_sub(1);
+    }
+
+    pub fn music(&self) -> &MusicPlayer {
+        &self.music
+    }
+
+    pub fn music_mut(&mut self) -> &mut MusicPlayer {
+        &mut self.music
+    }
+
+    /// Advance ducking envelopes and music fades.
+    pub fn update(&mut self, dt: &Duration) {
+        let secs = dt.as_secs_f32();
+        for (rule, gain) in self.ducking.iter_mut() {
+            let triggered = self.buses[rule.trigger.index()].active_voices > 0
+                && !(self.buses[rule.trigger.index()].paused
+                    || self.buses[Bus::Master.index()].paused);
+            let (target, duration) = if triggered {
+                (rule.amount, rule.attack)
+            } else {
+                (1.0, rule.release)
+            };
+            let range = (1.0 - rule.amount).abs();
+            let step = if duration.is_zero() {
+                f32::INFINITY
+            } else {
+                range * secs / duration.as_secs_f32()
+            };
+            *gain = if *gain < target {
+                (*gain + step).min(target)
+            } else {
+                (*gain - step).max(target)
+            };
+        }
+        self.music.update(dt);
+    }
+
+    /// The current ducking multiplier for a bus. When several rules target the
+    /// same bus the strongest one wins.
+    pub fn ducking(&self, bus: Bus) -> f32 {
+        self.ducking
+            .iter()
+            .filter(|(rule, _)| rule.target == bus)
+            .map(|(_, gain)| *gain)
+            .fold(1.0, f32::min)
+    }
+
+    /// The effective gain of a bus: its volume and ducking, combined with
+    /// `Master`. Paused buses are silent.
+    pub fn gain(&self, bus: Bus) -> f32 {
+        if self.is_paused(bus) {
+            return 0.0;
+        }
+        let master = self.volume(Bus::Master) * self.ducking(Bus::Master);
+        if bus == Bus::Master {
+            return master;
+        }
+        master * self.volume(bus) * self.ducking(bus)
+    }
+
+    /// Gain for a positional voice on `bus`, `distance` units from the
+    /// listener.
+    pub fn positional_gain(
+        &self,
+        bus: Bus,
+        distance: f32,
+        min_distance: f32,
+        max_distance: f32,
+    ) -> f32 {
+        self.gain(bus) * distance_attenuation(distance, min_distance, max_distance)
+    }
+
+    /// Every audible music track with its final gain, including the `Music`
+    /// bus.
+    pub fn music_gains(&self) -> impl Iterator<Item = (TrackId, f32)> + '_ {
+        let bus_gain = self.gain(Bus::Music);
+        self.music
+            .gains()
+            .map(move |(track, gain)| (track, gain * bus_gain))
+    }
+}
+
+/// Inverse-distance attenuation, clamped to full volume inside `min_distance`
+/// and faded to silence at `max_distance`.
+pub fn distance_attenuation(distance: f32, min_distance: f32, max_distance: f32) -> f32 {
+    let min_distance = min_distance.max(f32::EPSILON);
+    if distance <= min_distance {
+        return 1.0;
+    }
+    if distance >= max_distance {
+        return 0.0;
+    }
+    let inverse = min_distance / distance;
+    let fade = 1.0 - (distance - min_distance) / (max_distance - min_distance);
+    inverse * fade
+}
+
+/// Gain left to a fully occluded sound, see `occlusion_filter`.
+const OCCLUDED_GAIN: f32 = 0.3;
+/// Cutoff of the low-pass filter a fully occluded sound is played through.
+const OCCLUDED_CUTOFF_HZ: f32 = 600.0;
+/// Cutoff a barely occluded sound starts from, around the top of hearing.
+const OPEN_CUTOFF_HZ: f32 = 20_000.0;
+
+/// How a sound is muffled by geometry between it and the listener, from 0 for
+/// not at all to 1: the gain to scale it by, and the cutoff of the low-pass
+/// filter to play it through, None when it isn't occluded.
+pub fn occlusion_filter(occlusion: f32) -> (f32, Option<f32>) {
+    let occlusion = occlusion.clamp(0.0, 1.0);
+    if occlusion == 0.0 {
+        return (1.0, None);
+    }
+    let gain = 1.0 - (1.0 - OCCLUDED_GAIN) * occlusion;
+    // Pitch is heard logarithmically, so the cutoff falls geometrically.
+    let cutoff = OPEN_CUTOFF_HZ * (OCCLUDED_CUTOFF_HZ / OPEN_CUTOFF_HZ).powf(occlusion);
+    (gain, Some(cutoff))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn approx(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-4
+    }
+
+    #[test]
+    fn bus_gain_includes_master() {
+        let mut mixer = Mixer::new();
+        mixer.set_volume(Bus::Master, 0.5);
+        mixer.set_volume(Bus::Sfx, 0.5);
+        assert!(approx(mixer.gain(Bus::Sfx), 0.25));
+        assert!(approx(mixer.gain(Bus::Music), 0.5));
+
+        mixer.set_volume(Bus::Ui, 3.0);
+        assert!(approx(mixer.volume(Bus::Ui), 1.0));
+    }
+
+    #[test]
+    fn pausing_master_silences_everything() {
+        let mut mixer = Mixer::new();
+        mixer.pause(Bus::Music);
+        assert_eq!(mixer.gain(Bus::Music), 0.0);
+        assert_eq!(mixer.gain(Bus::Sfx), 1.0);
+
+        mixer.pause(Bus::Master);
+        for bus in Bus::ALL {
+            assert!(mixer.is_paused(bus));
+            assert_eq!(mixer.gain(bus), 0.0);
+        }
+        mixer.resume(Bus::Master);
+        mixer.resume(Bus::Music);
+        assert_eq!(mixer.gain(Bus::Music), 1.0);
+    }
+
+    #[test]
+    fn ducking_attacks_and_releases() {
+        let mut mixer = Mixer::new();
+        mixer.add_ducking_rule(DuckingRule {
+            trigger: Bus::Sfx,
+            target: Bus::Music,
+            amount: 0.5,
+            attack: Duration::from_millis(100),
+            release: Duration::from_millis(200),
+        });
+
+        mixer.voice_started(Bus::Sfx);
+        mixer.update(&Duration::from_millis(50));
+        assert!(approx(mixer.gain(Bus::Music), 0.75));
+        mixer.update(&Duration::from_millis(100));
+        assert!(approx(mixer.gain(Bus::Music), 0.5));
+        // sfx itself is not affected
+        assert_eq!(mixer.gain(Bus::Sfx), 1.0);
+
+        mixer.voice_stopped(Bus::Sfx);
+        mixer.update(&Duration::from_millis(100));
+        assert!(approx(mixer.gain(Bus::Music), 0.75));
+        mixer.update(&Duration::from_millis(100));
+        assert!(approx(mixer.gain(Bus::Music), 1.0));
+    }
+
+    #[test]
+    fn configuring_keeps_the_ducking_envelope() {
+        let mut mixer = Mixer::new();
+        let mut settings = MixerSettings {
+            music_duck_attack: Duration::from_millis(100),
+            ..Default::default()
+        };
+        mixer.configure(&settings);
+        mixer.voice_started(Bus::Sfx);
+        mixer.update(&Duration::from_millis(50));
+        assert!(approx(mixer.gain(Bus::Music), 0.75));
+
+        // Configured again mid-attack, the envelope carries on.
+        settings.music_volume = 0.5;
+        mixer.configure(&settings);
+        assert!(approx(mixer.gain(Bus::Music), 0.375));
+        mixer.update(&Duration::from_millis(50));
+        assert!(approx(mixer.gain(Bus::Music), 0.25));
+
+        settings.music_duck_under_sfx = 1.0;
+        mixer.configure(&settings);
+        assert!(approx(mixer.gain(Bus::Music), 0.5));
+    }
+
+    #[test]
+    fn attenuation_is_clamped() {
+        assert_eq!(distance_attenuation(0.5, 1.0, 10.0), 1.0);
+        assert_eq!(distance_attenuation(10.0, 1.0, 10.0), 0.0);
+        assert_eq!(distance_attenuation(20.0, 1.0, 10.0), 0.0);
+        let mid = distance_attenuation(5.0, 1.0, 10.0);
+        assert!(mid > 0.0 && mid < 1.0);
+        assert!(distance_attenuation(4.0, 1.0, 10.0) > mid);
+    }
+
+    #[test]
+    fn occlusion_muffles_and_quietens() {
+        assert_eq!(occlusion_filter(0.0), (1.0, None));
+        let (gain, cutoff) = occlusion_filter(1.0);
+        assert!(approx(gain, OCCLUDED_GAIN));
+        assert!((cutoff.unwrap() - OCCLUDED_CUTOFF_HZ).abs() < 0.01);
+        let (half_gain, half_cutoff) = occlusion_filter(0.5);
+        assert!(half_gain < 1.0 && half_gain > gain);
+        assert!(half_cutoff.unwrap() < OPEN_CUTOFF_HZ && half_cutoff > cutoff);
+        assert_eq!(occlusion_filter(2.0), occlusion_filter(1.0));
+    }
+
+    #[test]
+    fn music_crossfades_between_tracks() {
+        let mut mixer = Mixer::new();
+        let fade = Duration::from_secs(1);
+        mixer.music_mut().play_looping(TrackId(1), Duration::ZERO);
+        assert_eq!(mixer.music_gains().collect::<Vec<_>>(), [(TrackId(1), 1.0)]);
+
+        mixer.music_mut().play_looping(TrackId(2), fade);
+        mixer.update(&Duration::from_millis(500));
+        let gains = mixer.music_gains().collect::<Vec<_>>();
+        assert_eq!(gains.len(), 2);
+        assert!(approx(gains[0].1, 0.5));
+        assert!(approx(gains[1].1, 0.5));
+
+        mixer.update(&Duration::from_millis(500));
+        assert_eq!(mixer.music_gains().collect::<Vec<_>>(), [(TrackId(2), 1.0)]);
+        assert_eq!(mixer.music().current(), Some(TrackId(2)));
+
+        mixer.set_volume(Bus::Music, 0.5);
+        assert_eq!(mixer.music_gains().collect::<Vec<_>>(), [(TrackId(2), 0.5)]);
+
+        mixer.music_mut().stop(fade);
+        mixer.update(&Duration::from_secs(2));
+        assert_eq!(mixer.music_gains().count(), 0);
+    }
+}
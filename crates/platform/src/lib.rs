@@ -1,15 +1,24 @@
+pub mod audio;
+pub mod sound;
+
+use std::cell::RefCell;
 use std::collections::HashMap;
 use std::fmt::Debug;
 use std::path::Path;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
 
 use image::GenericImageView;
-use input::{Button, DeviceEvent, EngineEvent, InputEvent};
-use logger::{info, Logger};
+use input::calibration::Calibration;
+use input::haptics::{HapticsRequest, Rumble, RumbleCommand, RumbleSequencer};
+use input::{Button, DeviceEvent, EngineEvent, InputEvent, MouseButton, MouseLook};
+use logger::{info, trace, Logger};
 use raw_window_handle::{HasRawWindowHandle, RawWindowHandle};
 use sdl2::controller::GameController;
-use sdl2::event::Event as SdlEvent;
+use sdl2::event::{Event as SdlEvent, WindowEvent};
 use sdl2::haptic::Haptic;
 use sdl2::keyboard::Keycode;
+use sdl2::mouse::{MouseUtil, MouseWheelDirection};
 use sdl2::pixels::PixelFormatEnum;
 use sdl2::surface::Surface;
 
@@ -53,19 +62,96 @@ unsafe impl HasRawWindowHandle for WinPtr {
     }
 }
 
+/// Size of a window. On high-DPI displays the drawable, in pixels, is larger
+/// than the window's logical size in screen coordinates.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct WindowSize {
+    pub logical: (u32, u32),
+    pub drawable: (u32, u32),
+}
+
+impl WindowSize {
+    /// Pixels per screen coordinate, which UI should be scaled by.
+    pub fn scale_factor(&self) -> f32 {
+        if self.logical.0 == 0 {
+            return 1.0;
+        }
+        self.drawable.0 as f32 / self.logical.0 as f32
+    }
+}
+
+/// Probe each SDL subsystem the engine uses, for `nshell --diagnose`. Returns
+/// a description of each subsystem, or why it's unavailable.
+pub fn diagnose_subsystems() -> Vec<(&'static str, Result<String, String>)> {
+    let sdl_context = match sdl2::init() {
+        Ok(sdl_context) => sdl_context,
+        Err(err) => return vec![("sdl", Err(err))],
+    };
+    let video = sdl_context.video().and_then(|video| {
+        let displays = video.num_video_displays()?;
+        Ok(format!(
+            "driver {}, {displays} display(s)",
+            video.current_video_driver()
+        ))
+    });
+    let audio = sdl_context.audio().map(|audio| {
+        format!(
+            "driver {}, {} playback device(s)",
+            audio.current_audio_driver(),
+            audio
+                .num_audio_playback_devices()
+                .map_or_else(|| "unknown".to_string(), |count| count.to_string())
+        )
+    });
+    let game_controllers = sdl_context.game_controller().and_then(|controllers| {
+        let joysticks = controllers.num_joysticks()?;
+        let game_controllers = (0..joysticks)
+            .filter(|index| controllers.is_game_controller(*index))
+            .count();
+        Ok(format!(
+            "{joysticks} joystick(s), {game_controllers} game controller(s)"
+        ))
+    });
+    let haptic = sdl_context.haptic().map(|_| "available".to_string());
+    vec![
+        ("sdl", Ok(sdl2::version::version().to_string())),
+        ("video", video),
+        ("audio", audio),
+        ("game_controllers", game_controllers),
+        ("haptic", haptic),
+    ]
+}
+
 pub struct PlatformContext {
     _sdl_context: sdl2::Sdl,
     haptic_subsystem: sdl2::HapticSubsystem,
     game_controller_subsystem: sdl2::GameControllerSubsystem,
     event_pump: sdl2::EventPump,
     video_subsystem: sdl2::VideoSubsystem,
-    _todo_audio_subsystem: sdl2::AudioSubsystem,
+    _audio_subsystem: sdl2::AudioSubsystem,
+    audio_mixer: audio::Mixer,
+    /// Plays sounds through `audio_mixer`'s buses.
+    sound: sound::AudioMixer,
+    mouse: MouseUtil,
+    /// Relative mouse mode was asked for, see `set_relative_mouse_mode`.
+    relative_mouse: bool,
+    cursor_captured_by_ui: bool,
+    mouse_look: MouseLook,
+    /// Relative motion and where the cursor moved to over the events pumped
+    /// so far this frame, reported once they're all pumped.
+    mouse_motion: (i32, i32),
+    mouse_moved_to: Option<(i32, i32)>,
 
     //
     windows: Vec<sdl2::video::Window>,
     outgoing_events: Vec<EngineEvent>,
     game_controllers: HashMap<u32, GameController>,
     haptic_devices: HashMap<u32, Haptic>,
+    /// Applied to controller axes before they're sent on, see
+    /// `set_calibration`.
+    calibration: Rc<RefCell<Calibration>>,
+    /// Pattern playing on the haptic devices, see `play_rumble`.
+    rumble: RumbleSequencer,
     logger: Logger,
 }
 
@@ -82,9 +168,18 @@ impl PlatformContext {
             .map_err(PlatformError::EventPumpInit)?;
         let audio_subsystem = sdl_context.audio().map_err(PlatformError::AudioInit)?;
         let video_subsystem = sdl_context.video().map_err(PlatformError::VideoInit)?;
+        let mouse = sdl_context.mouse();
         Ok(Self {
             _sdl_context: sdl_context,
-            _todo_audio_subsystem: audio_subsystem,
+            sound: sound::AudioMixer::open(&audio_subsystem, &logger),
+            _audio_subsystem: audio_subsystem,
+            audio_mixer: audio::Mixer::new(),
+            mouse,
+            relative_mouse: false,
+            cursor_captured_by_ui: false,
+            mouse_look: MouseLook::default(),
+            mouse_motion: (0, 0),
+            mouse_moved_to: None,
 
             haptic_subsystem,
             game_controller_subsystem,
@@ -94,6 +189,8 @@ impl PlatformContext {
             outgoing_events: Vec::with_capacity(50),
             game_controllers: HashMap::new(),
             haptic_devices: HashMap::new(),
+            calibration: Rc::default(),
+            rumble: RumbleSequencer::default(),
             logger,
         })
     }
@@ -150,6 +247,15 @@ impl PlatformContext {
         })
     }
 
+    /// Current size of the window at `index`, in screen coordinates and in
+    /// pixels of its vulkan surface.
+    pub fn window_size(&self, index: usize) -> Option<WindowSize> {
+        self.windows.get(index).map(|w| WindowSize {
+            logical: w.size(),
+            drawable: w.vulkan_drawable_size(),
+        })
+    }
+
     // Pump a maximum of 50 events.
     pub fn pump_events(&mut self) {
         let logger = self.logger.sub("pump_events");
@@ -170,12 +276,81 @@ impl PlatformContext {
                 break 'poll_event;
             }
         }
+        let mouse_motion = std::mem::take(&mut self.mouse_motion);
+        let mouse_moved_to = self.mouse_moved_to.take();
+        if self.is_relative_mouse_active() {
+            if mouse_motion != (0, 0) {
+                self.outgoing_events
+                    .push(EngineEvent::Input(InputEvent::MouseMotion(
+                        mouse_motion.0,
+                        mouse_motion.1,
+                    )));
+            }
+        } else if let Some((x, y)) = mouse_moved_to {
+            self.outgoing_events
+                .push(EngineEvent::Input(InputEvent::MouseMoved(x, y)));
+        }
     }
 
     pub fn peek_events(&self) -> &[EngineEvent] {
         &self.outgoing_events
     }
 
+    /// Hide and confine the cursor, reporting relative mouse motion for
+    /// FPS-style controls. Suspended while UI has the cursor, see
+    /// `set_cursor_captured_by_ui`.
+    pub fn set_relative_mouse_mode(&mut self, enabled: bool) {
+        self.relative_mouse = enabled;
+        self.apply_relative_mouse_mode();
+    }
+
+    /// UI showing a cursor, such as a debug UI, captures it to suspend
+    /// relative mouse mode until it's released.
+    pub fn set_cursor_captured_by_ui(&mut self, captured: bool) {
+        self.cursor_captured_by_ui = captured;
+        self.apply_relative_mouse_mode();
+    }
+
+    /// Whether relative mouse mode was asked for, whether or not UI has
+    /// suspended it.
+    pub fn relative_mouse_mode(&self) -> bool {
+        self.relative_mouse
+    }
+
+    pub fn is_relative_mouse_active(&self) -> bool {
+        self.relative_mouse && !self.cursor_captured_by_ui
+    }
+
+    fn apply_relative_mouse_mode(&self) {
+        self.mouse
+            .set_relative_mouse_mode(self.is_relative_mouse_active());
+    }
+
+    /// Calibrate controller axes with profiles shared with whatever edits
+    /// them, such as console commands.
+    pub fn set_calibration(&mut self, calibration: Rc<RefCell<Calibration>>) {
+        self.calibration = calibration;
+    }
+
+    pub fn set_mouse_look(&mut self, mouse_look: MouseLook) {
+        self.mouse_look = mouse_look;
+    }
+
+    /// Yaw and pitch in radians from this frame's relative mouse motion.
+    pub fn look_delta(&self) -> (f32, f32) {
+        self.outgoing_events
+            .iter()
+            .filter_map(|event| match event {
+                EngineEvent::Input(InputEvent::MouseMotion(dx, dy)) => {
+                    Some(self.mouse_look.look_delta(*dx, *dy))
+                }
+                _ => None,
+            })
+            .fold((0.0, 0.0), |(yaw, pitch), (dyaw, dpitch)| {
+                (yaw + dyaw, pitch + dpitch)
+            })
+    }
+
     // TODO: Probably we want either a Result<EngineEvent, ...> or
     /// Evaluate an event from SDL, modify state internally and then raise an
     /// engine event event if it's relevant to the event loop.
@@ -187,14 +362,50 @@ impl PlatformContext {
                 ..
             } => return EngineEvent::ExitToDesktop,
             SdlEvent::KeyDown {
-                keycode: Some(key), ..
+                keycode: Some(key),
+                repeat,
+                ..
             } => {
-                return EngineEvent::Input(InputEvent::KeyPressed(keycode_to_button(*key)));
+                return match function_key(*key) {
+                    // Shortcuts fire once however long they're held.
+                    Some(_) if *repeat => EngineEvent::Continue,
+                    Some(number) => EngineEvent::Input(InputEvent::FunctionKey(number, true)),
+                    None => EngineEvent::Input(InputEvent::KeyPressed(keycode_to_button(*key))),
+                };
             }
             SdlEvent::KeyUp {
                 keycode: Some(key), ..
             } => {
-                return EngineEvent::Input(InputEvent::KeyReleased(keycode_to_button(*key)));
+                return match function_key(*key) {
+                    Some(number) => EngineEvent::Input(InputEvent::FunctionKey(number, false)),
+                    None => EngineEvent::Input(InputEvent::KeyReleased(keycode_to_button(*key))),
+                };
+            }
+            // Motion is summed over the frame, see `pump_events`.
+            SdlEvent::MouseMotion {
+                x, y, xrel, yrel, ..
+            } => {
+                self.mouse_motion = (self.mouse_motion.0 + xrel, self.mouse_motion.1 + yrel);
+                self.mouse_moved_to = Some((*x, *y));
+            }
+            SdlEvent::MouseButtonDown { mouse_btn, .. } => {
+                if let Some(button) = mouse_button(*mouse_btn) {
+                    return EngineEvent::Input(InputEvent::MouseButton(button, true));
+                }
+            }
+            SdlEvent::MouseButtonUp { mouse_btn, .. } => {
+                if let Some(button) = mouse_button(*mouse_btn) {
+                    return EngineEvent::Input(InputEvent::MouseButton(button, false));
+                }
+            }
+            SdlEvent::MouseWheel {
+                x, y, direction, ..
+            } => {
+                let (x, y) = match direction {
+                    MouseWheelDirection::Flipped => (-x, -y),
+                    _ => (*x, *y),
+                };
+                return EngineEvent::Input(InputEvent::MouseWheel(x, y));
             }
             SdlEvent::ControllerButtonDown {
                 timestamp: _,
@@ -222,11 +433,15 @@ impl PlatformContext {
                 axis,
                 value,
             } => {
+                let value = self
+                    .calibration
+                    .borrow_mut()
+                    .apply(*which, *axis as u8, *value);
                 return EngineEvent::Input(InputEvent::AxisMotion(
                     *which as u8,
                     *axis as u8,
-                    (*value >> 8) as i8,
-                ))
+                    value,
+                ));
             }
             SdlEvent::ControllerDeviceAdded {
                 timestamp: _,
@@ -238,6 +453,9 @@ impl PlatformContext {
                         todo!("handle this error better")
                     }
                 };
+                self.calibration
+                    .borrow_mut()
+                    .connect(game_controller.instance_id(), &game_controller.name());
                 self.game_controllers
                     .insert(*controller_index, game_controller);
                 if let Ok(haptic) = self
@@ -255,18 +473,110 @@ impl PlatformContext {
                 timestamp: _,
                 which,
             } => {
+                self.calibration.borrow_mut().disconnect(*which);
+                // Controllers are added by device index but removed by
+                // instance id.
+                let removed = self
+                    .game_controllers
+                    .iter()
+                    .find(|(_, controller)| controller.instance_id() == *which)
+                    .map(|(index, _)| *index);
+                if let Some(index) = removed {
+                    self.game_controllers.remove(&index);
+                    self.haptic_devices.remove(&index);
+                }
                 return EngineEvent::InputDevice(DeviceEvent::GameControllerRemoved(*which));
             }
+            SdlEvent::Window {
+                window_id,
+                win_event: WindowEvent::SizeChanged(..),
+                ..
+            } => {
+                if let Some(index) = self.windows.iter().position(|w| w.id() == *window_id) {
+                    return EngineEvent::WindowResized(index);
+                }
+            }
             _ => {}
         }
         EngineEvent::Continue
     }
 
-    // TODO: evaluate this library's sound capabilities
-    // fn play_sound(&self) {
-    //     self.audio_subsystem
-    //         .open_playback(device, spec, get_callback)
-    // }
+    /// Rumble the game controllers, unless another rumble started too
+    /// recently. Played by `update_rumble`.
+    pub fn play_rumble(&mut self, rumble: Rumble) {
+        if !self.rumble.play(rumble, Instant::now()) {
+            trace!(
+                self.logger,
+                "rumble {rumble:?} dropped, too soon after the last"
+            );
+        }
+    }
+
+    /// Drive one controller's motors directly, reported in this frame's
+    /// events. Ignored for controllers that aren't connected or can't rumble.
+    pub fn play_haptics(&mut self, request: HapticsRequest) {
+        let controller = match request {
+            HapticsRequest::Rumble { controller, .. } | HapticsRequest::Stop { controller } => {
+                controller
+            }
+        };
+        let haptic = match self.haptic_devices.get_mut(&controller) {
+            Some(haptic) => haptic,
+            None => {
+                trace!(
+                    self.logger,
+                    "haptics {request:?} dropped, controller {controller} can't rumble"
+                );
+                return;
+            }
+        };
+        match request {
+            HapticsRequest::Rumble {
+                strength, duration, ..
+            } => haptic.rumble_play(strength, duration.as_millis() as u32),
+            HapticsRequest::Stop { .. } => haptic.rumble_stop(),
+        }
+        self.outgoing_events.push(EngineEvent::Haptics(request));
+    }
+
+    /// Move the playing rumble pattern along, called every frame.
+    pub fn update_rumble(&mut self) {
+        let command = match self.rumble.update(Instant::now()) {
+            Some(command) => command,
+            None => return,
+        };
+        for haptic in self.haptic_devices.values_mut() {
+            match command {
+                RumbleCommand::Play { strength, duration } => {
+                    haptic.rumble_play(strength, duration.as_millis() as u32)
+                }
+                RumbleCommand::Stop => haptic.rumble_stop(),
+            }
+        }
+    }
+
+    pub fn audio_mixer(&self) -> &audio::Mixer {
+        &self.audio_mixer
+    }
+
+    pub fn audio_mixer_mut(&mut self) -> &mut audio::Mixer {
+        &mut self.audio_mixer
+    }
+
+    pub fn sound(&self) -> &sound::AudioMixer {
+        &self.sound
+    }
+
+    pub fn sound_mut(&mut self) -> &mut sound::AudioMixer {
+        &mut self.sound
+    }
+
+    /// Advance the mixer's ducking and fades by `dt`, and hand the gains of
+    /// its buses to the sounds playing.
+    pub fn update_audio(&mut self, dt: &Duration) {
+        self.audio_mixer.update(dt);
+        self.sound.update(&mut self.audio_mixer);
+    }
 }
 
 fn keycode_to_button(button: sdl2::keyboard::Keycode) -> Button {
@@ -281,6 +591,38 @@ fn keycode_to_button(button: sdl2::keyboard::Keycode) -> Button {
     }
 }
 
+/// Number of a function key, F1 to F12.
+fn function_key(key: sdl2::keyboard::Keycode) -> Option<u8> {
+    let keys = [
+        Keycode::F1,
+        Keycode::F2,
+        Keycode::F3,
+        Keycode::F4,
+        Keycode::F5,
+        Keycode::F6,
+        Keycode::F7,
+        Keycode::F8,
+        Keycode::F9,
+        Keycode::F10,
+        Keycode::F11,
+        Keycode::F12,
+    ];
+    keys.iter()
+        .position(|function_key| *function_key == key)
+        .map(|index| index as u8 + 1)
+}
+
+fn mouse_button(button: sdl2::mouse::MouseButton) -> Option<MouseButton> {
+    match button {
+        sdl2::mouse::MouseButton::Left => Some(MouseButton::Left),
+        sdl2::mouse::MouseButton::Middle => Some(MouseButton::Middle),
+        sdl2::mouse::MouseButton::Right => Some(MouseButton::Right),
+        sdl2::mouse::MouseButton::X1 => Some(MouseButton::X1),
+        sdl2::mouse::MouseButton::X2 => Some(MouseButton::X2),
+        sdl2::mouse::MouseButton::Unknown => None,
+    }
+}
+
 fn button_to_button(button: sdl2::controller::Button) -> Button {
     match button {
         sdl2::controller::Button::A => Button::Ok,
@@ -0,0 +1,587 @@
+//! Sound playback: sounds loaded from WAV or OGG files, mixed into the SDL
+//! audio device's callback as one-shot or looping voices.
+//!
+//! Sounds are converted to the device's rate as interleaved stereo floats when
+//! they're loaded, so the callback only has to add them up. Each voice is
+//! scaled by its own gain and by the gain of its bus, which
+//! `AudioMixer::update` copies from the `audio::Mixer` every frame, and may be
+//! muffled through a low-pass filter of its own. Music is played as looping
+//! voices on the music bus, one for each track the mixer's `MusicPlayer` has
+//! audible, at the track's fade gain.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use logger::{info, warn, Logger};
+use sdl2::audio::{
+    AudioCVT, AudioCallback, AudioDevice, AudioFormat, AudioSpecDesired, AudioSpecWAV,
+};
+
+use crate::audio::{Bus, Mixer, TrackId};
+
+/// Rate sounds are converted to when there's no device to play them, or the
+/// device doesn't say.
+const FALLBACK_RATE: i32 = 48_000;
+
+/// Channels voices are mixed into, interleaved left then right.
+const CHANNELS: u8 = 2;
+
+#[derive(thiserror::Error, Debug)]
+pub enum SoundError {
+    #[error("reading {0}: {1}")]
+    Io(PathBuf, std::io::Error),
+    #[error("decoding {0}: {1}")]
+    Wav(PathBuf, String),
+    #[error("decoding {0}: {1}")]
+    Ogg(PathBuf, lewton::VorbisError),
+    #[error("converting {0}: {1}")]
+    Convert(PathBuf, String),
+    #[error("{0} isn't a WAV or OGG file")]
+    UnknownFormat(PathBuf),
+}
+
+/// A sound loaded into an `AudioMixer`, which can be played any number of
+/// times at once.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct SoundHandle(u32);
+
+/// A sound being played, see `AudioMixer::play`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct VoiceId(u64);
+
+/// How a sound is played.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct PlayOptions {
+    pub bus: Bus,
+    pub gain: f32,
+    /// Start over from the beginning at the end, until stopped.
+    pub looping: bool,
+    /// Cutoff in Hz of a low-pass filter to play the sound through, or None
+    /// to play it as is.
+    pub low_pass: Option<f32>,
+}
+
+impl Default for PlayOptions {
+    fn default() -> Self {
+        Self {
+            bus: Bus::Sfx,
+            gain: 1.0,
+            looping: false,
+            low_pass: None,
+        }
+    }
+}
+
+/// A one-pole low-pass filter over interleaved stereo samples.
+#[derive(Debug, Clone, Copy)]
+struct LowPass {
+    // How far each output moves toward its input, from the cutoff.
+    alpha: f32,
+    // Last output of each channel.
+    last: [f32; CHANNELS as usize],
+}
+
+impl LowPass {
+    fn new(cutoff: f32, rate: i32) -> Self {
+        let mut low_pass = Self {
+            alpha: 1.0,
+            last: [0.0; CHANNELS as usize],
+        };
+        low_pass.set_cutoff(cutoff, rate);
+        low_pass
+    }
+
+    /// Move the cutoff, keeping what's been filtered so far so it doesn't
+    /// click.
+    fn set_cutoff(&mut self, cutoff: f32, rate: i32) {
+        let rate = rate as f32;
+        let cutoff = cutoff.clamp(1.0, rate / 2.0);
+        self.alpha = 1.0 - (-std::f32::consts::TAU * cutoff / rate).exp();
+    }
+
+    fn filter(&mut self, channel: usize, sample: f32) -> f32 {
+        let last = &mut self.last[channel];
+        *last += self.alpha * (sample - *last);
+        *last
+    }
+}
+
+#[derive(Debug)]
+struct Voice {
+    id: VoiceId,
+    samples: Arc<[f32]>,
+    // Next sample to mix.
+    cursor: usize,
+    gain: f32,
+    bus: Bus,
+    looping: bool,
+    low_pass: Option<LowPass>,
+}
+
+/// The voices the audio callback mixes, shared with the main thread through
+/// `AudioDevice::lock`.
+#[derive(Debug, Default)]
+struct Voices {
+    voices: Vec<Voice>,
+    bus_gains: [f32; Bus::ALL.len()],
+    // One-shot voices that have played to the end since the last update.
+    finished: Vec<(VoiceId, Bus)>,
+}
+
+impl Voices {
+    fn mix(&mut self, out: &mut [f32]) {
+        out.fill(0.0);
+        let bus_gains = self.bus_gains;
+        let finished = &mut self.finished;
+        self.voices.retain_mut(|voice| {
+            let gain = voice.gain * bus_gains[voice.bus as usize];
+            let mut written = 0;
+            while written < out.len() {
+                if voice.cursor >= voice.samples.len() {
+                    if !voice.looping || voice.samples.is_empty() {
+                        finished.push((voice.id, voice.bus));
+                        return false;
+                    }
+                    voice.cursor = 0;
+                }
+                let len = (out.len() - written).min(voice.samples.len() - voice.cursor);
+                let samples = &voice.samples[voice.cursor..voice.cursor + len];
+                for (i, (out, sample)) in out[written..written + len]
+                    .iter_mut()
+                    .zip(samples)
+                    .enumerate()
+                {
+                    let sample = match voice.low_pass.as_mut() {
+                        Some(low_pass) => {
+                            low_pass.filter((written + i) % usize::from(CHANNELS), *sample)
+                        }
+                        None => *sample,
+                    };
+                    *out += sample * gain;
+                }
+                written += len;
+                voice.cursor += len;
+            }
+            true
+        });
+    }
+}
+
+impl AudioCallback for Voices {
+    type Channel = f32;
+
+    fn callback(&mut self, out: &mut [f32]) {
+        self.mix(out);
+    }
+}
+
+/// Plays sounds on the default audio device. Without a device, sounds still
+/// load and play, silently, so nothing has to check for one.
+pub struct AudioMixer {
+    device: Option<AudioDevice<Voices>>,
+    // Stands in for the device's voices without a device.
+    silent: Voices,
+    rate: i32,
+    sounds: Vec<Arc<[f32]>>,
+    by_path: HashMap<PathBuf, SoundHandle>,
+    next_voice: u64,
+    // Buses of the voices started since the last update.
+    started: Vec<Bus>,
+    // Voice playing each audible music track.
+    music: HashMap<TrackId, VoiceId>,
+    logger: Logger,
+}
+
+impl AudioMixer {
+    /// Open the default playback device, or play silently if it can't be.
+    pub fn open(audio: &sdl2::AudioSubsystem, logger: &Logger) -> Self {
+        let logger = logger.sub("sound");
+        let desired = AudioSpecDesired {
+            freq: Some(FALLBACK_RATE),
+            channels: Some(CHANNELS),
+            samples: None,
+        };
+        let device = match audio.open_playback(None, &desired, |_spec| Voices::default()) {
+            Ok(device) => {
+                let spec = device.spec();
+                info!(
+                    logger,
+                    "playing sound at {} Hz, {} samples a callback", spec.freq, spec.samples
+                );
+                device.resume();
+                Some(device)
+            }
+            Err(err) => {
+                warn!(logger, "playing sound silently, no audio device: {err}");
+                None
+            }
+        };
+        Self::with_device(device, logger)
+    }
+
+    fn with_device(device: Option<AudioDevice<Voices>>, logger: Logger) -> Self {
+        let rate = device
+            .as_ref()
+            .map_or(FALLBACK_RATE, |device| device.spec().freq);
+        Self {
+            device,
+            silent: Voices::default(),
+            rate,
+            sounds: Vec::new(),
+            by_path: HashMap::new(),
+            next_voice: 0,
+            started: Vec::new(),
+            music: HashMap::new(),
+            logger,
+        }
+    }
+
+    /// Load a WAV or OGG file, by its extension, or return the handle it was
+    /// loaded as already.
+    pub fn load(&mut self, path: &Path) -> Result<SoundHandle, SoundError> {
+        if let Some(handle) = self.by_path.get(path) {
+            return Ok(*handle);
+        }
+        let samples = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("wav") => load_wav(path, self.rate)?,
+            Some("ogg") => load_ogg(path, self.rate)?,
+            _ => return Err(SoundError::UnknownFormat(path.to_path_buf())),
+        };
+        let handle = SoundHandle(self.sounds.len() as u32);
+        info!(
+            self.logger,
+            "loaded {} as {handle:?}, {:.2}s",
+            path.display(),
+            samples.len() as f32 / (self.rate as f32 * f32::from(CHANNELS))
+        );
+        self.sounds.push(samples);
+        self.by_path.insert(path.to_path_buf(), handle);
+        Ok(handle)
+    }
+
+    /// Start playing a sound. It plays until it ends, or until stopped when
+    /// looping.
+    pub fn play(&mut self, sound: SoundHandle, options: PlayOptions) -> VoiceId {
+        let id = VoiceId(self.next_voice);
+        self.next_voice += 1;
+        let voice = Voice {
+            id,
+            samples: Arc::clone(&self.sounds[sound.0 as usize]),
+            cursor: 0,
+            gain: options.gain,
+            bus: options.bus,
+            looping: options.looping,
+            low_pass: options
+                .low_pass
+                .map(|cutoff| LowPass::new(cutoff, self.rate)),
+        };
+        self.with_voices(|voices| voices.voices.push(voice));
+        self.started.push(options.bus);
+        id
+    }
+
+    /// Start looping `sound` as music, cross-fading from the track playing
+    /// over `crossfade`, see `MusicPlayer::play_looping`.
+    pub fn play_music(&mut self, mixer: &mut Mixer, sound: SoundHandle, crossfade: Duration) {
+        mixer.music_mut().play_looping(TrackId(sound.0), crossfade);
+    }
+
+    /// Set the gain of a voice, such as one attenuated as it moves.
+    pub fn set_gain(&mut self, id: VoiceId, gain: f32) {
+        self.with_voices(|voices| {
+            if let Some(voice) = voices.voices.iter_mut().find(|voice| voice.id == id) {
+                voice.gain = gain;
+            }
+        });
+    }
+
+    /// Play a voice through a low-pass filter with `cutoff` in Hz, such as
+    /// one muffled behind a wall, or as is with None.
+    pub fn set_low_pass(&mut self, id: VoiceId, cutoff: Option<f32>) {
+        let rate = self.rate;
+        self.with_voices(|voices| {
+            if let Some(voice) = voices.voices.iter_mut().find(|voice| voice.id == id) {
+                voice.low_pass = match (voice.low_pass, cutoff) {
+                    (Some(mut low_pass), Some(cutoff)) => {
+                        low_pass.set_cutoff(cutoff, rate);
+                        Some(low_pass)
+                    }
+                    (None, Some(cutoff)) => Some(LowPass::new(cutoff, rate)),
+                    (_, None) => None,
+                };
+            }
+        });
+    }
+
+    /// Stop a voice, if it's still playing.
+    pub fn stop(&mut self, id: VoiceId) {
+        self.with_voices(|voices| {
+            if let Some(index) = voices.voices.iter().position(|voice| voice.id == id) {
+                let voice = voices.voices.swap_remove(index);
+                voices.finished.push((voice.id, voice.bus));
+            }
+        });
+    }
+
+    pub fn is_playing(&mut self, id: VoiceId) -> bool {
+        self.with_voices(|voices| voices.voices.iter().any(|voice| voice.id == id))
+    }
+
+    /// Copy each bus' gain from `mixer` for the callback, and tell it which
+    /// voices started and stopped so its ducking rules follow them.
+    pub fn update(&mut self, mixer: &mut Mixer) {
+        self.update_music(mixer);
+        for bus in self.started.drain(..) {
+            mixer.voice_started(bus);
+        }
+        if self.device.is_none() {
+            // Nothing plays the silent voices, so one-shots end right away.
+            let silent = &mut self.silent;
+            for voice in silent.voices.iter().filter(|voice| !voice.looping) {
+                silent.finished.push((voice.id, voice.bus));
+            }
+            silent.voices.retain(|voice| voice.looping);
+        }
+        let finished = self.with_voices(|voices| {
+            for bus in Bus::ALL {
+                voices.bus_gains[bus as usize] = mixer.gain(bus);
+            }
+            std::mem::take(&mut voices.finished)
+        });
+        for (_id, bus) in finished {
+            mixer.voice_stopped(bus);
+        }
+    }
+
+    /// Play each audible music track at its fade gain, and stop the tracks
+    /// that have faded out. Tracks that aren't a loaded sound are skipped.
+    fn update_music(&mut self, mixer: &Mixer) {
+        let audible: Vec<_> = mixer
+            .music()
+            .gains()
+            .filter(|(track, _)| (track.0 as usize) < self.sounds.len())
+            .collect();
+        let faded: Vec<_> = self
+            .music
+            .keys()
+            .filter(|track| !audible.iter().any(|(audible, _)| audible == *track))
+            .copied()
+            .collect();
+        for track in faded {
+            if let Some(voice) = self.music.remove(&track) {
+                self.stop(voice);
+            }
+        }
+        for (track, gain) in audible {
+            match self.music.get(&track) {
+                Some(&voice) => self.set_gain(voice, gain),
+                None => {
+                    let options = PlayOptions {
+                        bus: Bus::Music,
+                        gain,
+                        looping: true,
+                        low_pass: None,
+                    };
+                    let voice = self.play(SoundHandle(track.0), options);
+                    self.music.insert(track, voice);
+                }
+            }
+        }
+    }
+
+    fn with_voices<T>(&mut self, f: impl FnOnce(&mut Voices) -> T) -> T {
+        match self.device.as_mut() {
+            Some(device) => f(&mut device.lock()),
+            None => f(&mut self.silent),
+        }
+    }
+}
+
+fn load_wav(path: &Path, rate: i32) -> Result<Arc<[f32]>, SoundError> {
+    let wav =
+        AudioSpecWAV::load_wav(path).map_err(|err| SoundError::Wav(path.to_path_buf(), err))?;
+    convert(
+        path,
+        wav.format,
+        wav.channels,
+        wav.freq,
+        wav.buffer().to_vec(),
+        rate,
+    )
+}
+
+fn load_ogg(path: &Path, rate: i32) -> Result<Arc<[f32]>, SoundError> {
+    let file = File::open(path).map_err(|err| SoundError::Io(path.to_path_buf(), err))?;
+    let mut reader = lewton::inside_ogg::OggStreamReader::new(BufReader::new(file))
+        .map_err(|err| SoundError::Ogg(path.to_path_buf(), err))?;
+    let mut bytes = Vec::new();
+    while let Some(packet) = reader
+        .read_dec_packet_itl()
+        .map_err(|err| SoundError::Ogg(path.to_path_buf(), err))?
+    {
+        bytes.extend(packet.iter().flat_map(|sample| sample.to_ne_bytes()));
+    }
+    convert(
+        path,
+        AudioFormat::s16_sys(),
+        reader.ident_hdr.audio_channels,
+        reader.ident_hdr.audio_sample_rate as i32,
+        bytes,
+        rate,
+    )
+}
+
+/// Convert samples to interleaved stereo floats at `rate`.
+fn convert(
+    path: &Path,
+    format: AudioFormat,
+    channels: u8,
+    src_rate: i32,
+    bytes: Vec<u8>,
+    rate: i32,
+) -> Result<Arc<[f32]>, SoundError> {
+    let cvt = AudioCVT::new(
+        format,
+        channels,
+        src_rate,
+        AudioFormat::f32_sys(),
+        CHANNELS,
+        rate,
+    )
+    .map_err(|err| SoundError::Convert(path.to_path_buf(), err))?;
+    let bytes = cvt.convert(bytes);
+    Ok(bytes
+        .chunks_exact(4)
+        .map(|sample| f32::from_ne_bytes([sample[0], sample[1], sample[2], sample[3]]))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use logger::LogLevel;
+
+    use super::*;
+    use crate::audio::MixerSettings;
+
+    fn voice(id: u64, samples: &[f32], looping: bool) -> Voice {
+        Voice {
+            id: VoiceId(id),
+            samples: samples.into(),
+            cursor: 0,
+            gain: 1.0,
+            bus: Bus::Sfx,
+            looping,
+            low_pass: None,
+        }
+    }
+
+    #[test]
+    fn one_shots_finish_and_loops_wrap() {
+        let mut voices = Voices {
+            voices: vec![
+                voice(0, &[1.0, 1.0], false),
+                voice(1, &[0.5, 0.25, 0.125], true),
+            ],
+            bus_gains: [1.0; Bus::ALL.len()],
+            finished: Vec::new(),
+        };
+        let mut out = [0.0; 5];
+        voices.mix(&mut out);
+        assert_eq!(out, [1.5, 1.25, 0.125, 0.5, 0.25]);
+        assert_eq!(voices.finished, [(VoiceId(0), Bus::Sfx)]);
+        assert_eq!(voices.voices.len(), 1);
+
+        voices.mix(&mut out);
+        assert_eq!(out, [0.125, 0.5, 0.25, 0.125, 0.5]);
+    }
+
+    #[test]
+    fn voices_are_scaled_by_their_bus() {
+        let mut voices = Voices {
+            voices: vec![voice(0, &[1.0; 4], false)],
+            bus_gains: [1.0; Bus::ALL.len()],
+            finished: Vec::new(),
+        };
+        voices.voices[0].gain = 0.5;
+        voices.bus_gains[Bus::Sfx as usize] = 0.5;
+        let mut out = [0.0; 2];
+        voices.mix(&mut out);
+        assert_eq!(out, [0.25, 0.25]);
+    }
+
+    #[test]
+    fn low_pass_muffles_high_frequencies() {
+        // Alternating frames, as high a frequency as there is.
+        let samples = [1.0, 1.0, -1.0, -1.0].repeat(64);
+        let mut voices = Voices {
+            voices: vec![voice(0, &samples, true)],
+            bus_gains: [1.0; Bus::ALL.len()],
+            finished: Vec::new(),
+        };
+        voices.voices[0].low_pass = Some(LowPass::new(100.0, FALLBACK_RATE));
+        let mut out = [0.0; 256];
+        voices.mix(&mut out);
+        assert!(out[128..].iter().all(|sample| sample.abs() < 0.02));
+
+        // Steady sound passes through.
+        voices.voices[0] = voice(0, &[0.5; 4], true);
+        voices.voices[0].low_pass = Some(LowPass::new(5_000.0, FALLBACK_RATE));
+        voices.mix(&mut out);
+        assert!((out[255] - 0.5).abs() < 1e-3);
+    }
+
+    #[test]
+    fn music_plays_ducked_on_the_music_bus() {
+        let mut sound = AudioMixer::with_device(None, LogLevel::Info.logger());
+        sound.sounds = vec![
+            Arc::from([0.5; 4].as_slice()),
+            Arc::from([0.25; 4].as_slice()),
+        ];
+        let (first, second) = (SoundHandle(0), SoundHandle(1));
+        let mut mixer = Mixer::new();
+        mixer.configure(&MixerSettings {
+            music_duck_attack: Duration::ZERO,
+            ..Default::default()
+        });
+
+        sound.play_music(&mut mixer, first, Duration::ZERO);
+        sound.update(&mut mixer);
+        let music = |sound: &AudioMixer| {
+            let mut gains: Vec<_> = sound
+                .silent
+                .voices
+                .iter()
+                .filter(|voice| voice.bus == Bus::Music)
+                .map(|voice| (voice.samples[0], voice.gain))
+                .collect();
+            gains.sort_by(|a, b| a.0.total_cmp(&b.0));
+            gains
+        };
+        assert_eq!(music(&sound), [(0.5, 1.0)]);
+        assert_eq!(sound.silent.bus_gains[Bus::Music as usize], 1.0);
+
+        // Sound effects duck the music bus the voices play on.
+        let looping = PlayOptions {
+            looping: true,
+            ..Default::default()
+        };
+        sound.play(first, looping);
+        sound.update(&mut mixer);
+        mixer.update(&Duration::from_millis(16));
+        sound.update(&mut mixer);
+        assert_eq!(sound.silent.bus_gains[Bus::Music as usize], 0.5);
+
+        // Cross-fading plays both tracks until the first fades out.
+        sound.play_music(&mut mixer, second, Duration::from_secs(1));
+        mixer.update(&Duration::from_millis(500));
+        sound.update(&mut mixer);
+        assert_eq!(music(&sound), [(0.25, 0.5), (0.5, 0.5)]);
+        mixer.update(&Duration::from_millis(500));
+        sound.update(&mut mixer);
+        assert_eq!(music(&sound), [(0.25, 1.0)]);
+    }
+}
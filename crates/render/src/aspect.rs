@@ -0,0 +1,144 @@
+//! How the scene is fit to a window whose aspect ratio differs from the one
+//! the camera's projection was made for.
+
+use std::fmt;
+use std::str::FromStr;
+
+use glam::Mat4;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum AspectPolicy {
+    /// Fill the window, stretching the camera's projection to fit.
+    #[default]
+    Stretch,
+    /// Keep this aspect ratio, with black bars on the sides or top and bottom
+    /// of windows that don't match it.
+    Letterbox { width: u32, height: u32 },
+    /// Fill the window, keeping the camera's vertical field of view and
+    /// widening or narrowing the horizontal one.
+    VerticalFovLock,
+}
+
+impl AspectPolicy {
+    pub const LETTERBOX_16_9: AspectPolicy = AspectPolicy::Letterbox {
+        width: 16,
+        height: 9,
+    };
+
+    /// The aspect ratio to project the scene at in a `width` by `height`
+    /// window, or None to use the camera's projection as it is.
+    pub fn projection_aspect(self, width: u32, height: u32) -> Option<f32> {
+        match self {
+            AspectPolicy::Stretch => None,
+            AspectPolicy::Letterbox { width, height } => Some(width as f32 / height as f32),
+            AspectPolicy::VerticalFovLock => Some(width.max(1) as f32 / height.max(1) as f32),
+        }
+    }
+
+    /// The area of a `width` by `height` target the scene is drawn in, as
+    /// `(x, y, width, height)`.
+    pub fn viewport(self, width: u32, height: u32) -> (u32, u32, u32, u32) {
+        match self {
+            AspectPolicy::Letterbox {
+                width: ratio_width,
+                height: ratio_height,
+            } => {
+                let ratio = ratio_width as f32 / ratio_height as f32;
+                let fit_width = ((height as f32 * ratio).round() as u32).clamp(1, width.max(1));
+                let fit_height =
+                    ((fit_width as f32 / ratio).round() as u32).clamp(1, height.max(1));
+                (
+                    (width - fit_width.min(width)) / 2,
+                    (height - fit_height.min(height)) / 2,
+                    fit_width,
+                    fit_height,
+                )
+            }
+            AspectPolicy::Stretch | AspectPolicy::VerticalFovLock => (0, 0, width, height),
+        }
+    }
+}
+
+/// `projection`, a perspective projection, at another aspect ratio with the
+/// same vertical field of view.
+pub fn with_aspect(projection: Mat4, aspect: f32) -> Mat4 {
+    let mut projection = projection;
+    projection.x_axis.x = projection.y_axis.y / aspect;
+    projection
+}
+
+impl fmt::Display for AspectPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AspectPolicy::Stretch => f.write_str("stretch"),
+            AspectPolicy::Letterbox { width, height } => write!(f, "letterbox:{width}:{height}"),
+            AspectPolicy::VerticalFovLock => f.write_str("vertical_fov"),
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+#[error(
+    "unknown aspect policy {0}, expected stretch, letterbox, letterbox:<w>:<h> or vertical_fov"
+)]
+pub struct UnknownAspectPolicy(String);
+
+impl FromStr for AspectPolicy {
+    type Err = UnknownAspectPolicy;
+
+    /// Parse "stretch", "letterbox" (16:9), "letterbox:<w>:<h>" or
+    /// "vertical_fov".
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || UnknownAspectPolicy(s.to_string());
+        match s.split(':').collect::<Vec<_>>()[..] {
+            ["stretch"] => Ok(AspectPolicy::Stretch),
+            ["letterbox"] => Ok(AspectPolicy::LETTERBOX_16_9),
+            ["letterbox", width, height] => match (width.parse(), height.parse()) {
+                (Ok(width), Ok(height)) if width > 0 && height > 0 => {
+                    Ok(AspectPolicy::Letterbox { width, height })
+                }
+                _ => Err(err()),
+            },
+            ["vertical_fov"] => Ok(AspectPolicy::VerticalFovLock),
+            _ => Err(err()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Vec4;
+
+    use super::*;
+
+    #[test]
+    fn letterbox_fits_the_ratio_in_the_window() {
+        let policy: AspectPolicy = "letterbox".parse().unwrap();
+        assert_eq!(policy, AspectPolicy::LETTERBOX_16_9);
+        // Bars on the sides of a wide window, and above and below a tall one.
+        assert_eq!(policy.viewport(2560, 720), (640, 0, 1280, 720));
+        assert_eq!(policy.viewport(1280, 1024), (0, 152, 1280, 720));
+        assert_eq!(policy.viewport(1920, 1080), (0, 0, 1920, 1080));
+        assert_eq!(policy.projection_aspect(2560, 720), Some(16.0 / 9.0));
+
+        assert_eq!(AspectPolicy::Stretch.viewport(800, 600), (0, 0, 800, 600));
+        assert_eq!(AspectPolicy::Stretch.projection_aspect(800, 600), None);
+        assert_eq!(
+            AspectPolicy::VerticalFovLock.projection_aspect(800, 400),
+            Some(2.0)
+        );
+        for policy in [policy, AspectPolicy::Stretch, AspectPolicy::VerticalFovLock] {
+            assert_eq!(policy.to_string().parse::<AspectPolicy>().unwrap(), policy);
+        }
+        assert!("letterbox:0:9".parse::<AspectPolicy>().is_err());
+    }
+
+    #[test]
+    fn with_aspect_keeps_vertical_fov() {
+        let projection = Mat4::perspective_lh(0.75, 1.7, 0.1, 1000.0);
+        let wide = with_aspect(projection, 3.4);
+        assert_eq!(wide, Mat4::perspective_lh(0.75, 3.4, 0.1, 1000.0));
+        let point = Vec4::new(1.0, 1.0, 10.0, 1.0);
+        assert_eq!((wide * point).y, (projection * point).y);
+    }
+}
@@ -0,0 +1,335 @@
+//! Clustered light culling.
+//!
+//! The view frustum is split into `CLUSTERS_X` by `CLUSTERS_Y` tiles across
+//! the viewport, and `CLUSTERS_Z` slices in depth, spaced exponentially so
+//! near slices are thin. Each frame the lights touching each cluster are
+//! listed on the CPU into `ClusteredLights`, and the fragment shader shades
+//! only the lights of the cluster a fragment is in. Scenes can then have
+//! hundreds of lights while each fragment is lit by a few.
+//!
+//! Clusters are built for projections made by `Mat4::perspective_lh`, as the
+//! camera's are.
+
+use glam::{Mat4, Vec3, Vec4, Vec4Swizzles};
+use shader_objects::{
+    ClusterGrid, ClusteredLight, ClusteredLights, CLUSTERS_X, CLUSTERS_Y, CLUSTERS_Z,
+    CLUSTER_COUNT, MAX_CLUSTERED_LIGHTS, MAX_CLUSTER_LIGHT_INDICES,
+};
+
+/// Lights in a cluster past this are dropped, so the count fits the high 16
+/// bits of `ClusteredLights::clusters`.
+const MAX_LIGHTS_PER_CLUSTER: usize = u16::MAX as usize;
+
+/// Lights assigned in a frame, and what didn't fit.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct ClusterStats {
+    /// Lights in view, written to `ClusteredLights::lights`.
+    pub lights: usize,
+    /// Lights in view past `MAX_CLUSTERED_LIGHTS`, the farthest are dropped.
+    pub dropped_lights: usize,
+    /// Lights listed in clusters, summed over clusters.
+    pub assignments: usize,
+    /// Lights not listed in a cluster they touch, for lack of room.
+    pub dropped_assignments: usize,
+}
+
+/// View space bounds of a cluster.
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct ClusterBounds {
+    min: Vec3,
+    max: Vec3,
+}
+
+impl ClusterBounds {
+    fn touches_sphere(&self, center: Vec3, radius: f32) -> bool {
+        let closest = center.clamp(self.min, self.max);
+        closest.distance_squared(center) <= radius * radius
+    }
+}
+
+/// Assigns lights to clusters, keeping cluster bounds and light lists between
+/// frames.
+#[derive(Default)]
+pub struct LightClusters {
+    /// Projection the cluster bounds were built for.
+    projection: Option<Mat4>,
+    near: f32,
+    far: f32,
+    bounds: Vec<ClusterBounds>,
+    /// Lights of each cluster, as indices into `ClusteredLights::lights`.
+    lists: Vec<Vec<u16>>,
+    /// Lights in view, with their view space center and distance.
+    in_view: Vec<(f32, Vec3, ClusteredLight)>,
+}
+
+impl LightClusters {
+    /// Assign `lights` to the clusters of a camera with `view` and
+    /// `projection`, drawing into the area of the target at `viewport`, as
+    /// `(x, y, width, height)` in pixels. Returns None, with every cluster
+    /// empty, for projections clusters can't be built for.
+    pub fn assign(
+        &mut self,
+        view: Mat4,
+        projection: Mat4,
+        viewport: (u32, u32, u32, u32),
+        lights: impl IntoIterator<Item = ClusteredLight>,
+        out: &mut ClusteredLights,
+    ) -> Option<ClusterStats> {
+        out.clusters.fill(0);
+        if self.projection != Some(projection) {
+            self.build(projection);
+        }
+        self.projection?;
+
+        let (x, y, width, height) = viewport;
+        let depth_scale = CLUSTERS_Z as f32 / (self.far / self.near).ln();
+        out.grid = ClusterGrid {
+            viewport: Vec4::new(
+                x as f32,
+                y as f32,
+                width.max(1) as f32 / CLUSTERS_X as f32,
+                height.max(1) as f32 / CLUSTERS_Y as f32,
+            ),
+            depth: Vec4::new(depth_scale, -depth_scale * self.near.ln(), 0.0, 0.0),
+        };
+
+        // Lights entirely in front of the near plane or past the far one
+        // light nothing in view.
+        self.in_view.clear();
+        self.in_view.extend(lights.into_iter().filter_map(|light| {
+            let radius = light.pos_radius.w;
+            let center = view.transform_point3(light.pos_radius.xyz());
+            let in_view =
+                radius > 0.0 && center.z + radius >= self.near && center.z - radius <= self.far;
+            in_view.then(|| (center.length(), center, light))
+        }));
+        let mut stats = ClusterStats::default();
+        if self.in_view.len() > MAX_CLUSTERED_LIGHTS {
+            self.in_view
+                .select_nth_unstable_by(MAX_CLUSTERED_LIGHTS, |a, b| a.0.total_cmp(&b.0));
+            stats.dropped_lights = self.in_view.len() - MAX_CLUSTERED_LIGHTS;
+            self.in_view.truncate(MAX_CLUSTERED_LIGHTS);
+        }
+        stats.lights = self.in_view.len();
+
+        for list in self.lists.iter_mut() {
+            list.clear();
+        }
+        for (index, &(_, center, light)) in self.in_view.iter().enumerate() {
+            out.lights[index] = light;
+            let radius = light.pos_radius.w;
+            let first_slice = self.slice(center.z - radius);
+            let last_slice = self.slice(center.z + radius);
+            for slice in first_slice..=last_slice {
+                for cluster in
+                    slice * CLUSTERS_X * CLUSTERS_Y..(slice + 1) * CLUSTERS_X * CLUSTERS_Y
+                {
+                    if self.bounds[cluster].touches_sphere(center, radius) {
+                        self.lists[cluster].push(index as u16);
+                    }
+                }
+            }
+        }
+
+        let mut offset = 0;
+        for (cluster, list) in out.clusters.iter_mut().zip(self.lists.iter()) {
+            let count = list
+                .len()
+                .min(MAX_LIGHTS_PER_CLUSTER)
+                .min(MAX_CLUSTER_LIGHT_INDICES - offset);
+            for (index, &light) in out.indices[offset..offset + count].iter_mut().zip(list) {
+                *index = light as u32;
+            }
+            *cluster = offset as u32 | ((count as u32) << 16);
+            offset += count;
+            stats.assignments += count;
+            stats.dropped_assignments += list.len() - count;
+        }
+        Some(stats)
+    }
+
+    /// The depth slice of view depth `depth`, clamped to the slices there
+    /// are.
+    fn slice(&self, depth: f32) -> usize {
+        let depth = depth.clamp(self.near, self.far);
+        let slice = (depth / self.near).ln() / (self.far / self.near).ln() * CLUSTERS_Z as f32;
+        (slice as usize).min(CLUSTERS_Z - 1)
+    }
+
+    /// Build cluster bounds for `projection`, or forget them if it isn't a
+    /// left-handed perspective projection with a far plane.
+    fn build(&mut self, projection: Mat4) {
+        self.projection = None;
+        self.bounds.clear();
+        self.lists.resize_with(CLUSTER_COUNT, Vec::new);
+        let Some((near, far)) = perspective_depth_range(projection) else {
+            return;
+        };
+        self.near = near;
+        self.far = far;
+
+        // The view space point at `depth` that projects to `ndc`.
+        let unproject = |ndc_x: f32, ndc_y: f32, depth: f32| {
+            Vec3::new(
+                (ndc_x - projection.z_axis.x) * depth / projection.x_axis.x,
+                (ndc_y - projection.z_axis.y) * depth / projection.y_axis.y,
+                depth,
+            )
+        };
+        let slice_depth = |slice: usize| near * (far / near).powf(slice as f32 / CLUSTERS_Z as f32);
+        let ndc = |tile: usize, tiles: usize| tile as f32 / tiles as f32 * 2.0 - 1.0;
+        for z in 0..CLUSTERS_Z {
+            let (near, far) = (slice_depth(z), slice_depth(z + 1));
+            for y in 0..CLUSTERS_Y {
+                for x in 0..CLUSTERS_X {
+                    let corners = [
+                        (ndc(x, CLUSTERS_X), ndc(y, CLUSTERS_Y)),
+                        (ndc(x + 1, CLUSTERS_X), ndc(y + 1, CLUSTERS_Y)),
+                    ];
+                    let mut bounds = ClusterBounds {
+                        min: Vec3::splat(f32::INFINITY),
+                        max: Vec3::splat(f32::NEG_INFINITY),
+                    };
+                    for depth in [near, far] {
+                        for (ndc_x, _) in corners {
+                            for (_, ndc_y) in corners {
+                                let corner = unproject(ndc_x, ndc_y, depth);
+                                bounds.min = bounds.min.min(corner);
+                                bounds.max = bounds.max.max(corner);
+                            }
+                        }
+                    }
+                    self.bounds.push(bounds);
+                }
+            }
+        }
+        self.projection = Some(projection);
+    }
+}
+
+/// Near and far planes of a projection made by `Mat4::perspective_lh`, or
+/// None for other projections.
+pub fn perspective_depth_range(projection: Mat4) -> Option<(f32, f32)> {
+    // perspective_lh puts far / (far - near) in z, -near times that in w.
+    let r = projection.z_axis.z;
+    if projection.z_axis.w != 1.0 || projection.w_axis.w != 0.0 || r <= 1.0 {
+        return None;
+    }
+    let near = -projection.w_axis.z / r;
+    let far = near * r / (r - 1.0);
+    (near > 0.0 && far.is_finite() && far > near).then_some((near, far))
+}
+
+#[cfg(test)]
+mod tests {
+    use bytemuck::Zeroable;
+
+    use super::*;
+
+    const VIEWPORT: (u32, u32, u32, u32) = (0, 0, 1600, 900);
+
+    fn projection() -> Mat4 {
+        Mat4::perspective_lh(0.75, 16.0 / 9.0, 0.1, 1000.0)
+    }
+
+    fn light(pos: Vec3, radius: f32) -> ClusteredLight {
+        ClusteredLight {
+            pos_radius: pos.extend(radius),
+            color: Vec4::ONE,
+        }
+    }
+
+    /// Lights of the cluster a point in view space projects into.
+    fn lights_at(out: &ClusteredLights, point: Vec3) -> Vec<u32> {
+        let clip = projection() * point.extend(1.0);
+        let (x, y) = (clip.x / clip.w, clip.y / clip.w);
+        let tile =
+            |ndc: f32, tiles: usize| (((ndc + 1.0) / 2.0 * tiles as f32) as usize).min(tiles - 1);
+        let slice =
+            ((point.z.ln() * out.grid.depth.x + out.grid.depth.y) as usize).min(CLUSTERS_Z - 1);
+        let cluster = out.clusters
+            [(slice * CLUSTERS_Y + tile(y, CLUSTERS_Y)) * CLUSTERS_X + tile(x, CLUSTERS_X)];
+        let (offset, count) = ((cluster & 0xffff) as usize, (cluster >> 16) as usize);
+        out.indices[offset..offset + count].to_vec()
+    }
+
+    #[test]
+    fn depth_range_of_perspective() {
+        let (near, far) = perspective_depth_range(projection()).unwrap();
+        assert!((near - 0.1).abs() < 1e-4);
+        assert!((far - 1000.0).abs() / 1000.0 < 1e-3);
+        assert_eq!(perspective_depth_range(Mat4::IDENTITY), None);
+        assert_eq!(
+            perspective_depth_range(Mat4::perspective_rh(0.75, 1.0, 0.1, 100.0)),
+            None
+        );
+    }
+
+    #[test]
+    fn lights_are_listed_in_the_clusters_they_touch() {
+        let mut clusters = LightClusters::default();
+        let mut out = ClusteredLights::zeroed();
+        let lights = [
+            light(Vec3::new(0.0, 0.0, 10.0), 1.0),
+            light(Vec3::new(5.0, 0.0, 40.0), 3.0),
+            // Behind the camera.
+            light(Vec3::new(0.0, 0.0, -10.0), 1.0),
+        ];
+        let stats = clusters
+            .assign(Mat4::IDENTITY, projection(), VIEWPORT, lights, &mut out)
+            .unwrap();
+        assert_eq!(stats.lights, 2);
+        assert_eq!(stats.dropped_lights, 0);
+        assert_eq!(stats.dropped_assignments, 0);
+
+        assert_eq!(lights_at(&out, Vec3::new(0.0, 0.0, 10.0)), [0]);
+        assert_eq!(lights_at(&out, Vec3::new(0.5, 0.0, 10.5)), [0]);
+        assert_eq!(lights_at(&out, Vec3::new(5.0, 0.0, 40.0)), [1]);
+        assert_eq!(lights_at(&out, Vec3::new(5.0, 2.5, 41.0)), [1]);
+        assert!(lights_at(&out, Vec3::new(0.0, 0.0, 100.0)).is_empty());
+        assert!(lights_at(&out, Vec3::new(-10.0, 0.0, 10.0)).is_empty());
+        assert_eq!(out.lights[1].pos_radius, lights[1].pos_radius);
+
+        // The camera moved back, bringing the light behind it into view.
+        let view = Mat4::from_translation(Vec3::new(0.0, 0.0, 20.0));
+        let stats = clusters
+            .assign(view, projection(), VIEWPORT, lights, &mut out)
+            .unwrap();
+        assert_eq!(stats.lights, 3);
+        assert_eq!(lights_at(&out, Vec3::new(0.0, 0.0, 10.0)), [2]);
+        assert_eq!(lights_at(&out, Vec3::new(0.0, 0.0, 30.0)), [0]);
+    }
+
+    #[test]
+    fn nearest_lights_are_kept() {
+        let mut clusters = LightClusters::default();
+        let mut out = ClusteredLights::zeroed();
+        let lights = (0..MAX_CLUSTERED_LIGHTS + 10)
+            .map(|i| light(Vec3::new(0.0, 0.0, 1.0 + i as f32), 0.5))
+            .rev();
+        let stats = clusters
+            .assign(Mat4::IDENTITY, projection(), VIEWPORT, lights, &mut out)
+            .unwrap();
+        assert_eq!(stats.lights, MAX_CLUSTERED_LIGHTS);
+        assert_eq!(stats.dropped_lights, 10);
+        let farthest = out
+            .lights
+            .iter()
+            .map(|light| light.pos_radius.z)
+            .fold(0.0, f32::max);
+        assert_eq!(farthest, MAX_CLUSTERED_LIGHTS as f32);
+    }
+
+    #[test]
+    fn unsupported_projection_leaves_clusters_empty() {
+        let mut clusters = LightClusters::default();
+        let mut out = ClusteredLights::zeroed();
+        out.clusters[0] = 1 << 16;
+        let lights = [light(Vec3::new(0.0, 0.0, 10.0), 1.0)];
+        assert_eq!(
+            clusters.assign(Mat4::IDENTITY, Mat4::IDENTITY, VIEWPORT, lights, &mut out),
+            None
+        );
+        assert_eq!(out.clusters[0], 0);
+    }
+}
@@ -0,0 +1,316 @@
+//! Drawables extracted from the world for a frame, kept as a structure of
+//! arrays so culling runs over contiguous memory.
+//!
+//! Each drawable's bounds are reduced to a world space bounding sphere, split
+//! into arrays of x, y, z and radius padded to a multiple of `LANES`, so the
+//! frustum test checks four spheres against a plane with each `Vec4`
+//! operation. Model matrices are stored contiguously too, and multiplied by
+//! the view projection in one pass for whatever needs them in clip space.
+
+use std::time::Instant;
+
+use glam::{Mat4, Vec3, Vec4, Vec4Swizzles};
+use world::components::{
+    Drawable, PhysicsPose, RenderFlags, RenderLayers, ShaderParams, WorldTransform,
+};
+use world::{Entity, World};
+
+use crate::occlusion::Aabb;
+
+/// Spheres tested against a plane at once.
+pub const LANES: usize = 4;
+
+/// The planes of a view frustum, facing inwards.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Frustum {
+    planes: [Vec4; 6],
+}
+
+impl Frustum {
+    /// The frustum of a view projection matrix. The near plane is taken for
+    /// a -1 to 1 depth range, which also holds everything in a 0 to 1 range.
+    pub fn from_view_projection(view_projection: Mat4) -> Self {
+        let rows = view_projection.transpose();
+        let planes = [
+            rows.w_axis + rows.x_axis,
+            rows.w_axis - rows.x_axis,
+            rows.w_axis + rows.y_axis,
+            rows.w_axis - rows.y_axis,
+            rows.w_axis + rows.z_axis,
+            rows.w_axis - rows.z_axis,
+        ]
+        .map(|plane| plane / plane.xyz().length());
+        Self { planes }
+    }
+
+    /// Whether a sphere is at least partly inside.
+    pub fn contains_sphere(&self, center: Vec3, radius: f32) -> bool {
+        self.planes
+            .iter()
+            .all(|plane| plane.xyz().dot(center) + plane.w >= -radius)
+    }
+}
+
+/// Drawables of a frame, each at the same index in every array.
+#[derive(Debug, Default)]
+pub struct ExtractedDrawables {
+    pub entities: Vec<Entity>,
+    /// The graphic each is drawn with.
+    pub gfx: Vec<Entity>,
+    /// Model matrices, interpolated for physics bodies.
+    pub models: Vec<Mat4>,
+    pub flags: Vec<RenderFlags>,
+    pub params: Vec<ShaderParams>,
+    /// Whether each survived culling, all true until `cull` is called.
+    pub visible: Vec<bool>,
+    // World space bounding spheres, padded to a multiple of `LANES`. Drawables
+    // without bounds get an infinite radius and are never culled.
+    center_x: Vec<f32>,
+    center_y: Vec<f32>,
+    center_z: Vec<f32>,
+    radius: Vec<f32>,
+    clip_from_model: Vec<Mat4>,
+}
+
+impl ExtractedDrawables {
+    pub fn len(&self) -> usize {
+        self.entities.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entities.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.entities.clear();
+        self.gfx.clear();
+        self.models.clear();
+        self.flags.clear();
+        self.params.clear();
+        self.visible.clear();
+        self.center_x.clear();
+        self.center_y.clear();
+        self.center_z.clear();
+        self.radius.clear();
+        self.clip_from_model.clear();
+    }
+
+    /// Extract every drawable that isn't hidden and is on one of `layers`,
+    /// such as a camera's, in one pass over the world. `bounds` gives the
+    /// model space bounds of a graphic, if it's known.
+    pub fn extract(
+        &mut self,
+        world: &World,
+        now: Instant,
+        layers: RenderLayers,
+        bounds: impl Fn(Entity) -> Option<Aabb>,
+    ) {
+        self.clear();
+        let mut query = world.hecs_world.query::<(
+            &Drawable,
+            &WorldTransform,
+            Option<&RenderFlags>,
+            Option<&RenderLayers>,
+            Option<&PhysicsPose>,
+            Option<&ShaderParams>,
+        )>();
+        for (entity, (drawable, world_transform, flags, drawable_layers, pose, params)) in
+            query.iter()
+        {
+            let flags = flags.copied().unwrap_or_default();
+            if flags.contains(RenderFlags::HIDDEN)
+                || !drawable_layers
+                    .copied()
+                    .unwrap_or_default()
+                    .intersects(layers)
+            {
+                continue;
+            }
+            // Physics bodies are drawn between their last two poses.
+            let model = match pose {
+                Some(pose) => pose.interpolated(now),
+                None => world_transform.world,
+            };
+            self.push(
+                entity,
+                drawable.gfx,
+                model,
+                flags,
+                params.copied().unwrap_or_default(),
+                bounds(drawable.gfx).as_ref(),
+            );
+        }
+        self.pad();
+    }
+
+    /// Add a drawable, `pad` must be called before culling.
+    pub fn push(
+        &mut self,
+        entity: Entity,
+        gfx: Entity,
+        model: Mat4,
+        flags: RenderFlags,
+        params: ShaderParams,
+        bounds: Option<&Aabb>,
+    ) {
+        // Sphere padding from a previous `pad` is overwritten.
+        let index = self.entities.len();
+        self.center_x.truncate(index);
+        self.center_y.truncate(index);
+        self.center_z.truncate(index);
+        self.radius.truncate(index);
+
+        let (center, radius) = match bounds {
+            Some(bounds) => {
+                let center = model.transform_point3((bounds.min + bounds.max) * 0.5);
+                let scale = model
+                    .x_axis
+                    .xyz()
+                    .length()
+                    .max(model.y_axis.xyz().length())
+                    .max(model.z_axis.xyz().length());
+                (center, (bounds.max - bounds.min).length() * 0.5 * scale)
+            }
+            None => (Vec3::ZERO, f32::INFINITY),
+        };
+        self.entities.push(entity);
+        self.gfx.push(gfx);
+        self.models.push(model);
+        self.flags.push(flags);
+        self.params.push(params);
+        self.visible.push(true);
+        self.center_x.push(center.x);
+        self.center_y.push(center.y);
+        self.center_z.push(center.z);
+        self.radius.push(radius);
+    }
+
+    /// Pad the bounding spheres to a whole number of `LANES`.
+    pub fn pad(&mut self) {
+        let padded = self.entities.len().next_multiple_of(LANES);
+        self.center_x.resize(padded, 0.0);
+        self.center_y.resize(padded, 0.0);
+        self.center_z.resize(padded, 0.0);
+        self.radius.resize(padded, f32::INFINITY);
+    }
+
+    /// Mark the drawables whose bounds are entirely outside `frustum` as not
+    /// visible, returning how many were culled.
+    pub fn cull(&mut self, frustum: &Frustum) -> usize {
+        debug_assert_eq!(self.radius.len() % LANES, 0, "pad before culling");
+        let mut culled = 0;
+        let lanes = self
+            .center_x
+            .chunks_exact(LANES)
+            .zip(self.center_y.chunks_exact(LANES))
+            .zip(self.center_z.chunks_exact(LANES))
+            .zip(self.radius.chunks_exact(LANES));
+        for (chunk, (((x, y), z), radius)) in lanes.enumerate() {
+            let (x, y, z) = (
+                Vec4::from_slice(x),
+                Vec4::from_slice(y),
+                Vec4::from_slice(z),
+            );
+            let neg_radius = -Vec4::from_slice(radius);
+            let inside = frustum.planes.iter().fold(0b1111, |inside, plane| {
+                let distance = x * plane.x + y * plane.y + z * plane.z + Vec4::splat(plane.w);
+                inside & distance.cmpge(neg_radius).bitmask()
+            });
+            let start = chunk * LANES;
+            let end = (start + LANES).min(self.visible.len());
+            for (lane, visible) in self.visible[start..end].iter_mut().enumerate() {
+                if *visible && inside & (1 << lane) == 0 {
+                    *visible = false;
+                    culled += 1;
+                }
+            }
+        }
+        culled
+    }
+
+    /// Multiply every drawable's model matrix by `view_projection`, for
+    /// `clip_from_model`.
+    pub fn transform_to_clip(&mut self, view_projection: Mat4) {
+        self.clip_from_model.clear();
+        self.clip_from_model
+            .extend(self.models.iter().map(|model| view_projection * *model));
+    }
+
+    /// Model matrices in clip space by the same index, once transformed with
+    /// `transform_to_clip`, otherwise empty.
+    pub fn clip_from_model(&self) -> &[Mat4] {
+        &self.clip_from_model
+    }
+
+    /// Indices of the visible drawables, ordered by graphic so draws sharing
+    /// a pipeline are recorded together.
+    pub fn visible_by_graphic(&self, order: &mut Vec<usize>) {
+        order.clear();
+        order.extend((0..self.len()).filter(|index| self.visible[*index]));
+        order.sort_by_key(|index| self.gfx[*index]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn culls_spheres_outside_the_frustum() {
+        let projection = Mat4::perspective_rh(std::f32::consts::FRAC_PI_2, 1.0, 0.1, 100.0);
+        let view = Mat4::look_at_rh(Vec3::ZERO, Vec3::NEG_Z, Vec3::Y);
+        let frustum = Frustum::from_view_projection(projection * view);
+        let unit = Aabb::new(Vec3::splat(-0.5), Vec3::splat(0.5));
+
+        let entity = |id: u64| Entity::from_bits((1 << 32) | id).unwrap();
+        let gfx = entity(0);
+        let mut extracted = ExtractedDrawables::default();
+        let positions = [
+            Vec3::new(0.0, 0.0, -10.0),
+            Vec3::new(0.0, 0.0, 10.0),
+            Vec3::new(50.0, 0.0, -10.0),
+            // Partly inside the left plane.
+            Vec3::new(-10.5, 0.0, -10.0),
+            Vec3::new(0.0, 0.0, -200.0),
+        ];
+        for (id, position) in (1..).zip(positions) {
+            extracted.push(
+                entity(id),
+                gfx,
+                Mat4::from_translation(position),
+                RenderFlags::NONE,
+                ShaderParams::default(),
+                Some(&unit),
+            );
+        }
+        // No bounds, never culled.
+        extracted.push(
+            entity(6),
+            gfx,
+            Mat4::from_translation(Vec3::new(0.0, 0.0, 10.0)),
+            RenderFlags::NONE,
+            ShaderParams::default(),
+            None,
+        );
+        extracted.pad();
+
+        assert_eq!(extracted.cull(&frustum), 3);
+        assert_eq!(
+            extracted.visible,
+            vec![true, false, false, true, false, true]
+        );
+        for (index, position) in positions.into_iter().enumerate() {
+            assert_eq!(
+                frustum.contains_sphere(position, 3f32.sqrt() * 0.5),
+                extracted.visible[index]
+            );
+        }
+
+        let mut order = Vec::new();
+        extracted.visible_by_graphic(&mut order);
+        assert_eq!(order, vec![0, 3, 5]);
+        assert!(extracted.clip_from_model().is_empty());
+        extracted.transform_to_clip(projection * view);
+        assert_eq!(extracted.clip_from_model().len(), 6);
+    }
+}
@@ -0,0 +1,152 @@
+//! What the GPU did for each pass of a frame, and the memory held by each
+//! graphic, so expensive assets stand out. Captured by the renderer while
+//! `RenderState::capture_gpu_stats` is set, see `Presenter::gpu_stats`.
+
+use std::fmt;
+
+use world::Entity;
+
+/// Counters from a pipeline statistics query over a pass.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct PipelineStatistics {
+    pub input_assembly_vertices: u64,
+    pub vertex_shader_invocations: u64,
+    /// Primitives that reached clipping, and those that came out of it.
+    pub clipping_invocations: u64,
+    pub clipping_primitives: u64,
+    pub fragment_shader_invocations: u64,
+}
+
+impl PipelineStatistics {
+    /// Counters in the order the renderer queries them.
+    pub fn from_counters(counters: [u64; 5]) -> Self {
+        Self {
+            input_assembly_vertices: counters[0],
+            vertex_shader_invocations: counters[1],
+            clipping_invocations: counters[2],
+            clipping_primitives: counters[3],
+            fragment_shader_invocations: counters[4],
+        }
+    }
+}
+
+/// Statistics of one pass, such as the scene or reflection probe captures.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PassStatistics {
+    pub pass: &'static str,
+    pub draws: usize,
+    pub statistics: PipelineStatistics,
+}
+
+/// GPU memory held by a graphic, which may be shared with other graphics of
+/// identical content.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct GraphicMemory {
+    pub gfx: Entity,
+    pub bytes: u64,
+    /// Drawables drawn with the graphic last frame.
+    pub drawables: usize,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GpuStats {
+    /// Empty if the device can't query pipeline statistics.
+    pub passes: Vec<PassStatistics>,
+    /// Largest first.
+    pub graphics: Vec<GraphicMemory>,
+}
+
+impl GpuStats {
+    /// Memory held by every graphic, counting shared uploads once each time
+    /// they're shared.
+    pub fn graphics_memory(&self) -> u64 {
+        self.graphics.iter().map(|graphic| graphic.bytes).sum()
+    }
+
+    /// Sort graphics largest first.
+    pub fn sort_graphics(&mut self) {
+        self.graphics
+            .sort_by(|a, b| b.bytes.cmp(&a.bytes).then(a.gfx.cmp(&b.gfx)));
+    }
+}
+
+/// The passes, then up to this many of the largest graphics.
+const LISTED_GRAPHICS: usize = 10;
+
+impl fmt::Display for GpuStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.passes.is_empty() {
+            writeln!(f, "no pipeline statistics")?;
+        }
+        for pass in self.passes.iter() {
+            let stats = pass.statistics;
+            writeln!(
+                f,
+                "{}: {} draws, {} vertices, {} vertex invocations, {} of {} primitives \
+                 clipped, {} fragment invocations",
+                pass.pass,
+                pass.draws,
+                stats.input_assembly_vertices,
+                stats.vertex_shader_invocations,
+                stats
+                    .clipping_invocations
+                    .saturating_sub(stats.clipping_primitives),
+                stats.clipping_invocations,
+                stats.fragment_shader_invocations,
+            )?;
+        }
+        write!(
+            f,
+            "{} graphics, {} KiB",
+            self.graphics.len(),
+            self.graphics_memory() / 1024
+        )?;
+        for graphic in self.graphics.iter().take(LISTED_GRAPHICS) {
+            write!(
+                f,
+                "\n  {:?}: {} KiB, {} drawables",
+                graphic.gfx,
+                graphic.bytes / 1024,
+                graphic.drawables
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lists_passes_and_largest_graphics() {
+        let entity = |id: u64| Entity::from_bits((1 << 32) | id).unwrap();
+        let mut stats = GpuStats {
+            passes: vec![PassStatistics {
+                pass: "scene",
+                draws: 3,
+                statistics: PipelineStatistics::from_counters([300, 250, 100, 80, 5000]),
+            }],
+            graphics: vec![
+                GraphicMemory {
+                    gfx: entity(1),
+                    bytes: 2048,
+                    drawables: 1,
+                },
+                GraphicMemory {
+                    gfx: entity(2),
+                    bytes: 8192,
+                    drawables: 2,
+                },
+            ],
+        };
+        stats.sort_graphics();
+        assert_eq!(stats.graphics[0].gfx, entity(2));
+        assert_eq!(stats.graphics_memory(), 10240);
+
+        let text = stats.to_string();
+        assert!(text.starts_with("scene: 3 draws, 300 vertices, 250 vertex invocations"));
+        assert!(text.contains("20 of 100 primitives clipped, 5000 fragment invocations"));
+        assert!(text.contains("2 graphics, 10 KiB"));
+    }
+}
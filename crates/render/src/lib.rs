@@ -4,16 +4,45 @@
 //! This module is a landing-pad (In particular VulkanBase) for functionality
 //! from
 
+pub mod aspect;
+pub mod clusters;
+pub mod extract;
+pub mod gpu_stats;
+pub mod lights;
+pub mod occlusion;
+pub mod probes;
+pub mod readback;
+pub mod render_path;
+pub mod render_scale;
+pub mod settings;
+pub mod shadows;
+pub mod target;
+
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
+use aspect::AspectPolicy;
 use async_lock::Mutex;
 use gfx::Graphic;
+use gpu_stats::GpuStats;
 use logger::{info, trace, warn, LogLevel, Logger};
-use platform::WinPtr;
-use world::components::GraphicPrefab;
+use occlusion::OcclusionStats;
+use platform::{WinPtr, WindowSize};
+use readback::{Readback, ReadbackImage};
+use render_path::RenderPath;
+use render_scale::RenderScale;
+use settings::RenderSettings;
+use target::{RenderTargetId, RenderTargets};
+use world::components::{GraphicPrefab, ReloadedGraphic};
 use world::{Entity, World};
 
+/// Frames the renderer records ahead of the GPU by default, see
+/// `RenderState::frames_in_flight`.
+pub const DEFAULT_FRAMES_IN_FLIGHT: u32 = 2;
+/// Most frames the renderer records ahead of the GPU.
+pub const MAX_FRAMES_IN_FLIGHT: u32 = 3;
+
 #[derive(thiserror::Error, Debug)]
 pub enum RenderStateError {
     #[error("plugin error {0:?}")]
@@ -34,64 +63,318 @@ pub enum SceneError {
 /// instead have RenderState track them
 pub struct RenderState {
     pub updates: u64,
-    pub win_ptr: WinPtr,
+    /// Window rendered to, None when rendering offscreen, see
+    /// `RenderState::offscreen`.
+    pub win_ptr: Option<WinPtr>,
+    /// Size of the window being rendered to, kept up to date on resize.
+    /// Offscreen, the size of the images rendered into.
+    pub window_size: WindowSize,
     pub enable_validation_layer: bool,
+    /// Resolution the scene is rendered at, relative to the window. Changes
+    /// are picked up by the renderer on its next update.
+    pub render_scale: RenderScale,
+    /// How long the loop's last frame took, as recorded in the engine's frame
+    /// histogram, which a dynamic `render_scale` adjusts to. Taken by the
+    /// renderer on its next update.
+    pub frame_time: Option<Duration>,
+    /// How the scene is fit to the window's aspect ratio, picked up by the
+    /// renderer on its next update.
+    pub aspect_policy: AspectPolicy,
+    /// Whether the renderer queries pipeline statistics of each pass, for
+    /// `Presenter::gpu_stats`. Picked up by the renderer on its next update.
+    pub capture_gpu_stats: bool,
+    /// Windows rendered to besides the main one, picked up by the renderer on
+    /// its next update.
+    pub targets: RenderTargets,
+    /// Frames recorded while the GPU renders the ones before them, from 1 to
+    /// `MAX_FRAMES_IN_FLIGHT`. Only read when the renderer is loaded.
+    pub frames_in_flight: u32,
+    // See `set_settings`.
+    settings: RenderSettings,
+    // Set when the present mode changes, until the renderer takes it.
+    recreate_swapchain: bool,
+    /// Whether the main window is lit forward or through the experimental
+    /// deferred path. Picked up by the renderer on its next update.
+    pub render_path: RenderPath,
+    /// Directory reports are written to when Vulkan fails unexpectedly. Only
+    /// read when the renderer is loaded.
+    pub crash_report_dir: PathBuf,
     pub logger: Logger,
 }
 
 impl RenderState {
     pub fn new(
         win_ptr: WinPtr,
+        window_size: WindowSize,
         enable_validation_layer: bool,
         is_server: bool,
         logger: Logger,
+    ) -> Self {
+        Self::with_window(Some(win_ptr), window_size, enable_validation_layer, logger)
+    }
+
+    /// Render without a window, into images `width` by `height` pixels read
+    /// back with `Presenter::read_back_frame`, for dedicated servers and
+    /// golden image tests. The size is only read when the renderer is loaded.
+    pub fn offscreen(
+        width: u32,
+        height: u32,
+        enable_validation_layer: bool,
+        logger: Logger,
+    ) -> Self {
+        let size = WindowSize {
+            logical: (width, height),
+            drawable: (width, height),
+        };
+        Self::with_window(None, size, enable_validation_layer, logger)
+    }
+
+    fn with_window(
+        win_ptr: Option<WinPtr>,
+        window_size: WindowSize,
+        enable_validation_layer: bool,
+        logger: Logger,
     ) -> Self {
         Self {
             updates: 0,
             win_ptr,
+            window_size,
             enable_validation_layer,
+            render_scale: RenderScale::default(),
+            frame_time: None,
+            aspect_policy: AspectPolicy::default(),
+            capture_gpu_stats: false,
+            targets: RenderTargets::default(),
+            frames_in_flight: DEFAULT_FRAMES_IN_FLIGHT,
+            settings: RenderSettings::default(),
+            recreate_swapchain: false,
+            render_path: RenderPath::default(),
+            crash_report_dir: PathBuf::from("crash_reports"),
             logger,
         }
     }
 
+    /// Record a new window size, after the window was resized or moved to a
+    /// display with a different scale.
+    pub fn set_window_size(&mut self, window_size: WindowSize) {
+        if window_size != self.window_size {
+            info!(
+                self.logger,
+                "window resized to {:?} ({:?} drawable, scale {})",
+                window_size.logical,
+                window_size.drawable,
+                window_size.scale_factor()
+            );
+            self.window_size = window_size;
+        }
+    }
+
+    /// Record a new size of a target's window, see `set_window_size`.
+    pub fn set_target_window_size(&mut self, id: RenderTargetId, window_size: WindowSize) {
+        if let Some(target) = self.targets.get_mut(id) {
+            if window_size != target.window_size {
+                info!(
+                    self.logger,
+                    "{id} window resized to {:?} ({:?} drawable)",
+                    window_size.logical,
+                    window_size.drawable
+                );
+                target.window_size = window_size;
+            }
+        }
+    }
+
+    pub fn settings(&self) -> &RenderSettings {
+        &self.settings
+    }
+
+    /// Change how frames are presented and the frame cap while running. A
+    /// new present mode flags the swapchain to be recreated on the renderer's
+    /// next update, see `take_swapchain_recreation`.
+    pub fn set_settings(&mut self, settings: RenderSettings) {
+        if settings.present_mode != self.settings.present_mode {
+            info!(
+                self.logger,
+                "present mode {} requested, was {}",
+                settings.present_mode,
+                self.settings.present_mode
+            );
+            self.recreate_swapchain = true;
+        }
+        self.settings = settings;
+    }
+
+    /// Whether the swapchain has to be recreated for settings changed since
+    /// the last call.
+    pub fn take_swapchain_recreation(&mut self) -> bool {
+        std::mem::take(&mut self.recreate_swapchain)
+    }
+
+    /// Pixels per screen coordinate, UI should be scaled by this.
+    pub fn scale_factor(&self) -> f32 {
+        self.window_size.scale_factor()
+    }
+
     pub fn into_shared(self) -> Arc<Mutex<Self>> {
         Arc::new(Mutex::new(self))
     }
 
+    /// Have the renderer release the graphics of prefabs despawned since the
+    /// last call. Entities are never reused with the same generation, so
+    /// anything left tracked for them would be kept forever.
+    pub fn release_despawned_graphics<P>(&mut self, world: &mut World, system: &mut P)
+    where
+        P: Presenter + Send + Sync,
+    {
+        if world.despawned_graphics.is_empty() {
+            return;
+        }
+        let despawned = std::mem::take(&mut world.despawned_graphics);
+        info!(
+            self.logger,
+            "releasing {} despawned graphics",
+            despawned.len()
+        );
+        system.release_graphics(&despawned);
+    }
+
+    /// Tracked graphics whose prefab no longer exists, which should have been
+    /// released by `release_despawned_graphics`. Orphans are left alone, they
+    /// point at a path where despawns aren't reported.
+    pub fn orphaned_graphics<P>(&self, world: &World, system: &P) -> Vec<Entity>
+    where
+        P: Presenter + Send + Sync,
+    {
+        system
+            .tracked_entities()
+            .into_iter()
+            .filter(|entity| world.hecs_world.get::<&GraphicPrefab>(*entity).is_err())
+            .collect()
+    }
+
     /// Search through the world for models that need to be uploaded, and do so.
-    /// Does not yet handle updates to models.
+    /// Prefabs that have been reloaded since they were last uploaded are
+    /// uploaded again, replacing the graphic the renderer is tracking.
     pub fn upload_untracked_graphics_prefabs<P>(&mut self, world: &World, system: &mut P)
     where
         P: Presenter + Send + Sync,
     {
-        for (entity, graphic) in world.hecs_world.query::<&GraphicPrefab>().iter() {
-            if let Some(uploaded_at) = system.tracked_graphics(entity) {
-                trace!(
-                    self.logger,
-                    "graphic {:?} already tracked for {}ms",
-                    entity,
-                    Instant::now().duration_since(uploaded_at).as_millis()
-                );
-            } else {
-                info!(self.logger, "uploading graphic {:?}", entity);
-                system
-                    .upload_graphics(&[(entity, &graphic.gfx)])
-                    .expect("unable to upload graphics");
+        let mut query = world
+            .hecs_world
+            .query::<(&GraphicPrefab, Option<&ReloadedGraphic>)>();
+        let mut pending = Vec::new();
+        for (entity, (graphic, reloaded)) in query.iter() {
+            match (system.tracked_graphics(entity), reloaded) {
+                (Some(uploaded_at), Some(reloaded)) if reloaded.at > uploaded_at => {
+                    info!(self.logger, "re-uploading reloaded graphic {:?}", entity);
+                    pending.push((entity, &graphic.gfx));
+                }
+                (Some(_), Some(_)) if system.is_reload_pending(entity) => {
+                    trace!(
+                        self.logger,
+                        "reload of graphic {:?} waiting to be swapped in",
+                        entity
+                    );
+                }
+                (Some(uploaded_at), _) => {
+                    trace!(
+                        self.logger,
+                        "graphic {:?} already tracked for {}ms",
+                        entity,
+                        Instant::now().duration_since(uploaded_at).as_millis()
+                    );
+                }
+                (None, _) => {
+                    info!(self.logger, "uploading graphic {:?}", entity);
+                    pending.push((entity, &graphic.gfx));
+                }
             }
         }
+        if !pending.is_empty() {
+            system
+                .upload_graphics(&pending)
+                .expect("unable to upload graphics");
+        }
     }
 }
 
+/// When the last presented frame was handed to the GPU.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct PresentTimings {
+    /// Its command buffers were submitted.
+    pub submitted: Instant,
+    /// It was queued for presentation.
+    pub presented: Instant,
+}
+
 /// Basic trait for calling into rendering functionality.
 pub trait Presenter {
     fn present(&mut self, world: &World);
+
+    /// Present the scene to a window added to `RenderState::targets`, from
+    /// the target's camera. Does nothing for targets the presenter hasn't
+    /// picked up yet, or can't render to.
+    fn present_to(&mut self, _target: RenderTargetId, _world: &World) {}
     fn update_resources(&mut self);
     fn deallocate(&mut self);
 
-    /// Query for a tracked drawable.
+    /// Query for a tracked drawable, returning when it was uploaded. For a
+    /// drawable with a reload pending, this is when the reload was uploaded.
     fn tracked_graphics(&self, entity: Entity) -> Option<Instant>;
 
+    /// Whether a reload of the drawable has been uploaded, but isn't drawn yet.
+    /// Until it is, the previous upload keeps being drawn.
+    fn is_reload_pending(&self, _entity: Entity) -> bool {
+        false
+    }
+
+    /// Upload graphics, replacing any already tracked for the same entity.
     fn upload_graphics(&mut self, graphics: &[(Entity, &Graphic)]) -> Result<(), RenderStateError>;
+
+    /// Release whatever is tracked for drawables that were despawned, once
+    /// nothing in flight draws them.
+    fn release_graphics(&mut self, entities: &[Entity]);
+
+    /// Every drawable with a graphic tracked, for auditing against the world.
+    fn tracked_entities(&self) -> Vec<Entity> {
+        Vec::new()
+    }
+
+    /// Occlusion culling counts for the last presented frame, if the presenter
+    /// culls occluded drawables.
+    fn occlusion_stats(&self) -> Option<OcclusionStats> {
+        None
+    }
+
+    /// Bytes of device memory held by uploaded graphics, if the presenter
+    /// keeps count.
+    fn graphics_memory(&self) -> Option<u64> {
+        None
+    }
+
+    /// Timings of the last presented frame, if the presenter records them.
+    fn present_timings(&self) -> Option<PresentTimings> {
+        None
+    }
+
+    /// Pipeline statistics of the last frame's passes and memory held by
+    /// each graphic, while `RenderState::capture_gpu_stats` is set.
+    fn gpu_stats(&self) -> Option<GpuStats> {
+        None
+    }
+
+    /// Read the next presented frame back from the GPU, if the presenter can.
+    /// Resolves once that frame has been rendered, without waiting on it.
+    fn read_back_frame(&mut self) -> Option<Readback<ReadbackImage>> {
+        None
+    }
+
+    /// Capture the next presented frame as a PNG screenshot in `dir`, named
+    /// by `readback::screenshot_path`, if the presenter can. Resolves to the
+    /// file once it's written, which happens off the calling thread.
+    fn capture_frame(&mut self, _dir: &Path) -> Option<Readback<PathBuf>> {
+        None
+    }
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -110,3 +393,78 @@ pub enum TextureUploaderError {
     #[error("queue send error")]
     QueueRecv,
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    /// Tracks uploads the way a renderer does, without a device.
+    #[derive(Default)]
+    struct Tracking {
+        tracked: HashMap<Entity, Instant>,
+    }
+
+    impl Presenter for Tracking {
+        fn present(&mut self, _world: &World) {}
+        fn update_resources(&mut self) {}
+        fn deallocate(&mut self) {}
+
+        fn tracked_graphics(&self, entity: Entity) -> Option<Instant> {
+            self.tracked.get(&entity).copied()
+        }
+
+        fn upload_graphics(
+            &mut self,
+            graphics: &[(Entity, &Graphic)],
+        ) -> Result<(), RenderStateError> {
+            for (entity, _) in graphics {
+                self.tracked.insert(*entity, Instant::now());
+            }
+            Ok(())
+        }
+
+        fn release_graphics(&mut self, entities: &[Entity]) {
+            for entity in entities {
+                self.tracked.remove(entity);
+            }
+        }
+
+        fn tracked_entities(&self) -> Vec<Entity> {
+            self.tracked.keys().copied().collect()
+        }
+    }
+
+    #[test]
+    fn despawning_a_prefab_releases_its_graphic() {
+        let logger = LogLevel::Info.logger();
+        let mut world = World::new(None, &logger, true);
+        let mut render_state = RenderState::offscreen(1, 1, false, logger);
+        let mut presenter = Tracking::default();
+        let kept = world
+            .hecs_world
+            .spawn((GraphicPrefab::new(Graphic::ParticleSystem),));
+        let despawned = world
+            .hecs_world
+            .spawn((GraphicPrefab::new(Graphic::ParticleSystem),));
+        render_state.upload_untracked_graphics_prefabs(&world, &mut presenter);
+        assert_eq!(presenter.tracked.len(), 2);
+
+        world.despawn(despawned).unwrap();
+        assert_eq!(
+            render_state.orphaned_graphics(&world, &presenter),
+            vec![despawned]
+        );
+        render_state.release_despawned_graphics(&mut world, &mut presenter);
+        assert!(world.despawned_graphics.is_empty());
+        assert_eq!(presenter.tracked_entities(), vec![kept]);
+        assert!(render_state
+            .orphaned_graphics(&world, &presenter)
+            .is_empty());
+
+        // Despawns are only released once.
+        render_state.release_despawned_graphics(&mut world, &mut presenter);
+        assert_eq!(presenter.tracked_entities(), vec![kept]);
+    }
+}
@@ -0,0 +1,163 @@
+//! Directional and spot lights passed to shaders in the uniform buffer of a
+//! view. Every fragment shades each of them, unlike point lights, which are
+//! clustered, so only those that reach into the view are passed, nearest
+//! first, and no more than the device's uniform buffers hold, see
+//! `UniformBuffer::lights_within`. Directional lights reach everywhere and
+//! come before any spot light.
+
+use glam::Vec3;
+use shader_objects::{Light, UniformBuffer, LIGHT_DIRECTIONAL, LIGHT_SPOT};
+use world::components::{DirectionalLight, SpotLight, WorldTransform};
+
+use crate::extract::Frustum;
+
+/// Lights gathered for a view, and what was left out.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct LightStats {
+    /// Lights written to `UniformBuffer::lights`.
+    pub lights: usize,
+    /// Spot lights out of view.
+    pub culled: usize,
+    /// Lights in view past the capacity, the farthest are dropped.
+    pub dropped: usize,
+}
+
+/// The shader's light for a directional light at `transform`.
+pub fn directional_light(light: &DirectionalLight, transform: &WorldTransform) -> Light {
+    Light {
+        color: (light.color * light.intensity).extend(1.0),
+        direction: transform.forward().extend(0.0),
+        kind: LIGHT_DIRECTIONAL,
+        ..Light::NONE
+    }
+}
+
+/// The shader's light for a spot light at `transform`.
+pub fn spot_light(light: &SpotLight, transform: &WorldTransform) -> Light {
+    Light {
+        color: (light.color * light.intensity).extend(1.0),
+        pos_radius: transform.get_pos().extend(light.radius),
+        direction: transform.forward().extend(0.0),
+        cone: glam::Vec4::new(light.inner_angle.cos(), light.outer_angle.cos(), 0.0, 0.0),
+        kind: LIGHT_SPOT,
+        ..Light::NONE
+    }
+}
+
+/// Picks the lights of a view, keeping its list between frames.
+#[derive(Default)]
+pub struct ViewLights {
+    /// Lights reaching into the view, by how far they are from the eye.
+    in_view: Vec<(f32, Light)>,
+}
+
+impl ViewLights {
+    /// Write the `capacity` nearest of `lights` reaching into a view from
+    /// `eye` into `out`, setting its `light_count`. Without a `frustum`,
+    /// such as for views in every direction, lights are only culled by
+    /// distance.
+    pub fn gather(
+        &mut self,
+        eye: Vec3,
+        frustum: Option<&Frustum>,
+        capacity: usize,
+        lights: impl IntoIterator<Item = Light>,
+        out: &mut UniformBuffer,
+    ) -> LightStats {
+        let mut stats = LightStats::default();
+        self.in_view.clear();
+        for light in lights {
+            if light.kind != LIGHT_SPOT {
+                self.in_view.push((f32::NEG_INFINITY, light));
+                continue;
+            }
+            let (center, radius) = (light.pos_radius.truncate(), light.pos_radius.w);
+            let in_view = radius > 0.0
+                && frustum.map_or(true, |frustum| frustum.contains_sphere(center, radius));
+            if in_view {
+                self.in_view
+                    .push(((eye.distance(center) - radius).max(0.0), light));
+            } else {
+                stats.culled += 1;
+            }
+        }
+
+        let capacity = capacity.min(out.lights.len());
+        if self.in_view.len() > capacity {
+            self.in_view
+                .select_nth_unstable_by(capacity, |a, b| a.0.total_cmp(&b.0));
+            stats.dropped = self.in_view.len() - capacity;
+            self.in_view.truncate(capacity);
+        }
+        for (slot, (_, light)) in out.lights.iter_mut().zip(&self.in_view) {
+            *slot = *light;
+        }
+        stats.lights = self.in_view.len();
+        out.light_count = stats.lights as u32;
+        stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::{Mat4, Vec4};
+
+    use super::*;
+
+    fn spot(pos: Vec3, radius: f32) -> Light {
+        Light {
+            pos_radius: pos.extend(radius),
+            kind: LIGHT_SPOT,
+            ..Light::NONE
+        }
+    }
+
+    #[test]
+    fn keeps_directional_lights_then_the_nearest_spots_in_view() {
+        let view = Mat4::look_at_lh(Vec3::ZERO, Vec3::Z, Vec3::Y);
+        let projection = Mat4::perspective_lh(1.0, 1.0, 0.1, 100.0);
+        let frustum = Frustum::from_view_projection(projection * view);
+        let sun = Light {
+            color: Vec4::ONE,
+            kind: LIGHT_DIRECTIONAL,
+            ..Light::NONE
+        };
+        let lights = [
+            spot(Vec3::new(0.0, 0.0, 30.0), 2.0),
+            spot(Vec3::new(0.0, 0.0, -30.0), 2.0),
+            spot(Vec3::new(0.0, 0.0, 10.0), 2.0),
+            sun,
+            spot(Vec3::new(0.0, 0.0, 20.0), 2.0),
+        ];
+
+        let mut out = UniformBuffer::new();
+        let stats = ViewLights::default().gather(Vec3::ZERO, Some(&frustum), 3, lights, &mut out);
+        assert_eq!(
+            stats,
+            LightStats {
+                lights: 3,
+                culled: 1,
+                dropped: 1,
+            }
+        );
+        assert_eq!(out.light_count, 3);
+        let mut kept: Vec<_> = out.lights[..3]
+            .iter()
+            .map(|light| (light.kind, light.pos_radius.z))
+            .collect();
+        kept.sort_by(|a, b| a.1.total_cmp(&b.1));
+        assert_eq!(
+            kept,
+            [
+                (LIGHT_DIRECTIONAL, 0.0),
+                (LIGHT_SPOT, 10.0),
+                (LIGHT_SPOT, 20.0)
+            ]
+        );
+
+        // Without a frustum the spot behind the eye is near enough.
+        let stats = ViewLights::default().gather(Vec3::ZERO, None, 8, lights, &mut out);
+        assert_eq!(stats.lights, 5);
+        assert_eq!(out.light_count, 5);
+    }
+}
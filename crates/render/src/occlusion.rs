@@ -0,0 +1,396 @@
+//! Occlusion culling against a coarse software depth buffer.
+//!
+//! Each frame the bounding boxes of large occluders (drawables flagged with
+//! `RenderFlags::OCCLUDER`) are rasterized on the CPU into a small depth
+//! buffer, which is then reduced into a hierarchical-Z pyramid holding the
+//! farthest depth of each block. Other drawables test their screen bounds
+//! against the pyramid and are skipped when they are entirely behind an
+//! occluder. Both sides are conservative: occluder triangles are written at
+//! their farthest depth, drawables are tested at their nearest, and anything
+//! crossing the near plane is left alone.
+
+use gfx::Vertex;
+use glam::{Mat4, Vec3, Vec4Swizzles};
+
+pub const OCCLUSION_BUFFER_WIDTH: usize = 128;
+pub const OCCLUSION_BUFFER_HEIGHT: usize = 64;
+
+// Depth of an empty texel. perspective_lh maps depth to 0..=1.
+const FAR_DEPTH: f32 = 1.0;
+
+// Corners with a w below this are treated as crossing the near plane.
+const MIN_W: f32 = 1e-4;
+
+// Triangles of a box, indexing into `Aabb::corners`.
+const BOX_TRIANGLES: [[usize; 3]; 12] = [
+    [0, 1, 3],
+    [0, 3, 2],
+    [4, 6, 7],
+    [4, 7, 5],
+    [0, 4, 5],
+    [0, 5, 1],
+    [2, 3, 7],
+    [2, 7, 6],
+    [0, 2, 6],
+    [0, 6, 4],
+    [1, 5, 7],
+    [1, 7, 3],
+];
+
+/// Axis-aligned bounding box.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    pub fn new(min: Vec3, max: Vec3) -> Self {
+        Self { min, max }
+    }
+
+    /// Bounds of the given vertices, or None if there are none.
+    pub fn from_vertices(vertices: &[Vertex]) -> Option<Self> {
+        let mut positions = vertices
+            .iter()
+            .map(|vertex| Vec3::new(vertex.pos[0], vertex.pos[1], vertex.pos[2]));
+        let first = positions.next()?;
+        Some(positions.fold(Self::new(first, first), |bounds, pos| {
+            Self::new(bounds.min.min(pos), bounds.max.max(pos))
+        }))
+    }
+
+    /// Corners, indexed by bit 0: x, bit 1: y, bit 2: z (set meaning max).
+    pub fn corners(&self) -> [Vec3; 8] {
+        let mut corners = [Vec3::ZERO; 8];
+        for (i, corner) in corners.iter_mut().enumerate() {
+            *corner = Vec3::new(
+                if i & 1 == 0 { self.min.x } else { self.max.x },
+                if i & 2 == 0 { self.min.y } else { self.max.y },
+                if i & 4 == 0 { self.min.z } else { self.max.z },
+            );
+        }
+        corners
+    }
+}
+
+/// Counts for one frame of occlusion culling.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct OcclusionStats {
+    /// Occluders rasterized into the depth buffer.
+    pub occluders: u32,
+    /// Drawables tested against the depth buffer.
+    pub tested: u32,
+    /// Drawables found to be hidden, and not drawn.
+    pub occluded: u32,
+}
+
+struct DepthLevel {
+    width: usize,
+    height: usize,
+    depth: Vec<f32>,
+}
+
+impl DepthLevel {
+    fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            depth: vec![FAR_DEPTH; width * height],
+        }
+    }
+
+    fn get(&self, x: usize, y: usize) -> f32 {
+        self.depth[y * self.width + x]
+    }
+}
+
+/// A corner projected into the depth buffer: pixel coordinates and depth.
+#[derive(Debug, Copy, Clone)]
+struct ScreenPoint {
+    x: f32,
+    y: f32,
+    depth: f32,
+}
+
+/// Software depth buffer and hierarchical-Z pyramid for occlusion culling.
+/// Call `clear`, add occluders, `finish`, and then test drawables.
+pub struct OcclusionBuffer {
+    // Level 0 is the rasterized depth buffer, each following level holds the
+    // farthest depth of 2x2 texels of the level before.
+    levels: Vec<DepthLevel>,
+    stats: OcclusionStats,
+}
+
+impl Default for OcclusionBuffer {
+    fn default() -> Self {
+        Self::new(OCCLUSION_BUFFER_WIDTH, OCCLUSION_BUFFER_HEIGHT)
+    }
+}
+
+impl OcclusionBuffer {
+    pub fn new(width: usize, height: usize) -> Self {
+        let mut levels = vec![DepthLevel::new(width.max(1), height.max(1))];
+        loop {
+            let last = levels.last().unwrap();
+            if last.width == 1 && last.height == 1 {
+                break;
+            }
+            let level = DepthLevel::new(last.width.div_ceil(2), last.height.div_ceil(2));
+            levels.push(level);
+        }
+        Self {
+            levels,
+            stats: OcclusionStats::default(),
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.levels[0].width
+    }
+
+    pub fn height(&self) -> usize {
+        self.levels[0].height
+    }
+
+    pub fn stats(&self) -> OcclusionStats {
+        self.stats
+    }
+
+    /// Start a new frame with no occluders.
+    pub fn clear(&mut self) {
+        for level in self.levels.iter_mut() {
+            level.depth.fill(FAR_DEPTH);
+        }
+        self.stats = OcclusionStats::default();
+    }
+
+    /// Rasterize the bounds of an occluder. Returns false if it wasn't added
+    /// because it crosses the near plane.
+    pub fn add_occluder(&mut self, model_view_projection: Mat4, bounds: &Aabb) -> bool {
+        let points = match self.project(model_view_projection, bounds) {
+            Some(points) => points,
+            None => return false,
+        };
+        for [a, b, c] in BOX_TRIANGLES {
+            self.rasterize_triangle(points[a], points[b], points[c]);
+        }
+        self.stats.occluders += 1;
+        true
+    }
+
+    /// Build the depth pyramid from the rasterized occluders.
+    pub fn finish(&mut self) {
+        for i in 1..self.levels.len() {
+            let (finer, coarser) = self.levels.split_at_mut(i);
+            let finer = &finer[i - 1];
+            let coarser = &mut coarser[0];
+            for y in 0..coarser.height {
+                for x in 0..coarser.width {
+                    let (fx, fy) = (x * 2, y * 2);
+                    let mut farthest = finer.get(fx, fy);
+                    if fx + 1 < finer.width {
+                        farthest = farthest.max(finer.get(fx + 1, fy));
+                    }
+                    if fy + 1 < finer.height {
+                        farthest = farthest.max(finer.get(fx, fy + 1));
+                        if fx + 1 < finer.width {
+                            farthest = farthest.max(finer.get(fx + 1, fy + 1));
+                        }
+                    }
+                    coarser.depth[y * coarser.width + x] = farthest;
+                }
+            }
+        }
+    }
+
+    /// Test whether anything within `bounds` may be visible past the
+    /// occluders. Bounds outside of the buffer are reported as visible,
+    /// frustum culling is a separate concern.
+    pub fn is_visible(&mut self, model_view_projection: Mat4, bounds: &Aabb) -> bool {
+        self.stats.tested += 1;
+        let points = match self.project(model_view_projection, bounds) {
+            Some(points) => points,
+            None => return true,
+        };
+        let (mut min_x, mut min_y, mut max_x, mut max_y) = (f32::MAX, f32::MAX, f32::MIN, f32::MIN);
+        let mut nearest = f32::MAX;
+        for point in points {
+            min_x = min_x.min(point.x);
+            min_y = min_y.min(point.y);
+            max_x = max_x.max(point.x);
+            max_y = max_y.max(point.y);
+            nearest = nearest.min(point.depth);
+        }
+        let (width, height) = (self.width() as f32, self.height() as f32);
+        if max_x < 0.0 || max_y < 0.0 || min_x >= width || min_y >= height {
+            return true;
+        }
+        let x0 = min_x.max(0.0) as usize;
+        let y0 = min_y.max(0.0) as usize;
+        let x1 = (max_x.min(width - 1.0)) as usize;
+        let y1 = (max_y.min(height - 1.0)) as usize;
+
+        // Use the finest level at which the bounds cover at most 2x2 texels.
+        let mut level = 0;
+        while level + 1 < self.levels.len()
+            && ((x1 >> level) - (x0 >> level) > 1 || (y1 >> level) - (y0 >> level) > 1)
+        {
+            level += 1;
+        }
+        let depth = &self.levels[level];
+        for y in (y0 >> level)..=(y1 >> level) {
+            for x in (x0 >> level)..=(x1 >> level) {
+                if depth.get(x, y) >= nearest {
+                    return true;
+                }
+            }
+        }
+        self.stats.occluded += 1;
+        false
+    }
+
+    fn project(&self, model_view_projection: Mat4, bounds: &Aabb) -> Option<[ScreenPoint; 8]> {
+        let (width, height) = (self.width() as f32, self.height() as f32);
+        let mut points = [ScreenPoint {
+            x: 0.0,
+            y: 0.0,
+            depth: 0.0,
+        }; 8];
+        for (point, corner) in points.iter_mut().zip(bounds.corners()) {
+            let clip = model_view_projection * corner.extend(1.0);
+            if clip.w < MIN_W {
+                return None;
+            }
+            let ndc = clip.xyz() / clip.w;
+            *point = ScreenPoint {
+                x: (ndc.x * 0.5 + 0.5) * width,
+                y: (ndc.y * 0.5 + 0.5) * height,
+                depth: ndc.z,
+            };
+        }
+        Some(points)
+    }
+
+    fn rasterize_triangle(&mut self, a: ScreenPoint, b: ScreenPoint, c: ScreenPoint) {
+        let area = edge(a, b, c.x, c.y);
+        if area.abs() < f32::EPSILON {
+            return;
+        }
+        let depth = a.depth.max(b.depth).max(c.depth);
+        let level = &mut self.levels[0];
+        let (width, height) = (level.width as f32, level.height as f32);
+        let min_x = a.x.min(b.x).min(c.x).max(0.0);
+        let min_y = a.y.min(b.y).min(c.y).max(0.0);
+        let max_x = a.x.max(b.x).max(c.x).min(width);
+        let max_y = a.y.max(b.y).max(c.y).min(height);
+        if min_x >= max_x || min_y >= max_y {
+            return;
+        }
+        for y in (min_y as usize)..(max_y.ceil() as usize).min(level.height) {
+            for x in (min_x as usize)..(max_x.ceil() as usize).min(level.width) {
+                let (px, py) = (x as f32 + 0.5, y as f32 + 0.5);
+                let w0 = edge(b, c, px, py) * area.signum();
+                let w1 = edge(c, a, px, py) * area.signum();
+                let w2 = edge(a, b, px, py) * area.signum();
+                if w0 >= 0.0 && w1 >= 0.0 && w2 >= 0.0 {
+                    let texel = &mut level.depth[y * level.width + x];
+                    *texel = texel.min(depth);
+                }
+            }
+        }
+    }
+}
+
+fn edge(a: ScreenPoint, b: ScreenPoint, x: f32, y: f32) -> f32 {
+    (b.x - a.x) * (y - a.y) - (b.y - a.y) * (x - a.x)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn view_projection() -> Mat4 {
+        // Looking down +z from the origin.
+        Mat4::perspective_lh(0.75, 1.7, 0.1, 1000.0)
+    }
+
+    fn unit_box_at(center: Vec3, half_extent: Vec3) -> Aabb {
+        Aabb::new(center - half_extent, center + half_extent)
+    }
+
+    fn buffer_with_wall() -> OcclusionBuffer {
+        let mut buffer = OcclusionBuffer::default();
+        buffer.clear();
+        let wall = unit_box_at(Vec3::new(0.0, 0.0, 5.5), Vec3::new(1.0, 1.0, 0.5));
+        assert!(buffer.add_occluder(view_projection(), &wall));
+        buffer.finish();
+        buffer
+    }
+
+    #[test]
+    fn aabb_from_vertices() {
+        let vertices = [
+            Vertex::pos(1.0, -2.0, 3.0),
+            Vertex::pos(-1.0, 2.0, 0.0),
+            Vertex::pos(0.0, 0.0, 5.0),
+        ];
+        let bounds = Aabb::from_vertices(&vertices).unwrap();
+        assert_eq!(bounds.min, Vec3::new(-1.0, -2.0, 0.0));
+        assert_eq!(bounds.max, Vec3::new(1.0, 2.0, 5.0));
+        assert_eq!(Aabb::from_vertices(&[]), None);
+    }
+
+    #[test]
+    fn hidden_behind_occluder() {
+        let mut buffer = buffer_with_wall();
+        let behind = unit_box_at(Vec3::new(0.0, 0.0, 20.0), Vec3::splat(0.5));
+        assert!(!buffer.is_visible(view_projection(), &behind));
+        assert_eq!(
+            buffer.stats(),
+            OcclusionStats {
+                occluders: 1,
+                tested: 1,
+                occluded: 1
+            }
+        );
+    }
+
+    #[test]
+    fn visible_in_front_of_or_beside_occluder() {
+        let mut buffer = buffer_with_wall();
+        let in_front = unit_box_at(Vec3::new(0.0, 0.0, 2.0), Vec3::splat(0.2));
+        assert!(buffer.is_visible(view_projection(), &in_front));
+        let beside = unit_box_at(Vec3::new(6.5, 0.0, 20.0), Vec3::splat(0.5));
+        assert!(buffer.is_visible(view_projection(), &beside));
+        // partially behind the wall
+        let straddling = unit_box_at(Vec3::new(4.0, 0.0, 20.0), Vec3::new(1.0, 0.5, 0.5));
+        assert!(buffer.is_visible(view_projection(), &straddling));
+        assert_eq!(buffer.stats().occluded, 0);
+    }
+
+    #[test]
+    fn near_plane_crossings_are_conservative() {
+        let mut buffer = OcclusionBuffer::default();
+        buffer.clear();
+        let around_camera = unit_box_at(Vec3::ZERO, Vec3::splat(10.0));
+        assert!(!buffer.add_occluder(view_projection(), &around_camera));
+        buffer.finish();
+
+        let mut buffer = buffer_with_wall();
+        assert!(buffer.is_visible(view_projection(), &around_camera));
+    }
+
+    #[test]
+    fn pyramid_holds_farthest_depth() {
+        let mut buffer = OcclusionBuffer::new(5, 3);
+        assert_eq!(buffer.levels.len(), 4);
+        buffer.clear();
+        buffer.levels[0].depth.fill(0.5);
+        buffer.levels[0].depth[4] = 0.9;
+        buffer.finish();
+        assert_eq!(buffer.levels[1].get(2, 0), 0.9);
+        assert_eq!(buffer.levels[1].get(0, 0), 0.5);
+        assert_eq!(buffer.levels[3].get(0, 0), 0.9);
+    }
+}
@@ -0,0 +1,149 @@
+//! Reflection probes: the scene captured into a cubemap from fixed points, for
+//! shiny drawables near them to reflect. A probe is captured by drawing the
+//! scene once per cube face, from the views given by `face_view_projection`.
+//! Shaders find the face and the point on it that a direction passes through
+//! with `shader_objects::cube_face_uv`, which follows the same convention.
+
+use std::f32::consts::FRAC_PI_2;
+
+use glam::{Mat4, Vec3, Vec4};
+use shader_objects::{cube_face, ReflectionProbes, MAX_REFLECTION_PROBES};
+
+/// Near and far planes of the views probes are captured with.
+pub const PROBE_NEAR: f32 = 0.05;
+pub const PROBE_FAR: f32 = 500.0;
+
+/// View and projection capturing cube face `face` from `position`.
+pub fn face_view_projection(face: usize, position: Vec3) -> Mat4 {
+    let (forward, up) = cube_face(face);
+    Mat4::perspective_lh(FRAC_PI_2, 1.0, PROBE_NEAR, PROBE_FAR)
+        * Mat4::look_to_lh(position, forward, up)
+}
+
+/// Which probes were last captured, to tell when they have to be captured
+/// again.
+#[derive(Debug, Default)]
+pub struct ProbeCaptures {
+    probes: Vec<Vec4>,
+    scratch: Vec<Vec4>,
+    dropped: usize,
+    // The world's capture request count when they were last captured.
+    captured_request: Option<u64>,
+}
+
+impl ProbeCaptures {
+    /// Take the world's probes, as positions with their radius in `w`, and
+    /// return whether they have to be captured: they differ from the last
+    /// ones captured, or `requested` has changed since. Only
+    /// `MAX_REFLECTION_PROBES` are kept, ordered by position so the order
+    /// they're listed in doesn't matter.
+    pub fn update(&mut self, requested: u64, probes: impl IntoIterator<Item = Vec4>) -> bool {
+        self.scratch.clear();
+        self.scratch.extend(probes);
+        self.scratch.sort_by(|a, b| {
+            a.to_array()
+                .iter()
+                .zip(b.to_array().iter())
+                .map(|(a, b)| a.total_cmp(b))
+                .find(|ordering| ordering.is_ne())
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+        self.dropped = self.scratch.len().saturating_sub(MAX_REFLECTION_PROBES);
+        self.scratch.truncate(MAX_REFLECTION_PROBES);
+        if self.captured_request == Some(requested) && self.scratch == self.probes {
+            return false;
+        }
+        std::mem::swap(&mut self.probes, &mut self.scratch);
+        self.captured_request = Some(requested);
+        true
+    }
+
+    /// The probes last returned by `update`, their faces in this order.
+    pub fn probes(&self) -> &[Vec4] {
+        &self.probes
+    }
+
+    /// Probes left out by the last `update`, past `MAX_REFLECTION_PROBES`.
+    pub fn dropped(&self) -> usize {
+        self.dropped
+    }
+
+    /// The probes as shaders read them, viewed from `eye`.
+    pub fn shader_probes(&self, eye: Vec3) -> ReflectionProbes {
+        let mut probes = [Vec4::ZERO; MAX_REFLECTION_PROBES];
+        probes[..self.probes.len()].copy_from_slice(&self.probes);
+        ReflectionProbes {
+            eye: eye.extend(1.0),
+            probes,
+            count: self.probes.len() as u32,
+            _pad1: 0,
+            _pad2: 0,
+            _pad3: 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use shader_objects::cube_face_uv;
+
+    use super::*;
+
+    #[test]
+    fn faces_are_sampled_where_they_were_rendered() {
+        let position = Vec3::new(3.0, -1.0, 7.0);
+        let directions = [
+            Vec3::new(1.0, 0.2, -0.3),
+            Vec3::new(-1.0, 0.7, 0.1),
+            Vec3::new(0.3, 1.0, -0.8),
+            Vec3::new(-0.4, -1.0, 0.2),
+            Vec3::new(0.5, -0.1, 1.0),
+            Vec3::new(-0.6, 0.9, -1.0),
+        ];
+        for (expected_face, direction) in directions.into_iter().enumerate() {
+            let (face, uv) = cube_face_uv(direction);
+            assert_eq!(face, expected_face, "{direction}");
+            let clip = face_view_projection(face, position) * (position + direction).extend(1.0);
+            let ndc = clip.truncate() / clip.w;
+            assert!((0.0..=1.0).contains(&ndc.z), "{direction} is clipped");
+            let rendered_at = ndc.truncate() * 0.5 + 0.5;
+            assert!(
+                (rendered_at - uv).abs().max_element() < 1e-5,
+                "{direction} rendered at {rendered_at}, sampled at {uv}"
+            );
+        }
+    }
+
+    #[test]
+    fn captures_again_when_probes_change_or_on_request() {
+        let a = Vec4::new(0.0, 1.0, 0.0, 5.0);
+        let b = Vec4::new(10.0, 1.0, 0.0, 5.0);
+        let mut captures = ProbeCaptures::default();
+        assert!(captures.update(0, [a, b]));
+        assert_eq!(captures.probes(), [a, b]);
+        // The order they're listed in doesn't matter.
+        assert!(!captures.update(0, [b, a]));
+        assert!(captures.update(1, [a, b]));
+        assert!(!captures.update(1, [a, b]));
+
+        let moved = Vec4::new(10.0, 2.0, 0.0, 5.0);
+        assert!(captures.update(1, [a, moved]));
+        assert!(captures.update(1, [a]));
+        assert!(captures.update(1, []));
+        assert!(captures.probes().is_empty());
+    }
+
+    #[test]
+    fn keeps_at_most_max_probes() {
+        let mut captures = ProbeCaptures::default();
+        let probes = (0..MAX_REFLECTION_PROBES + 3).map(|i| Vec4::new(i as f32, 0.0, 0.0, 1.0));
+        assert!(captures.update(0, probes));
+        assert_eq!(captures.probes().len(), MAX_REFLECTION_PROBES);
+        assert_eq!(captures.dropped(), 3);
+
+        let shader_probes = captures.shader_probes(Vec3::Y);
+        assert_eq!(shader_probes.count, MAX_REFLECTION_PROBES as u32);
+        assert_eq!(shader_probes.eye, Vec4::new(0.0, 1.0, 0.0, 1.0));
+        assert_eq!(shader_probes.probes[1], Vec4::new(1.0, 0.0, 0.0, 1.0));
+    }
+}
@@ -0,0 +1,269 @@
+//! Reading results back from the GPU without waiting on it.
+//!
+//! A readback is copied into host-visible memory by commands the renderer
+//! records into a frame, and delivered once the fence of that frame's
+//! submission has signaled. Whoever asked for it gets a `Readback`, a future
+//! that resolves then, so it can be awaited on an executor or checked each
+//! frame with `Readback::try_take`.
+
+use std::fs;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::time::SystemTime;
+
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+pub enum ReadbackError {
+    #[error("the renderer dropped the readback before it completed")]
+    Cancelled,
+    #[error("unable to read back {0}")]
+    Unsupported(&'static str),
+    #[error("device error reading back: {0}")]
+    Device(String),
+    #[error("unable to save what was read back: {0}")]
+    Save(String),
+}
+
+/// Pixels of a color image read back from the GPU, 8 bits per channel.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReadbackImage {
+    pub width: u32,
+    pub height: u32,
+    /// Whether channels are in blue, green, red, alpha order, as swapchain
+    /// images usually are, rather than red first.
+    pub bgra: bool,
+    /// Tightly packed rows, top first.
+    pub pixels: Vec<u8>,
+}
+
+impl ReadbackImage {
+    /// Tightly packed pixels with red, green, blue, alpha channels, rows top
+    /// first, such as to compare against a golden image.
+    pub fn into_rgba_bytes(self) -> Vec<u8> {
+        let mut pixels = self.pixels;
+        if self.bgra {
+            for pixel in pixels.chunks_exact_mut(4) {
+                pixel.swap(0, 2);
+            }
+        }
+        pixels
+    }
+
+    /// The image with red, green, blue, alpha channels.
+    pub fn into_rgba8(self) -> Option<image::RgbaImage> {
+        let (width, height) = (self.width, self.height);
+        image::RgbaImage::from_raw(width, height, self.into_rgba_bytes())
+    }
+
+    /// Encode the image as a PNG file at `path`, creating its directory.
+    pub fn save_png(self, path: &Path) -> Result<(), ReadbackError> {
+        let save_error = |err: &dyn std::fmt::Display| ReadbackError::Save(err.to_string());
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|err| save_error(&err))?;
+        }
+        let image = self
+            .into_rgba8()
+            .ok_or_else(|| save_error(&"pixels don't fill the image"))?;
+        image
+            .save_with_format(path, image::ImageFormat::Png)
+            .map_err(|err| save_error(&err))
+    }
+}
+
+/// Where a frame captured `at` is saved in `dir`.
+pub fn screenshot_path(dir: &Path, at: SystemTime) -> PathBuf {
+    let since_epoch = at
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default();
+    dir.join(format!(
+        "screenshot-{}.{:03}.png",
+        since_epoch.as_secs(),
+        since_epoch.subsec_millis()
+    ))
+}
+
+struct Slot<T> {
+    result: Option<Result<T, ReadbackError>>,
+    waker: Option<Waker>,
+}
+
+/// Create a readback, and where the renderer delivers it.
+pub fn readback<T>() -> (Delivery<T>, Readback<T>) {
+    let slot = Arc::new(Mutex::new(Slot {
+        result: None,
+        waker: None,
+    }));
+    (
+        Delivery {
+            slot: Some(Arc::clone(&slot)),
+        },
+        Readback { slot },
+    )
+}
+
+/// Completes a `Readback`. Dropped without completing, the readback resolves
+/// to `ReadbackError::Cancelled`.
+pub struct Delivery<T> {
+    slot: Option<Arc<Mutex<Slot<T>>>>,
+}
+
+impl<T> Delivery<T> {
+    pub fn complete(mut self, result: Result<T, ReadbackError>) {
+        self.fill(result);
+    }
+
+    /// Whether the `Readback` was dropped, so there's no point reading back.
+    pub fn is_abandoned(&self) -> bool {
+        self.slot.as_ref().map_or(0, Arc::strong_count) < 2
+    }
+
+    fn fill(&mut self, result: Result<T, ReadbackError>) {
+        if let Some(slot) = self.slot.take() {
+            let mut slot = slot.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            slot.result = Some(result);
+            if let Some(waker) = slot.waker.take() {
+                waker.wake();
+            }
+        }
+    }
+}
+
+impl<T> Drop for Delivery<T> {
+    fn drop(&mut self) {
+        self.fill(Err(ReadbackError::Cancelled));
+    }
+}
+
+/// A result on its way back from the GPU, see the module docs.
+#[must_use = "a readback does nothing unless it's awaited or taken"]
+pub struct Readback<T> {
+    slot: Arc<Mutex<Slot<T>>>,
+}
+
+impl<T> Readback<T> {
+    /// The result, if it has been delivered. It's only returned once.
+    pub fn try_take(&mut self) -> Option<Result<T, ReadbackError>> {
+        self.slot
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .result
+            .take()
+    }
+}
+
+impl<T> Future for Readback<T> {
+    type Output = Result<T, ReadbackError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut slot = self
+            .slot
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        match slot.result.take() {
+            Some(result) => Poll::Ready(result),
+            None => {
+                slot.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::task::Wake;
+
+    use super::*;
+
+    struct CountWakes(AtomicUsize);
+
+    impl Wake for CountWakes {
+        fn wake(self: Arc<Self>) {
+            self.0.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn resolves_once_delivered() {
+        let (delivery, mut pending) = readback();
+        let wakes = Arc::new(CountWakes(AtomicUsize::new(0)));
+        let waker = Waker::from(Arc::clone(&wakes));
+        let mut cx = Context::from_waker(&waker);
+
+        assert!(Pin::new(&mut pending).poll(&mut cx).is_pending());
+        assert!(!delivery.is_abandoned());
+        delivery.complete(Ok(vec![1u8, 2, 3]));
+        assert_eq!(wakes.0.load(Ordering::Relaxed), 1);
+        assert_eq!(
+            Pin::new(&mut pending).poll(&mut cx),
+            Poll::Ready(Ok(vec![1, 2, 3]))
+        );
+        assert_eq!(pending.try_take(), None);
+    }
+
+    #[test]
+    fn cancelled_when_dropped() {
+        let (delivery, mut pending) = readback::<u32>();
+        assert_eq!(pending.try_take(), None);
+        drop(delivery);
+        assert_eq!(pending.try_take(), Some(Err(ReadbackError::Cancelled)));
+
+        let (delivery, pending) = readback::<u32>();
+        drop(pending);
+        assert!(delivery.is_abandoned());
+    }
+
+    #[test]
+    fn swizzles_bgra() {
+        let image = ReadbackImage {
+            width: 1,
+            height: 1,
+            bgra: true,
+            pixels: vec![3, 2, 1, 4],
+        };
+        assert_eq!(
+            image.clone().into_rgba8().unwrap().into_raw(),
+            vec![1, 2, 3, 4]
+        );
+        assert_eq!(image.into_rgba_bytes(), vec![1, 2, 3, 4]);
+
+        let rgba = ReadbackImage {
+            width: 2,
+            height: 1,
+            bgra: false,
+            pixels: vec![1, 2, 3, 4, 5, 6, 7, 8],
+        };
+        assert_eq!(rgba.into_rgba_bytes(), vec![1, 2, 3, 4, 5, 6, 7, 8]);
+    }
+
+    #[test]
+    fn saves_png() {
+        let dir = std::env::temp_dir().join(format!("readback-png-{}", std::process::id()));
+        let at = SystemTime::UNIX_EPOCH + std::time::Duration::from_millis(1_500);
+        let path = screenshot_path(&dir, at);
+        assert_eq!(path, dir.join("screenshot-1.500.png"));
+
+        let image = ReadbackImage {
+            width: 2,
+            height: 1,
+            bgra: true,
+            pixels: vec![3, 2, 1, 255, 6, 5, 4, 255],
+        };
+        image.save_png(&path).unwrap();
+        let saved = image::open(&path).unwrap().into_rgba8();
+        assert_eq!(saved.dimensions(), (2, 1));
+        assert_eq!(saved.into_raw(), vec![1, 2, 3, 255, 4, 5, 6, 255]);
+
+        let short = ReadbackImage {
+            width: 2,
+            height: 2,
+            bgra: false,
+            pixels: vec![0; 4],
+        };
+        assert!(matches!(short.save_png(&path), Err(ReadbackError::Save(_))));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
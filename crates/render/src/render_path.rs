@@ -0,0 +1,56 @@
+//! How the scene is lit. The forward path shades each drawable as it's drawn,
+//! with the lights clustered by where they are in the view. The deferred path
+//! is experimental: drawables are drawn into a G-buffer first, then shaded at
+//! once by a lighting pass reading the same lights, probes and material
+//! parameters, so the two can be compared on the same scenes.
+
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum RenderPath {
+    #[default]
+    Forward,
+    /// Drawables shaded by the default shaders are drawn into a G-buffer and
+    /// lit afterwards, the rest are drawn forward over them. Only the main
+    /// window is rendered deferred.
+    Deferred,
+}
+
+impl fmt::Display for RenderPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RenderPath::Forward => f.write_str("forward"),
+            RenderPath::Deferred => f.write_str("deferred"),
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+#[error("unknown render path {0}, expected forward or deferred")]
+pub struct UnknownRenderPath(String);
+
+impl FromStr for RenderPath {
+    type Err = UnknownRenderPath;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "forward" => Ok(RenderPath::Forward),
+            "deferred" => Ok(RenderPath::Deferred),
+            _ => Err(UnknownRenderPath(s.to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_paths_parse_as_shown() {
+        for path in [RenderPath::Forward, RenderPath::Deferred] {
+            assert_eq!(path.to_string().parse::<RenderPath>().unwrap(), path);
+        }
+        assert!("forward+".parse::<RenderPath>().is_err());
+    }
+}
@@ -0,0 +1,225 @@
+//! Rendering the scene at a fraction of the window's resolution and upscaling
+//! it when presenting, so weak GPUs can trade sharpness for frame time.
+//!
+//! The scale is either fixed, or adjusted by `ScaleController` to hold a
+//! target frame time, measured over the engine's whole frame as recorded in
+//! its frame histogram: stepped down while frames are slower than the target,
+//! and back up once they're comfortably faster. Changes are made in steps, and
+//! no more often than every `ADJUST_INTERVAL_FRAMES`, so the render target
+//! isn't recreated every frame.
+
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+pub const MIN_SCALE: f32 = 0.25;
+pub const MAX_SCALE: f32 = 2.0;
+
+/// Change in scale per adjustment of a dynamic scale.
+const SCALE_STEP: f32 = 0.05;
+
+/// Frames a dynamic scale is held for before it's adjusted again.
+const ADJUST_INTERVAL_FRAMES: u32 = 30;
+
+/// Weight of each new frame time in the moving average.
+const FRAME_TIME_SMOOTHING: f32 = 0.1;
+
+/// Fraction of the target frame time frames must be under before a dynamic
+/// scale is raised.
+const RAISE_THRESHOLD: f32 = 0.8;
+
+/// How a scene rendered at another resolution is scaled to the window.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum UpscaleFilter {
+    /// Blocky, but cheap and sharp.
+    Nearest,
+    #[default]
+    Linear,
+}
+
+impl fmt::Display for UpscaleFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UpscaleFilter::Nearest => f.write_str("nearest"),
+            UpscaleFilter::Linear => f.write_str("linear"),
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+#[error("unknown upscale filter {0}, expected nearest or linear")]
+pub struct UnknownUpscaleFilter(String);
+
+impl FromStr for UpscaleFilter {
+    type Err = UnknownUpscaleFilter;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "nearest" => Ok(UpscaleFilter::Nearest),
+            "linear" => Ok(UpscaleFilter::Linear),
+            _ => Err(UnknownUpscaleFilter(s.to_string())),
+        }
+    }
+}
+
+/// Bounds for a scale adjusted to hold a frame time.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct DynamicResolution {
+    /// Time for a frame of the loop to take, including waiting for the GPU
+    /// to finish the previous one but not waiting out a frame cap, see
+    /// `RenderState::frame_time`.
+    pub target_frame_time: Duration,
+    pub min_scale: f32,
+    pub max_scale: f32,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct RenderScale {
+    /// Fraction of the window's resolution the scene is rendered at, the
+    /// starting scale when dynamic.
+    pub scale: f32,
+    pub filter: UpscaleFilter,
+    pub dynamic: Option<DynamicResolution>,
+}
+
+impl Default for RenderScale {
+    fn default() -> Self {
+        Self {
+            scale: 1.0,
+            filter: UpscaleFilter::default(),
+            dynamic: None,
+        }
+    }
+}
+
+/// The scale to render at, following a `RenderScale`.
+#[derive(Debug, Clone)]
+pub struct ScaleController {
+    config: RenderScale,
+    scale: f32,
+    average_frame_time: Option<f32>,
+    frames_since_change: u32,
+}
+
+impl ScaleController {
+    pub fn new(config: RenderScale) -> Self {
+        Self {
+            scale: clamp_scale(config.scale),
+            config,
+            average_frame_time: None,
+            frames_since_change: 0,
+        }
+    }
+
+    /// Follow a new configuration, starting over if it changed.
+    pub fn configure(&mut self, config: &RenderScale) {
+        if *config != self.config {
+            *self = Self::new(*config);
+        }
+    }
+
+    pub fn scale(&self) -> f32 {
+        self.scale
+    }
+
+    pub fn filter(&self) -> UpscaleFilter {
+        self.config.filter
+    }
+
+    /// Record how long a frame took, returning whether a dynamic scale changed.
+    pub fn record_frame(&mut self, frame_time: Duration) -> bool {
+        let dynamic = match self.config.dynamic {
+            Some(dynamic) => dynamic,
+            None => return false,
+        };
+        let frame_time = frame_time.as_secs_f32();
+        let average = match self.average_frame_time {
+            Some(average) => average + (frame_time - average) * FRAME_TIME_SMOOTHING,
+            None => frame_time,
+        };
+        self.average_frame_time = Some(average);
+        self.frames_since_change += 1;
+        if self.frames_since_change < ADJUST_INTERVAL_FRAMES {
+            return false;
+        }
+
+        let target = dynamic.target_frame_time.as_secs_f32();
+        let min = clamp_scale(dynamic.min_scale);
+        let max = clamp_scale(dynamic.max_scale).max(min);
+        let scale = if average > target {
+            (self.scale - SCALE_STEP).max(min)
+        } else if average < target * RAISE_THRESHOLD {
+            (self.scale + SCALE_STEP).min(max)
+        } else {
+            self.scale
+        };
+        if (scale - self.scale).abs() < f32::EPSILON {
+            return false;
+        }
+        self.scale = scale;
+        self.frames_since_change = 0;
+        true
+    }
+}
+
+fn clamp_scale(scale: f32) -> f32 {
+    scale.clamp(MIN_SCALE, MAX_SCALE)
+}
+
+/// Size of a `width` by `height` target at `scale`, at least a pixel.
+pub fn scaled_extent(width: u32, height: u32, scale: f32) -> (u32, u32) {
+    let scaled = |v: u32| ((v as f32 * scale).round() as u32).max(1);
+    (scaled(width), scaled(height))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(controller: &mut ScaleController, frame_time: Duration, frames: u32) {
+        for _ in 0..frames {
+            controller.record_frame(frame_time);
+        }
+    }
+
+    #[test]
+    fn dynamic_scale_holds_target_frame_time() {
+        let mut controller = ScaleController::new(RenderScale {
+            scale: 1.0,
+            filter: UpscaleFilter::Linear,
+            dynamic: Some(DynamicResolution {
+                target_frame_time: Duration::from_millis(16),
+                min_scale: 0.5,
+                max_scale: 1.0,
+            }),
+        });
+        // Not adjusted until enough frames were seen.
+        run(
+            &mut controller,
+            Duration::from_millis(30),
+            ADJUST_INTERVAL_FRAMES - 1,
+        );
+        assert_eq!(controller.scale(), 1.0);
+        assert!(controller.record_frame(Duration::from_millis(30)));
+        assert!(controller.scale() < 1.0);
+
+        run(&mut controller, Duration::from_millis(30), 1000);
+        assert_eq!(controller.scale(), 0.5);
+
+        // Just under the target holds, well under raises back to the max.
+        run(&mut controller, Duration::from_millis(15), 1000);
+        assert!(controller.scale() < 0.6);
+        run(&mut controller, Duration::from_millis(5), 1000);
+        assert_eq!(controller.scale(), 1.0);
+
+        let fixed = RenderScale {
+            scale: 0.75,
+            ..Default::default()
+        };
+        controller.configure(&fixed);
+        run(&mut controller, Duration::from_millis(30), 1000);
+        assert_eq!(controller.scale(), 0.75);
+        assert_eq!(scaled_extent(1280, 720, 0.75), (960, 540));
+        assert_eq!(scaled_extent(1, 1, MIN_SCALE), (1, 1));
+    }
+}
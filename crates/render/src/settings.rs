@@ -0,0 +1,121 @@
+//! Settings players change while running, from an options menu or the
+//! console: how frames are presented and the frame rate they're capped at.
+//! Changing how frames are presented recreates the swapchain, see
+//! `RenderState::set_settings`.
+
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// How frames are presented, falling back to the next best mode the surface
+/// supports, see `PresentMode::preference`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum PresentMode {
+    /// Wait for vertical blank, queueing frames behind each other. Supported
+    /// everywhere.
+    Fifo,
+    /// Wait for vertical blank, replacing a frame still waiting for it rather
+    /// than queueing behind it.
+    #[default]
+    Mailbox,
+    /// Present frames as soon as they're rendered, tearing.
+    Immediate,
+}
+
+impl PresentMode {
+    /// Modes tried in order until one is supported by the surface, ending
+    /// with `Fifo`, which always is.
+    pub fn preference(self) -> &'static [PresentMode] {
+        match self {
+            PresentMode::Fifo => &[PresentMode::Fifo],
+            PresentMode::Mailbox => &[PresentMode::Mailbox, PresentMode::Fifo],
+            PresentMode::Immediate => &[
+                PresentMode::Immediate,
+                PresentMode::Mailbox,
+                PresentMode::Fifo,
+            ],
+        }
+    }
+}
+
+impl fmt::Display for PresentMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PresentMode::Fifo => f.write_str("fifo"),
+            PresentMode::Mailbox => f.write_str("mailbox"),
+            PresentMode::Immediate => f.write_str("immediate"),
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+#[error("unknown present mode {0}, expected fifo, mailbox or immediate")]
+pub struct UnknownPresentMode(String);
+
+impl FromStr for PresentMode {
+    type Err = UnknownPresentMode;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fifo" => Ok(PresentMode::Fifo),
+            "mailbox" => Ok(PresentMode::Mailbox),
+            "immediate" => Ok(PresentMode::Immediate),
+            _ => Err(UnknownPresentMode(s.to_string())),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct RenderSettings {
+    /// How frames are presented.
+    pub present_mode: PresentMode,
+    /// Frames per second the loop is capped at, waiting out each frame
+    /// precisely rather than relying on presentation to pace it.
+    pub frame_cap: Option<f32>,
+}
+
+impl RenderSettings {
+    /// Length of a frame at the capped frame rate, if capped.
+    pub fn frame_length(&self) -> Option<Duration> {
+        self.frame_cap
+            .map(|fps| Duration::from_secs_f64(1.0 / f64::from(fps)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn present_modes_parse_as_shown() {
+        for mode in [
+            PresentMode::Fifo,
+            PresentMode::Mailbox,
+            PresentMode::Immediate,
+        ] {
+            assert_eq!(mode.to_string().parse::<PresentMode>().unwrap(), mode);
+        }
+        assert!("vsync".parse::<PresentMode>().is_err());
+    }
+
+    #[test]
+    fn every_preference_falls_back_to_fifo() {
+        for mode in [
+            PresentMode::Fifo,
+            PresentMode::Mailbox,
+            PresentMode::Immediate,
+        ] {
+            let preference = mode.preference();
+            assert_eq!(preference.first(), Some(&mode));
+            assert_eq!(preference.last(), Some(&PresentMode::Fifo));
+        }
+    }
+
+    #[test]
+    fn frame_length_follows_the_cap() {
+        let mut settings = RenderSettings::default();
+        assert_eq!(settings.frame_length(), None);
+        settings.frame_cap = Some(250.0);
+        assert_eq!(settings.frame_length(), Some(Duration::from_millis(4)));
+    }
+}
@@ -0,0 +1,154 @@
+//! Shadows cast by a directional light: the scene's depth is rendered from
+//! the light into a shadow map, covering a sphere around the eye, and
+//! fragments the shadow map sees something in front of aren't lit by it. Only
+//! the brightest directional light of a view casts shadows, see
+//! `ShadowCaster::update`.
+
+use glam::{Mat4, Vec3};
+use shader_objects::{UniformBuffer, LIGHT_DIRECTIONAL};
+
+/// Distance from the eye shadows are cast within.
+pub const SHADOW_DISTANCE: f32 = 40.0;
+
+/// How far past the shadowed sphere, towards the light, drawables still cast
+/// shadows into it, such as tall buildings or hills.
+pub const SHADOW_CASTER_DEPTH: f32 = 100.0;
+
+/// Takes world space to the clip space of a shadow map `resolution` texels
+/// wide, looking along `direction` at a sphere of `radius` around `center`.
+/// The shadow map moves in whole texels as the sphere does, so shadow edges
+/// don't shimmer as the eye moves.
+pub fn light_space(center: Vec3, radius: f32, direction: Vec3, resolution: u32) -> Mat4 {
+    let direction = direction.normalize_or_zero();
+    let direction = if direction == Vec3::ZERO {
+        Vec3::NEG_Y
+    } else {
+        direction
+    };
+    let up = if direction.y.abs() > 0.99 {
+        Vec3::Z
+    } else {
+        Vec3::Y
+    };
+    let view = Mat4::look_to_lh(Vec3::ZERO, direction, up);
+    let texel = 2.0 * radius / resolution.max(1) as f32;
+    let center = view.transform_point3(center);
+    let (x, y) = (
+        (center.x / texel).floor() * texel,
+        (center.y / texel).floor() * texel,
+    );
+    let projection = Mat4::orthographic_lh(
+        x - radius,
+        x + radius,
+        y - radius,
+        y + radius,
+        center.z - radius - SHADOW_CASTER_DEPTH,
+        center.z + radius,
+    );
+    projection * view
+}
+
+/// Picks the light casting shadows into a view.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct ShadowCaster {
+    /// Index into `UniformBuffer::lights` of the light last picked.
+    pub light: Option<usize>,
+}
+
+impl ShadowCaster {
+    /// Mark the brightest directional light gathered into `out` as shadowed
+    /// and set `out.light_space` to render its shadow map around `eye` with.
+    /// Returns whether there's a light to render the shadow map from.
+    pub fn update(&mut self, eye: Vec3, resolution: u32, out: &mut UniformBuffer) -> bool {
+        let lights = &mut out.lights[..out.light_count as usize];
+        let luminance = Vec3::new(0.2126, 0.7152, 0.0722);
+        self.light = lights
+            .iter()
+            .enumerate()
+            .filter(|(_, light)| light.kind == LIGHT_DIRECTIONAL)
+            .max_by(|(_, a), (_, b)| {
+                let a = a.color.truncate().dot(luminance);
+                let b = b.color.truncate().dot(luminance);
+                a.total_cmp(&b)
+            })
+            .map(|(i, _)| i);
+        for light in lights.iter_mut() {
+            light.shadowed = 0;
+        }
+        match self.light {
+            Some(i) => {
+                lights[i].shadowed = 1;
+                out.light_space = light_space(
+                    eye,
+                    SHADOW_DISTANCE,
+                    lights[i].direction.truncate(),
+                    resolution,
+                );
+                true
+            }
+            None => {
+                out.light_space = Mat4::IDENTITY;
+                false
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Vec4;
+    use shader_objects::{Light, LIGHT_SPOT};
+
+    use super::*;
+
+    #[test]
+    fn covers_the_sphere_in_whole_texels() {
+        let direction = Vec3::new(0.3, -1.0, 0.2);
+        for center in [Vec3::ZERO, Vec3::new(3.37, 1.2, -8.91)] {
+            let light_space = light_space(center, 10.0, direction, 1024);
+            for offset in [Vec3::X, Vec3::NEG_Y, Vec3::Z, Vec3::NEG_Z] {
+                let clip = light_space.project_point3(center + offset * 9.5);
+                assert!(clip.x.abs() <= 1.0 && clip.y.abs() <= 1.0, "{clip}");
+                assert!((0.0..=1.0).contains(&clip.z), "{clip}");
+            }
+            // Toward the light a caster further than the sphere still casts.
+            let caster = light_space.project_point3(center - direction.normalize() * 50.0);
+            assert!((0.0..=1.0).contains(&caster.z), "{caster}");
+
+            let texels = light_space.project_point3(Vec3::ZERO) * 512.0;
+            assert!((texels.x - texels.x.round()).abs() < 1e-2, "{texels}");
+            assert!((texels.y - texels.y.round()).abs() < 1e-2, "{texels}");
+        }
+    }
+
+    #[test]
+    fn the_brightest_directional_light_casts() {
+        let light = |kind, brightness| Light {
+            color: Vec4::splat(brightness),
+            direction: Vec4::new(0.0, -1.0, 0.0, 0.0),
+            kind,
+            ..Light::NONE
+        };
+        let mut out = UniformBuffer::new();
+        out.lights[..3].copy_from_slice(&[
+            light(LIGHT_DIRECTIONAL, 0.5),
+            light(LIGHT_SPOT, 4.0),
+            light(LIGHT_DIRECTIONAL, 1.0),
+        ]);
+        out.light_count = 3;
+
+        let mut caster = ShadowCaster::default();
+        assert!(caster.update(Vec3::ZERO, 1024, &mut out));
+        assert_eq!(caster.light, Some(2));
+        let shadowed: Vec<_> = out.lights[..3].iter().map(|l| l.shadowed).collect();
+        assert_eq!(shadowed, [0, 0, 1]);
+        assert_ne!(out.light_space, Mat4::IDENTITY);
+
+        out.light_count = 2;
+        out.lights[0].kind = LIGHT_SPOT;
+        assert!(!caster.update(Vec3::ZERO, 1024, &mut out));
+        assert_eq!(caster.light, None);
+        assert_eq!(out.light_space, Mat4::IDENTITY);
+        assert!(out.lights[..2].iter().all(|light| light.shadowed == 0));
+    }
+}
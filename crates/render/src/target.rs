@@ -0,0 +1,79 @@
+//! Windows rendered to besides the main one, such as a debug view shown by a
+//! dedicated server. Each target is a window with its own surface and
+//! swapchain, rendering the scene from a camera of its own, and is drawn with
+//! `Presenter::present_to`. The renderer picks up added and removed targets
+//! on its next update, like the rest of `RenderState`.
+
+use platform::{WinPtr, WindowSize};
+use world::Entity;
+
+/// Identifies a render target for as long as it exists. Ids aren't reused.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct RenderTargetId(u32);
+
+impl std::fmt::Display for RenderTargetId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "target {}", self.0)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RenderTarget {
+    pub id: RenderTargetId,
+    pub win_ptr: WinPtr,
+    /// Size of the window, kept up to date on resize.
+    pub window_size: WindowSize,
+    /// Camera the scene is rendered from, or the world's camera when `None`.
+    pub camera: Option<Entity>,
+}
+
+/// Render targets besides the main window, in the order they were added.
+#[derive(Debug, Default)]
+pub struct RenderTargets {
+    targets: Vec<RenderTarget>,
+    next_id: u32,
+}
+
+impl RenderTargets {
+    pub fn add(
+        &mut self,
+        win_ptr: WinPtr,
+        window_size: WindowSize,
+        camera: Option<Entity>,
+    ) -> RenderTargetId {
+        let id = RenderTargetId(self.next_id);
+        self.next_id += 1;
+        self.targets.push(RenderTarget {
+            id,
+            win_ptr,
+            window_size,
+            camera,
+        });
+        id
+    }
+
+    pub fn remove(&mut self, id: RenderTargetId) -> Option<RenderTarget> {
+        let index = self.targets.iter().position(|target| target.id == id)?;
+        Some(self.targets.remove(index))
+    }
+
+    pub fn get(&self, id: RenderTargetId) -> Option<&RenderTarget> {
+        self.targets.iter().find(|target| target.id == id)
+    }
+
+    pub fn get_mut(&mut self, id: RenderTargetId) -> Option<&mut RenderTarget> {
+        self.targets.iter_mut().find(|target| target.id == id)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &RenderTarget> {
+        self.targets.iter()
+    }
+
+    pub fn ids(&self) -> Vec<RenderTargetId> {
+        self.targets.iter().map(|target| target.id).collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.targets.is_empty()
+    }
+}
@@ -3,20 +3,227 @@
 #[cfg(feature = "std")]
 use bytemuck::{Pod, Zeroable};
 #[cfg(feature = "std")]
-use glam::{Mat4, Vec4};
+use glam::{Mat4, Vec2, Vec3, Vec4};
 // spirv-std has made glam a mandatory re-export, so we build two feature sets of this crate to
 // maintain compatibility with both spirv-std and std.
 #[cfg(feature = "spirv-std")]
-use spirv_std::glam::{Mat4, Vec4};
+use spirv_std::glam::{Mat4, Vec2, Vec3, Vec4};
 
-pub const MAX_LIGHTS: usize = 2;
+/// Most directional and spot lights in `UniformBuffer::lights`. Devices whose
+/// uniform buffers can't hold that many get fewer, see
+/// `UniformBuffer::lights_within`.
+pub const MAX_LIGHTS: usize = 256;
 
+/// Kinds of `Light`.
+pub const LIGHT_DIRECTIONAL: u32 = 0;
+pub const LIGHT_SPOT: u32 = 1;
+
+/// Number of per-drawable shader parameters, see `PushConstants::params`.
+pub const MAX_SHADER_PARAMS: usize = 4;
+
+/// Clusters across the viewport, down it and in depth, see
+/// `ClusteredLights`.
+pub const CLUSTERS_X: usize = 16;
+pub const CLUSTERS_Y: usize = 9;
+pub const CLUSTERS_Z: usize = 24;
+pub const CLUSTER_COUNT: usize = CLUSTERS_X * CLUSTERS_Y * CLUSTERS_Z;
+
+/// Most lights shaded through clusters in a frame.
+pub const MAX_CLUSTERED_LIGHTS: usize = 512;
+
+/// Room for light indices, over every cluster.
+pub const MAX_CLUSTER_LIGHT_INDICES: usize = 16_384;
+
+/// Binding of `ClusteredLights`, a storage buffer, in descriptor set 0.
+pub const CLUSTERED_LIGHTS_BINDING: u32 = 2;
+
+/// Most reflection probes captured at once.
+pub const MAX_REFLECTION_PROBES: usize = 8;
+
+/// Binding of `ReflectionProbes`, a uniform buffer, in descriptor set 0.
+pub const REFLECTION_PROBES_BINDING: u32 = 5;
+
+/// Binding of the faces captured by reflection probes, a 2D array image of
+/// six layers per probe in `cube_face` order, in descriptor set 0.
+pub const REFLECTION_PROBE_FACES_BINDING: u32 = 6;
+
+/// Binding of the texture the debug UI's meshes sample, in descriptor set 0.
+pub const UI_TEXTURE_BINDING: u32 = 0;
+
+/// Bindings of the G-buffer's normals and world positions, read by the
+/// deferred lighting pass in descriptor set 0. Its albedo is bound where the
+/// default shaders bind their diffuse map, 1, and the lights and probes where
+/// they bind them.
+pub const GBUFFER_NORMAL_BINDING: u32 = 3;
+pub const GBUFFER_POSITION_BINDING: u32 = 4;
+
+/// Slot of `PushConstants::params` the default shaders read how reflective a
+/// drawable is from, in `x`, from 0 to 1.
+pub const REFLECTIVITY_PARAM: usize = 0;
+
+/// Most joint matrices drawn with in a frame, over every skeleton.
+pub const MAX_JOINT_MATRICES: usize = 4096;
+
+/// Binding of `JointMatrices`, a storage buffer, in descriptor set 0.
+pub const JOINT_MATRICES_BINDING: u32 = 7;
+
+/// Slot of `PushConstants::params` the renderer sets for skinned drawables:
+/// the index of their first joint matrix in `JointMatrices`, in `x`, and how
+/// many joints they have, in `y`. Zero for drawables that aren't skinned.
+pub const SKIN_PARAM: usize = 3;
+
+/// Binding of the shadow map, a depth image rendered from the light marked
+/// `Light::shadowed`, in descriptor set 0. See `UniformBuffer::light_space`.
+pub const SHADOW_MAP_BINDING: u32 = 8;
+
+/// Width and height of the shadow map, in texels.
+pub const SHADOW_MAP_RESOLUTION: u32 = 2048;
+
+/// A directional or spot light, shaded by every fragment. Point lights are
+/// shaded through clusters instead, see `ClusteredLights`.
 #[cfg_attr(feature = "std", derive(Pod, Zeroable))]
 #[derive(Copy, Clone)]
 #[repr(C)]
 pub struct Light {
+    /// Color scaled by intensity.
+    pub color: Vec4,
+    /// World space position of a spot light, with the distance it reaches in
+    /// `w`.
+    pub pos_radius: Vec4,
+    /// World space direction the light shines in.
+    pub direction: Vec4,
+    /// Cosines of the angles from its direction a spot light starts fading
+    /// out at, in `x`, and is out by, in `y`.
+    pub cone: Vec4,
+    /// `LIGHT_DIRECTIONAL` or `LIGHT_SPOT`.
+    pub kind: u32,
+    /// Non-zero for the light the shadow map is rendered from, whose light
+    /// is shaded only where the shadow map sees a fragment.
+    pub shadowed: u32,
+    pub _pad2: u32,
+    pub _pad3: u32,
+}
+
+impl Light {
+    /// No light, filling `UniformBuffer::lights` past its `light_count`.
+    pub const NONE: Self = Self {
+        color: Vec4::ZERO,
+        pos_radius: Vec4::ZERO,
+        direction: Vec4::ZERO,
+        cone: Vec4::ZERO,
+        kind: LIGHT_DIRECTIONAL,
+        shadowed: 0,
+        _pad2: 0,
+        _pad3: 0,
+    };
+}
+
+/// A point light shaded through clusters.
+#[cfg_attr(feature = "std", derive(Pod, Zeroable))]
+#[derive(Copy, Clone)]
+#[repr(C)]
+pub struct ClusteredLight {
+    /// World space position, with the distance the light reaches in `w`.
+    pub pos_radius: Vec4,
+    /// Color scaled by intensity.
     pub color: Vec4,
-    pub pos: Vec4,
+}
+
+/// How fragments find their cluster.
+#[cfg_attr(feature = "std", derive(Pod, Zeroable))]
+#[derive(Copy, Clone)]
+#[repr(C)]
+pub struct ClusterGrid {
+    /// Left and top of the viewport, then the width and height of a
+    /// cluster, in pixels.
+    pub viewport: Vec4,
+    /// Scale and bias giving a depth slice from the log of view depth, then
+    /// unused.
+    pub depth: Vec4,
+}
+
+/// Lights and the clusters they touch, for the fragment shader to shade only
+/// the lights of the cluster a fragment is in.
+#[cfg_attr(feature = "std", derive(Pod, Zeroable))]
+#[derive(Copy, Clone)]
+#[repr(C)]
+pub struct ClusteredLights {
+    pub grid: ClusterGrid,
+    /// For each cluster, x first then y then depth, the offset of its first
+    /// light in `indices` in the low 16 bits and the number of lights in the
+    /// high 16.
+    pub clusters: [u32; CLUSTER_COUNT],
+    /// Indices into `lights`.
+    pub indices: [u32; MAX_CLUSTER_LIGHT_INDICES],
+    pub lights: [ClusteredLight; MAX_CLUSTERED_LIGHTS],
+}
+
+/// Reflection probes fragments can reflect, see `REFLECTION_PROBES_BINDING`.
+#[cfg_attr(feature = "std", derive(Pod, Zeroable))]
+#[derive(Copy, Clone)]
+#[repr(C)]
+pub struct ReflectionProbes {
+    /// World space position of the eye, then unused.
+    pub eye: Vec4,
+    /// World space position of each probe, with the distance it's reflected
+    /// within in `w`.
+    pub probes: [Vec4; MAX_REFLECTION_PROBES],
+    /// Number of probes captured.
+    pub count: u32,
+    pub _pad1: u32,
+    pub _pad2: u32,
+    pub _pad3: u32,
+}
+
+/// Direction and up of the view a probe captures cube face `face` with,
+/// +x, -x, +y, -y, +z then -z.
+pub fn cube_face(face: usize) -> (Vec3, Vec3) {
+    match face {
+        0 => (Vec3::X, Vec3::Y),
+        1 => (Vec3::NEG_X, Vec3::Y),
+        2 => (Vec3::Y, Vec3::NEG_Z),
+        3 => (Vec3::NEG_Y, Vec3::Z),
+        4 => (Vec3::Z, Vec3::Y),
+        _ => (Vec3::NEG_Z, Vec3::Y),
+    }
+}
+
+/// The cube face `direction` points through, and where on it, from 0 to 1
+/// across the face as it was rendered.
+pub fn cube_face_uv(direction: Vec3) -> (usize, Vec2) {
+    let abs = direction.abs();
+    let face = if abs.x >= abs.y && abs.x >= abs.z {
+        if direction.x >= 0.0 {
+            0
+        } else {
+            1
+        }
+    } else if abs.y >= abs.z {
+        if direction.y >= 0.0 {
+            2
+        } else {
+            3
+        }
+    } else if direction.z >= 0.0 {
+        4
+    } else {
+        5
+    };
+    let (forward, up) = cube_face(face);
+    let right = up.cross(forward);
+    let depth = forward.dot(direction);
+    let ndc = Vec2::new(right.dot(direction), up.dot(direction)) / depth;
+    (face, ndc * 0.5 + 0.5)
+}
+
+/// Joint matrices of the skinned drawables of a frame, see `SKIN_PARAM`. Each
+/// takes a vertex from where its mesh was bound to the skeleton to where the
+/// joint has moved it, in the model space of the mesh.
+#[cfg_attr(feature = "std", derive(Pod, Zeroable))]
+#[derive(Copy, Clone)]
+#[repr(C)]
+pub struct JointMatrices {
+    pub matrices: [Mat4; MAX_JOINT_MATRICES],
 }
 
 #[cfg_attr(feature = "std", derive(Pod, Zeroable))]
@@ -24,13 +231,53 @@ pub struct Light {
 #[repr(C)]
 pub struct PushConstants {
     pub model_transform: Mat4,
+    /// Parameters of the drawable for effects such as dissolving or scrolling
+    /// UVs, what each one means is up to the shader. Along with the transform
+    /// these fill the 128 bytes of push constants every device supports.
+    pub params: [Vec4; MAX_SHADER_PARAMS],
 }
 
 impl PushConstants {
     pub fn new(model_transform: Mat4) -> Self {
-        Self { model_transform }
+        Self::with_params(model_transform, [Vec4::ZERO; MAX_SHADER_PARAMS])
+    }
+
+    pub fn with_params(model_transform: Mat4, params: [Vec4; MAX_SHADER_PARAMS]) -> Self {
+        Self {
+            model_transform,
+            params,
+        }
+    }
+
+    pub fn to_bytes(&self) -> &[u8] {
+        bytemuck::bytes_of(self)
     }
+}
+
+/// Push constants of the debug UI's shaders, which draw egui's meshes.
+#[cfg_attr(feature = "std", derive(Pod, Zeroable))]
+#[derive(Copy, Clone)]
+#[repr(C)]
+pub struct UiPushConstants {
+    /// Size of the screen in points, which the UI's vertices are in, from
+    /// the top left.
+    pub screen_size: Vec2,
+    /// Non-zero when the color attachment is sRGB, and encodes what's
+    /// written to it. Otherwise the fragment shader encodes its output.
+    pub srgb_target: u32,
+    pub _pad: u32,
+}
 
+impl UiPushConstants {
+    pub fn new(screen_size: Vec2, srgb_target: bool) -> Self {
+        Self {
+            screen_size,
+            srgb_target: srgb_target as u32,
+            _pad: 0,
+        }
+    }
+
+    #[cfg(feature = "std")]
     pub fn to_bytes(&self) -> &[u8] {
         bytemuck::bytes_of(self)
     }
@@ -41,36 +288,48 @@ impl PushConstants {
 #[repr(C)]
 pub struct UniformBuffer {
     pub proj: Mat4,
-    pub lights: [Light; MAX_LIGHTS],
+    /// Takes world space to the shadow map's clip space, see
+    /// `SHADOW_MAP_BINDING`.
+    pub light_space: Mat4,
     pub fog_color: Vec4,
     pub fog_start: f32,
     pub fog_end: f32,
-    pub _pad1: f32,
-    pub _pad2: f32,
+    /// Lights shaded, the first of `lights`.
+    pub light_count: u32,
+    pub _pad1: u32,
+    /// Last, so devices with a smaller uniform buffer range than the whole
+    /// buffer can bind the start of it, see `lights_within`.
+    pub lights: [Light; MAX_LIGHTS],
 }
 
 impl UniformBuffer {
     pub fn new() -> Self {
         Self::with_proj(Mat4::IDENTITY)
     }
+
+    /// A uniform buffer without lights.
     pub fn with_proj(proj: Mat4) -> Self {
         Self {
             proj,
-            lights: [
-                Light {
-                    color: Vec4::new(1.0, 1.0, 1.0, 1.0),
-                    pos: Vec4::new(10.0, 10.0, 10.0, 1.0),
-                },
-                Light {
-                    color: Vec4::new(1.0, 1.0, 1.0, 1.0),
-                    pos: Vec4::new(-10.0, 10.0, -10.0, 1.0),
-                },
-            ],
+            light_space: Mat4::IDENTITY,
             fog_color: Vec4::ONE,
             fog_start: 1.0,
             fog_end: 5.0,
-            _pad1: 0.0,
-            _pad2: 0.0,
+            light_count: 0,
+            _pad1: 0,
+            lights: [Light::NONE; MAX_LIGHTS],
+        }
+    }
+
+    /// Lights a uniform buffer holds when only its first `range` bytes can
+    /// be bound, up to `MAX_LIGHTS`.
+    pub const fn lights_within(range: usize) -> usize {
+        let header = core::mem::size_of::<Self>() - core::mem::size_of::<[Light; MAX_LIGHTS]>();
+        let lights = range.saturating_sub(header) / core::mem::size_of::<Light>();
+        if lights < MAX_LIGHTS {
+            lights
+        } else {
+            MAX_LIGHTS
         }
     }
 }
@@ -0,0 +1,36 @@
+//! Building the pipelines themselves needs a device, so this measures the
+//! work a pipeline rebuild does before it gets to one: reading and reflecting
+//! over each graphic's shaders.
+
+use std::path::PathBuf;
+
+use ash_renderer_system::Shader;
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+const SHADERS: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/../../../assets/shaders/spv");
+
+/// Shader pairs of the built-in graphics, each built into a pipeline.
+const PIPELINES: &[(&str, &str)] = &[
+    ("default_vertex.spv", "default_fragment.spv"),
+    ("debug_mesh_vertex.spv", "debug_mesh_fragment.spv"),
+];
+
+fn pipeline_rebuild_benchmark(c: &mut Criterion) {
+    let mut group = c.benchmark_group("pipeline_rebuild");
+    for &(vertex, fragment) in PIPELINES {
+        let vertex = PathBuf::from(SHADERS).join(vertex);
+        let fragment = PathBuf::from(SHADERS).join(fragment);
+        let name = vertex.file_stem().unwrap().to_string_lossy();
+        group.bench_function(format!("reflect_{name}"), |b| {
+            b.iter(|| {
+                let vertex = Shader::read_spv(black_box(vertex.clone())).unwrap();
+                let fragment = Shader::read_spv(black_box(fragment.clone())).unwrap();
+                (vertex, fragment)
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, pipeline_rebuild_benchmark);
+criterion_main!(benches);
@@ -0,0 +1,44 @@
+//! Screenshots, see `Presenter::capture_frame`. A frame read back from the
+//! GPU is encoded as a PNG and written on a worker thread, so the frame loop
+//! isn't held up by compression or the disk.
+
+use std::num::NonZeroUsize;
+use std::path::PathBuf;
+use std::thread;
+
+use core_executor::ThreadAffineExecutor;
+use render::readback::{Readback, ReadbackImage};
+
+/// Core screenshots are saved on, below audio occlusion's and the network IO
+/// thread's.
+fn capture_core() -> usize {
+    thread::available_parallelism()
+        .map_or(1, NonZeroUsize::get)
+        .saturating_sub(3)
+}
+
+#[derive(Default)]
+pub(crate) struct FrameCaptures {
+    // Started by the first capture, most runs never take one.
+    worker: Option<ThreadAffineExecutor>,
+}
+
+impl FrameCaptures {
+    /// Save `frame` to `path` once it's read back. The capture resolves to
+    /// `path` once it's written, and is cancelled if the worker is too far
+    /// behind to take it.
+    pub fn save(&mut self, frame: Readback<ReadbackImage>, path: PathBuf) -> Readback<PathBuf> {
+        let (delivery, capture) = render::readback::readback();
+        let worker = self
+            .worker
+            .get_or_insert_with(|| ThreadAffineExecutor::new(capture_core()));
+        worker.spawner.fire_or_drop(async move {
+            let saved = match frame.await {
+                Ok(image) => image.save_png(&path).map(|()| path),
+                Err(err) => Err(err),
+            };
+            delivery.complete(saved);
+        });
+        capture
+    }
+}
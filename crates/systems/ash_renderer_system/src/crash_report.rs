@@ -0,0 +1,116 @@
+//! Diagnostic reports written when Vulkan fails in a way the renderer doesn't
+//! expect, such as losing the device while presenting. Failures like these
+//! tend to be sporadic and specific to a driver, so the report gathers what's
+//! needed to report one: the last validation messages, how the device,
+//! swapchain and pipelines are set up, the graphics being tracked and how long
+//! recent frames took.
+
+use std::collections::VecDeque;
+use std::fmt::Display;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// Validation messages kept for a report.
+pub(crate) const RECENT_MESSAGES: usize = 32;
+
+/// Frame lengths kept for a report.
+pub(crate) const RECENT_FRAMES: usize = 120;
+
+/// The last `capacity` items pushed, oldest first.
+#[derive(Debug, Clone)]
+pub(crate) struct Recent<T> {
+    items: VecDeque<T>,
+    capacity: usize,
+}
+
+impl<T> Recent<T> {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            items: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    pub fn push(&mut self, item: T) {
+        if self.items.len() == self.capacity {
+            self.items.pop_front();
+        }
+        self.items.push_back(item);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.items.iter()
+    }
+}
+
+/// What was going on when Vulkan failed, see the module docs. Sections are
+/// written in order, each as a list of lines.
+#[derive(Debug, Default)]
+pub(crate) struct CrashReport {
+    pub error: String,
+    pub sections: Vec<(&'static str, Vec<String>)>,
+}
+
+impl CrashReport {
+    pub fn new(error: impl Display) -> Self {
+        Self {
+            error: error.to_string(),
+            sections: Vec::new(),
+        }
+    }
+
+    /// Add a section, listing `lines`.
+    pub fn section(
+        &mut self,
+        title: &'static str,
+        lines: impl IntoIterator<Item = impl Display>,
+    ) -> &mut Self {
+        let lines = lines.into_iter().map(|line| line.to_string()).collect();
+        self.sections.push((title, lines));
+        self
+    }
+
+    /// Write the report to a new file in `dir`, named for when it was
+    /// written, returning its path.
+    pub fn write(&self, dir: &Path, at: SystemTime) -> io::Result<PathBuf> {
+        fs::create_dir_all(dir)?;
+        let since_epoch = at
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default();
+        let path = dir.join(format!(
+            "gpu-crash-{}.{:03}.txt",
+            since_epoch.as_secs(),
+            since_epoch.subsec_millis()
+        ));
+        let mut writer = BufWriter::new(File::create(&path)?);
+        self.write_to(&mut writer)?;
+        writer.flush()?;
+        Ok(path)
+    }
+
+    pub fn write_to(&self, writer: &mut impl Write) -> io::Result<()> {
+        writeln!(writer, "error: {}", self.error)?;
+        for (title, lines) in self.sections.iter() {
+            writeln!(writer, "\n{title}:")?;
+            for line in lines {
+                writeln!(writer, "  {line}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Lines describing frame lengths, with the longest and average first.
+pub(crate) fn frame_time_lines(frames: &Recent<Duration>) -> Vec<String> {
+    let count = frames.iter().count();
+    if count == 0 {
+        return Vec::new();
+    }
+    let longest = frames.iter().max().copied().unwrap_or_default();
+    let average = frames.iter().sum::<Duration>() / count as u32;
+    let mut lines = vec![format!("longest {longest:?}, average {average:?}")];
+    lines.extend(frames.iter().map(|frame| format!("{frame:?}")));
+    lines
+}
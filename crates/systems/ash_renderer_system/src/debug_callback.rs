@@ -45,6 +45,16 @@ unsafe extern "system" fn vulkan_debug_callback(
                 &message_id_number.to_string(),
                 message.trim(),
             );
+            if let Ok(mut recent) = debug_struct.recent_messages.lock() {
+                recent.push(format!(
+                    "{:?} {:?} [{} ({})]: {}",
+                    message_severity,
+                    message_type,
+                    message_id_name,
+                    message_id_number,
+                    message.trim(),
+                ));
+            }
         }
         // We're done with this call, but Weak::from_raw takes ownership, and we don't
         // want to drop this pointer. We will be called many times with this pointer.
@@ -0,0 +1,189 @@
+//! Draws the lines in `World::debug_draw`. They change every frame, so rather
+//! than being uploaded as a graphic they're written into a vertex buffer that
+//! is kept between frames, and replaced with a larger one when it runs out of
+//! room. Each frame in flight has a batch of its own.
+
+use ash::vk;
+use gfx::{DebugMesh, GpuNeeds, Primitive, Vertex};
+use glam::{Mat4, Vec4};
+use logger::{debug, Logger};
+use shader_objects::PushConstants;
+use world::debug_draw::DebugLine;
+use world::Entity;
+
+use crate::device::{DeviceWrapper, GraphicsHandle, PUSH_CONSTANT_STAGES};
+use crate::pipeline_cache::PipelineCache;
+use crate::types::{Pipeline, RenderError, Shader};
+use crate::{Renderer, VulkanBase};
+
+// Vertices the first buffer has room for, two per line.
+const MIN_CAPACITY: usize = 1024;
+
+pub(crate) struct DebugLineBatch {
+    handle: GraphicsHandle,
+    pipeline: Pipeline,
+    capacity: usize,
+    len: usize,
+}
+
+impl DebugLineBatch {
+    fn new(
+        base: &VulkanBase,
+        descriptor_pool: vk::DescriptorPool,
+        pipeline_cache: &mut PipelineCache,
+        capacity: usize,
+        logger: &Logger,
+    ) -> Result<Self, RenderError> {
+        debug!(logger, "allocating debug lines for {capacity} vertices");
+        let w = DeviceWrapper::wrap(&base.device, logger);
+        let vertex_buffer = w.allocate_and_init_buffer(
+            vk::BufferUsageFlags::VERTEX_BUFFER,
+            base.device_memory_properties,
+            &vec![Vertex::pos(0.0, 0.0, 0.0); capacity],
+        )?;
+        // Lines are drawn straight from the vertex buffer, in order.
+        let index_buffer = w.allocate_and_init_buffer(
+            vk::BufferUsageFlags::INDEX_BUFFER,
+            base.device_memory_properties,
+            &(0..capacity as u32).collect::<Vec<_>>(),
+        )?;
+
+        // Debug meshes draw their vertices in the color stored in the normal.
+        let mesh = DebugMesh::line_list(Vec::new(), Vec::new(), Vec4::ONE);
+        let handle = GraphicsHandle::new(
+            None,
+            vertex_buffer,
+            index_buffer,
+            Shader::read_spv(mesh.vertex_shader_path().to_path_buf())?,
+            Shader::read_spv(mesh.fragment_shader_path().to_path_buf())?,
+            Primitive::LineList,
+            None,
+        );
+        // Not a graphic in the world, the entity is only used for logging.
+        let pipeline = Renderer::build_pipeline(
+            base,
+            descriptor_pool,
+            pipeline_cache,
+            Entity::DANGLING,
+            &handle,
+            logger,
+        )?;
+        Ok(Self {
+            handle,
+            pipeline,
+            capacity,
+            len: 0,
+        })
+    }
+
+    /// Write this frame's lines into `batch`, growing it if needed. Must only
+    /// be called once the frame it was last drawn in has completed.
+    pub fn prepare(
+        batch: &mut Option<Self>,
+        base: &VulkanBase,
+        descriptor_pool: vk::DescriptorPool,
+        pipeline_cache: &mut PipelineCache,
+        lines: &[DebugLine],
+        logger: &Logger,
+    ) -> Result<(), RenderError> {
+        if lines.is_empty() {
+            if let Some(batch) = batch.as_mut() {
+                batch.len = 0;
+            }
+            return Ok(());
+        }
+
+        let vertices = lines
+            .iter()
+            .flat_map(|line| {
+                let color = (line.color.x, line.color.y, line.color.z);
+                [line.start, line.end]
+                    .map(|pos| Vertex::new((pos.x, pos.y, pos.z, 1.0), (0.0, 0.0, 0.0), color))
+            })
+            .collect::<Vec<_>>();
+
+        if batch
+            .as_ref()
+            .map_or(true, |batch| batch.capacity < vertices.len())
+        {
+            if let Some(old) = batch.take() {
+                old.destroy(base, descriptor_pool, pipeline_cache);
+            }
+            let capacity = vertices.len().next_power_of_two().max(MIN_CAPACITY);
+            *batch = Some(Self::new(
+                base,
+                descriptor_pool,
+                pipeline_cache,
+                capacity,
+                logger,
+            )?);
+        }
+
+        let batch = batch.as_mut().unwrap();
+        DeviceWrapper::wrap(&base.device, logger)
+            .update_buffer(&mut batch.handle.vertex_buffer, &vertices)?;
+        batch.len = vertices.len();
+        Ok(())
+    }
+
+    /// Record drawing the lines in world space, with the view of the frame in
+    /// flight at `frame`.
+    pub fn draw(
+        &self,
+        w: &DeviceWrapper,
+        command_buffer: vk::CommandBuffer,
+        frame: usize,
+        viewports: &[vk::Viewport],
+        scissors: &[vk::Rect2D],
+    ) -> Result<(), RenderError> {
+        let pipeline = match (self.len, self.pipeline.shared.vk.as_ref()) {
+            (0, _) | (_, None) => return Ok(()),
+            (_, Some(pipeline)) => **pipeline,
+        };
+
+        w.cmd_bind_descriptor_sets(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            *self.pipeline.shared.layout,
+            0,
+            &[self.pipeline.descriptor_sets[frame]],
+            &[],
+        );
+        w.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, pipeline);
+        w.cmd_set_viewport(command_buffer, 0, viewports);
+        w.cmd_set_scissor(command_buffer, 0, scissors);
+        w.cmd_bind_vertex_buffers(
+            command_buffer,
+            0,
+            &[*self.handle.vertex_buffer.buffer],
+            &[0],
+        );
+        w.cmd_bind_index_buffer(
+            command_buffer,
+            *self.handle.index_buffer.buffer,
+            0,
+            vk::IndexType::UINT32,
+        );
+        let push_constants = PushConstants::new(Mat4::IDENTITY);
+        w.cmd_push_constants(
+            command_buffer,
+            *self.pipeline.shared.layout,
+            PUSH_CONSTANT_STAGES,
+            0,
+            push_constants.to_bytes(),
+        );
+        w.cmd_draw_indexed(command_buffer, self.len as u32, 1, 0, 0, 1);
+        Ok(())
+    }
+
+    pub fn destroy(
+        self,
+        base: &VulkanBase,
+        descriptor_pool: vk::DescriptorPool,
+        pipeline_cache: &mut PipelineCache,
+    ) {
+        Renderer::destroy_pipeline(base, descriptor_pool, pipeline_cache, self.pipeline);
+        self.handle.vertex_buffer.deallocate(&base.device);
+        self.handle.index_buffer.deallocate(&base.device);
+    }
+}
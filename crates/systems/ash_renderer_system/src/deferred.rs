@@ -0,0 +1,682 @@
+//! The experimental deferred path, see `render::render_path`. Drawables shaded
+//! by the default fragment shader are drawn into a G-buffer of their albedo,
+//! normals, world positions and reflectivity, then shaded at once by a pass
+//! covering the screen at the start of the scene pass. It reads the same
+//! uniforms, clustered lights, reflection probes and shadow map the forward
+//! path shades with, and writes the depth of what it shades, so everything
+//! drawn forward after it, like the skybox and debug meshes, is tested
+//! against it.
+//!
+//! Each shared pipeline drawn deferred gets a variant drawing into the
+//! G-buffer, built the first time it's needed, with the same layout and
+//! descriptor sets. The G-buffer is shared by every frame in flight, like the
+//! scaled target, and recreated when the scene pass changes size.
+
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use ash::vk;
+use logger::{debug, warn, Logger};
+use shader_objects::{GBUFFER_NORMAL_BINDING, GBUFFER_POSITION_BINDING};
+
+use crate::device::DeviceWrapper;
+use crate::resource::Owned;
+use crate::scaled_target::TargetImage;
+use crate::secondary::{self, DrawCall};
+use crate::types::{
+    RenderError, Shader, ShaderStage, ShaderStages, SharedPipeline, VertexInputAssembly,
+};
+use crate::ui::create_sampler;
+use crate::VulkanBase;
+
+/// File name of the fragment shader graphics have to be drawn with to be
+/// drawn deferred, whose shading the lighting pass reproduces.
+pub(crate) const DEFERRABLE_FRAGMENT_SHADER: &str = "default_fragment.spv";
+
+const GBUFFER_FRAGMENT_SHADER: &str = "assets/shaders/spv/gbuffer_fragment.spv";
+const LIGHTING_VERTEX_SHADER: &str = "assets/shaders/spv/fullscreen_vertex.spv";
+const LIGHTING_FRAGMENT_SHADER: &str = "assets/shaders/spv/deferred_lighting_fragment.spv";
+
+/// Formats of the G-buffer's albedo, normals, and world positions with
+/// reflectivity in `w`, in the order they're attached.
+const GBUFFER_FORMATS: [vk::Format; 3] = [
+    vk::Format::R8G8B8A8_UNORM,
+    vk::Format::R16G16B16A16_SFLOAT,
+    vk::Format::R32G32B32A32_SFLOAT,
+];
+const DEPTH_FORMAT: vk::Format = vk::Format::D16_UNORM;
+
+/// The G-buffer of a scene pass of `extent`.
+struct GBuffer {
+    extent: vk::Extent2D,
+    framebuffer: Owned<vk::Framebuffer>,
+    /// Albedo, normals and positions, see `GBUFFER_FORMATS`.
+    colors: Vec<TargetImage>,
+    depth: TargetImage,
+}
+
+impl GBuffer {
+    fn new(
+        base: &VulkanBase,
+        render_pass: vk::RenderPass,
+        extent: vk::Extent2D,
+    ) -> Result<Self, RenderError> {
+        let device = &base.device;
+        let mut colors = Vec::with_capacity(GBUFFER_FORMATS.len());
+        let destroy_colors = |colors: &[TargetImage]| {
+            for color in colors {
+                unsafe { color.destroy(device) };
+            }
+        };
+        for format in GBUFFER_FORMATS {
+            match TargetImage::new(
+                base,
+                extent,
+                1,
+                format,
+                vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+                vk::ImageAspectFlags::COLOR,
+            ) {
+                Ok(color) => colors.push(color),
+                Err(err) => {
+                    destroy_colors(&colors);
+                    return Err(err);
+                }
+            }
+        }
+        let depth = match TargetImage::new(
+            base,
+            extent,
+            1,
+            DEPTH_FORMAT,
+            vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+            vk::ImageAspectFlags::DEPTH,
+        ) {
+            Ok(depth) => depth,
+            Err(err) => {
+                destroy_colors(&colors);
+                return Err(err);
+            }
+        };
+
+        let attachments = colors
+            .iter()
+            .map(|color| color.view)
+            .chain([depth.view])
+            .collect::<Vec<_>>();
+        let framebuffer_info = vk::FramebufferCreateInfo::builder()
+            .render_pass(render_pass)
+            .attachments(&attachments)
+            .width(extent.width)
+            .height(extent.height)
+            .layers(1);
+        match unsafe { device.create_framebuffer(&framebuffer_info, None) } {
+            Ok(framebuffer) => Ok(Self {
+                extent,
+                framebuffer: Owned::new(framebuffer),
+                colors,
+                depth,
+            }),
+            Err(err) => {
+                destroy_colors(&colors);
+                unsafe { depth.destroy(device) };
+                Err(RenderError::VkResultToDo(err))
+            }
+        }
+    }
+
+    fn destroy(self, device: &ash::Device) {
+        self.framebuffer.destroy(device);
+        unsafe {
+            for color in self.colors.iter() {
+                color.destroy(device);
+            }
+            self.depth.destroy(device);
+        }
+    }
+}
+
+pub(crate) struct DeferredPass {
+    /// The pass G-buffer variants of shared pipelines draw in, for
+    /// G-buffers of any size.
+    gbuffer_pass: Owned<vk::RenderPass>,
+    /// The fragment shader G-buffer variants are built with, in place of the
+    /// default one.
+    gbuffer_stages: ShaderStages,
+    /// Created for the first scene pass drawn deferred.
+    gbuffer: Option<GBuffer>,
+    lighting_pipeline: Owned<vk::Pipeline>,
+    lighting_layout: Owned<vk::PipelineLayout>,
+    lighting_set_layout: Owned<vk::DescriptorSetLayout>,
+    lighting_stages: ShaderStages,
+    descriptor_pool: vk::DescriptorPool,
+    /// The lighting pass's descriptor set for each frame in flight, bound to
+    /// the frame's buffers and rewritten whenever the G-buffer is recreated.
+    descriptor_sets: Vec<vk::DescriptorSet>,
+    /// Samples the G-buffer a pixel at a time.
+    sampler: Owned<vk::Sampler>,
+    logger: Logger,
+}
+
+impl DeferredPass {
+    pub fn new(base: &VulkanBase, logger: &Logger) -> Result<Self, RenderError> {
+        debug!(logger, "creating the deferred pass");
+        let device = &base.device;
+        let gbuffer_fragment = Arc::new(Shader::read_spv(PathBuf::from(GBUFFER_FRAGMENT_SHADER))?);
+        let lighting_vertex = Arc::new(Shader::read_spv(PathBuf::from(LIGHTING_VERTEX_SHADER))?);
+        let lighting_fragment =
+            Arc::new(Shader::read_spv(PathBuf::from(LIGHTING_FRAGMENT_SHADER))?);
+
+        // Each step destroys what the steps before it created if it fails.
+        let gbuffer_pass = Owned::new(create_gbuffer_pass(device)?);
+        let mut gbuffer_stages = ShaderStages::new();
+        if let Err(err) =
+            gbuffer_stages.add_shader(device, gbuffer_fragment, vk::ShaderStageFlags::FRAGMENT)
+        {
+            gbuffer_stages.deallocate(device);
+            gbuffer_pass.destroy(device);
+            return Err(err);
+        }
+        let destroy_gbuffer_pass = || {
+            gbuffer_stages.deallocate(device);
+            gbuffer_pass.destroy(device);
+        };
+
+        let lighting_set_layout =
+            match base.create_descriptor_set_layout(&lighting_vertex, &lighting_fragment) {
+                Ok(layout) => Owned::new(layout),
+                Err(err) => {
+                    destroy_gbuffer_pass();
+                    return Err(err);
+                }
+            };
+        // The lighting shaders don't read any push constants.
+        let set_layouts = [*lighting_set_layout];
+        let layout_info = vk::PipelineLayoutCreateInfo::builder().set_layouts(&set_layouts);
+        let lighting_layout = match unsafe { device.create_pipeline_layout(&layout_info, None) } {
+            Ok(layout) => Owned::new(layout),
+            Err(err) => {
+                lighting_set_layout.destroy(device);
+                destroy_gbuffer_pass();
+                return Err(RenderError::VkResultToDo(err));
+            }
+        };
+        let destroy_layouts = || {
+            lighting_layout.destroy(device);
+            lighting_set_layout.destroy(device);
+            destroy_gbuffer_pass();
+        };
+
+        let mut lighting_stages = ShaderStages::new();
+        let lighting_pipeline = lighting_stages
+            .add_shader(device, lighting_vertex, vk::ShaderStageFlags::VERTEX)
+            .and_then(|()| {
+                lighting_stages.add_shader(
+                    device,
+                    lighting_fragment,
+                    vk::ShaderStageFlags::FRAGMENT,
+                )
+            })
+            .and_then(|()| create_lighting_pipeline(base, &lighting_stages, *lighting_layout));
+        let lighting_pipeline = match lighting_pipeline {
+            Ok(pipeline) => Owned::new(pipeline),
+            Err(err) => {
+                lighting_stages.deallocate(device);
+                destroy_layouts();
+                return Err(err);
+            }
+        };
+
+        // A set for each frame in flight, binding its uniforms, lights,
+        // probes and shadow map, and the G-buffer.
+        let frames = base.frames.len() as u32;
+        let pool_sizes = [
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::UNIFORM_BUFFER,
+                descriptor_count: 2 * frames,
+            },
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::STORAGE_BUFFER,
+                descriptor_count: frames,
+            },
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                descriptor_count: (GBUFFER_FORMATS.len() as u32 + 2) * frames,
+            },
+        ];
+        let pool_info = vk::DescriptorPoolCreateInfo::builder()
+            .pool_sizes(&pool_sizes)
+            .max_sets(frames);
+        let resources = unsafe { device.create_descriptor_pool(&pool_info, None) }
+            .map_err(RenderError::VkResultToDo)
+            .and_then(|descriptor_pool| {
+                let layouts = vec![*lighting_set_layout; frames as usize];
+                let sets = base
+                    .allocate_descriptor_sets(descriptor_pool, &layouts)
+                    .and_then(|sets| {
+                        create_sampler(device, vk::Filter::NEAREST).map(|sampler| (sets, sampler))
+                    });
+                match sets {
+                    Ok((sets, sampler)) => Ok((descriptor_pool, sets, sampler)),
+                    Err(err) => {
+                        unsafe { device.destroy_descriptor_pool(descriptor_pool, None) };
+                        Err(err)
+                    }
+                }
+            });
+        let (descriptor_pool, descriptor_sets, sampler) = match resources {
+            Ok(resources) => resources,
+            Err(err) => {
+                lighting_pipeline.destroy(device);
+                lighting_stages.deallocate(device);
+                destroy_layouts();
+                return Err(err);
+            }
+        };
+
+        Ok(Self {
+            gbuffer_pass,
+            gbuffer_stages,
+            gbuffer: None,
+            lighting_pipeline,
+            lighting_layout,
+            lighting_set_layout,
+            lighting_stages,
+            descriptor_pool,
+            descriptor_sets,
+            sampler,
+            logger: logger.sub("deferred"),
+        })
+    }
+
+    /// Create the G-buffer for a scene pass of `extent`, if it isn't that size
+    /// already. The old one is destroyed once every frame in flight, which may
+    /// be using it, completes.
+    pub fn prepare(
+        &mut self,
+        base: &mut VulkanBase,
+        extent: vk::Extent2D,
+    ) -> Result<(), RenderError> {
+        if self.gbuffer.as_ref().map(|gbuffer| gbuffer.extent) == Some(extent) {
+            return Ok(());
+        }
+        if let Some(gbuffer) = self.gbuffer.take() {
+            base.frames.wait_all(&base.device)?;
+            gbuffer.destroy(&base.device);
+        }
+        debug!(
+            self.logger,
+            "creating a {}x{} G-buffer", extent.width, extent.height
+        );
+        let gbuffer = GBuffer::new(base, *self.gbuffer_pass, extent)?;
+
+        let probes = base
+            .reflection_probes
+            .as_ref()
+            .expect("created with the renderer");
+        let image_info = |color: &TargetImage| {
+            [*vk::DescriptorImageInfo::builder()
+                .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                .image_view(color.view)
+                .sampler(*self.sampler)]
+        };
+        let normals = image_info(&gbuffer.colors[1]);
+        let positions = image_info(&gbuffer.colors[2]);
+        for (descriptor_set, frame) in self.descriptor_sets.iter().zip(base.frames.iter()) {
+            // The albedo is bound where the default shaders bind their diffuse
+            // map.
+            VulkanBase::update_descriptor_set(
+                &base.device,
+                *descriptor_set,
+                &frame.uniform,
+                base.max_uniform_buffer_range,
+                Some(&frame.clustered_lights),
+                Some((&frame.reflection_probes, probes)),
+                None,
+                base.shadow_map.as_ref(),
+                Some(gbuffer.colors[0].view),
+                Some(*self.sampler),
+            );
+            let writes = [
+                *vk::WriteDescriptorSet::builder()
+                    .dst_set(*descriptor_set)
+                    .dst_binding(GBUFFER_NORMAL_BINDING)
+                    .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                    .image_info(&normals),
+                *vk::WriteDescriptorSet::builder()
+                    .dst_set(*descriptor_set)
+                    .dst_binding(GBUFFER_POSITION_BINDING)
+                    .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                    .image_info(&positions),
+            ];
+            unsafe { base.device.update_descriptor_sets(&writes, &[]) };
+        }
+        self.gbuffer = Some(gbuffer);
+        Ok(())
+    }
+
+    /// The variant of `shared` drawing into the G-buffer, built the first time
+    /// it's asked for. None when graphics drawn with `shared` are drawn
+    /// forward instead.
+    pub fn gbuffer_pipeline(
+        &self,
+        base: &VulkanBase,
+        shared: &SharedPipeline,
+    ) -> Option<vk::Pipeline> {
+        if !shared.deferrable {
+            return None;
+        }
+        shared
+            .gbuffer
+            .get_or_init(|| {
+                match create_gbuffer_pipeline(
+                    base,
+                    shared,
+                    &self.gbuffer_stages.shader_stage_defs[0],
+                    *self.gbuffer_pass,
+                ) {
+                    Ok(pipeline) => Some(Owned::new(pipeline)),
+                    Err(err) => {
+                        warn!(self.logger, "drawing forward, no G-buffer pipeline: {err}");
+                        None
+                    }
+                }
+            })
+            .as_deref()
+            .copied()
+    }
+
+    /// Record drawing `draws`, drawn with G-buffer variants, into the
+    /// G-buffer, before the scene pass begins.
+    pub fn cmd_fill_gbuffer(
+        &self,
+        w: &DeviceWrapper,
+        command_buffer: vk::CommandBuffer,
+        draws: &[DrawCall],
+        viewports: &[vk::Viewport],
+        scissors: &[vk::Rect2D],
+    ) {
+        let gbuffer = match self.gbuffer.as_ref() {
+            Some(gbuffer) => gbuffer,
+            None => return,
+        };
+        let clear_color = |float32: [f32; 4]| vk::ClearValue {
+            color: vk::ClearColorValue { float32 },
+        };
+        let clear_values = [
+            clear_color([0.0; 4]),
+            clear_color([0.0; 4]),
+            // Where nothing was drawn, see `gbuffer_fragment`.
+            clear_color([0.0, 0.0, 0.0, -1.0]),
+            vk::ClearValue {
+                depth_stencil: vk::ClearDepthStencilValue {
+                    depth: 1.0,
+                    stencil: 0,
+                },
+            },
+        ];
+        let render_pass_begin_info = vk::RenderPassBeginInfo::builder()
+            .render_pass(*self.gbuffer_pass)
+            .framebuffer(*gbuffer.framebuffer)
+            .render_area(gbuffer.extent.into())
+            .clear_values(&clear_values);
+        w.cmd_begin_render_pass(
+            command_buffer,
+            &render_pass_begin_info,
+            vk::SubpassContents::INLINE,
+        );
+        secondary::record_draws(w, command_buffer, draws, viewports, scissors);
+        w.cmd_end_render_pass(command_buffer);
+    }
+
+    /// Record shading the G-buffer, at the start of the scene pass of the
+    /// frame in flight at `frame`.
+    pub fn cmd_light(&self, w: &DeviceWrapper, command_buffer: vk::CommandBuffer, frame: usize) {
+        let gbuffer = match self.gbuffer.as_ref() {
+            Some(gbuffer) => gbuffer,
+            None => return,
+        };
+        // Pixels outside the scene's viewport were cleared as empty, so the
+        // whole pass is covered and they're left alone.
+        let area = vk::Rect2D::from(gbuffer.extent);
+        w.cmd_bind_pipeline(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            *self.lighting_pipeline,
+        );
+        w.cmd_bind_descriptor_sets(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            *self.lighting_layout,
+            0,
+            &[self.descriptor_sets[frame]],
+            &[],
+        );
+        w.cmd_set_viewport(command_buffer, 0, &VulkanBase::viewports_of(area));
+        w.cmd_set_scissor(command_buffer, 0, &VulkanBase::scissors_of(area));
+        w.cmd_draw(command_buffer, 3, 1, 0, 0);
+    }
+
+    /// Destroy the pass, once no frame in flight uses it. G-buffer variants
+    /// are destroyed with their shared pipelines.
+    pub fn destroy(self, device: &ash::Device) {
+        if let Some(gbuffer) = self.gbuffer {
+            gbuffer.destroy(device);
+        }
+        self.sampler.destroy(device);
+        // Frees the descriptor sets with it.
+        unsafe { device.destroy_descriptor_pool(self.descriptor_pool, None) };
+        self.lighting_pipeline.destroy(device);
+        self.lighting_stages.deallocate(device);
+        self.lighting_layout.destroy(device);
+        self.lighting_set_layout.destroy(device);
+        self.gbuffer_stages.deallocate(device);
+        self.gbuffer_pass.destroy(device);
+    }
+}
+
+/// Create the G-buffer's render pass, leaving its colors ready for the
+/// lighting pass to read.
+fn create_gbuffer_pass(device: &ash::Device) -> Result<vk::RenderPass, RenderError> {
+    let attachments = GBUFFER_FORMATS
+        .iter()
+        .map(|format| {
+            *vk::AttachmentDescription::builder()
+                .format(*format)
+                .samples(vk::SampleCountFlags::TYPE_1)
+                .load_op(vk::AttachmentLoadOp::CLEAR)
+                .store_op(vk::AttachmentStoreOp::STORE)
+                .final_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+        })
+        .chain([*vk::AttachmentDescription::builder()
+            .format(DEPTH_FORMAT)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .load_op(vk::AttachmentLoadOp::CLEAR)
+            .store_op(vk::AttachmentStoreOp::DONT_CARE)
+            .final_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)])
+        .collect::<Vec<_>>();
+    let color_refs = (0..GBUFFER_FORMATS.len() as u32)
+        .map(|attachment| vk::AttachmentReference {
+            attachment,
+            layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+        })
+        .collect::<Vec<_>>();
+    let depth_ref = vk::AttachmentReference {
+        attachment: GBUFFER_FORMATS.len() as u32,
+        layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+    };
+    // The G-buffer is shared by every frame in flight, so a frame waits for
+    // the one before it to be done shading from it, and the lighting pass
+    // waits for it to be written.
+    let dependencies = [
+        *vk::SubpassDependency::builder()
+            .src_subpass(vk::SUBPASS_EXTERNAL)
+            .dst_subpass(0)
+            .src_stage_mask(
+                vk::PipelineStageFlags::FRAGMENT_SHADER
+                    | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+            )
+            .src_access_mask(vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE)
+            .dst_stage_mask(
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                    | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS,
+            )
+            .dst_access_mask(
+                vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+                    | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
+                    | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            ),
+        *vk::SubpassDependency::builder()
+            .src_subpass(0)
+            .dst_subpass(vk::SUBPASS_EXTERNAL)
+            .src_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+            .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+            .dst_stage_mask(vk::PipelineStageFlags::FRAGMENT_SHADER)
+            .dst_access_mask(vk::AccessFlags::SHADER_READ),
+    ];
+    let subpasses = [*vk::SubpassDescription::builder()
+        .color_attachments(&color_refs)
+        .depth_stencil_attachment(&depth_ref)
+        .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)];
+    let render_pass_info = vk::RenderPassCreateInfo::builder()
+        .attachments(&attachments)
+        .subpasses(&subpasses)
+        .dependencies(&dependencies);
+    unsafe { device.create_render_pass(&render_pass_info, None) }.map_err(RenderError::VkResultToDo)
+}
+
+/// Create the variant of `shared` drawing into the G-buffer, with its vertex
+/// shader and `fragment`, testing and writing depth as the scene does.
+fn create_gbuffer_pipeline(
+    base: &VulkanBase,
+    shared: &SharedPipeline,
+    fragment: &ShaderStage,
+    render_pass: vk::RenderPass,
+) -> Result<vk::Pipeline, RenderError> {
+    // The vertex shader's stage is added first, see `finish_shared_pipeline`.
+    let shader_stage_create_infos = [
+        shared.shader_stages.shader_stage_defs[0].create_info(),
+        fragment.create_info(),
+    ];
+    let viewport_state_info = vk::PipelineViewportStateCreateInfo::builder()
+        .scissors(&shared.scissors)
+        .viewports(&shared.viewports);
+    let rasterization_info = vk::PipelineRasterizationStateCreateInfo {
+        front_face: vk::FrontFace::COUNTER_CLOCKWISE,
+        line_width: 1.0,
+        polygon_mode: shared.polygon_mode,
+        ..Default::default()
+    };
+    let multisample_state_info = vk::PipelineMultisampleStateCreateInfo::builder()
+        .rasterization_samples(vk::SampleCountFlags::TYPE_1);
+    let depth_state_info = vk::PipelineDepthStencilStateCreateInfo {
+        depth_test_enable: 1,
+        depth_write_enable: 1,
+        depth_compare_op: vk::CompareOp::LESS_OR_EQUAL,
+        max_depth_bounds: 1.0,
+        ..Default::default()
+    };
+    let color_blend_attachment_states = [vk::PipelineColorBlendAttachmentState {
+        blend_enable: 0,
+        color_write_mask: vk::ColorComponentFlags::RGBA,
+        ..Default::default()
+    }; GBUFFER_FORMATS.len()];
+    let color_blend_state = vk::PipelineColorBlendStateCreateInfo::builder()
+        .attachments(&color_blend_attachment_states);
+    let dynamic_state = [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+    let dynamic_state_info =
+        vk::PipelineDynamicStateCreateInfo::builder().dynamic_states(&dynamic_state);
+    let vertex_input_state_info = shared.vertex_input_assembly.input_state_info();
+    let vertex_input_assembly_state_info = shared.vertex_input_assembly.assembly_state_info();
+    let graphics_pipeline_info = vk::GraphicsPipelineCreateInfo::builder()
+        .stages(&shader_stage_create_infos)
+        .vertex_input_state(&vertex_input_state_info)
+        .input_assembly_state(&vertex_input_assembly_state_info)
+        .viewport_state(&viewport_state_info)
+        .rasterization_state(&rasterization_info)
+        .multisample_state(&multisample_state_info)
+        .depth_stencil_state(&depth_state_info)
+        .color_blend_state(&color_blend_state)
+        .dynamic_state(&dynamic_state_info)
+        .layout(*shared.layout)
+        .render_pass(render_pass);
+    let pipeline = unsafe {
+        base.device.create_graphics_pipelines(
+            vk::PipelineCache::null(),
+            &[*graphics_pipeline_info],
+            None,
+        )
+    }
+    .map_err(|(pipeline, result)| RenderError::FailedToCreatePipeline(pipeline, result))?[0];
+    Ok(pipeline)
+}
+
+/// Create the lighting pass's pipeline, drawing a triangle over the whole
+/// scene pass without any vertex buffers, and writing the depth the fragment
+/// shader gives it.
+fn create_lighting_pipeline(
+    base: &VulkanBase,
+    shader_stages: &ShaderStages,
+    layout: vk::PipelineLayout,
+) -> Result<vk::Pipeline, RenderError> {
+    let shader_stage_create_infos = shader_stages
+        .shader_stage_defs
+        .iter()
+        .map(ShaderStage::create_info)
+        .collect::<Vec<_>>();
+    let vertex_input_assembly = VertexInputAssembly::new(vk::PrimitiveTopology::TRIANGLE_LIST);
+    let vertex_input_state_info = vertex_input_assembly.input_state_info();
+    let vertex_input_assembly_state_info = vertex_input_assembly.assembly_state_info();
+
+    // Viewport and scissor are set as the G-buffer is shaded.
+    let viewport_state_info = vk::PipelineViewportStateCreateInfo::builder()
+        .viewport_count(1)
+        .scissor_count(1);
+    let rasterization_info = vk::PipelineRasterizationStateCreateInfo {
+        cull_mode: vk::CullModeFlags::NONE,
+        line_width: 1.0,
+        polygon_mode: vk::PolygonMode::FILL,
+        ..Default::default()
+    };
+    let multisample_state_info = vk::PipelineMultisampleStateCreateInfo::builder()
+        .rasterization_samples(vk::SampleCountFlags::TYPE_1);
+    let depth_state_info = vk::PipelineDepthStencilStateCreateInfo {
+        depth_test_enable: 1,
+        depth_write_enable: 1,
+        depth_compare_op: vk::CompareOp::ALWAYS,
+        max_depth_bounds: 1.0,
+        ..Default::default()
+    };
+    let color_blend_attachment_states = [vk::PipelineColorBlendAttachmentState {
+        blend_enable: 0,
+        color_write_mask: vk::ColorComponentFlags::RGBA,
+        ..Default::default()
+    }];
+    let color_blend_state = vk::PipelineColorBlendStateCreateInfo::builder()
+        .attachments(&color_blend_attachment_states);
+    let dynamic_state = [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+    let dynamic_state_info =
+        vk::PipelineDynamicStateCreateInfo::builder().dynamic_states(&dynamic_state);
+    let graphics_pipeline_info = vk::GraphicsPipelineCreateInfo::builder()
+        .stages(&shader_stage_create_infos)
+        .vertex_input_state(&vertex_input_state_info)
+        .input_assembly_state(&vertex_input_assembly_state_info)
+        .viewport_state(&viewport_state_info)
+        .rasterization_state(&rasterization_info)
+        .multisample_state(&multisample_state_info)
+        .depth_stencil_state(&depth_state_info)
+        .color_blend_state(&color_blend_state)
+        .dynamic_state(&dynamic_state_info)
+        .layout(layout)
+        // The scaled target's render pass is compatible with the window's.
+        .render_pass(*base.render_pass);
+    let pipeline = unsafe {
+        base.device.create_graphics_pipelines(
+            vk::PipelineCache::null(),
+            &[*graphics_pipeline_info],
+            None,
+        )
+    }
+    .map_err(|(pipeline, result)| RenderError::FailedToCreatePipeline(pipeline, result))?[0];
+    Ok(pipeline)
+}
@@ -1,14 +1,20 @@
 use std::mem::align_of;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex, MutexGuard};
 
 use ash::util::Align;
 use ash::vk;
 use gfx::{Image, Primitive};
 use logger::Logger;
+use render::occlusion::Aabb;
 
 use crate::types::{BufferAndMemory, RenderError, Shader, Texture};
 use crate::VulkanBase;
 
+/// Stages that can read `PushConstants`, the fragment stage for its `params`.
+pub(crate) const PUSH_CONSTANT_STAGES: vk::ShaderStageFlags = vk::ShaderStageFlags::from_raw(
+    vk::ShaderStageFlags::VERTEX.as_raw() | vk::ShaderStageFlags::FRAGMENT.as_raw(),
+);
+
 /// Newtype over `ash::Device` allowing our own methods to be implemented.
 /// TODO: decide on what parts of this API should be implemented in the plugin
 /// vs in the rendering module
@@ -32,7 +38,7 @@ impl<'a> DeviceWrapper<'a> {
         desc_set_layouts: &[vk::DescriptorSetLayout],
     ) -> Result<vk::PipelineLayout, RenderError> {
         let push_constant_ranges = [*vk::PushConstantRange::builder()
-            .stage_flags(vk::ShaderStageFlags::VERTEX)
+            .stage_flags(PUSH_CONSTANT_STAGES)
             .offset(0)
             .size(push_constants_len)];
 
@@ -63,16 +69,21 @@ impl<'a> DeviceWrapper<'a> {
         &self,
         memory_properties: vk::PhysicalDeviceMemoryProperties,
         image_extent: vk::Extent2D,
+        mip_levels: u32,
     ) -> Result<Texture, RenderError> {
+        // Mip levels are blitted from the one above, so the image is a
+        // transfer source as well.
         let texture_create_info = vk::ImageCreateInfo {
             image_type: vk::ImageType::TYPE_2D,
             format: vk::Format::R8G8B8A8_UNORM,
             extent: image_extent.into(),
-            mip_levels: 1,
+            mip_levels,
             array_layers: 1,
             samples: vk::SampleCountFlags::TYPE_1,
             tiling: vk::ImageTiling::OPTIMAL,
-            usage: vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED,
+            usage: vk::ImageUsageFlags::TRANSFER_SRC
+                | vk::ImageUsageFlags::TRANSFER_DST
+                | vk::ImageUsageFlags::SAMPLED,
             sharing_mode: vk::SharingMode::EXCLUSIVE,
             ..Default::default()
         };
@@ -104,8 +115,10 @@ impl<'a> DeviceWrapper<'a> {
 
         Texture::create(
             texture_create_info.format,
+            mip_levels,
             texture_image,
             texture_memory,
+            texture_allocate_info.allocation_size,
             self.device,
         )
     }
@@ -121,7 +134,7 @@ impl<'a> DeviceWrapper<'a> {
     {
         let ptr = unsafe {
             self.device.map_memory(
-                buffer.memory,
+                *buffer.memory,
                 0,
                 buffer.allocation_size,
                 vk::MemoryMapFlags::empty(),
@@ -130,7 +143,7 @@ impl<'a> DeviceWrapper<'a> {
         .map_err(RenderError::VkResultToDo)?;
         let mut slice = unsafe { Align::new(ptr, align_of::<T>() as u64, buffer.allocation_size) };
         slice.copy_from_slice(data);
-        unsafe { self.device.unmap_memory(buffer.memory) };
+        unsafe { self.device.unmap_memory(*buffer.memory) };
         Ok(())
     }
 
@@ -167,12 +180,17 @@ impl<'a> DeviceWrapper<'a> {
         let buffer_memory = unsafe { self.device.allocate_memory(&allocate_info, None) }
             .map_err(RenderError::VkResultToDo)?;
         let mut buffer = BufferAndMemory::new(buffer, buffer_memory, data.len(), allocation_size);
-        self.update_buffer(&mut buffer, data)?;
-        unsafe {
-            self.device
-                .bind_buffer_memory(buffer.buffer, buffer.memory, 0)
+        let bound = self.update_buffer(&mut buffer, data).and_then(|()| {
+            unsafe {
+                self.device
+                    .bind_buffer_memory(*buffer.buffer, *buffer.memory, 0)
+            }
+            .map_err(RenderError::VkResultToDo)
+        });
+        if let Err(err) = bound {
+            buffer.deallocate(&self.device);
+            return Err(err);
         }
-        .map_err(RenderError::VkResultToDo)?;
         Ok(buffer)
     }
 
@@ -218,8 +236,8 @@ impl<'a> DeviceWrapper<'a> {
         unsafe {
             self.device.cmd_copy_buffer_to_image(
                 command_buffer,
-                src_image.buffer,
-                dest_texture.image,
+                *src_image.buffer,
+                *dest_texture.image,
                 vk::ImageLayout::TRANSFER_DST_OPTIMAL,
                 &buffer_copy_regions,
             )
@@ -243,18 +261,32 @@ impl<'a> DeviceWrapper<'a> {
             .map_err(RenderError::EndCommandBuffer)
     }
 
-    /// Insert a barrier end.
+    /// Insert a barrier end, making a mip level that was copied or blitted
+    /// to (`TRANSFER_DST_OPTIMAL`), or blitted from (`TRANSFER_SRC_OPTIMAL`),
+    /// readable by fragment shaders.
     // just a few flags are different between * and *_end versions, but need to
     // better understand the ... <half-written note>
-    pub fn cmd_pipeline_barrier_end(&self, image: vk::Image, command_buffer: vk::CommandBuffer) {
+    pub fn cmd_pipeline_barrier_end(
+        &self,
+        image: vk::Image,
+        mip_level: u32,
+        old_layout: vk::ImageLayout,
+        command_buffer: vk::CommandBuffer,
+    ) {
+        let src_access_mask = if old_layout == vk::ImageLayout::TRANSFER_SRC_OPTIMAL {
+            vk::AccessFlags::TRANSFER_READ
+        } else {
+            vk::AccessFlags::TRANSFER_WRITE
+        };
         let texture_barrier_end = vk::ImageMemoryBarrier {
-            src_access_mask: vk::AccessFlags::TRANSFER_WRITE,
+            src_access_mask,
             dst_access_mask: vk::AccessFlags::SHADER_READ,
-            old_layout: vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            old_layout,
             new_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
             image,
             subresource_range: vk::ImageSubresourceRange {
                 aspect_mask: vk::ImageAspectFlags::COLOR,
+                base_mip_level: mip_level,
                 level_count: 1,
                 layer_count: 1,
                 ..Default::default()
@@ -274,15 +306,20 @@ impl<'a> DeviceWrapper<'a> {
         };
     }
 
-    /// Insert a barrier start.
-    pub fn cmd_pipeline_barrier_start(&self, image: vk::Image, command_buffer: vk::CommandBuffer) {
+    /// Insert a barrier start, readying every mip level to be written.
+    pub fn cmd_pipeline_barrier_start(
+        &self,
+        image: vk::Image,
+        mip_levels: u32,
+        command_buffer: vk::CommandBuffer,
+    ) {
         let texture_barrier = vk::ImageMemoryBarrier {
             dst_access_mask: vk::AccessFlags::TRANSFER_WRITE,
             new_layout: vk::ImageLayout::TRANSFER_DST_OPTIMAL,
             image,
             subresource_range: vk::ImageSubresourceRange {
                 aspect_mask: vk::ImageAspectFlags::COLOR,
-                level_count: 1,
+                level_count: mip_levels,
                 layer_count: 1,
                 ..Default::default()
             },
@@ -301,6 +338,96 @@ impl<'a> DeviceWrapper<'a> {
         }
     }
 
+    /// Fill in mip levels below the first, each blitted from the one above,
+    /// and leave them all readable by fragment shaders. Linear blits are
+    /// always supported for `R8G8B8A8_UNORM` textures.
+    pub fn cmd_generate_mipmaps(
+        &self,
+        image: vk::Image,
+        image_extent: vk::Extent2D,
+        mip_levels: u32,
+        command_buffer: vk::CommandBuffer,
+    ) {
+        let subresource = |mip_level| vk::ImageSubresourceLayers {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            mip_level,
+            base_array_layer: 0,
+            layer_count: 1,
+        };
+        let (mut width, mut height) = (image_extent.width as i32, image_extent.height as i32);
+        for level in 1..mip_levels {
+            let to_transfer_src = vk::ImageMemoryBarrier {
+                src_access_mask: vk::AccessFlags::TRANSFER_WRITE,
+                dst_access_mask: vk::AccessFlags::TRANSFER_READ,
+                old_layout: vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                new_layout: vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                image,
+                subresource_range: vk::ImageSubresourceRange {
+                    aspect_mask: vk::ImageAspectFlags::COLOR,
+                    base_mip_level: level - 1,
+                    level_count: 1,
+                    layer_count: 1,
+                    ..Default::default()
+                },
+                ..Default::default()
+            };
+            let (next_width, next_height) = ((width / 2).max(1), (height / 2).max(1));
+            let blit = vk::ImageBlit {
+                src_subresource: subresource(level - 1),
+                src_offsets: [
+                    vk::Offset3D::default(),
+                    vk::Offset3D {
+                        x: width,
+                        y: height,
+                        z: 1,
+                    },
+                ],
+                dst_subresource: subresource(level),
+                dst_offsets: [
+                    vk::Offset3D::default(),
+                    vk::Offset3D {
+                        x: next_width,
+                        y: next_height,
+                        z: 1,
+                    },
+                ],
+            };
+            unsafe {
+                self.device.cmd_pipeline_barrier(
+                    command_buffer,
+                    vk::PipelineStageFlags::TRANSFER,
+                    vk::PipelineStageFlags::TRANSFER,
+                    vk::DependencyFlags::empty(),
+                    &[],
+                    &[],
+                    &[to_transfer_src],
+                );
+                self.device.cmd_blit_image(
+                    command_buffer,
+                    image,
+                    vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                    image,
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    &[blit],
+                    vk::Filter::LINEAR,
+                );
+            }
+            self.cmd_pipeline_barrier_end(
+                image,
+                level - 1,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                command_buffer,
+            );
+            (width, height) = (next_width, next_height);
+        }
+        self.cmd_pipeline_barrier_end(
+            image,
+            mip_levels - 1,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            command_buffer,
+        );
+    }
+
     /// Bind image data to a `BufferAndMemory`.
     pub fn copy_image_to_transfer_src_buffer(
         &self,
@@ -350,6 +477,49 @@ impl<'a> DeviceWrapper<'a> {
         .map_err(RenderError::VkResultToDo)
     }
 
+    /// Allocate a secondary command buffer, for recording commands executed
+    /// from a primary one.
+    pub fn allocate_secondary_command_buffer(
+        &self,
+        pool: vk::CommandPool,
+    ) -> Result<vk::CommandBuffer, RenderError> {
+        let command_buffer_allocate_info = vk::CommandBufferAllocateInfo::builder()
+            .command_buffer_count(1)
+            .command_pool(pool)
+            .level(vk::CommandBufferLevel::SECONDARY);
+        unsafe {
+            self.device
+                .allocate_command_buffers(&command_buffer_allocate_info)
+        }
+        .map(|command_buffers| command_buffers[0])
+        .map_err(RenderError::VkResultToDo)
+    }
+
+    /// Begin a secondary command buffer, continuing the first subpass of
+    /// `render_pass` into `framebuffer`.
+    pub fn begin_secondary_command_buffer(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        render_pass: vk::RenderPass,
+        framebuffer: vk::Framebuffer,
+    ) -> Result<(), RenderError> {
+        let inheritance_info = vk::CommandBufferInheritanceInfo::builder()
+            .render_pass(render_pass)
+            .subpass(0)
+            .framebuffer(framebuffer);
+        let command_buffer_begin_info = vk::CommandBufferBeginInfo::builder()
+            .flags(
+                vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT
+                    | vk::CommandBufferUsageFlags::RENDER_PASS_CONTINUE,
+            )
+            .inheritance_info(&inheritance_info);
+        unsafe {
+            self.device
+                .begin_command_buffer(command_buffer, &command_buffer_begin_info)
+        }
+        .map_err(RenderError::BeginCommandBuffer)
+    }
+
     /// Creates a command pool.
     pub fn create_command_pool(
         &self,
@@ -362,6 +532,15 @@ impl<'a> DeviceWrapper<'a> {
             .map_err(RenderError::VkResultToDo)
     }
 
+    /// Resets every command buffer allocated from a pool.
+    pub fn reset_command_pool(&self, pool: vk::CommandPool) -> Result<(), RenderError> {
+        unsafe {
+            self.device
+                .reset_command_pool(pool, vk::CommandPoolResetFlags::empty())
+        }
+        .map_err(RenderError::ResetCommandPool)
+    }
+
     /// Resets a fence.
     pub fn reset_fence(&self, fence: vk::Fence) -> Result<(), RenderError> {
         unsafe { self.device.reset_fences(&[fence]) }.map_err(RenderError::FenceReset)
@@ -470,6 +649,18 @@ impl<'a> DeviceWrapper<'a> {
         }
     }
 
+    /// Records executing secondary command buffers.
+    pub fn cmd_execute_commands(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        secondary_command_buffers: &[vk::CommandBuffer],
+    ) {
+        unsafe {
+            self.device
+                .cmd_execute_commands(command_buffer, secondary_command_buffers);
+        }
+    }
+
     /// Records an indexed draw call.
     pub fn cmd_draw_indexed(
         &self,
@@ -492,6 +683,26 @@ impl<'a> DeviceWrapper<'a> {
         }
     }
 
+    /// Records a draw call without an index buffer.
+    pub fn cmd_draw(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        vertex_count: u32,
+        instance_count: u32,
+        first_vertex: u32,
+        first_instance: u32,
+    ) {
+        unsafe {
+            self.device.cmd_draw(
+                command_buffer,
+                vertex_count,
+                instance_count,
+                first_vertex,
+                first_instance,
+            );
+        }
+    }
+
     /// Records the end of a render pass.
     pub(crate) fn cmd_end_render_pass(&self, command_buffer: vk::CommandBuffer) {
         unsafe {
@@ -521,29 +732,52 @@ impl<'a> DeviceWrapper<'a> {
         let (image_extent, src_image) = self
             .copy_image_to_transfer_src_buffer(image, device_memory_properties)
             .unwrap();
+        let mip_levels = mip_levels(image_extent);
         let dest_texture = self
-            .allocate_texture_dest_buffer(device_memory_properties, image_extent)
+            .allocate_texture_dest_buffer(device_memory_properties, image_extent, mip_levels)
             .unwrap();
-        self.cmd_pipeline_barrier_start(dest_texture.image, command_buffer);
+        self.cmd_pipeline_barrier_start(*dest_texture.image, mip_levels, command_buffer);
         self.cmd_copy_buffer_to_image(&src_image, image_extent, &dest_texture, command_buffer);
-        self.cmd_pipeline_barrier_end(dest_texture.image, command_buffer);
+        self.cmd_generate_mipmaps(
+            *dest_texture.image,
+            image_extent,
+            mip_levels,
+            command_buffer,
+        );
         (src_image, dest_texture)
     }
 }
 
+/// Number of levels in a full mip chain for an image, down to 1x1.
+fn mip_levels(image_extent: vk::Extent2D) -> u32 {
+    u32::BITS
+        - image_extent
+            .width
+            .max(image_extent.height)
+            .max(1)
+            .leading_zeros()
+}
+
 /// Handle to resources on the GPU comprising a mesh, texture and shader.
 pub struct GraphicsHandle {
     pub vertex_buffer: BufferAndMemory,
+    /// Skin weights of a skinned graphic's vertices, see
+    /// `SKIN_WEIGHTS_BINDING`.
+    pub skin_buffer: Option<BufferAndMemory>,
     pub index_buffer: BufferAndMemory,
     pub diffuse_map: Option<Texture>,
     // pub specular_map: Option<Texture>,
     // pub bump_map: Option<Texture>,
 
     // TODO: list of shaders, or a stages object?
-    pub vertex_shader: Arc<Shader>,
-    pub fragment_shader: Arc<Shader>,
+    /// Vertex and fragment shaders, replaced when they're reloaded, see
+    /// `reload_shaders`.
+    shaders: Mutex<[Arc<Shader>; 2]>,
 
     pub primitive: Primitive,
+
+    /// Bounds of the vertices in model space, used for occlusion culling.
+    pub bounds: Option<Aabb>,
 }
 
 impl GraphicsHandle {
@@ -556,30 +790,82 @@ impl GraphicsHandle {
         vertex_shader: Shader,
         fragment_shader: Shader,
         primitive: Primitive,
+        bounds: Option<Aabb>,
     ) -> Self {
         Self {
             diffuse_map,
             // specular_map,
             // bump_map,
             vertex_buffer,
+            skin_buffer: None,
             index_buffer,
-            vertex_shader: Arc::new(vertex_shader),
-            fragment_shader: Arc::new(fragment_shader),
+            shaders: Mutex::new([Arc::new(vertex_shader), Arc::new(fragment_shader)]),
             primitive,
+            bounds,
         }
     }
-    pub(crate) fn deallocate(&self, base: &mut VulkanBase) {
-        self.index_buffer.deallocate(&base.device);
-        self.vertex_buffer.deallocate(&base.device);
-        self.diffuse_map
-            .as_ref()
-            .map(|map| map.deallocate(&base.device));
+    /// Give the graphic skin weights, uploaded beside its vertices.
+    pub(crate) fn with_skin_buffer(mut self, skin_buffer: Option<BufferAndMemory>) -> Self {
+        self.skin_buffer = skin_buffer;
+        self
+    }
+
+    pub(crate) fn deallocate(&self, device: &ash::Device) {
+        self.index_buffer.deallocate(device);
+        self.vertex_buffer.deallocate(device);
+        if let Some(skin_buffer) = self.skin_buffer.as_ref() {
+            skin_buffer.deallocate(device);
+        }
+        self.diffuse_map.as_ref().map(|map| map.deallocate(device));
         // self.specular_map
         //     .as_ref()
-        //     .map(|map| map.deallocate(&base.device));
+        //     .map(|map| map.deallocate(device));
         // self.bump_map
         //     .as_ref()
-        //     .map(|map| map.deallocate(&base.device));
+        //     .map(|map| map.deallocate(device));
+    }
+
+    fn shaders(&self) -> MutexGuard<'_, [Arc<Shader>; 2]> {
+        // Shaders are only ever replaced whole.
+        self.shaders
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    pub fn vertex_shader(&self) -> Arc<Shader> {
+        Arc::clone(&self.shaders()[0])
+    }
+
+    pub fn fragment_shader(&self) -> Arc<Shader> {
+        Arc::clone(&self.shaders()[1])
+    }
+
+    /// Read the shaders from disk again, returning whether their code changed.
+    /// Pipelines already built keep the shaders they were built with. If
+    /// either can't be read, both are kept as they were.
+    pub fn reload_shaders(&self) -> Result<bool, RenderError> {
+        let [vertex_shader, fragment_shader] = self.shaders().clone();
+        let reloaded = [
+            Shader::read_spv(vertex_shader.path().to_path_buf())?,
+            Shader::read_spv(fragment_shader.path().to_path_buf())?,
+        ];
+        let changed = reloaded[0].content_hash() != vertex_shader.content_hash()
+            || reloaded[1].content_hash() != fragment_shader.content_hash();
+        if changed {
+            *self.shaders() = reloaded.map(Arc::new);
+        }
+        Ok(changed)
+    }
+
+    /// Bytes of device memory held by the buffers and texture.
+    pub fn memory_size(&self) -> u64 {
+        self.vertex_buffer.allocation_size
+            + self
+                .skin_buffer
+                .as_ref()
+                .map_or(0, |skin| skin.allocation_size)
+            + self.index_buffer.allocation_size
+            + self.diffuse_map.as_ref().map_or(0, |map| map.size)
     }
 
     pub fn primitive_topology(&self) -> vk::PrimitiveTopology {
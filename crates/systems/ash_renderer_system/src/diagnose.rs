@@ -0,0 +1,147 @@
+//! Checks for `nshell --diagnose`. These run without a window, so they stop
+//! short of creating a surface or swapchain.
+
+use std::ffi::CStr;
+use std::path::Path;
+
+use ash::{vk, Entry};
+
+use crate::types::Shader;
+
+/// What the vulkan loader and drivers on this machine offer.
+#[derive(Debug, Clone)]
+pub struct VulkanDiagnostics {
+    pub api_version: String,
+    pub layers: Vec<String>,
+    pub extensions: Vec<String>,
+    pub devices: Vec<DeviceDiagnostics>,
+}
+
+#[derive(Debug, Clone)]
+pub struct DeviceDiagnostics {
+    pub name: String,
+    pub device_type: String,
+    pub api_version: String,
+    pub supports_swapchain: bool,
+    /// Whether a logical device could be created on a graphics queue, and why
+    /// not if it couldn't.
+    pub created: Result<(), String>,
+}
+
+/// Load vulkan, create an instance and try creating a device on every
+/// physical device.
+pub fn diagnose_vulkan() -> Result<VulkanDiagnostics, String> {
+    let entry = unsafe { Entry::load() }.map_err(|err| format!("unable to load vulkan: {err}"))?;
+    let api_version = entry
+        .try_enumerate_instance_version()
+        .map_err(|err| format!("unable to query instance version: {err}"))?
+        .map_or_else(|| "1.0".to_string(), version_string);
+    let layers = entry
+        .enumerate_instance_layer_properties()
+        .map_err(|err| format!("unable to list layers: {err}"))?
+        .iter()
+        .map(|layer| c_chars_to_string(&layer.layer_name))
+        .collect();
+    let extensions = entry
+        .enumerate_instance_extension_properties(None)
+        .map_err(|err| format!("unable to list instance extensions: {err}"))?
+        .iter()
+        .map(|extension| c_chars_to_string(&extension.extension_name))
+        .collect();
+
+    let application_info = vk::ApplicationInfo {
+        api_version: vk::make_api_version(0, 1, 0, 0),
+        ..Default::default()
+    };
+    let create_info = vk::InstanceCreateInfo::builder().application_info(&application_info);
+    let instance = unsafe { entry.create_instance(&create_info, None) }
+        .map_err(|err| format!("unable to create instance: {err}"))?;
+
+    let devices = match unsafe { instance.enumerate_physical_devices() } {
+        Ok(physical_devices) => physical_devices
+            .into_iter()
+            .map(|physical_device| diagnose_device(&instance, physical_device))
+            .collect(),
+        Err(err) => {
+            unsafe { instance.destroy_instance(None) };
+            return Err(format!("unable to list physical devices: {err}"));
+        }
+    };
+    unsafe { instance.destroy_instance(None) };
+
+    Ok(VulkanDiagnostics {
+        api_version,
+        layers,
+        extensions,
+        devices,
+    })
+}
+
+fn diagnose_device(
+    instance: &ash::Instance,
+    physical_device: vk::PhysicalDevice,
+) -> DeviceDiagnostics {
+    let properties = unsafe { instance.get_physical_device_properties(physical_device) };
+    let supports_swapchain =
+        unsafe { instance.enumerate_device_extension_properties(physical_device) }
+            .map(|extensions| {
+                extensions.iter().any(|extension| {
+                    c_chars_to_string(&extension.extension_name)
+                        == ash::extensions::khr::Swapchain::name().to_string_lossy()
+                })
+            })
+            .unwrap_or(false);
+
+    let graphics_queue_family =
+        unsafe { instance.get_physical_device_queue_family_properties(physical_device) }
+            .iter()
+            .position(|family| family.queue_flags.contains(vk::QueueFlags::GRAPHICS));
+    let created = match graphics_queue_family {
+        Some(queue_family_index) => {
+            let priorities = [1.0];
+            let queue_create_infos = [*vk::DeviceQueueCreateInfo::builder()
+                .queue_family_index(queue_family_index as u32)
+                .queue_priorities(&priorities)];
+            let device_create_info =
+                vk::DeviceCreateInfo::builder().queue_create_infos(&queue_create_infos);
+            unsafe { instance.create_device(physical_device, &device_create_info, None) }
+                .map(|device| unsafe { device.destroy_device(None) })
+                .map_err(|err| format!("unable to create device: {err}"))
+        }
+        None => Err("no graphics queue".to_string()),
+    };
+
+    DeviceDiagnostics {
+        name: c_chars_to_string(&properties.device_name),
+        device_type: format!("{:?}", properties.device_type),
+        api_version: version_string(properties.api_version),
+        supports_swapchain,
+        created,
+    }
+}
+
+/// Check that a SPIR-V file can be read and reflected over, returning the
+/// names of its entry points.
+pub fn validate_spirv(path: &Path) -> Result<Vec<String>, String> {
+    let shader = Shader::read_spv(path.to_path_buf()).map_err(|err| err.to_string())?;
+    Ok(shader
+        .entry_points()
+        .iter()
+        .map(|entry_point| entry_point.name().to_string())
+        .collect())
+}
+
+pub(crate) fn version_string(version: u32) -> String {
+    format!(
+        "{}.{}.{}",
+        vk::api_version_major(version),
+        vk::api_version_minor(version),
+        vk::api_version_patch(version)
+    )
+}
+
+pub(crate) fn c_chars_to_string(chars: &[std::os::raw::c_char]) -> String {
+    unsafe { CStr::from_ptr(chars.as_ptr()) }
+        .to_string_lossy()
+        .into_owned()
+}
@@ -0,0 +1,228 @@
+//! The resources each frame in flight records and submits with. Frames take
+//! turns, so recording one only waits for the frame that last used the same
+//! resources, `len` submissions ago, and the CPU records a frame while the GPU
+//! is still rendering the ones before it.
+//!
+//! Submissions are numbered as they're made. Waiting on a frame's fence
+//! completes its submission and every one before it on the queue, which is
+//! how resources shared by every frame, like retired pipelines, know when
+//! nothing uses them anymore.
+
+use ash::{vk, Device};
+use bytemuck::Zeroable;
+use logger::Logger;
+use render::MAX_FRAMES_IN_FLIGHT;
+use shader_objects::{ClusteredLights, JointMatrices, ReflectionProbes, UniformBuffer};
+
+use crate::device::DeviceWrapper;
+use crate::types::{BufferAndMemory, RenderError};
+
+pub(crate) struct Frame {
+    pub command_buffer: vk::CommandBuffer,
+    /// Signaled once the swapchain image the frame is presented to is
+    /// acquired.
+    pub image_acquired: vk::Semaphore,
+    /// Signaled once the frame has rendered, for presentation to wait on.
+    pub rendering_complete: vk::Semaphore,
+    /// Signaled once the frame's commands complete, created signaled.
+    pub fence: vk::Fence,
+    /// `UniformBuffer` of the frame's view, bound by every pipeline. Also
+    /// written by reflection probe captures, between faces.
+    pub uniform: BufferAndMemory,
+    /// Point lights by cluster, bound by every pipeline whose shaders bind
+    /// `CLUSTERED_LIGHTS_BINDING`.
+    pub clustered_lights: BufferAndMemory,
+    /// `ReflectionProbes` seen from the frame's view, bound by every pipeline
+    /// whose shaders bind `REFLECTION_PROBES_BINDING`.
+    pub reflection_probes: BufferAndMemory,
+    /// Joint matrices of the skinned drawables of the frame, bound by every
+    /// pipeline whose shaders bind `JOINT_MATRICES_BINDING`.
+    pub joint_matrices: BufferAndMemory,
+    /// Number of the last submission made with the frame, 0 before any.
+    submission: u64,
+}
+
+/// Every frame in flight, used in turn.
+pub(crate) struct Frames {
+    frames: Vec<Frame>,
+    current: usize,
+    /// Submissions made so far, the last one's number.
+    submissions: u64,
+    /// Every submission up to this one has completed.
+    completed: u64,
+}
+
+impl Frames {
+    /// Create `count` frames, clamped to between 1 and
+    /// `MAX_FRAMES_IN_FLIGHT`, allocating their command buffers from `pool`.
+    pub fn new(
+        device: &Device,
+        pool: vk::CommandPool,
+        memory_properties: vk::PhysicalDeviceMemoryProperties,
+        count: u32,
+        logger: &Logger,
+    ) -> Result<Self, RenderError> {
+        let count = count.clamp(1, MAX_FRAMES_IN_FLIGHT);
+        let allocate_info = vk::CommandBufferAllocateInfo::builder()
+            .command_buffer_count(count)
+            .command_pool(pool)
+            .level(vk::CommandBufferLevel::PRIMARY);
+        let command_buffers = unsafe { device.allocate_command_buffers(&allocate_info) }
+            .map_err(RenderError::VkResultToDo)?;
+        let mut frames = Vec::with_capacity(command_buffers.len());
+        for &command_buffer in command_buffers.iter() {
+            match Frame::new(device, command_buffer, memory_properties, logger) {
+                Ok(frame) => frames.push(frame),
+                Err(err) => {
+                    for frame in frames {
+                        frame.destroy(device);
+                    }
+                    unsafe { device.free_command_buffers(pool, &command_buffers) };
+                    return Err(err);
+                }
+            }
+        }
+        Ok(Self {
+            frames,
+            current: 0,
+            submissions: 0,
+            completed: 0,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Frame> {
+        self.frames.iter()
+    }
+
+    /// Index of the frame being recorded.
+    pub fn index(&self) -> usize {
+        self.current
+    }
+
+    pub fn current(&self) -> &Frame {
+        &self.frames[self.current]
+    }
+
+    pub fn current_mut(&mut self) -> &mut Frame {
+        &mut self.frames[self.current]
+    }
+
+    /// Move on to the next frame, waiting for its last submission to
+    /// complete, and return its index. Its fence is left signaled, to be
+    /// reset right before it's submitted again.
+    pub fn begin(&mut self, device: &Device) -> Result<usize, RenderError> {
+        self.current = (self.current + 1) % self.frames.len();
+        let frame = &self.frames[self.current];
+        unsafe { device.wait_for_fences(&[frame.fence], true, u64::MAX) }
+            .map_err(RenderError::Fence)?;
+        self.completed = self.completed.max(frame.submission);
+        Ok(self.current)
+    }
+
+    /// Number the current frame's submission, once it's submitted.
+    pub fn submitted(&mut self) {
+        self.submissions += 1;
+        self.frames[self.current].submission = self.submissions;
+    }
+
+    /// Wait for every frame in flight to complete.
+    pub fn wait_all(&mut self, device: &Device) -> Result<(), RenderError> {
+        let fences = self
+            .frames
+            .iter()
+            .map(|frame| frame.fence)
+            .collect::<Vec<_>>();
+        unsafe { device.wait_for_fences(&fences, true, u64::MAX) }.map_err(RenderError::Fence)?;
+        self.idle();
+        Ok(())
+    }
+
+    /// Record that every submission has completed, after waiting for the
+    /// queue or device to be idle.
+    pub fn idle(&mut self) {
+        self.completed = self.submissions;
+    }
+
+    /// Number of the last submission made, which anything used so far may be
+    /// in flight until.
+    pub fn last_submission(&self) -> u64 {
+        self.submissions
+    }
+
+    /// Whether `submission` and every submission before it has completed.
+    pub fn is_complete(&self, submission: u64) -> bool {
+        submission <= self.completed
+    }
+
+    /// Destroy the frames, once none is in flight. Their command buffers are
+    /// freed with their pool.
+    pub fn destroy(&mut self, device: &Device) {
+        for frame in self.frames.drain(..) {
+            frame.destroy(device);
+        }
+    }
+}
+
+impl Frame {
+    fn new(
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        memory_properties: vk::PhysicalDeviceMemoryProperties,
+        logger: &Logger,
+    ) -> Result<Self, RenderError> {
+        let w = DeviceWrapper::wrap(device, logger);
+        let semaphore_create_info = vk::SemaphoreCreateInfo::default();
+        let image_acquired = unsafe { device.create_semaphore(&semaphore_create_info, None) }
+            .map_err(RenderError::VkResultToDo)?;
+        let rendering_complete = unsafe { device.create_semaphore(&semaphore_create_info, None) }
+            .map_err(RenderError::VkResultToDo)?;
+        let fence = w.create_fence()?;
+        let uniform = w.allocate_and_init_buffer(
+            vk::BufferUsageFlags::UNIFORM_BUFFER | vk::BufferUsageFlags::TRANSFER_DST,
+            memory_properties,
+            bytemuck::bytes_of(&UniformBuffer::new()),
+        )?;
+        let clustered_lights = w.allocate_and_init_buffer(
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            memory_properties,
+            bytemuck::bytes_of(&*bytemuck::zeroed_box::<ClusteredLights>()),
+        )?;
+        let reflection_probes = w.allocate_and_init_buffer(
+            vk::BufferUsageFlags::UNIFORM_BUFFER,
+            memory_properties,
+            bytemuck::bytes_of(&ReflectionProbes::zeroed()),
+        )?;
+        let joint_matrices = w.allocate_and_init_buffer(
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            memory_properties,
+            bytemuck::bytes_of(&*bytemuck::zeroed_box::<JointMatrices>()),
+        )?;
+        Ok(Self {
+            command_buffer,
+            image_acquired,
+            rendering_complete,
+            fence,
+            uniform,
+            clustered_lights,
+            reflection_probes,
+            joint_matrices,
+            submission: 0,
+        })
+    }
+
+    fn destroy(self, device: &Device) {
+        unsafe {
+            device.destroy_semaphore(self.image_acquired, None);
+            device.destroy_semaphore(self.rendering_complete, None);
+            device.destroy_fence(self.fence, None);
+        }
+        self.uniform.deallocate(device);
+        self.clustered_lights.deallocate(device);
+        self.reflection_probes.deallocate(device);
+        self.joint_matrices.deallocate(device);
+    }
+}
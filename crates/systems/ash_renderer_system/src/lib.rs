@@ -5,51 +5,231 @@
 //! crate, and only expose the plugin for truly dynamic things that are
 //! desireable to change at runtime.
 
+mod capture;
+mod crash_report;
 mod debug_callback;
+mod debug_lines;
+mod deferred;
 mod device;
+pub mod diagnose;
+mod frame;
+mod offscreen;
+mod pipeline_cache;
+mod probes;
+mod readback;
+mod resource;
+mod scaled_target;
+mod secondary;
+mod shadows;
+mod stats;
 mod types;
+mod ui;
+mod upload;
+mod window_target;
 
-use std::collections::{hash_map, HashMap};
-use std::ffi::CString;
+use std::collections::{hash_map, HashMap, HashSet, VecDeque};
+use std::ffi::{CString, OsStr};
 use std::mem;
-use std::sync::Arc;
-use std::time::{Duration, Instant};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
 
 use ash::extensions::khr::{Surface, Swapchain};
 use ash::{vk, Device, Entry};
+use bytemuck::Zeroable;
 use device::GraphicsHandle;
-use gfx::{DiffuseColor, GpuNeeds, Graphic, Primitive, Vertex};
-use logger::{debug, error, info, Logger};
+use gfx::{Graphic, Primitive, SkinWeights, Vertex};
+use glam::{Mat4, Vec3, Vec4};
+use logger::{debug, error, info, trace, warn, Logger};
 use platform::WinPtr;
-use render::{Presenter, RenderState, RenderStateError};
-use shader_objects::{PushConstants, UniformBuffer};
+use render::aspect::{self, AspectPolicy};
+use render::clusters::LightClusters;
+use render::extract::{ExtractedDrawables, Frustum};
+use render::gpu_stats::{GpuStats, GraphicMemory};
+use render::lights::{directional_light, spot_light, ViewLights};
+use render::occlusion::{OcclusionBuffer, OcclusionStats};
+use render::probes::ProbeCaptures;
+use render::readback::{Readback, ReadbackImage};
+use render::render_path::RenderPath;
+use render::render_scale::{scaled_extent, RenderScale, ScaleController, UpscaleFilter};
+use render::settings::PresentMode;
+use render::shadows::ShadowCaster;
+use render::target::RenderTargetId;
+use render::{PresentTimings, Presenter, RenderState, RenderStateError};
+use shader_objects::{
+    ClusteredLight, ClusteredLights, JointMatrices, PushConstants, ReflectionProbes, UniformBuffer,
+    CLUSTERED_LIGHTS_BINDING, JOINT_MATRICES_BINDING, MAX_JOINT_MATRICES,
+    REFLECTION_PROBES_BINDING, REFLECTION_PROBE_FACES_BINDING, SHADOW_MAP_BINDING,
+    SHADOW_MAP_RESOLUTION, SKIN_PARAM,
+};
 use stable_typeid::StableTypeId;
+// Public only for the pipeline bench.
+#[doc(hidden)]
+pub use types::Shader;
 use types::{
-    Attachments, AttachmentsModifier, BufferAndMemory, Pipeline, RenderError, Shader, ShaderStage,
-    ShaderStages, VertexInputAssembly,
+    Attachments, AttachmentsModifier, BufferAndMemory, Pipeline, RenderError, ShaderStage,
+    ShaderStages, SharedPipeline, VertexInputAssembly, VertexLayout,
 };
+use world::animation::{Skeleton, Skinned};
 use world::components::spatial::SpatialHierarchyNode;
-use world::components::{Camera, Drawable, WorldTransform};
+use world::components::{
+    Camera, DirectionalLight, Drawable, PointLight, ReflectionProbe, RenderFlags, RenderLayers,
+    SpotLight, WorldTransform,
+};
 use world::{Entity, World};
 
+use crate::capture::FrameCaptures;
+use crate::crash_report::{frame_time_lines, CrashReport, Recent, RECENT_FRAMES, RECENT_MESSAGES};
+use crate::debug_lines::DebugLineBatch;
+use crate::deferred::{DeferredPass, DEFERRABLE_FRAGMENT_SHADER};
 use crate::device::DeviceWrapper;
+use crate::frame::{Frame, Frames};
+use crate::offscreen::{OffscreenImages, OFFSCREEN_FORMAT, OFFSCREEN_LAYOUT};
+use crate::pipeline_cache::{PipelineCache, PipelineKey};
+use crate::probes::ReflectionProbeFaces;
+use crate::readback::Readbacks;
+use crate::resource::Owned;
+use crate::scaled_target::ScaledTarget;
+use crate::secondary::{DrawCall, SecondaryRecorder};
+use crate::shadows::ShadowMap;
+use crate::stats::{PipelineStatisticsQueries, StatsPass};
 use crate::types::DescriptorSetLayoutBinding;
-
-// Prevent the renderer from rebuilding more than once every N ms.
-const PIPELINE_REBUILD_DELAY_MILLIS: u64 = 250;
+use crate::ui::UiPass;
+use crate::upload::UploadBatch;
+use crate::window_target::WindowTarget;
+
+// Time allowed per frame for building pipelines. At least one pipeline is
+// always built, so rebuilds make progress on slow drivers.
+const PIPELINE_REBUILD_BUDGET: Duration = Duration::from_millis(2);
+
+/// Vertex buffer binding of the skin weights of skinned graphics, beside
+/// their vertices at 0.
+const SKIN_WEIGHTS_BINDING: u32 = 1;
+
+/// Version of the plugin, shown with its name in what it logs.
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// What `Renderer::collect_draws` collects draws for.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum DrawPass {
+    /// The scene, drawn forward.
+    Forward,
+    /// The scene, drawing into the G-buffer what can be drawn deferred.
+    Deferred,
+    /// The shadow map, drawing only what casts shadows.
+    Shadow,
+}
 
 /// Renderer struct owning the descriptor pool, pipelines and descriptions.
 struct Renderer {
     descriptor_pool: vk::DescriptorPool,
     pipelines: HashMap<Entity, Pipeline>,
+    /// Pipelines shared by graphics drawn with the same shaders.
+    pipeline_cache: PipelineCache,
+    /// Graphics whose pipelines need to be (re)built, oldest first.
+    dirty_pipelines: VecDeque<Entity>,
+    /// Pipelines that have been replaced, but may still be in use by frames in
+    /// flight, with the last submission that may use them.
+    retired_pipelines: Vec<(u64, Pipeline)>,
+    /// Pipelines built for reloaded graphics, swapped in with them at the start
+    /// of the next frame.
+    pending_pipelines: HashMap<Entity, Pipeline>,
+    /// Occluders rasterized for the current frame.
+    occlusion: OcclusionBuffer,
+    /// Lines from the world's debug draw for each frame in flight, allocated
+    /// once there are some.
+    debug_lines: Vec<Option<DebugLineBatch>>,
+    /// Draws the world's debug UI, created once it's first shown.
+    ui: Option<UiPass>,
+    /// Whether the debug UI couldn't be drawn, and that was reported.
+    warned_ui: bool,
+    /// Whether the main window is lit forward or deferred, see `RenderState`.
+    render_path: RenderPath,
+    /// Draws into the G-buffer and shades it, created the first time the
+    /// scene is drawn deferred.
+    deferred: Option<DeferredPass>,
+    /// Whether the scene couldn't be drawn deferred, and that was reported.
+    warned_deferred: bool,
+    /// Resolution the scene is rendered at, relative to the window.
+    scaler: ScaleController,
+    /// Where the scene is rendered when it isn't rendered at the window's
+    /// resolution.
+    scaled_target: Option<ScaledTarget>,
+    aspect_policy: AspectPolicy,
+    /// The frame's draws, kept to reuse the allocation.
+    draws: Vec<DrawCall>,
+    /// The frame's draws into the G-buffer, when drawn deferred.
+    gbuffer_draws: Vec<DrawCall>,
+    /// The frame's draws into the shadow map, when a light casts shadows.
+    shadow_draws: Vec<DrawCall>,
+    /// Drawables extracted from the world for the draws being collected.
+    extracted: ExtractedDrawables,
+    /// Indices into `extracted` of the drawables being drawn, by graphic.
+    draw_order: Vec<usize>,
+    /// Assigns point lights to clusters of the view.
+    light_clusters: LightClusters,
+    /// Picks the directional and spot lights of the view.
+    view_lights: ViewLights,
+    /// Picks the light casting shadows into the view.
+    shadow_caster: ShadowCaster,
+    /// The frame's view and its directional and spot lights, copied to the
+    /// frame's `uniform`.
+    uniform: Box<UniformBuffer>,
+    /// The frame's point lights by cluster, copied to the frame's
+    /// `clustered_lights`.
+    clustered_lights: Box<ClusteredLights>,
+    /// The frame's joint matrices, copied to the frame's `joint_matrices`.
+    joint_matrices: Box<JointMatrices>,
+    /// Where each skeleton's joints are in `joint_matrices`, the first and
+    /// how many.
+    joint_ranges: HashMap<Entity, (u32, u32)>,
+    /// Whether skeletons past the most joint matrices were reported.
+    warned_dropped_joints: bool,
+    /// Which reflection probes are in `VulkanBase::reflection_probes`.
+    probe_captures: ProbeCaptures,
+    /// Whether probes past the most that can be captured were reported.
+    warned_dropped_probes: bool,
+    /// Records the draws of larger scenes on several threads, for each frame
+    /// in flight.
+    secondary: Vec<SecondaryRecorder>,
+    /// When the last frame was submitted and presented.
+    present_timings: Option<PresentTimings>,
+    /// How long the last frames took to present, for crash reports.
+    frame_times: Recent<Duration>,
+    /// Whether pipeline statistics are queried, see `RenderState`.
+    capture_gpu_stats: bool,
+    /// Created the first time statistics are captured, if the device can.
+    statistics: Option<PipelineStatisticsQueries>,
+    /// Copies back from the GPU, delivered once the frame they were recorded
+    /// in completes.
+    readbacks: Readbacks,
     logger: Logger,
-    last_pipeline_rebuild: Instant,
+}
+
+/// Where the scene is viewed from, for a window of a given size.
+#[derive(Copy, Clone)]
+struct SceneView {
+    view: Mat4,
+    projection: Mat4,
+    view_projection: Mat4,
+    occlusion_culling: bool,
+    /// Layers of the drawables seen, see `RenderLayers`.
+    layers: RenderLayers,
+}
+
+/// What the scene is rendered into.
+struct ScenePass {
+    render_pass: vk::RenderPass,
+    framebuffer: vk::Framebuffer,
+    extent: vk::Extent2D,
 }
 
 #[repr(C)]
 struct VulkanDebug {
     placeholder: u32,
     logger: Logger,
+    /// The last messages from the validation layers, for crash reports.
+    recent_messages: Mutex<Recent<String>>,
 }
 
 impl VulkanDebug {
@@ -57,6 +237,7 @@ impl VulkanDebug {
         let s = VulkanDebug {
             placeholder: 42,
             logger,
+            recent_messages: Mutex::new(Recent::new(RECENT_MESSAGES)),
         };
         Arc::new(s)
     }
@@ -68,14 +249,27 @@ impl Renderer {
             base.recreate_swapchain()?;
         }
 
-        let present_index = match unsafe {
-            base.swapchain_loader.acquire_next_image(
-                base.swapchain,
-                300 * 1000,
-                base.present_complete_semaphore,
-                vk::Fence::null(),
-            )
-        } {
+        // Continue any rebuilds that didn't fit in previous frames.
+        self.rebuild_pipelines(base, Some(PIPELINE_REBUILD_BUDGET))?;
+        self.swap_in_reloaded_graphics(base);
+
+        // The frame's semaphores can only be reused once it has completed.
+        self.begin_frame(base)?;
+        let acquired = if base.offscreen.is_some() {
+            // Each frame in flight has an image of its own, free once the
+            // frame has begun.
+            Ok((base.frames.index() as u32, false))
+        } else {
+            unsafe {
+                base.swapchain_loader.acquire_next_image(
+                    base.swapchain,
+                    300 * 1000,
+                    base.frames.current().image_acquired,
+                    vk::Fence::null(),
+                )
+            }
+        };
+        let present_index = match acquired {
             Ok((index, _suboptimal @ false)) => index,
             Ok((_index, _suboptimal @ true)) => {
                 debug!(
@@ -99,181 +293,137 @@ impl Renderer {
         };
 
         // TODO: cleanup move to world to ensure we get a real camera.
-        let (camera, cam_spatial) = {
-            let camera_entity = world.camera().expect("camera should exist");
-            let entity = world.hecs_world.entity(camera_entity).unwrap();
-            if !entity.has::<Camera>() {
-                return Err(RenderError::ComponentMissingFromCameraEntity(
-                    camera_entity,
-                    std::any::type_name::<Camera>(),
-                    StableTypeId::of::<Camera>(),
-                ));
-            }
-            if !entity.has::<SpatialHierarchyNode>() {
-                return Err(RenderError::ComponentMissingFromCameraEntity(
-                    camera_entity,
-                    std::any::type_name::<SpatialHierarchyNode>(),
-                    StableTypeId::of::<SpatialHierarchyNode>(),
-                ));
-            }
-
-            (
-                world
-                    .hecs_world
-                    .get::<&Camera>(camera_entity)
-                    .expect("camera entity"),
-                world
-                    .hecs_world
-                    .get::<&SpatialHierarchyNode>(camera_entity)
-                    .expect("spatial entity"),
-            )
-        };
+        let camera_entity = world.camera().expect("camera should exist");
+        let view = self.scene_view(world, camera_entity, base.surface_resolution)?;
+        if view.occlusion_culling {
+            self.rasterize_occluders(base, world, view.view_projection, view.layers);
+        }
 
-        let clear_values = [
-            vk::ClearValue {
-                color: vk::ClearColorValue {
-                    float32: [0.0, 0.0, 0.0, 0.0],
-                },
+        self.prepare_scaled_target(base)?;
+        let pass = match self.scaled_target.as_ref() {
+            Some(target) => ScenePass {
+                render_pass: target.render_pass,
+                framebuffer: target.framebuffer,
+                extent: target.extent,
             },
-            vk::ClearValue {
-                depth_stencil: vk::ClearDepthStencilValue {
-                    depth: 1.0,
-                    stencil: 0,
-                },
+            None => ScenePass {
+                render_pass: *base.render_pass,
+                framebuffer: *base.framebuffers[present_index as usize],
+                extent: base.surface_resolution,
             },
-        ];
+        };
 
-        let scissors = base.scissors();
-        let viewports = base.viewports();
+        // Probes are captured before the frame, so it reflects them.
+        let now = Instant::now();
+        self.capture_reflection_probes(base, world, now)?;
+        let capture_gpu_stats = self.capture_gpu_stats;
+        let deferred = self.render_path == RenderPath::Deferred;
+        self.record_scene(
+            base,
+            world,
+            &view,
+            &pass,
+            now,
+            capture_gpu_stats,
+            true,
+            deferred,
+        )?;
 
         let w = DeviceWrapper::wrap(&base.device, &self.logger);
+        let command_buffer = base.frames.current().command_buffer;
 
-        w.wait_for_fence(base.draw_commands_reuse_fence)?;
-        w.reset_fence(base.draw_commands_reuse_fence)?;
-        w.begin_command_buffer(base.draw_cmd_buf)?;
-
-        let render_pass_begin_info = vk::RenderPassBeginInfo::builder()
-            .render_pass(base.render_pass)
-            .framebuffer(base.framebuffers[present_index as usize])
-            .render_area(base.surface_resolution.into())
-            .clear_values(&clear_values);
-
-        w.cmd_begin_render_pass(
-            base.draw_cmd_buf,
-            &render_pass_begin_info,
-            vk::SubpassContents::INLINE,
+        if let Some(target) = self.scaled_target.as_ref() {
+            target.cmd_blit_to(
+                &base.device,
+                command_buffer,
+                base.present_images[present_index as usize],
+                base.surface_resolution,
+                base.present_layout(),
+                self.scaler.filter(),
+            );
+        }
+        self.readbacks.cmd_read_frame(
+            &base.device,
+            command_buffer,
+            base.frames.current().fence,
+            base.present_images[present_index as usize],
+            base.present_layout(),
+            base.surface_resolution,
+            base.surface_format.format,
+            base.present_readable,
         );
 
-        let proj_mat = camera.combined_projection();
-
-        for (gfx_index, (model, _uploaded_instant)) in base.tracked_graphics.iter() {
-            // TODO: unified struct for models & pipelines
-            let desc = match self.pipelines.get_mut(gfx_index) {
-                Some(desc) => desc,
-                None => continue,
-            };
-            let pipeline = match desc.vk.as_ref() {
-                Some(pipeline) => pipeline,
-                None => continue,
-            };
-
-            let ubo = UniformBuffer::with_proj(proj_mat);
-            let ubo_bytes = bytemuck::bytes_of(&ubo);
-            w.update_buffer(&mut desc.uniform_buffer, ubo_bytes)?;
-
-            w.cmd_bind_descriptor_sets(
-                base.draw_cmd_buf,
-                vk::PipelineBindPoint::GRAPHICS,
-                desc.layout,
-                0,
-                &[desc.descriptor_set],
-                &[],
+        if view.occlusion_culling {
+            let stats = self.occlusion.stats();
+            trace!(
+                self.logger,
+                "occlusion: {} occluders, {} of {} drawables occluded",
+                stats.occluders,
+                stats.occluded,
+                stats.tested
             );
-            w.cmd_bind_pipeline(
-                base.draw_cmd_buf,
-                vk::PipelineBindPoint::GRAPHICS,
-                *pipeline,
-            );
-            w.cmd_set_viewport(base.draw_cmd_buf, 0, &viewports);
-            w.cmd_set_scissor(base.draw_cmd_buf, 0, &scissors);
-            w.cmd_bind_vertex_buffers(base.draw_cmd_buf, 0, &[model.vertex_buffer.buffer], &[0]);
-            w.cmd_bind_index_buffer(
-                base.draw_cmd_buf,
-                model.index_buffer.buffer,
-                0,
-                vk::IndexType::UINT32,
-            );
-
-            // Don't calculate or update the world transform, just use what's been cached.
-            for (drawable, world_transform) in world
-                .hecs_world
-                .query::<(&Drawable, &WorldTransform)>()
-                .iter()
-                .filter_map(|(_entity, (drawable, spatial))| {
-                    if drawable.gfx == *gfx_index {
-                        Some((drawable, spatial))
-                    } else {
-                        None
-                    }
-                })
-            {
-                let push_constants = PushConstants::new(world_transform.world);
-                let push_constant_bytes = push_constants.to_bytes();
-
-                let (model, _) = base.tracked_graphics.get(&drawable.gfx).unwrap();
-                w.cmd_push_constants(
-                    base.draw_cmd_buf,
-                    desc.layout,
-                    vk::ShaderStageFlags::VERTEX,
-                    0,
-                    push_constant_bytes,
-                );
-                w.cmd_draw_indexed(
-                    base.draw_cmd_buf,
-                    model.index_buffer.original_len as u32,
-                    1,
-                    0,
-                    0,
-                    1,
-                );
-            }
         }
 
-        w.cmd_end_render_pass(base.draw_cmd_buf);
-
-        let command_buffers = vec![base.draw_cmd_buf];
+        let command_buffers = vec![command_buffer];
 
         // NOT calling build on the builder here prevents a segfault in
         // the release profile.
-        let signal = [base.rendering_complete_semaphore];
-        let wait = [base.present_complete_semaphore];
+        let signal = [base.frames.current().rendering_complete];
+        let wait = [base.frames.current().image_acquired];
+        // A scaled scene is blitted onto the swapchain image, which mustn't
+        // happen before it's acquired.
+        let wait_stages = if self.scaled_target.is_some() {
+            [vk::PipelineStageFlags::TRANSFER]
+        } else {
+            [vk::PipelineStageFlags::BOTTOM_OF_PIPE]
+        };
+        // Offscreen images aren't acquired or presented, only the frame's
+        // fence orders them.
+        let (wait, wait_stages, signal_semaphores) = match base.offscreen {
+            Some(_) => (&[][..], &[][..], &[][..]),
+            None => (&wait[..], &wait_stages[..], &signal[..]),
+        };
         let submit_info = vk::SubmitInfo::builder()
-            .wait_semaphores(&wait)
-            .wait_dst_stage_mask(&[vk::PipelineStageFlags::BOTTOM_OF_PIPE])
+            .wait_semaphores(wait)
+            .wait_dst_stage_mask(wait_stages)
             .command_buffers(&command_buffers)
-            .signal_semaphores(&signal);
+            .signal_semaphores(signal_semaphores);
 
-        w.end_command_buffer(base.draw_cmd_buf)?;
+        w.end_command_buffer(command_buffer)?;
 
         w.queue_submit(
-            base.draw_commands_reuse_fence,
+            base.frames.current().fence,
             base.present_queue,
             &[*submit_info],
         )?;
+        base.frames.submitted();
+        let submitted = Instant::now();
+        if base.offscreen.is_some() {
+            self.present_timings = Some(PresentTimings {
+                submitted,
+                presented: submitted,
+            });
+            return Ok(());
+        }
 
         let present_info = vk::PresentInfoKHR {
             wait_semaphore_count: 1,
-            p_wait_semaphores: &base.rendering_complete_semaphore,
+            p_wait_semaphores: &signal[0],
             swapchain_count: 1,
             p_swapchains: &base.swapchain,
             p_image_indices: &present_index,
             ..Default::default()
         };
 
-        match unsafe {
+        let presented = unsafe {
             base.swapchain_loader
                 .queue_present(base.present_queue, &present_info)
-        } {
+        };
+        self.present_timings = Some(PresentTimings {
+            submitted,
+            presented: Instant::now(),
+        });
+        match presented {
             Ok(_suboptimal @ false) => {}
             Ok(_suboptimal @ true) => {
                 base.flag_recreate_swapchain = true;
@@ -290,175 +440,1359 @@ impl Renderer {
         Ok(())
     }
 
-    /// Rebuilds pipelines and reloads shaders *from disk*.
-    // TODO: build pipeline and bindings from more rich introspection of assets.
-    fn rebuild_pipelines(&mut self, base: &mut VulkanBase) -> Result<(), RenderError> {
-        let logger = self.logger.sub("rebuild_pipelines");
-        if Instant::now().duration_since(self.last_pipeline_rebuild)
-            < Duration::from_secs(PIPELINE_REBUILD_DELAY_MILLIS)
-        {
-            info!(logger, "pipeline rebuild too soon, skipping");
+    /// Present the scene to a window besides the main one, from the window's
+    /// camera. Pipelines are shared with the main window, and it's recorded in
+    /// the next frame in flight, as though it were another frame.
+    fn present_target(
+        &mut self,
+        base: &mut VulkanBase,
+        target: &mut WindowTarget,
+        world: &World,
+    ) -> Result<(), RenderError> {
+        if target.flag_recreate_swapchain {
+            // Any frame in flight may be blitting to the old swapchain.
+            unsafe { base.device.queue_wait_idle(base.present_queue) }
+                .map_err(RenderError::VkResultToDo)?;
+            base.frames.idle();
+            target.create_swapchain(base)?;
         }
-        if !self.pipelines.is_empty() {
-            info!(logger, "destroying {} pipelines", self.pipelines.len());
-            unsafe {
-                base.device
-                    .device_wait_idle()
-                    .map_err(RenderError::VkResultToDo)?;
+        // Without a camera there's nothing to show, and an acquired image has
+        // to be presented.
+        let view = match target.camera.or(world.camera()) {
+            Some(camera_entity) => self.scene_view(world, camera_entity, target.extent)?,
+            None => return Ok(()),
+        };
+        let frame = self.begin_frame(base)?;
+        let index = match target.acquire(base, frame)? {
+            Some(index) => index,
+            None => return Ok(()),
+        };
+        let scene = target.scene.as_ref().expect("acquired with a scene target");
+        let pass = ScenePass {
+            render_pass: scene.render_pass,
+            framebuffer: scene.framebuffer,
+            extent: scene.extent,
+        };
 
-                for (_, mut pipeline) in self.pipelines.drain() {
-                    if let Some(vk_pipeline) = pipeline.vk.take() {
-                        base.device.destroy_pipeline(vk_pipeline, None);
-                    }
-                    pipeline.deallocate(&base.device);
+        // Drawables aren't culled against the main view's occluders.
+        let view = SceneView {
+            occlusion_culling: false,
+            ..view
+        };
+        self.record_scene(
+            base,
+            world,
+            &view,
+            &pass,
+            Instant::now(),
+            false,
+            false,
+            false,
+        )?;
+
+        let w = DeviceWrapper::wrap(&base.device, &self.logger);
+        let command_buffer = base.frames.current().command_buffer;
+        scene.cmd_blit_to(
+            &base.device,
+            command_buffer,
+            target.image(index),
+            target.extent,
+            vk::ImageLayout::PRESENT_SRC_KHR,
+            UpscaleFilter::Linear,
+        );
+        w.end_command_buffer(command_buffer)?;
+        let (image_acquired, blitted) = target.semaphores(frame);
+        let wait = [image_acquired];
+        let wait_stages = [vk::PipelineStageFlags::TRANSFER];
+        let signal = [blitted];
+        let command_buffers = [command_buffer];
+        let submit_info = vk::SubmitInfo::builder()
+            .wait_semaphores(&wait)
+            .wait_dst_stage_mask(&wait_stages)
+            .command_buffers(&command_buffers)
+            .signal_semaphores(&signal);
+        w.queue_submit(
+            base.frames.current().fence,
+            base.present_queue,
+            &[*submit_info],
+        )?;
+        base.frames.submitted();
+        target.present(base, index, frame)
+    }
+
+    /// Move on to the next frame in flight, once the frame that last used its
+    /// resources has completed, and return its index. Whatever that frame and
+    /// the ones before it read back is delivered, and pipelines and graphics
+    /// retired before them are destroyed.
+    fn begin_frame(&mut self, base: &mut VulkanBase) -> Result<usize, RenderError> {
+        let frame = base.frames.begin(&base.device)?;
+        self.readbacks.poll(&base.device);
+        if let Some(statistics) = self.statistics.as_mut() {
+            statistics.read();
+        }
+        if self.capture_gpu_stats && self.statistics.is_none() && base.pipeline_statistics_query {
+            self.statistics = Some(PipelineStatisticsQueries::new(
+                &base.device,
+                base.frames.len(),
+            )?);
+        }
+
+        let (completed, retired) = mem::take(&mut self.retired_pipelines)
+            .into_iter()
+            .partition::<Vec<_>, _>(|(submission, _)| base.frames.is_complete(*submission));
+        self.retired_pipelines = retired;
+        for (_, pipeline) in completed {
+            Self::destroy_pipeline(
+                base,
+                self.descriptor_pool,
+                &mut self.pipeline_cache,
+                pipeline,
+            );
+        }
+        base.destroy_retired_graphics();
+        Ok(frame)
+    }
+
+    /// The view and projection of the scene from `camera_entity`, presented
+    /// to a window of `window`'s size.
+    fn scene_view(
+        &self,
+        world: &World,
+        camera_entity: Entity,
+        window: vk::Extent2D,
+    ) -> Result<SceneView, RenderError> {
+        let entity = world
+            .hecs_world
+            .entity(camera_entity)
+            .map_err(|_| RenderError::CameraEntityMissing(camera_entity))?;
+        if !entity.has::<SpatialHierarchyNode>() {
+            return Err(RenderError::ComponentMissingFromCameraEntity(
+                camera_entity,
+                std::any::type_name::<SpatialHierarchyNode>(),
+                StableTypeId::of::<SpatialHierarchyNode>(),
+            ));
+        }
+        let camera = entity.get::<&Camera>().ok_or_else(|| {
+            RenderError::ComponentMissingFromCameraEntity(
+                camera_entity,
+                std::any::type_name::<Camera>(),
+                StableTypeId::of::<Camera>(),
+            )
+        })?;
+        let projection = match self
+            .aspect_policy
+            .projection_aspect(window.width, window.height)
+        {
+            Some(ratio) => aspect::with_aspect(camera.projection, ratio),
+            None => camera.projection,
+        };
+        Ok(SceneView {
+            view: camera.view,
+            projection,
+            view_projection: projection * camera.view,
+            occlusion_culling: camera.occlusion_culling,
+            layers: camera.layers,
+        })
+    }
+
+    /// Write the world's debug UI into the current frame's buffers, for a pass
+    /// of `extent`, returning whether there's any to draw. The UI isn't
+    /// drawn, rather than the frame failing, when its pass can't be created.
+    fn prepare_ui(&mut self, base: &mut VulkanBase, world: &World, extent: vk::Extent2D) -> bool {
+        if !world.debug_ui.is_enabled() || self.warned_ui {
+            return false;
+        }
+        if self.ui.is_none() {
+            match UiPass::new(base, &self.logger) {
+                Ok(ui) => self.ui = Some(ui),
+                Err(err) => {
+                    warn!(self.logger, "not drawing the debug UI: {err}");
+                    self.warned_ui = true;
+                    return false;
                 }
             }
         }
+        let frame_index = base.frames.index();
+        let ui = self.ui.as_mut().unwrap();
+        match ui.prepare(base, frame_index, &world.debug_ui, extent) {
+            Ok(()) => true,
+            Err(err) => {
+                warn!(self.logger, "not drawing the debug UI this frame: {err}");
+                false
+            }
+        }
+    }
 
-        // For now we are creating a pipeline per model.
-        // TODO: programmatically compose descriptor set and shader bindings from the
-        // shaders themselves:
-        // - do they have a uniform buffer?
-        // - do they have a texture/sampler etc?
-        // - compose the shaders into a pipeline and determine stages from reflected
-        //   entry points
-        for (graphics_index, (handle, _uploaded_instant)) in base.tracked_graphics.iter() {
-            info!(
-                logger,
-                "upload graphics {graphics_index:?}, vert: {} frag: {}",
-                handle.vertex_shader.path().display(),
-                handle.fragment_shader.path().display()
-            );
-            let uniform_buffer = {
-                let uniform_buffer = UniformBuffer::new();
-                let uniform_bytes = bytemuck::bytes_of(&uniform_buffer);
-                let device = DeviceWrapper::wrap(&base.device, &logger);
-                device.allocate_and_init_buffer(
-                    vk::BufferUsageFlags::UNIFORM_BUFFER,
-                    base.device_memory_properties,
-                    uniform_bytes,
-                )?
-            };
+    /// Create the deferred pass if it hasn't been, and its G-buffer for a
+    /// scene pass of `extent`, returning whether the scene can be drawn
+    /// deferred. It's drawn forward, rather than the frame failing, when the
+    /// pass can't be created.
+    fn prepare_deferred(&mut self, base: &mut VulkanBase, extent: vk::Extent2D) -> bool {
+        if self.warned_deferred {
+            return false;
+        }
+        if self.deferred.is_none() {
+            match DeferredPass::new(base, &self.logger) {
+                Ok(deferred) => self.deferred = Some(deferred),
+                Err(err) => {
+                    warn!(
+                        self.logger,
+                        "drawing the scene forward, not deferred: {err}"
+                    );
+                    self.warned_deferred = true;
+                    return false;
+                }
+            }
+        }
+        let deferred = self.deferred.as_mut().unwrap();
+        match deferred.prepare(base, extent) {
+            Ok(()) => true,
+            Err(err) => {
+                warn!(self.logger, "drawing the scene forward this frame: {err}");
+                false
+            }
+        }
+    }
 
-            // todo: take a list of shaders instead, and compose a descriptor set from them
-            let desc_set_layout =
-                base.create_descriptor_set_layout(&handle.vertex_shader, &handle.fragment_shader)?;
+    /// Record drawing the scene from `view` into `pass`, in the current
+    /// frame's command buffer, beginning it. The debug UI is drawn over it
+    /// with `draw_ui`, and it's lit deferred with `deferred`. Once the frame
+    /// has begun, as its uniforms, lights and fence are reused.
+    #[allow(clippy::too_many_arguments)]
+    fn record_scene(
+        &mut self,
+        base: &mut VulkanBase,
+        world: &World,
+        view: &SceneView,
+        pass: &ScenePass,
+        now: Instant,
+        capture_gpu_stats: bool,
+        draw_ui: bool,
+        deferred: bool,
+    ) -> Result<(), RenderError> {
+        let draw_ui = draw_ui && self.prepare_ui(base, world, pass.extent);
+        let deferred = deferred && self.prepare_deferred(base, pass.extent);
+        let clear_values = [
+            vk::ClearValue {
+                color: vk::ClearColorValue {
+                    float32: [0.0, 0.0, 0.0, 0.0],
+                },
+            },
+            vk::ClearValue {
+                depth_stencil: vk::ClearDepthStencilValue {
+                    depth: 1.0,
+                    stencil: 0,
+                },
+            },
+        ];
+        // Anything outside the viewport is left the clear color.
+        let (x, y, width, height) = self
+            .aspect_policy
+            .viewport(pass.extent.width, pass.extent.height);
+        let area = vk::Rect2D {
+            offset: vk::Offset2D {
+                x: x as i32,
+                y: y as i32,
+            },
+            extent: vk::Extent2D { width, height },
+        };
+        let scissors = VulkanBase::scissors_of(area);
+        let viewports = VulkanBase::viewports_of(area);
 
-            let descriptor_sets =
-                base.allocate_descriptor_sets(self.descriptor_pool, &[desc_set_layout])?;
+        let w = DeviceWrapper::wrap(&base.device, &self.logger);
+        let frame_index = base.frames.index();
+        let frame = base.frames.current_mut();
+        let eye = view.view.inverse().w_axis.truncate();
+        w.update_buffer(
+            &mut frame.reflection_probes,
+            bytemuck::bytes_of(&self.probe_captures.shader_probes(eye)),
+        )?;
 
-            // TODO compose a struct for containing samplers and related images
-            //let specular_sampler = bw.create_sampler()?;
-            //let bump_sampler = bw.create_sampler()?;
+        // Point lights are listed by the clusters of the view they touch, so
+        // fragments are only shaded by lights near them.
+        let mut lights = world.hecs_world.query::<(&PointLight, &WorldTransform)>();
+        let lights = lights
+            .iter()
+            .map(|(_entity, (light, transform))| ClusteredLight {
+                pos_radius: transform.get_pos().extend(light.radius),
+                color: (light.color * light.intensity).extend(1.0),
+            });
+        if let Some(stats) = self.light_clusters.assign(
+            view.view,
+            view.projection,
+            (x, y, width, height),
+            lights,
+            &mut self.clustered_lights,
+        ) {
+            trace!(
+                self.logger,
+                "clustered lights: {} lights in {} clusters, {} lights and {} assignments dropped",
+                stats.lights,
+                stats.assignments,
+                stats.dropped_lights,
+                stats.dropped_assignments
+            );
+        }
+        w.update_buffer(
+            &mut frame.clustered_lights,
+            bytemuck::bytes_of(&*self.clustered_lights),
+        )?;
+        self.update_joint_matrices(&w, frame, world)?;
+        let casts_shadows = self.update_uniform(
+            &w,
+            frame,
+            world,
+            view.view_projection,
+            eye,
+            Some(&Frustum::from_view_projection(view.view_projection)),
+            true,
+            base.max_uniform_buffer_range,
+        )?;
+        let (command_buffer, fence) = (frame.command_buffer, frame.fence);
+        let uniform_buffer = *frame.uniform.buffer;
+        DebugLineBatch::prepare(
+            &mut self.debug_lines[frame_index],
+            base,
+            self.descriptor_pool,
+            &mut self.pipeline_cache,
+            world.debug_draw.lines(),
+            &self.logger,
+        )?;
 
-            let mut maybe_diffuse_sampler = None;
-            let maybe_diffuse_image_view = handle.diffuse_map.as_ref().map(|map| map.image_view);
-            let descriptor_set = descriptor_sets[0];
+        // Drawables out of view still cast shadows into it.
+        if casts_shadows {
+            self.collect_draws(
+                base,
+                world,
+                now,
+                Some(self.uniform.light_space),
+                false,
+                view.layers,
+                DrawPass::Shadow,
+            );
+        }
+        self.collect_draws(
+            base,
+            world,
+            now,
+            Some(view.view_projection),
+            view.occlusion_culling,
+            view.layers,
+            if deferred {
+                DrawPass::Deferred
+            } else {
+                DrawPass::Forward
+            },
+        );
 
-            if handle.diffuse_map.is_some() {
-                let diffuse_sampler = base.create_sampler()?;
-                maybe_diffuse_sampler = Some(diffuse_sampler);
-            }
+        w.reset_fence(fence)?;
+        w.begin_command_buffer(command_buffer)?;
 
-            VulkanBase::update_descriptor_set(
+        // Rendered even without anything casting shadows, to clear what was
+        // cast last frame.
+        if let Some(shadow_map) = base.shadow_map.as_ref().filter(|_| casts_shadows) {
+            shadow_map.cmd_render(
                 &base.device,
-                descriptor_set,
-                &uniform_buffer,
-                maybe_diffuse_image_view,
-                // None, // model.specular_map.as_ref().map(|x| x.image_view),
-                // None, // model.bump_map.as_ref().map(|x| x.image_view),
-                maybe_diffuse_sampler,
-                // specular_sampler,
-                // bump_sampler,
+                command_buffer,
+                uniform_buffer,
+                self.uniform.light_space,
+                view.view_projection,
+                &self.shadow_draws,
+                &self.logger,
             );
+        }
 
-            let mut shader_stages = ShaderStages::new();
-            shader_stages.add_shader(
+        let render_pass_begin_info = vk::RenderPassBeginInfo::builder()
+            .render_pass(pass.render_pass)
+            .framebuffer(pass.framebuffer)
+            .render_area(pass.extent.into())
+            .clear_values(&clear_values);
+
+        // Queries aren't inherited by secondary command buffers, so draws are
+        // recorded on one thread while statistics are captured. Drawn
+        // deferred, they're recorded on one thread too, after shading the
+        // G-buffer, and statistics cover filling it.
+        let draws = self.draws.len() + self.gbuffer_draws.len();
+        let statistics = self.statistics.as_mut().filter(|_| capture_gpu_stats);
+        let threads = match statistics {
+            Some(statistics) => {
+                statistics.cmd_begin(
+                    &base.device,
+                    command_buffer,
+                    frame_index,
+                    StatsPass::Scene,
+                    draws,
+                );
+                1
+            }
+            None if deferred => 1,
+            None => SecondaryRecorder::threads_for(self.draws.len()),
+        };
+        let deferred = self.deferred.as_ref().filter(|_| deferred);
+        if let Some(deferred) = deferred {
+            deferred.cmd_fill_gbuffer(
+                &w,
+                command_buffer,
+                &self.gbuffer_draws,
+                &viewports,
+                &scissors,
+            );
+        }
+        if threads > 1 {
+            w.cmd_begin_render_pass(
+                command_buffer,
+                &render_pass_begin_info,
+                vk::SubpassContents::SECONDARY_COMMAND_BUFFERS,
+            );
+            // Everything in the subpass has to be in a secondary command
+            // buffer, the debug lines and UI are recorded after the last draws.
+            let debug_lines = self.debug_lines[frame_index].as_ref();
+            let ui = self.ui.as_ref().filter(|_| draw_ui);
+            let secondary_command_buffers = self.secondary[frame_index].record(
                 &base.device,
-                Arc::clone(&handle.vertex_shader),
-                vk::ShaderStageFlags::VERTEX,
+                pass.render_pass,
+                pass.framebuffer,
+                &self.draws,
+                &viewports,
+                &scissors,
+                threads,
+                &self.logger,
+                |w, command_buffer| {
+                    if let Some(debug_lines) = debug_lines {
+                        debug_lines.draw(w, command_buffer, frame_index, &viewports, &scissors)?;
+                    }
+                    if let Some(ui) = ui {
+                        ui.draw(w, command_buffer, frame_index);
+                    }
+                    Ok(())
+                },
             )?;
-            shader_stages.add_shader(
+            w.cmd_execute_commands(command_buffer, &secondary_command_buffers);
+        } else {
+            w.cmd_begin_render_pass(
+                command_buffer,
+                &render_pass_begin_info,
+                vk::SubpassContents::INLINE,
+            );
+            if let Some(deferred) = deferred {
+                deferred.cmd_light(&w, command_buffer, frame_index);
+            }
+            secondary::record_draws(&w, command_buffer, &self.draws, &viewports, &scissors);
+            if let Some(debug_lines) = self.debug_lines[frame_index].as_ref() {
+                debug_lines.draw(&w, command_buffer, frame_index, &viewports, &scissors)?;
+            }
+            if let Some(ui) = self.ui.as_ref().filter(|_| draw_ui) {
+                ui.draw(&w, command_buffer, frame_index);
+            }
+        }
+        trace!(self.logger, "recorded {draws} draws on {threads} threads");
+
+        w.cmd_end_render_pass(command_buffer);
+        if let Some(statistics) = self.statistics.as_mut().filter(|_| capture_gpu_stats) {
+            statistics.cmd_end(
                 &base.device,
-                Arc::clone(&handle.fragment_shader),
-                vk::ShaderStageFlags::FRAGMENT,
+                command_buffer,
+                frame_index,
+                StatsPass::Scene,
+                &mut self.readbacks,
+                fence,
+            );
+        }
+        Ok(())
+    }
+
+    /// Fill `draws` with every drawable on one of `layers` that has a
+    /// pipeline, bound to the current frame's descriptor sets. Drawables
+    /// outside the view projection's frustum are left out, and so are those
+    /// hidden behind occluders when occlusion culling, with the occluders
+    /// rasterized with the same view projection. Drawn deferred, those that
+    /// can be drawn deferred fill `gbuffer_draws` instead. For the shadow
+    /// map, only those casting shadows fill `shadow_draws`, and `draws` is
+    /// left as it is.
+    #[allow(clippy::too_many_arguments)]
+    fn collect_draws(
+        &mut self,
+        base: &VulkanBase,
+        world: &World,
+        now: Instant,
+        view_projection: Option<Mat4>,
+        occlusion_culling: bool,
+        layers: RenderLayers,
+        pass: DrawPass,
+    ) {
+        let (deferred, shadow_map) = match pass {
+            DrawPass::Forward => (None, None),
+            DrawPass::Deferred => (self.deferred.as_ref(), None),
+            DrawPass::Shadow => (None, base.shadow_map.as_ref()),
+        };
+        if pass == DrawPass::Shadow {
+            self.shadow_draws.clear();
+        } else {
+            self.draws.clear();
+            self.gbuffer_draws.clear();
+        }
+        let frame = base.frames.index();
+        let query_start = Instant::now();
+        self.extracted.extract(world, now, layers, |gfx| {
+            base.tracked_graphics
+                .get(&gfx)
+                .and_then(|tracked| tracked.handle.bounds)
+        });
+        world.query_stats.record(
+            "ash_renderer",
+            "drawables",
+            self.extracted.len(),
+            query_start.elapsed(),
+        );
+
+        if let Some(view_projection) = view_projection {
+            let culled = self
+                .extracted
+                .cull(&Frustum::from_view_projection(view_projection));
+            trace!(
+                self.logger,
+                "frustum culled {culled} of {} drawables",
+                self.extracted.len()
+            );
+        }
+        self.extracted.visible_by_graphic(&mut self.draw_order);
+
+        if let Some(view_projection) = view_projection.filter(|_| occlusion_culling) {
+            self.extracted.transform_to_clip(view_projection);
+        }
+        let extracted = &self.extracted;
+        let clip_from_model = extracted.clip_from_model();
+        for index in self.draw_order.iter().copied() {
+            let gfx = extracted.gfx[index];
+            let (tracked, desc) = match (base.tracked_graphics.get(&gfx), self.pipelines.get(&gfx))
+            {
+                (Some(tracked), Some(desc)) => (tracked, desc),
+                _ => continue,
+            };
+            let pipeline = match desc.shared.vk.as_ref() {
+                Some(pipeline) => pipeline,
+                None => continue,
+            };
+            let shadow_pipeline = shadow_map.and_then(|shadow_map| {
+                shadow_map.shadow_pipeline(base, &desc.shared, &self.logger)
+            });
+            if pass == DrawPass::Shadow && shadow_pipeline.is_none() {
+                continue;
+            }
+            let model = &tracked.handle;
+            let flags = extracted.flags[index];
+
+            // Occluders are drawn regardless, they're what's hiding everything else.
+            if let Some(bounds) = model.bounds.as_ref().filter(|_| {
+                !clip_from_model.is_empty()
+                    && !flags.intersects(RenderFlags::OCCLUDER | RenderFlags::NEVER_OCCLUDED)
+            }) {
+                if !self.occlusion.is_visible(clip_from_model[index], bounds) {
+                    continue;
+                }
+            }
+
+            let mut params = extracted.params[index].0;
+            if let Some((first, count)) = model.skin_buffer.as_ref().and_then(|_| {
+                let skinned = world.hecs_world.get::<&Skinned>(extracted.entities[index]);
+                skinned
+                    .ok()
+                    .and_then(|skinned| self.joint_ranges.get(&skinned.skeleton).copied())
+            }) {
+                params[SKIN_PARAM] = Vec4::new(first as f32, count as f32, 0.0, 0.0);
+            }
+            let draw = DrawCall {
+                pipeline: **pipeline,
+                layout: *desc.shared.layout,
+                descriptor_set: desc.descriptor_sets[frame],
+                vertex_buffer: *model.vertex_buffer.buffer,
+                skin_buffer: model
+                    .skin_buffer
+                    .as_ref()
+                    .map_or(vk::Buffer::null(), |skin| *skin.buffer),
+                index_buffer: *model.index_buffer.buffer,
+                index_count: model.index_buffer.original_len as u32,
+                push_constants: PushConstants::with_params(extracted.models[index], params),
+            };
+            if let Some(pipeline) = shadow_pipeline {
+                self.shadow_draws.push(DrawCall { pipeline, ..draw });
+                continue;
+            }
+            match deferred.and_then(|deferred| deferred.gbuffer_pipeline(base, &desc.shared)) {
+                Some(pipeline) => self.gbuffer_draws.push(DrawCall { pipeline, ..draw }),
+                None => self.draws.push(draw),
+            }
+        }
+    }
+
+    /// Gather the joint matrices of the world's skeletons into the frame's
+    /// `joint_matrices`, noting where each skeleton's are for `collect_draws`
+    /// to tell its drawables. Skeletons past the most that fit are drawn
+    /// unskinned.
+    fn update_joint_matrices(
+        &mut self,
+        w: &DeviceWrapper,
+        frame: &mut Frame,
+        world: &World,
+    ) -> Result<(), RenderError> {
+        self.joint_ranges.clear();
+        let mut count = 0;
+        let mut dropped = 0;
+        for (entity, skeleton) in world.hecs_world.query::<&Skeleton>().iter() {
+            let joints = skeleton.joint_matrices.as_slice();
+            if count + joints.len() > MAX_JOINT_MATRICES {
+                dropped += 1;
+                continue;
+            }
+            self.joint_matrices.matrices[count..count + joints.len()].copy_from_slice(joints);
+            self.joint_ranges
+                .insert(entity, (count as u32, joints.len() as u32));
+            count += joints.len();
+        }
+        if dropped > 0 && !self.warned_dropped_joints {
+            warn!(
+                self.logger,
+                "{dropped} skeletons past the most joint matrices, {MAX_JOINT_MATRICES}, are \
+                 drawn unskinned"
+            );
+            self.warned_dropped_joints = true;
+        }
+        if count > 0 {
+            w.update_buffer(
+                &mut frame.joint_matrices,
+                &self.joint_matrices.matrices[..count],
             )?;
+        }
+        Ok(())
+    }
 
-            let topology = handle.primitive_topology();
-            let mut vertex_input_assembly = VertexInputAssembly::new(topology);
+    /// Write the frame's uniform buffer for a view with `view_projection`,
+    /// with the directional and spot lights reaching into it from `eye`, as
+    /// many as the device binds. See `ViewLights::gather`. With `shadows`,
+    /// the brightest directional light casts shadows around `eye`, and
+    /// whether there's one to render the shadow map from is returned.
+    #[allow(clippy::too_many_arguments)]
+    fn update_uniform(
+        &mut self,
+        w: &DeviceWrapper,
+        frame: &mut Frame,
+        world: &World,
+        view_projection: Mat4,
+        eye: Vec3,
+        frustum: Option<&Frustum>,
+        shadows: bool,
+        max_uniform_buffer_range: u32,
+    ) -> Result<bool, RenderError> {
+        let mut directional = world
+            .hecs_world
+            .query::<(&DirectionalLight, &WorldTransform)>();
+        let mut spot = world.hecs_world.query::<(&SpotLight, &WorldTransform)>();
+        let lights = directional
+            .iter()
+            .map(|(_entity, (light, transform))| directional_light(light, transform))
+            .chain(
+                spot.iter()
+                    .map(|(_entity, (light, transform))| spot_light(light, transform)),
+            );
+        self.uniform.proj = view_projection;
+        let stats = self.view_lights.gather(
+            eye,
+            frustum,
+            UniformBuffer::lights_within(max_uniform_buffer_range as usize),
+            lights,
+            &mut self.uniform,
+        );
+        trace!(
+            self.logger,
+            "view lights: {} lights, {} culled and {} dropped",
+            stats.lights,
+            stats.culled,
+            stats.dropped
+        );
+        let casts_shadows = shadows
+            && self
+                .shadow_caster
+                .update(eye, SHADOW_MAP_RESOLUTION, &mut self.uniform);
+        w.update_buffer(&mut frame.uniform, bytemuck::bytes_of(&*self.uniform))?;
+        Ok(casts_shadows)
+    }
 
-            vertex_input_assembly.add_binding_description::<Vertex>(0, vk::VertexInputRate::VERTEX);
-            vertex_input_assembly.add_attribute_description(
-                0,
-                0,
-                vk::Format::R32G32B32A32_SFLOAT,
-                offset_of!(Vertex, pos) as u32,
+    /// Capture the world's reflection probes, if they've changed since they
+    /// were last captured or the world asked for them to be captured again.
+    /// Draws with the current frame's uniform buffers, so only called once
+    /// the frame has begun. Waits for every frame in flight, as they may be
+    /// sampling the faces, and for the capture to complete.
+    fn capture_reflection_probes(
+        &mut self,
+        base: &mut VulkanBase,
+        world: &World,
+        now: Instant,
+    ) -> Result<(), RenderError> {
+        let mut probes = world
+            .hecs_world
+            .query::<(&ReflectionProbe, &WorldTransform)>();
+        let probes = probes
+            .iter()
+            .map(|(_entity, (probe, transform))| transform.get_pos().extend(probe.radius));
+        if !self
+            .probe_captures
+            .update(world.reflection_probe_captures, probes)
+        {
+            return Ok(());
+        }
+        let dropped = self.probe_captures.dropped();
+        if dropped > 0 && !self.warned_dropped_probes {
+            warn!(
+                self.logger,
+                "{dropped} reflection probes past the most that can be captured are ignored"
             );
-            vertex_input_assembly.add_attribute_description(
-                0,
-                1,
-                vk::Format::R32G32_SFLOAT,
-                offset_of!(Vertex, uv) as u32,
+            self.warned_dropped_probes = true;
+        }
+
+        // Nothing is reflected in the probes, and point lights are clustered
+        // for the camera's view rather than the probes', so they're left out.
+        // Directional and spot lights nearest the probes light every face,
+        // only the view of the uniform buffer changes between faces.
+        let w = DeviceWrapper::wrap(&base.device, &self.logger);
+        let frame = base.frames.current_mut();
+        self.clustered_lights.clusters.fill(0);
+        w.update_buffer(
+            &mut frame.clustered_lights,
+            bytemuck::bytes_of(&*self.clustered_lights),
+        )?;
+        w.update_buffer(
+            &mut frame.reflection_probes,
+            bytemuck::bytes_of(&ReflectionProbes::zeroed()),
+        )?;
+        self.update_joint_matrices(&w, frame, world)?;
+        let probes = self.probe_captures.probes();
+        if base.reflection_probes.is_none() || probes.is_empty() {
+            return Ok(());
+        }
+        let center =
+            probes.iter().map(|probe| probe.truncate()).sum::<Vec3>() / probes.len() as f32;
+        self.update_uniform(
+            &w,
+            frame,
+            world,
+            Mat4::IDENTITY,
+            center,
+            None,
+            false,
+            base.max_uniform_buffer_range,
+        )?;
+        let uniform_buffer = *frame.uniform.buffer;
+
+        base.frames.wait_all(&base.device)?;
+        // Probes capture the scene as it is, not what only some cameras see.
+        self.collect_draws(
+            base,
+            world,
+            now,
+            None,
+            false,
+            RenderLayers::DEFAULT,
+            DrawPass::Forward,
+        );
+        let frame_index = base.frames.index();
+        let started = Instant::now();
+        let faces = base.reflection_probes.as_ref().unwrap();
+        let fence = base.setup_commands_reuse_fence;
+        VulkanBase::record_and_submit_commandbuffer(
+            &base.device,
+            base.setup_command_buffer,
+            base.setup_commands_reuse_fence,
+            base.present_queue,
+            &[],
+            &[],
+            &[],
+            |device, command_buffer| {
+                let statistics = self.statistics.as_mut().filter(|_| self.capture_gpu_stats);
+                if let Some(statistics) = statistics.as_deref_mut() {
+                    statistics.cmd_begin(
+                        device,
+                        command_buffer,
+                        frame_index,
+                        StatsPass::Probes,
+                        self.draws.len() * self.probe_captures.probes().len() * 6,
+                    );
+                }
+                faces.cmd_capture(
+                    device,
+                    command_buffer,
+                    self.probe_captures.probes(),
+                    uniform_buffer,
+                    &self.draws,
+                    &self.logger,
+                );
+                if let Some(statistics) = statistics {
+                    statistics.cmd_end(
+                        device,
+                        command_buffer,
+                        frame_index,
+                        StatsPass::Probes,
+                        &mut self.readbacks,
+                        fence,
+                    );
+                }
+            },
+        );
+        unsafe { base.device.queue_wait_idle(base.present_queue) }
+            .map_err(RenderError::VkResultToDo)?;
+        base.frames.idle();
+        self.readbacks.poll(&base.device);
+        if let Some(statistics) = self.statistics.as_mut() {
+            statistics.read();
+        }
+        info!(
+            self.logger,
+            "captured {} reflection probes in {}ms",
+            self.probe_captures.probes().len(),
+            started.elapsed().as_millis()
+        );
+        Ok(())
+    }
+
+    /// Create, resize or drop the target the scene is rendered into when it's
+    /// rendered at another resolution than the window's. The old target is
+    /// destroyed once every frame in flight, which may be using it, completes.
+    fn prepare_scaled_target(&mut self, base: &mut VulkanBase) -> Result<(), RenderError> {
+        let scale = self.scaler.scale();
+        let extent = if (scale - 1.0).abs() < f32::EPSILON {
+            None
+        } else {
+            let (width, height) = scaled_extent(
+                base.surface_resolution.width,
+                base.surface_resolution.height,
+                scale,
             );
-            vertex_input_assembly.add_attribute_description(
-                0,
-                2,
-                vk::Format::R32G32B32_SFLOAT,
-                offset_of!(Vertex, normal) as u32,
+            Some(vk::Extent2D { width, height })
+        };
+        if self.scaled_target.as_ref().map(|target| target.extent) == extent {
+            return Ok(());
+        }
+        if let Some(target) = self.scaled_target.take() {
+            base.frames.wait_all(&base.device)?;
+            target.destroy(&base.device);
+        }
+        if let Some(extent) = extent {
+            debug!(
+                self.logger,
+                "rendering at {}x{}, {scale:.2} of the window", extent.width, extent.height
             );
+            self.scaled_target = Some(ScaledTarget::new(base, extent)?);
+        }
+        Ok(())
+    }
+
+    /// Rasterize the bounds of every drawable flagged as an occluder on one of
+    /// `layers` into the occlusion buffer, for drawables to be tested against
+    /// this frame.
+    fn rasterize_occluders(
+        &mut self,
+        base: &VulkanBase,
+        world: &World,
+        view_projection: Mat4,
+        layers: RenderLayers,
+    ) {
+        self.occlusion.clear();
+        let query_start = Instant::now();
+        let mut occluders = 0;
+        for (_entity, (drawable, world_transform, flags, drawable_layers)) in world
+            .hecs_world
+            .query::<(
+                &Drawable,
+                &WorldTransform,
+                &RenderFlags,
+                Option<&RenderLayers>,
+            )>()
+            .iter()
+        {
+            // Occluders the camera doesn't see don't hide anything from it.
+            if !flags.contains(RenderFlags::OCCLUDER)
+                || flags.contains(RenderFlags::HIDDEN)
+                || !drawable_layers
+                    .copied()
+                    .unwrap_or_default()
+                    .intersects(layers)
+            {
+                continue;
+            }
+            let bounds = base
+                .tracked_graphics
+                .get(&drawable.gfx)
+                .and_then(|tracked| tracked.handle.bounds);
+            if let Some(bounds) = bounds {
+                self.occlusion
+                    .add_occluder(view_projection * world_transform.world, &bounds);
+                occluders += 1;
+            }
+        }
+        world.query_stats.record(
+            "ash_renderer",
+            "occluders",
+            occluders,
+            query_start.elapsed(),
+        );
+        self.occlusion.finish();
+    }
 
-            let w = DeviceWrapper::wrap(&base.device, &logger);
+    /// Mark the pipeline for a graphic as needing a rebuild, for instance
+    /// because it was uploaded again.
+    fn mark_pipeline_dirty(&mut self, graphics_index: Entity) {
+        if !self.dirty_pipelines.contains(&graphics_index) {
+            self.dirty_pipelines.push_back(graphics_index);
+        }
+    }
 
-            let pipeline_layout = w.pipeline_layout(
-                std::mem::size_of::<PushConstants>() as u32,
-                &[desc_set_layout],
-            )?;
+    /// Mark every tracked graphic's pipeline as needing a rebuild, building
+    /// new shared pipelines for them too.
+    fn mark_all_pipelines_dirty(&mut self, base: &VulkanBase) {
+        self.pipeline_cache.clear();
+        for graphics_index in base.tracked_graphics.keys() {
+            self.mark_pipeline_dirty(*graphics_index);
+        }
+    }
 
-            let vk = base.create_pipeline(
-                &shader_stages,
-                &base.scissors(),
-                &base.viewports(),
-                pipeline_layout,
-                primitive_to_vk_polygon_mode(handle.primitive),
-                &vertex_input_assembly,
-                base.render_pass,
-            )?;
-            let mut pipeline = Pipeline::create(
-                desc_set_layout,
-                uniform_buffer,
-                descriptor_set,
-                maybe_diffuse_sampler,
+    /// Mark the pipelines of graphics uploaded from any of `content_hashes`
+    /// as needing a rebuild, including those of reloads still pending. The
+    /// rest keep the pipelines they have.
+    fn mark_changed_pipelines_dirty(&mut self, base: &VulkanBase, content_hashes: &HashSet<u64>) {
+        let changed = base
+            .tracked_graphics
+            .iter()
+            .chain(base.pending_graphics.iter())
+            .filter(|(_, tracked)| content_hashes.contains(&tracked.content_hash))
+            .map(|(graphics_index, _)| *graphics_index)
+            .collect::<Vec<_>>();
+        for graphics_index in changed {
+            self.mark_pipeline_dirty(graphics_index);
+        }
+    }
+
+    /// Rebuild dirty pipelines, spending at most `budget` if one is given.
+    /// Anything left over is picked up on the next call. New pipelines are
+    /// built before the ones they replace are retired, and retired pipelines
+    /// are destroyed once the frames using them have completed, so a rebuild
+    /// never waits on the device.
+    // TODO: build pipeline and bindings from more rich introspection of assets.
+    fn rebuild_pipelines(
+        &mut self,
+        base: &mut VulkanBase,
+        budget: Option<Duration>,
+    ) -> Result<(), RenderError> {
+        if self.dirty_pipelines.is_empty() {
+            return Ok(());
+        }
+        let logger = self.logger.sub("rebuild_pipelines");
+        let started = Instant::now();
+        let mut rebuilt = 0;
+
+        while let Some(graphics_index) = self.dirty_pipelines.pop_front() {
+            // A reloaded graphic gets a pipeline of its own, the current one
+            // stays in use until they're swapped together.
+            if let Some(pending) = base.pending_graphics.get(&graphics_index) {
+                let pipeline = Self::build_pipeline(
+                    base,
+                    self.descriptor_pool,
+                    &mut self.pipeline_cache,
+                    graphics_index,
+                    &pending.handle,
+                    &logger,
+                )?;
+                let replaced = self.pending_pipelines.insert(graphics_index, pipeline);
+                self.retire(base, replaced);
+                rebuilt += 1;
+                if matches!(budget, Some(budget) if started.elapsed() >= budget) {
+                    break;
+                }
+                continue;
+            }
+
+            let pipeline = match base.tracked_graphics.get(&graphics_index) {
+                Some(tracked) => Some(Self::build_pipeline(
+                    base,
+                    self.descriptor_pool,
+                    &mut self.pipeline_cache,
+                    graphics_index,
+                    &tracked.handle,
+                    &logger,
+                )?),
+                // The graphic has gone away, just retire its pipeline.
+                None => None,
+            };
+            let replaced = match pipeline {
+                Some(pipeline) => self.pipelines.insert(graphics_index, pipeline),
+                None => self.pipelines.remove(&graphics_index),
+            };
+            self.retire(base, replaced);
+            rebuilt += 1;
+
+            if matches!(budget, Some(budget) if started.elapsed() >= budget) {
+                break;
+            }
+        }
+
+        info!(
+            logger,
+            "rebuilt {rebuilt} pipelines in {}µs, {} remaining, {} shared",
+            started.elapsed().as_micros(),
+            self.dirty_pipelines.len(),
+            self.pipeline_cache.len()
+        );
+
+        Ok(())
+    }
+
+    /// Replace reloaded graphics and their pipelines with the new versions once
+    /// those pipelines are built. Called at a frame boundary, so a frame only
+    /// ever draws a graphic with the pipeline built for it.
+    fn swap_in_reloaded_graphics(&mut self, base: &mut VulkanBase) {
+        let ready = self.pending_pipelines.keys().copied().collect::<Vec<_>>();
+        for graphics_index in ready {
+            let pipeline = self.pending_pipelines.remove(&graphics_index).unwrap();
+            if base.swap_in_pending_graphic(graphics_index) {
+                debug!(
+                    self.logger,
+                    "swapped in reloaded graphic {graphics_index:?}"
+                );
+                let replaced = self.pipelines.insert(graphics_index, pipeline);
+                self.retire(base, replaced);
+            } else {
+                self.retire(base, Some(pipeline));
+            }
+        }
+    }
+
+    /// Retire the pipelines of graphics that have gone away. They're destroyed
+    /// once the frames that may be drawing them have completed.
+    fn retire_pipelines(&mut self, base: &VulkanBase, entities: &[Entity]) {
+        self.dirty_pipelines
+            .retain(|graphics_index| !entities.contains(graphics_index));
+        for graphics_index in entities {
+            let removed = self.pipelines.remove(graphics_index);
+            self.retire(base, removed);
+            let removed = self.pending_pipelines.remove(graphics_index);
+            self.retire(base, removed);
+        }
+    }
+
+    /// Retire pipelines that submissions made so far may be using, to be
+    /// destroyed once they have completed.
+    fn retire(&mut self, base: &VulkanBase, pipelines: impl IntoIterator<Item = Pipeline>) {
+        let submission = base.frames.last_submission();
+        self.retired_pipelines
+            .extend(pipelines.into_iter().map(|pipeline| (submission, pipeline)));
+    }
+
+    /// Build a pipeline for a single tracked graphic, sharing the pipeline
+    /// built for graphics with the same shaders, or building one.
+    fn build_pipeline(
+        base: &VulkanBase,
+        descriptor_pool: vk::DescriptorPool,
+        pipeline_cache: &mut PipelineCache,
+        graphics_index: Entity,
+        handle: &GraphicsHandle,
+        logger: &Logger,
+    ) -> Result<Pipeline, RenderError> {
+        debug!(logger, "build pipeline {graphics_index:?}");
+        let shared = pipeline_cache.get_or_build(PipelineKey::of(handle), |key| {
+            Self::build_shared_pipeline(base, key, handle, logger)
+        })?;
+
+        // A descriptor set for each frame in flight, binding its buffers.
+        let layouts = vec![*shared.desc_set_layout; base.frames.len()];
+        let descriptor_sets = match base.allocate_descriptor_sets(descriptor_pool, &layouts) {
+            Ok(descriptor_sets) => descriptor_sets,
+            Err(err) => {
+                // Nothing was submitted with a pipeline only just built.
+                if let Some(shared) = pipeline_cache.release(shared) {
+                    shared.deallocate(&base.device);
+                }
+                return Err(err);
+            }
+        };
+
+        let mut pipeline = Pipeline::new(shared, descriptor_sets);
+        if let Err(err) = Self::bind_descriptors(base, &mut pipeline, handle) {
+            Self::destroy_pipeline(base, descriptor_pool, pipeline_cache, pipeline);
+            return Err(err);
+        }
+        Ok(pipeline)
+    }
+
+    /// Build the pipeline shared by graphics with `key`, reading the shaders
+    /// of the graphic it's first built for.
+    // TODO: programmatically compose descriptor set and shader bindings from the
+    // shaders themselves:
+    // - do they have a uniform buffer?
+    // - do they have a texture/sampler etc?
+    // - compose the shaders into a pipeline and determine stages from reflected
+    //   entry points
+    fn build_shared_pipeline(
+        base: &VulkanBase,
+        key: PipelineKey,
+        handle: &GraphicsHandle,
+        logger: &Logger,
+    ) -> Result<SharedPipeline, RenderError> {
+        let (vertex_shader, fragment_shader) = (handle.vertex_shader(), handle.fragment_shader());
+        info!(
+            logger,
+            "build shared pipeline, vert: {} frag: {}",
+            vertex_shader.path().display(),
+            fragment_shader.path().display()
+        );
+        // Shaders can only read as many push constants as every drawable gets.
+        let max = std::mem::size_of::<PushConstants>() as u32;
+        for shader in [&vertex_shader, &fragment_shader] {
+            for range in shader
+                .entry_points()
+                .iter()
+                .flat_map(|entry_point| entry_point.push_constant_ranges())
+            {
+                let size = range.offset + range.size;
+                if size > max {
+                    return Err(RenderError::PushConstantsTooLarge {
+                        shader: shader.path().to_path_buf(),
+                        size,
+                        max,
+                    });
+                }
+            }
+        }
+
+        let vertex_input_assembly = VertexInputAssembly::reflect(
+            key.topology,
+            &vertex_shader,
+            &[&[mesh_vertex_layout(), skin_vertex_layout()]],
+        )?;
+
+        // Each step destroys what the steps before it created if it fails,
+        // until there's a pipeline to destroy instead.
+        let device = &base.device;
+        // todo: take a list of shaders instead, and compose a descriptor set from them
+        let desc_set_layout =
+            Owned::new(base.create_descriptor_set_layout(&vertex_shader, &fragment_shader)?);
+
+        let w = DeviceWrapper::wrap(device, logger);
+        let pipeline_layout = match w.pipeline_layout(
+            std::mem::size_of::<PushConstants>() as u32,
+            &[*desc_set_layout],
+        ) {
+            Ok(pipeline_layout) => Owned::new(pipeline_layout),
+            Err(err) => {
+                desc_set_layout.destroy(device);
+                return Err(err);
+            }
+        };
+
+        let polygon_mode = key.polygon_mode;
+        let mut pipeline = SharedPipeline::create(
+            key,
+            desc_set_layout,
+            pipeline_layout,
+            base.viewports(),
+            base.scissors(),
+            ShaderStages::new(),
+            vertex_input_assembly,
+            polygon_mode,
+        );
+        pipeline.deferrable =
+            fragment_shader.path().file_name() == Some(OsStr::new(DEFERRABLE_FRAGMENT_SHADER));
+        if let Err(err) =
+            Self::finish_shared_pipeline(base, &mut pipeline, vertex_shader, fragment_shader)
+        {
+            pipeline.deallocate(device);
+            return Err(err);
+        }
+        Ok(pipeline)
+    }
+
+    /// Create a new shared pipeline's shader stages and the pipeline itself.
+    /// Whatever was created is destroyed with the pipeline if this fails.
+    fn finish_shared_pipeline(
+        base: &VulkanBase,
+        pipeline: &mut SharedPipeline,
+        vertex_shader: Arc<Shader>,
+        fragment_shader: Arc<Shader>,
+    ) -> Result<(), RenderError> {
+        pipeline.shader_stages.add_shader(
+            &base.device,
+            vertex_shader,
+            vk::ShaderStageFlags::VERTEX,
+        )?;
+        pipeline.shader_stages.add_shader(
+            &base.device,
+            fragment_shader,
+            vk::ShaderStageFlags::FRAGMENT,
+        )?;
+
+        let vk = base.create_pipeline(
+            &pipeline.shader_stages,
+            &pipeline.scissors,
+            &pipeline.viewports,
+            *pipeline.layout,
+            pipeline.polygon_mode,
+            &pipeline.vertex_input_assembly,
+            *base.render_pass,
+        )?;
+        pipeline.set_vk(vk);
+        Ok(())
+    }
+
+    /// Bind a new pipeline's descriptors to the graphic's resources and the
+    /// frames' buffers. Whatever was created is destroyed with the pipeline if
+    /// this fails.
+    fn bind_descriptors(
+        base: &VulkanBase,
+        pipeline: &mut Pipeline,
+        handle: &GraphicsHandle,
+    ) -> Result<(), RenderError> {
+        // TODO compose a struct for containing samplers and related images
+        //let specular_sampler = bw.create_sampler()?;
+        //let bump_sampler = bw.create_sampler()?;
+
+        // Skinning shaders read weights from a vertex buffer beside the
+        // vertices, which only skinned graphics have.
+        if pipeline
+            .shared
+            .vertex_input_assembly
+            .reads_binding(SKIN_WEIGHTS_BINDING)
+            && handle.skin_buffer.is_none()
+        {
+            return Err(RenderError::NoSkinWeights(
+                handle.vertex_shader().path().to_path_buf(),
+            ));
+        }
+
+        let maybe_diffuse_image_view = handle.diffuse_map.as_ref().map(|map| *map.image_view);
+        if handle.diffuse_map.is_some() {
+            pipeline.maybe_diffuse_sampler = Some(Owned::new(base.create_sampler()?));
+        }
+
+        // Every pipeline reading clustered lights, reflection probes, joint
+        // matrices or the shadow map shares them, shaders that don't declare
+        // them aren't given them.
+        let shaders = [handle.vertex_shader(), handle.fragment_shader()];
+        let shaders_bind = |binding: u32, descriptor_type: vk::DescriptorType| {
+            shaders
+                .iter()
+                .flat_map(|shader| shader.entry_points())
+                .flat_map(|entry_point| entry_point.desc_set_layout_bindings())
+                .any(|layout_binding| {
+                    layout_binding.binding == binding
+                        && layout_binding.descriptor_type == descriptor_type
+                })
+        };
+        let reads_clustered_lights =
+            shaders_bind(CLUSTERED_LIGHTS_BINDING, vk::DescriptorType::STORAGE_BUFFER);
+        let reads_reflection_probes = shaders_bind(
+            REFLECTION_PROBES_BINDING,
+            vk::DescriptorType::UNIFORM_BUFFER,
+        ) && shaders_bind(
+            REFLECTION_PROBE_FACES_BINDING,
+            vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+        );
+        let reads_joint_matrices =
+            shaders_bind(JOINT_MATRICES_BINDING, vk::DescriptorType::STORAGE_BUFFER);
+        let reads_shadow_map = shaders_bind(
+            SHADOW_MAP_BINDING,
+            vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+        );
+
+        for (descriptor_set, frame) in pipeline.descriptor_sets.iter().zip(base.frames.iter()) {
+            VulkanBase::update_descriptor_set(
+                &base.device,
+                *descriptor_set,
+                &frame.uniform,
+                base.max_uniform_buffer_range,
+                reads_clustered_lights.then_some(&frame.clustered_lights),
+                base.reflection_probes
+                    .as_ref()
+                    .filter(|_| reads_reflection_probes)
+                    .map(|faces| (&frame.reflection_probes, faces)),
+                reads_joint_matrices.then_some(&frame.joint_matrices),
+                base.shadow_map.as_ref().filter(|_| reads_shadow_map),
+                maybe_diffuse_image_view,
+                // None, // model.specular_map.as_ref().map(|x| x.image_view),
+                // None, // model.bump_map.as_ref().map(|x| x.image_view),
+                pipeline.maybe_diffuse_sampler.as_deref().copied(),
                 // specular_sampler,
                 // bump_sampler,
-                pipeline_layout,
-                base.viewports(),
-                base.scissors(),
-                shader_stages,
-                vertex_input_assembly,
-                primitive_to_vk_polygon_mode(handle.primitive),
             );
-            pipeline.set_vk(vk);
-            self.pipelines.insert(*graphics_index, pipeline);
         }
-
-        info!(logger, "rebuilt {} pipelines", self.pipelines.len());
-
         Ok(())
     }
 
+    /// Destroy a graphic's pipeline and return its descriptor sets to the
+    /// pool, destroying the shared pipeline too if it was the last graphic
+    /// drawn with it.
+    fn destroy_pipeline(
+        base: &VulkanBase,
+        descriptor_pool: vk::DescriptorPool,
+        pipeline_cache: &mut PipelineCache,
+        pipeline: Pipeline,
+    ) {
+        pipeline.deallocate(&base.device);
+        // Only fails for pools without FREE_DESCRIPTOR_SET.
+        let _ = unsafe {
+            base.device
+                .free_descriptor_sets(descriptor_pool, &pipeline.descriptor_sets)
+        };
+        if let Some(shared) = pipeline_cache.release(pipeline.shared) {
+            shared.deallocate(&base.device);
+        }
+    }
+
     fn deallocate(&mut self, base: &mut VulkanBase) -> Result<(), RenderError> {
         unsafe {
             base.device
                 .device_wait_idle()
                 .map_err(RenderError::VkResultToDo)?;
         }
-        for (_, desc) in self.pipelines.iter() {
-            unsafe {
-                if let Some(pipeline) = desc.vk {
-                    base.device.destroy_pipeline(pipeline, None);
-                }
-            };
-            desc.deallocate(&base.device);
+        self.dirty_pipelines.clear();
+        for (_, pipeline) in self.pipelines.drain() {
+            Self::destroy_pipeline(
+                base,
+                self.descriptor_pool,
+                &mut self.pipeline_cache,
+                pipeline,
+            );
+        }
+        for (_, pipeline) in self.retired_pipelines.drain(..) {
+            Self::destroy_pipeline(
+                base,
+                self.descriptor_pool,
+                &mut self.pipeline_cache,
+                pipeline,
+            );
+        }
+        for (_, pipeline) in self.pending_pipelines.drain() {
+            Self::destroy_pipeline(
+                base,
+                self.descriptor_pool,
+                &mut self.pipeline_cache,
+                pipeline,
+            );
+        }
+        for debug_lines in self.debug_lines.iter_mut().filter_map(Option::take) {
+            debug_lines.destroy(base, self.descriptor_pool, &mut self.pipeline_cache);
+        }
+        if let Some(ui) = self.ui.take() {
+            ui.destroy(&base.device);
+        }
+        if let Some(deferred) = self.deferred.take() {
+            deferred.destroy(&base.device);
+        }
+        if let Some(target) = self.scaled_target.take() {
+            target.destroy(&base.device);
+        }
+        for secondary in self.secondary.iter_mut() {
+            secondary.destroy(&base.device);
+        }
+        self.readbacks.destroy(&base.device);
+        if let Some(statistics) = self.statistics.take() {
+            statistics.destroy(&base.device);
         }
         unsafe {
             base.device
@@ -471,17 +1805,48 @@ impl Renderer {
 impl Presenter for VulkanRenderPluginState {
     fn present(&mut self, world: &World) {
         if let Some(renderer) = &mut self.renderer {
-            if let Err(err) = renderer.present(self.base.as_mut().unwrap(), world) {
+            let start = Instant::now();
+            let presented = renderer.present(self.base.as_mut().unwrap(), world);
+            let elapsed = start.elapsed();
+            renderer.frame_times.push(elapsed);
+            if let Err(err) = presented {
                 error!(self.logger.sub("entity"), "error in present : {:?}", err);
+                if err.vk_result().is_some() {
+                    self.report_crash(&err);
+                }
             }
         }
     }
 
+    fn present_to(&mut self, target: RenderTargetId, world: &World) {
+        let window_target = self
+            .window_targets
+            .iter_mut()
+            .find(|window_target| window_target.id == target);
+        if let (Some(renderer), Some(base), Some(window_target)) =
+            (&mut self.renderer, self.base.as_mut(), window_target)
+        {
+            if let Err(err) = renderer.present_target(base, window_target, world) {
+                error!(self.logger, "error presenting to {target}: {:?}", err);
+            }
+        }
+    }
+
+    /// Reload shaders from disk and rebuild the pipelines of graphics whose
+    /// shaders changed. A pipeline that fails to build, such as for a shader
+    /// that no longer fits the vertex layout, keeps drawing with the one it
+    /// had.
     fn update_resources(&mut self) {
         if let Some(renderer) = &mut self.renderer {
-            renderer
-                .rebuild_pipelines(self.base.as_mut().unwrap())
-                .unwrap();
+            let base = self.base.as_mut().unwrap();
+            let changed = base.reload_shaders();
+            if changed.is_empty() {
+                return;
+            }
+            renderer.mark_changed_pipelines_dirty(base, &changed);
+            if let Err(err) = renderer.rebuild_pipelines(base, Some(PIPELINE_REBUILD_BUDGET)) {
+                error!(self.logger, "unable to rebuild pipelines: {err}");
+            }
         }
     }
 
@@ -489,14 +1854,106 @@ impl Presenter for VulkanRenderPluginState {
         if let Some(renderer) = &mut self.renderer {
             renderer.deallocate(self.base.as_mut().unwrap()).unwrap();
         }
+        self.destroy_window_targets();
+    }
+
+    fn tracked_graphics(&self, entity: Entity) -> Option<Instant> {
+        let base = self.base.as_ref()?;
+        base.pending_graphics
+            .get(&entity)
+            .or_else(|| base.tracked_graphics.get(&entity))
+            .map(|tracked| tracked.uploaded_at)
+    }
+
+    fn is_reload_pending(&self, entity: Entity) -> bool {
+        self.base
+            .as_ref()
+            .is_some_and(|base| base.pending_graphics.contains_key(&entity))
+    }
+
+    fn release_graphics(&mut self, entities: &[Entity]) {
+        if let (Some(renderer), Some(base)) = (&mut self.renderer, &self.base) {
+            renderer.retire_pipelines(base, entities);
+        }
+        if let Some(base) = &mut self.base {
+            for entity in entities {
+                base.untrack_graphic(*entity);
+            }
+        }
+    }
+
+    fn tracked_entities(&self) -> Vec<Entity> {
+        self.base.as_ref().map_or_else(Vec::new, |base| {
+            base.tracked_graphics
+                .keys()
+                .chain(base.pending_graphics.keys())
+                .copied()
+                .collect()
+        })
+    }
+
+    fn occlusion_stats(&self) -> Option<OcclusionStats> {
+        self.renderer
+            .as_ref()
+            .map(|renderer| renderer.occlusion.stats())
+    }
+
+    fn present_timings(&self) -> Option<PresentTimings> {
+        self.renderer.as_ref()?.present_timings
+    }
+
+    fn graphics_memory(&self) -> Option<u64> {
+        let base = self.base.as_ref()?;
+        Some(
+            base.shared_graphics
+                .values()
+                .map(|shared| shared.handle.memory_size())
+                .sum(),
+        )
+    }
+
+    fn gpu_stats(&self) -> Option<GpuStats> {
+        let (base, renderer) = self.base.as_ref().zip(self.renderer.as_ref())?;
+        if !renderer.capture_gpu_stats {
+            return None;
+        }
+        let mut drawables = HashMap::<Entity, usize>::new();
+        for gfx in renderer.extracted.gfx.iter() {
+            *drawables.entry(*gfx).or_default() += 1;
+        }
+        let mut stats = GpuStats {
+            passes: renderer
+                .statistics
+                .as_ref()
+                .map(PipelineStatisticsQueries::passes)
+                .unwrap_or_default(),
+            graphics: base
+                .tracked_graphics
+                .iter()
+                .map(|(gfx, tracked)| GraphicMemory {
+                    gfx: *gfx,
+                    bytes: tracked.handle.memory_size(),
+                    drawables: drawables.get(gfx).copied().unwrap_or_default(),
+                })
+                .collect(),
+        };
+        stats.sort_graphics();
+        Some(stats)
     }
 
-    fn tracked_graphics(&self, entity: Entity) -> Option<Instant> {
-        self.base
-            .as_ref()?
-            .tracked_graphics
-            .get(&entity)
-            .map(|(_, tracked_instant)| *tracked_instant)
+    fn read_back_frame(&mut self) -> Option<Readback<ReadbackImage>> {
+        Some(self.renderer.as_mut()?.readbacks.request_frame())
+    }
+
+    fn capture_frame(&mut self, dir: &Path) -> Option<Readback<PathBuf>> {
+        let frame = self.renderer.as_mut()?.readbacks.request_frame();
+        let path = render::readback::screenshot_path(dir, SystemTime::now());
+        info!(
+            self.logger,
+            "capturing the next frame to {}",
+            path.display()
+        );
+        Some(self.captures.save(frame, path))
     }
 
     fn upload_graphics(&mut self, graphics: &[(Entity, &Graphic)]) -> Result<(), RenderStateError> {
@@ -508,20 +1965,58 @@ impl Presenter for VulkanRenderPluginState {
             .zip(self.renderer.as_mut())
             .ok_or(RenderStateError::NoVulkanBase)?;
 
-        for (index, handle) in base.upload_graphics(graphics, &logger) {
+        let uploads = base.upload_graphics(graphics, &logger);
+        if uploads.is_empty() {
+            return Ok(());
+        }
+        for (index, content_hash, handle) in uploads {
             info!(logger, "plugin side upload graphics: {:?}", index);
-            base.track_uploaded_graphic(index, handle);
+            // Reloaded graphics are staged, and the old version is drawn until
+            // the new pipeline is built.
+            base.track_uploaded_graphic(index, content_hash, handle);
+            renderer.mark_pipeline_dirty(index);
         }
-
-        // todo: do this only when we actually upload something
         renderer
-            .rebuild_pipelines(self.base.as_mut().unwrap())
+            .rebuild_pipelines(base, Some(PIPELINE_REBUILD_BUDGET))
             .unwrap();
 
         Ok(())
     }
 }
 
+/// Layout of the vertex buffers of models and debug meshes.
+fn mesh_vertex_layout() -> VertexLayout {
+    VertexLayout::of::<Vertex>()
+        .with_attribute(
+            0,
+            vk::Format::R32G32B32A32_SFLOAT,
+            offset_of!(Vertex, pos) as u32,
+        )
+        .with_attribute(1, vk::Format::R32G32_SFLOAT, offset_of!(Vertex, uv) as u32)
+        .with_attribute(
+            2,
+            vk::Format::R32G32B32_SFLOAT,
+            offset_of!(Vertex, normal) as u32,
+        )
+}
+
+/// Layout of the skin weights of skinned models, in a vertex buffer of
+/// their own bound at `SKIN_WEIGHTS_BINDING`.
+fn skin_vertex_layout() -> VertexLayout {
+    VertexLayout::of::<SkinWeights>()
+        .at_binding(SKIN_WEIGHTS_BINDING)
+        .with_attribute(
+            3,
+            vk::Format::R32G32B32A32_UINT,
+            offset_of!(SkinWeights, joints) as u32,
+        )
+        .with_attribute(
+            4,
+            vk::Format::R32G32B32A32_SFLOAT,
+            offset_of!(SkinWeights, weights) as u32,
+        )
+}
+
 // Simple offset_of macro akin to C++ offsetof
 #[macro_export]
 macro_rules! offset_of {
@@ -534,6 +2029,8 @@ macro_rules! offset_of {
     }};
 }
 
+/// A device and queue family able to render and present to `surface`, or
+/// only to render when it's null, offscreen.
 fn surface_loader_physical_device<'a>(
     physical_devices: &'a [vk::PhysicalDevice],
     instance: &ash::Instance,
@@ -546,14 +2043,15 @@ fn surface_loader_physical_device<'a>(
             .enumerate()
             .find_map(move |(index, info)| {
                 if info.queue_flags.contains(vk::QueueFlags::GRAPHICS)
-                    && unsafe {
-                        surface_loader.get_physical_device_surface_support(
-                            *p,
-                            index as u32,
-                            surface,
-                        )
-                    }
-                    .unwrap()
+                    && (surface == vk::SurfaceKHR::null()
+                        || unsafe {
+                            surface_loader.get_physical_device_surface_support(
+                                *p,
+                                index as u32,
+                                surface,
+                            )
+                        }
+                        .unwrap())
                 {
                     Some((p, index as u32))
                 } else {
@@ -563,15 +2061,10 @@ fn surface_loader_physical_device<'a>(
     })
 }
 
-// TODO: write a frame struct here that's to hold all the resources a frame
-// needs
-struct Frame {
-    pipelines: Vec<Pipeline>,
-}
-
 /// Carries vulkan state.
 struct VulkanBase {
-    win_ptr: platform::WinPtr,
+    /// None when frames are rendered offscreen, see `offscreen`.
+    win_ptr: Option<platform::WinPtr>,
     entry: ash::Entry,
     instance: ash::Instance,
     device: Device,
@@ -580,6 +2073,9 @@ struct VulkanBase {
 
     physical_device: vk::PhysicalDevice,
     device_memory_properties: vk::PhysicalDeviceMemoryProperties,
+    /// Most bytes of a uniform buffer a descriptor can bind, which bounds the
+    /// lights of `UniformBuffer` shaders see.
+    max_uniform_buffer_range: u32,
     queue_family_index: u32,
     present_queue: vk::Queue,
 
@@ -588,36 +2084,75 @@ struct VulkanBase {
     surface_resolution: vk::Extent2D,
 
     swapchain: vk::SwapchainKHR,
+    present_mode: vk::PresentModeKHR,
     present_images: Vec<vk::Image>,
-    present_image_views: Vec<vk::ImageView>,
+    present_image_views: Vec<Owned<vk::ImageView>>,
 
     pool: vk::CommandPool,
-    draw_cmd_buf: vk::CommandBuffer,
     setup_command_buffer: vk::CommandBuffer,
+    /// Command buffers, synchronization and buffers of each frame in flight.
+    frames: Frames,
 
-    depth_image: vk::Image,
-    depth_image_view: vk::ImageView,
-    depth_image_memory: vk::DeviceMemory,
+    depth_image: Owned<vk::Image>,
+    depth_image_view: Owned<vk::ImageView>,
+    depth_image_memory: Owned<vk::DeviceMemory>,
 
-    present_complete_semaphore: vk::Semaphore,
-    rendering_complete_semaphore: vk::Semaphore,
-
-    draw_commands_reuse_fence: vk::Fence,
     setup_commands_reuse_fence: vk::Fence,
 
     maybe_debug_utils_loader: Option<ash::extensions::ext::DebugUtils>,
     maybe_debug_call_back: Option<vk::DebugUtilsMessengerEXT>,
 
-    tracked_graphics: HashMap<Entity, (GraphicsHandle, Instant)>,
-
-    framebuffers: Vec<vk::Framebuffer>,
-    render_pass: vk::RenderPass,
-
+    tracked_graphics: HashMap<Entity, TrackedGraphic>,
+    /// Reloaded graphics waiting for their pipelines, replacing the tracked
+    /// graphic for the same entity when they're swapped in.
+    pending_graphics: HashMap<Entity, TrackedGraphic>,
+    /// Uploaded graphics by content hash, shared by every tracked graphic with
+    /// identical content.
+    shared_graphics: HashMap<u64, SharedGraphics>,
+    /// Graphics no longer tracked, with the last submission that may draw
+    /// them. They're deallocated once it has completed.
+    retired_graphics: Vec<(u64, Arc<GraphicsHandle>)>,
+
+    framebuffers: Vec<Owned<vk::Framebuffer>>,
+    render_pass: Owned<vk::RenderPass>,
+
+    /// Whether the device was created with pipeline statistics queries.
+    pipeline_statistics_query: bool,
+    /// Whether swapchain images can be copied from, to read frames back.
+    present_readable: bool,
+    /// Images frames are rendered into in place of the swapchain's, when
+    /// there's no window. Their views aren't in `present_image_views`.
+    offscreen: Option<OffscreenImages>,
+
+    /// Faces of the reflection probes, read by every pipeline whose shaders
+    /// bind `REFLECTION_PROBE_FACES_BINDING`. Created with the renderer.
+    reflection_probes: Option<ReflectionProbeFaces>,
+    /// Depth of the scene from the light casting shadows, read by every
+    /// pipeline whose shaders bind `SHADOW_MAP_BINDING`. Created with the
+    /// renderer.
+    shadow_map: Option<ShadowMap>,
+
+    /// How swapchains present frames, where the surface supports it, see
+    /// `RenderState::set_settings`.
+    requested_present_mode: PresentMode,
     flag_recreate_swapchain: bool,
 
     logger: Logger,
 
-    _debug_struct: Arc<VulkanDebug>,
+    debug_struct: Arc<VulkanDebug>,
+}
+
+/// A graphic uploaded on behalf of an entity.
+struct TrackedGraphic {
+    handle: Arc<GraphicsHandle>,
+    content_hash: u64,
+    uploaded_at: Instant,
+}
+
+/// A GraphicsHandle and the number of tracked graphics using it.
+struct SharedGraphics {
+    handle: Arc<GraphicsHandle>,
+    refs: usize,
 }
 
 impl VulkanBase {
@@ -625,103 +2160,71 @@ impl VulkanBase {
         &mut self,
         upload_queue: &[(Entity, &Graphic)],
         logger: &Logger,
-    ) -> Vec<(Entity, GraphicsHandle)> {
+    ) -> Vec<(Entity, u64, Arc<GraphicsHandle>)> {
         let logger = logger.sub("upload_graphics");
 
         if upload_queue.is_empty() {
             return vec![];
         }
 
-        let device = self.device.clone();
-        let queue = self.present_queue;
-        let queue_family_index = self.queue_family_index;
-        let device_memory_properties = self.device_memory_properties;
-        let w = DeviceWrapper::wrap(&device, &logger.sub("device"));
-        let pool = w.create_command_pool(queue_family_index).unwrap();
-        let fence = w.create_fence().unwrap();
-        let mut src_images = Vec::new();
+        // Graphics already uploaded are shared, as are duplicates within this
+        // batch, so each distinct graphic is only uploaded once.
         let mut completed_uploads = Vec::new();
+        let mut pending_uploads = Vec::new();
+        let mut distinct: Vec<(u64, &Graphic)> = Vec::new();
+        let mut seen = HashSet::new();
         for (index, graphic) in upload_queue {
-            debug!(logger, "loading graphics object at {index:?}");
-
-            let command_buffers = w.allocate_command_buffers(pool).unwrap();
-            let command_buffer = command_buffers[0];
-
-            w.wait_for_fence(fence).unwrap();
-            w.begin_command_buffer(command_buffer).unwrap();
-
-            let diffuse_map = match graphic.diffuse_color() {
-                Some(DiffuseColor::Texture(texture)) => Some(w.cmd_upload_image(
-                    texture,
-                    device_memory_properties,
-                    command_buffer,
-                    &mut src_images,
-                )),
-                None | Some(DiffuseColor::Color(_)) => None,
-            };
-
-            // let specular_map = maybe_cmd_upload_image(
-            //     &w,
-            //     model.material.specular_map.as_ref(),
-            //     device_memory_properties,
-            //     command_buffer,
-            //     &mut src_images,
-            // );
-            // let bump_map = maybe_cmd_upload_image(
-            //     &w,
-            //     model.material.bump_map.as_ref(),
-            //     device_memory_properties,
-            //     command_buffer,
-            //     &mut src_images,
-            // );
-
-            w.end_command_buffer(command_buffer).unwrap();
-
-            let submit_infos = [*vk::SubmitInfo::builder().command_buffers(&command_buffers)];
-            w.queue_submit(fence, queue, &submit_infos).unwrap();
-
-            let vertex_buffer = w
-                .allocate_and_init_buffer(
-                    vk::BufferUsageFlags::VERTEX_BUFFER,
-                    device_memory_properties,
-                    graphic.vertices(),
-                )
-                .unwrap();
-
-            let index_buffer = w
-                .allocate_and_init_buffer(
-                    vk::BufferUsageFlags::INDEX_BUFFER,
-                    device_memory_properties,
-                    graphic.indices(),
-                )
-                .unwrap();
-
-            // reflect over shaders and determine descriptor sets
-            let vertex_shader =
-                Shader::read_spv(graphic.vertex_shader_path().to_path_buf()).unwrap();
-
-            let fragment_shader =
-                Shader::read_spv(graphic.fragment_shader_path().to_path_buf()).unwrap();
-
-            let handle = GraphicsHandle::new(
-                diffuse_map,
-                vertex_buffer,
-                index_buffer,
-                vertex_shader,
-                fragment_shader,
-                graphic.primitive(),
-            );
-            completed_uploads.push((*index, handle));
-        }
-        unsafe {
-            device.device_wait_idle().unwrap();
+            let content_hash = graphic.content_hash();
+            if let Some(shared) = self.shared_graphics.get(&content_hash) {
+                debug!(
+                    logger,
+                    "graphics object at {index:?} shares existing upload {content_hash:x}"
+                );
+                completed_uploads.push((*index, content_hash, Arc::clone(&shared.handle)));
+                continue;
+            }
+            if seen.insert(content_hash) {
+                distinct.push((content_hash, *graphic));
+            }
+            pending_uploads.push((*index, content_hash));
         }
-        for image in src_images {
-            image.deallocate(&device);
+        if distinct.is_empty() {
+            return completed_uploads;
         }
+
+        let device = self.device.clone();
+        let batches = UploadBatch::record_parallel(
+            &device,
+            self.queue_family_index,
+            self.device_memory_properties,
+            &distinct,
+            &logger,
+        )
+        .unwrap();
+
+        let w = DeviceWrapper::wrap(&device, &logger.sub("device"));
+        let command_buffers = batches
+            .iter()
+            .map(|batch| batch.command_buffer)
+            .collect::<Vec<_>>();
+        let fence = w.create_fence().unwrap();
+        w.reset_fence(fence).unwrap();
+        let submit_infos = [*vk::SubmitInfo::builder().command_buffers(&command_buffers)];
+        w.queue_submit(fence, self.present_queue, &submit_infos)
+            .unwrap();
+        w.wait_for_fence(fence).unwrap();
         unsafe {
             device.destroy_fence(fence, None);
-            device.destroy_command_pool(pool, None);
+        }
+
+        let uploaded = batches
+            .into_iter()
+            .flat_map(|batch| batch.destroy_staging(&device))
+            .map(|(content_hash, handle)| (content_hash, Arc::new(handle)))
+            .collect::<HashMap<_, _>>();
+        for (index, content_hash) in pending_uploads {
+            debug!(logger, "loaded graphics object at {index:?}");
+            completed_uploads.push((index, content_hash, Arc::clone(&uploaded[&content_hash])));
         }
         completed_uploads
     }
@@ -731,27 +2234,85 @@ impl VulkanBase {
         // sets.
         //? TODO: any pool can be a thread local, but then any object must be destroyed
         //? on that thread.
-        let descriptor_pool = self.create_descriptor_pool(40, 40, 40)?;
+        // Each pipeline can read a texture, reflection probes, the shadow
+        // map, clustered lights and joint matrices, with a descriptor set for
+        // each frame in flight.
+        let frames = self.frames.len() as u32;
+        let descriptor_pool =
+            self.create_descriptor_pool(40 * frames, 120 * frames, 80 * frames, 80 * frames)?;
+        if self.reflection_probes.is_none() {
+            self.reflection_probes = Some(ReflectionProbeFaces::new(self)?);
+        }
+        if self.shadow_map.is_none() {
+            self.shadow_map = Some(ShadowMap::new(self)?);
+        }
         let mut renderer = Renderer {
             descriptor_pool,
             pipelines: HashMap::new(),
+            pipeline_cache: PipelineCache::default(),
+            dirty_pipelines: VecDeque::new(),
+            retired_pipelines: Vec::new(),
+            pending_pipelines: HashMap::new(),
+            occlusion: OcclusionBuffer::default(),
+            debug_lines: self.frames.iter().map(|_| None).collect(),
+            ui: None,
+            warned_ui: false,
+            render_path: RenderPath::default(),
+            deferred: None,
+            warned_deferred: false,
+            scaler: ScaleController::new(RenderScale::default()),
+            scaled_target: None,
+            aspect_policy: AspectPolicy::default(),
+            draws: Vec::new(),
+            gbuffer_draws: Vec::new(),
+            shadow_draws: Vec::new(),
+            extracted: ExtractedDrawables::default(),
+            draw_order: Vec::new(),
+            light_clusters: LightClusters::default(),
+            view_lights: ViewLights::default(),
+            shadow_caster: ShadowCaster::default(),
+            uniform: Box::new(UniformBuffer::new()),
+            clustered_lights: bytemuck::zeroed_box(),
+            joint_matrices: bytemuck::zeroed_box(),
+            joint_ranges: HashMap::new(),
+            warned_dropped_joints: false,
+            probe_captures: ProbeCaptures::default(),
+            warned_dropped_probes: false,
+            secondary: self
+                .frames
+                .iter()
+                .map(|_| SecondaryRecorder::new(self.queue_family_index))
+                .collect(),
+            present_timings: None,
+            frame_times: Recent::new(RECENT_FRAMES),
+            capture_gpu_stats: false,
+            statistics: None,
+            readbacks: Readbacks::new(self.device_memory_properties),
             logger: self.logger.sub("renderer"),
-            last_pipeline_rebuild: Instant::now() - Duration::from_secs(60),
         };
-        renderer.rebuild_pipelines(self)?;
+        renderer.mark_all_pipelines_dirty(self);
+        renderer.rebuild_pipelines(self, None)?;
         Ok(renderer)
     }
 
     pub fn scissors(&self) -> Vec<vk::Rect2D> {
-        vec![self.surface_resolution.into()]
+        Self::scissors_of(self.surface_resolution.into())
     }
 
     pub fn viewports(&self) -> Vec<vk::Viewport> {
+        Self::viewports_of(self.surface_resolution.into())
+    }
+
+    pub fn scissors_of(area: vk::Rect2D) -> Vec<vk::Rect2D> {
+        vec![area]
+    }
+
+    pub fn viewports_of(area: vk::Rect2D) -> Vec<vk::Viewport> {
         vec![vk::Viewport {
-            x: 0.0,
-            y: 0.0,
-            width: self.surface_resolution.width as f32,
-            height: self.surface_resolution.height as f32,
+            x: area.offset.x as f32,
+            y: area.offset.y as f32,
+            width: area.extent.width as f32,
+            height: area.extent.height as f32,
             min_depth: 0.0,
             max_depth: 1.0,
         }]
@@ -846,6 +2407,11 @@ impl VulkanBase {
         device: &ash::Device,
         descriptor_set: vk::DescriptorSet,
         uniform_buffer: &BufferAndMemory,
+        max_uniform_buffer_range: u32,
+        maybe_clustered_lights: Option<&BufferAndMemory>,
+        maybe_reflection_probes: Option<(&BufferAndMemory, &ReflectionProbeFaces)>,
+        maybe_joint_matrices: Option<&BufferAndMemory>,
+        maybe_shadow_map: Option<&ShadowMap>,
 
         // TODO: imageview + sampler struct
         maybe_diffuse_image_view: Option<vk::ImageView>,
@@ -855,9 +2421,12 @@ impl VulkanBase {
         //_specular_sampler: vk::Sampler,
         //_bump_sampler: vk::Sampler,
     ) {
+        // Devices binding less than all of the uniform buffer leave out lights
+        // at its end, which shaders don't read, see
+        // `UniformBuffer::lights_within`.
         let uniform_descriptors = [*vk::DescriptorBufferInfo::builder()
-            .buffer(uniform_buffer.buffer)
-            .range(uniform_buffer.original_len as u64)];
+            .buffer(*uniform_buffer.buffer)
+            .range((uniform_buffer.original_len as u64).min(max_uniform_buffer_range.into()))];
 
         let mut write_desc_sets = vec![*vk::WriteDescriptorSet::builder()
             .dst_set(descriptor_set)
@@ -865,6 +2434,82 @@ impl VulkanBase {
             .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
             .buffer_info(&uniform_descriptors)];
 
+        let light_descriptors = maybe_clustered_lights.map(|lights| {
+            [*vk::DescriptorBufferInfo::builder()
+                .buffer(*lights.buffer)
+                .range(lights.original_len as u64)]
+        });
+        if let Some(light_descriptors) = light_descriptors.as_ref() {
+            write_desc_sets.push(
+                *vk::WriteDescriptorSet::builder()
+                    .dst_set(descriptor_set)
+                    .dst_binding(CLUSTERED_LIGHTS_BINDING)
+                    .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                    .buffer_info(light_descriptors),
+            );
+        }
+
+        let joint_descriptors = maybe_joint_matrices.map(|joints| {
+            [*vk::DescriptorBufferInfo::builder()
+                .buffer(*joints.buffer)
+                .range(joints.original_len as u64)]
+        });
+        if let Some(joint_descriptors) = joint_descriptors.as_ref() {
+            write_desc_sets.push(
+                *vk::WriteDescriptorSet::builder()
+                    .dst_set(descriptor_set)
+                    .dst_binding(JOINT_MATRICES_BINDING)
+                    .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                    .buffer_info(joint_descriptors),
+            );
+        }
+
+        let probe_descriptors = maybe_reflection_probes.map(|(uniform, probes)| {
+            let (faces, sampler) = probes.faces();
+            (
+                [*vk::DescriptorBufferInfo::builder()
+                    .buffer(*uniform.buffer)
+                    .range(uniform.original_len as u64)],
+                [*vk::DescriptorImageInfo::builder()
+                    .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                    .image_view(faces)
+                    .sampler(sampler)],
+            )
+        });
+        if let Some((uniform_descriptors, faces_descriptors)) = probe_descriptors.as_ref() {
+            write_desc_sets.push(
+                *vk::WriteDescriptorSet::builder()
+                    .dst_set(descriptor_set)
+                    .dst_binding(REFLECTION_PROBES_BINDING)
+                    .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+                    .buffer_info(uniform_descriptors),
+            );
+            write_desc_sets.push(
+                *vk::WriteDescriptorSet::builder()
+                    .dst_set(descriptor_set)
+                    .dst_binding(REFLECTION_PROBE_FACES_BINDING)
+                    .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                    .image_info(faces_descriptors),
+            );
+        }
+
+        let shadow_descriptors = maybe_shadow_map.map(|shadow_map| {
+            let (map, sampler) = shadow_map.map();
+            [*vk::DescriptorImageInfo::builder()
+                .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                .image_view(map)
+                .sampler(sampler)]
+        });
+        if let Some(shadow_descriptors) = shadow_descriptors.as_ref() {
+            write_desc_sets.push(
+                *vk::WriteDescriptorSet::builder()
+                    .dst_set(descriptor_set)
+                    .dst_binding(SHADOW_MAP_BINDING)
+                    .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                    .image_info(shadow_descriptors),
+            );
+        }
+
         if let (Some(diffuse), Some(diffuse_sampler)) =
             (maybe_diffuse_image_view, maybe_diffuse_sampler)
         {
@@ -982,12 +2627,17 @@ impl VulkanBase {
         max_sets: u32,
         max_samplers: u32,
         max_uniform_buffers: u32,
+        max_storage_buffers: u32,
     ) -> Result<vk::DescriptorPool, RenderError> {
         let descriptor_sizes = [
             vk::DescriptorPoolSize {
                 ty: vk::DescriptorType::UNIFORM_BUFFER,
                 descriptor_count: max_uniform_buffers,
             },
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::STORAGE_BUFFER,
+                descriptor_count: max_storage_buffers,
+            },
             vk::DescriptorPoolSize {
                 ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
                 descriptor_count: max_samplers,
@@ -1001,7 +2651,9 @@ impl VulkanBase {
             //     descriptor_count: max_samplers,
             // },
         ];
+        // Pipelines are rebuilt individually, so their sets are freed individually.
         let descriptor_pool_info = vk::DescriptorPoolCreateInfo::builder()
+            .flags(vk::DescriptorPoolCreateFlags::FREE_DESCRIPTOR_SET)
             .pool_sizes(&descriptor_sizes)
             .max_sets(max_sets);
         unsafe {
@@ -1011,13 +2663,15 @@ impl VulkanBase {
         .map_err(RenderError::VkResultToDo)
     }
 
-    /// Creates a sampler.
+    /// Creates a sampler, blending between every mip level of a texture.
     pub fn create_sampler(&self) -> Result<vk::Sampler, RenderError> {
         // start preparing shader related structures
         let sampler_info = vk::SamplerCreateInfo {
             mag_filter: vk::Filter::LINEAR,
             min_filter: vk::Filter::LINEAR,
             mipmap_mode: vk::SamplerMipmapMode::LINEAR,
+            min_lod: 0.0,
+            max_lod: vk::LOD_CLAMP_NONE,
             address_mode_u: vk::SamplerAddressMode::MIRRORED_REPEAT,
             address_mode_v: vk::SamplerAddressMode::MIRRORED_REPEAT,
             address_mode_w: vk::SamplerAddressMode::MIRRORED_REPEAT,
@@ -1030,9 +2684,11 @@ impl VulkanBase {
         unsafe { self.device.create_sampler(&sampler_info, None) }
             .map_err(RenderError::VkResultToDo)
     }
-    /// Create attachments for renderpass construction
+    /// Create attachments for renderpass construction, leaving the color
+    /// attachment in `final_layout`.
     pub fn create_attachments(
         format: vk::Format,
+        final_layout: vk::ImageLayout,
     ) -> (
         Attachments,
         Vec<vk::AttachmentReference>,
@@ -1046,7 +2702,7 @@ impl VulkanBase {
                     .samples(vk::SampleCountFlags::TYPE_1)
                     .load_op(vk::AttachmentLoadOp::CLEAR)
                     .store_op(vk::AttachmentStoreOp::STORE)
-                    .final_layout(vk::ImageLayout::PRESENT_SRC_KHR),
+                    .final_layout(final_layout),
                 vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
             )
             .into_refs();
@@ -1069,13 +2725,27 @@ impl VulkanBase {
         color_attachment_refs: &[vk::AttachmentReference],
         depth_attachment_ref: &vk::AttachmentReference,
     ) -> Result<vk::RenderPass, RenderError> {
+        // Depth and off-screen color attachments are shared by every frame in
+        // flight, so a frame waits for the one before it to be done testing
+        // depth and blitting from them.
         let dependencies = [*vk::SubpassDependency::builder()
             .src_subpass(vk::SUBPASS_EXTERNAL)
-            .src_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+            .src_stage_mask(
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                    | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS
+                    | vk::PipelineStageFlags::TRANSFER,
+            )
+            .src_access_mask(vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE)
             .dst_access_mask(
-                vk::AccessFlags::COLOR_ATTACHMENT_READ | vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+                vk::AccessFlags::COLOR_ATTACHMENT_READ
+                    | vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+                    | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
+                    | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
             )
-            .dst_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)];
+            .dst_stage_mask(
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                    | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS,
+            )];
         let subpass = vk::SubpassDescription::builder()
             .color_attachments(color_attachment_refs)
             .depth_stencil_attachment(depth_attachment_ref)
@@ -1116,25 +2786,133 @@ impl VulkanBase {
         }
         Ok(framebuffers)
     }
-    /// Track a model reference for cleanup when VulkanBase is dropped.
-    fn track_uploaded_graphic(&mut self, entity: Entity, handle: GraphicsHandle) {
+    /// Track a model reference for cleanup when VulkanBase is dropped. Graphics
+    /// with the same content share a handle, which is reference counted. If the
+    /// entity was already tracked, the new graphic is staged as pending until
+    /// `swap_in_pending_graphic`, so the old one is never released while it
+    /// could still be drawn.
+    fn track_uploaded_graphic(
+        &mut self,
+        entity: Entity,
+        content_hash: u64,
+        handle: Arc<GraphicsHandle>,
+    ) {
         debug!(self.logger, "Tracking model {:?}", entity);
-        if let Some((existing_model, _instant)) = self
-            .tracked_graphics
-            .insert(entity, (handle, Instant::now()))
-        {
-            debug!(self.logger, "Deallocating existing model {:?}", entity);
-            existing_model.deallocate(self);
+        self.shared_graphics
+            .entry(content_hash)
+            .or_insert_with(|| SharedGraphics {
+                handle: Arc::clone(&handle),
+                refs: 0,
+            })
+            .refs += 1;
+        let tracked = TrackedGraphic {
+            handle,
+            content_hash,
+            uploaded_at: Instant::now(),
+        };
+        if self.tracked_graphics.contains_key(&entity) {
+            if let Some(stale) = self.pending_graphics.insert(entity, tracked) {
+                self.release_graphic(stale.content_hash);
+            }
+        } else {
+            self.tracked_graphics.insert(entity, tracked);
+        }
+    }
+
+    /// Replace the tracked graphic for an entity with its pending reload,
+    /// releasing the old one. Returns false if nothing was pending.
+    fn swap_in_pending_graphic(&mut self, entity: Entity) -> bool {
+        let pending = match self.pending_graphics.remove(&entity) {
+            Some(pending) => pending,
+            None => return false,
+        };
+        if let Some(replaced) = self.tracked_graphics.insert(entity, pending) {
+            self.release_graphic(replaced.content_hash);
+        }
+        true
+    }
+
+    /// Stop tracking an entity's graphic and any reload of it, after the
+    /// entity was despawned.
+    fn untrack_graphic(&mut self, entity: Entity) {
+        let tracked = self.tracked_graphics.remove(&entity);
+        let pending = self.pending_graphics.remove(&entity);
+        for released in tracked.into_iter().chain(pending) {
+            debug!(self.logger, "Untracking model {:?}", entity);
+            self.release_graphic(released.content_hash);
+        }
+    }
+
+    /// Read the shaders of every uploaded graphic from disk again, returning
+    /// the content hashes of those whose shaders changed. Those that can't be
+    /// read, such as while they're being written, stay as they were.
+    fn reload_shaders(&mut self) -> HashSet<u64> {
+        let mut reloaded = HashSet::new();
+        for (content_hash, shared) in self.shared_graphics.iter() {
+            match shared.handle.reload_shaders() {
+                Ok(true) => {
+                    reloaded.insert(*content_hash);
+                }
+                Ok(false) => {}
+                Err(err) => warn!(
+                    self.logger,
+                    "unable to reload shaders of graphic {content_hash:x}: {err}"
+                ),
+            }
+        }
+        info!(
+            self.logger,
+            "reloaded shaders of {} graphics",
+            reloaded.len()
+        );
+        reloaded
+    }
+
+    /// Drop a reference to a shared graphic, retiring it if it was the last.
+    /// It's deallocated once the frames that may be drawing it have
+    /// completed, see `destroy_retired_graphics`.
+    fn release_graphic(&mut self, content_hash: u64) {
+        let unused = match self.shared_graphics.get_mut(&content_hash) {
+            Some(shared) => {
+                shared.refs -= 1;
+                shared.refs == 0
+            }
+            None => false,
+        };
+        if !unused {
+            return;
+        }
+        if let Some(shared) = self.shared_graphics.remove(&content_hash) {
+            debug!(self.logger, "Retiring unused graphic {content_hash:x}");
+            let submission = self.frames.last_submission();
+            self.retired_graphics.push((submission, shared.handle));
+        }
+    }
+
+    /// Deallocate retired graphics whose last submission has completed.
+    fn destroy_retired_graphics(&mut self) {
+        let (completed, retired) = mem::take(&mut self.retired_graphics)
+            .into_iter()
+            .partition::<Vec<_>, _>(|(submission, _)| self.frames.is_complete(*submission));
+        self.retired_graphics = retired;
+        for (_, handle) in completed {
+            handle.deallocate(&self.device);
         }
     }
 
     /// Create a new instance of VulkanBase, takes a platform::WinPtr and some
     /// flags. This allows each window created by a process to be injected
-    /// into the renderer intended to bind it. Returns an error when the
-    /// instance cannot be created.
+    /// into the renderer intended to bind it, with `frames_in_flight` frames
+    /// recorded ahead of the GPU, presented as `requested_present_mode` where
+    /// supported. Without a window, frames are rendered offscreen into
+    /// images of `offscreen_size`. Returns an error when the instance cannot
+    /// be created.
     pub fn new(
-        win_ptr: platform::WinPtr,
+        win_ptr: Option<platform::WinPtr>,
+        offscreen_size: (u32, u32),
         enable_validation_layer: bool,
+        frames_in_flight: u32,
+        requested_present_mode: PresentMode,
         logger: Logger,
     ) -> Result<Self, RenderError> {
         let entry = unsafe { Entry::load() }.expect("unable to load vulkan");
@@ -1143,9 +2921,12 @@ impl VulkanBase {
             ..Default::default()
         };
 
-        let mut required_extension_names = ash_window::enumerate_required_extensions(&win_ptr)
-            .unwrap()
-            .to_vec();
+        let mut required_extension_names = match win_ptr.as_ref() {
+            Some(win_ptr) => ash_window::enumerate_required_extensions(win_ptr)
+                .unwrap()
+                .to_vec(),
+            None => Vec::new(),
+        };
 
         // TODO: make validation optional as this layer won't exist on most systems if
         // the Vulkan SDK isn't installed
@@ -1179,8 +2960,12 @@ impl VulkanBase {
             }
         };
 
-        let surface =
-            unsafe { ash_window::create_surface(&entry, &instance, &win_ptr, None) }.unwrap();
+        let surface = match win_ptr.as_ref() {
+            Some(win_ptr) => {
+                unsafe { ash_window::create_surface(&entry, &instance, win_ptr, None) }.unwrap()
+            }
+            None => vk::SurfaceKHR::null(),
+        };
 
         let physical_devices = unsafe { instance.enumerate_physical_devices() }.unwrap();
         let surface_loader = Surface::new(&entry, &instance);
@@ -1188,10 +2973,19 @@ impl VulkanBase {
             surface_loader_physical_device(&physical_devices, &instance, &surface_loader, surface)
                 .expect("couldn't find suitable device");
 
-        let device_extension_names_raw = [Swapchain::name().as_ptr()];
+        let device_extension_names_raw = match win_ptr {
+            Some(_) => vec![Swapchain::name().as_ptr()],
+            None => Vec::new(),
+        };
+        // Pipeline statistics are only captured when asked for, see
+        // `RenderState::capture_gpu_stats`, and not every device can.
+        let pipeline_statistics_query =
+            unsafe { instance.get_physical_device_features(*physical_device) }
+                .pipeline_statistics_query;
         let features = vk::PhysicalDeviceFeatures {
             shader_clip_distance: 1,
             fill_mode_non_solid: 1,
+            pipeline_statistics_query,
             ..Default::default()
         };
         let priorities = [1.0];
@@ -1208,49 +3002,79 @@ impl VulkanBase {
             unsafe { instance.create_device(*physical_device, &device_create_info, None) }.unwrap();
 
         let present_queue = unsafe { device.get_device_queue(queue_family_index, 0) };
-        let surface_format = unsafe {
-            surface_loader.get_physical_device_surface_formats(*physical_device, surface)
-        }
-        .unwrap()[0];
-        let surface_capabilities = unsafe {
-            surface_loader.get_physical_device_surface_capabilities(*physical_device, surface)
-        }
-        .unwrap();
-
-        let desired_image_count =
-            (surface_capabilities.min_image_count + 1).max(surface_capabilities.max_image_count);
-
-        let surface_resolution = surface_capabilities.current_extent;
-        let pre_transform = surface_capabilities.current_transform;
-        let present_modes = unsafe {
-            surface_loader.get_physical_device_surface_present_modes(*physical_device, surface)
-        }
-        .unwrap();
-        let present_mode = present_modes
-            .iter()
-            .cloned()
-            .find(|&mode| mode == vk::PresentModeKHR::MAILBOX)
-            .unwrap_or(vk::PresentModeKHR::FIFO);
-
-        info!(logger, "present_mode: {present_mode:?}");
-
         let swapchain_loader = Swapchain::new(&instance, &device);
-        let swapchain_create_info = *vk::SwapchainCreateInfoKHR::builder()
-            .surface(surface)
-            .min_image_count(desired_image_count)
-            .image_color_space(surface_format.color_space)
-            .image_format(surface_format.format)
-            .image_extent(surface_resolution)
-            .image_usage(vk::ImageUsageFlags::COLOR_ATTACHMENT)
-            .image_sharing_mode(vk::SharingMode::EXCLUSIVE)
-            .pre_transform(pre_transform)
-            .composite_alpha(vk::CompositeAlphaFlagsKHR::OPAQUE)
-            .present_mode(present_mode)
-            .clipped(true)
-            .image_array_layers(1);
+        let (surface_format, surface_resolution, present_mode, swapchain, present_readable) =
+            match win_ptr {
+                Some(_) => {
+                    let surface_format = unsafe {
+                        surface_loader
+                            .get_physical_device_surface_formats(*physical_device, surface)
+                    }
+                    .unwrap()[0];
+                    let surface_capabilities = unsafe {
+                        surface_loader
+                            .get_physical_device_surface_capabilities(*physical_device, surface)
+                    }
+                    .unwrap();
+
+                    let desired_image_count = (surface_capabilities.min_image_count + 1)
+                        .max(surface_capabilities.max_image_count);
 
-        let swapchain =
-            unsafe { swapchain_loader.create_swapchain(&swapchain_create_info, None) }.unwrap();
+                    let surface_resolution = surface_capabilities.current_extent;
+                    let pre_transform = surface_capabilities.current_transform;
+                    let present_modes = unsafe {
+                        surface_loader
+                            .get_physical_device_surface_present_modes(*physical_device, surface)
+                    }
+                    .unwrap();
+                    let present_mode = present_mode(&present_modes, requested_present_mode);
+
+                    info!(logger, "present_mode: {present_mode:?}");
+
+                    let swapchain_create_info = *vk::SwapchainCreateInfoKHR::builder()
+                        .surface(surface)
+                        .min_image_count(desired_image_count)
+                        .image_color_space(surface_format.color_space)
+                        .image_format(surface_format.format)
+                        .image_extent(surface_resolution)
+                        .image_usage(swapchain_image_usage(&surface_capabilities))
+                        .image_sharing_mode(vk::SharingMode::EXCLUSIVE)
+                        .pre_transform(pre_transform)
+                        .composite_alpha(vk::CompositeAlphaFlagsKHR::OPAQUE)
+                        .present_mode(present_mode)
+                        .clipped(true)
+                        .image_array_layers(1);
+
+                    let swapchain =
+                        unsafe { swapchain_loader.create_swapchain(&swapchain_create_info, None) }
+                            .unwrap();
+                    (
+                        surface_format,
+                        surface_resolution,
+                        present_mode,
+                        swapchain,
+                        swapchain_image_usage(&surface_capabilities)
+                            .contains(vk::ImageUsageFlags::TRANSFER_SRC),
+                    )
+                }
+                None => {
+                    let resolution = vk::Extent2D {
+                        width: offscreen_size.0.max(1),
+                        height: offscreen_size.1.max(1),
+                    };
+                    info!(
+                        logger,
+                        "rendering offscreen at {}x{}", resolution.width, resolution.height
+                    );
+                    let format = vk::SurfaceFormatKHR {
+                        format: OFFSCREEN_FORMAT,
+                        color_space: vk::ColorSpaceKHR::SRGB_NONLINEAR,
+                    };
+                    // Nothing waits for vertical blank offscreen.
+                    let mode = vk::PresentModeKHR::IMMEDIATE;
+                    (format, resolution, mode, vk::SwapchainKHR::null(), true)
+                }
+            };
 
         let pool_create_info = *vk::CommandPoolCreateInfo::builder()
             .flags(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER)
@@ -1258,18 +3082,43 @@ impl VulkanBase {
 
         let pool = unsafe { device.create_command_pool(&pool_create_info, None) }.unwrap();
         let command_buffer_allocate_info = *vk::CommandBufferAllocateInfo::builder()
-            .command_buffer_count(2)
+            .command_buffer_count(1)
             .command_pool(pool)
             .level(vk::CommandBufferLevel::PRIMARY);
 
         let command_buffers =
             unsafe { device.allocate_command_buffers(&command_buffer_allocate_info) }.unwrap();
         let setup_command_buffer = command_buffers[0];
-        let draw_command_buffer = command_buffers[1];
 
-        let present_images = unsafe { swapchain_loader.get_swapchain_images(swapchain) }.unwrap();
+        let device_memory_properties =
+            unsafe { instance.get_physical_device_memory_properties(*physical_device) };
+        let frames = Frames::new(
+            &device,
+            pool,
+            device_memory_properties,
+            frames_in_flight,
+            &logger,
+        )?;
+        info!(logger, "{} frames in flight", frames.len());
+
+        // Offscreen, each frame in flight renders into an image of its own.
+        let offscreen = match win_ptr {
+            Some(_) => None,
+            None => Some(OffscreenImages::new(
+                &device,
+                &device_memory_properties,
+                surface_resolution,
+                frames.len(),
+            )?),
+        };
+        let present_images = match offscreen.as_ref() {
+            Some(offscreen) => offscreen.images(),
+            None => unsafe { swapchain_loader.get_swapchain_images(swapchain) }.unwrap(),
+        };
+        // Offscreen images have views of their own.
         let present_image_views: Vec<vk::ImageView> = present_images
             .iter()
+            .filter(|_| offscreen.is_none())
             .map(|&image| {
                 let create_view_info = vk::ImageViewCreateInfo::builder()
                     .view_type(vk::ImageViewType::TYPE_2D)
@@ -1291,8 +3140,12 @@ impl VulkanBase {
                 unsafe { device.create_image_view(&create_view_info, None) }.unwrap()
             })
             .collect();
-        let device_memory_properties =
-            unsafe { instance.get_physical_device_memory_properties(*physical_device) };
+        let max_uniform_buffer_range = unsafe {
+            instance
+                .get_physical_device_properties(*physical_device)
+                .limits
+                .max_uniform_buffer_range
+        };
         let depth_image_create_info = vk::ImageCreateInfo::builder()
             .image_type(vk::ImageType::TYPE_2D)
             .format(vk::Format::D16_UNORM)
@@ -1324,8 +3177,6 @@ impl VulkanBase {
         let fence_create_info =
             *vk::FenceCreateInfo::builder().flags(vk::FenceCreateFlags::SIGNALED);
 
-        let draw_commands_reuse_fence =
-            unsafe { device.create_fence(&fence_create_info, None) }.unwrap();
         let setup_commands_reuse_fence =
             unsafe { device.create_fence(&fence_create_info, None) }.unwrap();
 
@@ -1381,19 +3232,20 @@ impl VulkanBase {
         let depth_image_view =
             unsafe { device.create_image_view(&depth_image_view_info, None) }.unwrap();
 
-        let semaphore_create_info = vk::SemaphoreCreateInfo::default();
-
-        let present_complete_semaphore =
-            unsafe { device.create_semaphore(&semaphore_create_info, None) }.unwrap();
-        let rendering_complete_semaphore =
-            unsafe { device.create_semaphore(&semaphore_create_info, None) }.unwrap();
-
-        let (attachments, color, depth) = Self::create_attachments(surface_format.format);
+        let (attachments, color, depth) = Self::create_attachments(
+            surface_format.format,
+            match offscreen {
+                Some(_) => OFFSCREEN_LAYOUT,
+                None => vk::ImageLayout::PRESENT_SRC_KHR,
+            },
+        );
         let render_pass = Self::create_render_pass(&device, attachments.all(), &color, &depth)?;
         let framebuffers = Self::create_framebuffers(
             &device,
             depth_image_view,
-            &present_image_views,
+            &offscreen
+                .as_ref()
+                .map_or_else(|| present_image_views.clone(), OffscreenImages::views),
             render_pass,
             surface_resolution,
         )
@@ -1407,44 +3259,69 @@ impl VulkanBase {
             queue_family_index,
             physical_device: *physical_device,
             device_memory_properties,
+            max_uniform_buffer_range,
             surface_loader,
             surface_format,
             present_queue,
             surface_resolution,
             swapchain_loader,
             swapchain,
+            present_mode,
             present_images,
-            present_image_views,
+            present_image_views: present_image_views.into_iter().map(Owned::new).collect(),
             pool,
-            draw_cmd_buf: draw_command_buffer,
             setup_command_buffer,
-            depth_image,
-            depth_image_view,
-            present_complete_semaphore,
-            rendering_complete_semaphore,
-            draw_commands_reuse_fence,
+            frames,
+            depth_image: Owned::new(depth_image),
+            depth_image_view: Owned::new(depth_image_view),
             setup_commands_reuse_fence,
             surface,
-            depth_image_memory,
+            depth_image_memory: Owned::new(depth_image_memory),
             maybe_debug_utils_loader,
             maybe_debug_call_back,
             tracked_graphics: HashMap::new(),
-            framebuffers,
-            render_pass,
+            pending_graphics: HashMap::new(),
+            shared_graphics: HashMap::new(),
+            retired_graphics: Vec::new(),
+            framebuffers: framebuffers.into_iter().map(Owned::new).collect(),
+            render_pass: Owned::new(render_pass),
+            pipeline_statistics_query: pipeline_statistics_query == vk::TRUE,
+            present_readable,
+            offscreen,
+            reflection_probes: None,
+            shadow_map: None,
+            requested_present_mode,
             flag_recreate_swapchain: false,
             logger,
-            _debug_struct: debug,
+            debug_struct: debug,
         })
     }
 
+    /// Layout frames are left in once they're rendered, ready to present, or
+    /// to read back when they're rendered offscreen.
+    fn present_layout(&self) -> vk::ImageLayout {
+        match self.offscreen {
+            Some(_) => OFFSCREEN_LAYOUT,
+            None => vk::ImageLayout::PRESENT_SRC_KHR,
+        }
+    }
+
     /// Re-create the swapchain bound. Useful when window properties change, on
     /// resize, fullscreen, focus, etc.
     pub fn recreate_swapchain(&mut self) -> Result<(), RenderError> {
+        let win_ptr = match self.win_ptr.as_ref() {
+            Some(win_ptr) => win_ptr,
+            None => {
+                // Offscreen images keep their size, and aren't presented.
+                self.flag_recreate_swapchain = false;
+                return Ok(());
+            }
+        };
         let surface_loader = Surface::new(&self.entry, &self.instance);
         let old_surface_loader = mem::replace(&mut self.surface_loader, surface_loader);
 
         let surface =
-            unsafe { ash_window::create_surface(&self.entry, &self.instance, &self.win_ptr, None) }
+            unsafe { ash_window::create_surface(&self.entry, &self.instance, win_ptr, None) }
                 .map_err(RenderError::VkResultToDo)?;
         let old_surface = mem::replace(&mut self.surface, surface);
 
@@ -1472,6 +3349,8 @@ impl VulkanBase {
 
         println!("recreate_swapchain with surface resolution {surface_resolution:?}");
         self.surface_resolution = surface_resolution;
+        self.present_readable = swapchain_image_usage(&surface_capabilities)
+            .contains(vk::ImageUsageFlags::TRANSFER_SRC);
 
         let pre_transform = surface_capabilities.current_transform;
         let present_modes = unsafe {
@@ -1480,12 +3359,9 @@ impl VulkanBase {
         }
         .unwrap();
 
-        let present_mode = present_modes
-            .iter()
-            .cloned()
-            .find(|&mode| mode == vk::PresentModeKHR::MAILBOX)
-            .unwrap_or(vk::PresentModeKHR::FIFO);
+        let present_mode = present_mode(&present_modes, self.requested_present_mode);
         println!("recreate with present mode {present_mode:?}");
+        self.present_mode = present_mode;
         let swapchain_loader = Swapchain::new(&self.instance, &self.device);
         let old_swapchain_loader = mem::replace(&mut self.swapchain_loader, swapchain_loader);
 
@@ -1495,7 +3371,7 @@ impl VulkanBase {
             .image_color_space(self.surface_format.color_space)
             .image_format(self.surface_format.format)
             .image_extent(self.surface_resolution)
-            .image_usage(vk::ImageUsageFlags::COLOR_ATTACHMENT)
+            .image_usage(swapchain_image_usage(&surface_capabilities))
             .image_sharing_mode(vk::SharingMode::EXCLUSIVE)
             .pre_transform(pre_transform)
             .composite_alpha(vk::CompositeAlphaFlagsKHR::OPAQUE)
@@ -1545,6 +3421,7 @@ impl VulkanBase {
                     .map_err(RenderError::VkResultToDo)
                     .unwrap()
             })
+            .map(Owned::new)
             .collect();
         let old_present_image_views =
             mem::replace(&mut self.present_image_views, present_image_views);
@@ -1564,10 +3441,10 @@ impl VulkanBase {
             .sharing_mode(vk::SharingMode::EXCLUSIVE);
         let depth_image =
             unsafe { self.device.create_image(&depth_image_create_info, None) }.unwrap();
-        let old_depth_image = mem::replace(&mut self.depth_image, depth_image);
+        let old_depth_image = mem::replace(&mut self.depth_image, Owned::new(depth_image));
 
         let depth_image_memory_req =
-            unsafe { self.device.get_image_memory_requirements(self.depth_image) };
+            unsafe { self.device.get_image_memory_requirements(depth_image) };
         let depth_image_memory_index = Self::find_memorytype_index(
             &depth_image_memory_req,
             &self.device_memory_properties,
@@ -1586,10 +3463,11 @@ impl VulkanBase {
         .map_err(RenderError::VkResultToDo)?;
         unsafe {
             self.device
-                .bind_image_memory(self.depth_image, depth_image_memory, 0)
+                .bind_image_memory(depth_image, depth_image_memory, 0)
         }
         .map_err(RenderError::VkResultToDo)?;
-        let old_depth_image_memory = mem::replace(&mut self.depth_image_memory, depth_image_memory);
+        let old_depth_image_memory =
+            mem::replace(&mut self.depth_image_memory, Owned::new(depth_image_memory));
 
         Self::record_and_submit_commandbuffer(
             &self.device,
@@ -1643,35 +3521,46 @@ impl VulkanBase {
         let depth_image_view =
             unsafe { self.device.create_image_view(&depth_image_view_info, None) }
                 .map_err(RenderError::VkResultToDo)?;
-        let old_depth_image_view = mem::replace(&mut self.depth_image_view, depth_image_view);
+        let old_depth_image_view =
+            mem::replace(&mut self.depth_image_view, Owned::new(depth_image_view));
 
-        let (attachments, color, depth) = Self::create_attachments(self.surface_format.format);
+        let (attachments, color, depth) =
+            Self::create_attachments(self.surface_format.format, vk::ImageLayout::PRESENT_SRC_KHR);
         let render_pass =
             Self::create_render_pass(&self.device, attachments.all(), &color, &depth)?;
-        let old_render_pass = mem::replace(&mut self.render_pass, render_pass);
+        let old_render_pass = mem::replace(&mut self.render_pass, Owned::new(render_pass));
+        let present_image_views = self
+            .present_image_views
+            .iter()
+            .map(|view| **view)
+            .collect::<Vec<_>>();
         let framebuffers = Self::create_framebuffers(
             &self.device,
-            self.depth_image_view,
-            &self.present_image_views,
-            self.render_pass,
+            depth_image_view,
+            &present_image_views,
+            render_pass,
             self.surface_resolution,
         )
         .unwrap();
-        let old_framebuffers = mem::replace(&mut self.framebuffers, framebuffers);
+        let old_framebuffers = mem::replace(
+            &mut self.framebuffers,
+            framebuffers.into_iter().map(Owned::new).collect(),
+        );
 
+        println!("cleaning up old swapchain");
+        unsafe { self.device.device_wait_idle() }.unwrap();
+        self.frames.idle();
+        for framebuffer in old_framebuffers.iter() {
+            framebuffer.destroy(&self.device);
+        }
+        old_render_pass.destroy(&self.device);
+        old_depth_image_view.destroy(&self.device);
+        old_depth_image.destroy(&self.device);
+        old_depth_image_memory.destroy(&self.device);
+        for old_image_view in old_present_image_views.iter() {
+            old_image_view.destroy(&self.device);
+        }
         unsafe {
-            println!("cleaning up old swapchain");
-            self.device.device_wait_idle().unwrap();
-            self.device.free_memory(old_depth_image_memory, None);
-            self.device.destroy_image_view(old_depth_image_view, None);
-            self.device.destroy_image(old_depth_image, None);
-            for &old_image_view in old_present_image_views.iter() {
-                self.device.destroy_image_view(old_image_view, None);
-            }
-            for framebuffer in old_framebuffers.iter() {
-                self.device.destroy_framebuffer(*framebuffer, None);
-            }
-            self.device.destroy_render_pass(old_render_pass, None);
             old_swapchain_loader.destroy_swapchain(old_swapchain, None);
             old_surface_loader.destroy_surface(old_surface, None);
         }
@@ -1742,39 +3631,61 @@ impl Drop for VulkanBase {
     fn drop(&mut self) {
         unsafe {
             self.device.device_wait_idle().unwrap();
-            self.device
-                .destroy_semaphore(self.present_complete_semaphore, None);
-            self.device
-                .destroy_semaphore(self.rendering_complete_semaphore, None);
-            self.device
-                .destroy_fence(self.draw_commands_reuse_fence, None);
+            self.frames.destroy(&self.device);
             self.device
                 .destroy_fence(self.setup_commands_reuse_fence, None);
 
-            let tracked_models: Vec<_> = self.tracked_graphics.drain().collect();
-            for (_index, (gpu_model, _instant)) in tracked_models {
-                gpu_model.deallocate(self);
+            self.tracked_graphics.clear();
+            self.pending_graphics.clear();
+            let shared_models: Vec<_> = self.shared_graphics.drain().collect();
+            for (_, handle) in mem::take(&mut self.retired_graphics) {
+                handle.deallocate(&self.device);
             }
-
-            self.device.free_memory(self.depth_image_memory, None);
-            self.device.destroy_image_view(self.depth_image_view, None);
-            self.device.destroy_image(self.depth_image, None);
-            for &image_view in self.present_image_views.iter() {
-                self.device.destroy_image_view(image_view, None);
+            for (_content_hash, shared) in shared_models {
+                shared.handle.deallocate(&self.device);
+            }
+            if let Some(reflection_probes) = self.reflection_probes.take() {
+                reflection_probes.destroy(&self.device);
+            }
+            if let Some(shadow_map) = self.shadow_map.take() {
+                shadow_map.destroy(&self.device);
             }
 
             for framebuffer in self.framebuffers.iter() {
-                self.device.destroy_framebuffer(*framebuffer, None);
+                framebuffer.destroy(&self.device);
+            }
+            self.render_pass.destroy(&self.device);
+            self.depth_image_view.destroy(&self.device);
+            self.depth_image.destroy(&self.device);
+            self.depth_image_memory.destroy(&self.device);
+            for image_view in self.present_image_views.iter() {
+                image_view.destroy(&self.device);
+            }
+            if let Some(offscreen) = self.offscreen.take() {
+                offscreen.destroy(&self.device);
+            }
+
+            // Everything created with the device has to be destroyed before it.
+            let leaks = resource::take_leaks();
+            if !leaks.is_empty() {
+                error!(
+                    self.logger,
+                    "Vulkan resources dropped without being destroyed: {leaks:?}"
+                );
             }
-            self.device.destroy_render_pass(self.render_pass, None);
 
             self.device.destroy_command_pool(self.pool, None);
 
-            self.swapchain_loader
-                .destroy_swapchain(self.swapchain, None);
+            // Offscreen, neither the swapchain nor surface extension is loaded.
+            if self.win_ptr.is_some() {
+                self.swapchain_loader
+                    .destroy_swapchain(self.swapchain, None);
+            }
 
             self.device.destroy_device(None);
-            self.surface_loader.destroy_surface(self.surface, None);
+            if self.win_ptr.is_some() {
+                self.surface_loader.destroy_surface(self.surface, None);
+            }
 
             if let Some((debug_utils, call_back)) = Option::zip(
                 self.maybe_debug_utils_loader.take(),
@@ -1792,6 +3703,17 @@ pub struct VulkanRenderPluginState {
     _win_ptr: Option<WinPtr>,
     base: Option<VulkanBase>,
     renderer: Option<Renderer>,
+    /// Windows rendered to besides the main one, see `RenderState::targets`.
+    window_targets: Vec<WindowTarget>,
+    /// Targets that couldn't be rendered to, not tried again.
+    failed_targets: Vec<RenderTargetId>,
+    /// Screenshots being saved, see `Presenter::capture_frame`.
+    captures: FrameCaptures,
+    /// Where crash reports are written, see `RenderState::crash_report_dir`.
+    crash_report_dir: PathBuf,
+    /// Whether a crash report was written, only the first failure is
+    /// reported.
+    crash_reported: bool,
     logger: Logger,
 }
 
@@ -1807,16 +3729,28 @@ impl VulkanRenderPluginState {
 
         info!(logger, "loaded ash_renderer_system...");
 
+        // The swapchain is created with the settings as they are now.
+        state.take_swapchain_recreation();
         let mut base = VulkanBase::new(
             state.win_ptr,
+            state.window_size.drawable,
             state.enable_validation_layer,
+            state.frames_in_flight,
+            state.settings().present_mode,
             logger.sub("vulkan-base"),
         )
         .expect("unable to create VulkanBase");
 
         info!(logger, "initialized vulkan base");
 
-        self.renderer = Some(base.renderer().expect("unable to setup renderer"));
+        let mut renderer = base.renderer().expect("unable to setup renderer");
+        renderer.scaler.configure(&state.render_scale);
+        renderer.aspect_policy = state.aspect_policy;
+        renderer.capture_gpu_stats = state.capture_gpu_stats;
+        renderer.render_path = state.render_path;
+        self.renderer = Some(renderer);
+        self.crash_report_dir = state.crash_report_dir.clone();
+        self.crash_reported = false;
         info!(logger, "set presenter");
 
         self.base = Some(base);
@@ -1824,7 +3758,62 @@ impl VulkanRenderPluginState {
         info!(logger, "set base");
     }
 
+    /// Write a crash report for `err`, the first time presenting fails with
+    /// an unexpected result from Vulkan, and log where it was written.
+    fn report_crash(&mut self, err: &RenderError) {
+        if self.crash_reported {
+            return;
+        }
+        self.crash_reported = true;
+        let (renderer, base) = match (self.renderer.as_ref(), self.base.as_ref()) {
+            (Some(renderer), Some(base)) => (renderer, base),
+            _ => return,
+        };
+        let report = crash_report(renderer, base, err);
+        match report.write(&self.crash_report_dir, SystemTime::now()) {
+            Ok(path) => error!(
+                self.logger,
+                "error in present: {err:?}, crash report written to {}",
+                path.display()
+            ),
+            Err(write_err) => error!(
+                self.logger,
+                "error in present: {err:?}, unable to write crash report to {}: {write_err}",
+                self.crash_report_dir.display()
+            ),
+        }
+    }
+
     pub fn update(&mut self, state: &mut RenderState, _dt: &Duration) {
+        if let Some(renderer) = self.renderer.as_mut() {
+            renderer.scaler.configure(&state.render_scale);
+            if let Some(frame_time) = state.frame_time.take() {
+                if renderer.scaler.record_frame(frame_time) {
+                    debug!(
+                        self.logger,
+                        "render scale adjusted to {:.2}",
+                        renderer.scaler.scale()
+                    );
+                }
+            }
+            renderer.aspect_policy = state.aspect_policy;
+            renderer.capture_gpu_stats = state.capture_gpu_stats;
+            renderer.render_path = state.render_path;
+            // Deliver readbacks while frames aren't presented, too.
+            if let Some(base) = self.base.as_ref() {
+                renderer.readbacks.poll(&base.device);
+            }
+        }
+        if state.take_swapchain_recreation() {
+            if let Some(base) = self.base.as_mut() {
+                base.requested_present_mode = state.settings().present_mode;
+                base.flag_recreate_swapchain = true;
+            }
+            for window_target in self.window_targets.iter_mut() {
+                window_target.flag_recreate_swapchain = true;
+            }
+        }
+        self.sync_window_targets(state);
         // let (state, world) = state;
         // Call render, buffers are updated etc
         // if let Some(renderer) = self.renderer.as_mut() {
@@ -1842,6 +3831,72 @@ impl VulkanRenderPluginState {
         if let Some(presenter) = &mut self.renderer {
             presenter.deallocate(self.base.as_mut().unwrap()).unwrap();
         }
+        self.destroy_window_targets();
+    }
+
+    /// Create window targets added to `state` since the last update, destroy
+    /// those removed, and pick up changes to the rest.
+    fn sync_window_targets(&mut self, state: &RenderState) {
+        let base = match self.base.as_ref() {
+            Some(base) => base,
+            None => return,
+        };
+        let (kept, removed): (Vec<_>, Vec<_>) = mem::take(&mut self.window_targets)
+            .into_iter()
+            .partition(|window_target| state.targets.get(window_target.id).is_some());
+        self.window_targets = kept;
+        if !removed.is_empty() {
+            // The last frames presented to them may still be in flight.
+            if let Err(err) = unsafe { base.device.device_wait_idle() } {
+                error!(self.logger, "unable to wait for the device: {:?}", err);
+            }
+            for window_target in removed {
+                info!(self.logger, "no longer rendering to {}", window_target.id);
+                window_target.destroy(base);
+            }
+        }
+        for target in state.targets.iter() {
+            let existing = self
+                .window_targets
+                .iter_mut()
+                .find(|window_target| window_target.id == target.id);
+            if let Some(window_target) = existing {
+                window_target.configure(target);
+            } else if self.failed_targets.contains(&target.id) {
+                continue;
+            } else if base.offscreen.is_some() {
+                // The instance was created without the extensions surfaces
+                // need.
+                error!(
+                    self.logger,
+                    "unable to render to {}: rendering offscreen", target.id
+                );
+                self.failed_targets.push(target.id);
+            } else {
+                match WindowTarget::new(base, target) {
+                    Ok(window_target) => {
+                        info!(self.logger, "rendering to {}", target.id);
+                        self.window_targets.push(window_target);
+                    }
+                    Err(err) => {
+                        error!(self.logger, "unable to render to {}: {:?}", target.id, err);
+                        self.failed_targets.push(target.id);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Destroy every window target, once nothing in flight uses them.
+    fn destroy_window_targets(&mut self) {
+        if let Some(base) = self.base.as_ref() {
+            if let Err(err) = unsafe { base.device.device_wait_idle() } {
+                error!(self.logger, "unable to wait for the device: {:?}", err);
+            }
+            for window_target in self.window_targets.drain(..) {
+                window_target.destroy(base);
+            }
+        }
     }
 }
 
@@ -1881,6 +3936,114 @@ fn merge_vertex_and_fragment_bindings<'a>(
     merged.into_iter().map(|(_, binding)| binding)
 }
 
+/// Swapchain images are rendered and blitted to, and copied from to read
+/// frames back where the surface allows.
+fn swapchain_image_usage(capabilities: &vk::SurfaceCapabilitiesKHR) -> vk::ImageUsageFlags {
+    let usage = vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_DST;
+    usage | (capabilities.supported_usage_flags & vk::ImageUsageFlags::TRANSFER_SRC)
+}
+
+/// Gather what the renderer and device are doing into a report for `err`.
+fn crash_report(renderer: &Renderer, base: &VulkanBase, err: &RenderError) -> CrashReport {
+    let mut report = CrashReport::new(format!("{err} ({err:?})"));
+
+    let properties = unsafe {
+        base.instance
+            .get_physical_device_properties(base.physical_device)
+    };
+    report.section(
+        "device",
+        [
+            diagnose::c_chars_to_string(&properties.device_name),
+            format!(
+                "api {}, driver {:#x}",
+                diagnose::version_string(properties.api_version),
+                properties.driver_version
+            ),
+        ],
+    );
+
+    report.section(
+        "swapchain",
+        [
+            format!(
+                "format {:?}, {:?}",
+                base.surface_format.format, base.surface_format.color_space
+            ),
+            format!(
+                "resolution {}x{}",
+                base.surface_resolution.width, base.surface_resolution.height
+            ),
+            format!(
+                "present mode {:?}, {} requested",
+                base.present_mode, base.requested_present_mode
+            ),
+            format!(
+                "{} images, {} frames in flight",
+                base.present_images.len(),
+                base.frames.len()
+            ),
+        ],
+    );
+
+    let mut pipelines = vec![format!(
+        "{} pipelines, {} pending, {} dirty, {} retired",
+        renderer.pipelines.len(),
+        renderer.pending_pipelines.len(),
+        renderer.dirty_pipelines.len(),
+        renderer.retired_pipelines.len()
+    )];
+    pipelines.extend(
+        renderer
+            .pipeline_cache
+            .iter()
+            .map(|(key, users)| format!("{key:?} used by {users}")),
+    );
+    report.section("pipelines", pipelines);
+
+    let mut tracked: Vec<_> = base.tracked_graphics.iter().collect();
+    tracked.sort_by_key(|(entity, _)| **entity);
+    report.section(
+        "tracked graphics",
+        tracked.into_iter().map(|(entity, tracked)| {
+            let handle = &tracked.handle;
+            format!(
+                "{entity:?} content {:#018x}, {} vertex and {} index bytes, shaders {} {}",
+                tracked.content_hash,
+                handle.vertex_buffer.allocation_size,
+                handle.index_buffer.allocation_size,
+                handle.vertex_shader().path().display(),
+                handle.fragment_shader().path().display()
+            )
+        }),
+    );
+
+    let messages = match base.debug_struct.recent_messages.lock() {
+        Ok(recent) => recent.iter().cloned().collect(),
+        Err(_) => vec!["unavailable".to_string()],
+    };
+    report.section("validation messages", messages);
+
+    report.section("frame times", frame_time_lines(&renderer.frame_times));
+    report
+}
+
+/// Present mode swapchains are created with: the first of `requested`'s
+/// preference the surface supports. Falls back to queueing frames for
+/// vertical blank, which every surface supports.
+fn present_mode(modes: &[vk::PresentModeKHR], requested: PresentMode) -> vk::PresentModeKHR {
+    requested
+        .preference()
+        .iter()
+        .map(|mode| match mode {
+            PresentMode::Fifo => vk::PresentModeKHR::FIFO,
+            PresentMode::Mailbox => vk::PresentModeKHR::MAILBOX,
+            PresentMode::Immediate => vk::PresentModeKHR::IMMEDIATE,
+        })
+        .find(|mode| modes.contains(mode))
+        .unwrap_or(vk::PresentModeKHR::FIFO)
+}
+
 fn primitive_to_vk_polygon_mode(primitive: Primitive) -> vk::PolygonMode {
     match primitive {
         Primitive::PointList => vk::PolygonMode::POINT,
@@ -0,0 +1,71 @@
+//! Images frames are rendered into when there's no window, such as on a
+//! dedicated server or in golden image tests, see `RenderState::offscreen`.
+//! They take the place of the swapchain's images, one for each frame in
+//! flight, and instead of being presented they're left to be read back with
+//! `Presenter::read_back_frame`.
+
+use ash::{vk, Device};
+
+use crate::scaled_target::TargetImage;
+use crate::types::RenderError;
+
+/// Format of offscreen images, read back as RGBA without swizzling.
+pub(crate) const OFFSCREEN_FORMAT: vk::Format = vk::Format::R8G8B8A8_UNORM;
+
+/// Layout offscreen images are left in once a frame is rendered, where a
+/// swapchain image would be left ready to present.
+pub(crate) const OFFSCREEN_LAYOUT: vk::ImageLayout = vk::ImageLayout::TRANSFER_SRC_OPTIMAL;
+
+pub(crate) struct OffscreenImages {
+    images: Vec<TargetImage>,
+}
+
+impl OffscreenImages {
+    /// Create `count` images of `extent`, rendered to, blitted to and copied
+    /// from.
+    pub fn new(
+        device: &Device,
+        memory_properties: &vk::PhysicalDeviceMemoryProperties,
+        extent: vk::Extent2D,
+        count: usize,
+    ) -> Result<Self, RenderError> {
+        let mut images = Vec::with_capacity(count);
+        for _ in 0..count {
+            let image = TargetImage::with_device(
+                device,
+                memory_properties,
+                extent,
+                1,
+                OFFSCREEN_FORMAT,
+                vk::ImageUsageFlags::COLOR_ATTACHMENT
+                    | vk::ImageUsageFlags::TRANSFER_SRC
+                    | vk::ImageUsageFlags::TRANSFER_DST,
+                vk::ImageAspectFlags::COLOR,
+            );
+            match image {
+                Ok(image) => images.push(image),
+                Err(err) => {
+                    for image in images {
+                        unsafe { image.destroy(device) };
+                    }
+                    return Err(err);
+                }
+            }
+        }
+        Ok(Self { images })
+    }
+
+    pub fn images(&self) -> Vec<vk::Image> {
+        self.images.iter().map(|image| image.image).collect()
+    }
+
+    pub fn views(&self) -> Vec<vk::ImageView> {
+        self.images.iter().map(|image| image.view).collect()
+    }
+
+    pub unsafe fn destroy(&self, device: &Device) {
+        for image in self.images.iter() {
+            image.destroy(device);
+        }
+    }
+}
@@ -0,0 +1,99 @@
+//! Pipelines shared between graphics. Graphics drawn with the same shaders,
+//! topology and vertex layout only differ in what their descriptor sets bind,
+//! so they share a single pipeline and descriptor set layout, built for the
+//! first of them and destroyed once the last is.
+//!
+//! Graphics hold their shared pipeline with an `Arc`, which the cache also
+//! holds while the pipeline can be handed out. Releasing the last graphic's
+//! hold gives the pipeline back to be destroyed.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use ash::vk;
+use gfx::Vertex;
+use stable_typeid::StableTypeId;
+
+use crate::device::GraphicsHandle;
+use crate::primitive_to_vk_polygon_mode;
+use crate::types::{RenderError, SharedPipeline};
+
+/// What a shared pipeline is built from.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct PipelineKey {
+    /// `Shader::content_hash` of the vertex shader, so a reloaded shader with
+    /// new code gets a pipeline of its own.
+    pub vertex_shader: u64,
+    pub fragment_shader: u64,
+    pub topology: vk::PrimitiveTopology,
+    pub polygon_mode: vk::PolygonMode,
+    /// Type of the vertices in the vertex buffer.
+    pub vertex_layout: StableTypeId,
+}
+
+impl PipelineKey {
+    pub fn of(handle: &GraphicsHandle) -> Self {
+        Self {
+            vertex_shader: handle.vertex_shader().content_hash(),
+            fragment_shader: handle.fragment_shader().content_hash(),
+            topology: handle.primitive_topology(),
+            polygon_mode: primitive_to_vk_polygon_mode(handle.primitive),
+            vertex_layout: StableTypeId::of::<Vertex>(),
+        }
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct PipelineCache {
+    pipelines: HashMap<PipelineKey, Arc<SharedPipeline>>,
+}
+
+impl PipelineCache {
+    /// The pipeline for graphics with `key`, built with `build` if there isn't
+    /// one yet. Every pipeline handed out must be given back to `release`.
+    pub fn get_or_build(
+        &mut self,
+        key: PipelineKey,
+        build: impl FnOnce(PipelineKey) -> Result<SharedPipeline, RenderError>,
+    ) -> Result<Arc<SharedPipeline>, RenderError> {
+        if let Some(shared) = self.pipelines.get(&key) {
+            return Ok(Arc::clone(shared));
+        }
+        let shared = Arc::new(build(key.clone())?);
+        self.pipelines.insert(key, Arc::clone(&shared));
+        Ok(shared)
+    }
+
+    /// Release a graphic's hold on `shared`, returning the pipeline to destroy
+    /// once nothing holds it.
+    pub fn release(&mut self, shared: Arc<SharedPipeline>) -> Option<SharedPipeline> {
+        let cached = self
+            .pipelines
+            .get(&shared.key)
+            .is_some_and(|cached| Arc::ptr_eq(cached, &shared));
+        // Only the cache and this graphic hold it.
+        if cached && Arc::strong_count(&shared) == 2 {
+            self.pipelines.remove(&shared.key);
+        }
+        Arc::try_unwrap(shared).ok()
+    }
+
+    /// Stop handing out the pipelines built so far, so graphics built from now
+    /// on get new ones. Those still held are destroyed with their last graphic.
+    pub fn clear(&mut self) {
+        self.pipelines.clear();
+    }
+
+    /// Number of pipelines that can be handed out.
+    pub fn len(&self) -> usize {
+        self.pipelines.len()
+    }
+
+    /// Pipelines that can be handed out, with the number of graphics using
+    /// each.
+    pub fn iter(&self) -> impl Iterator<Item = (&PipelineKey, usize)> {
+        self.pipelines
+            .iter()
+            .map(|(key, shared)| (key, Arc::strong_count(shared) - 1))
+    }
+}
@@ -0,0 +1,372 @@
+//! Capturing reflection probes, see `render::probes`. Each face of a probe is
+//! drawn into a target the size of a face, with the same pipelines as the
+//! scene, then copied into its layer of an array image shaders sample, six
+//! layers per probe. Capturing waits on the device, so probes are only
+//! captured when they change or are asked to be, not every frame.
+
+use ash::{vk, Device};
+use glam::Vec4;
+use logger::Logger;
+use render::probes::face_view_projection;
+use shader_objects::MAX_REFLECTION_PROBES;
+
+use crate::device::DeviceWrapper;
+use crate::scaled_target::TargetImage;
+use crate::secondary::{self, DrawCall};
+use crate::types::RenderError;
+use crate::VulkanBase;
+
+/// Width and height of each face, in pixels.
+const FACE_RESOLUTION: u32 = 128;
+
+pub(crate) struct ReflectionProbeFaces {
+    /// Every face of every probe, kept ready for shaders to read.
+    faces: TargetImage,
+    sampler: vk::Sampler,
+    /// Where a face is drawn before it's copied into `faces`. Its render pass
+    /// is compatible with `VulkanBase::render_pass`, so the scene's pipelines
+    /// draw into it.
+    capture_color: TargetImage,
+    capture_depth: TargetImage,
+    render_pass: vk::RenderPass,
+    framebuffer: vk::Framebuffer,
+}
+
+impl ReflectionProbeFaces {
+    pub fn new(base: &VulkanBase) -> Result<Self, RenderError> {
+        let format = base.surface_format.format;
+        let extent = vk::Extent2D {
+            width: FACE_RESOLUTION,
+            height: FACE_RESOLUTION,
+        };
+        let faces = TargetImage::new(
+            base,
+            extent,
+            MAX_REFLECTION_PROBES as u32 * 6,
+            format,
+            vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST,
+            vk::ImageAspectFlags::COLOR,
+        )?;
+        let capture_color = TargetImage::new(
+            base,
+            extent,
+            1,
+            format,
+            vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC,
+            vk::ImageAspectFlags::COLOR,
+        )?;
+        let capture_depth = TargetImage::new(
+            base,
+            extent,
+            1,
+            vk::Format::D16_UNORM,
+            vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+            vk::ImageAspectFlags::DEPTH,
+        )?;
+
+        // Faces start out black, until they're captured. The render pass
+        // expects depth to already be in its attachment layout.
+        let (faces_image, depth_image) = (faces.image, capture_depth.image);
+        VulkanBase::record_and_submit_commandbuffer(
+            &base.device,
+            base.setup_command_buffer,
+            base.setup_commands_reuse_fence,
+            base.present_queue,
+            &[],
+            &[],
+            &[],
+            |device, command_buffer| {
+                let faces_range = faces_range(0, MAX_REFLECTION_PROBES as u32 * 6);
+                let to_clear = [
+                    *vk::ImageMemoryBarrier::builder()
+                        .image(faces_image)
+                        .dst_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                        .old_layout(vk::ImageLayout::UNDEFINED)
+                        .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+                        .subresource_range(faces_range),
+                    *vk::ImageMemoryBarrier::builder()
+                        .image(depth_image)
+                        .dst_access_mask(
+                            vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
+                                | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+                        )
+                        .old_layout(vk::ImageLayout::UNDEFINED)
+                        .new_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+                        .subresource_range(
+                            *vk::ImageSubresourceRange::builder()
+                                .aspect_mask(vk::ImageAspectFlags::DEPTH)
+                                .layer_count(1)
+                                .level_count(1),
+                        ),
+                ];
+                let to_read = *vk::ImageMemoryBarrier::builder()
+                    .image(faces_image)
+                    .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                    .dst_access_mask(vk::AccessFlags::SHADER_READ)
+                    .old_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+                    .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                    .subresource_range(faces_range);
+                unsafe {
+                    device.cmd_pipeline_barrier(
+                        command_buffer,
+                        vk::PipelineStageFlags::TOP_OF_PIPE,
+                        vk::PipelineStageFlags::TRANSFER
+                            | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS,
+                        vk::DependencyFlags::empty(),
+                        &[],
+                        &[],
+                        &to_clear,
+                    );
+                    device.cmd_clear_color_image(
+                        command_buffer,
+                        faces_image,
+                        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                        &vk::ClearColorValue::default(),
+                        &[faces_range],
+                    );
+                    device.cmd_pipeline_barrier(
+                        command_buffer,
+                        vk::PipelineStageFlags::TRANSFER,
+                        vk::PipelineStageFlags::FRAGMENT_SHADER,
+                        vk::DependencyFlags::empty(),
+                        &[],
+                        &[],
+                        &[to_read],
+                    );
+                }
+            },
+        );
+
+        let (attachments, color_refs, depth_ref) =
+            VulkanBase::create_attachments(format, vk::ImageLayout::TRANSFER_SRC_OPTIMAL);
+        let render_pass = VulkanBase::create_render_pass(
+            &base.device,
+            attachments.all(),
+            &color_refs,
+            &depth_ref,
+        )?;
+        let framebuffer = VulkanBase::create_framebuffers(
+            &base.device,
+            capture_depth.view,
+            &[capture_color.view],
+            render_pass,
+            extent,
+        )?
+        .remove(0);
+
+        let sampler_info = vk::SamplerCreateInfo {
+            mag_filter: vk::Filter::LINEAR,
+            min_filter: vk::Filter::LINEAR,
+            address_mode_u: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+            address_mode_v: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+            address_mode_w: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+            max_anisotropy: 1.0,
+            ..Default::default()
+        };
+        let sampler = unsafe { base.device.create_sampler(&sampler_info, None) }
+            .map_err(RenderError::VkResultToDo)?;
+
+        Ok(Self {
+            faces,
+            sampler,
+            capture_color,
+            capture_depth,
+            render_pass,
+            framebuffer,
+        })
+    }
+
+    /// The faces, and the sampler they're read with.
+    pub fn faces(&self) -> (vk::ImageView, vk::Sampler) {
+        (self.faces.view, self.sampler)
+    }
+
+    /// Record capturing every face of `probes`, drawing `draws` with the view
+    /// of each face written to `uniform_buffer`, the frame's uniform buffer the
+    /// draws' descriptor sets bind. Only its view changes, the rest is as
+    /// written before. It's left with the view of the last face.
+    pub fn cmd_capture(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        probes: &[Vec4],
+        uniform_buffer: vk::Buffer,
+        draws: &[DrawCall],
+        logger: &Logger,
+    ) {
+        let w = DeviceWrapper::wrap(device, logger);
+        let area = vk::Rect2D::from(vk::Extent2D {
+            width: FACE_RESOLUTION,
+            height: FACE_RESOLUTION,
+        });
+        let viewports = VulkanBase::viewports_of(area);
+        let scissors = VulkanBase::scissors_of(area);
+        let clear_values = [
+            vk::ClearValue {
+                color: vk::ClearColorValue::default(),
+            },
+            vk::ClearValue {
+                depth_stencil: vk::ClearDepthStencilValue {
+                    depth: 1.0,
+                    stencil: 0,
+                },
+            },
+        ];
+        let render_pass_begin_info = vk::RenderPassBeginInfo::builder()
+            .render_pass(self.render_pass)
+            .framebuffer(self.framebuffer)
+            .render_area(area)
+            .clear_values(&clear_values);
+        let color_range = faces_range(0, 1);
+        let color_layers = *vk::ImageSubresourceLayers::builder()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .layer_count(1);
+
+        for (probe_index, probe) in probes.iter().enumerate() {
+            for face in 0..6 {
+                let layer = (probe_index * 6 + face) as u32;
+                let view_projection = face_view_projection(face, probe.truncate());
+                // Draws of the last face have to be done reading the uniforms
+                // before they're changed.
+                let before_update =
+                    *vk::MemoryBarrier::builder().dst_access_mask(vk::AccessFlags::TRANSFER_WRITE);
+                let after_update = *vk::MemoryBarrier::builder()
+                    .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                    .dst_access_mask(vk::AccessFlags::UNIFORM_READ);
+                unsafe {
+                    device.cmd_pipeline_barrier(
+                        command_buffer,
+                        vk::PipelineStageFlags::VERTEX_SHADER
+                            | vk::PipelineStageFlags::FRAGMENT_SHADER,
+                        vk::PipelineStageFlags::TRANSFER,
+                        vk::DependencyFlags::empty(),
+                        &[before_update],
+                        &[],
+                        &[],
+                    );
+                    // The view is `UniformBuffer::proj`, which comes first.
+                    device.cmd_update_buffer(
+                        command_buffer,
+                        uniform_buffer,
+                        0,
+                        bytemuck::bytes_of(&view_projection),
+                    );
+                    device.cmd_pipeline_barrier(
+                        command_buffer,
+                        vk::PipelineStageFlags::TRANSFER,
+                        vk::PipelineStageFlags::VERTEX_SHADER
+                            | vk::PipelineStageFlags::FRAGMENT_SHADER,
+                        vk::DependencyFlags::empty(),
+                        &[after_update],
+                        &[],
+                        &[],
+                    );
+                }
+
+                w.cmd_begin_render_pass(
+                    command_buffer,
+                    &render_pass_begin_info,
+                    vk::SubpassContents::INLINE,
+                );
+                secondary::record_draws(&w, command_buffer, draws, &viewports, &scissors);
+                w.cmd_end_render_pass(command_buffer);
+
+                let to_copy = [
+                    *vk::ImageMemoryBarrier::builder()
+                        .image(self.capture_color.image)
+                        .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+                        .dst_access_mask(vk::AccessFlags::TRANSFER_READ)
+                        .old_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+                        .new_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+                        .subresource_range(color_range),
+                    *vk::ImageMemoryBarrier::builder()
+                        .image(self.faces.image)
+                        .src_access_mask(vk::AccessFlags::SHADER_READ)
+                        .dst_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                        .old_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                        .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+                        .subresource_range(faces_range(layer, 1)),
+                ];
+                let copy = *vk::ImageCopy::builder()
+                    .src_subresource(color_layers)
+                    .dst_subresource(vk::ImageSubresourceLayers {
+                        base_array_layer: layer,
+                        ..color_layers
+                    })
+                    .extent(vk::Extent3D {
+                        width: FACE_RESOLUTION,
+                        height: FACE_RESOLUTION,
+                        depth: 1,
+                    });
+                // The next face mustn't be drawn until this one is copied.
+                let after_copy = [
+                    *vk::ImageMemoryBarrier::builder()
+                        .image(self.capture_color.image)
+                        .src_access_mask(vk::AccessFlags::TRANSFER_READ)
+                        .dst_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+                        .old_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+                        .new_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+                        .subresource_range(color_range),
+                    *vk::ImageMemoryBarrier::builder()
+                        .image(self.faces.image)
+                        .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                        .dst_access_mask(vk::AccessFlags::SHADER_READ)
+                        .old_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+                        .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                        .subresource_range(faces_range(layer, 1)),
+                ];
+                unsafe {
+                    device.cmd_pipeline_barrier(
+                        command_buffer,
+                        vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                            | vk::PipelineStageFlags::FRAGMENT_SHADER,
+                        vk::PipelineStageFlags::TRANSFER,
+                        vk::DependencyFlags::empty(),
+                        &[],
+                        &[],
+                        &to_copy,
+                    );
+                    device.cmd_copy_image(
+                        command_buffer,
+                        self.capture_color.image,
+                        vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                        self.faces.image,
+                        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                        &[copy],
+                    );
+                    device.cmd_pipeline_barrier(
+                        command_buffer,
+                        vk::PipelineStageFlags::TRANSFER,
+                        vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                            | vk::PipelineStageFlags::FRAGMENT_SHADER,
+                        vk::DependencyFlags::empty(),
+                        &[],
+                        &[],
+                        &after_copy,
+                    );
+                }
+            }
+        }
+    }
+
+    /// Destroy the faces and capture target, once no frame in flight uses
+    /// them.
+    pub fn destroy(self, device: &Device) {
+        unsafe {
+            device.destroy_framebuffer(self.framebuffer, None);
+            device.destroy_render_pass(self.render_pass, None);
+            device.destroy_sampler(self.sampler, None);
+            self.faces.destroy(device);
+            self.capture_color.destroy(device);
+            self.capture_depth.destroy(device);
+        }
+    }
+}
+
+fn faces_range(first_layer: u32, layers: u32) -> vk::ImageSubresourceRange {
+    *vk::ImageSubresourceRange::builder()
+        .aspect_mask(vk::ImageAspectFlags::COLOR)
+        .base_array_layer(first_layer)
+        .layer_count(layers)
+        .level_count(1)
+}
@@ -0,0 +1,354 @@
+//! Copying from the GPU into host-visible staging buffers, delivered as
+//! `render::readback::Readback`s.
+//!
+//! Copies are recorded into a command buffer the renderer is about to submit,
+//! along with the fence it's submitted with. `Readbacks::poll` delivers each
+//! copy once its fence has signaled, which the renderer calls right after
+//! waiting on a fence and before resetting it, so nothing ever waits on the
+//! device for a readback.
+
+use ash::{vk, Device};
+use render::readback::{self, Delivery, Readback, ReadbackError, ReadbackImage};
+
+use crate::types::{BufferAndMemory, RenderError};
+use crate::VulkanBase;
+
+/// Bytes per pixel of the color formats that can be read back.
+const BYTES_PER_PIXEL: u32 = 4;
+
+/// Readbacks recorded and not yet delivered, and frames asked for.
+pub(crate) struct Readbacks {
+    memory_properties: vk::PhysicalDeviceMemoryProperties,
+    pending: Vec<PendingReadback>,
+    /// Read back from the next frame presented.
+    frame_requests: Vec<Delivery<ReadbackImage>>,
+}
+
+struct PendingReadback {
+    staging: BufferAndMemory,
+    /// Signaled once the copy into `staging` has completed.
+    fence: vk::Fence,
+    deliver: Deliver,
+}
+
+enum Deliver {
+    Bytes(Delivery<Vec<u8>>),
+    /// An image can be asked for by several readers at once.
+    Image {
+        extent: vk::Extent2D,
+        bgra: bool,
+        deliveries: Vec<Delivery<ReadbackImage>>,
+    },
+}
+
+impl Deliver {
+    fn complete(self, bytes: Result<Vec<u8>, ReadbackError>) {
+        match self {
+            Deliver::Bytes(delivery) => delivery.complete(bytes),
+            Deliver::Image {
+                extent,
+                bgra,
+                deliveries,
+            } => {
+                let image = bytes.map(|pixels| ReadbackImage {
+                    width: extent.width,
+                    height: extent.height,
+                    bgra,
+                    pixels,
+                });
+                for delivery in deliveries {
+                    delivery.complete(image.clone());
+                }
+            }
+        }
+    }
+}
+
+impl Readbacks {
+    pub fn new(memory_properties: vk::PhysicalDeviceMemoryProperties) -> Self {
+        Self {
+            memory_properties,
+            pending: Vec::new(),
+            frame_requests: Vec::new(),
+        }
+    }
+
+    /// Ask for the next frame presented to be read back.
+    pub fn request_frame(&mut self) -> Readback<ReadbackImage> {
+        let (delivery, readback) = readback::readback();
+        self.frame_requests.push(delivery);
+        readback
+    }
+
+    /// Record reading back the frame in `image`, in `layout` and the
+    /// swapchain's `format`, if it was asked for. Called once the frame has
+    /// been rendered into it, before it's presented. Readbacks that fail are
+    /// delivered as errors, the frame goes on regardless.
+    #[allow(clippy::too_many_arguments)]
+    pub fn cmd_read_frame(
+        &mut self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        fence: vk::Fence,
+        image: vk::Image,
+        layout: vk::ImageLayout,
+        extent: vk::Extent2D,
+        format: vk::Format,
+        readable: bool,
+    ) {
+        self.frame_requests
+            .retain(|delivery| !delivery.is_abandoned());
+        if self.frame_requests.is_empty() {
+            return;
+        }
+        let deliveries = std::mem::take(&mut self.frame_requests);
+        let bgra = match format {
+            vk::Format::B8G8R8A8_UNORM | vk::Format::B8G8R8A8_SRGB => true,
+            vk::Format::R8G8B8A8_UNORM | vk::Format::R8G8B8A8_SRGB => false,
+            _ => return Self::unsupported(deliveries, "frames of this format"),
+        };
+        if !readable {
+            return Self::unsupported(deliveries, "frames from this surface");
+        }
+        self.cmd_read_image(
+            device,
+            command_buffer,
+            fence,
+            image,
+            layout,
+            extent,
+            Deliver::Image {
+                extent,
+                bgra,
+                deliveries,
+            },
+        );
+    }
+
+    /// Record copying query results into a buffer, such as pipeline
+    /// statistics, once the queries are available.
+    #[allow(clippy::too_many_arguments)]
+    pub fn cmd_read_query_results(
+        &mut self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        fence: vk::Fence,
+        pool: vk::QueryPool,
+        first_query: u32,
+        query_count: u32,
+        stride: u64,
+    ) -> Result<Readback<Vec<u8>>, RenderError> {
+        let (delivery, readback) = readback::readback();
+        let size = stride * u64::from(query_count);
+        let staging = self.allocate_staging(device, size)?;
+        unsafe {
+            device.cmd_copy_query_pool_results(
+                command_buffer,
+                pool,
+                first_query,
+                query_count,
+                *staging.buffer,
+                0,
+                stride,
+                vk::QueryResultFlags::TYPE_64 | vk::QueryResultFlags::WAIT,
+            );
+        }
+        self.push(
+            device,
+            command_buffer,
+            fence,
+            staging,
+            Deliver::Bytes(delivery),
+        );
+        Ok(readback)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn cmd_read_image(
+        &mut self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        fence: vk::Fence,
+        image: vk::Image,
+        layout: vk::ImageLayout,
+        extent: vk::Extent2D,
+        deliver: Deliver,
+    ) {
+        let size = u64::from(extent.width * extent.height * BYTES_PER_PIXEL);
+        let staging = match self.allocate_staging(device, size) {
+            Ok(staging) => staging,
+            Err(err) => return deliver.complete(Err(ReadbackError::Device(err.to_string()))),
+        };
+        let color_range = *vk::ImageSubresourceRange::builder()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .layer_count(1)
+            .level_count(1);
+        let to_transfer = *vk::ImageMemoryBarrier::builder()
+            .image(image)
+            .src_access_mask(
+                vk::AccessFlags::COLOR_ATTACHMENT_WRITE | vk::AccessFlags::TRANSFER_WRITE,
+            )
+            .dst_access_mask(vk::AccessFlags::TRANSFER_READ)
+            .old_layout(layout)
+            .new_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+            .subresource_range(color_range);
+        let region = *vk::BufferImageCopy::builder()
+            .image_subresource(
+                *vk::ImageSubresourceLayers::builder()
+                    .aspect_mask(vk::ImageAspectFlags::COLOR)
+                    .layer_count(1),
+            )
+            .image_extent(extent.into());
+        let to_original = *vk::ImageMemoryBarrier::builder()
+            .image(image)
+            .src_access_mask(vk::AccessFlags::TRANSFER_READ)
+            .old_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+            .new_layout(layout)
+            .subresource_range(color_range);
+        unsafe {
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT | vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[to_transfer],
+            );
+            device.cmd_copy_image_to_buffer(
+                command_buffer,
+                image,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                *staging.buffer,
+                &[region],
+            );
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[to_original],
+            );
+        }
+        self.push(device, command_buffer, fence, staging, deliver);
+    }
+
+    /// Make the copy into `staging` visible to the host, and track it until
+    /// `fence` signals.
+    fn push(
+        &mut self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        fence: vk::Fence,
+        staging: BufferAndMemory,
+        deliver: Deliver,
+    ) {
+        let to_host = *vk::BufferMemoryBarrier::builder()
+            .buffer(*staging.buffer)
+            .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+            .dst_access_mask(vk::AccessFlags::HOST_READ)
+            .size(vk::WHOLE_SIZE);
+        unsafe {
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::HOST,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[to_host],
+                &[],
+            );
+        }
+        self.pending.push(PendingReadback {
+            staging,
+            fence,
+            deliver,
+        });
+    }
+
+    /// Deliver every readback whose fence has signaled. Fences are reused
+    /// frame to frame, so this must be called after waiting on one and
+    /// before it's reset.
+    pub fn poll(&mut self, device: &Device) {
+        let mut index = 0;
+        while index < self.pending.len() {
+            let pending = &self.pending[index];
+            let bytes = match unsafe { device.get_fence_status(pending.fence) } {
+                Ok(false) => {
+                    index += 1;
+                    continue;
+                }
+                Ok(true) => Self::read_staging(device, &pending.staging),
+                Err(err) => Err(ReadbackError::Device(format!("{err:?}"))),
+            };
+            let pending = self.pending.swap_remove(index);
+            pending.staging.deallocate(device);
+            pending.deliver.complete(bytes);
+        }
+    }
+
+    /// Free the staging buffers, cancelling anything not yet delivered. Only
+    /// called once nothing in flight uses them.
+    pub fn destroy(&mut self, device: &Device) {
+        self.frame_requests.clear();
+        for pending in self.pending.drain(..) {
+            pending.staging.deallocate(device);
+        }
+    }
+
+    fn unsupported(deliveries: Vec<Delivery<ReadbackImage>>, what: &'static str) {
+        for delivery in deliveries {
+            delivery.complete(Err(ReadbackError::Unsupported(what)));
+        }
+    }
+
+    fn allocate_staging(&self, device: &Device, size: u64) -> Result<BufferAndMemory, RenderError> {
+        let buffer_info = vk::BufferCreateInfo::builder()
+            .size(size)
+            .usage(vk::BufferUsageFlags::TRANSFER_DST)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE);
+        let buffer = unsafe { device.create_buffer(&buffer_info, None) }
+            .map_err(RenderError::VkResultToDo)?;
+        let requirements = unsafe { device.get_buffer_memory_requirements(buffer) };
+        let memory_type_index = VulkanBase::find_memorytype_index(
+            &requirements,
+            &self.memory_properties,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+        );
+        let memory = memory_type_index
+            .ok_or(RenderError::UnableToFindMemoryTypeForBuffer)
+            .and_then(|memory_type_index| {
+                let allocate_info = vk::MemoryAllocateInfo::builder()
+                    .allocation_size(requirements.size)
+                    .memory_type_index(memory_type_index);
+                unsafe { device.allocate_memory(&allocate_info, None) }
+                    .map_err(RenderError::VkResultToDo)
+            });
+        let memory = match memory {
+            Ok(memory) => memory,
+            Err(err) => {
+                unsafe { device.destroy_buffer(buffer, None) };
+                return Err(err);
+            }
+        };
+        let staging = BufferAndMemory::new(buffer, memory, size as usize, requirements.size);
+        if let Err(err) = unsafe { device.bind_buffer_memory(buffer, memory, 0) } {
+            staging.deallocate(device);
+            return Err(RenderError::VkResultToDo(err));
+        }
+        Ok(staging)
+    }
+
+    fn read_staging(device: &Device, staging: &BufferAndMemory) -> Result<Vec<u8>, ReadbackError> {
+        let len = staging.original_len;
+        let ptr = unsafe {
+            device.map_memory(*staging.memory, 0, len as u64, vk::MemoryMapFlags::empty())
+        }
+        .map_err(|err| ReadbackError::Device(format!("{err:?}")))?;
+        let bytes = unsafe { std::slice::from_raw_parts(ptr as *const u8, len) }.to_vec();
+        unsafe { device.unmap_memory(*staging.memory) };
+        Ok(bytes)
+    }
+}
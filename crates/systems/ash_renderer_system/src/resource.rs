@@ -0,0 +1,110 @@
+//! Vulkan handles owned by the renderer.
+//!
+//! Handles are destroyed explicitly with the device that created them, so the
+//! order resources go away in stays visible where it matters, such as before
+//! the device itself or only once the frame using them has completed. An
+//! `Owned` handle can only be destroyed once, and debug builds count handles
+//! dropped without being destroyed, which `VulkanBase` reports as leaks when
+//! it's torn down.
+
+use std::collections::BTreeMap;
+use std::ops::Deref;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use ash::{vk, Device};
+
+/// A Vulkan handle that's destroyed through the device.
+pub(crate) trait Destroy: Copy {
+    /// What kind of resource this is, for leak reports.
+    const KIND: &'static str;
+
+    /// # Safety
+    /// The handle must have been created with `device`, and nothing in
+    /// flight may still use it.
+    unsafe fn destroy(self, device: &Device);
+}
+
+macro_rules! impl_destroy {
+    ($($handle:ty => $kind:literal, $method:ident;)*) => {
+        $(
+            impl Destroy for $handle {
+                const KIND: &'static str = $kind;
+
+                unsafe fn destroy(self, device: &Device) {
+                    device.$method(self, None);
+                }
+            }
+        )*
+    };
+}
+
+impl_destroy! {
+    vk::Buffer => "buffer", destroy_buffer;
+    vk::DeviceMemory => "memory", free_memory;
+    vk::Image => "image", destroy_image;
+    vk::ImageView => "image view", destroy_image_view;
+    vk::Sampler => "sampler", destroy_sampler;
+    vk::ShaderModule => "shader module", destroy_shader_module;
+    vk::DescriptorSetLayout => "descriptor set layout", destroy_descriptor_set_layout;
+    vk::PipelineLayout => "pipeline layout", destroy_pipeline_layout;
+    vk::Pipeline => "pipeline", destroy_pipeline;
+    vk::RenderPass => "render pass", destroy_render_pass;
+    vk::Framebuffer => "framebuffer", destroy_framebuffer;
+}
+
+/// A handle owned by whatever holds this, dereferencing to the raw handle.
+pub(crate) struct Owned<T: Destroy> {
+    handle: T,
+    destroyed: AtomicBool,
+}
+
+impl<T: Destroy> Owned<T> {
+    pub fn new(handle: T) -> Self {
+        Self {
+            handle,
+            destroyed: AtomicBool::new(false),
+        }
+    }
+
+    /// Destroy the handle, only the first call does anything. Only called
+    /// once nothing in flight uses it.
+    pub fn destroy(&self, device: &Device) {
+        let destroyed = self.destroyed.swap(true, Ordering::AcqRel);
+        debug_assert!(!destroyed, "{} destroyed twice", T::KIND);
+        if !destroyed {
+            unsafe { self.handle.destroy(device) };
+        }
+    }
+}
+
+impl<T: Destroy> Deref for Owned<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.handle
+    }
+}
+
+impl<T: Destroy> Drop for Owned<T> {
+    fn drop(&mut self) {
+        if cfg!(debug_assertions) && !*self.destroyed.get_mut() {
+            let mut leaks = LEAKS
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            *leaks.entry(T::KIND).or_default() += 1;
+        }
+    }
+}
+
+/// Handles dropped without being destroyed, by kind. Only counted in debug
+/// builds.
+static LEAKS: Mutex<BTreeMap<&'static str, usize>> = Mutex::new(BTreeMap::new());
+
+/// Take the handles leaked so far, by kind.
+pub(crate) fn take_leaks() -> BTreeMap<&'static str, usize> {
+    let mut leaks = LEAKS
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    std::mem::take(&mut *leaks)
+}
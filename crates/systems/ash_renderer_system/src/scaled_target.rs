@@ -0,0 +1,318 @@
+//! The off-screen target the scene is rendered into when it's rendered at
+//! another resolution than the window's, see `render::render_scale`. When the
+//! frame is presented it's blitted onto the swapchain image, filtered to the
+//! window's size.
+
+use ash::{vk, Device};
+use render::render_scale::UpscaleFilter;
+
+use crate::types::RenderError;
+use crate::VulkanBase;
+
+/// An image with its memory and a view of it, an array view when it has more
+/// than one layer.
+pub(crate) struct TargetImage {
+    pub image: vk::Image,
+    memory: vk::DeviceMemory,
+    pub view: vk::ImageView,
+}
+
+impl TargetImage {
+    pub fn new(
+        base: &VulkanBase,
+        extent: vk::Extent2D,
+        layers: u32,
+        format: vk::Format,
+        usage: vk::ImageUsageFlags,
+        aspect_mask: vk::ImageAspectFlags,
+    ) -> Result<Self, RenderError> {
+        Self::with_device(
+            &base.device,
+            &base.device_memory_properties,
+            extent,
+            layers,
+            format,
+            usage,
+            aspect_mask,
+        )
+    }
+
+    /// Create the image on `device`, before there's a `VulkanBase`.
+    pub fn with_device(
+        device: &Device,
+        memory_properties: &vk::PhysicalDeviceMemoryProperties,
+        extent: vk::Extent2D,
+        layers: u32,
+        format: vk::Format,
+        usage: vk::ImageUsageFlags,
+        aspect_mask: vk::ImageAspectFlags,
+    ) -> Result<Self, RenderError> {
+        let image_info = vk::ImageCreateInfo::builder()
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(format)
+            .extent(extent.into())
+            .mip_levels(1)
+            .array_layers(layers)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .usage(usage)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE);
+        let image =
+            unsafe { device.create_image(&image_info, None) }.map_err(RenderError::VkResultToDo)?;
+
+        let memory_req = unsafe { device.get_image_memory_requirements(image) };
+        let memory_index = match VulkanBase::find_memorytype_index(
+            &memory_req,
+            memory_properties,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        ) {
+            Some(memory_index) => memory_index,
+            None => {
+                unsafe { device.destroy_image(image, None) };
+                return Err(RenderError::UnableToFindMemoryTypeForImage);
+            }
+        };
+        let allocate_info = *vk::MemoryAllocateInfo::builder()
+            .allocation_size(memory_req.size)
+            .memory_type_index(memory_index);
+        let memory = match unsafe { device.allocate_memory(&allocate_info, None) } {
+            Ok(memory) => memory,
+            Err(err) => {
+                unsafe { device.destroy_image(image, None) };
+                return Err(RenderError::VkResultToDo(err));
+            }
+        };
+        let destroy_image = || unsafe {
+            device.destroy_image(image, None);
+            device.free_memory(memory, None);
+        };
+        if let Err(err) = unsafe { device.bind_image_memory(image, memory, 0) } {
+            destroy_image();
+            return Err(RenderError::VkResultToDo(err));
+        }
+
+        let view_info = *vk::ImageViewCreateInfo::builder()
+            .subresource_range(
+                *vk::ImageSubresourceRange::builder()
+                    .aspect_mask(aspect_mask)
+                    .level_count(1)
+                    .layer_count(layers),
+            )
+            .image(image)
+            .format(format)
+            .view_type(if layers > 1 {
+                vk::ImageViewType::TYPE_2D_ARRAY
+            } else {
+                vk::ImageViewType::TYPE_2D
+            });
+        match unsafe { device.create_image_view(&view_info, None) } {
+            Ok(view) => Ok(Self {
+                image,
+                memory,
+                view,
+            }),
+            Err(err) => {
+                destroy_image();
+                Err(RenderError::VkResultToDo(err))
+            }
+        }
+    }
+
+    pub unsafe fn destroy(&self, device: &Device) {
+        device.destroy_image_view(self.view, None);
+        device.destroy_image(self.image, None);
+        device.free_memory(self.memory, None);
+    }
+}
+
+pub(crate) struct ScaledTarget {
+    pub extent: vk::Extent2D,
+    /// Compatible with `VulkanBase::render_pass`, so the same pipelines draw
+    /// into it, but leaves the color attachment ready to blit from.
+    pub render_pass: vk::RenderPass,
+    pub framebuffer: vk::Framebuffer,
+    color: TargetImage,
+    depth: TargetImage,
+}
+
+impl ScaledTarget {
+    pub fn new(base: &VulkanBase, extent: vk::Extent2D) -> Result<Self, RenderError> {
+        let color = TargetImage::new(
+            base,
+            extent,
+            1,
+            base.surface_format.format,
+            vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC,
+            vk::ImageAspectFlags::COLOR,
+        )?;
+        let depth = TargetImage::new(
+            base,
+            extent,
+            1,
+            vk::Format::D16_UNORM,
+            vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+            vk::ImageAspectFlags::DEPTH,
+        )?;
+
+        // The render pass expects depth to already be in its attachment layout.
+        let depth_image = depth.image;
+        VulkanBase::record_and_submit_commandbuffer(
+            &base.device,
+            base.setup_command_buffer,
+            base.setup_commands_reuse_fence,
+            base.present_queue,
+            &[],
+            &[],
+            &[],
+            |device, setup_command_buffer| {
+                let barrier = *vk::ImageMemoryBarrier::builder()
+                    .image(depth_image)
+                    .dst_access_mask(
+                        vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
+                            | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+                    )
+                    .new_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+                    .old_layout(vk::ImageLayout::UNDEFINED)
+                    .subresource_range(
+                        *vk::ImageSubresourceRange::builder()
+                            .aspect_mask(vk::ImageAspectFlags::DEPTH)
+                            .layer_count(1)
+                            .level_count(1),
+                    );
+                unsafe {
+                    device.cmd_pipeline_barrier(
+                        setup_command_buffer,
+                        vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                        vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+                        vk::DependencyFlags::empty(),
+                        &[],
+                        &[],
+                        &[barrier],
+                    );
+                }
+            },
+        );
+
+        let (attachments, color_refs, depth_ref) = VulkanBase::create_attachments(
+            base.surface_format.format,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+        );
+        let render_pass = VulkanBase::create_render_pass(
+            &base.device,
+            attachments.all(),
+            &color_refs,
+            &depth_ref,
+        )?;
+        let framebuffer = VulkanBase::create_framebuffers(
+            &base.device,
+            depth.view,
+            &[color.view],
+            render_pass,
+            extent,
+        )?
+        .remove(0);
+        Ok(Self {
+            extent,
+            render_pass,
+            framebuffer,
+            color,
+            depth,
+        })
+    }
+
+    /// Record a blit of the rendered scene onto `dst`, a swapchain image of
+    /// `dst_extent`, after the render pass. Leaves `dst` in `dst_layout`,
+    /// ready to present or read back.
+    #[allow(clippy::too_many_arguments)]
+    pub fn cmd_blit_to(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        dst: vk::Image,
+        dst_extent: vk::Extent2D,
+        dst_layout: vk::ImageLayout,
+        filter: UpscaleFilter,
+    ) {
+        let color_range = *vk::ImageSubresourceRange::builder()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .layer_count(1)
+            .level_count(1);
+        let to_transfer = [
+            *vk::ImageMemoryBarrier::builder()
+                .image(self.color.image)
+                .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+                .dst_access_mask(vk::AccessFlags::TRANSFER_READ)
+                .old_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+                .new_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+                .subresource_range(color_range),
+            *vk::ImageMemoryBarrier::builder()
+                .image(dst)
+                .dst_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                .old_layout(vk::ImageLayout::UNDEFINED)
+                .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+                .subresource_range(color_range),
+        ];
+        let layers = *vk::ImageSubresourceLayers::builder()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .layer_count(1);
+        let corner = |extent: vk::Extent2D| vk::Offset3D {
+            x: extent.width as i32,
+            y: extent.height as i32,
+            z: 1,
+        };
+        let blit = *vk::ImageBlit::builder()
+            .src_subresource(layers)
+            .src_offsets([vk::Offset3D::default(), corner(self.extent)])
+            .dst_subresource(layers)
+            .dst_offsets([vk::Offset3D::default(), corner(dst_extent)]);
+        let to_present = *vk::ImageMemoryBarrier::builder()
+            .image(dst)
+            .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+            .old_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+            .new_layout(dst_layout)
+            .subresource_range(color_range);
+        let filter = match filter {
+            UpscaleFilter::Nearest => vk::Filter::NEAREST,
+            UpscaleFilter::Linear => vk::Filter::LINEAR,
+        };
+        unsafe {
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &to_transfer,
+            );
+            device.cmd_blit_image(
+                command_buffer,
+                self.color.image,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                dst,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &[blit],
+                filter,
+            );
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[to_present],
+            );
+        }
+    }
+
+    /// Destroy the target, once no frame in flight uses it.
+    pub fn destroy(self, device: &Device) {
+        unsafe {
+            device.destroy_framebuffer(self.framebuffer, None);
+            device.destroy_render_pass(self.render_pass, None);
+            self.color.destroy(device);
+            self.depth.destroy(device);
+        }
+    }
+}
@@ -0,0 +1,234 @@
+//! Recording draws into secondary command buffers in parallel. Scenes with
+//! thousands of draws have their draw list split into chunks, each recorded on
+//! its own thread into a secondary command buffer from the thread's own
+//! command pool, since pools can't be used from more than one thread. The
+//! primary command buffer then executes them in order. Smaller scenes are
+//! recorded inline, where starting threads would cost more than it saves.
+
+use std::num::NonZeroUsize;
+use std::thread;
+
+use ash::{vk, Device};
+use logger::Logger;
+use shader_objects::PushConstants;
+
+use crate::device::{DeviceWrapper, PUSH_CONSTANT_STAGES};
+use crate::types::RenderError;
+
+/// Fewest draws worth handing to another thread.
+const MIN_DRAWS_PER_THREAD: usize = 512;
+
+/// A draw, with everything needed to record it.
+#[derive(Copy, Clone)]
+pub(crate) struct DrawCall {
+    pub pipeline: vk::Pipeline,
+    pub layout: vk::PipelineLayout,
+    pub descriptor_set: vk::DescriptorSet,
+    pub vertex_buffer: vk::Buffer,
+    /// Skin weights, bound beside the vertices. Null for graphics without.
+    pub skin_buffer: vk::Buffer,
+    pub index_buffer: vk::Buffer,
+    pub index_count: u32,
+    pub push_constants: PushConstants,
+}
+
+/// Record `draws` into `command_buffer`, binding pipelines and buffers only
+/// when they differ from the draw before.
+pub(crate) fn record_draws(
+    w: &DeviceWrapper,
+    command_buffer: vk::CommandBuffer,
+    draws: &[DrawCall],
+    viewports: &[vk::Viewport],
+    scissors: &[vk::Rect2D],
+) {
+    let mut last: Option<&DrawCall> = None;
+    for draw in draws {
+        if last.map_or(true, |last| {
+            last.pipeline != draw.pipeline || last.descriptor_set != draw.descriptor_set
+        }) {
+            w.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                draw.layout,
+                0,
+                &[draw.descriptor_set],
+                &[],
+            );
+            w.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                draw.pipeline,
+            );
+            w.cmd_set_viewport(command_buffer, 0, viewports);
+            w.cmd_set_scissor(command_buffer, 0, scissors);
+        }
+        if last.map_or(true, |last| {
+            last.vertex_buffer != draw.vertex_buffer
+                || last.skin_buffer != draw.skin_buffer
+                || last.index_buffer != draw.index_buffer
+        }) {
+            if draw.skin_buffer == vk::Buffer::null() {
+                w.cmd_bind_vertex_buffers(command_buffer, 0, &[draw.vertex_buffer], &[0]);
+            } else {
+                w.cmd_bind_vertex_buffers(
+                    command_buffer,
+                    0,
+                    &[draw.vertex_buffer, draw.skin_buffer],
+                    &[0, 0],
+                );
+            }
+            w.cmd_bind_index_buffer(command_buffer, draw.index_buffer, 0, vk::IndexType::UINT32);
+        }
+        w.cmd_push_constants(
+            command_buffer,
+            draw.layout,
+            PUSH_CONSTANT_STAGES,
+            0,
+            draw.push_constants.to_bytes(),
+        );
+        w.cmd_draw_indexed(command_buffer, draw.index_count, 1, 0, 0, 1);
+        last = Some(draw);
+    }
+}
+
+/// A command pool and secondary command buffer per recording thread, kept
+/// between frames and reset once the frame using them has completed.
+pub(crate) struct SecondaryRecorder {
+    queue_family_index: u32,
+    workers: Vec<Worker>,
+}
+
+struct Worker {
+    pool: vk::CommandPool,
+    command_buffer: vk::CommandBuffer,
+}
+
+impl SecondaryRecorder {
+    pub fn new(queue_family_index: u32) -> Self {
+        Self {
+            queue_family_index,
+            workers: Vec::new(),
+        }
+    }
+
+    /// Threads to record `draws` draws on, one when they're recorded inline.
+    pub fn threads_for(draws: usize) -> usize {
+        let cores = thread::available_parallelism().map_or(1, NonZeroUsize::get);
+        (draws / MIN_DRAWS_PER_THREAD).clamp(1, cores)
+    }
+
+    /// Record `draws` split between `threads`, continuing the first subpass
+    /// of `render_pass`. `finish` records anything else in the subpass on the
+    /// calling thread, after the draws. Returns the secondary command buffers
+    /// to execute, in order. Must only be called once the command buffers
+    /// from the last call have completed.
+    #[allow(clippy::too_many_arguments)]
+    pub fn record<F>(
+        &mut self,
+        device: &Device,
+        render_pass: vk::RenderPass,
+        framebuffer: vk::Framebuffer,
+        draws: &[DrawCall],
+        viewports: &[vk::Viewport],
+        scissors: &[vk::Rect2D],
+        threads: usize,
+        logger: &Logger,
+        finish: F,
+    ) -> Result<Vec<vk::CommandBuffer>, RenderError>
+    where
+        F: FnOnce(&DeviceWrapper, vk::CommandBuffer) -> Result<(), RenderError>,
+    {
+        let threads = threads.max(1);
+        let w = DeviceWrapper::wrap(device, logger);
+        while self.workers.len() < threads {
+            let pool = w.create_command_pool(self.queue_family_index)?;
+            let command_buffer = w.allocate_secondary_command_buffer(pool)?;
+            self.workers.push(Worker {
+                pool,
+                command_buffer,
+            });
+        }
+
+        let chunk_size = ((draws.len() + threads - 1) / threads).max(1);
+        let mut chunks = draws.chunks(chunk_size).collect::<Vec<_>>();
+        let last_chunk = chunks.pop().unwrap_or_default();
+        let (workers, last_worker) = self.workers.split_at(chunks.len());
+        thread::scope(|scope| {
+            // The calling thread records the last chunk, and whatever follows.
+            let tasks = chunks
+                .into_iter()
+                .zip(workers)
+                .map(|(chunk, worker)| {
+                    let logger = logger.sub("worker");
+                    scope.spawn(move || {
+                        worker.record(
+                            device,
+                            render_pass,
+                            framebuffer,
+                            chunk,
+                            viewports,
+                            scissors,
+                            &logger,
+                            |_, _| Ok(()),
+                        )
+                    })
+                })
+                .collect::<Vec<_>>();
+            let last = last_worker[0].record(
+                device,
+                render_pass,
+                framebuffer,
+                last_chunk,
+                viewports,
+                scissors,
+                logger,
+                finish,
+            );
+            let mut command_buffers = tasks
+                .into_iter()
+                .map(|task| {
+                    task.join()
+                        .expect("command buffer recording thread panicked")
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            command_buffers.push(last?);
+            Ok(command_buffers)
+        })
+    }
+
+    /// Destroy the command pools, freeing their command buffers. Must only be
+    /// called once the command buffers have completed.
+    pub fn destroy(&mut self, device: &Device) {
+        for worker in self.workers.drain(..) {
+            unsafe {
+                device.destroy_command_pool(worker.pool, None);
+            }
+        }
+    }
+}
+
+impl Worker {
+    #[allow(clippy::too_many_arguments)]
+    fn record<F>(
+        &self,
+        device: &Device,
+        render_pass: vk::RenderPass,
+        framebuffer: vk::Framebuffer,
+        draws: &[DrawCall],
+        viewports: &[vk::Viewport],
+        scissors: &[vk::Rect2D],
+        logger: &Logger,
+        finish: F,
+    ) -> Result<vk::CommandBuffer, RenderError>
+    where
+        F: FnOnce(&DeviceWrapper, vk::CommandBuffer) -> Result<(), RenderError>,
+    {
+        let w = DeviceWrapper::wrap(device, logger);
+        w.reset_command_pool(self.pool)?;
+        w.begin_secondary_command_buffer(self.command_buffer, render_pass, framebuffer)?;
+        record_draws(&w, self.command_buffer, draws, viewports, scissors);
+        finish(&w, self.command_buffer)?;
+        w.end_command_buffer(self.command_buffer)?;
+        Ok(self.command_buffer)
+    }
+}
@@ -0,0 +1,395 @@
+//! Rendering the shadow map, see `render::shadows`. Before the scene pass,
+//! the depth of everything casting shadows is drawn from the light into a
+//! depth image, which the scene's shaders then sample at
+//! `SHADOW_MAP_BINDING`. It's drawn with the same descriptor sets as the
+//! scene, with the view of the frame's uniform buffer switched to the
+//! light's for the pass.
+//!
+//! Each shared pipeline casting shadows gets a depth-only variant, built the
+//! first time it's needed, with the same layout and vertex shader. Only
+//! graphics shaded by the default fragment shader cast shadows, leaving out
+//! the skybox and effects. The shadow map is shared by every frame in flight,
+//! like the G-buffer.
+
+use ash::{vk, Device};
+use glam::Mat4;
+use logger::{warn, Logger};
+use shader_objects::SHADOW_MAP_RESOLUTION;
+
+use crate::device::DeviceWrapper;
+use crate::resource::Owned;
+use crate::scaled_target::TargetImage;
+use crate::secondary::{self, DrawCall};
+use crate::types::{RenderError, SharedPipeline};
+use crate::VulkanBase;
+
+const DEPTH_FORMAT: vk::Format = vk::Format::D16_UNORM;
+
+/// Depth bias of shadow casters, so surfaces don't shadow themselves.
+const DEPTH_BIAS_CONSTANT: f32 = 1.25;
+const DEPTH_BIAS_SLOPE: f32 = 1.75;
+
+pub(crate) struct ShadowMap {
+    /// Kept ready for shaders to read, outside of the shadow pass.
+    depth: TargetImage,
+    sampler: vk::Sampler,
+    render_pass: vk::RenderPass,
+    framebuffer: vk::Framebuffer,
+}
+
+impl ShadowMap {
+    pub fn new(base: &VulkanBase) -> Result<Self, RenderError> {
+        let extent = extent();
+        let depth = TargetImage::new(
+            base,
+            extent,
+            1,
+            DEPTH_FORMAT,
+            vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT
+                | vk::ImageUsageFlags::SAMPLED
+                | vk::ImageUsageFlags::TRANSFER_DST,
+            vk::ImageAspectFlags::DEPTH,
+        )?;
+
+        // Nothing is shadowed until the shadow map is first rendered.
+        let depth_image = depth.image;
+        VulkanBase::record_and_submit_commandbuffer(
+            &base.device,
+            base.setup_command_buffer,
+            base.setup_commands_reuse_fence,
+            base.present_queue,
+            &[],
+            &[],
+            &[],
+            |device, command_buffer| {
+                let to_clear = *vk::ImageMemoryBarrier::builder()
+                    .image(depth_image)
+                    .dst_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                    .old_layout(vk::ImageLayout::UNDEFINED)
+                    .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+                    .subresource_range(depth_range());
+                let to_read = *vk::ImageMemoryBarrier::builder()
+                    .image(depth_image)
+                    .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                    .dst_access_mask(vk::AccessFlags::SHADER_READ)
+                    .old_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+                    .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                    .subresource_range(depth_range());
+                unsafe {
+                    device.cmd_pipeline_barrier(
+                        command_buffer,
+                        vk::PipelineStageFlags::TOP_OF_PIPE,
+                        vk::PipelineStageFlags::TRANSFER,
+                        vk::DependencyFlags::empty(),
+                        &[],
+                        &[],
+                        &[to_clear],
+                    );
+                    device.cmd_clear_depth_stencil_image(
+                        command_buffer,
+                        depth_image,
+                        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                        &vk::ClearDepthStencilValue {
+                            depth: 1.0,
+                            stencil: 0,
+                        },
+                        &[depth_range()],
+                    );
+                    device.cmd_pipeline_barrier(
+                        command_buffer,
+                        vk::PipelineStageFlags::TRANSFER,
+                        vk::PipelineStageFlags::FRAGMENT_SHADER,
+                        vk::DependencyFlags::empty(),
+                        &[],
+                        &[],
+                        &[to_read],
+                    );
+                }
+            },
+        );
+
+        let render_pass = create_shadow_pass(&base.device)?;
+        let attachments = [depth.view];
+        let framebuffer_info = vk::FramebufferCreateInfo::builder()
+            .render_pass(render_pass)
+            .attachments(&attachments)
+            .width(extent.width)
+            .height(extent.height)
+            .layers(1);
+        let framebuffer = unsafe { base.device.create_framebuffer(&framebuffer_info, None) }
+            .map_err(RenderError::VkResultToDo)?;
+
+        // Past the edges of the shadow map nothing is in front of anything.
+        let sampler_info = vk::SamplerCreateInfo {
+            mag_filter: vk::Filter::NEAREST,
+            min_filter: vk::Filter::NEAREST,
+            address_mode_u: vk::SamplerAddressMode::CLAMP_TO_BORDER,
+            address_mode_v: vk::SamplerAddressMode::CLAMP_TO_BORDER,
+            address_mode_w: vk::SamplerAddressMode::CLAMP_TO_BORDER,
+            border_color: vk::BorderColor::FLOAT_OPAQUE_WHITE,
+            max_anisotropy: 1.0,
+            ..Default::default()
+        };
+        let sampler = unsafe { base.device.create_sampler(&sampler_info, None) }
+            .map_err(RenderError::VkResultToDo)?;
+
+        Ok(Self {
+            depth,
+            sampler,
+            render_pass,
+            framebuffer,
+        })
+    }
+
+    /// The shadow map, and the sampler it's read with.
+    pub fn map(&self) -> (vk::ImageView, vk::Sampler) {
+        (self.depth.view, self.sampler)
+    }
+
+    /// The depth-only variant of `shared` drawing into the shadow map, built
+    /// the first time it's asked for. None when graphics drawn with `shared`
+    /// don't cast shadows.
+    pub fn shadow_pipeline(
+        &self,
+        base: &VulkanBase,
+        shared: &SharedPipeline,
+        logger: &Logger,
+    ) -> Option<vk::Pipeline> {
+        if !shared.deferrable {
+            return None;
+        }
+        shared
+            .shadow
+            .get_or_init(
+                || match create_shadow_pipeline(base, shared, self.render_pass) {
+                    Ok(pipeline) => Some(Owned::new(pipeline)),
+                    Err(err) => {
+                        warn!(logger, "casting no shadows, no shadow pipeline: {err}");
+                        None
+                    }
+                },
+            )
+            .as_deref()
+            .copied()
+    }
+
+    /// Record drawing `draws`, drawn with shadow variants, into the shadow
+    /// map, before the scene pass begins. The view of `uniform_buffer`, the
+    /// frame's uniform buffer the draws' descriptor sets bind, is switched to
+    /// `light_space` for the pass, then back to `view_projection`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn cmd_render(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        uniform_buffer: vk::Buffer,
+        light_space: Mat4,
+        view_projection: Mat4,
+        draws: &[DrawCall],
+        logger: &Logger,
+    ) {
+        let w = DeviceWrapper::wrap(device, logger);
+        let area = vk::Rect2D::from(extent());
+        let clear_values = [vk::ClearValue {
+            depth_stencil: vk::ClearDepthStencilValue {
+                depth: 1.0,
+                stencil: 0,
+            },
+        }];
+        let render_pass_begin_info = vk::RenderPassBeginInfo::builder()
+            .render_pass(self.render_pass)
+            .framebuffer(self.framebuffer)
+            .render_area(area)
+            .clear_values(&clear_values);
+
+        cmd_set_view(device, command_buffer, uniform_buffer, light_space);
+        w.cmd_begin_render_pass(
+            command_buffer,
+            &render_pass_begin_info,
+            vk::SubpassContents::INLINE,
+        );
+        secondary::record_draws(
+            &w,
+            command_buffer,
+            draws,
+            &VulkanBase::viewports_of(area),
+            &VulkanBase::scissors_of(area),
+        );
+        w.cmd_end_render_pass(command_buffer);
+        cmd_set_view(device, command_buffer, uniform_buffer, view_projection);
+    }
+
+    /// Destroy the shadow map, once no frame in flight uses it. Shadow
+    /// variants are destroyed with their shared pipelines.
+    pub fn destroy(self, device: &Device) {
+        unsafe {
+            device.destroy_framebuffer(self.framebuffer, None);
+            device.destroy_render_pass(self.render_pass, None);
+            device.destroy_sampler(self.sampler, None);
+            self.depth.destroy(device);
+        }
+    }
+}
+
+fn extent() -> vk::Extent2D {
+    vk::Extent2D {
+        width: SHADOW_MAP_RESOLUTION,
+        height: SHADOW_MAP_RESOLUTION,
+    }
+}
+
+fn depth_range() -> vk::ImageSubresourceRange {
+    *vk::ImageSubresourceRange::builder()
+        .aspect_mask(vk::ImageAspectFlags::DEPTH)
+        .layer_count(1)
+        .level_count(1)
+}
+
+/// Record writing `view_projection` to the view of `uniform_buffer`, once the
+/// draws before have read it and before the draws after do.
+fn cmd_set_view(
+    device: &Device,
+    command_buffer: vk::CommandBuffer,
+    uniform_buffer: vk::Buffer,
+    view_projection: Mat4,
+) {
+    let before_update =
+        *vk::MemoryBarrier::builder().dst_access_mask(vk::AccessFlags::TRANSFER_WRITE);
+    let after_update = *vk::MemoryBarrier::builder()
+        .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+        .dst_access_mask(vk::AccessFlags::UNIFORM_READ);
+    unsafe {
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::VERTEX_SHADER | vk::PipelineStageFlags::FRAGMENT_SHADER,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::DependencyFlags::empty(),
+            &[before_update],
+            &[],
+            &[],
+        );
+        // The view is `UniformBuffer::proj`, which comes first.
+        device.cmd_update_buffer(
+            command_buffer,
+            uniform_buffer,
+            0,
+            bytemuck::bytes_of(&view_projection),
+        );
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::VERTEX_SHADER | vk::PipelineStageFlags::FRAGMENT_SHADER,
+            vk::DependencyFlags::empty(),
+            &[after_update],
+            &[],
+            &[],
+        );
+    }
+}
+
+/// Create the shadow map's render pass, leaving it ready for the scene's
+/// shaders to read.
+fn create_shadow_pass(device: &Device) -> Result<vk::RenderPass, RenderError> {
+    let attachments = [*vk::AttachmentDescription::builder()
+        .format(DEPTH_FORMAT)
+        .samples(vk::SampleCountFlags::TYPE_1)
+        .load_op(vk::AttachmentLoadOp::CLEAR)
+        .store_op(vk::AttachmentStoreOp::STORE)
+        .final_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)];
+    let depth_ref = vk::AttachmentReference {
+        attachment: 0,
+        layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+    };
+    // The shadow map is shared by every frame in flight, so a frame waits for
+    // the one before it to be done shading with it, and the scene pass waits
+    // for it to be written.
+    let dependencies = [
+        *vk::SubpassDependency::builder()
+            .src_subpass(vk::SUBPASS_EXTERNAL)
+            .dst_subpass(0)
+            .src_stage_mask(vk::PipelineStageFlags::FRAGMENT_SHADER)
+            .dst_stage_mask(
+                vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS
+                    | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+            )
+            .dst_access_mask(
+                vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
+                    | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            ),
+        *vk::SubpassDependency::builder()
+            .src_subpass(0)
+            .dst_subpass(vk::SUBPASS_EXTERNAL)
+            .src_stage_mask(vk::PipelineStageFlags::LATE_FRAGMENT_TESTS)
+            .src_access_mask(vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE)
+            .dst_stage_mask(vk::PipelineStageFlags::FRAGMENT_SHADER)
+            .dst_access_mask(vk::AccessFlags::SHADER_READ),
+    ];
+    let subpasses = [*vk::SubpassDescription::builder()
+        .depth_stencil_attachment(&depth_ref)
+        .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)];
+    let render_pass_info = vk::RenderPassCreateInfo::builder()
+        .attachments(&attachments)
+        .subpasses(&subpasses)
+        .dependencies(&dependencies);
+    unsafe { device.create_render_pass(&render_pass_info, None) }.map_err(RenderError::VkResultToDo)
+}
+
+/// Create the depth-only variant of `shared` drawing into the shadow map,
+/// with only its vertex shader, and depth biased.
+fn create_shadow_pipeline(
+    base: &VulkanBase,
+    shared: &SharedPipeline,
+    render_pass: vk::RenderPass,
+) -> Result<vk::Pipeline, RenderError> {
+    // The vertex shader's stage is added first, see `finish_shared_pipeline`.
+    let shader_stage_create_infos = [shared.shader_stages.shader_stage_defs[0].create_info()];
+    let viewport_state_info = vk::PipelineViewportStateCreateInfo::builder()
+        .scissors(&shared.scissors)
+        .viewports(&shared.viewports);
+    let rasterization_info = vk::PipelineRasterizationStateCreateInfo {
+        front_face: vk::FrontFace::COUNTER_CLOCKWISE,
+        line_width: 1.0,
+        polygon_mode: shared.polygon_mode,
+        depth_bias_enable: 1,
+        depth_bias_constant_factor: DEPTH_BIAS_CONSTANT,
+        depth_bias_slope_factor: DEPTH_BIAS_SLOPE,
+        ..Default::default()
+    };
+    let multisample_state_info = vk::PipelineMultisampleStateCreateInfo::builder()
+        .rasterization_samples(vk::SampleCountFlags::TYPE_1);
+    let depth_state_info = vk::PipelineDepthStencilStateCreateInfo {
+        depth_test_enable: 1,
+        depth_write_enable: 1,
+        depth_compare_op: vk::CompareOp::LESS_OR_EQUAL,
+        max_depth_bounds: 1.0,
+        ..Default::default()
+    };
+    // The shadow pass has no color attachments to blend.
+    let color_blend_state = vk::PipelineColorBlendStateCreateInfo::builder();
+    let dynamic_state = [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+    let dynamic_state_info =
+        vk::PipelineDynamicStateCreateInfo::builder().dynamic_states(&dynamic_state);
+    let vertex_input_state_info = shared.vertex_input_assembly.input_state_info();
+    let vertex_input_assembly_state_info = shared.vertex_input_assembly.assembly_state_info();
+    let graphics_pipeline_info = vk::GraphicsPipelineCreateInfo::builder()
+        .stages(&shader_stage_create_infos)
+        .vertex_input_state(&vertex_input_state_info)
+        .input_assembly_state(&vertex_input_assembly_state_info)
+        .viewport_state(&viewport_state_info)
+        .rasterization_state(&rasterization_info)
+        .multisample_state(&multisample_state_info)
+        .depth_stencil_state(&depth_state_info)
+        .color_blend_state(&color_blend_state)
+        .dynamic_state(&dynamic_state_info)
+        .layout(*shared.layout)
+        .render_pass(render_pass);
+    let pipeline = unsafe {
+        base.device.create_graphics_pipelines(
+            vk::PipelineCache::null(),
+            &[*graphics_pipeline_info],
+            None,
+        )
+    }
+    .map_err(|(pipeline, result)| RenderError::FailedToCreatePipeline(pipeline, result))?[0];
+    Ok(pipeline)
+}
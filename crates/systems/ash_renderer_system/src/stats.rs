@@ -0,0 +1,176 @@
+//! Pipeline statistics queries over the renderer's passes, reported through
+//! `Presenter::gpu_stats`.
+
+use std::mem::size_of;
+
+use ash::{vk, Device};
+use render::gpu_stats::{PassStatistics, PipelineStatistics};
+use render::readback::Readback;
+
+use crate::readback::Readbacks;
+use crate::types::RenderError;
+
+/// Counters queried, in the order `PipelineStatistics::from_counters` takes
+/// them.
+const STATISTICS: vk::QueryPipelineStatisticFlags = vk::QueryPipelineStatisticFlags::from_raw(
+    vk::QueryPipelineStatisticFlags::INPUT_ASSEMBLY_VERTICES.as_raw()
+        | vk::QueryPipelineStatisticFlags::VERTEX_SHADER_INVOCATIONS.as_raw()
+        | vk::QueryPipelineStatisticFlags::CLIPPING_INVOCATIONS.as_raw()
+        | vk::QueryPipelineStatisticFlags::CLIPPING_PRIMITIVES.as_raw()
+        | vk::QueryPipelineStatisticFlags::FRAGMENT_SHADER_INVOCATIONS.as_raw(),
+);
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum StatsPass {
+    Scene,
+    Probes,
+}
+
+impl StatsPass {
+    const ALL: [StatsPass; 2] = [StatsPass::Scene, StatsPass::Probes];
+
+    /// The pass's query in the pool, for the frame in flight at `frame`.
+    fn query(self, frame: usize) -> u32 {
+        (frame * Self::ALL.len()) as u32 + self as u32
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            StatsPass::Scene => "scene",
+            StatsPass::Probes => "probes",
+        }
+    }
+}
+
+type Counters = [u64; 5];
+
+/// A query per pass of each frame in flight, each read back once the
+/// commands it was recorded in complete. Results are kept per pass, whichever
+/// frame they came from.
+pub(crate) struct PipelineStatisticsQueries {
+    pool: vk::QueryPool,
+    /// Draws of each query recorded since its results were last read.
+    recorded: Vec<Option<usize>>,
+    /// Results of each query on their way back, with its draws.
+    in_flight: Vec<Option<(usize, Readback<Vec<u8>>)>>,
+    results: [Option<PassStatistics>; StatsPass::ALL.len()],
+}
+
+impl PipelineStatisticsQueries {
+    /// Only valid on a device created with the `pipeline_statistics_query`
+    /// feature.
+    pub fn new(device: &Device, frames: usize) -> Result<Self, RenderError> {
+        let queries = frames * StatsPass::ALL.len();
+        let create_info = vk::QueryPoolCreateInfo::builder()
+            .query_type(vk::QueryType::PIPELINE_STATISTICS)
+            .query_count(queries as u32)
+            .pipeline_statistics(STATISTICS);
+        let pool = unsafe { device.create_query_pool(&create_info, None) }
+            .map_err(RenderError::VkResultToDo)?;
+        Ok(Self {
+            pool,
+            recorded: vec![None; queries],
+            in_flight: (0..queries).map(|_| None).collect(),
+            results: [None, None],
+        })
+    }
+
+    /// Record starting the pass's query for the frame in flight at `frame`,
+    /// outside of any render pass.
+    pub fn cmd_begin(
+        &mut self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        frame: usize,
+        pass: StatsPass,
+        draws: usize,
+    ) {
+        let query = pass.query(frame);
+        unsafe {
+            device.cmd_reset_query_pool(command_buffer, self.pool, query, 1);
+            device.cmd_begin_query(
+                command_buffer,
+                self.pool,
+                query,
+                vk::QueryControlFlags::empty(),
+            );
+        }
+        self.recorded[query as usize] = Some(draws);
+    }
+
+    /// Record ending the pass's query for the frame in flight at `frame`,
+    /// outside of any render pass, and reading its results back once `fence`
+    /// signals.
+    #[allow(clippy::too_many_arguments)]
+    pub fn cmd_end(
+        &mut self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        frame: usize,
+        pass: StatsPass,
+        readbacks: &mut Readbacks,
+        fence: vk::Fence,
+    ) {
+        let query = pass.query(frame);
+        let index = query as usize;
+        unsafe { device.cmd_end_query(command_buffer, self.pool, query) };
+        let draws = match self.recorded[index].take() {
+            Some(draws) => draws,
+            None => return,
+        };
+        // Without a staging buffer to read them into, the last results are
+        // kept.
+        if let Ok(readback) = readbacks.cmd_read_query_results(
+            device,
+            command_buffer,
+            fence,
+            self.pool,
+            query,
+            1,
+            size_of::<Counters>() as u64,
+        ) {
+            self.in_flight[index] = Some((draws, readback));
+        }
+    }
+
+    /// Take the results of each pass that have been read back since they
+    /// were last taken, from any frame.
+    pub fn read(&mut self) {
+        let frames = self.in_flight.len() / StatsPass::ALL.len();
+        for (frame, pass) in (0..frames).flat_map(|frame| StatsPass::ALL.map(|pass| (frame, pass)))
+        {
+            let index = pass.query(frame) as usize;
+            let taken = self.in_flight[index]
+                .as_mut()
+                .and_then(|(draws, readback)| Some((*draws, readback.try_take()?)));
+            let (draws, bytes) = match taken {
+                Some(taken) => taken,
+                None => continue,
+            };
+            self.in_flight[index] = None;
+            // Results that couldn't be read back keep the last ones.
+            if let Some(counters) = bytes
+                .ok()
+                .filter(|bytes| bytes.len() >= size_of::<Counters>())
+            {
+                let counters: Counters =
+                    bytemuck::pod_read_unaligned(&counters[..size_of::<Counters>()]);
+                self.results[pass as usize] = Some(PassStatistics {
+                    pass: pass.name(),
+                    draws,
+                    statistics: PipelineStatistics::from_counters(counters),
+                });
+            }
+        }
+    }
+
+    /// The latest results of each pass that has been read.
+    pub fn passes(&self) -> Vec<PassStatistics> {
+        self.results.iter().flatten().cloned().collect()
+    }
+
+    /// Only called once nothing in flight uses the queries.
+    pub fn destroy(self, device: &Device) {
+        unsafe { device.destroy_query_pool(self.pool, None) };
+    }
+}
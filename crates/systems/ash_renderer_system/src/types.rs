@@ -1,14 +1,20 @@
+use std::collections::hash_map::DefaultHasher;
 use std::ffi::{CString, NulError};
 use std::fs::File;
+use std::hash::{Hash, Hasher};
 use std::io::{self, BufReader, Cursor, Read};
 use std::mem;
 use std::path::{Path, PathBuf};
-use std::sync::Arc;
+use std::sync::{Arc, OnceLock};
 
 use ash::vk;
+use render::target::RenderTargetId;
 use stable_typeid::StableTypeId;
 use world::Entity;
 
+use crate::pipeline_cache::PipelineKey;
+use crate::resource::Owned;
+
 /// Collection of specific error types that Vulkan can raise, in rust form.
 #[derive(thiserror::Error, Debug)]
 pub enum RenderError {
@@ -21,6 +27,9 @@ pub enum RenderError {
     #[error("Unable to find suitable memorytype for the buffer")]
     UnableToFindMemoryTypeForBuffer,
 
+    #[error("Unable to find suitable memorytype for the image")]
+    UnableToFindMemoryTypeForImage,
+
     // TODO: find call sites and generate new error variants for this
     #[error("vk result ({0:?}) todo: assign a real error variant")]
     VkResultToDo(vk::Result),
@@ -54,27 +63,70 @@ pub enum RenderError {
     #[error("submit command buffer error {0:?}")]
     SubmitCommandBuffers(vk::Result),
 
+    #[error("reset command pool error {0:?}")]
+    ResetCommandPool(vk::Result),
+
     #[error("error enumerating physical devices {0:?})")]
     EnumeratePhysicalDevices(vk::Result),
 
     #[error("error no shader entry point found")]
     NoShaderEntryPoint,
 
+    #[error("shader {shader:?} reads vertex inputs at {locations:?}, no vertex layout has them")]
+    NoVertexLayout {
+        shader: PathBuf,
+        locations: Vec<u32>,
+    },
+
+    #[error("shader {0:?} skins vertices, but the graphic has no skin weights")]
+    NoSkinWeights(PathBuf),
+
+    #[error("shader {shader:?} reads {size} bytes of push constants, only {max} are pushed")]
+    PushConstantsTooLarge {
+        shader: PathBuf,
+        size: u32,
+        max: u32,
+    },
+
     #[error("component missing from camera entity {0:?}, {1:?}, {2:?}")]
     ComponentMissingFromCameraEntity(Entity, &'static str, StableTypeId),
+
+    #[error("camera entity {0:?} doesn't exist")]
+    CameraEntityMissing(Entity),
+
+    #[error("the device can't present to the window of {0}")]
+    SurfaceNotSupported(RenderTargetId),
 }
 
 impl RenderError {
     pub fn shader_reflect(s: &str) -> Self {
         RenderError::ShaderReflect(s.to_string())
     }
+
+    /// The result Vulkan failed with, if it did.
+    pub fn vk_result(&self) -> Option<vk::Result> {
+        match self {
+            RenderError::VkResultToDo(result)
+            | RenderError::Present(result)
+            | RenderError::FailedToCreatePipeline(_, result)
+            | RenderError::SwapchainAcquireNextImage(result)
+            | RenderError::Fence(result)
+            | RenderError::FenceReset(result)
+            | RenderError::BeginCommandBuffer(result)
+            | RenderError::EndCommandBuffer(result)
+            | RenderError::SubmitCommandBuffers(result)
+            | RenderError::ResetCommandPool(result)
+            | RenderError::EnumeratePhysicalDevices(result) => Some(*result),
+            _ => None,
+        }
+    }
 }
 
 /// A handle to a Vulkan GPU buffer and it's backing memory.
 /// TODO: typed buffer and memory
 pub struct BufferAndMemory {
-    pub buffer: vk::Buffer,
-    pub memory: vk::DeviceMemory,
+    pub buffer: Owned<vk::Buffer>,
+    pub memory: Owned<vk::DeviceMemory>,
 
     /// len of the original slice copied from.
     pub original_len: usize,
@@ -93,18 +145,16 @@ impl BufferAndMemory {
         allocation_size: u64,
     ) -> Self {
         Self {
-            buffer,
-            memory,
+            buffer: Owned::new(buffer),
+            memory: Owned::new(memory),
             original_len,
             allocation_size,
         }
     }
 
     pub fn deallocate(&self, device: &ash::Device) {
-        unsafe {
-            device.free_memory(self.memory, None);
-            device.destroy_buffer(self.buffer, None);
-        }
+        self.buffer.destroy(device);
+        self.memory.destroy(device);
     }
 }
 
@@ -141,16 +191,22 @@ impl DescriptorSetLayoutBinding {
 /// Holds references to GPU resources for a texture.
 pub struct Texture {
     pub format: vk::Format,
-    pub image: vk::Image,
-    pub memory: vk::DeviceMemory,
-    pub image_view: vk::ImageView,
+    pub image: Owned<vk::Image>,
+    pub memory: Owned<vk::DeviceMemory>,
+    pub image_view: Owned<vk::ImageView>,
+    /// Bytes of `memory`.
+    pub size: u64,
 }
 
 impl Texture {
+    /// Create a texture from an image with `mip_levels` levels, all of which
+    /// are seen through its view.
     pub fn create(
         format: vk::Format,
+        mip_levels: u32,
         image: vk::Image,
         memory: vk::DeviceMemory,
+        size: u64,
         device: &ash::Device,
     ) -> Result<Self, RenderError> {
         let img_view_info = vk::ImageViewCreateInfo {
@@ -164,30 +220,36 @@ impl Texture {
             },
             subresource_range: vk::ImageSubresourceRange {
                 aspect_mask: vk::ImageAspectFlags::COLOR,
-                level_count: 1,
+                level_count: mip_levels,
                 layer_count: 1,
                 ..Default::default()
             },
             image,
             ..Default::default()
         };
-        let image_view = unsafe { device.create_image_view(&img_view_info, None) }
-            .map_err(RenderError::VkResultToDo)?;
+        let (image, memory) = (Owned::new(image), Owned::new(memory));
+        let image_view = match unsafe { device.create_image_view(&img_view_info, None) } {
+            Ok(image_view) => Owned::new(image_view),
+            Err(err) => {
+                image.destroy(device);
+                memory.destroy(device);
+                return Err(RenderError::VkResultToDo(err));
+            }
+        };
 
         Ok(Self {
             image,
             format,
             memory,
             image_view,
+            size,
         })
     }
 
     pub fn deallocate(&self, device: &ash::Device) {
-        unsafe {
-            device.destroy_image_view(self.image_view, None);
-            device.free_memory(self.memory, None);
-            device.destroy_image(self.image, None);
-        }
+        self.image_view.destroy(device);
+        self.image.destroy(device);
+        self.memory.destroy(device);
     }
 }
 
@@ -244,6 +306,174 @@ impl VertexInputAssembly {
             .vertex_attribute_descriptions(&self.attribute_descriptions)
             .vertex_binding_descriptions(&self.binding_descriptions)
     }
+
+    /// Describe the vertex inputs `vertex_shader` reads, as reflected, from
+    /// the first of `layouts` with an attribute of the same kind at each of
+    /// their locations. Each of `layouts` is the vertex buffers bound at once,
+    /// such as a mesh's vertices and its skin weights. Attributes the shader
+    /// doesn't read are left out, and so are buffers it reads none of.
+    pub fn reflect(
+        topology: vk::PrimitiveTopology,
+        vertex_shader: &Shader,
+        layouts: &[&[VertexLayout]],
+    ) -> Result<Self, RenderError> {
+        let inputs = vertex_shader
+            .entry_points()
+            .iter()
+            .flat_map(EntryPoint::vertex_inputs)
+            .collect::<Vec<_>>();
+        let mut assembly = Self::new(topology);
+        if inputs.is_empty() {
+            return Ok(assembly);
+        }
+        let buffers = layouts
+            .iter()
+            .find(|buffers| {
+                inputs
+                    .iter()
+                    .all(|input| buffers.iter().any(|layout| layout.provides(input)))
+            })
+            .ok_or_else(|| RenderError::NoVertexLayout {
+                shader: vertex_shader.path().to_path_buf(),
+                locations: inputs.iter().map(|input| input.location).collect(),
+            })?;
+        for layout in buffers.iter() {
+            let read: Vec<&VertexAttribute> = layout
+                .attributes
+                .iter()
+                .filter(|attribute| {
+                    inputs
+                        .iter()
+                        .any(|input| input.location == attribute.location)
+                })
+                .collect();
+            if read.is_empty() {
+                continue;
+            }
+            assembly
+                .binding_descriptions
+                .push(vk::VertexInputBindingDescription {
+                    binding: layout.binding,
+                    stride: layout.stride,
+                    input_rate: vk::VertexInputRate::VERTEX,
+                });
+            for attribute in read {
+                assembly.add_attribute_description(
+                    layout.binding,
+                    attribute.location,
+                    attribute.format,
+                    attribute.offset,
+                );
+            }
+        }
+        Ok(assembly)
+    }
+
+    /// Whether a vertex buffer is read at `binding`.
+    pub fn reads_binding(&self, binding: u32) -> bool {
+        self.binding_descriptions
+            .iter()
+            .any(|description| description.binding == binding)
+    }
+}
+
+/// How vertices are laid out in a vertex buffer, matched against the inputs
+/// a vertex shader reads to describe them to a pipeline.
+#[derive(Debug, Clone)]
+pub struct VertexLayout {
+    /// Binding the buffer is bound at when drawing.
+    pub binding: u32,
+    pub stride: u32,
+    pub attributes: Vec<VertexAttribute>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct VertexAttribute {
+    pub location: u32,
+    pub format: vk::Format,
+    pub offset: u32,
+}
+
+impl VertexLayout {
+    /// A layout of vertices of type `T` bound at 0, without attributes yet.
+    pub fn of<T>() -> Self
+    where
+        T: Copy,
+    {
+        Self {
+            binding: 0,
+            stride: std::mem::size_of::<T>() as u32,
+            attributes: Vec::new(),
+        }
+    }
+
+    pub fn at_binding(mut self, binding: u32) -> Self {
+        self.binding = binding;
+        self
+    }
+
+    pub fn with_attribute(mut self, location: u32, format: vk::Format, offset: u32) -> Self {
+        self.attributes.push(VertexAttribute {
+            location,
+            format,
+            offset,
+        });
+        self
+    }
+
+    /// Whether there's an attribute for `input` to read. Vulkan fills in or
+    /// drops components when their counts differ, but not their kind.
+    fn provides(&self, input: &VertexInput) -> bool {
+        self.attributes.iter().any(|attribute| {
+            attribute.location == input.location
+                && NumericKind::of(attribute.format) == NumericKind::of(input.format)
+        })
+    }
+}
+
+/// What a vertex attribute's components are read as.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum NumericKind {
+    Float,
+    Sint,
+    Uint,
+}
+
+impl NumericKind {
+    fn of(format: vk::Format) -> Self {
+        match format {
+            vk::Format::R8_UINT
+            | vk::Format::R8G8_UINT
+            | vk::Format::R8G8B8A8_UINT
+            | vk::Format::R16_UINT
+            | vk::Format::R16G16_UINT
+            | vk::Format::R16G16B16A16_UINT
+            | vk::Format::R32_UINT
+            | vk::Format::R32G32_UINT
+            | vk::Format::R32G32B32_UINT
+            | vk::Format::R32G32B32A32_UINT => NumericKind::Uint,
+            vk::Format::R8_SINT
+            | vk::Format::R8G8_SINT
+            | vk::Format::R8G8B8A8_SINT
+            | vk::Format::R16_SINT
+            | vk::Format::R16G16_SINT
+            | vk::Format::R16G16B16A16_SINT
+            | vk::Format::R32_SINT
+            | vk::Format::R32G32_SINT
+            | vk::Format::R32G32B32_SINT
+            | vk::Format::R32G32B32A32_SINT => NumericKind::Sint,
+            // Normalized and scaled formats are read as floats too.
+            _ => NumericKind::Float,
+        }
+    }
+}
+
+/// A vertex input read by a vertex entry point, as reflected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VertexInput {
+    pub location: u32,
+    /// Format the shader reads, which the attribute's is converted to.
+    pub format: vk::Format,
 }
 
 #[derive(Default)]
@@ -299,34 +529,67 @@ impl<'a> AttachmentsModifier<'a> {
     }
 }
 
-/// Describes a pipeline.
+/// Describes how a graphic is drawn: the pipeline shared by graphics with the
+/// same shaders, and descriptor sets binding the graphic's own resources.
 pub struct Pipeline {
-    pub desc_set_layout: vk::DescriptorSetLayout,
-    pub uniform_buffer: BufferAndMemory,
-    pub descriptor_set: vk::DescriptorSet,
-    pub maybe_diffuse_sampler: Option<vk::Sampler>,
+    pub shared: Arc<SharedPipeline>,
+    /// A descriptor set per frame in flight, bound to that frame's buffers.
+    pub descriptor_sets: Vec<vk::DescriptorSet>,
+    pub maybe_diffuse_sampler: Option<Owned<vk::Sampler>>,
     // pub specular_sampler: vk::Sampler,
     // pub bump_sampler: vk::Sampler,
-    pub layout: vk::PipelineLayout,
+}
+
+impl Pipeline {
+    pub fn new(shared: Arc<SharedPipeline>, descriptor_sets: Vec<vk::DescriptorSet>) -> Self {
+        Self {
+            shared,
+            descriptor_sets,
+            maybe_diffuse_sampler: None,
+        }
+    }
+
+    /// Deallocate the graphic's own resources on the GPU. The shared pipeline
+    /// is released to the `PipelineCache` instead.
+    pub fn deallocate(&self, device: &ash::Device) {
+        if let Some(sampler) = self.maybe_diffuse_sampler.as_ref() {
+            sampler.destroy(device);
+        }
+        // device.destroy_sampler(self.specular_sampler, None);
+        // device.destroy_sampler(self.bump_sampler, None);
+    }
+}
+
+/// A pipeline and its layouts, shared by every graphic drawn with the same
+/// shaders, topology and vertex layout, see `PipelineCache`.
+pub struct SharedPipeline {
+    pub key: PipelineKey,
+    pub desc_set_layout: Owned<vk::DescriptorSetLayout>,
+    pub layout: Owned<vk::PipelineLayout>,
     pub viewports: Vec<vk::Viewport>,
     pub scissors: Vec<vk::Rect2D>,
     pub shader_stages: ShaderStages,
     pub vertex_input_assembly: VertexInputAssembly,
     pub polygon_mode: vk::PolygonMode,
-    pub vk: Option<vk::Pipeline>,
+    pub vk: Option<Owned<vk::Pipeline>>,
+    /// Whether graphics drawn with this can be drawn deferred, because they're
+    /// shaded by the default fragment shader, see `DeferredPass`.
+    pub deferrable: bool,
+    /// The variant drawing into the G-buffer instead, built the first time
+    /// it's drawn deferred. None once it couldn't be built.
+    pub gbuffer: OnceLock<Option<Owned<vk::Pipeline>>>,
+    /// The depth-only variant drawing into the shadow map, built the first
+    /// time it casts shadows. None once it couldn't be built.
+    pub shadow: OnceLock<Option<Owned<vk::Pipeline>>>,
 }
 
-impl Pipeline {
-    /// Create a new PipelineDesc.
+impl SharedPipeline {
+    /// Create a new SharedPipeline.
     #[allow(clippy::too_many_arguments)]
     pub fn create(
-        desc_set_layout: vk::DescriptorSetLayout,
-        uniform_buffer: BufferAndMemory,
-        descriptor_set: vk::DescriptorSet,
-        maybe_diffuse_sampler: Option<vk::Sampler>,
-        // specular_sampler: vk::Sampler,
-        // bump_sampler: vk::Sampler,
-        layout: vk::PipelineLayout,
+        key: PipelineKey,
+        desc_set_layout: Owned<vk::DescriptorSetLayout>,
+        layout: Owned<vk::PipelineLayout>,
         viewports: Vec<vk::Viewport>,
         scissors: Vec<vk::Rect2D>,
         shader_stages: ShaderStages,
@@ -334,12 +597,8 @@ impl Pipeline {
         polygon_mode: vk::PolygonMode,
     ) -> Self {
         Self {
+            key,
             desc_set_layout,
-            uniform_buffer,
-            descriptor_set,
-            maybe_diffuse_sampler,
-            // specular_sampler,
-            // bump_sampler,
             layout,
             viewports,
             scissors,
@@ -347,34 +606,31 @@ impl Pipeline {
             vertex_input_assembly,
             polygon_mode,
             vk: None,
+            deferrable: false,
+            gbuffer: OnceLock::new(),
+            shadow: OnceLock::new(),
         }
     }
 
-    /// Deallocate PipelineDesc's resources on the GPU.
+    /// Deallocate SharedPipeline's resources on the GPU, the pipeline before
+    /// what it was created from.
     pub fn deallocate(&self, device: &ash::Device) {
-        self.uniform_buffer.deallocate(device);
-
-        unsafe {
-            device.destroy_pipeline_layout(self.layout, None);
+        if let Some(Some(gbuffer)) = self.gbuffer.get() {
+            gbuffer.destroy(device);
         }
-
-        self.shader_stages.deallocate(device);
-
-        if let Some(sampler) = self.maybe_diffuse_sampler {
-            unsafe {
-                device.destroy_sampler(sampler, None);
-            }
+        if let Some(Some(shadow)) = self.shadow.get() {
+            shadow.destroy(device);
         }
-
-        // device.destroy_sampler(self.specular_sampler, None);
-        // device.destroy_sampler(self.bump_sampler, None);
-        unsafe {
-            device.destroy_descriptor_set_layout(self.desc_set_layout, None);
+        if let Some(vk) = self.vk.as_ref() {
+            vk.destroy(device);
         }
+        self.layout.destroy(device);
+        self.shader_stages.deallocate(device);
+        self.desc_set_layout.destroy(device);
     }
 
     pub(crate) fn set_vk(&mut self, vk: vk::Pipeline) {
-        self.vk = Some(vk)
+        self.vk = Some(Owned::new(vk))
     }
 }
 
@@ -385,6 +641,7 @@ pub struct EntryPoint {
     stage_flags: vk::ShaderStageFlags,
     descriptor_set_layout_bindings: Vec<DescriptorSetLayoutBinding>,
     push_constant_ranges: Vec<vk::PushConstantRange>,
+    vertex_inputs: Vec<VertexInput>,
 }
 
 impl EntryPoint {
@@ -408,10 +665,18 @@ impl EntryPoint {
     pub fn push_constant_ranges(&self) -> &[vk::PushConstantRange] {
         &self.push_constant_ranges
     }
+
+    /// Get the vertex inputs of the entry point by location, without
+    /// built-ins such as the vertex index. Empty for other stages.
+    pub fn vertex_inputs(&self) -> &[VertexInput] {
+        &self.vertex_inputs
+    }
 }
 
 pub struct Shader {
     data: Vec<u8>,
+    /// Hash of `data`, telling shaders apart regardless of their path.
+    content_hash: u64,
     path: PathBuf,
     entry_points: Vec<EntryPoint>,
 }
@@ -468,6 +733,11 @@ impl Shader {
         &self.path
     }
 
+    /// Hash of the SPIR-V, equal for shaders with the same code.
+    pub fn content_hash(&self) -> u64 {
+        self.content_hash
+    }
+
     /// Reload this shader from disk.
     pub fn reload(&mut self) -> Result<(), RenderError> {
         let new = Self::read_spv(self.path.clone())?;
@@ -529,15 +799,38 @@ impl Shader {
                     size,
                 });
             }
+            let mut vertex_inputs = Vec::new();
+            if stage_flags.contains(vk::ShaderStageFlags::VERTEX) {
+                for input in shader_module
+                    .enumerate_input_variables(Some(&entry_point.name))
+                    .map_err(RenderError::shader_reflect)?
+                {
+                    if input
+                        .decoration_flags
+                        .contains(spirv_reflect::types::ReflectDecorationFlags::BUILT_IN)
+                    {
+                        continue;
+                    }
+                    vertex_inputs.push(VertexInput {
+                        location: input.location,
+                        format: spirv_reflect_format_to_vk(&input.format),
+                    });
+                }
+                vertex_inputs.sort_by_key(|input| input.location);
+            }
             entry_points.push(EntryPoint {
                 name: entry_point.name.clone(),
                 stage_flags,
                 descriptor_set_layout_bindings: descriptor_set_bindings.clone(),
                 push_constant_ranges: Vec::new(),
+                vertex_inputs,
             })
         }
 
+        let mut hasher = DefaultHasher::new();
+        data.hash(&mut hasher);
         Ok(Self {
+            content_hash: hasher.finish(),
             data,
             path,
             entry_points,
@@ -551,6 +844,25 @@ fn spirv_reflect_shader_stage_to_vk(
     vk::ShaderStageFlags::from_raw(stage.bits())
 }
 
+fn spirv_reflect_format_to_vk(format: &spirv_reflect::types::ReflectFormat) -> vk::Format {
+    use spirv_reflect::types::ReflectFormat::*;
+    match format {
+        Undefined => vk::Format::UNDEFINED,
+        R32_UINT => vk::Format::R32_UINT,
+        R32_SINT => vk::Format::R32_SINT,
+        R32_SFLOAT => vk::Format::R32_SFLOAT,
+        R32G32_UINT => vk::Format::R32G32_UINT,
+        R32G32_SINT => vk::Format::R32G32_SINT,
+        R32G32_SFLOAT => vk::Format::R32G32_SFLOAT,
+        R32G32B32_UINT => vk::Format::R32G32B32_UINT,
+        R32G32B32_SINT => vk::Format::R32G32B32_SINT,
+        R32G32B32_SFLOAT => vk::Format::R32G32B32_SFLOAT,
+        R32G32B32A32_UINT => vk::Format::R32G32B32A32_UINT,
+        R32G32B32A32_SINT => vk::Format::R32G32B32A32_SINT,
+        R32G32B32A32_SFLOAT => vk::Format::R32G32B32A32_SFLOAT,
+    }
+}
+
 fn spirv_reflect_descriptor_type_to_vk(
     desc: &spirv_reflect::types::ReflectDescriptorType,
 ) -> vk::DescriptorType {
@@ -575,7 +887,7 @@ fn spirv_reflect_descriptor_type_to_vk(
 /// Tracks modules and definitions used to initialize shaders.
 #[derive(Default)]
 pub struct ShaderStages {
-    pub modules: Vec<vk::ShaderModule>,
+    pub modules: Vec<Owned<vk::ShaderModule>>,
     pub shader_stage_defs: Vec<ShaderStage>,
 }
 
@@ -603,10 +915,10 @@ impl ShaderStages {
         let module = shader.as_shader_module(device)?;
 
         let idx = self.modules.len();
-        self.modules.push(module);
+        self.modules.push(Owned::new(module));
 
         let shader_stage = ShaderStage::new(
-            self.modules[idx],
+            *self.modules[idx],
             entry_point_name,
             stage_flags,
             pipeline_flags,
@@ -617,7 +929,7 @@ impl ShaderStages {
 
     pub fn deallocate(&self, device: &ash::Device) {
         for shader_module in self.modules.iter() {
-            unsafe { device.destroy_shader_module(*shader_module, None) };
+            shader_module.destroy(device);
         }
     }
 }
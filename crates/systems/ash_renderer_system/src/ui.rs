@@ -0,0 +1,810 @@
+//! Draws the debug UI in `World::debug_ui` over the scene. Its meshes change
+//! every frame, so like the debug lines they're written into vertex and index
+//! buffers kept between frames, replaced with larger ones when they run out of
+//! room, with buffers for each frame in flight. Textures egui asks for, such as
+//! its font atlas, are shared by every frame and uploaded whenever they change,
+//! which is rarely, so frames in flight are waited on rather than textures
+//! being kept until they're done.
+//!
+//! The UI is drawn at the end of the scene pass, so it's scaled along with the
+//! scene when that's rendered at another resolution than the window's.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use ash::vk;
+use egui::epaint::{ImageDelta, Primitive, Vertex as UiVertex};
+use egui::{Color32, ImageData, Rect, TextureFilter, TextureId, TexturesDelta};
+use logger::{debug, trace, warn, Logger};
+use shader_objects::{UiPushConstants, UI_TEXTURE_BINDING};
+use world::debug_ui::DebugUi;
+
+use crate::device::DeviceWrapper;
+use crate::resource::Owned;
+use crate::types::{
+    BufferAndMemory, RenderError, Shader, ShaderStage, ShaderStages, Texture, VertexInputAssembly,
+    VertexLayout,
+};
+use crate::VulkanBase;
+
+const VERTEX_SHADER: &str = "assets/shaders/spv/egui_vertex.spv";
+const FRAGMENT_SHADER: &str = "assets/shaders/spv/egui_fragment.spv";
+
+// Vertices and indices the first buffers have room for.
+const MIN_VERTICES: usize = 4096;
+const MIN_INDICES: usize = 8192;
+
+// Most textures the UI has at once, each with a descriptor set.
+const MAX_TEXTURES: u32 = 64;
+
+/// A texture egui asked for, sampled by the meshes that name it.
+struct UiTexture {
+    texture: Texture,
+    descriptor_set: vk::DescriptorSet,
+    /// The texture's pixels, so updates to part of it can be applied and the
+    /// whole texture uploaded again.
+    pixels: Vec<Color32>,
+    size: [usize; 2],
+}
+
+struct UiBuffers {
+    vertices: BufferAndMemory,
+    indices: BufferAndMemory,
+    vertex_capacity: usize,
+    index_capacity: usize,
+}
+
+impl UiBuffers {
+    fn deallocate(&self, device: &ash::Device) {
+        self.vertices.deallocate(device);
+        self.indices.deallocate(device);
+    }
+}
+
+/// A mesh of the UI, drawn with its texture and clipped to its rect.
+struct UiDraw {
+    descriptor_set: vk::DescriptorSet,
+    scissor: vk::Rect2D,
+    first_index: u32,
+    index_count: u32,
+    vertex_offset: i32,
+}
+
+/// What's drawn in a frame in flight.
+#[derive(Default)]
+struct UiFrame {
+    buffers: Option<UiBuffers>,
+    draws: Vec<UiDraw>,
+    screen_size: glam::Vec2,
+    extent: vk::Extent2D,
+}
+
+pub(crate) struct UiPass {
+    pipeline: Owned<vk::Pipeline>,
+    layout: Owned<vk::PipelineLayout>,
+    desc_set_layout: Owned<vk::DescriptorSetLayout>,
+    shader_stages: ShaderStages,
+    descriptor_pool: vk::DescriptorPool,
+    linear_sampler: Owned<vk::Sampler>,
+    nearest_sampler: Owned<vk::Sampler>,
+    textures: HashMap<TextureId, UiTexture>,
+    frames: Vec<UiFrame>,
+    /// Whether the swapchain encodes what's written to it as sRGB.
+    srgb_target: bool,
+    /// The frame's meshes, kept to reuse the allocations.
+    vertices: Vec<UiVertex>,
+    indices: Vec<u32>,
+    logger: Logger,
+}
+
+impl UiPass {
+    pub fn new(base: &VulkanBase, logger: &Logger) -> Result<Self, RenderError> {
+        debug!(logger, "creating the debug UI pass");
+        let device = &base.device;
+        let vertex_shader = Arc::new(Shader::read_spv(PathBuf::from(VERTEX_SHADER))?);
+        let fragment_shader = Arc::new(Shader::read_spv(PathBuf::from(FRAGMENT_SHADER))?);
+        let vertex_input_assembly = VertexInputAssembly::reflect(
+            vk::PrimitiveTopology::TRIANGLE_LIST,
+            &vertex_shader,
+            &[&[ui_vertex_layout()]],
+        )?;
+
+        // Each step destroys what the steps before it created if it fails.
+        let bindings = [vk::DescriptorSetLayoutBinding {
+            binding: UI_TEXTURE_BINDING,
+            descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            descriptor_count: 1,
+            stage_flags: vk::ShaderStageFlags::FRAGMENT,
+            ..Default::default()
+        }];
+        let layout_info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(&bindings);
+        let desc_set_layout = Owned::new(
+            unsafe { device.create_descriptor_set_layout(&layout_info, None) }
+                .map_err(RenderError::VkResultToDo)?,
+        );
+        let w = DeviceWrapper::wrap(device, logger);
+        let layout = match w.pipeline_layout(
+            std::mem::size_of::<UiPushConstants>() as u32,
+            &[*desc_set_layout],
+        ) {
+            Ok(layout) => Owned::new(layout),
+            Err(err) => {
+                desc_set_layout.destroy(device);
+                return Err(err);
+            }
+        };
+        let destroy_layouts = || {
+            layout.destroy(device);
+            desc_set_layout.destroy(device);
+        };
+
+        let mut shader_stages = ShaderStages::new();
+        let pipeline = shader_stages
+            .add_shader(device, vertex_shader, vk::ShaderStageFlags::VERTEX)
+            .and_then(|()| {
+                shader_stages.add_shader(device, fragment_shader, vk::ShaderStageFlags::FRAGMENT)
+            })
+            .and_then(|()| create_pipeline(base, &shader_stages, &vertex_input_assembly, *layout));
+        let pipeline = match pipeline {
+            Ok(pipeline) => Owned::new(pipeline),
+            Err(err) => {
+                shader_stages.deallocate(device);
+                destroy_layouts();
+                return Err(err);
+            }
+        };
+
+        let pool_sizes = [vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            descriptor_count: MAX_TEXTURES,
+        }];
+        let pool_info = vk::DescriptorPoolCreateInfo::builder()
+            .flags(vk::DescriptorPoolCreateFlags::FREE_DESCRIPTOR_SET)
+            .pool_sizes(&pool_sizes)
+            .max_sets(MAX_TEXTURES);
+        let samplers = unsafe { device.create_descriptor_pool(&pool_info, None) }
+            .map_err(RenderError::VkResultToDo)
+            .and_then(|descriptor_pool| {
+                let samplers = create_sampler(device, vk::Filter::LINEAR).and_then(|linear| {
+                    match create_sampler(device, vk::Filter::NEAREST) {
+                        Ok(nearest) => Ok((linear, nearest)),
+                        Err(err) => {
+                            linear.destroy(device);
+                            Err(err)
+                        }
+                    }
+                });
+                match samplers {
+                    Ok((linear, nearest)) => Ok((descriptor_pool, linear, nearest)),
+                    Err(err) => {
+                        unsafe { device.destroy_descriptor_pool(descriptor_pool, None) };
+                        Err(err)
+                    }
+                }
+            });
+        let (descriptor_pool, linear_sampler, nearest_sampler) = match samplers {
+            Ok(samplers) => samplers,
+            Err(err) => {
+                pipeline.destroy(device);
+                shader_stages.deallocate(device);
+                destroy_layouts();
+                return Err(err);
+            }
+        };
+
+        Ok(Self {
+            pipeline,
+            layout,
+            desc_set_layout,
+            shader_stages,
+            descriptor_pool,
+            linear_sampler,
+            nearest_sampler,
+            textures: HashMap::new(),
+            frames: base.frames.iter().map(|_| UiFrame::default()).collect(),
+            srgb_target: is_srgb(base.surface_format.format),
+            vertices: Vec::new(),
+            indices: Vec::new(),
+            logger: logger.sub("ui"),
+        })
+    }
+
+    /// Apply changes to the UI's textures, then write the UI's last frame
+    /// into the buffers of the frame in flight at `frame`, drawn to a pass of
+    /// `extent`. Must only be called once that frame has completed.
+    pub fn prepare(
+        &mut self,
+        base: &mut VulkanBase,
+        frame: usize,
+        ui: &DebugUi,
+        extent: vk::Extent2D,
+    ) -> Result<(), RenderError> {
+        self.update_textures(base, ui.take_textures_delta())?;
+
+        self.vertices.clear();
+        self.indices.clear();
+        let screen_size = ui.screen_size();
+        let ui_frame = &mut self.frames[frame];
+        ui_frame.draws.clear();
+        ui_frame.screen_size = glam::Vec2::new(screen_size.x, screen_size.y);
+        ui_frame.extent = extent;
+        for clipped in ui.primitives() {
+            let mesh = match &clipped.primitive {
+                Primitive::Mesh(mesh) => mesh,
+                Primitive::Callback(_) => {
+                    trace!(self.logger, "paint callbacks aren't supported, skipping");
+                    continue;
+                }
+            };
+            let descriptor_set = match self.textures.get(&mesh.texture_id) {
+                Some(texture) => texture.descriptor_set,
+                None => {
+                    trace!(self.logger, "no texture {:?}, skipping", mesh.texture_id);
+                    continue;
+                }
+            };
+            let scissor = match scissor(clipped.clip_rect, screen_size, extent) {
+                Some(scissor) if !mesh.indices.is_empty() => scissor,
+                _ => continue,
+            };
+            ui_frame.draws.push(UiDraw {
+                descriptor_set,
+                scissor,
+                first_index: self.indices.len() as u32,
+                index_count: mesh.indices.len() as u32,
+                vertex_offset: self.vertices.len() as i32,
+            });
+            self.vertices.extend_from_slice(&mesh.vertices);
+            self.indices.extend_from_slice(&mesh.indices);
+        }
+        if ui_frame.draws.is_empty() {
+            return Ok(());
+        }
+
+        let w = DeviceWrapper::wrap(&base.device, &self.logger);
+        let fits = ui_frame.buffers.as_ref().map_or(false, |buffers| {
+            buffers.vertex_capacity >= self.vertices.len()
+                && buffers.index_capacity >= self.indices.len()
+        });
+        if !fits {
+            if let Some(old) = ui_frame.buffers.take() {
+                old.deallocate(&base.device);
+            }
+            let vertex_capacity = self.vertices.len().next_power_of_two().max(MIN_VERTICES);
+            let index_capacity = self.indices.len().next_power_of_two().max(MIN_INDICES);
+            debug!(
+                self.logger,
+                "allocating UI buffers for {vertex_capacity} vertices, {index_capacity} indices"
+            );
+            let vertices = w.allocate_and_init_buffer(
+                vk::BufferUsageFlags::VERTEX_BUFFER,
+                base.device_memory_properties,
+                &vec![UiVertex::default(); vertex_capacity],
+            )?;
+            let indices = match w.allocate_and_init_buffer(
+                vk::BufferUsageFlags::INDEX_BUFFER,
+                base.device_memory_properties,
+                &vec![0u32; index_capacity],
+            ) {
+                Ok(indices) => indices,
+                Err(err) => {
+                    vertices.deallocate(&base.device);
+                    return Err(err);
+                }
+            };
+            ui_frame.buffers = Some(UiBuffers {
+                vertices,
+                indices,
+                vertex_capacity,
+                index_capacity,
+            });
+        }
+        let buffers = ui_frame.buffers.as_mut().unwrap();
+        w.update_buffer(&mut buffers.vertices, &self.vertices)?;
+        w.update_buffer(&mut buffers.indices, &self.indices)?;
+        Ok(())
+    }
+
+    /// Record drawing the UI prepared for the frame in flight at `frame`.
+    pub fn draw(&self, w: &DeviceWrapper, command_buffer: vk::CommandBuffer, frame: usize) {
+        let ui_frame = &self.frames[frame];
+        let buffers = match ui_frame.buffers.as_ref() {
+            Some(buffers) if !ui_frame.draws.is_empty() => buffers,
+            _ => return,
+        };
+
+        // The UI covers the whole pass, whatever part of it the scene is in.
+        let area = vk::Rect2D::from(ui_frame.extent);
+        w.cmd_bind_pipeline(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            *self.pipeline,
+        );
+        w.cmd_set_viewport(command_buffer, 0, &VulkanBase::viewports_of(area));
+        w.cmd_bind_vertex_buffers(command_buffer, 0, &[*buffers.vertices.buffer], &[0]);
+        w.cmd_bind_index_buffer(
+            command_buffer,
+            *buffers.indices.buffer,
+            0,
+            vk::IndexType::UINT32,
+        );
+        let push_constants = UiPushConstants::new(ui_frame.screen_size, self.srgb_target);
+        w.cmd_push_constants(
+            command_buffer,
+            *self.layout,
+            crate::device::PUSH_CONSTANT_STAGES,
+            0,
+            push_constants.to_bytes(),
+        );
+        for draw in ui_frame.draws.iter() {
+            w.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                *self.layout,
+                0,
+                &[draw.descriptor_set],
+                &[],
+            );
+            w.cmd_set_scissor(command_buffer, 0, &[draw.scissor]);
+            w.cmd_draw_indexed(
+                command_buffer,
+                draw.index_count,
+                1,
+                draw.first_index,
+                draw.vertex_offset,
+                0,
+            );
+        }
+    }
+
+    /// Upload textures that were added or changed, and destroy those that were
+    /// freed, once no frame in flight samples them.
+    fn update_textures(
+        &mut self,
+        base: &mut VulkanBase,
+        delta: TexturesDelta,
+    ) -> Result<(), RenderError> {
+        if delta.is_empty() {
+            return Ok(());
+        }
+        base.frames.wait_all(&base.device)?;
+        for (id, image_delta) in delta.set {
+            self.set_texture(base, id, image_delta)?;
+        }
+        for id in delta.free {
+            if let Some(texture) = self.textures.remove(&id) {
+                debug!(self.logger, "freeing texture {id:?}");
+                texture.texture.deallocate(&base.device);
+                // Only fails for pools without FREE_DESCRIPTOR_SET.
+                let _ = unsafe {
+                    base.device
+                        .free_descriptor_sets(self.descriptor_pool, &[texture.descriptor_set])
+                };
+            }
+        }
+        Ok(())
+    }
+
+    fn set_texture(
+        &mut self,
+        base: &mut VulkanBase,
+        id: TextureId,
+        delta: ImageDelta,
+    ) -> Result<(), RenderError> {
+        let (pixels, size) = match (delta.pos, self.textures.remove(&id)) {
+            (None, old) => {
+                if let Some(old) = old {
+                    old.texture.deallocate(&base.device);
+                    let _ = unsafe {
+                        base.device
+                            .free_descriptor_sets(self.descriptor_pool, &[old.descriptor_set])
+                    };
+                }
+                (image_pixels(&delta.image), delta.image.size())
+            }
+            (Some(pos), Some(old)) => {
+                let UiTexture {
+                    texture,
+                    descriptor_set,
+                    mut pixels,
+                    size,
+                } = old;
+                texture.deallocate(&base.device);
+                let _ = unsafe {
+                    base.device
+                        .free_descriptor_sets(self.descriptor_pool, &[descriptor_set])
+                };
+                patch_pixels(
+                    &mut pixels,
+                    size,
+                    pos,
+                    &image_pixels(&delta.image),
+                    delta.image.size(),
+                );
+                (pixels, size)
+            }
+            (Some(_), None) => {
+                warn!(self.logger, "update to unknown texture {id:?}, skipping");
+                return Ok(());
+            }
+        };
+        debug!(
+            self.logger,
+            "uploading texture {id:?}, {}x{}", size[0], size[1]
+        );
+
+        let texture = upload_texture(base, size, &pixels, &self.logger)?;
+        let sampler = match delta.options.magnification {
+            TextureFilter::Nearest => *self.nearest_sampler,
+            TextureFilter::Linear => *self.linear_sampler,
+        };
+        let descriptor_set =
+            match base.allocate_descriptor_sets(self.descriptor_pool, &[*self.desc_set_layout]) {
+                Ok(descriptor_sets) => descriptor_sets[0],
+                Err(err) => {
+                    texture.deallocate(&base.device);
+                    return Err(err);
+                }
+            };
+        let image_info = [vk::DescriptorImageInfo {
+            sampler,
+            image_view: *texture.image_view,
+            image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        }];
+        let write = vk::WriteDescriptorSet::builder()
+            .dst_set(descriptor_set)
+            .dst_binding(UI_TEXTURE_BINDING)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .image_info(&image_info);
+        unsafe { base.device.update_descriptor_sets(&[*write], &[]) };
+        self.textures.insert(
+            id,
+            UiTexture {
+                texture,
+                descriptor_set,
+                pixels,
+                size,
+            },
+        );
+        Ok(())
+    }
+
+    /// Destroy the pass, once no frame in flight uses it.
+    pub fn destroy(self, device: &ash::Device) {
+        for frame in self.frames.iter() {
+            if let Some(buffers) = frame.buffers.as_ref() {
+                buffers.deallocate(device);
+            }
+        }
+        for texture in self.textures.values() {
+            texture.texture.deallocate(device);
+        }
+        self.linear_sampler.destroy(device);
+        self.nearest_sampler.destroy(device);
+        // Frees the textures' descriptor sets with it.
+        unsafe { device.destroy_descriptor_pool(self.descriptor_pool, None) };
+        self.pipeline.destroy(device);
+        self.shader_stages.deallocate(device);
+        self.layout.destroy(device);
+        self.desc_set_layout.destroy(device);
+    }
+}
+
+/// Layout of egui's vertices, positioned in points with premultiplied colors.
+fn ui_vertex_layout() -> VertexLayout {
+    VertexLayout::of::<UiVertex>()
+        .with_attribute(
+            0,
+            vk::Format::R32G32_SFLOAT,
+            crate::offset_of!(UiVertex, pos) as u32,
+        )
+        .with_attribute(
+            1,
+            vk::Format::R32G32_SFLOAT,
+            crate::offset_of!(UiVertex, uv) as u32,
+        )
+        .with_attribute(
+            2,
+            vk::Format::R8G8B8A8_UNORM,
+            crate::offset_of!(UiVertex, color) as u32,
+        )
+}
+
+/// Create the UI's pipeline, which blends egui's premultiplied colors over
+/// the scene without testing depth.
+fn create_pipeline(
+    base: &VulkanBase,
+    shader_stages: &ShaderStages,
+    vertex_input_assembly: &VertexInputAssembly,
+    layout: vk::PipelineLayout,
+) -> Result<vk::Pipeline, RenderError> {
+    let shader_stage_create_infos = shader_stages
+        .shader_stage_defs
+        .iter()
+        .map(ShaderStage::create_info)
+        .collect::<Vec<_>>();
+    let vertex_input_state_info = vertex_input_assembly.input_state_info();
+    let vertex_input_assembly_state_info = vertex_input_assembly.assembly_state_info();
+
+    // Viewport and scissor are set as the UI is drawn.
+    let viewport_state_info = vk::PipelineViewportStateCreateInfo::builder()
+        .viewport_count(1)
+        .scissor_count(1);
+    let rasterization_info = vk::PipelineRasterizationStateCreateInfo {
+        cull_mode: vk::CullModeFlags::NONE,
+        line_width: 1.0,
+        polygon_mode: vk::PolygonMode::FILL,
+        ..Default::default()
+    };
+    let multisample_state_info = vk::PipelineMultisampleStateCreateInfo::builder()
+        .rasterization_samples(vk::SampleCountFlags::TYPE_1);
+    let depth_state_info = vk::PipelineDepthStencilStateCreateInfo {
+        depth_test_enable: 0,
+        depth_write_enable: 0,
+        max_depth_bounds: 1.0,
+        ..Default::default()
+    };
+    let color_blend_attachment_states = [vk::PipelineColorBlendAttachmentState {
+        blend_enable: 1,
+        src_color_blend_factor: vk::BlendFactor::ONE,
+        dst_color_blend_factor: vk::BlendFactor::ONE_MINUS_SRC_ALPHA,
+        color_blend_op: vk::BlendOp::ADD,
+        src_alpha_blend_factor: vk::BlendFactor::ONE_MINUS_DST_ALPHA,
+        dst_alpha_blend_factor: vk::BlendFactor::ONE,
+        alpha_blend_op: vk::BlendOp::ADD,
+        color_write_mask: vk::ColorComponentFlags::RGBA,
+    }];
+    let color_blend_state = vk::PipelineColorBlendStateCreateInfo::builder()
+        .attachments(&color_blend_attachment_states);
+    let dynamic_state = [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+    let dynamic_state_info =
+        vk::PipelineDynamicStateCreateInfo::builder().dynamic_states(&dynamic_state);
+    let graphics_pipeline_info = vk::GraphicsPipelineCreateInfo::builder()
+        .stages(&shader_stage_create_infos)
+        .vertex_input_state(&vertex_input_state_info)
+        .input_assembly_state(&vertex_input_assembly_state_info)
+        .viewport_state(&viewport_state_info)
+        .rasterization_state(&rasterization_info)
+        .multisample_state(&multisample_state_info)
+        .depth_stencil_state(&depth_state_info)
+        .color_blend_state(&color_blend_state)
+        .dynamic_state(&dynamic_state_info)
+        .layout(layout)
+        // The scaled target's render pass is compatible with the window's.
+        .render_pass(*base.render_pass);
+    let pipeline = unsafe {
+        base.device.create_graphics_pipelines(
+            vk::PipelineCache::null(),
+            &[*graphics_pipeline_info],
+            None,
+        )
+    }
+    .map_err(|(pipeline, result)| RenderError::FailedToCreatePipeline(pipeline, result))?[0];
+    Ok(pipeline)
+}
+
+/// A sampler clamping to the edges of the texture, without blending between
+/// mip levels.
+pub(crate) fn create_sampler(
+    device: &ash::Device,
+    filter: vk::Filter,
+) -> Result<Owned<vk::Sampler>, RenderError> {
+    let sampler_info = vk::SamplerCreateInfo {
+        mag_filter: filter,
+        min_filter: filter,
+        mipmap_mode: vk::SamplerMipmapMode::NEAREST,
+        address_mode_u: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+        address_mode_v: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+        address_mode_w: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+        max_anisotropy: 1.0,
+        ..Default::default()
+    };
+    unsafe { device.create_sampler(&sampler_info, None) }
+        .map(Owned::new)
+        .map_err(RenderError::VkResultToDo)
+}
+
+/// Upload pixels to a new sRGB texture that shaders can sample, waiting for
+/// the upload to complete.
+fn upload_texture(
+    base: &mut VulkanBase,
+    size: [usize; 2],
+    pixels: &[Color32],
+    logger: &Logger,
+) -> Result<Texture, RenderError> {
+    let device = &base.device;
+    let w = DeviceWrapper::wrap(device, logger);
+    let extent = vk::Extent2D {
+        width: size[0] as u32,
+        height: size[1] as u32,
+    };
+    let staging = w.allocate_and_init_buffer(
+        vk::BufferUsageFlags::TRANSFER_SRC,
+        base.device_memory_properties,
+        pixels,
+    )?;
+
+    let image_info = vk::ImageCreateInfo {
+        image_type: vk::ImageType::TYPE_2D,
+        format: vk::Format::R8G8B8A8_SRGB,
+        extent: extent.into(),
+        mip_levels: 1,
+        array_layers: 1,
+        samples: vk::SampleCountFlags::TYPE_1,
+        tiling: vk::ImageTiling::OPTIMAL,
+        usage: vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED,
+        sharing_mode: vk::SharingMode::EXCLUSIVE,
+        ..Default::default()
+    };
+    let texture = unsafe { device.create_image(&image_info, None) }
+        .map_err(RenderError::VkResultToDo)
+        .and_then(|image| {
+            let requirements = unsafe { device.get_image_memory_requirements(image) };
+            let memory = VulkanBase::find_memorytype_index(
+                &requirements,
+                &base.device_memory_properties,
+                vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            )
+            .ok_or(RenderError::UnableToFindMemoryTypeForImage)
+            .and_then(|memory_type_index| {
+                let allocate_info = vk::MemoryAllocateInfo {
+                    allocation_size: requirements.size,
+                    memory_type_index,
+                    ..Default::default()
+                };
+                unsafe { device.allocate_memory(&allocate_info, None) }
+                    .map_err(RenderError::VkResultToDo)
+            });
+            let memory = match memory {
+                Ok(memory) => memory,
+                Err(err) => {
+                    unsafe { device.destroy_image(image, None) };
+                    return Err(err);
+                }
+            };
+            if let Err(err) = unsafe { device.bind_image_memory(image, memory, 0) } {
+                unsafe {
+                    device.destroy_image(image, None);
+                    device.free_memory(memory, None);
+                }
+                return Err(RenderError::VkResultToDo(err));
+            }
+            Texture::create(
+                image_info.format,
+                1,
+                image,
+                memory,
+                requirements.size,
+                device,
+            )
+        });
+    let texture = match texture {
+        Ok(texture) => texture,
+        Err(err) => {
+            staging.deallocate(device);
+            return Err(err);
+        }
+    };
+
+    let image = *texture.image;
+    let range = *vk::ImageSubresourceRange::builder()
+        .aspect_mask(vk::ImageAspectFlags::COLOR)
+        .layer_count(1)
+        .level_count(1);
+    VulkanBase::record_and_submit_commandbuffer(
+        device,
+        base.setup_command_buffer,
+        base.setup_commands_reuse_fence,
+        base.present_queue,
+        &[],
+        &[],
+        &[],
+        |device, command_buffer| {
+            let to_transfer = *vk::ImageMemoryBarrier::builder()
+                .image(image)
+                .dst_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                .old_layout(vk::ImageLayout::UNDEFINED)
+                .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+                .subresource_range(range);
+            let to_read = *vk::ImageMemoryBarrier::builder()
+                .image(image)
+                .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                .dst_access_mask(vk::AccessFlags::SHADER_READ)
+                .old_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+                .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                .subresource_range(range);
+            unsafe {
+                device.cmd_pipeline_barrier(
+                    command_buffer,
+                    vk::PipelineStageFlags::TOP_OF_PIPE,
+                    vk::PipelineStageFlags::TRANSFER,
+                    vk::DependencyFlags::empty(),
+                    &[],
+                    &[],
+                    &[to_transfer],
+                );
+            }
+            DeviceWrapper::wrap(device, logger).cmd_copy_buffer_to_image(
+                &staging,
+                extent,
+                &texture,
+                command_buffer,
+            );
+            unsafe {
+                device.cmd_pipeline_barrier(
+                    command_buffer,
+                    vk::PipelineStageFlags::TRANSFER,
+                    vk::PipelineStageFlags::FRAGMENT_SHADER,
+                    vk::DependencyFlags::empty(),
+                    &[],
+                    &[],
+                    &[to_read],
+                );
+            }
+        },
+    );
+    let waited = unsafe { device.queue_wait_idle(base.present_queue) };
+    staging.deallocate(device);
+    base.frames.idle();
+    if let Err(err) = waited {
+        texture.deallocate(&base.device);
+        return Err(RenderError::VkResultToDo(err));
+    }
+    Ok(texture)
+}
+
+fn image_pixels(image: &ImageData) -> Vec<Color32> {
+    match image {
+        ImageData::Color(image) => image.pixels.clone(),
+        ImageData::Font(image) => image.srgba_pixels(None).collect(),
+    }
+}
+
+/// Write `patch`, of `patch_size`, into `pixels` at `pos`, clipping whatever
+/// falls outside of `size`.
+fn patch_pixels(
+    pixels: &mut [Color32],
+    size: [usize; 2],
+    pos: [usize; 2],
+    patch: &[Color32],
+    patch_size: [usize; 2],
+) {
+    let width = patch_size[0].min(size[0].saturating_sub(pos[0]));
+    for row in 0..patch_size[1].min(size[1].saturating_sub(pos[1])) {
+        let src = row * patch_size[0];
+        let dst = (pos[1] + row) * size[0] + pos[0];
+        pixels[dst..dst + width].copy_from_slice(&patch[src..src + width]);
+    }
+}
+
+/// The scissor of a clip rect in points, on a pass of `extent` showing a
+/// screen of `screen_size` points. None when nothing of it is on the pass.
+fn scissor(clip: Rect, screen_size: egui::Vec2, extent: vk::Extent2D) -> Option<vk::Rect2D> {
+    if screen_size.x <= 0.0 || screen_size.y <= 0.0 {
+        return None;
+    }
+    let (width, height) = (extent.width as f32, extent.height as f32);
+    let (sx, sy) = (width / screen_size.x, height / screen_size.y);
+    let min_x = (clip.min.x * sx).floor().clamp(0.0, width) as u32;
+    let min_y = (clip.min.y * sy).floor().clamp(0.0, height) as u32;
+    let max_x = (clip.max.x * sx).ceil().clamp(0.0, width) as u32;
+    let max_y = (clip.max.y * sy).ceil().clamp(0.0, height) as u32;
+    (max_x > min_x && max_y > min_y).then(|| vk::Rect2D {
+        offset: vk::Offset2D {
+            x: min_x as i32,
+            y: min_y as i32,
+        },
+        extent: vk::Extent2D {
+            width: max_x - min_x,
+            height: max_y - min_y,
+        },
+    })
+}
+
+fn is_srgb(format: vk::Format) -> bool {
+    matches!(
+        format,
+        vk::Format::B8G8R8A8_SRGB | vk::Format::R8G8B8A8_SRGB | vk::Format::A8B8G8R8_SRGB_PACK32
+    )
+}
@@ -0,0 +1,229 @@
+//! Uploading graphics in parallel. Graphics are split between worker threads,
+//! each creating buffers and textures for its share and recording the texture
+//! copies into a command buffer from its own command pool, since pools can't
+//! be used from more than one thread. The command buffers are then submitted
+//! together, so a level load waits on the queue once rather than per graphic.
+
+use std::num::NonZeroUsize;
+use std::thread;
+
+use ash::{vk, Device};
+use gfx::{DiffuseColor, GpuNeeds, Graphic};
+use logger::{debug, Logger};
+use render::occlusion::Aabb;
+
+use crate::device::{DeviceWrapper, GraphicsHandle};
+use crate::types::{BufferAndMemory, RenderError, Shader};
+
+/// Graphics uploaded by one worker, and the commands that finish uploading
+/// their textures.
+pub(crate) struct UploadBatch {
+    pool: vk::CommandPool,
+    pub command_buffer: vk::CommandBuffer,
+    /// Staging buffers for the textures, freed once the commands complete.
+    src_images: Vec<BufferAndMemory>,
+    /// Uploaded graphics by content hash, usable once the commands complete.
+    handles: Vec<(u64, GraphicsHandle)>,
+}
+
+impl UploadBatch {
+    /// Upload `graphics` using a thread per available core, returning one batch
+    /// per thread.
+    pub fn record_parallel(
+        device: &Device,
+        queue_family_index: u32,
+        device_memory_properties: vk::PhysicalDeviceMemoryProperties,
+        graphics: &[(u64, &Graphic)],
+        logger: &Logger,
+    ) -> Result<Vec<Self>, RenderError> {
+        if graphics.is_empty() {
+            return Ok(vec![]);
+        }
+        let workers = thread::available_parallelism()
+            .map_or(1, NonZeroUsize::get)
+            .min(graphics.len());
+        let chunk_size = (graphics.len() + workers - 1) / workers;
+        debug!(
+            logger,
+            "uploading {} graphics on {workers} threads",
+            graphics.len()
+        );
+
+        thread::scope(|scope| {
+            let tasks = graphics
+                .chunks(chunk_size)
+                .map(|chunk| {
+                    let logger = logger.sub("worker");
+                    scope.spawn(move || {
+                        Self::record(
+                            device,
+                            queue_family_index,
+                            device_memory_properties,
+                            chunk,
+                            &logger,
+                        )
+                    })
+                })
+                .collect::<Vec<_>>();
+            let mut batches = Vec::with_capacity(tasks.len());
+            let mut error = None;
+            for task in tasks {
+                match task.join().expect("graphics upload thread panicked") {
+                    Ok(batch) => batches.push(batch),
+                    Err(err) => error = error.or(Some(err)),
+                }
+            }
+            match error {
+                None => Ok(batches),
+                Some(err) => {
+                    for batch in batches {
+                        batch.destroy(device);
+                    }
+                    Err(err)
+                }
+            }
+        })
+    }
+
+    fn record(
+        device: &Device,
+        queue_family_index: u32,
+        device_memory_properties: vk::PhysicalDeviceMemoryProperties,
+        graphics: &[(u64, &Graphic)],
+        logger: &Logger,
+    ) -> Result<Self, RenderError> {
+        let w = DeviceWrapper::wrap(device, logger);
+        let pool = w.create_command_pool(queue_family_index)?;
+        let mut batch = Self {
+            pool,
+            command_buffer: vk::CommandBuffer::null(),
+            src_images: Vec::new(),
+            handles: Vec::with_capacity(graphics.len()),
+        };
+        match batch.record_graphics(&w, device, device_memory_properties, graphics, logger) {
+            Ok(()) => Ok(batch),
+            Err(err) => {
+                batch.destroy(device);
+                Err(err)
+            }
+        }
+    }
+
+    /// Record the uploads into a command buffer from the batch's pool. On
+    /// error, what was uploaded so far is left in the batch to be destroyed.
+    fn record_graphics(
+        &mut self,
+        w: &DeviceWrapper,
+        device: &Device,
+        device_memory_properties: vk::PhysicalDeviceMemoryProperties,
+        graphics: &[(u64, &Graphic)],
+        logger: &Logger,
+    ) -> Result<(), RenderError> {
+        self.command_buffer = w.allocate_command_buffers(self.pool)?[0];
+        w.begin_command_buffer(self.command_buffer)?;
+
+        for (content_hash, graphic) in graphics {
+            debug!(logger, "loading graphics object {content_hash:x}");
+
+            // reflect over shaders and determine descriptor sets
+            let vertex_shader = Shader::read_spv(graphic.vertex_shader_path().to_path_buf())?;
+            let fragment_shader = Shader::read_spv(graphic.fragment_shader_path().to_path_buf())?;
+
+            let vertex_buffer = w.allocate_and_init_buffer(
+                vk::BufferUsageFlags::VERTEX_BUFFER,
+                device_memory_properties,
+                graphic.vertices(),
+            )?;
+            let skin_buffer = match graphic.skin_weights() {
+                [] => None,
+                skin_weights => match w.allocate_and_init_buffer(
+                    vk::BufferUsageFlags::VERTEX_BUFFER,
+                    device_memory_properties,
+                    skin_weights,
+                ) {
+                    Ok(skin_buffer) => Some(skin_buffer),
+                    Err(err) => {
+                        vertex_buffer.deallocate(device);
+                        return Err(err);
+                    }
+                },
+            };
+            let index_buffer = match w.allocate_and_init_buffer(
+                vk::BufferUsageFlags::INDEX_BUFFER,
+                device_memory_properties,
+                graphic.indices(),
+            ) {
+                Ok(index_buffer) => index_buffer,
+                Err(err) => {
+                    vertex_buffer.deallocate(device);
+                    if let Some(skin_buffer) = skin_buffer {
+                        skin_buffer.deallocate(device);
+                    }
+                    return Err(err);
+                }
+            };
+
+            // Uploaded last, since it can't fail and leave the buffers above
+            // to be freed.
+            let diffuse_map = match graphic.diffuse_color() {
+                Some(DiffuseColor::Texture(texture)) => Some(w.cmd_upload_image(
+                    texture,
+                    device_memory_properties,
+                    self.command_buffer,
+                    &mut self.src_images,
+                )),
+                None | Some(DiffuseColor::Color(_)) => None,
+            };
+
+            // let specular_map = maybe_cmd_upload_image(
+            //     &w,
+            //     model.material.specular_map.as_ref(),
+            //     device_memory_properties,
+            //     command_buffer,
+            //     &mut src_images,
+            // );
+            // let bump_map = maybe_cmd_upload_image(
+            //     &w,
+            //     model.material.bump_map.as_ref(),
+            //     device_memory_properties,
+            //     command_buffer,
+            //     &mut src_images,
+            // );
+
+            self.handles.push((
+                *content_hash,
+                GraphicsHandle::new(
+                    diffuse_map,
+                    vertex_buffer,
+                    index_buffer,
+                    vertex_shader,
+                    fragment_shader,
+                    graphic.primitive(),
+                    Aabb::from_vertices(graphic.vertices()),
+                )
+                .with_skin_buffer(skin_buffer),
+            ));
+        }
+
+        w.end_command_buffer(self.command_buffer)
+    }
+
+    /// Free the staging buffers and command pool. Must only be called once the
+    /// command buffer has completed.
+    pub fn destroy_staging(self, device: &Device) -> Vec<(u64, GraphicsHandle)> {
+        for image in self.src_images {
+            image.deallocate(device);
+        }
+        unsafe {
+            device.destroy_command_pool(self.pool, None);
+        }
+        self.handles
+    }
+
+    /// Free everything the batch uploaded, after recording it failed.
+    fn destroy(self, device: &Device) {
+        for (_, handle) in self.destroy_staging(device) {
+            handle.deallocate(device);
+        }
+    }
+}
@@ -0,0 +1,254 @@
+//! A window rendered to besides the main one, see `render::target`. Each has
+//! its own surface and swapchain on the main window's device. The scene is
+//! rendered from the target's camera into an off-screen `ScaledTarget` of the
+//! window's size, with the same pipelines as the main window, and blitted
+//! onto the swapchain image, converting to the window's format on the way.
+
+use ash::vk;
+use platform::WindowSize;
+use render::target::{RenderTarget, RenderTargetId};
+use world::Entity;
+
+use crate::scaled_target::ScaledTarget;
+use crate::types::RenderError;
+use crate::{present_mode, swapchain_image_usage, VulkanBase};
+
+/// Time allowed to acquire a swapchain image, a target that isn't ready is
+/// skipped for the frame rather than holding up the main window.
+const ACQUIRE_TIMEOUT_NANOS: u64 = 300 * 1000;
+
+pub(crate) struct WindowTarget {
+    pub id: RenderTargetId,
+    pub camera: Option<Entity>,
+    window_size: WindowSize,
+    surface: vk::SurfaceKHR,
+    swapchain: vk::SwapchainKHR,
+    images: Vec<vk::Image>,
+    pub extent: vk::Extent2D,
+    /// Signaled once the acquired swapchain image can be blitted to, one for
+    /// each frame in flight.
+    image_acquired: Vec<vk::Semaphore>,
+    /// Signaled once the scene has been blitted, for presentation to wait on,
+    /// one for each frame in flight.
+    blitted: Vec<vk::Semaphore>,
+    /// Where the scene is rendered, `None` while the window has no area.
+    pub scene: Option<ScaledTarget>,
+    pub flag_recreate_swapchain: bool,
+}
+
+impl WindowTarget {
+    pub fn new(base: &VulkanBase, target: &RenderTarget) -> Result<Self, RenderError> {
+        let surface = unsafe {
+            ash_window::create_surface(&base.entry, &base.instance, &target.win_ptr, None)
+        }
+        .map_err(RenderError::VkResultToDo)?;
+        let supported = unsafe {
+            base.surface_loader.get_physical_device_surface_support(
+                base.physical_device,
+                base.queue_family_index,
+                surface,
+            )
+        };
+        if supported != Ok(true) {
+            unsafe { base.surface_loader.destroy_surface(surface, None) };
+            return Err(RenderError::SurfaceNotSupported(target.id));
+        }
+        let mut window_target = Self {
+            id: target.id,
+            camera: target.camera,
+            window_size: target.window_size,
+            surface,
+            swapchain: vk::SwapchainKHR::null(),
+            images: Vec::new(),
+            extent: vk::Extent2D::default(),
+            image_acquired: Vec::new(),
+            blitted: Vec::new(),
+            scene: None,
+            flag_recreate_swapchain: false,
+        };
+        let semaphore_create_info = vk::SemaphoreCreateInfo::default();
+        let create_semaphore = || {
+            unsafe { base.device.create_semaphore(&semaphore_create_info, None) }
+                .map_err(RenderError::VkResultToDo)
+        };
+        for _ in 0..base.frames.len() {
+            let created = create_semaphore()
+                .map(|semaphore| window_target.image_acquired.push(semaphore))
+                .and_then(|()| create_semaphore())
+                .map(|semaphore| window_target.blitted.push(semaphore));
+            if let Err(err) = created {
+                window_target.destroy(base);
+                return Err(err);
+            }
+        }
+        if let Err(err) = window_target.create_swapchain(base) {
+            window_target.destroy(base);
+            return Err(err);
+        }
+        Ok(window_target)
+    }
+
+    /// Pick up changes made to the target in `RenderState`.
+    pub fn configure(&mut self, target: &RenderTarget) {
+        self.camera = target.camera;
+        if target.window_size != self.window_size {
+            self.window_size = target.window_size;
+            self.flag_recreate_swapchain = true;
+        }
+    }
+
+    /// Create the swapchain, replacing the current one, and the scene target
+    /// of its size. Only called once nothing in flight uses either.
+    pub fn create_swapchain(&mut self, base: &VulkanBase) -> Result<(), RenderError> {
+        self.flag_recreate_swapchain = false;
+        let capabilities = unsafe {
+            base.surface_loader
+                .get_physical_device_surface_capabilities(base.physical_device, self.surface)
+        }
+        .map_err(RenderError::VkResultToDo)?;
+        // Surfaces that let the swapchain pick its size take the window's.
+        self.extent = if capabilities.current_extent.width == u32::MAX {
+            let (width, height) = self.window_size.drawable;
+            vk::Extent2D { width, height }
+        } else {
+            capabilities.current_extent
+        };
+        if let Some(scene) = self.scene.take() {
+            scene.destroy(&base.device);
+        }
+        if self.extent.width == 0 || self.extent.height == 0 {
+            // Minimized, there's nothing to present to until it's resized.
+            return Ok(());
+        }
+
+        let surface_format = unsafe {
+            base.surface_loader
+                .get_physical_device_surface_formats(base.physical_device, self.surface)
+        }
+        .map_err(RenderError::VkResultToDo)?
+        .first()
+        .copied()
+        .ok_or(RenderError::SurfaceNotSupported(self.id))?;
+        let mut image_count = capabilities.min_image_count + 1;
+        if capabilities.max_image_count > 0 {
+            image_count = image_count.min(capabilities.max_image_count);
+        }
+        let present_modes = unsafe {
+            base.surface_loader
+                .get_physical_device_surface_present_modes(base.physical_device, self.surface)
+        }
+        .map_err(RenderError::VkResultToDo)?;
+        let present_mode = present_mode(&present_modes, base.requested_present_mode);
+        let old_swapchain = self.swapchain;
+        let swapchain_create_info = *vk::SwapchainCreateInfoKHR::builder()
+            .surface(self.surface)
+            .min_image_count(image_count)
+            .image_color_space(surface_format.color_space)
+            .image_format(surface_format.format)
+            .image_extent(self.extent)
+            .image_usage(swapchain_image_usage(&capabilities))
+            .image_sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .pre_transform(capabilities.current_transform)
+            .composite_alpha(vk::CompositeAlphaFlagsKHR::OPAQUE)
+            .present_mode(present_mode)
+            .clipped(true)
+            .image_array_layers(1)
+            .old_swapchain(old_swapchain);
+        self.swapchain = unsafe {
+            base.swapchain_loader
+                .create_swapchain(&swapchain_create_info, None)
+        }
+        .map_err(RenderError::VkResultToDo)?;
+        if old_swapchain != vk::SwapchainKHR::null() {
+            unsafe { base.swapchain_loader.destroy_swapchain(old_swapchain, None) };
+        }
+        self.images = unsafe { base.swapchain_loader.get_swapchain_images(self.swapchain) }
+            .map_err(RenderError::VkResultToDo)?;
+        self.scene = Some(ScaledTarget::new(base, self.extent)?);
+        Ok(())
+    }
+
+    /// Acquire the next swapchain image to blit to in the frame in flight at
+    /// `frame`, unless the window can't be presented to this frame.
+    pub fn acquire(&mut self, base: &VulkanBase, frame: usize) -> Result<Option<u32>, RenderError> {
+        if self.scene.is_none() {
+            return Ok(None);
+        }
+        match unsafe {
+            base.swapchain_loader.acquire_next_image(
+                self.swapchain,
+                ACQUIRE_TIMEOUT_NANOS,
+                self.image_acquired[frame],
+                vk::Fence::null(),
+            )
+        } {
+            Ok((index, suboptimal)) => {
+                // The image is acquired either way, and is still presentable.
+                self.flag_recreate_swapchain |= suboptimal;
+                Ok(Some(index))
+            }
+            Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => {
+                self.flag_recreate_swapchain = true;
+                Ok(None)
+            }
+            Err(vk::Result::TIMEOUT | vk::Result::NOT_READY) => Ok(None),
+            Err(err) => Err(RenderError::SwapchainAcquireNextImage(err)),
+        }
+    }
+
+    pub fn image(&self, index: u32) -> vk::Image {
+        self.images[index as usize]
+    }
+
+    /// Semaphores the frame in flight at `frame` waits on and signals when
+    /// blitting to the acquired image.
+    pub fn semaphores(&self, frame: usize) -> (vk::Semaphore, vk::Semaphore) {
+        (self.image_acquired[frame], self.blitted[frame])
+    }
+
+    /// Present the image at `index` once the frame in flight at `frame` has
+    /// blitted to it.
+    pub fn present(
+        &mut self,
+        base: &VulkanBase,
+        index: u32,
+        frame: usize,
+    ) -> Result<(), RenderError> {
+        let present_info = vk::PresentInfoKHR {
+            wait_semaphore_count: 1,
+            p_wait_semaphores: &self.blitted[frame],
+            swapchain_count: 1,
+            p_swapchains: &self.swapchain,
+            p_image_indices: &index,
+            ..Default::default()
+        };
+        match unsafe {
+            base.swapchain_loader
+                .queue_present(base.present_queue, &present_info)
+        } {
+            Ok(false) | Err(vk::Result::TIMEOUT) => Ok(()),
+            Ok(true) | Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => {
+                self.flag_recreate_swapchain = true;
+                Ok(())
+            }
+            Err(err) => Err(RenderError::Present(err)),
+        }
+    }
+
+    /// Destroy the target, once nothing in flight uses it.
+    pub fn destroy(mut self, base: &VulkanBase) {
+        if let Some(scene) = self.scene.take() {
+            scene.destroy(&base.device);
+        }
+        unsafe {
+            for semaphore in self.image_acquired.iter().chain(&self.blitted) {
+                base.device.destroy_semaphore(*semaphore, None);
+            }
+            if self.swapchain != vk::SwapchainKHR::null() {
+                base.swapchain_loader
+                    .destroy_swapchain(self.swapchain, None);
+            }
+            base.surface_loader.destroy_surface(self.surface, None);
+        }
+    }
+}
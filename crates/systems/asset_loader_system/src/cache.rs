@@ -0,0 +1,89 @@
+//! Models loaded by any asset loader in the process, so that worlds running
+//! side by side, such as server rooms, parse each model once.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use gfx::{GpuNeeds, LoadError, Model};
+use interner::Symbol;
+
+/// The files a model is loaded from: its obj and its shaders, interned so
+/// that looking up a cached model doesn't allocate.
+type ModelKey = (Symbol, Symbol, Symbol);
+
+/// Loaded models, shared between asset loaders by cloning.
+#[derive(Clone, Default)]
+pub struct ModelCache {
+    models: Arc<Mutex<HashMap<ModelKey, Model>>>,
+}
+
+impl ModelCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn models(&self) -> MutexGuard<'_, HashMap<ModelKey, Model>> {
+        // Models are only ever inserted whole.
+        self.models
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// The model loaded from these files, loading it if nothing has yet.
+    pub fn load_obj(
+        &self,
+        filename: impl AsRef<Path>,
+        vertex_shader: impl AsRef<Path>,
+        fragment_shader: impl AsRef<Path>,
+    ) -> Result<Model, LoadError> {
+        let key = (
+            Symbol::intern_path(filename),
+            Symbol::intern_path(vertex_shader),
+            Symbol::intern_path(fragment_shader),
+        );
+        if let Some(model) = self.models().get(&key) {
+            return Ok(model.clone());
+        }
+        // Loaded without holding the lock, another loader may load it too.
+        let model = Model::load_obj(key.0, key.1, key.2)?;
+        self.models().insert(key, model.clone());
+        Ok(model)
+    }
+
+    /// Load `model` again from its files, replacing what's cached for them.
+    pub fn reload(&self, model: &Model) -> Result<Model, LoadError> {
+        let reloaded = model.reload()?;
+        let key = (
+            Symbol::intern_path(reloaded.source_paths()[0]),
+            Symbol::intern_path(reloaded.vertex_shader_path()),
+            Symbol::intern_path(reloaded.fragment_shader_path()),
+        );
+        self.models().insert(key, reloaded.clone());
+        Ok(reloaded)
+    }
+
+    /// Drop the model loaded from these files, so it's loaded again the next
+    /// time it's asked for. Copies already taken are unaffected.
+    pub fn forget(
+        &self,
+        filename: impl AsRef<Path>,
+        vertex_shader: impl AsRef<Path>,
+        fragment_shader: impl AsRef<Path>,
+    ) {
+        let key = (
+            Symbol::intern_path(filename),
+            Symbol::intern_path(vertex_shader),
+            Symbol::intern_path(fragment_shader),
+        );
+        self.models().remove(&key);
+    }
+
+    pub fn len(&self) -> usize {
+        self.models().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.models().is_empty()
+    }
+}
@@ -1,26 +1,65 @@
+mod cache;
+mod preload;
+
+use std::collections::HashMap;
 use std::f32::consts::PI;
-use std::time::Duration;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
 
-use gfx::Model;
-use logger::{info, LogLevel, Logger};
+use gfx::{Graphic, Model};
+use logger::{error, info, LogLevel, Logger};
 use world::bundles::{Player, StaticObject};
+use world::collision::CollisionGeometry;
 use world::components::spatial::SpatialHierarchyNode;
-use world::components::WorldTransform;
-use world::{AssetLoaderStateAndWorldLock, Vec3};
+use world::components::{DirectionalLight, GraphicPrefab, RenderFlags, WorldTransform};
+use world::journal::JournalEvent;
+use world::notifications::Severity;
+use world::{AssetLoaderState, AssetLoaderStateAndWorldLock, Entity, Mat4, Vec3, World};
+
+pub use crate::cache::ModelCache;
+
+// How often watched asset files are checked for modifications.
+const ASSET_POLL_INTERVAL_MILLIS: u64 = 500;
+
+// Where shaders are built to, along with a `.err` file for each one that
+// failed to build.
+const SHADER_DIR: &str = "assets/shaders/spv";
+
+/// Version of the plugin, shown with its name in what it logs.
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
 
 pub struct AssetLoader {
     logger: Logger,
+    last_poll: Instant,
+    /// Shader build errors already reported, by when they were written.
+    shader_errors: HashMap<PathBuf, Option<SystemTime>>,
+    models: ModelCache,
 }
 
 impl AssetLoader {
     pub fn new() -> Self {
+        Self::with_cache(ModelCache::new())
+    }
+
+    /// Load models through a cache shared with other asset loaders, such as
+    /// those of other worlds in the process.
+    pub fn with_cache(models: ModelCache) -> Self {
         Self {
             logger: LogLevel::Info.logger().sub("asset-loader"),
+            last_poll: Instant::now(),
+            shader_errors: HashMap::new(),
+            models,
         }
     }
 
+    /// Log through `logger` from now on, such as the root logger the engine
+    /// gives each plugin.
+    pub fn set_logger(&mut self, logger: Logger) {
+        self.logger = logger;
+    }
+
     pub fn load(&mut self, state: &mut AssetLoaderStateAndWorldLock) {
-        let logger = &state.world.logger.sub("asset-loader");
+        let logger = &self.logger.sub("load");
         logger.maybe_set_filter(state.world.logger.get_filter());
 
         // This plugin 'owns' the root entity and all it's children's lifetimes.
@@ -29,23 +68,29 @@ impl AssetLoader {
 
         let world = &mut state.world;
         let root = world.root.unwrap();
-        info!(self.logger.sub("load"), "asset loader plugin loaded.");
-
-        let tank_model = Model::load_obj(
-            "assets/models/static/tank.obj",
-            "assets/shaders/spv/default_vertex.spv",
-            "assets/shaders/spv/default_fragment.spv",
-        )
-        .unwrap();
-        let tank_gfx = world.add_model(tank_model);
-
-        let cube_model = Model::load_obj(
-            "assets/models/static/cube.obj",
-            "assets/shaders/spv/default_vertex.spv",
-            "assets/shaders/spv/default_fragment.spv",
-        )
-        .unwrap();
-        let cube_gfx = world.add_model(cube_model);
+        info!(logger, "asset loader plugin loaded.");
+
+        let tank_model = self
+            .models
+            .load_obj(
+                "assets/models/static/tank.obj",
+                "assets/shaders/spv/default_vertex.spv",
+                "assets/shaders/spv/default_fragment.spv",
+            )
+            .unwrap();
+        let tank_gfx = add_colliding_model(world, tank_model);
+        state.asset_loader_state.watch(world, tank_gfx).unwrap();
+
+        let cube_model = self
+            .models
+            .load_obj(
+                "assets/models/static/cube.obj",
+                "assets/shaders/spv/default_vertex.spv",
+                "assets/shaders/spv/default_fragment.spv",
+            )
+            .unwrap();
+        let cube_gfx = add_colliding_model(world, cube_model);
+        state.asset_loader_state.watch(world, cube_gfx).unwrap();
 
         let flip_angles = Vec3::new(0.0, 0.0 * PI, 1.0 * PI);
 
@@ -56,7 +101,7 @@ impl AssetLoader {
                 tank_gfx,
                 SpatialHierarchyNode::new_at(root, pos).with_angles(flip_angles),
             );
-            let _tank_id = world.add_player(tank);
+            world.add_player(tank).unwrap();
         }
 
         // initialize some state, lots of model_object entities
@@ -70,36 +115,201 @@ impl AssetLoader {
                         .with_angles(flip_angles),
                 );
 
-                // TODO: add_object
-                world.hecs_world.spawn(object);
+                let object = match world.add_object(object) {
+                    Ok(object) => object,
+                    // The world has told the user it's full.
+                    Err(_) => continue,
+                };
+                if model_prefab == cube_gfx {
+                    world
+                        .hecs_world
+                        .insert_one(object, RenderFlags::OCCLUDER)
+                        .unwrap();
+                }
             }
         }
 
-        let sky_model = Model::load_obj(
-            "assets/models/static/skybox.obj",
-            "assets/shaders/spv/skybox_vertex.spv",
-            "assets/shaders/spv/skybox_fragment.spv",
-        )
-        .unwrap();
+        let sky_model = self
+            .models
+            .load_obj(
+                "assets/models/static/skybox.obj",
+                "assets/shaders/spv/skybox_vertex.spv",
+                "assets/shaders/spv/skybox_fragment.spv",
+            )
+            .unwrap();
 
         let sky_prefab = world.add_model(sky_model);
+        state.asset_loader_state.watch(world, sky_prefab).unwrap();
         let sky = StaticObject::new(
             sky_prefab,
             SpatialHierarchyNode::new_with_scale(root, 200.0).with_angles(flip_angles),
         );
-        world.hecs_world.spawn(sky);
+        let sky = world.add_object(sky).unwrap();
+        world
+            .hecs_world
+            .insert_one(sky, RenderFlags::NEVER_OCCLUDED)
+            .unwrap();
+        // Two lights shining down on the middle of the scene from opposite
+        // corners.
+        for pos in [Vec3::new(10.0, 10.0, 10.0), Vec3::new(-10.0, 10.0, -10.0)] {
+            let transform = Mat4::look_at_rh(pos, Vec3::ZERO, Vec3::Y).inverse();
+            world.hecs_world.spawn((
+                SpatialHierarchyNode::new_with_transform(root, transform),
+                WorldTransform::default(),
+                DirectionalLight::new(Vec3::ONE, 1.0),
+            ));
+        }
+        world.record(JournalEvent::SceneLoaded {
+            entities: world.hecs_world.len(),
+        });
+
+        preload::declare_groups(world, logger);
+    }
+
+    /// Load the next preload group that fits, reload any watched models whose
+    /// files have changed on disk, and flag those whose shaders have for the
+    /// renderer to reload.
+    pub fn update(&mut self, state: &mut AssetLoaderStateAndWorldLock, _delta_time: &Duration) {
+        preload::load_next_group(&mut state.world, &self.models, &self.logger);
+
+        if self.last_poll.elapsed() < Duration::from_millis(ASSET_POLL_INTERVAL_MILLIS) {
+            return;
+        }
+        self.last_poll = Instant::now();
+
+        let logger = self.logger.sub("reload");
+        let AssetLoaderStateAndWorldLock {
+            world,
+            asset_loader_state,
+        } = state;
+        self.report_shader_errors(world);
+        let AssetLoaderState {
+            watched,
+            modified_shaders,
+        } = &mut **asset_loader_state;
+        for watched in watched.iter_mut() {
+            // Shaders are read by the renderer, which reloads them without the
+            // model having to be uploaded again.
+            let shaders_modified = watched.latest_shader_modification();
+            if shaders_modified > watched.shaders_last_modified {
+                watched.shaders_last_modified = shaders_modified;
+                info!(
+                    logger,
+                    "shaders of {:?} modified: {:?}", watched.prefab, watched.shader_paths
+                );
+                modified_shaders.push(watched.prefab);
+            }
+
+            let modified = watched.latest_modification();
+            if modified <= watched.last_modified {
+                continue;
+            }
+            // Don't retry a broken asset until it changes again.
+            watched.last_modified = modified;
+
+            let reloaded =
+                match world
+                    .hecs_world
+                    .get::<&GraphicPrefab>(watched.prefab)
+                    .map(|prefab| match &prefab.gfx {
+                        Graphic::Model(model) => Some(self.models.reload(model)),
+                        _ => None,
+                    }) {
+                    Ok(Some(reloaded)) => reloaded,
+                    Ok(None) => continue,
+                    Err(err) => {
+                        error!(
+                            logger,
+                            "watched prefab {:?} missing {:?}", watched.prefab, err
+                        );
+                        continue;
+                    }
+                };
+            match reloaded {
+                Ok(model) => {
+                    info!(
+                        logger,
+                        "reloaded {:?} from {:?}", watched.prefab, watched.paths[0]
+                    );
+                    // A material may now reference different images.
+                    watched.paths = model
+                        .source_paths()
+                        .into_iter()
+                        .map(|path| path.to_path_buf())
+                        .collect();
+                    if let Err(err) = world.reload_model(watched.prefab, model) {
+                        error!(logger, "unable to replace {:?} {:?}", watched.prefab, err);
+                    }
+                }
+                Err(err) => {
+                    world.notify(
+                        Severity::Warning,
+                        "asset_loader",
+                        format!("unable to reload {:?}: {err}", watched.paths[0]),
+                    );
+                }
+            }
+        }
     }
 
-    pub fn update(&mut self, state: &mut AssetLoaderStateAndWorldLock, delta_time: &Duration) {}
+    /// Notify about each shader that failed to build, once per failed build.
+    /// The last SPIR-V that built is still what's loaded.
+    fn report_shader_errors(&mut self, world: &mut World) {
+        let entries = match std::fs::read_dir(SHADER_DIR) {
+            Ok(entries) => entries,
+            Err(_) => return,
+        };
+        let mut errors = HashMap::new();
+        for path in entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().map_or(false, |ext| ext == "err"))
+        {
+            let written = std::fs::metadata(&path).and_then(|m| m.modified()).ok();
+            if self.shader_errors.get(&path) != Some(&written) {
+                let shader = path.file_stem().map(Path::new).unwrap_or(&path);
+                let error = std::fs::read_to_string(&path).unwrap_or_default();
+                world.notify(
+                    Severity::Error,
+                    "shaders",
+                    format!("{} failed to build: {}", shader.display(), error.trim()),
+                );
+            }
+            errors.insert(path, written);
+        }
+        self.shader_errors = errors;
+    }
 
     pub fn unload(&mut self, state: &mut AssetLoaderStateAndWorldLock) {
         let log = self.logger.sub("unload");
         state.world.players.clear();
+        state.world.preload = Default::default();
         let _ = std::mem::replace(&mut state.world.hecs_world, Default::default());
         state.world.root.take();
+        state.asset_loader_state.watched.clear();
+        state.asset_loader_state.modified_shaders.clear();
+        state.world.record(JournalEvent::SceneUnloaded);
         info!(
             log,
             "unloaded asset loader plugin ({})", state.world.stats.updates
         );
     }
 }
+
+/// Add a model's prefab along with the geometry it collides as, see
+/// `CollisionGeometry`. A model whose geometry can't be loaded is still drawn,
+/// it just doesn't collide.
+fn add_colliding_model(world: &mut World, model: Model) -> Entity {
+    let collision = CollisionGeometry::for_model(&model);
+    let prefab = world.add_model(model);
+    match collision {
+        Ok(geometry) => world
+            .add_collision_geometry(prefab, geometry)
+            .expect("prefab was just added"),
+        Err(err) => world.notify(
+            Severity::Warning,
+            "asset_loader",
+            format!("{err}, the model won't collide"),
+        ),
+    }
+    prefab
+}
@@ -0,0 +1,119 @@
+//! Loads the groups of models the scene file declares, see `world::preload`,
+//! a group an update so a big scene doesn't stall a single frame.
+
+use std::collections::HashSet;
+
+use gfx::Model;
+use logger::{info, Logger};
+use world::components::Drawable;
+use world::notifications::Severity;
+use world::preload::{PreloadModel, SceneFile};
+use world::World;
+
+use crate::cache::ModelCache;
+
+/// Declare the groups of the configured scene file, if there is one.
+pub(crate) fn declare_groups(world: &mut World, logger: &Logger) {
+    let path = match world.config.scene.clone() {
+        Some(path) => path,
+        None => return,
+    };
+    match SceneFile::load(&path) {
+        Ok(scene) => {
+            info!(
+                logger,
+                "preloading {} groups from {}",
+                scene.preload_groups.len(),
+                path.display()
+            );
+            world.preload.declare(scene);
+        }
+        Err(err) => world.notify(
+            Severity::Error,
+            "asset_loader",
+            format!("{err}, nothing will be preloaded"),
+        ),
+    }
+}
+
+/// Load the next group that fits in the budget, evicting lower priority
+/// groups that aren't being drawn to make room for it.
+pub(crate) fn load_next_group(world: &mut World, models: &ModelCache, logger: &Logger) {
+    let budget = world.config.preload_budget;
+    let drawn = world
+        .hecs_world
+        .query::<&Drawable>()
+        .iter()
+        .map(|(_, drawable)| drawable.gfx)
+        .collect::<HashSet<_>>();
+    let name = match world.preload.next_to_load(budget, &drawn) {
+        Some(name) => name.to_string(),
+        None => return,
+    };
+    let decls = world.preload.models(&name).unwrap_or_default().to_vec();
+
+    let mut loaded = Vec::with_capacity(decls.len());
+    for decl in &decls {
+        match models.load_obj(&decl.path, &decl.vertex_shader, &decl.fragment_shader) {
+            Ok(model) => loaded.push(model),
+            Err(err) => {
+                world.preload.set_failed(&name);
+                world.notify(
+                    Severity::Warning,
+                    "asset_loader",
+                    format!(
+                        "unable to preload {name:?}, loading {}: {err}",
+                        decl.path.display()
+                    ),
+                );
+                return;
+            }
+        }
+    }
+    let bytes = loaded.iter().map(Model::memory_size).sum();
+    world.preload.set_bytes(&name, bytes);
+
+    // Sizes are only known once loaded, so there may not be room after all.
+    let evictions = match world.preload.make_room(&name, bytes, budget, &drawn) {
+        Some(evictions) => evictions
+            .into_iter()
+            .map(str::to_string)
+            .collect::<Vec<_>>(),
+        None => {
+            info!(
+                logger,
+                "no room for preload group {name:?} yet, {} KiB of {} KiB used",
+                world.preload.resident_bytes() / 1024,
+                budget / 1024
+            );
+            forget(models, &decls);
+            return;
+        }
+    };
+    for evicted in evictions {
+        for prefab in world.preload.set_evicted(&evicted) {
+            // Only fails if the prefab is already gone.
+            let _ = world.despawn(prefab);
+        }
+        forget(models, world.preload.models(&evicted).unwrap_or_default());
+        info!(logger, "evicted preload group {evicted:?} for {name:?}");
+    }
+
+    let prefabs = loaded
+        .into_iter()
+        .map(|model| world.add_model(model))
+        .collect();
+    world.preload.set_resident(&name, prefabs);
+    info!(
+        logger,
+        "preload group {name:?} resident, {} KiB",
+        bytes / 1024
+    );
+}
+
+/// Drop cached models, so an evicted or waiting group doesn't hold on to them.
+fn forget(models: &ModelCache, decls: &[PreloadModel]) {
+    for decl in decls {
+        models.forget(&decl.path, &decl.vertex_shader, &decl.fragment_shader);
+    }
+}
@@ -0,0 +1,197 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use net_sync_system::wire::{self, EntityUpdate, ServerUpdate, WireBuffers, NO_SNAPSHOT};
+use network::compression::{Compression, CompressionStats};
+use world::{Entity, Quat, Vec3};
+
+const SNAPSHOT_ENTITIES: u32 = 96;
+
+/// Counts allocations, so ticks can be compared by how many they make as well
+/// as how long they take.
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+/// Updates for a server's worth of entities about the arena, sorted by entity
+/// as snapshots are. `moved` of them have moved along since `snapshot(0)`.
+fn snapshot(moved: u32) -> Vec<EntityUpdate> {
+    (0..SNAPSHOT_ENTITIES)
+        .map(|i| {
+            let entity = Entity::from_bits((1 << 32) | u64::from(i)).unwrap();
+            let angle = i as f32 * 0.1 + if i < moved { 0.01 } else { 0.0 };
+            let pos = Vec3::new(angle.cos() * 40.0, 1.0, angle.sin() * 40.0);
+            EntityUpdate::new(entity, pos, Quat::from_rotation_y(angle))
+        })
+        .collect()
+}
+
+/// Encode `updates` as a delta against `baseline`, which is snapshot 1 unless
+/// it's empty.
+fn encode(
+    compression: Compression,
+    stats: &mut CompressionStats,
+    baseline: &[EntityUpdate],
+    updates: &[EntityUpdate],
+    buffers: &mut WireBuffers,
+) {
+    let baseline_id = if baseline.is_empty() { NO_SNAPSHOT } else { 1 };
+    wire::encode_snapshot_delta(
+        2,
+        baseline_id,
+        baseline,
+        updates,
+        0,
+        &mut buffers.snapshot,
+        &mut buffers.delta,
+    );
+    wire::compress_world_updates(
+        Duration::ZERO,
+        compression,
+        stats,
+        &buffers.delta,
+        &buffers.spawns,
+        &buffers.despawns,
+        &buffers.haptics,
+        None,
+        &mut buffers.message,
+    )
+    .unwrap();
+}
+
+/// What the server does with a snapshot each tick: encode it for a client, and
+/// what a client does on the other end: decode it.
+fn tick(
+    compression: Compression,
+    stats: &mut CompressionStats,
+    baseline: &[EntityUpdate],
+    updates: &[EntityUpdate],
+    server: &mut WireBuffers,
+    client: &mut WireBuffers,
+) {
+    encode(compression, stats, baseline, updates, server);
+    wire::decompress_world_updates(&server.message, stats, &mut client.server_update).unwrap();
+    client
+        .server_update
+        .apply(baseline, &mut client.snapshot)
+        .unwrap();
+}
+
+fn allocations_per_tick(mut tick: impl FnMut()) -> f64 {
+    const TICKS: usize = 100;
+    // Warm up, reused buffers grow to size on the first tick.
+    tick();
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    for _ in 0..TICKS {
+        tick();
+    }
+    (ALLOCATIONS.load(Ordering::Relaxed) - before) as f64 / TICKS as f64
+}
+
+fn snapshot_benchmark(c: &mut Criterion) {
+    let mut group = c.benchmark_group("net_snapshot");
+    let baseline = snapshot(0);
+    // Everything new to a client, and a mostly static scene, against what the
+    // client has.
+    let cases = [
+        ("full", &[][..], snapshot(0)),
+        ("static", &baseline[..], snapshot(SNAPSHOT_ENTITIES / 16)),
+    ];
+    let mut stats = CompressionStats::default();
+    for compression in [Compression::None, Compression::Lz4, Compression::default()] {
+        // Bench ids become directory names, keep the zstd level out of them.
+        let codec = compression.to_string().replace(':', "_");
+        for (case, baseline, updates) in &cases {
+            let name = format!("{case}_{SNAPSHOT_ENTITIES}_{codec}");
+            let mut server = WireBuffers::default();
+            group.bench_function(format!("encode_{name}"), |b| {
+                b.iter(|| {
+                    encode(
+                        compression,
+                        &mut stats,
+                        baseline,
+                        black_box(updates),
+                        &mut server,
+                    )
+                })
+            });
+            let mut update = ServerUpdate::default();
+            let mut decoded = Vec::new();
+            group.bench_function(format!("decode_{name}"), |b| {
+                b.iter(|| {
+                    wire::decompress_world_updates(
+                        black_box(&server.message),
+                        &mut stats,
+                        &mut update,
+                    )
+                    .unwrap();
+                    update.apply(baseline, &mut decoded).unwrap();
+                })
+            });
+
+            let mut client = WireBuffers::default();
+            let reused = allocations_per_tick(|| {
+                tick(
+                    compression,
+                    &mut stats,
+                    baseline,
+                    updates,
+                    &mut server,
+                    &mut client,
+                )
+            });
+            let fresh = allocations_per_tick(|| {
+                tick(
+                    compression,
+                    &mut CompressionStats::default(),
+                    baseline,
+                    updates,
+                    &mut WireBuffers::default(),
+                    &mut WireBuffers::default(),
+                )
+            });
+            println!(
+                "tick_{name}: {} bytes, {reused} allocations reusing buffers, {fresh} with \
+                 fresh buffers",
+                server.message.len()
+            );
+            group.bench_function(format!("tick_{name}"), |b| {
+                b.iter(|| {
+                    tick(
+                        compression,
+                        &mut stats,
+                        baseline,
+                        black_box(updates),
+                        &mut server,
+                        &mut client,
+                    )
+                })
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, snapshot_benchmark);
+criterion_main!(benches);
@@ -1,10 +1,12 @@
 //! Plugin: `net_sync_system`
 //! Implements a plugin (see crates/plugin-loader) for prototyping network sync.
+//! Connections are moved onto their own core with `NetThread`, so updates only
+//! drain and enqueue messages, never waiting on the socket.
 //! TODO:
 //!     - move connection impl and pumping here.
 //!     - hone an api for world state -> net sync update transition.
 
-use std::collections::VecDeque;
+use std::collections::{HashMap, VecDeque};
 use std::io;
 use std::net::SocketAddr;
 use std::time::{Duration, Instant};
@@ -15,68 +17,557 @@ use bytemuck::PodCastError;
 use futures_lite::FutureExt;
 use histogram::Histogram;
 use input::wire::InputState;
-use logger::{error, info, LogLevel, Logger};
-use network::{Connection, Message, RpcError, Typed, MAX_UNACKED_PACKETS, MSG_LEN, PAYLOAD_LEN};
-use wire::EntityUpdate;
+use logger::{error, info, warn, LogLevel, Logger};
+use network::build_info::BuildInfo;
+use network::compression::{Compression, CompressionStats};
+use network::manager::ConnectionManager;
+use network::quality::QualitySample;
+use network::reconnect::{Backoff, ConnectionState, Resolve};
+use network::reliable::{self, Reliable};
+use network::trace::{Classify, Direction, MessageTrace, PayloadKind, PayloadType};
+use network::{
+    Connection, Message, PacketCounts, RpcError, SequenceNumber, Typed, MAX_UNACKED_PACKETS,
+    MSG_LEN, PAYLOAD_LEN,
+};
+use wire::{
+    EntityUpdate, HapticUpdate, ProjectileDespawnUpdate, ProjectileSpawnUpdate,
+    SliceChecksumUpdate, WireBuffers, NO_SNAPSHOT,
+};
+use world::bundles::ProjectileSpawn;
 use world::components::spatial::SpatialHierarchyNode;
-use world::components::PhysicsBody;
-use world::{Entity, Vec3, World, WorldError, WorldLockAndControllerState};
-
-const NUM_UPDATES_PER_MSG: u32 = 2;
-
+use world::components::{Drawable, Lifetime, PhysicsBody, Velocity};
+use world::journal::JournalEvent;
+use world::notifications::Severity;
+use world::replication::{ReplicationBuffer, ReplicationPolicy, ReplicationSample};
+use world::snapshot::{SliceChecksum, SliceComparison};
+use world::{Client, Entity, Quat, Vec3, World, WorldError, WorldLockAndControllerState};
+
+mod net_thread;
+pub mod schema;
+pub use net_thread::NetThread;
+
+/// Most entities removed or changed in each message, see
+/// `wire::encode_snapshot_delta`. Entities left out of one are sent in the
+/// next.
+const MAX_UPDATES_PER_MSG: usize = 24;
+
+/// Snapshots kept per client, and by a client, to make and apply deltas
+/// against. A client acknowledging older ones is sent everything again.
+const SNAPSHOT_HISTORY_LEN: usize = 32;
+
+/// Most projectile spawns announced in one update.
+const MAX_PROJECTILE_SPAWNS_PER_MSG: usize = 8;
+/// Most projectile despawns announced in one update.
+const MAX_PROJECTILE_DESPAWNS_PER_MSG: usize = 8;
+/// Updates each projectile spawn and despawn is announced in, so a client
+/// still sees it if some of them are lost.
+const PROJECTILE_ANNOUNCEMENT_SENDS: u32 = 3;
+/// Most haptic events sent in one update. Like the rest of the update they're
+/// best effort, and lost with it.
+const MAX_HAPTICS_PER_MSG: usize = 4;
+
+/// A client's first message to the server, followed by a mask of the
+/// compression codecs it supports and its build, see
+/// `BuildInfo::encode_into`.
+const HANDSHAKE: &[u8] = b"moar plz";
+
+/// The server's answer to the handshake of a client built differently,
+/// followed by the server's build. The client is dropped.
+const BUILD_MISMATCH: &[u8] = b"wrong build";
+
+/// How long a client waits to hear from the server before reconnecting.
+const CONNECTION_TIMEOUT: Duration = Duration::from_secs(3);
+
+// Weight of each new measurement in a peer's smoothed round trip time and
+// packet loss.
+const RTT_SMOOTHING: f64 = 0.125;
+const LOSS_SMOOTHING: f32 = 0.1;
+
+// Public only for the snapshot benches, through the wire codec functions.
+#[doc(hidden)]
 #[derive(thiserror::Error, Debug)]
-enum PluginError {
+pub enum PluginError {
     #[error("Error pod casting update from bytes {0:?} len {1}")]
     FromBytes(PodCastError, usize),
     #[error("world error {0}")]
     World(#[from] WorldError),
+    #[error("codec error {0}")]
+    Codec(#[from] codec::CodecError),
+    #[error("the server runs {0}, a different build")]
+    BuildMismatch(BuildInfo),
 }
 
 pub struct NetSyncState {
     logger: Logger,
+    connection: Option<Box<dyn Connection + Send + Sync + 'static>>,
+    /// When the server last sent world updates, which it does less often on a
+    /// congested connection.
+    last_update_sent: Option<Instant>,
+    /// Projectiles the server is announcing the spawn or despawn of.
+    projectile_announcements: ProjectileAnnouncements,
+    /// Projectiles a client has spawned from announcements, by the server's
+    /// id for them, so announcements repeated in later updates are ignored.
+    replicated_projectiles: HashMap<u32, Entity>,
+    /// How a client connects to the server, None for servers and provided
+    /// connections, which aren't reconnected.
+    reconnect: Option<Reconnect>,
+    buffers: WireBuffers,
+    /// Where a server listens, if not the default address.
+    listen_addr: Option<String>,
+    /// A server's socket, accepting clients over the updates.
+    manager: Option<ConnectionManager>,
+    /// When a server that couldn't bind its address tries again.
+    listen_retry_at: Option<Instant>,
+    /// Snapshots a server has sent each client.
+    sent_snapshots: ServerSnapshots,
+    /// Snapshots a client has received from the server.
+    received_snapshots: ReceivedSnapshots,
+    /// Where every connection's packets are traced, see `Config::net_trace`.
+    trace: Option<MessageTrace>,
+}
+
+/// The last snapshots of the replicated entities sent to or received from a
+/// peer, by id, for deltas to be made or applied against.
+#[derive(Debug, Default)]
+struct SnapshotHistory {
+    snapshots: VecDeque<(u32, Vec<EntityUpdate>)>,
+}
+
+impl SnapshotHistory {
+    /// The snapshot `id`, if it's still kept. `NO_SNAPSHOT` is always kept,
+    /// and empty.
+    fn get(&self, id: u32) -> Option<&[EntityUpdate]> {
+        if id == NO_SNAPSHOT {
+            return Some(&[]);
+        }
+        self.snapshots
+            .iter()
+            .find(|(kept, _)| *kept == id)
+            .map(|(_, snapshot)| &snapshot[..])
+    }
+
+    /// Keep `snapshot` as `id`, returning the emptied buffer of one that's no
+    /// longer kept, to fill next.
+    fn push(&mut self, id: u32, snapshot: Vec<EntityUpdate>) -> Vec<EntityUpdate> {
+        self.snapshots.push_back((id, snapshot));
+        let mut recycled = Vec::new();
+        if self.snapshots.len() > SNAPSHOT_HISTORY_LEN {
+            if let Some((_, oldest)) = self.snapshots.pop_front() {
+                recycled = oldest;
+            }
+        }
+        recycled.clear();
+        recycled
+    }
+}
+
+/// Snapshots sent to a client, see `wire::encode_snapshot_delta`.
+#[derive(Debug, Default)]
+struct ClientSnapshots {
+    sent: SnapshotHistory,
+    /// The latest snapshot the client acknowledged, which deltas are made
+    /// against.
+    acked: u32,
+    /// The entity the next delta's changes start from.
+    next_entity: u64,
+}
+
+#[derive(Debug, Default)]
+struct ServerSnapshots {
+    /// The id of the last snapshot sent.
+    last: u32,
+    clients: HashMap<Option<SocketAddr>, ClientSnapshots>,
+}
+
+impl ServerSnapshots {
+    fn next_id(&mut self) -> u32 {
+        self.last = self.last.wrapping_add(1).max(NO_SNAPSHOT + 1);
+        self.last
+    }
+}
+
+#[derive(Debug, Default)]
+struct ReceivedSnapshots {
+    received: SnapshotHistory,
+    /// The latest snapshot decoded, which is acknowledged to the server.
+    latest: u32,
+}
+
+/// Where a server binds unless given another address. `nshell --diagnose`
+/// checks its port is free.
+pub const DEFAULT_LISTEN_ADDR: &str = "0.0.0.0:12002";
+
+/// Version of the plugin, shown with its name in what it logs.
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Most clients a server syncs with at a time.
+const MAX_CLIENTS: usize = 8;
+
+/// How long a server that couldn't bind its address waits before trying
+/// again, such as while a reloaded plugin's old clients let go of it.
+const RELISTEN_DELAY: Duration = Duration::from_secs(1);
+
+/// A client's connection to the server by host name: resolving it off the
+/// main thread, connecting, and reconnecting with backoff when the server
+/// isn't heard from for `CONNECTION_TIMEOUT`. Changes are published with
+/// `World::set_connection_state`.
+struct Reconnect {
+    backoff: Backoff,
+    resolving: Option<Resolve>,
+    retry_at: Option<Instant>,
+    /// When the server was last heard from, or the connection made if it
+    /// hasn't been yet.
+    last_heard: Instant,
+}
+
+impl Reconnect {
+    fn new() -> Self {
+        Self {
+            backoff: Backoff::default(),
+            resolving: None,
+            retry_at: None,
+            last_heard: Instant::now(),
+        }
+    }
+
+    /// Move the connection along, called every update before pumping it.
+    fn maintain(&mut self, world: &mut World, trace: Option<&MessageTrace>, logger: &Logger) {
+        let now = Instant::now();
+        if world.connection.is_some() {
+            if now.duration_since(self.last_heard) > CONNECTION_TIMEOUT {
+                warn!(logger, "no word from the server in {CONNECTION_TIMEOUT:?}");
+                world.connection = None;
+                self.retry_later(world, now);
+            }
+            return;
+        }
+
+        if let Some(resolving) = self.resolving.as_ref() {
+            let resolved = match resolving.poll() {
+                Some(resolved) => resolved,
+                None => return,
+            };
+            let host = resolving.host().to_string();
+            self.resolving = None;
+            let build = &world.config.build_info;
+            let connected = resolved
+                .map_err(RpcError::Connect)
+                .and_then(|addr| futures_lite::future::block_on(connect_to_server(addr, build)));
+            match connected {
+                Ok(peer) => {
+                    world.connection = Some(off_thread(peer, trace, wire::classify_as_client));
+                    self.last_heard = now;
+                    world.set_connection_state(ConnectionState::Connecting);
+                }
+                Err(err) => {
+                    warn!(logger, "unable to connect to {host}: {err}");
+                    self.retry_later(world, now);
+                }
+            }
+            return;
+        }
+
+        if self.retry_at.map_or(true, |retry_at| now >= retry_at) {
+            let host = match world.config.maybe_server_addr.as_deref() {
+                Some(host) => host,
+                None => return,
+            };
+            self.retry_at = None;
+            self.resolving = Some(Resolve::start(host));
+            world.set_connection_state(ConnectionState::Resolving);
+        }
+    }
+
+    fn retry_later(&mut self, world: &mut World, now: Instant) {
+        let retry_in = self.backoff.next_delay();
+        self.retry_at = Some(now + retry_in);
+        world.set_connection_state(ConnectionState::Reconnecting {
+            attempt: self.backoff.attempt(),
+            retry_in,
+        });
+    }
+
+    fn heard_from_server(&mut self, world: &mut World) {
+        self.last_heard = Instant::now();
+        if world.connection_state != Some(ConnectionState::Connected) {
+            self.backoff.reset();
+            world.set_connection_state(ConnectionState::Connected);
+        }
+    }
+}
+
+/// Run a connection's IO on its own core, see `NetThread`, carrying a
+/// reliable channel. Both ends of every connection go through here, so both
+/// carry one. Packets are traced below the channel, as they went over the
+/// wire, when there's a trace.
+fn off_thread(
+    connection: Box<dyn Connection + Send + Sync + 'static>,
+    trace: Option<&MessageTrace>,
+    classify: Classify,
+) -> Box<dyn Connection + Send + Sync + 'static> {
+    let connection: Box<dyn Connection + Send + Sync + 'static> = match trace {
+        Some(trace) => Box::new(trace.trace(connection, classify)),
+        None => connection,
+    };
+    Box::new(NetThread::spawn(Box::new(Reliable::new(connection))))
+}
+
+/// Bind the client's socket and greet the server, which starts sending
+/// updates once it hears from the client.
+async fn connect_to_server(
+    addr: SocketAddr,
+    build: &BuildInfo,
+) -> Result<Box<dyn Connection + Send + Sync + 'static>, RpcError> {
+    // Any free port, so several clients can run on one host.
+    let mut client = Peer::bind_dest("0.0.0.0:0", &addr.to_string()).await?;
+    let mut handshake = HANDSHAKE.to_vec();
+    handshake.push(Compression::SUPPORTED);
+    build.encode_into(&mut handshake);
+    client.send(&handshake).await?;
+    Ok(Box::new(client))
 }
 
 impl NetSyncState {
     pub fn new() -> Self {
         Self {
             logger: LogLevel::Info.logger(),
+            connection: None,
+            last_update_sent: None,
+            projectile_announcements: ProjectileAnnouncements::default(),
+            replicated_projectiles: HashMap::new(),
+            reconnect: None,
+            buffers: WireBuffers::default(),
+            listen_addr: None,
+            manager: None,
+            listen_retry_at: None,
+            sent_snapshots: ServerSnapshots::default(),
+            received_snapshots: ReceivedSnapshots::default(),
+            trace: None,
+        }
+    }
+
+    /// Sync over an already established connection, such as one end of a
+    /// `network::loopback::LoopbackConnection` pair, instead of binding a
+    /// socket on load.
+    pub fn with_connection(connection: impl Connection + Send + Sync + 'static) -> Self {
+        Self {
+            connection: Some(Box::new(connection)),
+            ..Self::new()
+        }
+    }
+
+    /// Serve on `addr` instead of the default address. This lets several
+    /// server worlds, such as rooms, each listen in one process.
+    pub fn listening_on(addr: impl Into<String>) -> Self {
+        Self {
+            listen_addr: Some(addr.into()),
+            ..Self::new()
         }
     }
 
+    /// Log through `logger` from now on, such as the root logger the engine
+    /// gives each plugin.
+    pub fn set_logger(&mut self, logger: Logger) {
+        self.logger = logger;
+    }
+
     pub fn load(&mut self, state: &mut WorldLockAndControllerState) {
         info!(
-            state.logger,
+            self.logger,
             "reloaded net sync plugin ({})!", state.world.stats.updates
         );
         self.logger.maybe_set_filter(state.logger.get_filter());
+        self.open_trace(&state.world);
+
+        if let Some(connection) = self.connection.take() {
+            info!(self.logger, "syncing over a provided connection");
+            let classify: Classify = if state.world.is_server() {
+                wire::classify_as_server
+            } else {
+                wire::classify_as_client
+            };
+            let connection = off_thread(connection, self.trace.as_ref(), classify);
+            if state.world.is_server() {
+                state
+                    .world
+                    .record(JournalEvent::ClientConnected { addr: None });
+                state.world.clients.push(Client {
+                    addr: None,
+                    connection,
+                    // Both ends are this build, so every codec is supported.
+                    compression: state.world.config.net_compression,
+                });
+            } else {
+                state.world.connection = Some(connection);
+            }
+            return;
+        }
 
-        let connection = match state.world.config.maybe_server_addr {
-            Some(addr) => {
-                futures_lite::future::block_on(async move {
-                    let mut server = Peer::bind_dest("0.0.0.0:12001", &addr.to_string())
-                        .await
-                        .unwrap();
+        if !state.world.is_server() {
+            // Connected over the next updates, without blocking on the
+            // server's address being resolved.
+            self.reconnect = Some(Reconnect::new());
+            return;
+        }
 
-                    // initial message to client
-                    server.send(b"moar plz").await.unwrap();
-                    server
-                })
+        // Clients are accepted over the next updates.
+        self.listen();
+    }
+
+    /// Start tracing packets if the world asks for it, once, so reloading
+    /// doesn't start the trace over.
+    fn open_trace(&mut self, world: &World) {
+        let path = match world.config.net_trace.as_ref() {
+            Some(path) if self.trace.is_none() => path,
+            _ => return,
+        };
+        match MessageTrace::create(path) {
+            Ok(trace) => {
+                info!(self.logger, "tracing packets to {}", path.display());
+                self.trace = Some(trace);
             }
-            None => {
-                let logger = state.logger.sub("net-sync");
-                // We will run as a server, accepting new connections.
-                futures_lite::future::block_on(async move {
-                    let addr = "0.0.0.0:12002";
-                    info!(logger, "binding addr {addr}");
-                    let mut client = Peer::bind_only(addr).await.unwrap();
-                    client.recv().await.unwrap();
-                    client
-                })
+            Err(err) => warn!(
+                self.logger,
+                "unable to trace packets to {}: {err}",
+                path.display()
+            ),
+        }
+    }
+
+    /// Bind the server's socket, trying again later if that fails.
+    fn listen(&mut self) {
+        let addr = self.listen_addr.as_deref().unwrap_or(DEFAULT_LISTEN_ADDR);
+        match ConnectionManager::bind(addr, MAX_CLIENTS) {
+            Ok(manager) => {
+                info!(
+                    self.logger,
+                    "accepting up to {MAX_CLIENTS} clients on {addr}"
+                );
+                self.manager = Some(manager);
+                self.listen_retry_at = None;
             }
-        };
+            Err(err) => {
+                warn!(
+                    self.logger,
+                    "unable to listen on {addr}, trying again in {RELISTEN_DELAY:?}: {err}"
+                );
+                self.listen_retry_at = Some(Instant::now() + RELISTEN_DELAY);
+            }
+        }
+    }
 
-        state.world.connection =
-            Some(Box::new(connection) as Box<dyn Connection + Send + Sync + 'static>);
+    /// Forget clients that have gone, and add those whose handshake has
+    /// arrived since the last update.
+    fn accept_clients(&mut self, world: &mut World, logger: &Logger) {
+        if self.listen_retry_at.is_some_and(|at| at <= Instant::now()) {
+            self.listen();
+        }
+        let manager = match self.manager.as_ref() {
+            Some(manager) => manager,
+            None => return,
+        };
+        let trace = self.trace.as_ref();
+        let timed_out = manager.disconnect_silent(CONNECTION_TIMEOUT);
+        for addr in timed_out.iter() {
+            warn!(
+                logger,
+                "no word from client {addr} in {CONNECTION_TIMEOUT:?}"
+            );
+        }
+        let (journal, update) = (&world.journal, world.stats.updates);
+        world.clients.retain(|client| {
+            let connected = client.connection.is_connected();
+            if !connected {
+                if let Some(addr) = client.addr {
+                    info!(logger, "client {addr} disconnected");
+                }
+                let reason = match client.addr {
+                    Some(addr) if timed_out.contains(&addr) => "timed out",
+                    _ => "connection closed",
+                };
+                journal.record(
+                    update,
+                    JournalEvent::ClientDisconnected {
+                        addr: client.addr,
+                        reason: reason.to_string(),
+                    },
+                );
+            }
+            connected
+        });
+        let clients = &world.clients;
+        self.sent_snapshots
+            .clients
+            .retain(|addr, _| clients.iter().any(|client| client.addr == *addr));
+
+        loop {
+            let mut peer = match manager.accept() {
+                Ok(Some(peer)) => peer,
+                Ok(None) => return,
+                Err(err) => {
+                    world.notify(
+                        Severity::Error,
+                        "net_sync",
+                        format!("unable to accept clients: {err}"),
+                    );
+                    return;
+                }
+            };
+            // A client's first message is its handshake. Anything else is left
+            // over from an earlier connection, such as one that was kicked,
+            // and the client is dropped until it connects again.
+            let handshake = futures_lite::future::block_on(peer.recv_with_timeout(Duration::ZERO));
+            let handshake = handshake.ok().and_then(|handshake| {
+                let msg = handshake.try_ref().ok()?;
+                parse_handshake(&msg.payload)
+            });
+            let (codecs, client_build) = match handshake {
+                Some(handshake) => handshake,
+                None => continue,
+            };
+            // Told why before it's dropped, as the client is sure to desync.
+            let build = &world.config.build_info;
+            if !client_build
+                .as_ref()
+                .is_some_and(|client_build| build.is_compatible(client_build))
+            {
+                let client_build = client_build.map_or_else(
+                    || "a build from before builds were exchanged".to_string(),
+                    |client_build| client_build.to_string(),
+                );
+                let addr = peer.addr();
+                let mut reply = BUILD_MISMATCH.to_vec();
+                build.encode_into(&mut reply);
+                // Framed like every packet the client's connection receives.
+                let mut peer = Reliable::new(Box::new(peer));
+                if let Err(err) = futures_lite::future::block_on(peer.send(&reply)) {
+                    warn!(
+                        logger,
+                        "unable to tell client {addr} its build differs: {err}"
+                    );
+                }
+                let message =
+                    format!("refused client {addr}, which runs {client_build}, not {build}");
+                world.notify(Severity::Error, "net_sync", message);
+                continue;
+            }
+            let compression = world.config.net_compression.negotiate(codecs);
+            info!(
+                logger,
+                "client {} connected, compressing updates with {compression}",
+                peer.addr()
+            );
+            world.record(JournalEvent::ClientConnected {
+                addr: Some(peer.addr()),
+            });
+            // Reconnecting, the client has none of the snapshots sent before.
+            self.sent_snapshots.clients.remove(&Some(peer.addr()));
+            world.clients.push(Client {
+                addr: Some(peer.addr()),
+                connection: off_thread(Box::new(peer), trace, wire::classify_as_server),
+                compression,
+            });
+        }
     }
 
     pub fn update(
@@ -84,67 +575,237 @@ impl NetSyncState {
         s: &mut WorldLockAndControllerState,
         delta_time: &std::time::Duration,
     ) {
-        let logger = s.logger.sub("net_sync_system-update");
-        // TODO: fix sized net sync issue (try > NUM_UPDTES_PER_MSG items)
+        let logger = self.logger.sub("update");
         if s.world.is_server() {
-            assert!(s.world.hecs_world.len() <= 96, "too many entities FIXME");
+            self.accept_clients(&mut s.world, &logger);
+            if s.world.clients.is_empty() {
+                return;
+            }
 
-            match futures_lite::future::block_on(pump_connection_as_server(&mut s.world)) {
+            // Ready immediately, the connection only queues and dequeues.
+            match futures_lite::future::block_on(pump_connection_as_server(
+                &mut s.world,
+                &mut self.last_update_sent,
+                &mut self.projectile_announcements,
+                &mut self.sent_snapshots,
+                &mut self.buffers,
+            )) {
                 Ok(controller_state) => {
+                    // Keep the client's last state until it's heard from again.
                     // TODO: support N controllers, or just one per client?
-                    s.world.set_client_controller_state(controller_state[0]);
+                    if let Some(controller_state) = controller_state {
+                        s.world.set_client_controller_state(controller_state[0]);
+                    }
                     let new_server_states = s.controller_state[0];
                     s.world.set_server_controller_state(new_server_states);
                 }
-                Err(err) => error!(logger, "error pumping server connection {err:?}"),
+                Err(err) => s.world.notify(
+                    Severity::Error,
+                    "net_sync",
+                    format!("error pumping server connection: {err}"),
+                ),
             }
         } else {
-            match futures_lite::future::block_on(pump_connection_as_client(
-                &mut s.world,
-                &*s.controller_state,
-            )) {
-                Err(PluginError::World(WorldError::Network(network::RpcError::Receive(kind))))
-                    if kind.kind() == std::io::ErrorKind::TimedOut => {}
-                Err(err) => {
-                    error!(logger, "error in client connection {err:?}");
+            if let Some(reconnect) = self.reconnect.as_mut() {
+                reconnect.maintain(&mut s.world, self.trace.as_ref(), &logger);
+            }
+            if s.world.connection.is_some() {
+                // Ready immediately, the connection only queues and dequeues.
+                match futures_lite::future::block_on(pump_connection_as_client(
+                    &mut s.world,
+                    &*s.controller_state,
+                    &mut self.replicated_projectiles,
+                    &mut self.received_snapshots,
+                    &mut self.buffers,
+                )) {
+                    Ok(true) => {
+                        if let Some(reconnect) = self.reconnect.as_mut() {
+                            reconnect.heard_from_server(&mut s.world);
+                        }
+                    }
+                    Ok(false) => {}
+                    Err(PluginError::World(WorldError::Network(network::RpcError::Receive(
+                        kind,
+                    )))) if kind.kind() == std::io::ErrorKind::TimedOut => {}
+                    // Reconnecting would only be refused again.
+                    Err(err @ PluginError::BuildMismatch(_)) => {
+                        s.world.notify(
+                            Severity::Error,
+                            "net_sync",
+                            format!("{err}, this client is {}", s.world.config.build_info),
+                        );
+                        s.world.connection = None;
+                        self.reconnect = None;
+                        s.world.set_connection_state(ConnectionState::Refused);
+                    }
+                    Err(err) => {
+                        s.world.notify(
+                            Severity::Error,
+                            "net_sync",
+                            format!("error in client connection: {err}"),
+                        );
+                    }
                 }
-                _ => (),
             }
         };
+
+        // Updates are sent as often as the slowest connection allows.
+        let sample = s
+            .world
+            .connections()
+            .filter_map(|connection| connection.quality_sample())
+            .max_by_key(|sample| sample.rtt);
+        if let Some(sample) = sample {
+            if let Some(change) = s.world.connection_quality.update(sample, Instant::now()) {
+                info!(
+                    logger,
+                    "connection quality {:?} -> {:?} (rtt {:?}, loss {:.1}%)",
+                    change.from,
+                    change.to,
+                    sample.rtt,
+                    sample.loss * 100.0
+                );
+            }
+        }
     }
 
     pub fn unload(&mut self, state: &mut WorldLockAndControllerState) {
         info!(
-            state.logger,
+            self.logger,
             "unloaded net sync plugin ({})...", state.world.stats.updates
         );
         state.world.connection.take();
+        state.world.clients.clear();
+        self.manager.take();
+        self.sent_snapshots = ServerSnapshots::default();
+        self.received_snapshots = ReceivedSnapshots::default();
     }
 }
 
-async fn pump_connection_as_server(s: &mut World) -> Result<[InputState; 2], PluginError> {
-    // 1. construct a group of all updates fromo world state (dynamic physics
-    // objects only).
-    let packet = s
-        .hecs_world
-        .query::<(&mut SpatialHierarchyNode, &PhysicsBody)>()
-        .iter()
-        .map(|(entity, (spatial, _physics))| {
-            EntityUpdate::new(entity, spatial.get_pos(), spatial.get_angles().y)
-        })
-        .take(NUM_UPDATES_PER_MSG as usize)
-        .collect::<Vec<_>>();
-
-    // 2. Compress that
-    let compressed = wire::compress_world_updates(&packet)?;
-    let _seq = s.connection.as_mut().unwrap().send(&compressed).await;
-    let client_controller_data = s
-        .connection
-        .as_mut()
-        .unwrap()
-        .recv_with_timeout(Duration::from_millis(1))
-        .await
-        .map_err(WorldError::Network)?;
+/// Send every client the latest update, and take what each has sent since.
+/// Returns the first client's controller state, if it sent one.
+async fn pump_connection_as_server(
+    s: &mut World,
+    last_update_sent: &mut Option<Instant>,
+    projectiles: &mut ProjectileAnnouncements,
+    snapshots: &mut ServerSnapshots,
+    buffers: &mut WireBuffers,
+) -> Result<Option<[InputState; 2]>, PluginError> {
+    let now = Instant::now();
+    for projectile in s.new_projectiles.drain(..) {
+        projectiles.spawned(projectile);
+    }
+    let send_interval = s.connection_quality.quality().send_interval();
+    if last_update_sent.map_or(true, |sent| now.duration_since(sent) >= send_interval) {
+        // 1. Snapshot the world state (dynamic physics objects only), sorted
+        // for deltas to be made against earlier snapshots.
+        buffers.entity_updates.clear();
+        buffers.entity_updates.extend(
+            s.hecs_world
+                .query::<(&mut SpatialHierarchyNode, &PhysicsBody)>()
+                .iter()
+                .map(|(entity, (spatial, _physics))| {
+                    EntityUpdate::new(entity, spatial.get_pos(), spatial.get_rotation())
+                }),
+        );
+        buffers
+            .entity_updates
+            .sort_unstable_by_key(|update| update.entity_bits);
+
+        // 2. Announce projectiles spawned recently, where they are now, and
+        // those despawned before their lifetime ran out.
+        projectiles.updates(s, &mut buffers.spawns, &mut buffers.despawns);
+
+        // 3. Rumble the client's controllers, for hits on its player.
+        buffers.haptics.clear();
+        buffers.haptics.extend(
+            s.remote_rumbles
+                .drain(..)
+                .take(MAX_HAPTICS_PER_MSG)
+                .map(HapticUpdate::new),
+        );
+
+        // 4. Hash the next group of entities, for the client to check its
+        // world against.
+        let group = s.divergence.next_group();
+        let slice = replicated_slice(s, s.stats.updates, group, s.divergence.groups());
+        s.divergence.hashed(slice);
+
+        // 5. Encode the snapshot as a delta against the last one each client
+        // acknowledged, compressed with its codec and stamped with the server
+        // time for clients to sync to.
+        let server_time = s.clock.now(now);
+        let snapshot = snapshots.next_id();
+        for (index, client) in s.clients.iter_mut().enumerate() {
+            let sent = snapshots.clients.entry(client.addr).or_default();
+            // Clients acknowledging snapshots no longer kept are sent
+            // everything.
+            let baseline_id = match sent.sent.get(sent.acked) {
+                Some(_) => sent.acked,
+                None => NO_SNAPSHOT,
+            };
+            let baseline = sent.sent.get(baseline_id).unwrap_or_default();
+            sent.next_entity = wire::encode_snapshot_delta(
+                snapshot,
+                baseline_id,
+                baseline,
+                &buffers.entity_updates,
+                sent.next_entity,
+                &mut buffers.snapshot,
+                &mut buffers.delta,
+            );
+            buffers.snapshot = sent
+                .sent
+                .push(snapshot, std::mem::take(&mut buffers.snapshot));
+
+            // Rumbles are for the client's player, so only its client feels
+            // them.
+            let haptics = if index == 0 {
+                &buffers.haptics[..]
+            } else {
+                &[][..]
+            };
+            wire::compress_world_updates(
+                server_time,
+                client.compression,
+                &mut s.compression_stats,
+                &buffers.delta,
+                &buffers.spawns,
+                &buffers.despawns,
+                haptics,
+                Some(slice),
+                &mut buffers.message,
+            )?;
+            let _seq = client.connection.send(&buffers.message).await;
+        }
+        *last_update_sent = Some(now);
+    }
+    // Only the latest message received from each client matters, for the
+    // snapshot it acknowledges, and only the first client's controller state.
+    // Errors from the others are of no consequence, they're dropped once they
+    // disconnect.
+    let mut client_controller_data = None;
+    for (index, client) in s.clients.iter_mut().enumerate() {
+        let latest = match latest_message(client.connection.as_mut()).await {
+            Ok(latest) => latest,
+            Err(err) if index == 0 => return Err(err),
+            Err(_) => None,
+        };
+        let acked = latest
+            .as_ref()
+            .and_then(|latest| latest.try_ref().ok())
+            .and_then(|latest| acked_snapshot(&latest.payload));
+        if let (Some(acked), Some(sent)) = (acked, snapshots.clients.get_mut(&client.addr)) {
+            sent.acked = acked;
+        }
+        if index == 0 {
+            client_controller_data = latest;
+        }
+    }
+    let client_controller_data = match client_controller_data {
+        Some(data) => data,
+        None => return Ok(None),
+    };
 
     let payload = client_controller_data
         .try_ref()
@@ -160,146 +821,1157 @@ async fn pump_connection_as_server(s: &mut World) -> Result<[InputState; 2], Plu
             )),
         )));
     }
-    let cast: &[InputState; 2] = bytemuck::try_from_bytes(&payload[2..2 + len as usize])
-        .map_err(|err| PluginError::FromBytes(err, payload.len()))?;
-    Ok(*cast)
+    let mut controllers: [InputState; 2] = Default::default();
+    wire::decode_input_states(&payload[2..2 + len as usize], &mut controllers)?;
+    if let Some(slice) = SliceChecksumUpdate::read(&payload[2 + len as usize..]) {
+        compare_slice(s, &slice, "client");
+    }
+    Ok(Some(controllers))
+}
+
+/// The codecs a client supports and its build, from its handshake, see
+/// `connect_to_server`. None for anything else, or a handshake cut off before
+/// its codecs. The build is None for clients from before builds were
+/// exchanged.
+fn parse_handshake(payload: &[u8]) -> Option<(u8, Option<BuildInfo>)> {
+    let rest = payload.strip_prefix(HANDSHAKE)?;
+    let (&codecs, build) = rest.split_first()?;
+    Some((codecs, BuildInfo::decode(build)))
+}
+
+/// The snapshot a client acknowledged with its input, see
+/// `pump_connection_as_client`.
+fn acked_snapshot(payload: &[u8]) -> Option<u32> {
+    let len = u16::from_ne_bytes(payload.get(0..2)?.try_into().ok()?);
+    let at = 2 + len as usize + std::mem::size_of::<SliceChecksumUpdate>();
+    wire::read_acked_snapshot(payload.get(at..)?)
+}
+
+/// Hash one group of the entities replicated to clients, in the form they're
+/// sent in, so the server's and a client's hashes agree unless their worlds
+/// do.
+fn replicated_slice(s: &World, update: u64, group: u16, groups: u16) -> SliceChecksum {
+    let mut slice = SliceChecksum::new(update, group, groups);
+    for (entity, (spatial, _physics, buffer)) in s
+        .hecs_world
+        .query::<(
+            &SpatialHierarchyNode,
+            &PhysicsBody,
+            Option<&ReplicationBuffer>,
+        )>()
+        .iter()
+    {
+        let bits = entity.to_bits().into();
+        if slice.contains(bits) {
+            // Clients draw buffered entities away from the latest update,
+            // which is what the server sent.
+            let (pos, rot) = match buffer.and_then(ReplicationBuffer::latest) {
+                Some(latest) => (latest.position, latest.rotation),
+                None => (spatial.get_pos(), spatial.get_rotation()),
+            };
+            let state = EntityUpdate::new(entity, pos, rot);
+            slice.add(bits, bytemuck::bytes_of(&state));
+        }
+    }
+    slice
+}
+
+/// Compare the `peer`'s slice checksum with ours, reporting groups of
+/// entities as they diverge and recover.
+fn compare_slice(s: &mut World, remote: &SliceChecksum, peer: &str) {
+    let comparison = s.divergence.compare(remote);
+    let logger = &s.logger;
+    match (comparison, s.divergence.check(remote.group)) {
+        (Some(SliceComparison::Diverged), Some(check)) => warn!(
+            logger,
+            "entity group {}/{} diverged from the {peer}'s since update {}",
+            remote.group,
+            remote.groups,
+            check.mismatched_since.unwrap_or(remote.update)
+        ),
+        (Some(SliceComparison::Recovered), _) => info!(
+            logger,
+            "entity group {}/{} agrees with the {peer}'s again", remote.group, remote.groups
+        ),
+        _ => {}
+    }
+}
+
+/// Take every message received since the last update, returning the latest.
+async fn latest_message(
+    connection: &mut (dyn Connection + Send + Sync + 'static),
+) -> Result<Option<Typed<Message>>, PluginError> {
+    let mut last_pkt = None;
+    loop {
+        match connection.recv_with_timeout(Duration::ZERO).await {
+            Ok(pkt) => last_pkt = Some(pkt),
+            Err(RpcError::Receive(err)) if err.kind() == io::ErrorKind::TimedOut => {
+                return Ok(last_pkt);
+            }
+            Err(err) => return Err(PluginError::World(WorldError::Network(err))),
+        }
+    }
+}
+
+/// Projectiles a server announces to clients. Each is given an id of its own
+/// when it's spawned, so clients never see the server's entities, which are
+/// reused once projectiles are despawned.
+#[derive(Debug, Default)]
+struct ProjectileAnnouncements {
+    /// The last id given out, 0 before any.
+    last_id: u32,
+    /// Live projectiles, with their ids.
+    live: HashMap<Entity, u32>,
+    /// Projectiles being announced, with their ids and the updates left to
+    /// announce them in.
+    spawning: Vec<(Entity, u32, u32)>,
+    /// Ids of despawned projectiles being announced, with the updates left to
+    /// announce them in.
+    despawning: Vec<(u32, u32)>,
+}
+
+impl ProjectileAnnouncements {
+    /// Give a projectile just spawned an id and start announcing it. A pooled
+    /// projectile reused before the last one's despawn was noticed has that
+    /// announced too.
+    fn spawned(&mut self, projectile: Entity) {
+        self.last_id = self.last_id.wrapping_add(1).max(1);
+        let id = self.last_id;
+        if let Some(replaced) = self.live.insert(projectile, id) {
+            self.despawning
+                .push((replaced, PROJECTILE_ANNOUNCEMENT_SENDS));
+        }
+        self.spawning
+            .retain(|(spawning, ..)| *spawning != projectile);
+        self.spawning
+            .push((projectile, id, PROJECTILE_ANNOUNCEMENT_SENDS));
+    }
+
+    /// Fill `spawns` and `despawns` with the announcements for the next
+    /// update, counting this send for each. Projectiles despawned since the
+    /// last update stop being announced as spawned and are announced as
+    /// despawned instead, whether they hit something or their lifetime ran
+    /// out.
+    fn updates(
+        &mut self,
+        s: &World,
+        spawns: &mut Vec<ProjectileSpawnUpdate>,
+        despawns: &mut Vec<ProjectileDespawnUpdate>,
+    ) {
+        let despawning = &mut self.despawning;
+        self.live.retain(|projectile, id| {
+            let live = s.is_live(*projectile);
+            if !live {
+                despawning.push((*id, PROJECTILE_ANNOUNCEMENT_SENDS));
+            }
+            live
+        });
+
+        spawns.clear();
+        let live = &self.live;
+        self.spawning.retain_mut(|(projectile, id, sends_left)| {
+            if !live.contains_key(projectile) {
+                return false;
+            }
+            if spawns.len() < MAX_PROJECTILE_SPAWNS_PER_MSG {
+                match Self::spawn_update(s, *projectile, *id) {
+                    Some(spawn) => spawns.push(spawn),
+                    None => return false,
+                }
+                *sends_left -= 1;
+            }
+            *sends_left > 0
+        });
+
+        despawns.clear();
+        self.despawning.retain_mut(|(id, sends_left)| {
+            if despawns.len() < MAX_PROJECTILE_DESPAWNS_PER_MSG {
+                despawns.push(ProjectileDespawnUpdate { id: *id });
+                *sends_left -= 1;
+            }
+            *sends_left > 0
+        });
+    }
+
+    /// The announcement of a projectile's spawn, where it is now.
+    fn spawn_update(s: &World, projectile: Entity, id: u32) -> Option<ProjectileSpawnUpdate> {
+        let mut query = s
+            .hecs_world
+            .query_one::<(&SpatialHierarchyNode, &Velocity, &Lifetime, &Drawable)>(projectile)
+            .ok()?;
+        let (spatial, velocity, lifetime, drawable) = query.get()?;
+        Some(ProjectileSpawnUpdate::new(
+            id,
+            drawable.gfx,
+            spatial.get_pos(),
+            velocity.linear,
+            lifetime.remaining,
+        ))
+    }
 }
 
+/// Apply the latest update from the server and send it our controller state,
+/// returning whether anything was heard from the server.
 async fn pump_connection_as_client(
     s: &mut World,
     controllers: &[InputState],
-) -> Result<(), PluginError> {
+    replicated_projectiles: &mut HashMap<u32, Entity>,
+    snapshots: &mut ReceivedSnapshots,
+    buffers: &mut WireBuffers,
+) -> Result<bool, PluginError> {
     let logger = s.logger.sub("pump_connection_as_client");
-    let mut last_pkt = None;
 
-    // read until we timeout with 0ms, because we want to know only the very latest
-    // packet
-    let data = 'recv: loop {
-        match s
-            .connection
-            .as_mut()
-            .unwrap()
-            .recv_with_timeout(Duration::from_millis(0))
-            .await
-        {
-            Ok(pkt) => {
-                last_pkt = Some(pkt);
+    // Only the very latest packet matters. If nothing has arrived yet, still
+    // send our controller state.
+    let data = latest_message(s.connection.as_mut().unwrap().as_mut()).await?;
+    let server_build = data
+        .as_ref()
+        .and_then(|data| data.try_ref().ok())
+        .and_then(|msg| msg.payload.strip_prefix(BUILD_MISMATCH))
+        .map(|rest| BuildInfo::decode(rest).unwrap_or_default());
+    if let Some(server_build) = server_build {
+        return Err(PluginError::BuildMismatch(server_build));
+    }
+
+    let received = data.is_some();
+    let update = &mut buffers.server_update;
+    let (decompressed_updates, spawns, despawns, remote_slice, server_time) = match data {
+        Some(data) => {
+            wire::decompress_world_updates(
+                &data.try_ref().map_err(WorldError::UpdateFromBytes)?.payload,
+                &mut s.compression_stats,
+                update,
+            )?;
+            s.clock.sync(update.server_time, Instant::now());
+            // Played subject to the platform's rate limit.
+            s.local_rumbles
+                .extend(update.haptics.iter().filter_map(HapticUpdate::rumble));
+            // Reconstruct the snapshot from the one it's a delta against.
+            // Those arriving out of order are older than what we have, unless
+            // the server has started over, sending everything from snapshot 1.
+            if update.baseline == NO_SNAPSHOT && update.snapshot <= snapshots.latest {
+                *snapshots = ReceivedSnapshots::default();
             }
-            Err(network::RpcError::Receive(err)) => {
-                if err.kind() == std::io::ErrorKind::TimedOut {
-                    if let Some(last_pkt) = last_pkt {
-                        break 'recv last_pkt;
-                    }
-                } else {
-                    return Err(PluginError::World(WorldError::Network(
-                        network::RpcError::Receive(err),
-                    )));
+            // Projectiles announced in an update older than what we have may
+            // have been despawned since.
+            let stale = update.snapshot < snapshots.latest;
+            let mut decoded = None;
+            match snapshots.received.get(update.baseline) {
+                Some(_) if update.snapshot <= snapshots.latest => {}
+                Some(baseline) => {
+                    update.apply(baseline, &mut buffers.snapshot)?;
+                    buffers.snapshot = snapshots
+                        .received
+                        .push(update.snapshot, std::mem::take(&mut buffers.snapshot));
+                    snapshots.latest = update.snapshot;
+                    decoded = snapshots.received.get(update.snapshot);
                 }
+                None => warn!(
+                    logger,
+                    "snapshot {} is a delta against {}, which is no longer kept",
+                    update.snapshot,
+                    update.baseline
+                ),
             }
-            Err(other) => {
-                return Err(PluginError::World(WorldError::Network(other)));
-            }
+            (
+                decoded.unwrap_or_default(),
+                if stale { &[][..] } else { &update.spawns[..] },
+                &update.despawns[..],
+                update.slice,
+                update.server_time,
+            )
         }
+        None => (&[][..], &[][..], &[][..], None, Duration::ZERO),
     };
 
-    let decompressed_updates = wire::decompress_world_updates(
-        &data.try_ref().map_err(WorldError::UpdateFromBytes)?.payload,
-    )?;
+    // Spawn announced projectiles, which then move on their own, and despawn
+    // those the server despawned early, such as on hitting something. Forget
+    // the ones that have run out their lifetime, the server won't announce
+    // them again.
+    replicated_projectiles.retain(|_, projectile| s.is_live(*projectile));
+    for spawn in spawns {
+        let id = spawn.id;
+        if replicated_projectiles.contains_key(&id) {
+            continue;
+        }
+        let gfx_prefab = match Entity::from_bits(spawn.gfx_bits) {
+            Some(gfx_prefab) => gfx_prefab,
+            None => {
+                error!(logger, "projectile spawn with invalid graphic");
+                continue;
+            }
+        };
+        let velocity = spawn.velocity();
+        let projectile = match s.spawn_projectile(ProjectileSpawn {
+            origin: spawn.position(),
+            direction: velocity,
+            speed: velocity.length(),
+            lifetime: spawn.remaining(),
+            gfx_prefab,
+            // Only the server deals damage.
+            damage: 0,
+            owner: None,
+        }) {
+            Ok(projectile) => projectile,
+            // The world has told the user it's full, announcements repeated
+            // after there's room again will still be spawned.
+            Err(_) => continue,
+        };
+        replicated_projectiles.insert(id, projectile);
+    }
+    for despawn in despawns {
+        let id = despawn.id;
+        if let Some(projectile) = replicated_projectiles.remove(&id) {
+            // Live, it was just retained.
+            s.despawn(projectile).unwrap();
+        }
+    }
 
-    // Update entities in world from decompressed updates.
+    // Update entities in world from decompressed updates. Those that don't
+    // snap to each update are buffered, and moved every frame by the world
+    // update system.
     // TODO: support mapping of entities between views of the world, as entities
     // could vary!
-    for wire::EntityUpdate {
-        entity_bits,
-        pos: position,
-        y_rot: y_rotation,
-    } in decompressed_updates
-    {
-        let entity = Entity::from_bits(entity_bits).expect("unable to from_bits Entity");
+    let mut unbuffered = Vec::new();
+    for update in decompressed_updates {
+        let entity = Entity::from_bits(update.entity_bits).expect("unable to from_bits Entity");
+        let policy = s
+            .hecs_world
+            .get::<&ReplicationPolicy>(entity)
+            .map_or(s.config.replication, |policy| *policy);
+        if policy != ReplicationPolicy::Snap {
+            let sample = ReplicationSample {
+                server_time,
+                position: update.position(),
+                rotation: update.rotation(),
+            };
+            match s.hecs_world.get::<&mut ReplicationBuffer>(entity) {
+                Ok(mut buffer) => buffer.push(sample),
+                Err(_) => unbuffered.push((entity, sample)),
+            }
+            continue;
+        }
         match s.hecs_world.get::<&mut SpatialHierarchyNode>(entity) {
             Ok(mut spatial) => {
-                spatial.local_rotate(Vec3::new(0.0, y_rotation, 0.0));
-                spatial.translate(position);
+                spatial.set_translation_rotation(update.position(), update.rotation());
             }
             Err(err) => error!(logger, "error getting entity {:?}", err),
         }
     }
+    for (entity, sample) in unbuffered {
+        let mut buffer = ReplicationBuffer::default();
+        buffer.push(sample);
+        if let Err(err) = s.hecs_world.insert_one(entity, buffer) {
+            error!(logger, "error buffering updates for {entity:?}: {err}");
+        }
+    }
 
-    let mut msg_bytes = vec![];
+    // Hash the group the server did, now that the update is applied, and
+    // send ours back for the server to compare too.
+    let local_slice = remote_slice.map(|remote| {
+        let local = replicated_slice(s, remote.update, remote.group, remote.groups);
+        s.divergence.hashed(local);
+        compare_slice(s, &remote, "server");
+        local
+    });
 
     // TODO: support more controllers in another manner
-    let mut controllers_limited: [InputState; 2] = Default::default();
-    controllers_limited.copy_from_slice(controllers);
-
-    let controller_state_bytes = bytemuck::bytes_of(&controllers_limited);
-    let len = controller_state_bytes.len().min(PAYLOAD_LEN);
-    msg_bytes.extend(bytemuck::bytes_of(&(len as u16)));
-    msg_bytes.extend(controller_state_bytes);
+    wire::encode_input_states(&controllers[..2], &mut buffers.input_states);
+    let len = buffers.input_states.len().min(PAYLOAD_LEN);
+    buffers.message.clear();
+    buffers.message.extend(bytemuck::bytes_of(&(len as u16)));
+    buffers.message.extend_from_slice(&buffers.input_states);
+    buffers
+        .message
+        .extend_from_slice(bytemuck::bytes_of(&SliceChecksumUpdate::new(local_slice)));
+    wire::write_acked_snapshot(snapshots.latest, &mut buffers.message);
 
     // TODO: make use of this result properly
-    let _ = s.connection.as_mut().unwrap().send(&msg_bytes).await;
-    Ok(())
+    let _ = s.connection.as_mut().unwrap().send(&buffers.message).await;
+    Ok(received)
 }
 
 pub mod wire {
 
+    use std::f32::consts::FRAC_1_SQRT_2;
+
     use bytemuck::{Pod, Zeroable};
+    use codec::{BitReader, BitWriter, CodecError};
+    use input::haptics::{Rumble, RumblePattern};
 
     use super::*;
 
+    /// Half the extent of the cube, centered on the origin, that positions are
+    /// quantized within. 16 bits over 512 units is a resolution of ~8mm.
+    pub const POSITION_REGION_HALF_EXTENT: f32 = 256.0;
+
+    /// Bits per component of a smallest-three compressed quaternion.
+    const QUAT_COMPONENT_BITS: u32 = 10;
+    const QUAT_COMPONENT_MASK: u32 = (1 << QUAT_COMPONENT_BITS) - 1;
+
+    /// The id of no snapshot, the empty baseline of a client that hasn't
+    /// received one. A server's snapshots are numbered from 1.
+    pub const NO_SNAPSHOT: u32 = 0;
+    /// Bits of a zigzag encoded step along an axis, see
+    /// `encode_snapshot_delta`.
+    const SHORT_MOVE_BITS: u32 = 7;
+
     /// Entity network update.
-    #[derive(Debug, Copy, Clone, Pod, Zeroable)]
-    #[repr(C)]
+    ///
+    /// Position is quantized to 16 bits per axis within
+    /// `POSITION_REGION_HALF_EXTENT`, rotation is compressed with
+    /// `compress_quat`, for 10 bytes of transform per entity.
+    #[derive(Debug, Copy, Clone, PartialEq, Eq, Pod, Zeroable)]
+    #[repr(C, packed)]
     pub struct EntityUpdate {
         pub entity_bits: u64,
-        pub pos: Vec3,
-        pub y_rot: f32,
+        pub pos: [u16; 3],
+        pub rot: u32,
     }
 
     impl EntityUpdate {
-        pub fn new(entity: Entity, pos: Vec3, y_rot: f32) -> Self {
+        pub fn new(entity: Entity, pos: Vec3, rot: Quat) -> Self {
             Self {
                 entity_bits: entity.to_bits().into(),
-                pos,
-                y_rot,
+                pos: quantize_position(pos),
+                rot: compress_quat(rot),
+            }
+        }
+
+        pub fn position(&self) -> Vec3 {
+            dequantize_position(self.pos)
+        }
+
+        pub fn rotation(&self) -> Quat {
+            decompress_quat(self.rot)
+        }
+    }
+
+    /// Quantize a position to 16 bits per axis, clamping to the region.
+    pub fn quantize_position(pos: Vec3) -> [u16; 3] {
+        let quantize = |v: f32| {
+            codec::quantize(
+                v,
+                -POSITION_REGION_HALF_EXTENT,
+                POSITION_REGION_HALF_EXTENT,
+                16,
+            ) as u16
+        };
+        [quantize(pos.x), quantize(pos.y), quantize(pos.z)]
+    }
+
+    pub fn dequantize_position(pos: [u16; 3]) -> Vec3 {
+        let dequantize = |v: u16| {
+            codec::dequantize(
+                v as u32,
+                -POSITION_REGION_HALF_EXTENT,
+                POSITION_REGION_HALF_EXTENT,
+                16,
+            )
+        };
+        Vec3::new(dequantize(pos[0]), dequantize(pos[1]), dequantize(pos[2]))
+    }
+
+    /// Compress a unit quaternion with the "smallest three" method: the index
+    /// of the largest component is stored in the top two bits, followed by the
+    /// other three components at 10 bits each. The largest component is
+    /// recovered from the unit length constraint, and its sign made positive
+    /// by negating the quaternion (q and -q are the same rotation).
+    pub fn compress_quat(rot: Quat) -> u32 {
+        let rot = rot.normalize();
+        let components = rot.to_array();
+        let largest = components.iter().enumerate().fold(0, |largest, (i, c)| {
+            if c.abs() > components[largest].abs() {
+                i
+            } else {
+                largest
+            }
+        });
+        let sign = if components[largest] < 0.0 { -1.0 } else { 1.0 };
+
+        let mut packed = largest as u32;
+        for (i, c) in components.iter().enumerate() {
+            if i == largest {
+                continue;
+            }
+            let quantized =
+                codec::quantize(c * sign, -FRAC_1_SQRT_2, FRAC_1_SQRT_2, QUAT_COMPONENT_BITS);
+            packed = (packed << QUAT_COMPONENT_BITS) | quantized;
+        }
+        packed
+    }
+
+    pub fn decompress_quat(packed: u32) -> Quat {
+        let largest = (packed >> (QUAT_COMPONENT_BITS * 3)) as usize & 0b11;
+        let mut components = [0.0f32; 4];
+        let mut shift = QUAT_COMPONENT_BITS * 3;
+        let mut sum_squares = 0.0;
+        for (i, c) in components.iter_mut().enumerate() {
+            if i == largest {
+                continue;
+            }
+            shift -= QUAT_COMPONENT_BITS;
+            let quantized = (packed >> shift) & QUAT_COMPONENT_MASK;
+            *c = codec::dequantize(
+                quantized,
+                -FRAC_1_SQRT_2,
+                FRAC_1_SQRT_2,
+                QUAT_COMPONENT_BITS,
+            );
+            sum_squares += *c * *c;
+        }
+        components[largest] = (1.0 - sum_squares).max(0.0).sqrt();
+        Quat::from_array(components).normalize()
+    }
+
+    /// Bit-pack controller states: for each controller its id and button bits,
+    /// then each axis as a presence bit followed by its value if non-zero.
+    /// Idle controllers are 31 bits rather than 10 bytes. Written into `out`,
+    /// replacing what's there.
+    pub fn encode_input_states(states: &[InputState], out: &mut Vec<u8>) {
+        let mut writer = BitWriter::with_buffer(std::mem::take(out));
+        writer.write_bits(states.len() as u32, 8);
+        for state in states {
+            writer.write_bits(state.id() as u32, 8);
+            writer.write_bits(state.buttons() as u32, 16);
+            for axis in state.axis_values() {
+                writer.write_bool(axis != 0);
+                if axis != 0 {
+                    writer.write_bits(axis as u8 as u32, 8);
+                }
+            }
+        }
+        *out = writer.finish();
+    }
+
+    /// Decode controller states written by `encode_input_states` into
+    /// `states`, returning how many there were. States past the end of
+    /// `states` are read and dropped.
+    pub fn decode_input_states(
+        bytes: &[u8],
+        states: &mut [InputState],
+    ) -> Result<usize, CodecError> {
+        let mut reader = BitReader::new(bytes);
+        let count = reader.read_bits(8)? as usize;
+        for i in 0..count {
+            let id = reader.read_bits(8)? as u8;
+            let buttons = reader.read_bits(16)? as u16;
+            let mut axes = [0i8; 7];
+            for axis in axes.iter_mut() {
+                if reader.read_bool()? {
+                    *axis = reader.read_bits(8)? as u8 as i8;
+                }
+            }
+            if let Some(state) = states.get_mut(i) {
+                *state = InputState::from_parts(id, axes, buttons);
+            }
+        }
+        Ok(count)
+    }
+
+    /// A projectile spawned on the server, announced in a few updates in a row
+    /// in case some are lost. Carries where the projectile is as of the update
+    /// rather than where it was fired from, so clients that hear of it late
+    /// don't draw it behind.
+    #[derive(Debug, Copy, Clone, Pod, Zeroable)]
+    #[repr(C, packed)]
+    pub struct ProjectileSpawnUpdate {
+        /// The server's id for the projectile, from 1, rather than its entity.
+        pub id: u32,
+        pub gfx_bits: u64,
+        pub pos: [f32; 3],
+        pub vel: [f32; 3],
+        pub remaining_millis: u32,
+    }
+
+    impl ProjectileSpawnUpdate {
+        pub fn new(id: u32, gfx_prefab: Entity, pos: Vec3, vel: Vec3, remaining: Duration) -> Self {
+            Self {
+                id,
+                gfx_bits: gfx_prefab.to_bits().into(),
+                pos: pos.to_array(),
+                vel: vel.to_array(),
+                remaining_millis: remaining.as_millis().min(u32::MAX as u128) as u32,
             }
         }
+
+        pub fn position(&self) -> Vec3 {
+            Vec3::from_array(self.pos)
+        }
+
+        pub fn velocity(&self) -> Vec3 {
+            Vec3::from_array(self.vel)
+        }
+
+        pub fn remaining(&self) -> Duration {
+            Duration::from_millis(self.remaining_millis.into())
+        }
     }
 
-    const ZSTD_LEVEL: i32 = 3;
+    /// A projectile despawned on the server, such as on hitting something,
+    /// announced like its spawn.
+    #[derive(Debug, Copy, Clone, Pod, Zeroable)]
+    #[repr(C, packed)]
+    pub struct ProjectileDespawnUpdate {
+        /// The id the projectile's spawn was announced with.
+        pub id: u32,
+    }
 
-    /// Compress an update with zstd.
-    pub(crate) fn compress_world_updates(values: &[EntityUpdate]) -> Result<Vec<u8>, PluginError> {
-        let mut sized: [EntityUpdate; NUM_UPDATES_PER_MSG as usize] =
-            [EntityUpdate::new(Entity::DANGLING, Vec3::ZERO, 0.0); NUM_UPDATES_PER_MSG as usize];
-        sized.copy_from_slice(values);
-        let mut compressed_bytes = vec![];
-        let read_bytes = bytemuck::bytes_of(&sized);
-        let encoded =
-            zstd::encode_all(read_bytes, ZSTD_LEVEL).map_err(WorldError::UpdateCompression)?;
-        let len = encoded.len();
-        let len = len.min(PAYLOAD_LEN) as u16;
-        let len = bytemuck::bytes_of(&len);
-        compressed_bytes.extend(len);
-        compressed_bytes.extend(encoded);
-        Ok(compressed_bytes)
+    /// A rumble for the client's controllers, see `World::rumble`.
+    #[derive(Debug, Copy, Clone, Pod, Zeroable)]
+    #[repr(C)]
+    pub struct HapticUpdate {
+        pub pattern: u8,
+        pub strength: u8,
     }
 
-    /// Decompress an update using zstd.
-    pub(crate) fn decompress_world_updates(
+    impl HapticUpdate {
+        pub fn new(rumble: Rumble) -> Self {
+            Self {
+                pattern: rumble.pattern.id(),
+                strength: codec::quantize(rumble.strength, 0.0, 1.0, 8) as u8,
+            }
+        }
+
+        /// The rumble, or None for a pattern this build doesn't know.
+        pub fn rumble(&self) -> Option<Rumble> {
+            let pattern = RumblePattern::from_id(self.pattern)?;
+            Some(Rumble::new(
+                pattern,
+                codec::dequantize(self.strength.into(), 0.0, 1.0, 8),
+            ))
+        }
+    }
+
+    /// A checksum of one group of entities' replicated state, see
+    /// `world::snapshot::SliceChecksum`. Sent by the server with each update
+    /// and by clients with their input, for the group the server chose. All
+    /// zeroes, as in padding, is no checksum.
+    #[derive(Debug, Copy, Clone, Default, Pod, Zeroable)]
+    #[repr(C, packed)]
+    pub struct SliceChecksumUpdate {
+        pub update: u64,
+        pub checksum: u64,
+        pub group: u16,
+        pub groups: u16,
+        pub entities: u16,
+    }
+
+    impl SliceChecksumUpdate {
+        pub fn new(slice: Option<SliceChecksum>) -> Self {
+            slice.map_or_else(Self::default, |slice| Self {
+                update: slice.update,
+                checksum: slice.checksum,
+                group: slice.group,
+                groups: slice.groups,
+                entities: slice.entities,
+            })
+        }
+
+        pub fn slice(&self) -> Option<SliceChecksum> {
+            let (group, groups) = (self.group, self.groups);
+            (groups != 0 && group < groups).then_some(SliceChecksum {
+                update: self.update,
+                group,
+                groups,
+                entities: self.entities,
+                checksum: self.checksum,
+            })
+        }
+
+        /// Read from the start of `bytes`, if there's one there.
+        pub fn read(bytes: &[u8]) -> Option<SliceChecksum> {
+            let bytes = bytes.get(..std::mem::size_of::<Self>())?;
+            bytemuck::pod_read_unaligned::<Self>(bytes).slice()
+        }
+    }
+
+    /// An update from the server, decoded by `decompress_world_updates` into
+    /// buffers kept from one update to the next.
+    #[derive(Debug, Default)]
+    pub struct ServerUpdate {
+        /// Server time the update was sent at.
+        pub server_time: Duration,
+        /// Id of the snapshot the update carries.
+        pub snapshot: u32,
+        /// Id of the snapshot the update is a delta against, see `apply`.
+        pub baseline: u32,
+        /// The decompressed delta.
+        delta: Vec<u8>,
+        pub spawns: Vec<ProjectileSpawnUpdate>,
+        pub despawns: Vec<ProjectileDespawnUpdate>,
+        pub haptics: Vec<HapticUpdate>,
+        /// The server's checksum of a group of entities, as of the update.
+        pub slice: Option<SliceChecksum>,
+    }
+
+    impl ServerUpdate {
+        /// The update's snapshot, from `baseline`, the snapshot with the id
+        /// `self.baseline`. Written into `snapshot`, replacing what's there.
+        pub fn apply(
+            &self,
+            baseline: &[EntityUpdate],
+            snapshot: &mut Vec<EntityUpdate>,
+        ) -> Result<(), PluginError> {
+            decode_snapshot_delta(&self.delta, baseline, snapshot)?;
+            Ok(())
+        }
+    }
+
+    /// Buffers messages are encoded into and decoded from, kept so that
+    /// syncing doesn't allocate each tick once they've grown to size.
+    #[derive(Debug, Default)]
+    pub struct WireBuffers {
+        /// Every replicated entity, sorted by entity.
+        pub entity_updates: Vec<EntityUpdate>,
+        /// A snapshot being sent or received, see `SnapshotHistory::push`.
+        pub snapshot: Vec<EntityUpdate>,
+        pub delta: Vec<u8>,
+        pub spawns: Vec<ProjectileSpawnUpdate>,
+        pub despawns: Vec<ProjectileDespawnUpdate>,
+        pub haptics: Vec<HapticUpdate>,
+        pub input_states: Vec<u8>,
+        /// The message being sent.
+        pub message: Vec<u8>,
+        pub server_update: ServerUpdate,
+    }
+
+    const SERVER_TIME_LEN: usize = std::mem::size_of::<u64>();
+
+    /// Compress a snapshot delta, see `encode_snapshot_delta`, with
+    /// `compression`, after the server time in microseconds and the codec's
+    /// id. Projectile spawns follow uncompressed, after their count, then
+    /// projectile despawns and haptic events the same way, then the slice
+    /// checksum. Written into `out`, replacing what's there.
+    // Public only for the snapshot benches.
+    #[doc(hidden)]
+    #[allow(clippy::too_many_arguments)]
+    pub fn compress_world_updates(
+        server_time: Duration,
+        compression: Compression,
+        stats: &mut CompressionStats,
+        delta: &[u8],
+        spawns: &[ProjectileSpawnUpdate],
+        despawns: &[ProjectileDespawnUpdate],
+        haptics: &[HapticUpdate],
+        slice: Option<SliceChecksum>,
+        out: &mut Vec<u8>,
+    ) -> Result<(), PluginError> {
+        out.clear();
+        out.extend((server_time.as_micros() as u64).to_le_bytes());
+        out.push(compression.id());
+        // Length of the compressed updates, filled in once they're written.
+        let len_at = out.len();
+        out.extend([0, 0]);
+        stats
+            .compress_into(compression, delta, out)
+            .map_err(WorldError::UpdateCompression)?;
+        let len = (out.len() - len_at - 2).min(PAYLOAD_LEN) as u16;
+        out[len_at..len_at + 2].copy_from_slice(&len.to_le_bytes());
+        let spawns = &spawns[..spawns.len().min(MAX_PROJECTILE_SPAWNS_PER_MSG)];
+        out.push(spawns.len() as u8);
+        out.extend_from_slice(bytemuck::cast_slice(spawns));
+        let despawns = &despawns[..despawns.len().min(MAX_PROJECTILE_DESPAWNS_PER_MSG)];
+        out.push(despawns.len() as u8);
+        out.extend_from_slice(bytemuck::cast_slice(despawns));
+        let haptics = &haptics[..haptics.len().min(MAX_HAPTICS_PER_MSG)];
+        out.push(haptics.len() as u8);
+        out.extend_from_slice(bytemuck::cast_slice(haptics));
+        out.extend_from_slice(bytemuck::bytes_of(&SliceChecksumUpdate::new(slice)));
+        Ok(())
+    }
+
+    /// Decompress an update with the codec it's tagged with into `update`.
+    // Public only for the snapshot benches.
+    #[doc(hidden)]
+    pub fn decompress_world_updates(
         compressed: &[u8],
-    ) -> Result<Vec<EntityUpdate>, PluginError> {
-        let mut decoded_bytes = vec![];
-        let len: &u16 = bytemuck::from_bytes(&compressed[0..2]);
-        let len = *len;
-        let len = len.min(PAYLOAD_LEN as u16);
-        let decoded = zstd::decode_all(&compressed[2..(2 + len as usize).min(compressed.len())])
+        stats: &mut CompressionStats,
+        update: &mut ServerUpdate,
+    ) -> Result<(), PluginError> {
+        let server_time: [u8; SERVER_TIME_LEN] = compressed
+            .get(..SERVER_TIME_LEN)
+            .and_then(|bytes| bytes.try_into().ok())
+            .ok_or_else(|| cut_off("server time"))?;
+        let server_time = Duration::from_micros(u64::from_le_bytes(server_time));
+        let (&codec, compressed) = compressed[SERVER_TIME_LEN..]
+            .split_first()
+            .ok_or_else(|| cut_off("codec"))?;
+        let compression = Compression::from_id(codec).ok_or_else(|| {
+            WorldError::UpdateDecompression(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unknown compression codec {codec}"),
+            ))
+        })?;
+        // Right after the codec, so it's read without relying on alignment.
+        let len: [u8; 2] = compressed
+            .get(..2)
+            .and_then(|bytes| bytes.try_into().ok())
+            .ok_or_else(|| cut_off("length"))?;
+        let len = u16::from_le_bytes(len).min(PAYLOAD_LEN as u16);
+        let encoded_end = (2 + len as usize).min(compressed.len());
+        update.delta.clear();
+        stats
+            .decompress_into(compression, &compressed[2..encoded_end], &mut update.delta)
             .map_err(WorldError::UpdateDecompression)?;
-        decoded_bytes.extend(decoded);
-        let updates: &[EntityUpdate; NUM_UPDATES_PER_MSG as usize] =
-            bytemuck::try_from_bytes(&decoded_bytes)
-                .map_err(|err| PluginError::FromBytes(err, decoded_bytes.len()))?;
-        Ok(updates.to_vec())
+        (update.snapshot, update.baseline) = snapshot_delta_ids(&update.delta)?;
+        update.server_time = server_time;
+
+        // Payloads are padded with zeroes, which reads as no spawns, despawns,
+        // haptics or slice checksum.
+        let (spawns, rest) = read_counted::<ProjectileSpawnUpdate>(&compressed[encoded_end..])?;
+        update.spawns.clear();
+        update.spawns.extend_from_slice(spawns);
+        let (despawns, rest) = read_counted::<ProjectileDespawnUpdate>(rest)?;
+        update.despawns.clear();
+        update.despawns.extend_from_slice(despawns);
+        let (haptics, rest) = read_counted::<HapticUpdate>(rest)?;
+        update.haptics.clear();
+        update.haptics.extend_from_slice(haptics);
+        update.slice = SliceChecksumUpdate::read(rest);
+        Ok(())
+    }
+
+    /// An update too short to hold its `what`, such as a truncated datagram.
+    fn cut_off(what: &str) -> PluginError {
+        WorldError::UpdateDecompression(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            format!("update cut off before its {what}"),
+        ))
+        .into()
+    }
+
+    /// What a server's packet carried, for tracing below its reliable
+    /// channel: updates out, inputs in.
+    pub fn classify_as_server(direction: Direction, payload: &[u8]) -> PayloadType {
+        match direction {
+            Direction::Sent => payload_type(PayloadKind::Update, payload),
+            Direction::Received => payload_type(PayloadKind::Input, payload),
+        }
+    }
+
+    /// What a client's packet carried, see `classify_as_server`.
+    pub fn classify_as_client(direction: Direction, payload: &[u8]) -> PayloadType {
+        match direction {
+            Direction::Sent => payload_type(PayloadKind::Input, payload),
+            Direction::Received => payload_type(PayloadKind::Update, payload),
+        }
+    }
+
+    fn payload_type(kind: PayloadKind, payload: &[u8]) -> PayloadType {
+        let (fragments, payload) = match reliable::split_payload(payload) {
+            Some(split) => split,
+            None => return PayloadType::UNKNOWN,
+        };
+        // Updates are tagged with their codec after the server time.
+        let codec = match kind {
+            PayloadKind::Update => payload
+                .get(SERVER_TIME_LEN)
+                .and_then(|&codec| Compression::from_id(codec)),
+            _ => None,
+        };
+        PayloadType {
+            // Packets carrying only reliable fragments.
+            kind: if payload.iter().all(|&byte| byte == 0) {
+                PayloadKind::Unknown
+            } else {
+                kind
+            },
+            codec,
+            reliable_len: fragments.len() as u16,
+        }
+    }
+
+    /// Read a count and that many `T`s in place, returning them and the
+    /// bytes after.
+    fn read_counted<T: Pod>(bytes: &[u8]) -> Result<(&[T], &[u8]), PluginError> {
+        let (&count, bytes) = match bytes.split_first() {
+            Some(split) => split,
+            None => return Ok((&[], bytes)),
+        };
+        let len = (count as usize * std::mem::size_of::<T>()).min(bytes.len());
+        // Whole values only, of a truncated payload.
+        let len = len - len % std::mem::size_of::<T>();
+        let (values, rest) = bytes.split_at(len);
+        let values =
+            bytemuck::try_cast_slice(values).map_err(|err| PluginError::FromBytes(err, len))?;
+        Ok((values, rest))
+    }
+
+    /// A difference between two snapshots.
+    enum Change<'a> {
+        Removed(&'a EntityUpdate),
+        /// New if there's no update in the baseline, otherwise changed from it.
+        Changed(Option<&'a EntityUpdate>, &'a EntityUpdate),
+    }
+
+    impl Change<'_> {
+        fn entity_bits(&self) -> u64 {
+            match self {
+                Change::Removed(update) | Change::Changed(_, update) => update.entity_bits,
+            }
+        }
+    }
+
+    /// The differences from `baseline` to `current`, both sorted by entity, in
+    /// order of entity.
+    fn changes<'a>(
+        baseline: &'a [EntityUpdate],
+        current: &'a [EntityUpdate],
+    ) -> impl Iterator<Item = Change<'a>> + Clone + 'a {
+        let (mut old, mut new) = (0, 0);
+        std::iter::from_fn(move || loop {
+            let change = match (baseline.get(old), current.get(new)) {
+                (Some(before), Some(after)) if before.entity_bits == after.entity_bits => {
+                    old += 1;
+                    new += 1;
+                    if before == after {
+                        continue;
+                    }
+                    Change::Changed(Some(before), after)
+                }
+                (Some(before), Some(after)) if before.entity_bits > after.entity_bits => {
+                    new += 1;
+                    Change::Changed(None, after)
+                }
+                (Some(before), _) => {
+                    old += 1;
+                    Change::Removed(before)
+                }
+                (None, Some(after)) => {
+                    new += 1;
+                    Change::Changed(None, after)
+                }
+                (None, None) => return None,
+            };
+            return Some(change);
+        })
+    }
+
+    fn write_entity(writer: &mut BitWriter, entity_bits: u64) {
+        writer.write_bits(entity_bits as u32, 32);
+        writer.write_bits((entity_bits >> 32) as u32, 32);
+    }
+
+    fn read_entity(reader: &mut BitReader) -> Result<u64, CodecError> {
+        let low = reader.read_bits(32)?;
+        Ok(u64::from(low) | u64::from(reader.read_bits(32)?) << 32)
+    }
+
+    /// Bit-pack the changes from `baseline`, the snapshot with the id
+    /// `baseline_id`, to `current` as the snapshot `snapshot`. Both are sorted
+    /// by entity, and `NO_SNAPSHOT` is the empty baseline of a client that
+    /// hasn't acknowledged any.
+    ///
+    /// Removed entities are written first, then new and changed ones from the
+    /// entity `from` on, wrapping around, at most `MAX_UPDATES_PER_MSG` in
+    /// all. A changed entity's position is written per axis as the steps it
+    /// moved in 8 bits if it moved up to 63 of them, ~0.5m, otherwise as its
+    /// new value in 17, and its rotation only if it turned. New entities are
+    /// written as changed from the origin.
+    ///
+    /// `sent` is set to what the client has once it decodes the delta, which
+    /// is `current` unless some changes didn't fit, and the entity to start
+    /// from next time is returned, so those are written then. Written into
+    /// `out`, replacing what's there.
+    pub fn encode_snapshot_delta(
+        snapshot: u32,
+        baseline_id: u32,
+        baseline: &[EntityUpdate],
+        current: &[EntityUpdate],
+        from: u64,
+        sent: &mut Vec<EntityUpdate>,
+        out: &mut Vec<u8>,
+    ) -> u64 {
+        let removed = changes(baseline, current)
+            .filter(|change| matches!(change, Change::Removed(_)))
+            .take(MAX_UPDATES_PER_MSG)
+            .count();
+        let changed =
+            changes(baseline, current).filter(|change| matches!(change, Change::Changed(..)));
+        let (mut changed_from, mut changed_before) =
+            changed.clone().fold((0, 0), |(from_on, before), change| {
+                if change.entity_bits() >= from {
+                    (from_on + 1, before)
+                } else {
+                    (from_on, before + 1)
+                }
+            });
+        changed_from = changed_from.min(MAX_UPDATES_PER_MSG - removed);
+        changed_before = changed_before.min(MAX_UPDATES_PER_MSG - removed - changed_from);
+        let written = changed
+            .clone()
+            .filter(|change| change.entity_bits() >= from)
+            .take(changed_from)
+            .chain(
+                changed
+                    .filter(|change| change.entity_bits() < from)
+                    .take(changed_before),
+            );
+        let next = written
+            .clone()
+            .last()
+            .map_or(from, |change| change.entity_bits().wrapping_add(1));
+
+        let mut writer = BitWriter::with_buffer(std::mem::take(out));
+        writer.write_bits(snapshot, 32);
+        writer.write_bits(baseline_id, 32);
+        writer.write_bits(removed as u32, 16);
+        for change in changes(baseline, current)
+            .filter(|change| matches!(change, Change::Removed(_)))
+            .take(removed)
+        {
+            write_entity(&mut writer, change.entity_bits());
+        }
+        writer.write_bits((changed_from + changed_before) as u32, 16);
+        for change in written {
+            if let Change::Changed(before, after) = change {
+                write_changed(&mut writer, before, after);
+            }
+        }
+        *out = writer.finish();
+
+        // What the client has: the baseline, with the changes written.
+        sent.clear();
+        let mut baseline_at = 0;
+        let (mut removed_left, mut from_left, mut before_left) =
+            (removed, changed_from, changed_before);
+        for change in changes(baseline, current) {
+            let entity_bits = change.entity_bits();
+            while let Some(unchanged) = baseline
+                .get(baseline_at)
+                .filter(|update| update.entity_bits < entity_bits)
+            {
+                sent.push(*unchanged);
+                baseline_at += 1;
+            }
+            match change {
+                Change::Removed(before) => {
+                    baseline_at += 1;
+                    if removed_left > 0 {
+                        removed_left -= 1;
+                    } else {
+                        sent.push(*before);
+                    }
+                }
+                Change::Changed(before, after) => {
+                    if before.is_some() {
+                        baseline_at += 1;
+                    }
+                    let left = if after.entity_bits >= from {
+                        &mut from_left
+                    } else {
+                        &mut before_left
+                    };
+                    if *left > 0 {
+                        *left -= 1;
+                        sent.push(*after);
+                    } else if let Some(before) = before {
+                        sent.push(*before);
+                    }
+                }
+            }
+        }
+        sent.extend_from_slice(&baseline[baseline_at..]);
+        next
+    }
+
+    fn write_changed(writer: &mut BitWriter, before: Option<&EntityUpdate>, after: &EntityUpdate) {
+        let (before_pos, before_rot) =
+            before.map_or(([0; 3], 0), |before| (before.pos, before.rot));
+        let (after_pos, after_rot) = (after.pos, after.rot);
+        write_entity(writer, after.entity_bits);
+        writer.write_bool(before_pos != after_pos);
+        if before_pos != after_pos {
+            for (before, after) in before_pos.into_iter().zip(after_pos) {
+                let step = codec::zigzag_encode(i64::from(after) - i64::from(before));
+                let short = step < 1 << SHORT_MOVE_BITS;
+                writer.write_bool(short);
+                if short {
+                    writer.write_bits(step as u32, SHORT_MOVE_BITS);
+                } else {
+                    writer.write_bits(after.into(), 16);
+                }
+            }
+        }
+        writer.write_bool(before_rot != after_rot);
+        if before_rot != after_rot {
+            writer.write_bits(after_rot, 32);
+        }
+    }
+
+    /// The ids of the snapshot a delta carries and of its baseline.
+    pub fn snapshot_delta_ids(bytes: &[u8]) -> Result<(u32, u32), CodecError> {
+        let mut reader = BitReader::new(bytes);
+        Ok((reader.read_bits(32)?, reader.read_bits(32)?))
+    }
+
+    /// Decode a delta written by `encode_snapshot_delta` against `baseline`,
+    /// the snapshot with its baseline's id, into `snapshot`, replacing what's
+    /// there.
+    pub fn decode_snapshot_delta(
+        bytes: &[u8],
+        baseline: &[EntityUpdate],
+        snapshot: &mut Vec<EntityUpdate>,
+    ) -> Result<(), CodecError> {
+        snapshot.clear();
+        snapshot.extend_from_slice(baseline);
+        let mut reader = BitReader::new(bytes);
+        reader.read_bits(32)?;
+        reader.read_bits(32)?;
+        for _ in 0..reader.read_bits(16)? {
+            let entity_bits = read_entity(&mut reader)?;
+            if let Ok(at) = snapshot.binary_search_by_key(&entity_bits, |update| update.entity_bits)
+            {
+                snapshot.remove(at);
+            }
+        }
+        for _ in 0..reader.read_bits(16)? {
+            let entity_bits = read_entity(&mut reader)?;
+            let at = snapshot.binary_search_by_key(&entity_bits, |update| update.entity_bits);
+            let mut update = match at {
+                Ok(at) => snapshot[at],
+                Err(_) => EntityUpdate {
+                    entity_bits,
+                    pos: [0; 3],
+                    rot: 0,
+                },
+            };
+            if reader.read_bool()? {
+                let mut pos = update.pos;
+                for axis in pos.iter_mut() {
+                    *axis = if reader.read_bool()? {
+                        let step = codec::zigzag_decode(reader.read_bits(SHORT_MOVE_BITS)?.into());
+                        (i64::from(*axis) + step) as u16
+                    } else {
+                        reader.read_bits(16)? as u16
+                    };
+                }
+                update.pos = pos;
+            }
+            if reader.read_bool()? {
+                update.rot = reader.read_bits(32)?;
+            }
+            match at {
+                Ok(at) => snapshot[at] = update,
+                Err(at) => snapshot.insert(at, update),
+            }
+        }
+        Ok(())
+    }
+
+    /// The id of the last snapshot a client decoded, which it sends after its
+    /// input, see `read_acked_snapshot`.
+    pub fn write_acked_snapshot(snapshot: u32, out: &mut Vec<u8>) {
+        out.extend(snapshot.to_le_bytes());
+    }
+
+    /// The snapshot a client acknowledged in `bytes`, what's after its input,
+    /// if any. Clients older than deltas, and payloads padded short of it,
+    /// acknowledge none.
+    pub fn read_acked_snapshot(bytes: &[u8]) -> Option<u32> {
+        let bytes = bytes.get(..4)?.try_into().ok()?;
+        Some(u32::from_le_bytes(bytes)).filter(|&snapshot| snapshot != NO_SNAPSHOT)
     }
 
     #[cfg(test)]
@@ -309,39 +1981,371 @@ pub mod wire {
 
         use super::*;
 
+        fn entity_update(entity_bits: u64, pos: Vec3, rot: Quat) -> EntityUpdate {
+            EntityUpdate {
+                entity_bits,
+                pos: quantize_position(pos),
+                rot: compress_quat(rot),
+            }
+        }
+
         #[test]
         fn test_compression_roundtrip() {
-            let values = (0..NUM_UPDATES_PER_MSG)
-                .map(|i| {
-                    let wpos = Vec3::new(i as f32, i as f32, i as f32);
-                    let entity = Entity::DANGLING;
-                    EntityUpdate::new(entity, wpos, 0.0)
-                })
+            let values = (0..4)
+                .map(|i| entity_update(i, Vec3::splat(i as f32), Quat::IDENTITY))
                 .collect::<Vec<_>>();
-
-            let compressed_bytes = compress_world_updates(&values).unwrap();
+            let (mut sent, mut delta) = (Vec::new(), Vec::new());
+            encode_snapshot_delta(1, NO_SNAPSHOT, &[], &values, 0, &mut sent, &mut delta);
+
+            let server_time = Duration::from_micros(123_456_789);
+            let spawns = [ProjectileSpawnUpdate::new(
+                7,
+                Entity::DANGLING,
+                Vec3::new(1.0, 2.0, 3.0),
+                Vec3::X * 20.0,
+                Duration::from_millis(1500),
+            )];
+            let despawns = [ProjectileDespawnUpdate { id: 5 }];
+            let haptics = [HapticUpdate::new(Rumble::new(
+                RumblePattern::Heartbeat,
+                0.5,
+            ))];
+            let mut slice = SliceChecksum::new(42, 3, 16);
+            slice.add(3, &[1, 2, 3]);
+            let mut stats = CompressionStats::default();
+            let mut compressed_bytes = Vec::new();
+            compress_world_updates(
+                server_time,
+                Compression::default(),
+                &mut stats,
+                &delta,
+                &spawns,
+                &despawns,
+                &haptics,
+                Some(slice),
+                &mut compressed_bytes,
+            )
+            .unwrap();
             debug!(
                 LogLevel::Info.logger(),
                 "compressed_bytes {}",
                 compressed_bytes.len()
             );
-            let decompressed = decompress_world_updates(&compressed_bytes).unwrap();
-            assert_eq!(values.len(), decompressed.len());
+            // Sent in a fixed size payload, padded with zeroes.
+            compressed_bytes.resize(PAYLOAD_LEN, 0);
+            let mut decompressed = ServerUpdate::default();
+            decompress_world_updates(&compressed_bytes, &mut stats, &mut decompressed).unwrap();
+            assert_eq!(decompressed.server_time, server_time);
+            assert_eq!(
+                (decompressed.snapshot, decompressed.baseline),
+                (1, NO_SNAPSHOT)
+            );
+            let mut snapshot = Vec::new();
+            decompressed.apply(&[], &mut snapshot).unwrap();
+            assert_eq!(snapshot, values);
+            let decompressed_spawns = &decompressed.spawns;
+            assert_eq!(decompressed_spawns.len(), 1);
+            assert_eq!({ decompressed_spawns[0].id }, 7);
+            assert_eq!(decompressed_spawns[0].position(), spawns[0].position());
+            assert_eq!(decompressed_spawns[0].velocity(), spawns[0].velocity());
+            assert_eq!(decompressed_spawns[0].remaining(), spawns[0].remaining());
+            assert_eq!(decompressed.despawns.len(), 1);
+            assert_eq!({ decompressed.despawns[0].id }, 5);
+            assert_eq!(decompressed.haptics.len(), 1);
+            let rumble = decompressed.haptics[0].rumble().unwrap();
+            assert_eq!(rumble.pattern, RumblePattern::Heartbeat);
+            assert!((rumble.strength - 0.5).abs() < 0.01);
+            assert_eq!(decompressed.slice, Some(slice));
+
+            // Reusing the buffers replaces what was in them, without
+            // reallocating.
+            let buffer = compressed_bytes.as_ptr();
+            for compression in [Compression::None, Compression::Lz4] {
+                compress_world_updates(
+                    server_time,
+                    compression,
+                    &mut stats,
+                    &delta,
+                    &[],
+                    &[],
+                    &[],
+                    None,
+                    &mut compressed_bytes,
+                )
+                .unwrap();
+                assert_eq!(compressed_bytes.as_ptr(), buffer);
+                decompress_world_updates(&compressed_bytes, &mut stats, &mut decompressed).unwrap();
+                decompressed.apply(&[], &mut snapshot).unwrap();
+                assert_eq!(snapshot, values);
+                assert!(decompressed.spawns.is_empty());
+                assert!(decompressed.despawns.is_empty());
+                assert!(decompressed.haptics.is_empty());
+                assert!(decompressed.slice.is_none());
+            }
+            assert_eq!(stats.decompressed().count(), 3);
+            // Cut into the compressed updates, past the counts and slice.
+            let truncated = compressed_bytes.len() - 5 - std::mem::size_of::<SliceChecksumUpdate>();
+            assert!(decompress_world_updates(
+                &compressed_bytes[..truncated],
+                &mut stats,
+                &mut decompressed
+            )
+            .is_err());
+        }
+
+        #[test]
+        fn updates_decode_wherever_they_sit_in_memory() {
+            let values = (0..4)
+                .map(|i| entity_update(i, Vec3::splat(i as f32), Quat::IDENTITY))
+                .collect::<Vec<_>>();
+            let (mut sent, mut delta) = (Vec::new(), Vec::new());
+            encode_snapshot_delta(1, NO_SNAPSHOT, &[], &values, 0, &mut sent, &mut delta);
+            let mut stats = CompressionStats::default();
+            let mut compressed_bytes = Vec::new();
+            for compression in [Compression::None, Compression::Zstd(3), Compression::Lz4] {
+                compress_world_updates(
+                    Duration::from_millis(5),
+                    compression,
+                    &mut stats,
+                    &delta,
+                    &[],
+                    &[],
+                    &[],
+                    None,
+                    &mut compressed_bytes,
+                )
+                .unwrap();
+                // The length after the codec is at an odd offset, and at an
+                // even one behind a byte.
+                for offset in 0..2 {
+                    let mut payload = vec![0; offset];
+                    payload.extend_from_slice(&compressed_bytes);
+                    payload.resize(PAYLOAD_LEN + offset, 0);
+                    let mut decompressed = ServerUpdate::default();
+                    decompress_world_updates(&payload[offset..], &mut stats, &mut decompressed)
+                        .unwrap();
+                    let mut snapshot = Vec::new();
+                    decompressed.apply(&[], &mut snapshot).unwrap();
+                    assert_eq!(snapshot, values);
+                }
+            }
+        }
+
+        #[test]
+        fn short_updates_are_errors() {
+            let (mut sent, mut delta) = (Vec::new(), Vec::new());
+            encode_snapshot_delta(1, NO_SNAPSHOT, &[], &[], 0, &mut sent, &mut delta);
+            let mut stats = CompressionStats::default();
+            let mut compressed_bytes = Vec::new();
+            compress_world_updates(
+                Duration::from_millis(5),
+                Compression::None,
+                &mut stats,
+                &delta,
+                &[],
+                &[],
+                &[],
+                None,
+                &mut compressed_bytes,
+            )
+            .unwrap();
+            // Cut off in the server time, before and in the length, and
+            // before the delta.
+            let mut decompressed = ServerUpdate::default();
+            for len in 0..SERVER_TIME_LEN + 4 {
+                assert!(
+                    decompress_world_updates(
+                        &compressed_bytes[..len],
+                        &mut stats,
+                        &mut decompressed
+                    )
+                    .is_err(),
+                    "{len} bytes"
+                );
+            }
+        }
+
+        #[test]
+        fn test_snapshot_delta_roundtrip() {
+            let baseline = (0..40)
+                .map(|i| entity_update(i, Vec3::splat(i as f32), Quat::IDENTITY))
+                .collect::<Vec<_>>();
+            let mut current = baseline.clone();
+            // Moved a little, a long way, turned, removed and new.
+            current[1] = entity_update(1, Vec3::new(1.1, 0.9, 1.0), Quat::IDENTITY);
+            current[2] = entity_update(2, Vec3::new(-200.0, 2.0, 2.0), Quat::IDENTITY);
+            current[3] = entity_update(3, Vec3::splat(3.0), Quat::from_rotation_y(1.0));
+            current.remove(4);
+            current.push(entity_update(100, Vec3::X, Quat::IDENTITY));
+
+            let (mut sent, mut delta, mut decoded) = (Vec::new(), Vec::new(), Vec::new());
+            let next = encode_snapshot_delta(2, 1, &baseline, &current, 0, &mut sent, &mut delta);
+            assert_eq!(snapshot_delta_ids(&delta).unwrap(), (2, 1));
+            decode_snapshot_delta(&delta, &baseline, &mut decoded).unwrap();
+            assert_eq!(decoded, current);
+            assert_eq!(sent, current);
+            assert_eq!(next, 101);
+            // A fraction of sending every entity.
+            let full = current.len() * std::mem::size_of::<EntityUpdate>();
+            assert!(delta.len() < full / 8, "{} bytes", delta.len());
+
+            // Nothing changed is next to nothing.
+            encode_snapshot_delta(3, 2, &current, &current, next, &mut sent, &mut delta);
+            assert_eq!(delta.len(), 12);
+            assert_eq!(sent, current);
+
+            // Too many changes for one delta are sent over several, starting
+            // where the last left off.
+            let moved = current
+                .iter()
+                .map(|update| {
+                    entity_update(
+                        update.entity_bits,
+                        update.position() + Vec3::Y,
+                        update.rotation(),
+                    )
+                })
+                .collect::<Vec<_>>();
+            let mut next = 20;
+            let mut baseline = current.clone();
+            for (snapshot, moved_by_now) in [(4, 24), (5, 40)] {
+                next = encode_snapshot_delta(
+                    snapshot, 0, &baseline, &moved, next, &mut sent, &mut delta,
+                );
+                decode_snapshot_delta(&delta, &baseline, &mut decoded).unwrap();
+                assert_eq!(decoded, sent);
+                let moved_since = sent.iter().filter(|update| !current.contains(update));
+                assert_eq!(moved_since.count(), moved_by_now);
+                baseline.clone_from(&sent);
+            }
+            assert_eq!(sent, moved);
+            assert_eq!(next, 20);
+        }
+
+        #[test]
+        fn test_input_states_roundtrip() {
+            let mut states = [InputState::new(0), InputState::new(1)];
+            states[0].update_from_event(&input::InputEvent::ButtonPressed(0, input::Button::Up));
+            states[0].update_from_event(&input::InputEvent::AxisMotion(0, 3, -128));
+            states[1].update_from_event(&input::InputEvent::AxisMotion(1, 0, 127));
+
+            let mut encoded = vec![0xff; 4];
+            encode_input_states(&states, &mut encoded);
+            assert!(encoded.len() < std::mem::size_of_val(&states));
+            let mut decoded: [InputState; 2] = Default::default();
+            assert_eq!(decode_input_states(&encoded, &mut decoded).unwrap(), 2);
+            for (state, decoded) in states.iter().zip(decoded.iter()) {
+                assert_eq!(state.id(), decoded.id());
+                assert_eq!(state.buttons(), decoded.buttons());
+                assert_eq!(state.axis_values(), decoded.axis_values());
+            }
+            let mut first = [InputState::default()];
+            assert_eq!(decode_input_states(&encoded, &mut first).unwrap(), 2);
+            assert_eq!(first[0].axis_values(), states[0].axis_values());
+            assert!(decode_input_states(&encoded[..2], &mut decoded).is_err());
+        }
+
+        #[test]
+        fn test_entity_update_size() {
+            assert_eq!(std::mem::size_of::<EntityUpdate>(), 18);
+        }
+
+        #[test]
+        fn test_position_quantization() {
+            let resolution = POSITION_REGION_HALF_EXTENT * 2.0 / u16::MAX as f32;
+            for pos in [
+                Vec3::ZERO,
+                Vec3::new(10.0, -2.0, 40.0),
+                Vec3::new(-255.9, 255.9, 0.001),
+            ] {
+                let roundtrip = dequantize_position(quantize_position(pos));
+                assert!(
+                    pos.abs_diff_eq(roundtrip, resolution),
+                    "{pos} != {roundtrip}"
+                );
+            }
+            // out of region positions are clamped to the edge.
+            let clamped = dequantize_position(quantize_position(Vec3::new(1000.0, -1000.0, 0.0)));
+            assert_eq!(clamped.x, POSITION_REGION_HALF_EXTENT);
+            assert_eq!(clamped.y, -POSITION_REGION_HALF_EXTENT);
+        }
+
+        #[test]
+        fn test_quat_compression_roundtrip() {
+            let steps = 8;
+            for x in 0..steps {
+                for y in 0..steps {
+                    for z in 0..steps {
+                        let angle = |i: i32| (i as f32 / steps as f32) * std::f32::consts::TAU;
+                        let rot = Quat::from_euler(
+                            world::graphics::EULER_ROT_ORDER,
+                            angle(x),
+                            angle(y),
+                            angle(z),
+                        );
+                        let roundtrip = decompress_quat(compress_quat(rot));
+                        // q and -q are the same rotation.
+                        assert!(rot.dot(roundtrip).abs() > 0.9999, "{rot} != {roundtrip}");
+                    }
+                }
+            }
+            let identity = decompress_quat(compress_quat(Quat::IDENTITY));
+            assert!(identity.abs_diff_eq(Quat::IDENTITY, 1e-3), "{identity}");
+        }
+
+        #[test]
+        fn test_classify_traced_packets() {
+            let mut update = Vec::new();
+            compress_world_updates(
+                Duration::from_millis(5),
+                Compression::Lz4,
+                &mut CompressionStats::default(),
+                &[],
+                &[],
+                &[],
+                &[],
+                None,
+                &mut update,
+            )
+            .unwrap();
+            // Below the reliable channel, after 3 bytes of fragments, padded.
+            let mut packet = vec![3, 0, 1, 2, 3];
+            packet.extend_from_slice(&update);
+            packet.resize(PAYLOAD_LEN, 0);
+
+            let traced = classify_as_client(Direction::Received, &packet);
+            assert_eq!(traced.kind, PayloadKind::Update);
+            assert_eq!(traced.codec, Some(Compression::Lz4));
+            assert_eq!(traced.reliable_len, 3);
+            assert_eq!(
+                classify_as_server(Direction::Received, &[0, 0, 1]).kind,
+                PayloadKind::Input
+            );
+            let fragments_only = classify_as_server(Direction::Sent, &packet[..5]);
+            assert_eq!(fragments_only.kind, PayloadKind::Unknown);
+            assert_eq!(
+                classify_as_client(Direction::Sent, &[9]),
+                PayloadType::UNKNOWN
+            );
         }
     }
 }
 
 pub struct Peer {
     _id: u8, // TODO
-    seq: u16,
-    remote_seq: u16,
+    seq: SequenceNumber,
+    remote_seq: SequenceNumber,
     dest: Option<SocketAddr>,
     bytes_sent: usize,
+    packets: PacketCounts,
     socket: async_net::UdpSocket,
     pub rtt_micros: Histogram,
-    send_queue: VecDeque<(u16, Instant, bool)>,
-    recv_queue: VecDeque<(u16, Instant, bool)>,
-    own_final_ackd_sequences: Vec<u16>,
+    smoothed_rtt: Option<Duration>,
+    /// Fraction of sent packets that dropped out of the send queue unacked.
+    loss: f32,
+    send_queue: VecDeque<(SequenceNumber, Instant, bool)>,
+    recv_queue: VecDeque<(SequenceNumber, Instant, bool)>,
+    own_final_ackd_sequences: Vec<SequenceNumber>,
 }
 
 #[async_trait::async_trait]
@@ -364,7 +2368,7 @@ impl Connection for Box<Peer> {
             .await
     }
 
-    async fn send(&mut self, payload: &[u8]) -> Result<u16, RpcError> {
+    async fn send(&mut self, payload: &[u8]) -> Result<SequenceNumber, RpcError> {
         if payload.len() > PAYLOAD_LEN {
             return Err(RpcError::PayloadTooLarge(payload.len()));
         }
@@ -382,13 +2386,39 @@ impl Connection for Box<Peer> {
             .send_to(bytes, &self.dest.ok_or(RpcError::NotConnected)?)
             .await
             .map_err(RpcError::Send)?;
+        self.packets.sent += 1;
         Ok(msg.seq)
     }
+
+    fn quality_sample(&self) -> Option<QualitySample> {
+        self.smoothed_rtt.map(|rtt| QualitySample {
+            rtt,
+            loss: self.loss,
+        })
+    }
+
+    fn unacked_packets(&self) -> usize {
+        self.send_queue.iter().filter(|(_, _, ackd)| !ackd).count()
+    }
+
+    fn packet_counts(&self) -> PacketCounts {
+        self.packets
+    }
+
+    fn take_acked(&mut self) -> Vec<SequenceNumber> {
+        std::mem::take(&mut self.own_final_ackd_sequences)
+    }
+
+    /// Worked out again rather than kept, so only what the last message
+    /// acked until something more is received, as it is right after sending.
+    fn last_acks_sent(&self) -> Option<(SequenceNumber, u32)> {
+        (self.packets.sent > 0).then(|| (self.remote_seq, self.recvd_ack_bits(self.remote_seq)))
+    }
 }
 
 impl Peer {
     fn next_seq(&mut self) {
-        self.seq = advance_maybe_wrap(self.seq);
+        self.seq = self.seq.next();
     }
 
     pub async fn bind_only(addr: &str) -> Result<Box<Self>, RpcError> {
@@ -398,12 +2428,15 @@ impl Peer {
 
         Ok(Box::new(Self {
             _id: 123,
-            seq: 0,
-            remote_seq: 0,
+            seq: SequenceNumber::ZERO,
+            remote_seq: SequenceNumber::ZERO,
             dest: None,
             socket,
             bytes_sent: 0,
+            packets: PacketCounts::default(),
             rtt_micros: Histogram::new(),
+            smoothed_rtt: None,
+            loss: 0.0,
             send_queue: VecDeque::new(),
             recv_queue: VecDeque::new(),
             own_final_ackd_sequences: Vec::new(),
@@ -416,12 +2449,15 @@ impl Peer {
             .map_err(RpcError::Bind)?;
         Ok(Box::new(Self {
             _id: 123, // TODO think about id
-            seq: 0,
-            remote_seq: 0,
+            seq: SequenceNumber::ZERO,
+            remote_seq: SequenceNumber::ZERO,
             dest: Some(dest.parse().unwrap()),
             socket,
             bytes_sent: 0,
+            packets: PacketCounts::default(),
             rtt_micros: Histogram::new(),
+            smoothed_rtt: None,
+            loss: 0.0,
             send_queue: VecDeque::new(),
             recv_queue: VecDeque::new(),
             own_final_ackd_sequences: Vec::new(),
@@ -474,12 +2510,13 @@ impl Peer {
 
         let msg_wrap = Typed::new(bytes);
         let msg: &Message = msg_wrap.try_ref()?;
+        self.packets.received += 1;
 
         self.push_recv_queue(msg.seq);
 
         // if the remote sequence is higher, we set the remote sequence from the
         // message.
-        if let Some(_higher) = wrapping_sub(self.remote_seq, msg.seq) {
+        if msg.seq.is_newer_than(self.remote_seq) {
             self.remote_seq = msg.seq;
         }
 
@@ -494,29 +2531,35 @@ impl Peer {
             if !*bit {
                 continue;
             }
-            if let Some((seq, req_start, ackd @ false)) = self.send_queue.get_mut(index) {
-                *ackd = *bit;
+            // Bit n acks the packet n behind the latest one the remote received.
+            let acked = msg.ack.behind(index as u16);
+            if let Some((seq, req_start, ackd @ false)) =
+                self.send_queue.iter_mut().find(|(seq, _, _)| *seq == acked)
+            {
+                *ackd = true;
+                // Acks not taken by now are of no use to anyone.
+                if self.own_final_ackd_sequences.len() == MAX_UNACKED_PACKETS {
+                    self.own_final_ackd_sequences.remove(0);
+                }
                 self.own_final_ackd_sequences.push(*seq);
+                let rtt = req_start.elapsed();
                 self.rtt_micros
-                    .increment(req_start.elapsed().as_micros() as u64)
+                    .increment(rtt.as_micros() as u64)
                     .map_err(RpcError::Histogram)?;
+                self.smoothed_rtt = Some(self.smoothed_rtt.map_or(rtt, |smoothed| {
+                    smoothed.mul_f64(1.0 - RTT_SMOOTHING) + rtt.mul_f64(RTT_SMOOTHING)
+                }));
             }
         }
         Ok(())
     }
 
-    fn recvd_ack_bits(&self, latest_ack: u16) -> u32 {
+    fn recvd_ack_bits(&self, latest_ack: SequenceNumber) -> u32 {
         let mut ack_bits = 0u32;
         let bits = ack_bits.view_bits_mut::<bitvec::prelude::Lsb0>();
         for n in 0..MAX_UNACKED_PACKETS {
-            if latest_ack < n as u16 {
-                continue;
-            }
-            if self
-                .recv_queue
-                .iter()
-                .any(|(seq, _, _)| *seq == latest_ack - n as u16)
-            {
+            let acked = latest_ack.behind(n as u16);
+            if self.recv_queue.iter().any(|(seq, _, _)| *seq == acked) {
                 bits.set(n, true);
             }
         }
@@ -524,15 +2567,18 @@ impl Peer {
     }
 
     // Mark a message as sent, to be used when reading from ack_bits
-    fn push_send_queue(&mut self, seq: u16) {
+    fn push_send_queue(&mut self, seq: SequenceNumber) {
         if self.send_queue.len() == MAX_UNACKED_PACKETS {
-            self.send_queue.pop_front();
+            if let Some((_, _, ackd)) = self.send_queue.pop_front() {
+                let lost = if ackd { 0.0 } else { 1.0 };
+                self.loss += (lost - self.loss) * LOSS_SMOOTHING;
+            }
         }
         self.send_queue.push_back((seq, Instant::now(), false));
     }
 
     // mark a message as recieved, to be used in generation of ack_bits
-    fn push_recv_queue(&mut self, seq: u16) {
+    fn push_recv_queue(&mut self, seq: SequenceNumber) {
         if self.recv_queue.len() == MAX_UNACKED_PACKETS {
             self.recv_queue.pop_front();
         }
@@ -540,26 +2586,6 @@ impl Peer {
     }
 }
 
-fn advance_maybe_wrap(seq: u16) -> u16 {
-    if seq == std::u16::MAX {
-        0
-    } else {
-        seq + 1
-    }
-}
-
-fn wrapping_sub(seq: u16, maybe_next: u16) -> Option<u16> {
-    const HALF_MAX: u16 = std::u16::MAX / 2;
-    // if the number appears to have wrapped
-    if seq.saturating_sub(maybe_next) > HALF_MAX {
-        Some(std::u16::MAX - seq + maybe_next)
-    } else if maybe_next > seq {
-        Some(maybe_next.saturating_sub(seq))
-    } else {
-        None
-    }
-}
-
 #[cfg(test)]
 mod tests {
 
@@ -596,6 +2622,32 @@ mod tests {
         )
     }
 
+    #[smol_potat::test]
+    async fn test_acks_by_sequence() {
+        let mut p1 = Peer::bind_dest("127.0.0.1:8086", "127.0.0.1:8087")
+            .await
+            .unwrap();
+        let mut p2 = Peer::bind_dest("127.0.0.1:8087", "127.0.0.1:8086")
+            .await
+            .unwrap();
+
+        for _ in 0..3 {
+            p1.send(b"hello").await.unwrap();
+        }
+        for _ in 0..3 {
+            p2.recv().await.unwrap();
+        }
+        p2.send(b"acks").await.unwrap();
+        p1.recv().await.unwrap();
+        // Newest first, as the ack bits are.
+        assert_eq!(
+            p1.take_acked(),
+            [SequenceNumber(2), SequenceNumber(1), SequenceNumber(0)]
+        );
+        assert_eq!(p1.unacked_packets(), 0);
+        assert!(p1.take_acked().is_empty());
+    }
+
     #[smol_potat::test]
     async fn test_send_queue() {
         let mut p1 = Peer::bind_dest("127.0.0.1:8084", "127.0.0.1:8085")
@@ -624,11 +2676,11 @@ mod tests {
         println!("simulating 10 sent messages that are not received.");
         for n in 5..15 {
             // simulate a send
-            p1.push_send_queue(n);
+            p1.push_send_queue(SequenceNumber(n));
             p1.next_seq();
 
             // print out the ack_bits we would get at this seqence number
-            let ack_bits = p2.recvd_ack_bits(dbg!(n));
+            let ack_bits = p2.recvd_ack_bits(dbg!(SequenceNumber(n)));
             println!("n{n}: {ack_bits:#034b}");
         }
 
@@ -642,7 +2694,7 @@ mod tests {
             let msg = msg.try_ref().unwrap();
             assert!(mirrored_queue.contains(&msg.seq));
 
-            let ack_bits = p2.recvd_ack_bits(dbg!(n));
+            let ack_bits = p2.recvd_ack_bits(dbg!(SequenceNumber(n)));
             println!("n{n}: {ack_bits:#034b}");
             println!("recv queue {}", p2.recv_queue.len());
         }
@@ -656,10 +2708,10 @@ mod tests {
         // if MAX_UNACKED_PACKETS is less than 32, we shift over to mask.
         let shift_offset = 32 - MAX_UNACKED_PACKETS;
         assert_eq!(
-            p2.recvd_ack_bits(24) << shift_offset,
+            p2.recvd_ack_bits(SequenceNumber(24)) << shift_offset,
             expected_ack_bits << shift_offset,
             "left: {:#034b}, right: {:#034b}",
-            p2.recvd_ack_bits(24),
+            p2.recvd_ack_bits(SequenceNumber(24)),
             expected_ack_bits << shift_offset,
         );
         assert_eq!(
@@ -783,7 +2835,7 @@ mod tests {
             };
             assert_eq!(
                 &Message::new(
-                    x,
+                    SequenceNumber(x),
                     recvd.try_ref().unwrap().ack,
                     p1.recvd_ack_bits(p1.remote_seq),
                     b"hello world",
@@ -795,7 +2847,7 @@ mod tests {
                 Ok(ack) => {
                     assert_eq!(
                         &Message::new(
-                            x,
+                            SequenceNumber(x),
                             ack.try_ref().unwrap().ack,
                             p2.recvd_ack_bits(p2.remote_seq),
                             b"hey there"
@@ -809,8 +2861,8 @@ mod tests {
             }
         }
 
-        assert_eq!(p1.seq, 100);
-        assert_eq!(p2.seq, 100);
+        assert_eq!(p1.seq, SequenceNumber(100));
+        assert_eq!(p2.seq, SequenceNumber(100));
 
         println!(
             "rtt {:?}, mean {:?}, min {:?}, max {:?}",
@@ -847,18 +2899,58 @@ mod tests {
     }
 
     #[test]
-    fn wrapping_u16s() {
-        assert_eq!(wrapping_sub(std::u16::MAX - 5, 5), Some(10));
-        assert_eq!(wrapping_sub(std::u16::MAX - 500, 5), Some(505));
-        assert_eq!(wrapping_sub(0, std::u16::MAX), Some(std::u16::MAX));
-        assert_eq!(
-            wrapping_sub(std::u16::MAX / 2, (std::u16::MAX / 2) + 1),
-            Some(1)
-        );
+    fn handshakes_cut_off_before_the_codecs_are_ignored() {
+        assert_eq!(parse_handshake(HANDSHAKE), None);
+        assert_eq!(parse_handshake(&HANDSHAKE[..4]), None);
+        assert_eq!(parse_handshake(b"not a handshake"), None);
+
+        let build = BuildInfo::default();
+        let mut handshake = HANDSHAKE.to_vec();
+        handshake.push(Compression::SUPPORTED);
+        build.encode_into(&mut handshake);
+        // Padded out to a whole payload, as it's received.
+        handshake.resize(PAYLOAD_LEN, 0);
         assert_eq!(
-            wrapping_sub((std::u16::MAX / 2) - 1, (std::u16::MAX / 2) + 1),
-            Some(2)
+            parse_handshake(&handshake),
+            Some((Compression::SUPPORTED, Some(build)))
         );
-        assert_eq!(wrapping_sub(5, 1), None);
+    }
+
+    #[test]
+    fn projectiles_are_announced_by_id_until_despawned() {
+        let mut world = World::new(None, &LogLevel::Info.logger(), true);
+        let spawn = ProjectileSpawn {
+            origin: Vec3::ZERO,
+            direction: Vec3::NEG_Z,
+            speed: 10.0,
+            lifetime: Duration::from_secs(1),
+            gfx_prefab: world.root.unwrap(),
+            damage: 1,
+            owner: None,
+        };
+        let hit = world.spawn_projectile(spawn).unwrap();
+        world.spawn_projectile(spawn).unwrap();
+        let mut announcements = ProjectileAnnouncements::default();
+        for projectile in world.new_projectiles.drain(..) {
+            announcements.spawned(projectile);
+        }
+        let (mut spawns, mut despawns) = (Vec::new(), Vec::new());
+        announcements.updates(&world, &mut spawns, &mut despawns);
+        let ids =
+            |spawns: &[ProjectileSpawnUpdate]| spawns.iter().map(|s| s.id).collect::<Vec<_>>();
+        assert_eq!(ids(&spawns), [1, 2]);
+        assert!(despawns.is_empty());
+
+        // Despawned on hitting something, it's no longer announced as spawned.
+        world.despawn(hit).unwrap();
+        for _ in 0..PROJECTILE_ANNOUNCEMENT_SENDS {
+            announcements.updates(&world, &mut spawns, &mut despawns);
+            assert!(ids(&spawns).iter().all(|&id| id == 2));
+            assert_eq!(despawns.len(), 1);
+            assert_eq!({ despawns[0].id }, 1);
+        }
+        announcements.updates(&world, &mut spawns, &mut despawns);
+        assert!(spawns.is_empty());
+        assert!(despawns.is_empty());
     }
 }
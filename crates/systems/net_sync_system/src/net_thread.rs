@@ -0,0 +1,338 @@
+//! Network IO on its own executor core, off the simulation's update.
+//!
+//! `NetThread` runs a connection's sends and receives on a dedicated
+//! `ThreadAffineExecutor`, exchanging messages with the simulation through
+//! lock-free queues, so a slow receive never stalls a tick. It's a
+//! `Connection` itself, which is what the world holds, so the simulation
+//! drains received updates and enqueues snapshots through it without waiting
+//! on the socket. Reliable messages are passed through queues of their own.
+
+use std::io;
+use std::num::NonZeroUsize;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use async_io::Timer;
+use core_executor::spsc::{self, Consumer, Full, Producer};
+use core_executor::ThreadAffineExecutor;
+use network::quality::QualitySample;
+use network::reliable::MAX_MESSAGE_LEN;
+use network::{Connection, Message, PacketCounts, RpcError, SequenceNumber, Typed};
+
+/// Messages queued each way before more are dropped, several updates' worth.
+const QUEUE_LEN: usize = 64;
+
+/// Longest the IO thread waits on a receive before sending what's been
+/// queued, and how often a waiting `recv` checks for a message.
+const IO_POLL: Duration = Duration::from_millis(1);
+
+/// Core the IO thread is pinned to, the last one, as the executors doing
+/// simulation work are numbered from the first.
+fn net_core() -> usize {
+    std::thread::available_parallelism().map_or(1, NonZeroUsize::get) - 1
+}
+
+/// A connection whose IO happens on its own core. Sends are queued for the IO
+/// thread and receives are taken from what it has queued, so neither waits on
+/// the socket. Dropping this stops the thread and closes the connection.
+pub struct NetThread {
+    inbound: Consumer<Result<Typed<Message>, RpcError>>,
+    outbound: Producer<Vec<u8>>,
+    reliable_inbound: Consumer<Vec<u8>>,
+    reliable_outbound: Producer<Vec<u8>>,
+    shared: Arc<Shared>,
+    /// Counts sends, as the IO thread numbers messages when it sends them.
+    queued: SequenceNumber,
+    // Joined on drop, after `shared.stop` is set. Only ever locked there,
+    // it's behind a mutex so the connection can be shared as `World` needs.
+    _executor: Mutex<ThreadAffineExecutor>,
+}
+
+/// What the IO thread publishes about the connection, and its stop flag.
+#[derive(Default)]
+struct Shared {
+    stop: AtomicBool,
+    connected: AtomicBool,
+    /// Round trip time in microseconds, `u64::MAX` until it's measured.
+    rtt_micros: AtomicU64,
+    loss_bits: AtomicU32,
+    unacked: AtomicUsize,
+    sent: AtomicU64,
+    received: AtomicU64,
+    /// Received messages dropped because the simulation fell behind.
+    dropped: AtomicU64,
+}
+
+impl Shared {
+    fn publish(&self, connection: &(dyn Connection + Send + Sync)) {
+        self.connected
+            .store(connection.is_connected(), Ordering::Relaxed);
+        let (rtt, loss) = connection
+            .quality_sample()
+            .map_or((u64::MAX, 0.0), |sample| {
+                (sample.rtt.as_micros() as u64, sample.loss)
+            });
+        self.rtt_micros.store(rtt, Ordering::Relaxed);
+        self.loss_bits.store(loss.to_bits(), Ordering::Relaxed);
+        self.unacked
+            .store(connection.unacked_packets(), Ordering::Relaxed);
+        let packets = connection.packet_counts();
+        self.sent.store(packets.sent, Ordering::Relaxed);
+        self.received.store(packets.received, Ordering::Relaxed);
+    }
+}
+
+impl NetThread {
+    /// Move `connection` onto its own core.
+    pub fn spawn(connection: Box<dyn Connection + Send + Sync + 'static>) -> Self {
+        let (inbound_producer, inbound) = spsc::bounded(QUEUE_LEN);
+        let (outbound, outbound_consumer) = spsc::bounded(QUEUE_LEN);
+        let (reliable_inbound_producer, reliable_inbound) = spsc::bounded(QUEUE_LEN);
+        let (reliable_outbound, reliable_outbound_consumer) = spsc::bounded(QUEUE_LEN);
+        let shared = Arc::new(Shared {
+            rtt_micros: AtomicU64::new(u64::MAX),
+            ..Shared::default()
+        });
+        shared.publish(&*connection);
+
+        let mut executor = ThreadAffineExecutor::new(net_core());
+        executor.spawner.fire(pump(
+            connection,
+            Queues {
+                inbound: inbound_producer,
+                outbound: outbound_consumer,
+                reliable_inbound: reliable_inbound_producer,
+                reliable_outbound: reliable_outbound_consumer,
+            },
+            Arc::clone(&shared),
+        ));
+        Self {
+            inbound,
+            outbound,
+            reliable_inbound,
+            reliable_outbound,
+            shared,
+            queued: SequenceNumber::ZERO,
+            _executor: Mutex::new(executor),
+        }
+    }
+
+    /// Received messages dropped so far because they weren't taken in time.
+    pub fn dropped(&self) -> u64 {
+        self.shared.dropped.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for NetThread {
+    fn drop(&mut self) {
+        self.shared.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+/// The IO thread's ends of the queues to and from the simulation.
+struct Queues {
+    inbound: Producer<Result<Typed<Message>, RpcError>>,
+    outbound: Consumer<Vec<u8>>,
+    reliable_inbound: Producer<Vec<u8>>,
+    reliable_outbound: Consumer<Vec<u8>>,
+}
+
+/// The IO thread: send whatever the simulation queued, then wait briefly for
+/// a message, until stopped.
+async fn pump(
+    mut connection: Box<dyn Connection + Send + Sync + 'static>,
+    queues: Queues,
+    shared: Arc<Shared>,
+) {
+    let Queues {
+        mut inbound,
+        mut outbound,
+        mut reliable_inbound,
+        mut reliable_outbound,
+    } = queues;
+    let mut deliver = |received, shared: &Shared| {
+        if let Err(Full(_)) = inbound.push(received) {
+            shared.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    };
+    // A reliable message received while the simulation's queue was full.
+    let mut undelivered = None;
+    while !shared.stop.load(Ordering::Relaxed) {
+        while let Some(message) = reliable_outbound.pop() {
+            if let Err(err) = connection.send_reliable(&message) {
+                deliver(Err(err), &shared);
+            }
+        }
+        while let Some(payload) = outbound.pop() {
+            if let Err(err) = connection.send(&payload).await {
+                deliver(Err(err), &shared);
+            }
+        }
+        match connection.recv_with_timeout(IO_POLL).await {
+            Ok(message) => deliver(Ok(message), &shared),
+            Err(RpcError::Receive(err)) if err.kind() == io::ErrorKind::TimedOut => {}
+            Err(err) => {
+                deliver(Err(err), &shared);
+                // Don't spin on a connection that keeps failing.
+                Timer::after(IO_POLL).await;
+            }
+        }
+        // Unlike updates, reliable messages mustn't be dropped, so they wait
+        // in the connection until there's room.
+        while let Some(message) = undelivered.take().or_else(|| connection.recv_reliable()) {
+            if let Err(Full(message)) = reliable_inbound.push(message) {
+                undelivered = Some(message);
+                break;
+            }
+        }
+        shared.publish(&*connection);
+    }
+}
+
+#[async_trait::async_trait]
+impl Connection for NetThread {
+    fn is_connected(&self) -> bool {
+        self.shared.connected.load(Ordering::Relaxed)
+    }
+
+    async fn recv(&mut self) -> Result<Typed<Message>, RpcError> {
+        loop {
+            if let Some(received) = self.inbound.pop() {
+                return received;
+            }
+            Timer::after(IO_POLL).await;
+        }
+    }
+
+    /// Take the oldest message the IO thread has received, waiting up to
+    /// `timeout_duration` for one. With a zero timeout this never waits.
+    async fn recv_with_timeout(
+        &mut self,
+        timeout_duration: Duration,
+    ) -> Result<Typed<Message>, RpcError> {
+        let deadline = Instant::now() + timeout_duration;
+        loop {
+            if let Some(received) = self.inbound.pop() {
+                return received;
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(RpcError::Receive(io::ErrorKind::TimedOut.into()));
+            }
+            Timer::at(deadline.min(now + IO_POLL)).await;
+        }
+    }
+
+    /// Queue a message for the IO thread, failing without waiting if it has
+    /// fallen behind. The sequence number returned counts queued messages,
+    /// the one on the wire is assigned as it's sent.
+    async fn send(&mut self, payload: &[u8]) -> Result<SequenceNumber, RpcError> {
+        if let Err(Full(_)) = self.outbound.push(payload.to_vec()) {
+            return Err(RpcError::Send(io::Error::new(
+                io::ErrorKind::WouldBlock,
+                "network thread send queue full",
+            )));
+        }
+        let seq = self.queued;
+        self.queued = seq.next();
+        Ok(seq)
+    }
+
+    fn quality_sample(&self) -> Option<QualitySample> {
+        let rtt = self.shared.rtt_micros.load(Ordering::Relaxed);
+        (rtt != u64::MAX).then(|| QualitySample {
+            rtt: Duration::from_micros(rtt),
+            loss: f32::from_bits(self.shared.loss_bits.load(Ordering::Relaxed)),
+        })
+    }
+
+    fn unacked_packets(&self) -> usize {
+        self.shared.unacked.load(Ordering::Relaxed)
+    }
+
+    fn packet_counts(&self) -> PacketCounts {
+        PacketCounts {
+            sent: self.shared.sent.load(Ordering::Relaxed),
+            received: self.shared.received.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Queue a reliable message for the IO thread, which sends it with the
+    /// next payloads. Failures there are received as errors.
+    fn send_reliable(&mut self, message: &[u8]) -> Result<(), RpcError> {
+        if message.len() > MAX_MESSAGE_LEN {
+            return Err(RpcError::PayloadTooLarge(message.len()));
+        }
+        self.reliable_outbound
+            .push(message.to_vec())
+            .map_err(|Full(_)| {
+                RpcError::Send(io::Error::new(
+                    io::ErrorKind::WouldBlock,
+                    "network thread reliable queue full",
+                ))
+            })
+    }
+
+    fn recv_reliable(&mut self) -> Option<Vec<u8>> {
+        self.reliable_inbound.pop()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use network::loopback::LoopbackConnection;
+    use network::reliable::Reliable;
+
+    use super::*;
+
+    #[smol_potat::test]
+    async fn exchanges_messages_off_thread() {
+        let (server, client) = LoopbackConnection::pair(Duration::ZERO);
+        let mut server = NetThread::spawn(Box::new(server));
+        let mut client = NetThread::spawn(Box::new(client));
+
+        // Nothing has arrived, and looking doesn't wait.
+        assert!(matches!(
+            server.recv_with_timeout(Duration::ZERO).await,
+            Err(RpcError::Receive(err)) if err.kind() == io::ErrorKind::TimedOut
+        ));
+
+        client.send(b"stuff").await.unwrap();
+        let received = server
+            .recv_with_timeout(Duration::from_secs(5))
+            .await
+            .unwrap();
+        assert_eq!(&received.try_ref().unwrap().payload[..5], b"stuff");
+        assert!(server.is_connected());
+
+        drop(client);
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while server.is_connected() && Instant::now() < deadline {
+            Timer::after(IO_POLL).await;
+        }
+        assert!(!server.is_connected());
+    }
+
+    #[smol_potat::test]
+    async fn carries_reliable_messages_off_thread() {
+        let (server, client) = LoopbackConnection::pair(Duration::ZERO);
+        let mut server = NetThread::spawn(Box::new(Reliable::new(Box::new(server))));
+        let mut client = NetThread::spawn(Box::new(Reliable::new(Box::new(client))));
+
+        let manifest = (0..3000).map(|i| i as u8).collect::<Vec<_>>();
+        client.send_reliable(b"hello").unwrap();
+        client.send_reliable(&manifest).unwrap();
+        let mut received = Vec::new();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while received.len() < 2 && Instant::now() < deadline {
+            // Reliable messages go out with updates.
+            client.send(b"update").await.unwrap();
+            if let Ok(update) = server.recv_with_timeout(IO_POLL).await {
+                assert_eq!(&update.try_ref().unwrap().payload[..6], b"update");
+            }
+            received.extend(std::iter::from_fn(|| server.recv_reliable()));
+        }
+        assert_eq!(received, [b"hello".to_vec(), manifest]);
+    }
+}
@@ -0,0 +1,316 @@
+//! A description of the wire format, derived from the types that are sent, for
+//! keeping clients, servers and external tools in step.
+//!
+//! Struct layouts come from the types themselves, field offsets, sizes and
+//! types, so they can't drift from what's on the wire. Messages describe how
+//! those structs and the bit-packed parts are laid out in a payload. The
+//! schema's fingerprint is recorded for each `PROTOCOL_VERSION`, and a test
+//! fails when the schema changes without a new version.
+
+use std::fmt;
+use std::mem::{offset_of, size_of};
+
+use network::{Message, SequenceNumber, PAYLOAD_LEN};
+
+use crate::wire::{
+    EntityUpdate, HapticUpdate, ProjectileDespawnUpdate, ProjectileSpawnUpdate, SliceChecksumUpdate,
+};
+use crate::{
+    BUILD_MISMATCH, HANDSHAKE, MAX_HAPTICS_PER_MSG, MAX_PROJECTILE_DESPAWNS_PER_MSG,
+    MAX_PROJECTILE_SPAWNS_PER_MSG, MAX_UPDATES_PER_MSG,
+};
+
+/// Version of the wire format, bumped whenever the schema changes.
+pub const PROTOCOL_VERSION: u16 = 5;
+
+/// The schema fingerprint of each protocol version. A new version's is added
+/// as it's bumped, see `Schema::fingerprint`.
+#[cfg(test)]
+const FINGERPRINTS: &[(u16, u64)] = &[
+    (1, 0x9278_a934_64e2_bc02),
+    (2, 0xa725_10f7_2e68_2e43),
+    (3, 0x2a92_f9d8_aeec_72eb),
+    (4, 0xc772_c4d0_5698_1c45),
+    (5, 0xc8f5_a86f_4224_83a4),
+];
+
+/// A type sent as is, named the same on every platform.
+pub trait WireType {
+    fn wire_name() -> String;
+}
+
+macro_rules! impl_wire_type {
+    ($($ty:ty),*) => {
+        $(
+            impl WireType for $ty {
+                fn wire_name() -> String {
+                    stringify!($ty).to_string()
+                }
+            }
+        )*
+    };
+}
+
+impl_wire_type!(u8, u16, u32, u64, f32);
+
+impl<T: WireType, const N: usize> WireType for [T; N] {
+    fn wire_name() -> String {
+        format!("[{}; {N}]", T::wire_name())
+    }
+}
+
+impl WireType for SequenceNumber {
+    fn wire_name() -> String {
+        u16::wire_name()
+    }
+}
+
+/// The wire type and size of a field, from a function reading it.
+fn field_type<S, F: WireType>(_read: fn(S) -> F) -> (String, usize) {
+    (F::wire_name(), size_of::<F>())
+}
+
+/// Describe a struct from its fields, which must be all of them.
+macro_rules! struct_schema {
+    ($ty:ident { $($field:ident),* $(,)? }) => {{
+        StructSchema {
+            name: stringify!($ty),
+            size: size_of::<$ty>(),
+            fields: vec![$({
+                // By value, as fields of packed structs can't be borrowed.
+                let (ty, size) = field_type(|value: $ty| value.$field);
+                FieldSchema {
+                    name: stringify!($field),
+                    ty,
+                    offset: offset_of!($ty, $field),
+                    size,
+                }
+            }),*],
+        }
+    }};
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldSchema {
+    pub name: &'static str,
+    pub ty: String,
+    pub offset: usize,
+    pub size: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StructSchema {
+    pub name: &'static str,
+    pub size: usize,
+    pub fields: Vec<FieldSchema>,
+}
+
+/// A message sent in a `Message`'s payload, as the parts it's made of in
+/// order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MessageSchema {
+    pub name: &'static str,
+    pub direction: &'static str,
+    pub parts: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Schema {
+    pub version: u16,
+    pub payload_len: usize,
+    pub structs: Vec<StructSchema>,
+    pub messages: Vec<MessageSchema>,
+}
+
+impl Schema {
+    /// The schema of this build's wire format.
+    pub fn current() -> Self {
+        let structs = vec![
+            struct_schema!(Message {
+                seq,
+                ack,
+                ack_bits,
+                payload
+            }),
+            struct_schema!(EntityUpdate {
+                entity_bits,
+                pos,
+                rot
+            }),
+            struct_schema!(ProjectileSpawnUpdate {
+                id,
+                gfx_bits,
+                pos,
+                vel,
+                remaining_millis
+            }),
+            struct_schema!(ProjectileDespawnUpdate { id }),
+            struct_schema!(HapticUpdate { pattern, strength }),
+            struct_schema!(SliceChecksumUpdate {
+                update,
+                checksum,
+                group,
+                groups,
+                entities
+            }),
+        ];
+        let messages = vec![
+            MessageSchema {
+                name: "Handshake",
+                direction: "client to server",
+                parts: vec![
+                    format!(
+                        "magic: [u8; {}], {:?}",
+                        HANDSHAKE.len(),
+                        String::from_utf8_lossy(HANDSHAKE)
+                    ),
+                    "codecs: u8, mask of the compression codec ids supported".to_string(),
+                    "build: version, git hash, build date, profile and comma separated \
+                     features, separated by newlines"
+                        .to_string(),
+                ],
+            },
+            MessageSchema {
+                name: "BuildMismatch",
+                direction: "server to client",
+                parts: vec![
+                    format!(
+                        "magic: [u8; {}], {:?}",
+                        BUILD_MISMATCH.len(),
+                        String::from_utf8_lossy(BUILD_MISMATCH)
+                    ),
+                    "build: the server's, as in Handshake".to_string(),
+                ],
+            },
+            MessageSchema {
+                name: "ServerUpdate",
+                direction: "server to client",
+                parts: vec![
+                    "server_time: u64, microseconds, little endian".to_string(),
+                    "codec: u8, compression codec id".to_string(),
+                    "len: u16, bytes of the compressed delta, which is bit-packed".to_string(),
+                    "snapshot: 32 bits, id of the snapshot, from 1".to_string(),
+                    "baseline: 32 bits, id of the snapshot it's a delta against, 0 for none"
+                        .to_string(),
+                    "removed_count: 16 bits".to_string(),
+                    "per removed, entity_bits: 64 bits".to_string(),
+                    format!("changed_count: 16 bits, at most {MAX_UPDATES_PER_MSG} with removed"),
+                    "per changed, entity_bits: 64 bits".to_string(),
+                    "per changed, moved: 1 bit, then per axis short: 1 bit, then zigzag step: 7 \
+                     bits if short, otherwise pos: 16 bits"
+                        .to_string(),
+                    "per changed, turned: 1 bit, then rot: 32 bits if turned".to_string(),
+                    format!("spawn_count: u8, at most {MAX_PROJECTILE_SPAWNS_PER_MSG}"),
+                    "spawns: [ProjectileSpawnUpdate; spawn_count]".to_string(),
+                    format!("despawn_count: u8, at most {MAX_PROJECTILE_DESPAWNS_PER_MSG}"),
+                    "despawns: [ProjectileDespawnUpdate; despawn_count]".to_string(),
+                    format!("haptic_count: u8, at most {MAX_HAPTICS_PER_MSG}"),
+                    "haptics: [HapticUpdate; haptic_count]".to_string(),
+                    "slice: SliceChecksumUpdate, groups 0 when there's none".to_string(),
+                ],
+            },
+            MessageSchema {
+                name: "ClientInput",
+                direction: "client to server",
+                parts: vec![
+                    "len: u16, bytes of bit-packed states".to_string(),
+                    "count: 8 bits".to_string(),
+                    "per state, id: 8 bits".to_string(),
+                    "per state, buttons: 16 bits".to_string(),
+                    "per state, 7 axes: present: 1 bit, then value: 8 bits if present".to_string(),
+                    "slice: SliceChecksumUpdate, after len bytes of states, groups 0 when there's \
+                     none"
+                        .to_string(),
+                    "acked: u32, little endian, the latest snapshot decoded, 0 for none"
+                        .to_string(),
+                ],
+            },
+        ];
+        Self {
+            version: PROTOCOL_VERSION,
+            payload_len: PAYLOAD_LEN,
+            structs,
+            messages,
+        }
+    }
+
+    /// A hash of the layout, leaving out the version, to tell whether the
+    /// schema changed. FNV-1a, so it's the same from build to build.
+    pub fn fingerprint(&self) -> u64 {
+        let mut layout = String::new();
+        self.write_layout(&mut layout)
+            .expect("formatting into a string");
+        layout.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+        })
+    }
+
+    fn write_layout(&self, f: &mut impl fmt::Write) -> fmt::Result {
+        writeln!(f, "payloads of up to {} bytes", self.payload_len)?;
+        for message in &self.messages {
+            writeln!(f, "\nmessage {}, {}:", message.name, message.direction)?;
+            for part in &message.parts {
+                writeln!(f, "  {part}")?;
+            }
+        }
+        for schema in &self.structs {
+            writeln!(f, "\nstruct {}, {} bytes:", schema.name, schema.size)?;
+            for field in &schema.fields {
+                writeln!(f, "  {:>4} {}: {}", field.offset, field.name, field.ty)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Schema {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "wire protocol version {} ({:016x})",
+            self.version,
+            self.fingerprint()
+        )?;
+        self.write_layout(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn structs_are_fully_described() {
+        for schema in Schema::current().structs {
+            let mut offset = 0;
+            for field in &schema.fields {
+                assert_eq!(field.offset, offset, "{}.{}", schema.name, field.name);
+                offset += field.size;
+            }
+            assert_eq!(offset, schema.size, "{} has fields missing", schema.name);
+        }
+    }
+
+    #[test]
+    fn schema_changes_bump_the_protocol_version() {
+        let schema = Schema::current();
+        let fingerprint = schema.fingerprint();
+        let recorded = FINGERPRINTS
+            .iter()
+            .find(|(version, _)| *version == PROTOCOL_VERSION)
+            .map(|(_, fingerprint)| *fingerprint);
+        assert_eq!(
+            recorded,
+            Some(fingerprint),
+            "the wire schema changed, bump PROTOCOL_VERSION and record its \
+             fingerprint {fingerprint:#018x}:\n{schema}"
+        );
+        assert!(
+            FINGERPRINTS
+                .iter()
+                .all(|(version, recorded)| *version == PROTOCOL_VERSION
+                    || *recorded != fingerprint),
+            "the schema is the same as an earlier version's"
+        );
+    }
+}
@@ -0,0 +1,145 @@
+//! Rapier joints for the `Joint` components in the world. Joints are kept in
+//! step with their components every update: created for new components,
+//! rebuilt for changed ones, and removed once the component or either body is
+//! gone.
+
+use std::collections::{HashMap, HashSet};
+
+use glam::Vec3;
+use logger::{warn, Logger};
+use rapier3d::na::{self as nalgebra, point, vector};
+use rapier3d::prelude::{
+    FixedJointBuilder, GenericJoint, ImpulseJointHandle, ImpulseJointSet, Point,
+    PrismaticJointBuilder, Real, RevoluteJointBuilder, RigidBodyHandle, SphericalJointBuilder,
+    UnitVector,
+};
+use world::components::{Joint, JointKind};
+use world::{Entity, World};
+
+/// A joint created for a component, and what it was created from.
+struct BuiltJoint {
+    joint: Joint,
+    /// The bodies it joins, None when they couldn't be found.
+    bodies: Option<(RigidBodyHandle, RigidBodyHandle)>,
+    handle: Option<ImpulseJointHandle>,
+}
+
+#[derive(Default)]
+pub(crate) struct Joints {
+    built: HashMap<Entity, BuiltJoint>,
+}
+
+impl Joints {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create, rebuild and remove joints to match the `Joint` components in
+    /// `world`, finding the rigid body of an entity with `body_of`.
+    pub fn sync(
+        &mut self,
+        world: &World,
+        body_of: impl Fn(Entity) -> Option<RigidBodyHandle>,
+        impulse_joints: &mut ImpulseJointSet,
+        logger: &Logger,
+    ) {
+        let mut seen = HashSet::new();
+        for (entity, joint) in world.hecs_world.query::<&Joint>().iter() {
+            seen.insert(entity);
+            let bodies = body_of(joint.first)
+                .zip(body_of(joint.second))
+                .filter(|(first, second)| first != second);
+            let built = self.built.get(&entity);
+            if built.is_some_and(|built| built.joint == *joint && built.bodies == bodies) {
+                continue;
+            }
+            if let Some(handle) = built.and_then(|built| built.handle) {
+                impulse_joints.remove(handle, true);
+            }
+            let handle = match (bodies, rapier_joint(joint)) {
+                (Some((first, second)), Some(data)) => {
+                    Some(impulse_joints.insert(first, second, data, true))
+                }
+                (None, _) => {
+                    warn!(
+                        logger,
+                        "joint {entity:?} needs two different rigid bodies, {:?} and {:?} aren't",
+                        joint.first,
+                        joint.second
+                    );
+                    None
+                }
+                (_, None) => {
+                    warn!(logger, "joint {entity:?} has no axis: {:?}", joint.kind);
+                    None
+                }
+            };
+            self.built.insert(
+                entity,
+                BuiltJoint {
+                    joint: *joint,
+                    bodies,
+                    handle,
+                },
+            );
+        }
+
+        self.built.retain(|entity, built| {
+            let keep = seen.contains(entity);
+            if let Some(handle) = built.handle.filter(|_| !keep) {
+                impulse_joints.remove(handle, true);
+            }
+            keep
+        });
+    }
+
+    /// Number of joints created.
+    pub fn len(&self) -> usize {
+        self.built
+            .values()
+            .filter(|built| built.handle.is_some())
+            .count()
+    }
+}
+
+/// The rapier joint for `joint`, None if it has an axis of zero length.
+fn rapier_joint(joint: &Joint) -> Option<GenericJoint> {
+    let (anchor1, anchor2) = (to_point(joint.first_anchor), to_point(joint.second_anchor));
+    Some(match joint.kind {
+        JointKind::Fixed => FixedJointBuilder::new()
+            .local_anchor1(anchor1)
+            .local_anchor2(anchor2)
+            .into(),
+        JointKind::Revolute { axis, limits } => {
+            let mut builder = RevoluteJointBuilder::new(to_axis(axis)?)
+                .local_anchor1(anchor1)
+                .local_anchor2(anchor2);
+            if let Some(limits) = limits {
+                builder = builder.limits(limits);
+            }
+            builder.into()
+        }
+        JointKind::Prismatic { axis, limits } => {
+            let mut builder = PrismaticJointBuilder::new(to_axis(axis)?)
+                .local_anchor1(anchor1)
+                .local_anchor2(anchor2);
+            if let Some(limits) = limits {
+                builder = builder.limits(limits);
+            }
+            builder.into()
+        }
+        JointKind::Spherical => SphericalJointBuilder::new()
+            .local_anchor1(anchor1)
+            .local_anchor2(anchor2)
+            .into(),
+    })
+}
+
+fn to_point(v: Vec3) -> Point<Real> {
+    point![v.x, v.y, v.z]
+}
+
+fn to_axis(v: Vec3) -> Option<UnitVector<Real>> {
+    let v = v.try_normalize()?;
+    Some(UnitVector::new_unchecked(vector![v.x, v.y, v.z]))
+}
@@ -4,24 +4,55 @@
 //! accord based on a timestamp. For example: if running as a server, tick the
 //! simulation along based on the `dt` passed to the plugin.
 
-use std::collections::HashMap;
+mod joints;
+mod occlusion;
+mod physics_debug;
+
+use std::collections::{HashMap, HashSet};
 use std::time::{Duration, Instant};
 
 use glam::{vec3, vec4, Vec3};
-use input::wire::InputState;
+use input::accumulate::HeldInput;
+use input::haptics::{Rumble, RumblePattern};
 use input::Button;
-use logger::{error, info, trace, LogLevel, Logger};
+use logger::{error, info, trace, warn, LogLevel, Logger};
 use rapier3d::control::{DynamicRayCastVehicleController, WheelTuning};
 use rapier3d::na::{self as nalgebra, point, vector, Vector};
+use rapier3d::parry::query::RayCast;
 use rapier3d::prelude::{
-    ColliderBuilder, ColliderHandle, ColliderSet, RigidBodyBuilder, RigidBodySet,
+    ColliderBuilder, ColliderHandle, ColliderSet, ImpulseJointSet, Isometry, MultibodyJointSet,
+    NarrowPhase, Ray, RigidBodyBuilder, RigidBodySet, SharedShape,
 };
 use stable_typeid::StableTypeId;
 use world::bundles::StaticObject;
-use world::components::spatial::SpatialHierarchyNode;
-use world::components::{Camera, Control, PhysicsBody, WorldTransform};
+use world::collision::CollisionGeometry;
+use world::components::spatial::{self, SpatialHierarchyNode};
+use world::components::{
+    Camera, Control, Drawable, Lifetime, MotionPattern, PhysicsBody, PhysicsPose, Projectile,
+    Velocity, WorldTransform,
+};
 use world::graphics::Shape;
-use world::{Entity, World, WorldError};
+use world::health::HealthFacet;
+use world::journal::JournalEvent;
+use world::notifications::Severity;
+use world::pool::Pooled;
+use world::replication::{ReplicationBuffer, ReplicationPolicy};
+use world::{animation, Entity, World, WorldError};
+
+use crate::joints::Joints;
+use crate::occlusion::{Occluder, Occlusion};
+use crate::physics_debug::{PhysicsDebug, PhysicsState};
+
+/// Damage from a projectile that rumbles the hit player's controllers at full
+/// strength, less rumbles them proportionally less.
+const HIT_RUMBLE_FULL_DAMAGE: u32 = 10;
+
+/// Furthest mouse look tilts the camera up or down, short of straight up so
+/// the view never flips over.
+const MAX_LOOK_PITCH: f32 = 1.5;
+
+/// Version of the plugin, shown with its name in what it logs.
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
 
 /// Internal plugin state. The lifespan is load->update->unload and dropped
 /// after unload.
@@ -29,8 +60,19 @@ pub struct WorldUpdate {
     logger: Logger,
     rigid_bodies: RigidBodySet,
     colliders: ColliderSet,
+    impulse_joints: ImpulseJointSet,
+    multibody_joints: MultibodyJointSet,
+    narrow_phase: NarrowPhase,
     vehicle_controller: Option<DynamicRayCastVehicleController>,
     collider_handles: HashMap<world::Entity, ColliderHandle>,
+    /// Colliders built from imported collision geometry, drawn in their own
+    /// debug category.
+    static_colliders: HashSet<ColliderHandle>,
+    /// Joints created for `Joint` components.
+    joints: Joints,
+    /// Muffles audio emitters behind fixed colliders.
+    occlusion: Occlusion,
+    physics_debug: PhysicsDebug,
 }
 
 impl WorldUpdate {
@@ -39,15 +81,26 @@ impl WorldUpdate {
             logger: LogLevel::Info.logger().sub("world-update"),
             rigid_bodies: RigidBodySet::new(),
             colliders: ColliderSet::new(),
-            // impulse_joints: ImpulseJointSet::new(),
-            // multibody_joints: MultibodyJointSet::new(),
+            impulse_joints: ImpulseJointSet::new(),
+            multibody_joints: MultibodyJointSet::new(),
+            narrow_phase: NarrowPhase::new(),
             vehicle_controller: None,
             collider_handles: HashMap::new(),
+            static_colliders: HashSet::new(),
+            joints: Joints::new(),
+            occlusion: Occlusion::new(),
+            physics_debug: PhysicsDebug::new(),
         }
     }
 
+    /// Log through `logger` from now on, such as the root logger the engine
+    /// gives each plugin.
+    pub fn set_logger(&mut self, logger: Logger) {
+        self.logger = logger;
+    }
+
     pub fn load(&mut self, world: &mut World) {
-        info!(world.logger, "loaded.");
+        info!(self.logger, "loaded.");
         self.logger.maybe_set_filter(world.logger.get_filter());
 
         // Set up colliders and rigid bodies from the world state
@@ -57,16 +110,47 @@ impl WorldUpdate {
         self.setup_vehicle();
 
         self.setup_object_colliders(world);
+
+        self.setup_static_colliders(world);
+
+        self.setup_occlusion();
     }
 
     pub fn update(&mut self, world: &mut World, dt: &Duration) {
         let mut world = WorldExt::new(world);
         world.update_stats(dt);
+        apply_mouse_look(&mut world);
         if world.is_server() {
             world.step_physical();
         }
-
-        self.update_transform_hierarchy(&mut world);
+        animate_motion_patterns(&mut world);
+        if world.is_server() {
+            // Projectiles are cast against bodies where this tick left them.
+            self.sync_body_colliders(&world);
+        } else {
+            move_replicated_entities(&mut world);
+        }
+        self.update_projectiles(&mut world, *dt);
+        animation::sample_animations(&mut world.world.hecs_world, *dt);
+
+        let world_transforms_updated = self.update_transform_hierarchy(&mut world);
+        animation::update_joint_matrices(&mut world.world.hecs_world);
+        update_physics_poses(&mut world, &world_transforms_updated);
+        self.sync_joints(&world);
+        self.occlusion.update(world.world, *dt);
+
+        self.physics_debug.draw(
+            &mut world.world.debug_draw,
+            &PhysicsState {
+                rigid_bodies: &self.rigid_bodies,
+                colliders: &self.colliders,
+                impulse_joints: &self.impulse_joints,
+                multibody_joints: &self.multibody_joints,
+                narrow_phase: &self.narrow_phase,
+                vehicle: self.vehicle_controller.as_ref(),
+                static_colliders: &self.static_colliders,
+            },
+        );
     }
 
     pub fn unload(&mut self, _world: &mut World) {
@@ -76,44 +160,18 @@ impl WorldUpdate {
 }
 
 impl WorldUpdate {
-    /// For every child in the tree, walk it's ancestors and update it's world
-    /// transform from them.
-    fn update_transform_hierarchy(&self, world: &mut WorldExt) {
-        // TODO: is it worth marking entities dirty and re-iterating the list of updated
-        // ones
-        let world_transforms_updated = self.update_hierarchy(world);
-        mark_clean_updated_nodes(world, &world_transforms_updated);
-    }
-
-    fn update_hierarchy(&self, world: &mut WorldExt) -> Vec<Entity> {
+    /// Update the world transforms of dirty nodes and their descendants from
+    /// their ancestors. Returns the entities that were updated.
+    fn update_transform_hierarchy(&self, world: &mut WorldExt) -> Vec<Entity> {
         let root_entity = world.world.root.unwrap();
-        let mut root_transform = world
-            .world
-            .hecs_world
-            .query_one::<(&WorldTransform,)>(root_entity)
-            .unwrap();
-
-        let (root_transform,) = root_transform.get().unwrap();
-        let mut parents_query = world.world.hecs_world.query::<&SpatialHierarchyNode>();
-        let parents = parents_query.view();
-
-        let mut world_transforms_updated = vec![];
-        for (entity, (node, entity_world_transform)) in world
+        let root_transform = world
             .world
             .hecs_world
-            .query::<(&SpatialHierarchyNode, &mut WorldTransform)>()
-            .iter()
-            .filter(|(_entity, (node, _))| node.is_dirty())
-        {
-            let mut relative_matrix = node.transform;
-            let mut ancestor = node.parent;
-            while let Some(next) = parents.get(ancestor) {
-                relative_matrix = next.transform * relative_matrix;
-                ancestor = next.parent;
-            }
-            world_transforms_updated.push(entity);
-            entity_world_transform.world = root_transform.world * relative_matrix;
-        }
+            .get::<&WorldTransform>(root_entity)
+            .unwrap()
+            .world;
+        let world_transforms_updated =
+            spatial::update_world_transforms(&mut world.world.hecs_world, root_transform);
         if !world_transforms_updated.is_empty() {
             trace!(
                 self.logger,
@@ -150,6 +208,16 @@ impl WorldUpdate {
         self.vehicle_controller = Some(vehicle);
     }
 
+    /// Bring the rapier joints in line with the world's `Joint` components.
+    /// Entities are joined through the rigid bodies of their colliders.
+    fn sync_joints(&mut self, world: &WorldExt) {
+        let (colliders, collider_handles) = (&self.colliders, &self.collider_handles);
+        let body_of = |entity| colliders.get(*collider_handles.get(&entity)?)?.parent();
+        self.joints
+            .sync(world.world, body_of, &mut self.impulse_joints, &self.logger);
+        trace!(self.logger, "{} joints", self.joints.len());
+    }
+
     // create colliders for all objects that have a phyiscal facet
     fn setup_object_colliders(&mut self, world: &mut World) {
         let rad = 0.1;
@@ -178,6 +246,128 @@ impl WorldUpdate {
         }
     }
 
+    /// Move the colliders of physics bodies, and the rigid bodies they're
+    /// attached to, to where their entities are.
+    fn sync_body_colliders(&mut self, world: &WorldExt) {
+        for (entity, spatial) in world
+            .world
+            .hecs_world
+            .query::<&SpatialHierarchyNode>()
+            .with::<&PhysicsBody>()
+            .iter()
+        {
+            let collider = match self
+                .collider_handles
+                .get(&entity)
+                .and_then(|handle| self.colliders.get_mut(*handle))
+            {
+                Some(collider) => collider,
+                None => continue,
+            };
+            let (translation, (axis, angle)) =
+                (spatial.get_pos(), spatial.get_rotation().to_axis_angle());
+            let position = Isometry::new(
+                vector![translation.x, translation.y, translation.z],
+                vector![axis.x, axis.y, axis.z] * angle,
+            );
+            if let Some(body) = collider
+                .parent()
+                .and_then(|parent| self.rigid_bodies.get_mut(parent))
+            {
+                body.set_position(position, false);
+            }
+            collider.set_position(position);
+        }
+    }
+
+    /// Create fixed colliders for the static objects whose prefab has
+    /// collision geometry, a compound of its hulls at the object's transform.
+    fn setup_static_colliders(&mut self, world: &mut World) {
+        let root = world.root.unwrap();
+        let root_transform = world.hecs_world.get::<&WorldTransform>(root).unwrap().world;
+        // Objects are only placed so far, their world transforms haven't been
+        // updated yet.
+        spatial::update_world_transforms(&mut world.hecs_world, root_transform);
+
+        for (entity, (transform, drawable)) in world
+            .hecs_world
+            .query::<(&WorldTransform, &Drawable)>()
+            .without::<&PhysicsBody>()
+            .iter()
+        {
+            let geometry = match world.hecs_world.get::<&CollisionGeometry>(drawable.gfx) {
+                Ok(geometry) => geometry,
+                Err(_) => continue,
+            };
+            let (scale, rotation, translation) = transform.world.to_scale_rotation_translation();
+            let hulls = geometry
+                .scaled(scale)
+                .iter()
+                .filter_map(|hull| {
+                    let points = hull
+                        .iter()
+                        .map(|p| point![p.x, p.y, p.z])
+                        .collect::<Vec<_>>();
+                    SharedShape::convex_hull(&points)
+                })
+                .map(|hull| (Isometry::identity(), hull))
+                .collect::<Vec<_>>();
+            if hulls.len() < geometry.hulls.len() {
+                warn!(
+                    self.logger,
+                    "{} of {entity:?}'s collision hulls are flat, from {:?}",
+                    geometry.hulls.len() - hulls.len(),
+                    geometry.source
+                );
+            }
+            if hulls.is_empty() {
+                continue;
+            }
+
+            let (axis, angle) = rotation.to_axis_angle();
+            let rigid_body = RigidBodyBuilder::fixed()
+                .translation(vector![translation.x, translation.y, translation.z])
+                .rotation(vector![axis.x, axis.y, axis.z] * angle);
+            let handle = self.rigid_bodies.insert(rigid_body);
+            let collider_handle = self.colliders.insert_with_parent(
+                ColliderBuilder::compound(hulls),
+                handle,
+                &mut self.rigid_bodies,
+            );
+            self.collider_handles.insert(entity, collider_handle);
+            self.static_colliders.insert(collider_handle);
+        }
+        info!(
+            self.logger,
+            "built {} static colliders",
+            self.static_colliders.len()
+        );
+    }
+
+    /// Hand the fixed colliders to occlusion, which casts against a copy of
+    /// them off this thread. They're only built on load, so it's never stale.
+    fn setup_occlusion(&mut self) {
+        let occluders = self
+            .collider_handles
+            .iter()
+            .filter_map(|(entity, handle)| {
+                let collider = self.colliders.get(*handle)?;
+                let body = self.rigid_bodies.get(collider.parent()?)?;
+                body.is_fixed().then(|| Occluder {
+                    entity: *entity,
+                    position: *collider.position(),
+                    shape: collider.shared_shape().clone(),
+                })
+            })
+            .collect::<Vec<_>>();
+        info!(
+            self.logger,
+            "occluding audio with {} colliders",
+            occluders.len()
+        );
+        self.occlusion.load(occluders);
+    }
+
     // Create ground collider
     fn setup_ground_collider(&mut self, world: &mut World) {
         let ground_size = 10.0;
@@ -211,21 +401,197 @@ impl WorldUpdate {
     }
 }
 
-fn mark_clean_updated_nodes(world: &mut WorldExt, world_transforms_updated: &[Entity]) {
-    for node in world
+impl WorldUpdate {
+    /// Move projectiles along their velocity and despawn those whose lifetime
+    /// has run out. On the server, a projectile that hits a collider along the
+    /// way damages its entity and is despawned, clients only see it go.
+    ///
+    /// Projectiles move far in a tick, so hits are found by casting a ray
+    /// along the distance moved rather than from contacts, against colliders
+    /// moved to where the tick's physics step left their bodies.
+    fn update_projectiles(&self, world: &mut WorldExt, dt: Duration) {
+        let is_server = world.is_server();
+        let mut despawned = Vec::new();
+        let mut hits = Vec::new();
+        for (entity, (node, velocity, lifetime, projectile, pooled)) in world
+            .world
+            .hecs_world
+            .query::<(
+                &mut SpatialHierarchyNode,
+                &Velocity,
+                &mut Lifetime,
+                &Projectile,
+                Option<&Pooled>,
+            )>()
+            .iter()
+        {
+            if pooled.map_or(false, |pooled| !pooled.active) {
+                continue;
+            }
+            let travel = velocity.linear * dt.as_secs_f32();
+            if is_server {
+                if let Some(hit) = self.cast_projectile(node.get_pos(), travel, entity, projectile)
+                {
+                    hits.push((hit, projectile.damage));
+                    despawned.push(entity);
+                    continue;
+                }
+            }
+            node.translate(travel);
+            if lifetime.tick(dt) {
+                despawned.push(entity);
+            }
+        }
+
+        for (hit, damage) in hits {
+            if let Ok(mut health) = world.world.hecs_world.get::<&mut HealthFacet>(hit) {
+                health.take_dmg(damage);
+                trace!(self.logger, "projectile hit {hit:?}, {} hp left", health.hp);
+                world.world.record(JournalEvent::Damaged {
+                    entity: hit,
+                    damage,
+                    hp: health.hp,
+                });
+            }
+            if world.world.players.contains(&hit) {
+                let strength = damage as f32 / HIT_RUMBLE_FULL_DAMAGE as f32;
+                world
+                    .world
+                    .rumble(hit, Rumble::new(RumblePattern::Pulse, strength));
+            }
+        }
+        for entity in despawned {
+            // Entities were just seen in the query, so they exist.
+            world.world.despawn(entity).unwrap();
+        }
+    }
+
+    /// The closest entity with a collider hit by a projectile moving `travel`
+    /// from `from`, ignoring itself and whoever fired it.
+    fn cast_projectile(
+        &self,
+        from: Vec3,
+        travel: Vec3,
+        projectile_entity: Entity,
+        projectile: &Projectile,
+    ) -> Option<Entity> {
+        if travel == Vec3::ZERO {
+            return None;
+        }
+        let ray = Ray::new(
+            point![from.x, from.y, from.z],
+            vector![travel.x, travel.y, travel.z],
+        );
+        self.collider_handles
+            .iter()
+            .filter(|(entity, _)| {
+                **entity != projectile_entity && Some(**entity) != projectile.owner
+            })
+            .filter_map(|(entity, handle)| {
+                let collider = self.colliders.get(*handle)?;
+                // A time of impact of 1 is the end of the distance moved.
+                let toi = collider
+                    .shape()
+                    .cast_ray(collider.position(), &ray, 1.0, true)?;
+                Some((*entity, toi))
+            })
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(entity, _)| entity)
+    }
+}
+
+/// Move entities with a `MotionPattern` to where they are at the current server
+/// time. Every world does this for itself, so they aren't replicated.
+fn animate_motion_patterns(world: &mut WorldExt) {
+    let time = world.world.clock.now(Instant::now());
+    for (_entity, (pattern, node)) in world
         .world
         .hecs_world
-        .query::<&mut SpatialHierarchyNode>()
+        .query::<(&MotionPattern, &mut SpatialHierarchyNode)>()
         .iter()
-        .filter_map(|(e, node)| {
-            if world_transforms_updated.contains(&e) {
-                Some(node)
-            } else {
-                None
-            }
-        })
     {
-        node.set_clean();
+        let translation = pattern
+            .translation_at(time)
+            .unwrap_or_else(|| node.get_pos());
+        let rotation = pattern
+            .rotation_at(time)
+            .unwrap_or_else(|| node.get_rotation());
+        node.set_translation_rotation(translation, rotation);
+    }
+}
+
+/// Turn the player this world is seen from by the mouse look since the last
+/// update: its body by the yaw, so movement follows it, and its camera by the
+/// pitch. A client's player is moved by the server, so there only the camera
+/// is tilted.
+fn apply_mouse_look(world: &mut WorldExt) {
+    let world = &mut *world.world;
+    let (yaw, pitch) = std::mem::take(&mut world.pending_look);
+    if (yaw, pitch) == (0.0, 0.0) {
+        return;
+    }
+    let Some(entity) = world.camera() else {
+        return;
+    };
+    let is_server = world.is_server();
+    let query = world
+        .hecs_world
+        .query_one_mut::<(&mut Camera, &mut SpatialHierarchyNode, &WorldTransform)>(entity);
+    if let Ok((camera, node, transform)) = query {
+        if is_server {
+            node.local_rotate(Vec3::new(0.0, yaw, 0.0));
+        }
+        camera.pitch = (camera.pitch + pitch).clamp(-MAX_LOOK_PITCH, MAX_LOOK_PITCH);
+        camera.update_view_matrix(transform);
+    }
+}
+
+/// Move entities replicated from the server to where their policy, or the
+/// world's, puts them at the current server time, between or beyond the
+/// updates buffered for them.
+fn move_replicated_entities(world: &mut WorldExt) {
+    let world = &mut *world.world;
+    let server_now = world.clock.now(Instant::now());
+    let default_policy = world.config.replication;
+    let connection_delay = world
+        .config
+        .interpolation_delay
+        .unwrap_or_else(|| world.connection_quality.quality().interpolation_delay());
+    for (_entity, (policy, buffer, node)) in world.hecs_world.query_mut::<(
+        Option<&ReplicationPolicy>,
+        &ReplicationBuffer,
+        &mut SpatialHierarchyNode,
+    )>() {
+        let policy = policy.copied().unwrap_or(default_policy);
+        if let Some((position, rotation)) = buffer.pose_at(policy, server_now, connection_delay) {
+            node.set_translation_rotation(position, rotation);
+        }
+    }
+}
+
+/// Record the new world transforms of physics bodies, for renderers to
+/// interpolate between. Bodies seen for the first time get a `PhysicsPose`.
+fn update_physics_poses(world: &mut WorldExt, world_transforms_updated: &[Entity]) {
+    let now = Instant::now();
+    let mut missing = Vec::new();
+    for (entity, (world_transform, pose)) in world
+        .world
+        .hecs_world
+        .query::<(&WorldTransform, Option<&mut PhysicsPose>)>()
+        .with::<&PhysicsBody>()
+        .iter()
+    {
+        match pose {
+            Some(pose) if world_transforms_updated.contains(&entity) => {
+                pose.update(world_transform.world, now);
+            }
+            Some(_) => {}
+            None => missing.push((entity, PhysicsPose::new(world_transform.world, now))),
+        }
+    }
+    for (entity, pose) in missing {
+        // The entity was just seen in the query, so it exists.
+        world.world.hecs_world.insert_one(entity, pose).unwrap();
     }
 }
 
@@ -268,10 +634,21 @@ impl<'a> WorldExt<'a> {
             //
             // TODO: deal with hardcoded players
             //
-            if let Some(server_controller) = self.world.server_controller_state {
+            // Input held since the last tick, weighted by how long it was held.
+            let now = Instant::now();
+            let mut server_input = self.world.server_input.take(now);
+            let client_input = self.world.client_input.take(now);
+            // The server's own player is the local one.
+            let playing = self.world.input_macros.is_playing();
+            if let Some(state) = self.world.input_macros.tick(server_input.state()) {
+                server_input = HeldInput::constant(state, server_input.duration());
+            } else if playing {
+                self.world
+                    .notify(Severity::Info, "input_macro", "input macro finished");
+            }
+            if self.world.server_controller_state.is_some() {
                 let entity = self.world.player(0).unwrap();
-                if let Err(err) =
-                    self.move_camera_based_on_controller_state(&server_controller, entity)
+                if let Err(err) = self.move_camera_based_on_controller_state(&server_input, entity)
                 {
                     error!(self.logger, "Do any entities have a camera?");
                     let entitites = self.world.hecs_world.iter();
@@ -291,10 +668,9 @@ impl<'a> WorldExt<'a> {
                     panic!("exiting...");
                 }
             }
-            if let Some(client_controller) = self.world.client_controller_state {
+            if self.world.client_controller_state.is_some() {
                 let entity = self.world.player(1).unwrap();
-                if let Err(err) =
-                    self.move_camera_based_on_controller_state(&client_controller, entity)
+                if let Err(err) = self.move_camera_based_on_controller_state(&client_input, entity)
                 {
                     error!(self.logger, "Do any entities have a camera?");
                     let entitites = self.world.hecs_world.iter();
@@ -324,13 +700,15 @@ impl<'a> WorldExt<'a> {
                 let angular = control.angular_intention * action_scale;
                 spatial.local_rotate(angular);
             }
+            // Only count completed ticks, so they happen every SIM_TICK_DELAY
+            // regardless of frame rate.
+            self.set_last_tick(now);
         }
-        self.set_last_tick(Instant::now());
     }
 
     fn move_camera_based_on_controller_state(
         &mut self,
-        controller: &InputState,
+        controller: &HeldInput,
         entity: Entity,
     ) -> Result<(), WorldError> {
         let mut query = self
@@ -341,11 +719,8 @@ impl<'a> WorldExt<'a> {
 
         let (camera, control, spatial, physics) = query.get().ok_or(WorldError::NoSuchCamera)?;
 
-        let speed = if controller.is_button_pressed(Button::Cancel) {
-            5.0
-        } else {
-            2.0
-        };
+        // Running for part of the tick goes part of the way faster.
+        let speed = 2.0 + 3.0 * controller.held_fraction(Button::Cancel);
 
         if self.world.stats.updates % 120 == 0 && entity == self.world.player(0).unwrap() {
             info!(
@@ -358,21 +733,20 @@ impl<'a> WorldExt<'a> {
 
         let forward = spatial.forward();
 
-        if controller.is_button_pressed(Button::Down) {
-            control.linear_intention += forward * speed;
-        } else if controller.is_button_pressed(Button::Up) {
-            control.linear_intention -= forward * speed;
+        // Held for part of the tick pushes part as hard, and opposite
+        // directions held over the same tick cancel out.
+        let (down, up) = (
+            controller.held_fraction(Button::Down),
+            controller.held_fraction(Button::Up),
+        );
+        if down > 0.0 || up > 0.0 {
+            control.linear_intention += forward * speed * (down - up);
         } else {
             control.linear_intention = Vec3::ZERO;
         }
 
-        if controller.is_button_pressed(Button::Left) {
-            control.angular_intention.y = -1.0 * speed;
-        } else if controller.is_button_pressed(Button::Right) {
-            control.angular_intention.y = speed;
-        } else {
-            control.angular_intention.y = 0.0;
-        }
+        let turn = controller.held_fraction(Button::Right) - controller.held_fraction(Button::Left);
+        control.angular_intention.y = speed * turn;
 
         camera.update_view_matrix(&spatial);
 
@@ -0,0 +1,168 @@
+//! Audio occlusion: how much fixed geometry lies between the `AudioListener`
+//! and each `AudioEmitter`. Rays are cast a few times a second on a worker
+//! core, against a copy of the fixed colliders taken on load, and emitters
+//! ease toward what was found every update so they don't step between casts.
+
+use std::collections::HashMap;
+use std::num::NonZeroUsize;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use core_executor::ThreadAffineExecutor;
+use glam::Vec3;
+use rapier3d::na::{self as nalgebra, point, vector};
+use rapier3d::prelude::{Isometry, Ray, SharedShape};
+use world::components::{AudioEmitter, AudioListener, WorldTransform};
+use world::{Entity, World};
+
+/// How often occlusion is cast.
+const CAST_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Occlusion added by each occluder between the listener and an emitter.
+const OCCLUSION_PER_OCCLUDER: f32 = 0.5;
+
+/// Time an emitter takes to ease from unoccluded to fully occluded.
+const FADE: Duration = Duration::from_millis(200);
+
+/// Core occlusion is cast on, next to the network IO thread's on the last.
+fn occlusion_core() -> usize {
+    std::thread::available_parallelism()
+        .map_or(1, NonZeroUsize::get)
+        .saturating_sub(2)
+}
+
+/// A fixed collider sounds can be muffled by, and the entity it was built for.
+pub(crate) struct Occluder {
+    pub entity: Entity,
+    pub position: Isometry<f32>,
+    pub shape: SharedShape,
+}
+
+pub(crate) struct Occlusion {
+    occluders: Arc<[Occluder]>,
+    // Started on load, so a system that's only constructed has no thread.
+    executor: Option<ThreadAffineExecutor>,
+    results_tx: Sender<Vec<(Entity, f32)>>,
+    results: Receiver<Vec<(Entity, f32)>>,
+    // A cast has been queued, and hasn't reported back yet.
+    casting: bool,
+    last_cast: Option<Instant>,
+    /// Occlusion found by the last cast, which emitters ease toward.
+    targets: HashMap<Entity, f32>,
+}
+
+impl Occlusion {
+    pub fn new() -> Self {
+        let (results_tx, results) = mpsc::channel();
+        Self {
+            occluders: Arc::new([]),
+            executor: None,
+            results_tx,
+            results,
+            casting: false,
+            last_cast: None,
+            targets: HashMap::new(),
+        }
+    }
+
+    /// Cast against `occluders` from now on, starting the worker if it isn't.
+    pub fn load(&mut self, occluders: Vec<Occluder>) {
+        self.occluders = occluders.into();
+        self.executor
+            .get_or_insert_with(|| ThreadAffineExecutor::new(occlusion_core()));
+    }
+
+    /// Take what the last cast found, queue the next one when it's due, and
+    /// ease each emitter's occlusion toward what was found for it.
+    pub fn update(&mut self, world: &mut World, dt: Duration) {
+        while let Ok(found) = self.results.try_recv() {
+            self.targets = found.into_iter().collect();
+            self.casting = false;
+        }
+
+        let now = Instant::now();
+        let due = match self.last_cast {
+            Some(last) => now.duration_since(last) >= CAST_INTERVAL,
+            None => true,
+        };
+        if due && !self.casting {
+            self.last_cast = Some(now);
+            self.cast(world);
+        }
+
+        let step = dt.as_secs_f32() / FADE.as_secs_f32();
+        for (entity, emitter) in world.hecs_world.query_mut::<&mut AudioEmitter>() {
+            let target = self.targets.get(&entity).copied().unwrap_or(0.0);
+            let change = (target - emitter.occlusion).clamp(-step, step);
+            emitter.occlusion += change;
+        }
+    }
+
+    /// Queue a cast from the listener to every emitter. Without a listener
+    /// nothing is heard through geometry, so emitters ease back to none.
+    fn cast(&mut self, world: &mut World) {
+        let executor = match &self.executor {
+            Some(executor) => executor,
+            None => return,
+        };
+        let listener = world
+            .hecs_world
+            .query_mut::<(&AudioListener, &WorldTransform)>()
+            .into_iter()
+            .next()
+            .map(|(_, (_, transform))| transform.get_pos());
+        let listener = match listener {
+            Some(listener) => listener,
+            None => {
+                self.targets.clear();
+                return;
+            }
+        };
+        let emitters = world
+            .hecs_world
+            .query_mut::<(&AudioEmitter, &WorldTransform)>()
+            .into_iter()
+            .map(|(entity, (_, transform))| (entity, transform.get_pos()))
+            .collect::<Vec<_>>();
+        if emitters.is_empty() || self.occluders.is_empty() {
+            self.targets.clear();
+            return;
+        }
+
+        let occluders = Arc::clone(&self.occluders);
+        let results_tx = self.results_tx.clone();
+        // Skipped when the worker is behind, the next cast catches up.
+        self.casting = executor.spawner.fire_or_drop(async move {
+            // Only fails once the system is gone, when nobody's listening.
+            let _ = results_tx.send(occlusion(&occluders, listener, &emitters));
+        });
+    }
+}
+
+/// The occlusion of each emitter heard from `listener`, by how many occluders
+/// are in the way. An emitter isn't occluded by its own entity's collider.
+fn occlusion(
+    occluders: &[Occluder],
+    listener: Vec3,
+    emitters: &[(Entity, Vec3)],
+) -> Vec<(Entity, f32)> {
+    emitters
+        .iter()
+        .map(|(entity, at)| {
+            let towards = *at - listener;
+            let ray = Ray::new(
+                point![listener.x, listener.y, listener.z],
+                vector![towards.x, towards.y, towards.z],
+            );
+            // A time of impact of 1 is the emitter.
+            let in_the_way = occluders
+                .iter()
+                .filter(|occluder| occluder.entity != *entity)
+                .filter(|occluder| occluder.shape.intersects_ray(&occluder.position, &ray, 1.0))
+                .count();
+            let occlusion = (in_the_way as f32 * OCCLUSION_PER_OCCLUDER).min(1.0);
+            (*entity, occlusion)
+        })
+        .collect()
+}
@@ -0,0 +1,178 @@
+//! Draws the physics state through `World::debug_draw`: collider shapes and
+//! contacts using rapier's debug render pipeline, plus the vehicle's wheel
+//! rays and the velocities of rigid bodies, which rapier doesn't draw.
+//! Colliders built from imported collision geometry are drawn in their own
+//! category, joints are drawn with the other colliders.
+
+use std::collections::HashSet;
+
+use glam::{vec4, Vec3, Vec4};
+use rapier3d::control::DynamicRayCastVehicleController;
+use rapier3d::pipeline::{
+    DebugRenderBackend, DebugRenderMode, DebugRenderObject, DebugRenderPipeline, DebugRenderStyle,
+};
+use rapier3d::prelude::{
+    ColliderHandle, ColliderSet, ImpulseJointSet, MultibodyJointSet, NarrowPhase, Point, Real,
+    RigidBodySet, Vector,
+};
+use world::debug_draw::{DebugCategories, DebugDraw};
+
+/// Every category this module draws in.
+const PHYSICS_CATEGORIES: DebugCategories = DebugCategories::PHYSICS;
+
+const WHEEL_IN_CONTACT: Vec4 = vec4(0.0, 1.0, 0.0, 1.0);
+const WHEEL_IN_AIR: Vec4 = vec4(1.0, 0.0, 0.0, 1.0);
+const LINEAR_VELOCITY: Vec4 = vec4(1.0, 1.0, 0.0, 1.0);
+const ANGULAR_VELOCITY: Vec4 = vec4(1.0, 0.0, 1.0, 1.0);
+
+/// The physics state to draw.
+pub(crate) struct PhysicsState<'a> {
+    pub rigid_bodies: &'a RigidBodySet,
+    pub colliders: &'a ColliderSet,
+    pub impulse_joints: &'a ImpulseJointSet,
+    pub multibody_joints: &'a MultibodyJointSet,
+    pub narrow_phase: &'a NarrowPhase,
+    pub vehicle: Option<&'a DynamicRayCastVehicleController>,
+    pub static_colliders: &'a HashSet<ColliderHandle>,
+}
+
+pub(crate) struct PhysicsDebug {
+    pipeline: DebugRenderPipeline,
+}
+
+impl PhysicsDebug {
+    pub fn new() -> Self {
+        Self {
+            pipeline: DebugRenderPipeline::new(
+                DebugRenderStyle::default(),
+                DebugRenderMode::empty(),
+            ),
+        }
+    }
+
+    /// Replace the physics lines in `draw` with the current state, for the
+    /// categories that are enabled.
+    pub fn draw(&mut self, draw: &mut DebugDraw, physics: &PhysicsState) {
+        draw.clear(PHYSICS_CATEGORIES);
+        let enabled = draw.enabled();
+        if !enabled.intersects(PHYSICS_CATEGORIES) {
+            return;
+        }
+
+        let mut mode = DebugRenderMode::empty();
+        if enabled.intersects(DebugCategories::COLLIDERS | DebugCategories::STATIC_COLLISION) {
+            mode |= DebugRenderMode::COLLIDER_SHAPES;
+        }
+        if enabled.contains(DebugCategories::COLLIDERS) {
+            mode |= DebugRenderMode::JOINTS;
+        }
+        if enabled.contains(DebugCategories::CONTACTS) {
+            mode |= DebugRenderMode::CONTACTS;
+        }
+        if !mode.is_empty() {
+            self.pipeline.mode = mode;
+            self.pipeline.render(
+                &mut Backend {
+                    draw,
+                    static_colliders: physics.static_colliders,
+                },
+                physics.rigid_bodies,
+                physics.colliders,
+                physics.impulse_joints,
+                physics.multibody_joints,
+                physics.narrow_phase,
+            );
+        }
+
+        if let Some(vehicle) = physics
+            .vehicle
+            .filter(|_| enabled.contains(DebugCategories::WHEEL_RAYS))
+        {
+            for wheel in vehicle.wheels() {
+                let ray = wheel.raycast_info();
+                let color = if ray.is_in_contact {
+                    WHEEL_IN_CONTACT
+                } else {
+                    WHEEL_IN_AIR
+                };
+                draw.line(
+                    DebugCategories::WHEEL_RAYS,
+                    to_vec3(&ray.hard_point_ws.coords),
+                    to_vec3(&ray.contact_point_ws.coords),
+                    color,
+                );
+            }
+        }
+
+        if enabled.contains(DebugCategories::VELOCITIES) {
+            for (_handle, body) in physics.rigid_bodies.iter() {
+                if !body.is_dynamic() {
+                    continue;
+                }
+                let center = to_vec3(&body.center_of_mass().coords);
+                draw.line(
+                    DebugCategories::VELOCITIES,
+                    center,
+                    center + to_vec3(body.linvel()),
+                    LINEAR_VELOCITY,
+                );
+                draw.line(
+                    DebugCategories::VELOCITIES,
+                    center,
+                    center + to_vec3(body.angvel()),
+                    ANGULAR_VELOCITY,
+                );
+            }
+        }
+    }
+}
+
+struct Backend<'a> {
+    draw: &'a mut DebugDraw,
+    static_colliders: &'a HashSet<ColliderHandle>,
+}
+
+impl DebugRenderBackend for Backend<'_> {
+    fn draw_line(
+        &mut self,
+        object: DebugRenderObject,
+        a: Point<Real>,
+        b: Point<Real>,
+        color: [f32; 4],
+    ) {
+        let category = match object {
+            DebugRenderObject::ContactPair(..) => DebugCategories::CONTACTS,
+            DebugRenderObject::Collider(handle, _) if self.static_colliders.contains(&handle) => {
+                DebugCategories::STATIC_COLLISION
+            }
+            _ => DebugCategories::COLLIDERS,
+        };
+        self.draw.line(
+            category,
+            to_vec3(&a.coords),
+            to_vec3(&b.coords),
+            hsla_to_rgba(color),
+        );
+    }
+}
+
+fn to_vec3(v: &Vector<Real>) -> Vec3 {
+    Vec3::new(v.x, v.y, v.z)
+}
+
+/// Rapier's debug colors are hue (in degrees), saturation, lightness and alpha.
+fn hsla_to_rgba([h, s, l, a]: [f32; 4]) -> Vec4 {
+    let chroma = (1.0 - (2.0 * l - 1.0).abs()) * s;
+    let h = (h / 60.0).rem_euclid(6.0);
+    let x = chroma * (1.0 - (h % 2.0 - 1.0).abs());
+    let (r, g, b) = match h as u32 {
+        0 => (chroma, x, 0.0),
+        1 => (x, chroma, 0.0),
+        2 => (0.0, chroma, x),
+        3 => (0.0, x, chroma),
+        4 => (x, 0.0, chroma),
+        _ => (chroma, 0.0, x),
+    };
+    let m = l - chroma / 2.0;
+    vec4(r + m, g + m, b + m, a)
+}
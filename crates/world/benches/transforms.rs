@@ -0,0 +1,43 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use glam::{Mat4, Vec3};
+use world::components::spatial::{self, SpatialHierarchyNode};
+use world::components::WorldTransform;
+
+const STATIC_ENTITIES: usize = 10_000;
+
+/// A root with `count` children spread over a grid, as a level's static props.
+fn static_scene(count: usize) -> hecs::World {
+    let mut world = hecs::World::new();
+    let root = world.spawn((WorldTransform::default(),));
+    let side = (count as f32).sqrt().ceil() as usize;
+    world.spawn_batch((0..count).map(|i| {
+        let pos = Vec3::new((i % side) as f32, 0.0, (i / side) as f32) * 2.0;
+        (
+            SpatialHierarchyNode::new_at(root, pos),
+            WorldTransform::default(),
+        )
+    }));
+    spatial::update_world_transforms(&mut world, Mat4::IDENTITY);
+    world
+}
+
+fn transform_update_benchmark(c: &mut Criterion) {
+    let mut group = c.benchmark_group("transform_update");
+    let mut world = static_scene(STATIC_ENTITIES);
+
+    // The usual frame, where nothing has moved.
+    group.bench_function(format!("static_{STATIC_ENTITIES}_clean"), |b| {
+        b.iter(|| spatial::update_world_transforms(black_box(&mut world), Mat4::IDENTITY))
+    });
+    // After a scene is loaded, or the root moves.
+    group.bench_function(format!("static_{STATIC_ENTITIES}_all_dirty"), |b| {
+        b.iter(|| {
+            spatial::mark_all_dirty(&mut world);
+            spatial::update_world_transforms(black_box(&mut world), Mat4::IDENTITY)
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, transform_update_benchmark);
+criterion_main!(benches);
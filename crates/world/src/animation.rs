@@ -0,0 +1,240 @@
+//! Skeletal animation of meshes spawned from glTF scenes, see
+//! `World::add_gltf_scene`. A skinned node gets a `Skeleton` of the entities
+//! spawned for its joints, and its drawables are `Skinned` by it. Inserting an
+//! `Animator` beside the skeleton plays a clip on those joints.
+//!
+//! Each update `sample_animations` poses the joints of every playing skeleton
+//! before world transforms are updated, and `update_joint_matrices` then
+//! works out, from where the joints ended up, the matrices renderers hand to
+//! the skinning vertex shader.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use gfx::{AnimationClip, Channel};
+use glam::Mat4;
+use hecs::Entity;
+use interner::Symbol;
+
+use crate::components::spatial::SpatialHierarchyNode;
+use crate::components::WorldTransform;
+
+/// The joints deforming a skinned mesh, on the entity of the node drawing it.
+#[derive(Debug, Clone)]
+pub struct Skeleton {
+    /// Entity of each joint, in the order `SkinWeights` index them.
+    pub joints: Vec<Entity>,
+    /// Node of the glTF scene each joint was spawned from, and its name,
+    /// which the channels of clips refer to.
+    pub nodes: Vec<usize>,
+    pub names: Vec<Option<Symbol>>,
+    pub inverse_bind_matrices: Vec<Mat4>,
+    /// Clips of the scene the skeleton was spawned from.
+    pub clips: Vec<Arc<AnimationClip>>,
+    /// For each joint, the transform from the bind pose of the mesh to
+    /// where the joint has moved it, in the model space of the mesh. As of
+    /// the last `update_joint_matrices`.
+    pub joint_matrices: Vec<Mat4>,
+}
+
+impl Skeleton {
+    /// The clip named `name`, of the scene the skeleton was spawned from.
+    pub fn clip(&self, name: &str) -> Option<Arc<AnimationClip>> {
+        self.clips
+            .iter()
+            .find(|clip| clip.name.map(Symbol::as_str) == Some(name))
+            .cloned()
+    }
+
+    /// The joint a channel moves: the one named as its node is, or failing
+    /// that the one spawned from its node. Clips from other files with the
+    /// same skeleton are matched by name.
+    fn joint_of(&self, channel: &Channel) -> Option<usize> {
+        channel
+            .node_name
+            .and_then(|name| self.names.iter().position(|&joint| joint == Some(name)))
+            .or_else(|| self.nodes.iter().position(|&node| node == channel.node))
+    }
+}
+
+/// A drawable deformed by the `Skeleton` of another entity, or its own.
+#[derive(Debug, Copy, Clone)]
+pub struct Skinned {
+    pub skeleton: Entity,
+}
+
+/// Plays a clip on the joints of the `Skeleton` beside it.
+#[derive(Debug, Clone)]
+pub struct Animator {
+    pub clip: Arc<AnimationClip>,
+    /// Seconds into the clip.
+    pub time: f32,
+    /// How fast the clip plays, 1 at its own pace.
+    pub speed: f32,
+    /// Whether the clip starts over when it ends, rather than holding its
+    /// last keyframes.
+    pub looping: bool,
+}
+
+impl Animator {
+    pub fn new(clip: Arc<AnimationClip>, looping: bool) -> Self {
+        Self {
+            clip,
+            time: 0.0,
+            speed: 1.0,
+            looping,
+        }
+    }
+
+    /// Whether a clip that doesn't loop has played to its end.
+    pub fn is_finished(&self) -> bool {
+        !self.looping && self.time >= self.clip.duration
+    }
+
+    fn advance(&mut self, dt: Duration) {
+        self.time += dt.as_secs_f32() * self.speed;
+        if self.looping && self.clip.duration > 0.0 {
+            self.time = self.time.rem_euclid(self.clip.duration);
+        } else {
+            self.time = self.time.clamp(0.0, self.clip.duration);
+        }
+    }
+}
+
+/// Advance every `Animator` by `dt`, and pose the joints of its skeleton as
+/// its clip has them then. Call before world transforms are updated.
+pub fn sample_animations(world: &mut hecs::World, dt: Duration) {
+    let mut poses = Vec::new();
+    for (_entity, (skeleton, animator)) in world.query_mut::<(&Skeleton, &mut Animator)>() {
+        animator.advance(dt);
+        for (channel, sample) in animator.clip.sample(animator.time) {
+            if let Some(joint) = skeleton.joint_of(channel) {
+                poses.push((skeleton.joints[joint], sample));
+            }
+        }
+    }
+    for (joint, sample) in poses {
+        if let Ok(mut node) = world.get::<&mut SpatialHierarchyNode>(joint) {
+            node.transform = sample.apply(node.transform);
+            node.mark_updated();
+        }
+    }
+}
+
+/// Work out the joint matrices of every skeleton from the world transforms
+/// of its joints and of the mesh. Call after world transforms are updated.
+pub fn update_joint_matrices(world: &mut hecs::World) {
+    let mut transforms_query = world.query::<&WorldTransform>();
+    let transforms = transforms_query.view();
+    for (_entity, (skeleton, mesh_transform)) in
+        world.query::<(&mut Skeleton, &WorldTransform)>().iter()
+    {
+        let mesh_from_world = mesh_transform.world.inverse();
+        let Skeleton {
+            joints,
+            inverse_bind_matrices,
+            joint_matrices,
+            ..
+        } = skeleton;
+        joint_matrices.clear();
+        joint_matrices.extend(joints.iter().zip(inverse_bind_matrices.iter()).map(
+            |(&joint, &inverse_bind)| {
+                let joint_world = transforms
+                    .get(joint)
+                    .map_or(Mat4::IDENTITY, |transform| transform.world);
+                mesh_from_world * joint_world * inverse_bind
+            },
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::FRAC_PI_2;
+
+    use gfx::{Interpolation, Keyframes};
+    use glam::{Quat, Vec3};
+
+    use super::*;
+    use crate::components::spatial;
+
+    fn turn_clip() -> Arc<AnimationClip> {
+        Arc::new(AnimationClip::new(
+            Some(Symbol::intern("turn")),
+            vec![Channel {
+                node: 7,
+                node_name: Some(Symbol::intern("elbow")),
+                interpolation: Interpolation::Linear,
+                times: vec![0.0, 1.0],
+                keyframes: Keyframes::Rotation(vec![
+                    Quat::IDENTITY,
+                    Quat::from_rotation_z(FRAC_PI_2),
+                ]),
+            }],
+        ))
+    }
+
+    #[test]
+    fn animators_pose_joints_and_joint_matrices_follow() {
+        let mut world = hecs::World::new();
+        let root = world.spawn((WorldTransform::default(),));
+        let mesh = world.spawn((
+            SpatialHierarchyNode::new_at(root, Vec3::new(0.0, 0.0, 5.0)),
+            WorldTransform::default(),
+        ));
+        let elbow_bind = Mat4::from_translation(Vec3::Y);
+        let elbow = world.spawn((
+            SpatialHierarchyNode::new_with_transform(mesh, elbow_bind),
+            WorldTransform::default(),
+        ));
+        let clip = turn_clip();
+        world
+            .insert(
+                mesh,
+                (
+                    Skeleton {
+                        joints: vec![elbow],
+                        nodes: vec![7],
+                        names: vec![Some(Symbol::intern("elbow"))],
+                        inverse_bind_matrices: vec![elbow_bind.inverse()],
+                        clips: vec![clip.clone()],
+                        joint_matrices: Vec::new(),
+                    },
+                    Animator::new(clip, false),
+                ),
+            )
+            .unwrap();
+
+        // At rest the mesh stays where it's bound.
+        spatial::update_world_transforms(&mut world, Mat4::IDENTITY);
+        update_joint_matrices(&mut world);
+        let joint_matrices = world.get::<&Skeleton>(mesh).unwrap().joint_matrices.clone();
+        assert!(joint_matrices[0].abs_diff_eq(Mat4::IDENTITY, 1e-5));
+
+        sample_animations(&mut world, Duration::from_secs(2));
+        assert!(world.get::<&Animator>(mesh).unwrap().is_finished());
+        spatial::update_world_transforms(&mut world, Mat4::IDENTITY);
+        update_joint_matrices(&mut world);
+
+        // A vertex bound a unit above the elbow swings round it.
+        let skeleton = world.get::<&Skeleton>(mesh).unwrap();
+        let vertex = skeleton.joint_matrices[0].transform_point3(Vec3::new(0.0, 2.0, 0.0));
+        assert!(
+            vertex.abs_diff_eq(Vec3::new(-1.0, 1.0, 0.0), 1e-5),
+            "{vertex}"
+        );
+        assert!(skeleton.clip("turn").is_some());
+    }
+
+    #[test]
+    fn looping_animators_start_over() {
+        let mut animator = Animator::new(turn_clip(), true);
+        animator.advance(Duration::from_millis(1250));
+        assert!((animator.time - 0.25).abs() < 1e-5);
+        assert!(!animator.is_finished());
+
+        animator.speed = -1.0;
+        animator.advance(Duration::from_millis(500));
+        assert!((animator.time - 0.75).abs() < 1e-5);
+    }
+}
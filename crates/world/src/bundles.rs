@@ -1,10 +1,15 @@
 //! Bundles for common archetypes
 
-use glam::Mat4;
+use std::time::Duration;
+
+use glam::{Mat4, Quat, Vec3};
 use hecs::{Bundle, Entity, Query};
 
 use crate::components::spatial::SpatialHierarchyNode;
-use crate::components::{Camera, Control, Drawable, PhysicsBody, WorldTransform};
+use crate::components::{
+    Camera, Control, Drawable, Lifetime, PhysicsBody, Projectile, Velocity, WorldTransform,
+};
+use crate::replication::{ReplicationPolicy, DEFAULT_EXTRAPOLATION_CAP};
 
 #[derive(Debug, Bundle)]
 pub struct StaticObject {
@@ -29,6 +34,70 @@ impl StaticObject {
     }
 }
 
+/// A drawable moving in a straight line until it hits something or its
+/// lifetime runs out, see `World::spawn_projectile`.
+#[derive(Debug, Bundle)]
+pub struct ProjectileObject {
+    pub spatial: SpatialHierarchyNode,
+    pub drawable: Drawable,
+    pub world: WorldTransform,
+    pub velocity: Velocity,
+    pub lifetime: Lifetime,
+    pub projectile: Projectile,
+    pub replication: ReplicationPolicy,
+}
+
+/// Where a projectile starts, and how it moves.
+#[derive(Debug, Clone, Copy)]
+pub struct ProjectileSpawn {
+    pub origin: Vec3,
+    pub direction: Vec3,
+    pub speed: f32,
+    pub lifetime: Duration,
+    pub gfx_prefab: Entity,
+    pub damage: u32,
+    pub owner: Option<Entity>,
+}
+
+impl ProjectileObject {
+    /// Create a projectile as a child of `parent`, usually the world root,
+    /// facing the way it moves.
+    pub fn new(parent: Entity, spawn: ProjectileSpawn) -> Self {
+        let direction = spawn.direction.normalize_or_zero();
+        let rotation = if direction == Vec3::ZERO {
+            Quat::IDENTITY
+        } else {
+            // Entities face -z, see `WorldTransform::forward`.
+            Quat::from_rotation_arc(Vec3::NEG_Z, direction)
+        };
+        let mut spatial = SpatialHierarchyNode::new(parent);
+        spatial.set_translation_rotation(spawn.origin, rotation);
+        Self {
+            spatial,
+            drawable: Drawable {
+                gfx: spawn.gfx_prefab,
+                scale: 1.0,
+            },
+            world: WorldTransform {
+                world: Mat4::from_rotation_translation(rotation, spawn.origin),
+            },
+            velocity: Velocity {
+                linear: direction * spawn.speed,
+            },
+            lifetime: Lifetime {
+                remaining: spawn.lifetime,
+            },
+            projectile: Projectile {
+                damage: spawn.damage,
+                owner: spawn.owner,
+            },
+            replication: ReplicationPolicy::Extrapolate {
+                cap: DEFAULT_EXTRAPOLATION_CAP,
+            },
+        }
+    }
+}
+
 #[derive(Debug, Bundle)]
 pub struct Player {
     pub camera: Camera,
@@ -37,6 +106,7 @@ pub struct Player {
     pub physics: PhysicsBody,
     pub spatial: SpatialHierarchyNode,
     pub world: WorldTransform,
+    pub replication: ReplicationPolicy,
 }
 // TODO: bundles move into the plugin?
 #[derive(Debug, Query)]
@@ -81,6 +151,8 @@ impl Player {
             control: Control {
                 ..Default::default()
             },
+            // Buffered for as long as the connection needs.
+            replication: ReplicationPolicy::Interpolate { delay: None },
         }
     }
 }
@@ -101,4 +173,26 @@ mod tests {
 
         assert!(matches!(query.get(), Some(_)));
     }
+
+    #[test]
+    fn projectile_faces_and_moves_along_direction() {
+        let mut world = hecs::World::new();
+        let root = world.spawn((WorldTransform::default(),));
+        let gfx = world.spawn((WorldTransform::default(),));
+        let projectile = ProjectileObject::new(
+            root,
+            ProjectileSpawn {
+                origin: Vec3::Y,
+                direction: Vec3::X * 3.0,
+                speed: 10.0,
+                lifetime: Duration::from_secs(2),
+                gfx_prefab: gfx,
+                damage: 5,
+                owner: None,
+            },
+        );
+        assert_eq!(projectile.velocity.linear, Vec3::X * 10.0);
+        assert!(projectile.world.forward().abs_diff_eq(Vec3::X, 1e-5));
+        assert!(projectile.spatial.get_pos().abs_diff_eq(Vec3::Y, 1e-5));
+    }
 }
@@ -0,0 +1,79 @@
+//! The server's clock, which every world in a session agrees on. Servers count
+//! from when their world was created, and clients estimate the server's time
+//! from the timestamps on world updates.
+
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy)]
+pub struct ServerClock {
+    started: Instant,
+    /// Server time less local time since `started`, in microseconds.
+    offset_micros: i64,
+    synced: bool,
+}
+
+impl ServerClock {
+    pub fn new(now: Instant) -> Self {
+        Self {
+            started: now,
+            offset_micros: 0,
+            synced: false,
+        }
+    }
+
+    /// Time on the server at `now`.
+    pub fn now(&self, now: Instant) -> Duration {
+        let local = now.saturating_duration_since(self.started).as_micros() as i64;
+        Duration::from_micros((local + self.offset_micros).max(0) as u64)
+    }
+
+    /// Sync to a server timestamp received at `received_at`. Timestamps only
+    /// ever arrive late, so the estimate only moves forward, towards the least
+    /// delayed timestamp seen.
+    pub fn sync(&mut self, server_time: Duration, received_at: Instant) {
+        let local = received_at
+            .saturating_duration_since(self.started)
+            .as_micros() as i64;
+        let offset = server_time.as_micros() as i64 - local;
+        if !self.synced || offset > self.offset_micros {
+            self.offset_micros = offset;
+            self.synced = true;
+        }
+    }
+
+    /// Whether a client has heard from the server yet. Servers never sync.
+    pub fn is_synced(&self) -> bool {
+        self.synced
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn client_clock_follows_least_delayed_timestamp() {
+        let started = Instant::now();
+        let mut clock = ServerClock::new(started);
+        assert_eq!(
+            clock.now(started + Duration::from_secs(1)),
+            Duration::from_secs(1)
+        );
+
+        // The server has been running 10s longer, and this packet took 50ms.
+        let received_at = started + Duration::from_secs(1);
+        clock.sync(Duration::from_millis(10_950), received_at);
+        assert!(clock.is_synced());
+        assert_eq!(clock.now(received_at), Duration::from_millis(10_950));
+
+        // A packet that was delayed longer doesn't move the clock back.
+        clock.sync(Duration::from_millis(10_900), received_at);
+        assert_eq!(clock.now(received_at), Duration::from_millis(10_950));
+
+        clock.sync(Duration::from_millis(10_990), received_at);
+        assert_eq!(
+            clock.now(received_at + Duration::from_secs(1)),
+            Duration::from_millis(11_990)
+        );
+    }
+}
@@ -0,0 +1,187 @@
+//! Static collision geometry for models, simpler than what's drawn.
+//!
+//! A model's collision is authored next to it as `<model>_collision.obj`, each
+//! object in which is a convex brush. Models without one collide as the convex
+//! hull of their render mesh. The geometry is attached to the model's prefab,
+//! and colliders are built from it for the static objects drawing that prefab
+//! when physics is set up.
+
+use std::collections::HashSet;
+use std::io::{BufReader, Read};
+use std::path::{Path, PathBuf};
+
+use gfx::{GpuNeeds, Model};
+use glam::Vec3;
+use obj_parser::model::{Obj, ObjError};
+use obj_parser::parser::obj::ObjLine;
+
+/// Appended to a model's file stem to find its authored collision geometry.
+pub const COLLISION_SUFFIX: &str = "_collision";
+
+#[derive(thiserror::Error, Debug)]
+pub enum CollisionError {
+    #[error("unable to load collision geometry {path:?}: {err}")]
+    Obj { path: PathBuf, err: ObjError },
+    #[error("collision geometry {0:?} has no vertices")]
+    Empty(PathBuf),
+}
+
+/// Where a prefab's collision geometry came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CollisionSource {
+    /// Brushes from a `_collision.obj` file.
+    Authored(PathBuf),
+    /// The convex hull of the render mesh.
+    RenderMesh,
+}
+
+/// Convex hulls a prefab collides as, in its model's space. Attached to the
+/// prefab, see `World::add_collision_geometry`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CollisionGeometry {
+    /// The points each hull is built around, only the outermost of which
+    /// matter.
+    pub hulls: Vec<Vec<Vec3>>,
+    pub source: CollisionSource,
+}
+
+impl CollisionGeometry {
+    /// Where the collision geometry authored for the model at `model_path`
+    /// would be: `tank.obj` has `tank_collision.obj`.
+    pub fn authored_path(model_path: &Path) -> PathBuf {
+        let stem = model_path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy())
+            .unwrap_or_default();
+        let mut file_name = format!("{stem}{COLLISION_SUFFIX}");
+        if let Some(extension) = model_path.extension() {
+            file_name.push('.');
+            file_name.push_str(&extension.to_string_lossy());
+        }
+        model_path.with_file_name(file_name)
+    }
+
+    /// The model's authored collision geometry if it has any, otherwise the
+    /// hull of its render mesh.
+    pub fn for_model(model: &Model) -> Result<Self, CollisionError> {
+        if let Some(model_path) = model.source_paths().first() {
+            let authored = Self::authored_path(model_path);
+            if authored.exists() {
+                return Self::load(&authored);
+            }
+        }
+        let points = model
+            .vertices()
+            .iter()
+            .map(|vertex| Vec3::new(vertex.pos[0], vertex.pos[1], vertex.pos[2]));
+        Ok(Self {
+            hulls: vec![dedup_points(points)],
+            source: CollisionSource::RenderMesh,
+        })
+    }
+
+    /// Load brushes from an obj, a hull for each object in it.
+    pub fn load(path: &Path) -> Result<Self, CollisionError> {
+        let obj = Obj::load(path).map_err(|err| CollisionError::Obj {
+            path: path.to_path_buf(),
+            err,
+        })?;
+        Self::from_obj(&obj, path)
+    }
+
+    /// Read brushes from obj text, for geometry that isn't in a file.
+    pub fn from_reader(reader: impl Read, path: &Path) -> Result<Self, CollisionError> {
+        let obj = Obj::from_reader(BufReader::new(reader)).map_err(|err| CollisionError::Obj {
+            path: path.to_path_buf(),
+            err,
+        })?;
+        Self::from_obj(&obj, path)
+    }
+
+    fn from_obj(obj: &Obj, path: &Path) -> Result<Self, CollisionError> {
+        let hulls = obj
+            .objects
+            .iter()
+            .map(|object| {
+                dedup_points(object.vertices().iter().filter_map(|line| match line {
+                    ObjLine::Vertex(x, y, z, _) => Some(Vec3::new(*x, *y, *z)),
+                    _ => None,
+                }))
+            })
+            .filter(|hull| !hull.is_empty())
+            .collect::<Vec<_>>();
+        if hulls.is_empty() {
+            return Err(CollisionError::Empty(path.to_path_buf()));
+        }
+        Ok(Self {
+            hulls,
+            source: CollisionSource::Authored(path.to_path_buf()),
+        })
+    }
+
+    /// The geometry scaled, for an object drawn at a scale.
+    pub fn scaled(&self, scale: Vec3) -> Vec<Vec<Vec3>> {
+        self.hulls
+            .iter()
+            .map(|hull| hull.iter().map(|point| *point * scale).collect())
+            .collect()
+    }
+}
+
+/// Render meshes repeat a position for each normal and texture coordinate it's
+/// used with.
+fn dedup_points(points: impl Iterator<Item = Vec3>) -> Vec<Vec3> {
+    let mut seen = HashSet::new();
+    points
+        .filter(|point| seen.insert(point.to_array().map(f32::to_bits)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn authored_next_to_the_model() {
+        assert_eq!(
+            CollisionGeometry::authored_path(Path::new("assets/models/static/tank.obj")),
+            Path::new("assets/models/static/tank_collision.obj")
+        );
+    }
+
+    #[test]
+    fn a_hull_for_each_brush() {
+        let obj = "\
+o floor
+v -1 0 -1
+v 1 0 -1
+v 1 0 1
+v -1 0 1
+v -1 0 -1
+o pillar
+v 0 0 0
+v 0 2 0
+v 0.5 0 0
+v 0 0 0.5
+";
+        let path = Path::new("brushes_collision.obj");
+        let geometry = CollisionGeometry::from_reader(obj.as_bytes(), path).unwrap();
+        assert_eq!(
+            geometry.source,
+            CollisionSource::Authored(path.to_path_buf())
+        );
+        assert_eq!(geometry.hulls.len(), 2);
+        // The repeated corner is only kept once.
+        assert_eq!(geometry.hulls[0].len(), 4);
+        assert_eq!(geometry.hulls[1][1], Vec3::new(0.0, 2.0, 0.0));
+        assert_eq!(
+            geometry.scaled(Vec3::splat(2.0))[1][1],
+            Vec3::new(0.0, 4.0, 0.0)
+        );
+
+        assert!(matches!(
+            CollisionGeometry::from_reader("# nothing\n".as_bytes(), path),
+            Err(CollisionError::Empty(_))
+        ));
+    }
+}
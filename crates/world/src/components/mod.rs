@@ -1,8 +1,15 @@
 pub mod spatial;
 
+use std::f64::consts::TAU;
+use std::ops::BitOr;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
 use gfx::Graphic;
-use glam::{Mat4, Vec3};
+use glam::{Mat4, Quat, Vec3, Vec4};
 use hecs::Entity;
+use interner::Symbol;
+use shader_objects::MAX_SHADER_PARAMS;
 
 use crate::graphics::{Shape, EULER_ROT_ORDER};
 use crate::World;
@@ -14,6 +21,10 @@ pub struct Camera {
     pub projection: Mat4,
     pub view: Mat4,
     pub occlusion_culling: bool,
+    /// Layers of the drawables seen by the camera, see `RenderLayers`.
+    pub layers: RenderLayers,
+    /// Radians the camera is tilted up from its entity, by mouse look.
+    pub pitch: f32,
 }
 
 impl Camera {
@@ -31,8 +42,10 @@ impl Camera {
                 1000.0, //far
             ),
 
-            // because it's not supported yet
-            occlusion_culling: false,
+            // drawables hidden behind `RenderFlags::OCCLUDER`s are skipped
+            occlusion_culling: true,
+            layers: RenderLayers::DEFAULT,
+            pitch: 0.0,
         };
         camera.update_view_matrix(world_transform);
         camera
@@ -42,7 +55,9 @@ impl Camera {
         // fine to offset the camera somewhat here, but the orientation of the model
         // should be done at the entity level. I.e. spatial hierarchy entity
         let camera_offset = Mat4::from_translation(Vec3::new(0.0, 1.0, 0.0));
-        self.view = (world.world * camera_offset).inverse();
+        // The camera looks down +z, so tilting it up turns +z toward +y.
+        let tilt = Mat4::from_rotation_x(-self.pitch);
+        self.view = (world.world * camera_offset * tilt).inverse();
     }
 
     pub fn set_perspective(&mut self, fov: f32, aspect: f32, near: f32, far: f32) {
@@ -69,6 +84,203 @@ pub struct Drawable {
     pub scale: f32,
 }
 
+/// Parameters passed to the shaders of a drawable, such as a dissolve amount
+/// or UV scroll speed. What each slot means is up to the shader, and drawables
+/// without them get zeroes.
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct ShaderParams(pub [Vec4; MAX_SHADER_PARAMS]);
+
+impl ShaderParams {
+    /// Set the parameter in `slot`, which must be less than
+    /// `MAX_SHADER_PARAMS`.
+    pub fn with(mut self, slot: usize, value: Vec4) -> Self {
+        self.0[slot] = value;
+        self
+    }
+}
+
+/// A light shining in every direction from the entity's `WorldTransform`,
+/// fading out to nothing at `radius`. Lights are culled per cluster of the
+/// view, so a scene can have hundreds of them.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct PointLight {
+    pub color: Vec3,
+    pub intensity: f32,
+    pub radius: f32,
+}
+
+impl PointLight {
+    pub fn new(color: Vec3, intensity: f32, radius: f32) -> Self {
+        Self {
+            color,
+            intensity,
+            radius,
+        }
+    }
+}
+
+/// A light shining from the entity's `WorldTransform` the way it faces, see
+/// `WorldTransform::forward`, in a cone fading out from `inner_angle` to
+/// `outer_angle` off its axis, in radians, and out to nothing at `radius`.
+/// The spot and directional lights nearest the view are shaded by every
+/// fragment, so a scene should only have a handful in view at once.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SpotLight {
+    pub color: Vec3,
+    pub intensity: f32,
+    pub radius: f32,
+    pub inner_angle: f32,
+    pub outer_angle: f32,
+}
+
+impl SpotLight {
+    pub fn new(
+        color: Vec3,
+        intensity: f32,
+        radius: f32,
+        inner_angle: f32,
+        outer_angle: f32,
+    ) -> Self {
+        Self {
+            color,
+            intensity,
+            radius,
+            inner_angle,
+            outer_angle,
+        }
+    }
+}
+
+/// A light shining everywhere the way the entity's `WorldTransform` faces,
+/// like the sun.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct DirectionalLight {
+    pub color: Vec3,
+    pub intensity: f32,
+}
+
+impl DirectionalLight {
+    pub fn new(color: Vec3, intensity: f32) -> Self {
+        Self { color, intensity }
+    }
+}
+
+/// A point the surroundings are captured from, at the entity's
+/// `WorldTransform`, into a cubemap reflected by shiny drawables within
+/// `radius`. Probes are captured when they're added, moved or removed, and
+/// again on `World::recapture_reflection_probes`, so they're meant to stay
+/// put.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ReflectionProbe {
+    pub radius: f32,
+}
+
+impl ReflectionProbe {
+    pub fn new(radius: f32) -> Self {
+        Self { radius }
+    }
+}
+
+/// Flags controlling how a drawable is rendered.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct RenderFlags(u32);
+
+impl RenderFlags {
+    pub const NONE: Self = Self(0);
+    /// Large and solid enough to hide what is behind it. The bounds of
+    /// occluders are used for occlusion culling, so they should be roughly
+    /// box-shaped.
+    pub const OCCLUDER: Self = Self(1);
+    /// Always drawn, even when behind an occluder.
+    pub const NEVER_OCCLUDED: Self = Self(1 << 1);
+    /// Not drawn at all, such as a pooled entity waiting to be reused.
+    pub const HIDDEN: Self = Self(1 << 2);
+
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn intersects(self, other: Self) -> bool {
+        self.0 & other.0 != 0
+    }
+
+    pub fn insert(&mut self, other: Self) {
+        self.0 |= other.0;
+    }
+
+    pub fn remove(&mut self, other: Self) {
+        self.0 &= !other.0;
+    }
+}
+
+impl BitOr for RenderFlags {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+/// Layers a drawable is on, drawn by cameras whose `Camera::layers` share at
+/// least one of them. Drawables without the component are on
+/// `RenderLayers::DEFAULT`, as are cameras unless set otherwise, so a
+/// first-person model can be on a layer only the player's camera sees, or a
+/// minimap marker on one only the minimap's camera sees, without spawning
+/// copies of entities for each view.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct RenderLayers(u32);
+
+impl RenderLayers {
+    pub const NONE: Self = Self(0);
+    pub const DEFAULT: Self = Self::layer(0);
+    pub const ALL: Self = Self(u32::MAX);
+    /// Layers there are, numbered from 0.
+    pub const COUNT: u32 = u32::BITS;
+
+    /// Only the layer `layer`, which must be less than `COUNT`.
+    pub const fn layer(layer: u32) -> Self {
+        Self(1 << layer)
+    }
+
+    /// These layers and `layer` as well.
+    pub const fn with(self, layer: u32) -> Self {
+        Self(self.0 | Self::layer(layer).0)
+    }
+
+    /// These layers without `layer`.
+    pub const fn without(self, layer: u32) -> Self {
+        Self(self.0 & !Self::layer(layer).0)
+    }
+
+    pub fn contains(self, layer: u32) -> bool {
+        self.intersects(Self::layer(layer))
+    }
+
+    /// Whether any layer is in both, such as a drawable's and a camera's, for
+    /// the camera to draw it.
+    pub fn intersects(self, other: Self) -> bool {
+        self.0 & other.0 != 0
+    }
+
+    pub fn bits(self) -> u32 {
+        self.0
+    }
+}
+
+impl Default for RenderLayers {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+impl BitOr for RenderLayers {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
 /// Prefab of a graphic, represented as an entity.
 #[derive(Debug)]
 pub struct GraphicPrefab {
@@ -81,6 +293,11 @@ impl GraphicPrefab {
     }
 }
 
+/// The name of an entity, such as that of the glTF node it was spawned from.
+/// Interned, so entities are found by name by comparing ids.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Name(pub Symbol);
+
 /// Dynamic physics objects have a rigidbody.
 /// TODO: revisit this and store handles for physics lookups?
 #[derive(Debug, Default)]
@@ -92,6 +309,201 @@ pub struct PhysicsBody {
     pub mass: f32,
 }
 
+/// A joint between the rigid bodies of two entities, spawned as an entity of
+/// its own so a body can have any number of them, see `World::add_joint`. The
+/// physics system creates the joint when this is added, rebuilds it when it
+/// changes, and removes it along with this component or either body.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Joint {
+    pub kind: JointKind,
+    pub first: Entity,
+    pub second: Entity,
+    /// Where the joint attaches to the first body, relative to the body.
+    pub first_anchor: Vec3,
+    /// Where the joint attaches to the second body, relative to the body.
+    pub second_anchor: Vec3,
+}
+
+impl Joint {
+    /// A joint attached at the origin of both bodies.
+    pub fn new(kind: JointKind, first: Entity, second: Entity) -> Self {
+        Self {
+            kind,
+            first,
+            second,
+            first_anchor: Vec3::ZERO,
+            second_anchor: Vec3::ZERO,
+        }
+    }
+
+    pub fn with_anchors(mut self, first_anchor: Vec3, second_anchor: Vec3) -> Self {
+        self.first_anchor = first_anchor;
+        self.second_anchor = second_anchor;
+        self
+    }
+}
+
+/// How the bodies of a `Joint` can move relative to each other. Axes are
+/// relative to the first body, and limits are in radians for rotation and
+/// units for translation.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum JointKind {
+    /// The bodies move as one.
+    Fixed,
+    /// The bodies rotate about `axis` through the anchors, like a hinge.
+    Revolute {
+        axis: Vec3,
+        limits: Option<[f32; 2]>,
+    },
+    /// The bodies slide along `axis`, without rotating.
+    Prismatic {
+        axis: Vec3,
+        limits: Option<[f32; 2]>,
+    },
+    /// The bodies rotate freely about the anchors, like a ball and socket,
+    /// such as a trailer hitch or a ragdoll's shoulder.
+    Spherical,
+}
+
+/// World transforms of a `PhysicsBody` at its last two updates, whether from
+/// a simulation tick or the network. Renderers draw a blend of the two, so
+/// bodies move smoothly when frames are shorter than ticks, at the cost of
+/// drawing them up to one update behind.
+#[derive(Debug, Clone, Copy)]
+pub struct PhysicsPose {
+    previous: Mat4,
+    current: Mat4,
+    updated_at: Instant,
+    interval: Duration,
+}
+
+impl PhysicsPose {
+    /// Updates further apart than this snap to the new pose, instead of
+    /// sliding slowly towards it.
+    pub const MAX_INTERPOLATION_INTERVAL: Duration = Duration::from_millis(100);
+
+    pub fn new(world: Mat4, now: Instant) -> Self {
+        Self {
+            previous: world,
+            current: world,
+            updated_at: now,
+            interval: Duration::ZERO,
+        }
+    }
+
+    /// Record the body's latest world transform.
+    pub fn update(&mut self, world: Mat4, now: Instant) {
+        let interval = now.saturating_duration_since(self.updated_at);
+        self.previous = if interval > Self::MAX_INTERPOLATION_INTERVAL {
+            world
+        } else {
+            self.current
+        };
+        self.current = world;
+        self.updated_at = now;
+        self.interval = interval;
+    }
+
+    pub fn current(&self) -> Mat4 {
+        self.current
+    }
+
+    /// How far from the previous to the current pose the body is drawn at
+    /// `now`, where 1.0 is the current pose.
+    pub fn alpha(&self, now: Instant) -> f32 {
+        if self.interval.is_zero() {
+            return 1.0;
+        }
+        let elapsed = now.saturating_duration_since(self.updated_at);
+        (elapsed.as_secs_f32() / self.interval.as_secs_f32()).min(1.0)
+    }
+
+    /// The world transform to draw the body with at `now`.
+    pub fn interpolated(&self, now: Instant) -> Mat4 {
+        let alpha = self.alpha(now);
+        if alpha >= 1.0 {
+            return self.current;
+        }
+        let (prev_scale, prev_rot, prev_trans) = self.previous.to_scale_rotation_translation();
+        let (scale, rot, trans) = self.current.to_scale_rotation_translation();
+        Mat4::from_scale_rotation_translation(
+            prev_scale.lerp(scale, alpha),
+            prev_rot.slerp(rot, alpha),
+            prev_trans.lerp(trans, alpha),
+        )
+    }
+}
+
+/// Motion of simple moving scenery such as doors, elevators and rotators. It's
+/// evaluated from the server clock rather than simulated, so every world moves
+/// the entity the same way without it being replicated.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MotionPattern {
+    /// Spin about `axis` at `rate` radians per second.
+    Rotate { axis: Vec3, rate: f32 },
+    /// Move back and forth between `from` and `to`, easing at either end,
+    /// taking `period` for the round trip.
+    Oscillate {
+        from: Vec3,
+        to: Vec3,
+        period: Duration,
+    },
+    /// Move through `points` at `speed` units per second, returning from the
+    /// last to the first.
+    Waypoints { points: Vec<Vec3>, speed: f32 },
+}
+
+impl MotionPattern {
+    /// The translation at `time` on the server clock, relative to the
+    /// entity's parent. None if the pattern leaves the translation alone.
+    pub fn translation_at(&self, time: Duration) -> Option<Vec3> {
+        // Time is kept in f64, so long running servers don't lose precision.
+        let secs = time.as_secs_f64();
+        match self {
+            MotionPattern::Rotate { .. } => None,
+            MotionPattern::Oscillate { from, to, period } => {
+                if period.is_zero() {
+                    return Some(*from);
+                }
+                let phase = (secs % period.as_secs_f64()) / period.as_secs_f64();
+                let t = (1.0 - (phase * TAU).cos()) / 2.0;
+                Some(from.lerp(*to, t as f32))
+            }
+            MotionPattern::Waypoints { points, speed } => {
+                let segments = points
+                    .iter()
+                    .zip(points.iter().cycle().skip(1))
+                    .map(|(start, end)| (*start, *end, start.distance(*end) as f64))
+                    .collect::<Vec<_>>();
+                let length = segments.iter().map(|(_, _, len)| len).sum::<f64>();
+                if length <= 0.0 {
+                    return points.first().copied();
+                }
+                let mut distance = (secs * *speed as f64).rem_euclid(length);
+                for (start, end, len) in segments {
+                    if distance < len {
+                        return Some(start.lerp(end, (distance / len) as f32));
+                    }
+                    distance -= len;
+                }
+                points.first().copied()
+            }
+        }
+    }
+
+    /// The rotation at `time` on the server clock, relative to the entity's
+    /// parent. None if the pattern leaves the rotation alone.
+    pub fn rotation_at(&self, time: Duration) -> Option<Quat> {
+        match self {
+            MotionPattern::Rotate { axis, rate } => {
+                let angle = (time.as_secs_f64() * *rate as f64).rem_euclid(TAU);
+                Some(Quat::from_axis_angle(axis.normalize(), angle as f32))
+            }
+            MotionPattern::Oscillate { .. } | MotionPattern::Waypoints { .. } => None,
+        }
+    }
+}
+
 #[derive(Debug, Default)]
 pub struct Shaped {
     pub shape: Shape,
@@ -125,10 +537,137 @@ impl WorldTransform {
     }
 }
 
-/// A component representing an audio source.
-#[derive(Debug, Default)]
+/// Present on a `GraphicPrefab` that has been reloaded from disk since it was
+/// spawned. Renderers upload the prefab again if their copy is older than `at`,
+/// and keep drawing their previous copy until the new one is ready, swapping
+/// them between frames.
+#[derive(Debug, Clone, Copy)]
+pub struct ReloadedGraphic {
+    pub at: Instant,
+}
+
+/// A component representing an audio source. Positional sources are attenuated
+/// by their distance to the `AudioListener`.
+#[derive(Debug)]
 pub struct AudioSource {
     enabled: bool,
+    pub gain: f32,
+    /// Within this distance the source plays at full volume.
+    pub min_distance: f32,
+    /// Beyond this distance the source is inaudible.
+    pub max_distance: f32,
+}
+
+impl Default for AudioSource {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            gain: 1.0,
+            min_distance: 1.0,
+            max_distance: 100.0,
+        }
+    }
+}
+
+impl AudioSource {
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    /// Distance between a source positioned at `source` and the listener.
+    pub fn listener_distance(source: &WorldTransform, listener: &WorldTransform) -> f32 {
+        source.get_pos().distance(listener.get_pos())
+    }
+}
+
+/// Marks the entity that hears positional audio, usually the active camera.
+#[derive(Debug, Default)]
+pub struct AudioListener;
+
+/// Plays sounds from where an entity is, attenuated by its distance to the
+/// `AudioListener`. Sounds are named by the path of a WAV or OGG file, loaded
+/// the first time it's played.
+#[derive(Debug, Clone)]
+pub struct AudioEmitter {
+    /// Sound played over and over while the emitter is on an entity.
+    pub looping: Option<PathBuf>,
+    pub gain: f32,
+    /// Within this distance sounds play at full volume.
+    pub min_distance: f32,
+    /// Beyond this distance sounds are inaudible.
+    pub max_distance: f32,
+    /// How muffled sounds are by geometry between the emitter and the
+    /// listener, from 0 for not at all to 1. Kept up to date by world update.
+    pub occlusion: f32,
+    /// Sounds to play once, started on the next frame.
+    one_shots: Vec<PathBuf>,
+}
+
+impl Default for AudioEmitter {
+    fn default() -> Self {
+        Self {
+            looping: None,
+            gain: 1.0,
+            min_distance: 1.0,
+            max_distance: 100.0,
+            occlusion: 0.0,
+            one_shots: Vec::new(),
+        }
+    }
+}
+
+impl AudioEmitter {
+    /// An emitter playing `sound` over and over.
+    pub fn looping(sound: impl Into<PathBuf>) -> Self {
+        Self {
+            looping: Some(sound.into()),
+            ..Self::default()
+        }
+    }
+
+    /// Play `sound` once from the emitter, alongside anything it's playing.
+    pub fn play(&mut self, sound: impl Into<PathBuf>) {
+        self.one_shots.push(sound.into());
+    }
+
+    /// The sounds to start playing once, since they were last taken.
+    pub fn take_one_shots(&mut self) -> Vec<PathBuf> {
+        std::mem::take(&mut self.one_shots)
+    }
+}
+
+/// Linear velocity in world space, for entities moved by integrating it rather
+/// than by physics, such as projectiles.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Velocity {
+    pub linear: Vec3,
+}
+
+/// Time left before the entity is despawned.
+#[derive(Debug, Clone, Copy)]
+pub struct Lifetime {
+    pub remaining: Duration,
+}
+
+impl Lifetime {
+    /// Count down by `dt`, returning true once the lifetime has run out.
+    pub fn tick(&mut self, dt: Duration) -> bool {
+        self.remaining = self.remaining.saturating_sub(dt);
+        self.remaining.is_zero()
+    }
+}
+
+/// Damage dealt to the first thing a projectile hits, after which it's
+/// despawned.
+#[derive(Debug, Clone, Copy)]
+pub struct Projectile {
+    pub damage: u32,
+    /// Who fired it, which it passes through.
+    pub owner: Option<Entity>,
 }
 
 #[cfg(test)]
@@ -165,4 +704,97 @@ mod tests {
         // add a single component
         world.insert_one(entity, AudioSource::default()).unwrap();
     }
+
+    #[test]
+    fn physics_pose_blends_between_updates() {
+        let start = Instant::now();
+        let mut pose = PhysicsPose::new(Mat4::IDENTITY, start);
+        assert_eq!(pose.interpolated(start), Mat4::IDENTITY);
+
+        let tick = Duration::from_millis(10);
+        let moved = Mat4::from_translation(Vec3::X * 2.0);
+        pose.update(moved, start + tick);
+        let halfway = pose.interpolated(start + tick + tick / 2);
+        assert!(halfway.w_axis.truncate().abs_diff_eq(Vec3::X, 1e-5));
+        assert_eq!(pose.interpolated(start + tick * 3), moved);
+
+        // A long gap between updates snaps to the new pose.
+        let far = Mat4::from_translation(Vec3::Y * 5.0);
+        pose.update(far, start + Duration::from_secs(1));
+        assert!(pose
+            .interpolated(start + Duration::from_secs(1))
+            .abs_diff_eq(far, 1e-5));
+    }
+
+    #[test]
+    fn cameras_draw_drawables_sharing_a_layer() {
+        let first_person = RenderLayers::layer(1);
+        let minimap = RenderLayers::NONE.with(2);
+        let player_camera = RenderLayers::default().with(1);
+        assert!(RenderLayers::default().intersects(player_camera));
+        assert!(first_person.intersects(player_camera));
+        assert!(!minimap.intersects(player_camera));
+        assert!(!first_person.intersects(RenderLayers::DEFAULT));
+        assert!(RenderLayers::ALL.intersects(minimap));
+        assert!(!RenderLayers::NONE.intersects(RenderLayers::ALL));
+
+        let debug = (first_person | minimap).without(1);
+        assert_eq!(debug, minimap);
+        assert!(debug.contains(2) && !debug.contains(1));
+        assert_eq!(RenderLayers::layer(RenderLayers::COUNT - 1).bits(), 1 << 31);
+    }
+
+    #[test]
+    fn pitch_tilts_the_camera_up() {
+        let transform = WorldTransform {
+            world: Mat4::from_translation(Vec3::new(3.0, 0.0, 0.0)),
+        };
+        let mut camera = Camera::new(&transform);
+        let eye = |camera: &Camera| camera.view.inverse();
+        assert!(eye(&camera)
+            .transform_vector3(Vec3::Z)
+            .abs_diff_eq(Vec3::Z, 1e-5));
+
+        camera.pitch = 0.5;
+        camera.update_view_matrix(&transform);
+        let looking = eye(&camera).transform_vector3(Vec3::Z);
+        assert!(looking.abs_diff_eq(Vec3::new(0.0, 0.5f32.sin(), 0.5f32.cos()), 1e-5));
+        // Tilting turns the camera in place.
+        assert!(eye(&camera)
+            .transform_point3(Vec3::ZERO)
+            .abs_diff_eq(Vec3::new(3.0, 1.0, 0.0), 1e-5));
+    }
+
+    #[test]
+    fn motion_patterns_are_periodic() {
+        let oscillate = MotionPattern::Oscillate {
+            from: Vec3::ZERO,
+            to: Vec3::X,
+            period: Duration::from_secs(2),
+        };
+        let at = |secs: f32| oscillate.translation_at(Duration::from_secs_f32(secs));
+        assert!(at(0.0).unwrap().abs_diff_eq(Vec3::ZERO, 1e-5));
+        assert!(at(1.0).unwrap().abs_diff_eq(Vec3::X, 1e-5));
+        assert!(at(1000.5).unwrap().abs_diff_eq(at(0.5).unwrap(), 1e-4));
+        assert_eq!(oscillate.rotation_at(Duration::from_secs(1)), None);
+
+        let waypoints = MotionPattern::Waypoints {
+            points: vec![Vec3::ZERO, Vec3::X, Vec3::new(1.0, 1.0, 0.0)],
+            speed: 1.0,
+        };
+        let at = |secs: f32| waypoints.translation_at(Duration::from_secs_f32(secs));
+        assert!(at(0.5).unwrap().abs_diff_eq(Vec3::new(0.5, 0.0, 0.0), 1e-5));
+        assert!(at(1.5).unwrap().abs_diff_eq(Vec3::new(1.0, 0.5, 0.0), 1e-5));
+        // The loop back to the start is sqrt(2) long.
+        let length = 2.0 + std::f32::consts::SQRT_2;
+        assert!(at(length).unwrap().abs_diff_eq(Vec3::ZERO, 1e-4));
+
+        let rotate = MotionPattern::Rotate {
+            axis: Vec3::Y,
+            rate: std::f32::consts::PI,
+        };
+        let half_turn = rotate.rotation_at(Duration::from_secs(1)).unwrap();
+        assert!((half_turn * Vec3::X).abs_diff_eq(-Vec3::X, 1e-5));
+        assert_eq!(rotate.translation_at(Duration::from_secs(1)), None);
+    }
 }
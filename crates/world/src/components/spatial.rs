@@ -1,6 +1,9 @@
-use glam::{Mat4, Vec3};
+use std::collections::HashMap;
+
+use glam::{Mat4, Quat, Vec3};
 use hecs::Entity;
 
+use super::WorldTransform;
 use crate::graphics::EULER_ROT_ORDER;
 
 /// TODO: Docs
@@ -47,6 +50,15 @@ impl SpatialHierarchyNode {
         }
     }
 
+    /// Construct a new node with a parent and a transform relative to it.
+    pub fn new_with_transform(parent: Entity, transform: Mat4) -> Self {
+        Self {
+            transform,
+            updated: true,
+            parent,
+        }
+    }
+
     /// Construct a new node from the existing one with a new rotation in
     /// EULER_ROT_ORDER.
     pub fn with_angles(self, angles: Vec3) -> Self {
@@ -107,6 +119,19 @@ impl SpatialHierarchyNode {
         rot.to_euler(EULER_ROT_ORDER).into()
     }
 
+    /// Get the rotation of this transform.
+    pub fn get_rotation(&self) -> Quat {
+        let (_scale, rot, _trans) = self.transform.to_scale_rotation_translation();
+        rot
+    }
+
+    /// Replace the translation and rotation of this node, keeping its scale.
+    pub fn set_translation_rotation(&mut self, translation: Vec3, rotation: Quat) {
+        let scale = self.get_scale();
+        self.transform = Mat4::from_scale_rotation_translation(scale, rotation, translation);
+        self.mark_updated();
+    }
+
     /// Get the scale of this transform.
     pub fn get_scale(&self) -> Vec3 {
         let (scale, _rot, _trans) = self.transform.to_scale_rotation_translation();
@@ -128,3 +153,122 @@ impl SpatialHierarchyNode {
         self.transform.y_axis.truncate().normalize()
     }
 }
+
+/// Recompute the world transforms of dirty nodes, relative to `root`, and
+/// mark them clean. A node is recomputed if it or any of its ancestors is
+/// dirty, so moving a parent moves its children too. Returns the entities
+/// whose world transforms were recomputed.
+pub fn update_world_transforms(world: &mut hecs::World, root: Mat4) -> Vec<Entity> {
+    let dirty = dirty_nodes(world);
+    let mut updated = vec![];
+    {
+        let mut nodes_query = world.query::<&SpatialHierarchyNode>();
+        let nodes = nodes_query.view();
+        for (entity, (node, world_transform)) in world
+            .query::<(&SpatialHierarchyNode, &mut WorldTransform)>()
+            .iter()
+            .filter(|(entity, _)| dirty.get(entity).copied().unwrap_or(false))
+        {
+            let mut relative_matrix = node.transform;
+            let mut ancestor = node.parent;
+            while let Some(next) = nodes.get(ancestor) {
+                relative_matrix = next.transform * relative_matrix;
+                ancestor = next.parent;
+            }
+            world_transform.world = root * relative_matrix;
+            updated.push(entity);
+        }
+    }
+    for &entity in updated.iter() {
+        if let Ok(mut node) = world.get::<&mut SpatialHierarchyNode>(entity) {
+            node.set_clean();
+        }
+    }
+    updated
+}
+
+/// Whether each node or any of its ancestors is dirty. Every ancestor chain is
+/// walked once, however many nodes share it.
+fn dirty_nodes(world: &hecs::World) -> HashMap<Entity, bool> {
+    let mut nodes_query = world.query::<&SpatialHierarchyNode>();
+    let nodes = nodes_query.view();
+    let mut dirty = HashMap::new();
+    let mut chain = vec![];
+    for (entity, _) in world.query::<&SpatialHierarchyNode>().iter() {
+        // Walk up to the root, or to a node already known.
+        let mut next = entity;
+        let mut inherited = false;
+        while let Some(node) = nodes.get(next) {
+            if let Some(&known) = dirty.get(&next) {
+                inherited = known;
+                break;
+            }
+            chain.push((next, node.is_dirty()));
+            next = node.parent;
+        }
+        for (entity, node_dirty) in chain.drain(..).rev() {
+            inherited |= node_dirty;
+            dirty.insert(entity, inherited);
+        }
+    }
+    dirty
+}
+
+/// Mark every node dirty, so the next update recomputes every world transform,
+/// such as after loading a scene.
+pub fn mark_all_dirty(world: &mut hecs::World) {
+    for (_, node) in world.query_mut::<&mut SpatialHierarchyNode>() {
+        node.mark_updated();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A chain of nodes, each a unit along x from its parent.
+    fn chain(world: &mut hecs::World, len: usize) -> Vec<Entity> {
+        let root = world.spawn((WorldTransform::default(),));
+        let mut parent = root;
+        (0..len)
+            .map(|_| {
+                parent = world.spawn((
+                    SpatialHierarchyNode::new_at(parent, Vec3::X),
+                    WorldTransform::default(),
+                ));
+                parent
+            })
+            .collect()
+    }
+
+    fn pos(world: &hecs::World, entity: Entity) -> Vec3 {
+        world.get::<&WorldTransform>(entity).unwrap().get_pos()
+    }
+
+    #[test]
+    fn dirty_parents_update_their_descendants() {
+        let mut world = hecs::World::new();
+        let nodes = chain(&mut world, 6);
+        assert_eq!(update_world_transforms(&mut world, Mat4::IDENTITY), nodes);
+        assert_eq!(pos(&world, nodes[5]), Vec3::X * 6.0);
+        assert!(update_world_transforms(&mut world, Mat4::IDENTITY).is_empty());
+
+        // Moving a node in the middle moves it and everything below it.
+        world
+            .get::<&mut SpatialHierarchyNode>(nodes[2])
+            .unwrap()
+            .translate(Vec3::Y);
+        let mut updated = update_world_transforms(&mut world, Mat4::IDENTITY);
+        updated.sort();
+        let mut expected = nodes[2..].to_vec();
+        expected.sort();
+        assert_eq!(updated, expected);
+        assert_eq!(pos(&world, nodes[1]), Vec3::X * 2.0);
+        assert_eq!(pos(&world, nodes[5]), Vec3::new(6.0, 1.0, 0.0));
+
+        mark_all_dirty(&mut world);
+        let root = Mat4::from_translation(Vec3::Z);
+        assert_eq!(update_world_transforms(&mut world, root).len(), nodes.len());
+        assert_eq!(pos(&world, nodes[0]), Vec3::new(1.0, 0.0, 1.0));
+    }
+}
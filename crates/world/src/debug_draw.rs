@@ -0,0 +1,279 @@
+//! Immediate mode debug drawing. Systems add lines in world space under a
+//! category, and the renderer draws whatever lines are present each frame.
+//! Lines in categories that aren't enabled are dropped as they're added, so
+//! drawing is cheap to leave in place.
+
+use std::ops::BitOr;
+
+use glam::{vec4, Vec3, Vec4};
+
+/// Categories of debug lines, which can be enabled independently.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct DebugCategories(u32);
+
+impl DebugCategories {
+    pub const NONE: Self = Self(0);
+    /// Outlines of physics collider shapes.
+    pub const COLLIDERS: Self = Self(1);
+    /// Contact points and normals between colliders.
+    pub const CONTACTS: Self = Self(1 << 1);
+    /// Suspension rays cast by vehicle wheels.
+    pub const WHEEL_RAYS: Self = Self(1 << 2);
+    /// Linear and angular velocities of rigid bodies.
+    pub const VELOCITIES: Self = Self(1 << 3);
+    /// A grid on the ground plane around the camera.
+    pub const GRID: Self = Self(1 << 4);
+    /// Arrows along the world axes at the origin.
+    pub const AXES: Self = Self(1 << 5);
+    /// Colliders built from imported static collision geometry, drawn apart
+    /// from the other colliders.
+    pub const STATIC_COLLISION: Self = Self(1 << 6);
+    /// Every category drawn from the physics state.
+    pub const PHYSICS: Self = Self(0b100_1111);
+    pub const ALL: Self = Self(0b111_1111);
+
+    const NAMES: [(&'static str, Self); 7] = [
+        ("colliders", Self::COLLIDERS),
+        ("contacts", Self::CONTACTS),
+        ("wheel_rays", Self::WHEEL_RAYS),
+        ("velocities", Self::VELOCITIES),
+        ("grid", Self::GRID),
+        ("axes", Self::AXES),
+        ("static_collision", Self::STATIC_COLLISION),
+    ];
+
+    /// Look up a single category by name, `-` and `_` are interchangeable.
+    pub fn from_name(name: &str) -> Option<Self> {
+        let name = name.replace('-', "_");
+        if name == "all" {
+            return Some(Self::ALL);
+        }
+        Self::NAMES
+            .iter()
+            .find(|(category, _)| *category == name)
+            .map(|(_, flags)| *flags)
+    }
+
+    /// Names of the single categories set.
+    pub fn names(self) -> impl Iterator<Item = &'static str> {
+        Self::NAMES
+            .into_iter()
+            .filter(move |(_, flags)| self.contains(*flags))
+            .map(|(name, _)| name)
+    }
+
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn intersects(self, other: Self) -> bool {
+        self.0 & other.0 != 0
+    }
+
+    pub fn insert(&mut self, other: Self) {
+        self.0 |= other.0;
+    }
+
+    pub fn remove(&mut self, other: Self) {
+        self.0 &= !other.0;
+    }
+}
+
+impl BitOr for DebugCategories {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+/// Half the width of the ground grid, which follows the camera so it seems to
+/// go on forever.
+pub const GRID_HALF_EXTENT: f32 = 50.0;
+/// Distance between grid lines, every `GRID_MAJOR_EVERY`th of them brighter.
+pub const GRID_SPACING: f32 = 1.0;
+pub const GRID_MAJOR_EVERY: i32 = 10;
+pub const AXIS_LENGTH: f32 = 2.0;
+
+const GRID_MINOR: Vec4 = vec4(0.35, 0.35, 0.35, 1.0);
+const GRID_MAJOR: Vec4 = vec4(0.6, 0.6, 0.6, 1.0);
+const AXIS_X: Vec4 = vec4(1.0, 0.2, 0.2, 1.0);
+const AXIS_Y: Vec4 = vec4(0.2, 1.0, 0.2, 1.0);
+const AXIS_Z: Vec4 = vec4(0.2, 0.4, 1.0, 1.0);
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct DebugLine {
+    pub start: Vec3,
+    pub end: Vec3,
+    pub color: Vec4,
+    pub category: DebugCategories,
+}
+
+#[derive(Debug, Default)]
+pub struct DebugDraw {
+    enabled: DebugCategories,
+    lines: Vec<DebugLine>,
+}
+
+impl DebugDraw {
+    pub fn enabled(&self) -> DebugCategories {
+        self.enabled
+    }
+
+    /// Toggle categories at runtime. Lines already drawn in categories being
+    /// disabled are removed.
+    pub fn set_enabled(&mut self, categories: DebugCategories, enabled: bool) {
+        if enabled {
+            self.enabled.insert(categories);
+        } else {
+            self.enabled.remove(categories);
+            self.clear(categories);
+        }
+    }
+
+    pub fn is_enabled(&self, category: DebugCategories) -> bool {
+        self.enabled.intersects(category)
+    }
+
+    /// Add a line, if its category is enabled.
+    pub fn line(&mut self, category: DebugCategories, start: Vec3, end: Vec3, color: Vec4) {
+        if self.is_enabled(category) {
+            self.lines.push(DebugLine {
+                start,
+                end,
+                color,
+                category,
+            });
+        }
+    }
+
+    /// Remove the lines in `categories`, for a system to redraw them.
+    pub fn clear(&mut self, categories: DebugCategories) {
+        self.lines
+            .retain(|line| !categories.intersects(line.category));
+    }
+
+    pub fn lines(&self) -> &[DebugLine] {
+        &self.lines
+    }
+
+    /// Replace the grid and axes with ones around `focus`, usually the
+    /// camera's position, for the categories that are enabled.
+    pub fn draw_reference(&mut self, focus: Vec3) {
+        self.clear(DebugCategories::GRID | DebugCategories::AXES);
+        if self.is_enabled(DebugCategories::GRID) {
+            self.grid(focus, GRID_HALF_EXTENT, GRID_SPACING);
+        }
+        if self.is_enabled(DebugCategories::AXES) {
+            self.origin_axes(AXIS_LENGTH);
+        }
+    }
+
+    /// Lines on the y = 0 plane every `spacing`, out to `half_extent` from
+    /// `focus` in x and z. The lines snap to multiples of `spacing`, so the
+    /// grid stays put in the world as the focus moves, and the lines through
+    /// the origin are colored for the axis they run along.
+    pub fn grid(&mut self, focus: Vec3, half_extent: f32, spacing: f32) {
+        let lines = (half_extent / spacing).ceil() as i32;
+        let center_x = (focus.x / spacing).round() as i32;
+        let center_z = (focus.z / spacing).round() as i32;
+        let (min_x, max_x) = (center_x - lines, center_x + lines);
+        let (min_z, max_z) = (center_z - lines, center_z + lines);
+        let color = |index: i32, axis: Vec4| match index {
+            0 => axis,
+            _ if index % GRID_MAJOR_EVERY == 0 => GRID_MAJOR,
+            _ => GRID_MINOR,
+        };
+        for x in min_x..=max_x {
+            self.line(
+                DebugCategories::GRID,
+                Vec3::new(x as f32 * spacing, 0.0, min_z as f32 * spacing),
+                Vec3::new(x as f32 * spacing, 0.0, max_z as f32 * spacing),
+                color(x, AXIS_Z),
+            );
+        }
+        for z in min_z..=max_z {
+            self.line(
+                DebugCategories::GRID,
+                Vec3::new(min_x as f32 * spacing, 0.0, z as f32 * spacing),
+                Vec3::new(max_x as f32 * spacing, 0.0, z as f32 * spacing),
+                color(z, AXIS_X),
+            );
+        }
+    }
+
+    /// Arrows of `length` from the origin along x, y and z, in red, green
+    /// and blue.
+    pub fn origin_axes(&mut self, length: f32) {
+        let head = length * 0.15;
+        for (axis, side, color) in [
+            (Vec3::X, Vec3::Y, AXIS_X),
+            (Vec3::Y, Vec3::X, AXIS_Y),
+            (Vec3::Z, Vec3::Y, AXIS_Z),
+        ] {
+            let tip = axis * length;
+            let base = tip - axis * head;
+            self.line(DebugCategories::AXES, Vec3::ZERO, tip, color);
+            self.line(DebugCategories::AXES, tip, base + side * head * 0.5, color);
+            self.line(DebugCategories::AXES, tip, base - side * head * 0.5, color);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lines_only_kept_for_enabled_categories() {
+        let mut draw = DebugDraw::default();
+        draw.line(DebugCategories::COLLIDERS, Vec3::ZERO, Vec3::X, Vec4::ONE);
+        assert!(draw.lines().is_empty());
+
+        draw.set_enabled(DebugCategories::COLLIDERS | DebugCategories::CONTACTS, true);
+        draw.line(DebugCategories::COLLIDERS, Vec3::ZERO, Vec3::X, Vec4::ONE);
+        draw.line(DebugCategories::CONTACTS, Vec3::ZERO, Vec3::Y, Vec4::ONE);
+        draw.line(DebugCategories::VELOCITIES, Vec3::ZERO, Vec3::Z, Vec4::ONE);
+        assert_eq!(draw.lines().len(), 2);
+
+        draw.clear(DebugCategories::CONTACTS);
+        assert_eq!(draw.lines().len(), 1);
+
+        draw.set_enabled(DebugCategories::COLLIDERS, false);
+        assert!(draw.lines().is_empty());
+        assert!(draw.is_enabled(DebugCategories::CONTACTS));
+
+        assert_eq!(
+            DebugCategories::from_name("wheel-rays"),
+            Some(DebugCategories::WHEEL_RAYS)
+        );
+        assert_eq!(DebugCategories::from_name("joints"), None);
+        assert_eq!(
+            (DebugCategories::CONTACTS | DebugCategories::GRID)
+                .names()
+                .collect::<Vec<_>>(),
+            vec!["contacts", "grid"]
+        );
+    }
+
+    #[test]
+    fn grid_follows_focus_and_axes_are_redrawn() {
+        let mut draw = DebugDraw::default();
+        draw.draw_reference(Vec3::ZERO);
+        assert!(draw.lines().is_empty());
+
+        draw.set_enabled(DebugCategories::GRID | DebugCategories::AXES, true);
+        draw.grid(Vec3::new(10.4, 3.0, -0.2), 2.0, 1.0);
+        // Five lines each way, snapped around (10, -0).
+        assert_eq!(draw.lines().len(), 10);
+        assert_eq!(draw.lines()[0].start, Vec3::new(8.0, 0.0, -2.0));
+        assert_eq!(draw.lines()[9].end, Vec3::new(12.0, 0.0, 2.0));
+        // The line along x through the origin is colored for x.
+        assert_eq!(draw.lines()[7].color, AXIS_X);
+
+        draw.draw_reference(Vec3::ZERO);
+        draw.draw_reference(Vec3::ZERO);
+        let side = 2 * (GRID_HALF_EXTENT / GRID_SPACING) as usize + 1;
+        assert_eq!(draw.lines().len(), 2 * side + 9);
+    }
+}
@@ -0,0 +1,156 @@
+//! An in-engine debug UI drawn with egui. While it's enabled the engine begins
+//! a frame of the UI before systems update and ends it after they have, so
+//! systems and plugins can show windows in `DebugUi::context` from their
+//! updates. The renderer draws the meshes each frame is tessellated into over
+//! the scene.
+
+use std::mem;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+use egui::{ClippedPrimitive, Context, PlatformOutput, RawInput, TexturesDelta, Vec2};
+
+pub struct DebugUi {
+    context: Context,
+    enabled: bool,
+    /// Whether a frame was begun and hasn't ended yet.
+    in_frame: bool,
+    /// Meshes of the last frame that ended, in points.
+    primitives: Vec<ClippedPrimitive>,
+    /// Size of the screen the last frame was laid out on, in points.
+    screen_size: Vec2,
+    pixels_per_point: f32,
+    /// Changes to textures, such as the font atlas, the renderer hasn't
+    /// picked up yet. Frames can end without being drawn, so changes are
+    /// merged until they are.
+    textures: Mutex<TexturesDelta>,
+}
+
+impl Default for DebugUi {
+    fn default() -> Self {
+        Self {
+            context: Context::default(),
+            enabled: false,
+            in_frame: false,
+            primitives: Vec::new(),
+            screen_size: Vec2::ZERO,
+            pixels_per_point: 1.0,
+            textures: Mutex::new(TexturesDelta::default()),
+        }
+    }
+}
+
+impl DebugUi {
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Show or hide the UI from the next frame on.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    /// The context to show windows in, while a frame of the UI is being
+    /// built. None while the UI is hidden.
+    pub fn context(&self) -> Option<&Context> {
+        self.in_frame.then_some(&self.context)
+    }
+
+    /// Begin a frame with input from the platform, if the UI is shown.
+    pub fn begin_frame(&mut self, input: RawInput) {
+        if !self.enabled {
+            return;
+        }
+        if self.in_frame {
+            self.end_frame();
+        }
+        self.context.begin_frame(input);
+        self.in_frame = true;
+    }
+
+    /// End the frame begun with `begin_frame` and tessellate it for the
+    /// renderer, returning what the platform should do, such as change the
+    /// cursor. Without a frame begun, nothing is drawn.
+    pub fn end_frame(&mut self) -> Option<PlatformOutput> {
+        if !mem::take(&mut self.in_frame) {
+            self.primitives.clear();
+            return None;
+        }
+        let output = self.context.end_frame();
+        self.textures().append(output.textures_delta);
+        self.primitives = self.context.tessellate(output.shapes);
+        self.screen_size = self.context.screen_rect().size();
+        self.pixels_per_point = self.context.pixels_per_point();
+        Some(output.platform_output)
+    }
+
+    /// Meshes of the last frame, drawn in order over the scene.
+    pub fn primitives(&self) -> &[ClippedPrimitive] {
+        &self.primitives
+    }
+
+    /// Size of the screen the last frame was laid out on, in points.
+    pub fn screen_size(&self) -> Vec2 {
+        self.screen_size
+    }
+
+    pub fn pixels_per_point(&self) -> f32 {
+        self.pixels_per_point
+    }
+
+    /// Take the changes to textures since they were last taken, for the
+    /// renderer to apply before drawing `primitives`.
+    pub fn take_textures_delta(&self) -> TexturesDelta {
+        mem::take(&mut *self.textures())
+    }
+
+    /// Whether the UI is using the keyboard, such as for a text field with
+    /// focus, and the game shouldn't act on keys.
+    pub fn wants_keyboard_input(&self) -> bool {
+        self.enabled && self.context.wants_keyboard_input()
+    }
+
+    /// Whether the pointer is over or dragging a window, and clicks shouldn't
+    /// go through to the game.
+    pub fn wants_pointer_input(&self) -> bool {
+        self.enabled && self.context.wants_pointer_input()
+    }
+
+    fn textures(&self) -> MutexGuard<'_, TexturesDelta> {
+        self.textures.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frames_are_only_built_while_enabled() {
+        let mut ui = DebugUi::default();
+        ui.begin_frame(RawInput::default());
+        assert!(ui.context().is_none());
+        assert!(ui.end_frame().is_none());
+
+        ui.set_enabled(true);
+        // Windows are sized in their first frame, and only drawn after.
+        for _ in 0..2 {
+            ui.begin_frame(RawInput::default());
+            let context = ui.context().expect("a frame was begun");
+            egui::Window::new("stats").show(context, |ui| {
+                ui.label("entities: 3");
+            });
+            assert!(ui.end_frame().is_some());
+            assert!(ui.context().is_none());
+        }
+        assert!(!ui.primitives().is_empty());
+
+        // The font atlas is uploaded once, whenever the renderer gets to it.
+        assert!(!ui.take_textures_delta().set.is_empty());
+        assert!(ui.take_textures_delta().set.is_empty());
+
+        ui.set_enabled(false);
+        ui.begin_frame(RawInput::default());
+        ui.end_frame();
+        assert!(ui.primitives().is_empty());
+    }
+}
@@ -0,0 +1,219 @@
+//! Insight into how the hecs world is laid out and queried, to guide
+//! optimization. Archetype stats count entities per combination of
+//! components. Query stats count how often and for how long each system runs
+//! each of its queries in a frame, and warn about a query that runs many times
+//! a frame, which usually means it's run once per entity of another query.
+
+use std::any::{type_name, TypeId};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Mutex, PoisonError};
+use std::time::Duration;
+
+use crate::components::spatial::SpatialHierarchyNode;
+use crate::components::{
+    AudioEmitter, AudioListener, AudioSource, Camera, Control, DirectionalLight, Drawable,
+    GraphicPrefab, Joint, Lifetime, PhysicsBody, PhysicsPose, PointLight, Projectile,
+    ReflectionProbe, ReloadedGraphic, RenderFlags, RenderLayers, ShaderParams, Shaped, SpotLight,
+    StaticPhysics, Velocity, WorldTransform,
+};
+use crate::health::HealthFacet;
+use crate::pool::Pooled;
+use crate::replication::{ReplicationBuffer, ReplicationPolicy};
+
+/// Runs of one query in a frame above which it's reported as likely run per
+/// entity.
+pub const REPEATED_QUERY_RUNS: u32 = 8;
+
+/// Readable names for component types, since hecs only knows their
+/// `TypeId`s. The world's own components are registered by default, games
+/// register theirs with `register`.
+#[derive(Debug)]
+pub struct ComponentNames(HashMap<TypeId, &'static str>);
+
+impl Default for ComponentNames {
+    fn default() -> Self {
+        let mut names = Self(HashMap::new());
+        names.register::<AudioEmitter>();
+        names.register::<AudioListener>();
+        names.register::<AudioSource>();
+        names.register::<Camera>();
+        names.register::<Control>();
+        names.register::<DirectionalLight>();
+        names.register::<Drawable>();
+        names.register::<GraphicPrefab>();
+        names.register::<HealthFacet>();
+        names.register::<Joint>();
+        names.register::<Lifetime>();
+        names.register::<PhysicsBody>();
+        names.register::<PhysicsPose>();
+        names.register::<PointLight>();
+        names.register::<Pooled>();
+        names.register::<Projectile>();
+        names.register::<ReflectionProbe>();
+        names.register::<ReloadedGraphic>();
+        names.register::<RenderFlags>();
+        names.register::<RenderLayers>();
+        names.register::<ReplicationBuffer>();
+        names.register::<ReplicationPolicy>();
+        names.register::<ShaderParams>();
+        names.register::<Shaped>();
+        names.register::<SpatialHierarchyNode>();
+        names.register::<SpotLight>();
+        names.register::<StaticPhysics>();
+        names.register::<Velocity>();
+        names.register::<WorldTransform>();
+        names
+    }
+}
+
+impl ComponentNames {
+    pub fn register<T: 'static>(&mut self) {
+        let name = type_name::<T>();
+        let name = name.rsplit("::").next().unwrap_or(name);
+        self.0.insert(TypeId::of::<T>(), name);
+    }
+
+    pub fn name(&self, type_id: TypeId) -> &'static str {
+        self.0.get(&type_id).copied().unwrap_or("<unregistered>")
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArchetypeStats {
+    /// Component names, sorted.
+    pub components: Vec<&'static str>,
+    pub entities: u32,
+}
+
+/// Entities per combination of components, most populated first. Empty
+/// archetypes are left out.
+pub fn archetype_stats(world: &hecs::World, names: &ComponentNames) -> Vec<ArchetypeStats> {
+    let mut stats = world
+        .archetypes()
+        .filter(|archetype| !archetype.is_empty())
+        .map(|archetype| {
+            let mut components = archetype
+                .component_types()
+                .map(|type_id| names.name(type_id))
+                .collect::<Vec<_>>();
+            components.sort_unstable();
+            ArchetypeStats {
+                components,
+                entities: archetype.len(),
+            }
+        })
+        .collect::<Vec<_>>();
+    stats.sort_by(|a, b| {
+        b.entities
+            .cmp(&a.entities)
+            .then_with(|| a.components.cmp(&b.components))
+    });
+    stats
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct QueryCounter {
+    pub runs: u32,
+    /// Items yielded across every run.
+    pub items: u64,
+    pub time: Duration,
+}
+
+/// Per frame counters for queries, keyed by system and query name. Recording
+/// only needs a shared reference, so systems that only read the world, like
+/// renderers, can record too.
+#[derive(Debug, Default)]
+pub struct QueryStats {
+    current: Mutex<HashMap<(&'static str, &'static str), QueryCounter>>,
+    last_frame: HashMap<(&'static str, &'static str), QueryCounter>,
+    warned: HashSet<(&'static str, &'static str)>,
+}
+
+impl QueryStats {
+    /// Record a run of `query` by `system` that yielded `items` in `time`.
+    pub fn record(&self, system: &'static str, query: &'static str, items: usize, time: Duration) {
+        let mut current = self.current.lock().unwrap_or_else(PoisonError::into_inner);
+        let counter = current.entry((system, query)).or_default();
+        counter.runs += 1;
+        counter.items += items as u64;
+        counter.time += time;
+    }
+
+    /// Finish the frame, keeping its counters for `last_frame`. Returns a
+    /// warning for each query first seen running more than
+    /// `REPEATED_QUERY_RUNS` times in a frame.
+    pub fn end_frame(&mut self) -> Vec<String> {
+        self.last_frame = std::mem::take(
+            self.current
+                .get_mut()
+                .unwrap_or_else(PoisonError::into_inner),
+        );
+        let mut warnings = Vec::new();
+        for (&(system, query), counter) in self.last_frame.iter() {
+            if counter.runs > REPEATED_QUERY_RUNS && self.warned.insert((system, query)) {
+                warnings.push(format!(
+                    "{system} ran query {query} {} times in a frame, taking {:?}, likely once \
+                     per entity. Consider running it once and grouping the results.",
+                    counter.runs, counter.time
+                ));
+            }
+        }
+        warnings.sort();
+        warnings
+    }
+
+    /// Counters for the last finished frame, slowest first.
+    pub fn last_frame(&self) -> Vec<(&'static str, &'static str, QueryCounter)> {
+        let mut counters = self
+            .last_frame
+            .iter()
+            .map(|(&(system, query), counter)| (system, query, *counter))
+            .collect::<Vec<_>>();
+        counters.sort_by(|a, b| b.2.time.cmp(&a.2.time));
+        counters
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn archetypes_are_counted_and_repeated_queries_warned_once() {
+        let mut world = hecs::World::new();
+        for _ in 0..3 {
+            world.spawn((WorldTransform::default(), Control::default()));
+        }
+        world.spawn((WorldTransform::default(),));
+        let stats = archetype_stats(&world, &ComponentNames::default());
+        assert_eq!(
+            stats,
+            vec![
+                ArchetypeStats {
+                    components: vec!["Control", "WorldTransform"],
+                    entities: 3,
+                },
+                ArchetypeStats {
+                    components: vec!["WorldTransform"],
+                    entities: 1,
+                },
+            ]
+        );
+
+        let mut queries = QueryStats::default();
+        let mut warnings = Vec::new();
+        for _ in 0..2 {
+            queries.record("renderer", "cameras", 1, Duration::from_micros(5));
+            for _ in 0..=REPEATED_QUERY_RUNS {
+                queries.record("renderer", "drawables", 4, Duration::from_micros(10));
+            }
+            warnings.push(queries.end_frame().len());
+            let last_frame = queries.last_frame();
+            assert_eq!(last_frame[0].1, "drawables");
+            assert_eq!(last_frame[0].2.runs, REPEATED_QUERY_RUNS + 1);
+            assert_eq!(last_frame[1].2.items, 1);
+        }
+        // Only warned about the first time it's seen.
+        assert_eq!(warnings, vec![1, 0]);
+    }
+}
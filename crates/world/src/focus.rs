@@ -0,0 +1,309 @@
+//! Decides which of the UI and gameplay each input event goes to, so typing
+//! in a text field or clicking on a window doesn't also move the player.
+//! Systems ask `InputFocus` whether gameplay has the keyboard, pointer or
+//! gamepads before acting on input of their own, such as mouse look.
+//!
+//! What the UI wants is as of its last frame, since a frame's events are
+//! routed before the UI is laid out with them. Keys and buttons gameplay was
+//! holding when it lost them are released to it, see `take_releases`, and a
+//! mouse button held by gameplay keeps the pointer until it's let go, so a
+//! drag doesn't end when the cursor crosses a window.
+
+use std::collections::{BTreeSet, HashSet};
+use std::mem;
+
+use input::{Button, InputEvent, MouseButton};
+
+/// Where an input event goes.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum InputTarget {
+    Ui,
+    Game,
+    /// Pointer motion, so the UI knows what's hovered and gameplay where
+    /// the cursor is, and shortcut keys.
+    Both,
+}
+
+impl InputTarget {
+    pub fn to_ui(self) -> bool {
+        matches!(self, InputTarget::Ui | InputTarget::Both)
+    }
+
+    pub fn to_game(self) -> bool {
+        matches!(self, InputTarget::Game | InputTarget::Both)
+    }
+}
+
+/// A key, button or axis held by gameplay.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+enum Held {
+    Key(Button),
+    Gamepad(u8, Button),
+    Axis(u8, u8),
+    Mouse(MouseButton),
+}
+
+#[derive(Debug, Default)]
+pub struct InputFocus {
+    ui_shown: bool,
+    ui_keyboard: bool,
+    ui_pointer: bool,
+    ui_navigation: bool,
+    /// Whatever has taken the keyboard from gameplay, such as an open console.
+    keyboard_captures: BTreeSet<String>,
+    held: HashSet<Held>,
+    /// Releases of what gameplay held when it lost focus, not yet taken.
+    releases: Vec<InputEvent>,
+}
+
+impl InputFocus {
+    /// Take what the UI wanted as of its last frame: whether it's shown, is
+    /// using the keyboard, such as for a text field with focus, and is using
+    /// the pointer, being over or dragging a window.
+    pub fn update_from_ui(&mut self, shown: bool, wants_keyboard: bool, wants_pointer: bool) {
+        self.ui_shown = shown;
+        self.ui_keyboard = shown && wants_keyboard;
+        self.ui_pointer = shown && wants_pointer;
+        self.release_lost();
+    }
+
+    /// Take the keyboard from gameplay until released by the same `owner`.
+    pub fn capture_keyboard(&mut self, owner: &str) {
+        self.keyboard_captures.insert(owner.to_string());
+        self.release_lost();
+    }
+
+    pub fn release_keyboard(&mut self, owner: &str) {
+        self.keyboard_captures.remove(owner);
+    }
+
+    /// Whether gamepads and the keyboard navigate the UI while it's shown,
+    /// rather than play.
+    pub fn ui_navigation(&self) -> bool {
+        self.ui_navigation
+    }
+
+    pub fn set_ui_navigation(&mut self, navigation: bool) {
+        self.ui_navigation = navigation;
+        self.release_lost();
+    }
+
+    pub fn game_has_keyboard(&self) -> bool {
+        !self.ui_keyboard && self.keyboard_captures.is_empty() && !self.navigating()
+    }
+
+    pub fn game_has_pointer(&self) -> bool {
+        !self.ui_pointer || self.held.iter().any(|held| matches!(held, Held::Mouse(_)))
+    }
+
+    pub fn game_has_gamepads(&self) -> bool {
+        !self.navigating()
+    }
+
+    /// Where `event` goes, keeping track of what gameplay holds so each
+    /// release goes where its press did.
+    pub fn route(&mut self, event: &InputEvent) -> InputTarget {
+        match event {
+            InputEvent::KeyPressed(button) => {
+                self.press(Held::Key(*button), self.game_has_keyboard())
+            }
+            InputEvent::KeyReleased(button) => self.release(Held::Key(*button)),
+            InputEvent::ButtonPressed(id, button) => {
+                self.press(Held::Gamepad(*id, *button), self.game_has_gamepads())
+            }
+            InputEvent::ButtonReleased(id, button) => self.release(Held::Gamepad(*id, *button)),
+            InputEvent::AxisMotion(id, axis, 0) => self.release(Held::Axis(*id, *axis)),
+            InputEvent::AxisMotion(id, axis, _) => {
+                self.press(Held::Axis(*id, *axis), self.game_has_gamepads())
+            }
+            InputEvent::MouseButton(button, true) => {
+                // Clicks on the UI don't go through to the game.
+                self.press(Held::Mouse(*button), self.game_has_pointer())
+            }
+            InputEvent::MouseButton(button, false) => self.release(Held::Mouse(*button)),
+            InputEvent::MouseMotion(..) | InputEvent::MouseWheel(..) => {
+                match self.game_has_pointer() {
+                    true => InputTarget::Game,
+                    false => InputTarget::Ui,
+                }
+            }
+            InputEvent::MouseMoved(..) | InputEvent::FunctionKey(..) => InputTarget::Both,
+        }
+    }
+
+    /// Take releases of what gameplay held when it lost focus, to apply
+    /// before this frame's routed events.
+    pub fn take_releases(&mut self) -> Vec<InputEvent> {
+        mem::take(&mut self.releases)
+    }
+
+    fn navigating(&self) -> bool {
+        self.ui_shown && self.ui_navigation
+    }
+
+    fn press(&mut self, held: Held, to_game: bool) -> InputTarget {
+        if to_game {
+            self.held.insert(held);
+            InputTarget::Game
+        } else {
+            InputTarget::Ui
+        }
+    }
+
+    fn release(&mut self, held: Held) -> InputTarget {
+        if self.held.remove(&held) {
+            InputTarget::Game
+        } else {
+            InputTarget::Ui
+        }
+    }
+
+    /// Release keys, buttons and axes held by gameplay if it no longer has
+    /// their device. Mouse buttons keep the pointer, so aren't.
+    fn release_lost(&mut self) {
+        let keyboard = self.game_has_keyboard();
+        let gamepads = self.game_has_gamepads();
+        let releases = &mut self.releases;
+        self.held.retain(|held| {
+            let release = match *held {
+                Held::Key(button) if !keyboard => InputEvent::KeyReleased(button),
+                Held::Gamepad(id, button) if !gamepads => InputEvent::ButtonReleased(id, button),
+                Held::Axis(id, axis) if !gamepads => InputEvent::AxisMotion(id, axis, 0),
+                _ => return true,
+            };
+            releases.push(release);
+            false
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn everything_goes_to_the_game_without_the_ui() {
+        let mut focus = InputFocus::default();
+        focus.set_ui_navigation(true);
+        for event in [
+            InputEvent::KeyPressed(Button::Up),
+            InputEvent::ButtonPressed(0, Button::Ok),
+            InputEvent::AxisMotion(0, 1, 90),
+            InputEvent::MouseButton(MouseButton::Left, true),
+            InputEvent::MouseMotion(3, -2),
+        ] {
+            assert_eq!(focus.route(&event), InputTarget::Game, "{event:?}");
+        }
+    }
+
+    #[test]
+    fn clicks_on_the_ui_dont_go_through() {
+        let mut focus = InputFocus::default();
+        focus.update_from_ui(true, false, true);
+
+        let click = InputEvent::MouseButton(MouseButton::Left, true);
+        assert_eq!(focus.route(&click), InputTarget::Ui);
+        assert_eq!(focus.route(&InputEvent::MouseWheel(0, 1)), InputTarget::Ui);
+        assert_eq!(
+            focus.route(&InputEvent::MouseMoved(10, 10)),
+            InputTarget::Both
+        );
+        assert_eq!(
+            focus.route(&InputEvent::FunctionKey(12, true)),
+            InputTarget::Both
+        );
+        assert!(!focus.game_has_pointer());
+
+        focus.update_from_ui(true, false, false);
+        assert_eq!(focus.route(&click), InputTarget::Game);
+    }
+
+    #[test]
+    fn drags_begun_in_the_game_stay_there() {
+        let mut focus = InputFocus::default();
+        focus.update_from_ui(true, false, false);
+        focus.route(&InputEvent::MouseButton(MouseButton::Right, true));
+
+        // The cursor crosses a window mid drag.
+        focus.update_from_ui(true, false, true);
+        assert!(focus.game_has_pointer());
+        assert_eq!(
+            focus.route(&InputEvent::MouseMotion(5, 0)),
+            InputTarget::Game
+        );
+        assert_eq!(
+            focus.route(&InputEvent::MouseButton(MouseButton::Right, false)),
+            InputTarget::Game
+        );
+        assert!(!focus.game_has_pointer());
+    }
+
+    #[test]
+    fn typing_takes_the_keyboard_and_releases_held_keys() {
+        let mut focus = InputFocus::default();
+        focus.update_from_ui(true, false, false);
+        assert_eq!(
+            focus.route(&InputEvent::KeyPressed(Button::Up)),
+            InputTarget::Game
+        );
+
+        focus.update_from_ui(true, true, false);
+        assert!(!focus.game_has_keyboard());
+        assert_eq!(
+            focus.take_releases(),
+            vec![InputEvent::KeyReleased(Button::Up)]
+        );
+        assert_eq!(
+            focus.route(&InputEvent::KeyPressed(Button::Left)),
+            InputTarget::Ui
+        );
+        // Already released to the game.
+        assert_eq!(
+            focus.route(&InputEvent::KeyReleased(Button::Up)),
+            InputTarget::Ui
+        );
+        assert!(focus.take_releases().is_empty());
+    }
+
+    #[test]
+    fn captures_hold_the_keyboard_until_released() {
+        let mut focus = InputFocus::default();
+        focus.capture_keyboard("console");
+        focus.capture_keyboard("chat");
+        focus.release_keyboard("console");
+        assert!(!focus.game_has_keyboard());
+        focus.release_keyboard("chat");
+        assert!(focus.game_has_keyboard());
+    }
+
+    #[test]
+    fn gamepads_navigate_the_shown_ui() {
+        let mut focus = InputFocus::default();
+        focus.update_from_ui(true, false, false);
+        focus.route(&InputEvent::AxisMotion(0, 2, -70));
+        focus.route(&InputEvent::ButtonPressed(0, Button::Left));
+
+        focus.set_ui_navigation(true);
+        let mut releases = focus.take_releases();
+        releases.sort_by_key(|event| format!("{event:?}"));
+        assert_eq!(
+            releases,
+            vec![
+                InputEvent::AxisMotion(0, 2, 0),
+                InputEvent::ButtonReleased(0, Button::Left),
+            ]
+        );
+        assert_eq!(
+            focus.route(&InputEvent::ButtonPressed(0, Button::Down)),
+            InputTarget::Ui
+        );
+        assert_eq!(
+            focus.route(&InputEvent::KeyPressed(Button::Ok)),
+            InputTarget::Ui
+        );
+
+        focus.update_from_ui(false, false, false);
+        assert!(focus.game_has_gamepads());
+        assert!(focus.game_has_keyboard());
+    }
+}
@@ -0,0 +1,313 @@
+//! A journal of significant events in a world, such as entities spawning,
+//! clients connecting and scenes loading, for working out after a failure
+//! what led up to it. Only the most recent events are kept, and the journal is
+//! written to a file with `Journal::dump`, for instance from a panic hook, see
+//! `Journal::dump_on_panic`.
+//!
+//! Clones of a journal share its events, so a panic hook can write them out
+//! while the world is locked. Projectiles aren't journaled, they'd crowd out
+//! everything else.
+
+use std::collections::VecDeque;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant, SystemTime};
+use std::{fmt, panic};
+
+use hecs::Entity;
+use network::reconnect::ConnectionState;
+
+/// Events kept unless configured otherwise, older ones are dropped first.
+pub const DEFAULT_CAPACITY: usize = 1024;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum JournalEvent {
+    /// An entity was spawned, `kind` is what it is, such as a player.
+    Spawned {
+        entity: Entity,
+        kind: &'static str,
+    },
+    Despawned {
+        entity: Entity,
+    },
+    /// An entity took damage, leaving it with `hp`.
+    Damaged {
+        entity: Entity,
+        damage: u32,
+        hp: u32,
+    },
+    /// A client connected to the server. Clients without an address were
+    /// provided to the server rather than accepted.
+    ClientConnected {
+        addr: Option<SocketAddr>,
+    },
+    ClientDisconnected {
+        addr: Option<SocketAddr>,
+        reason: String,
+    },
+    /// The state of a client's connection to the server changed.
+    Connection(ConnectionState),
+    SceneLoaded {
+        entities: u32,
+    },
+    SceneUnloaded,
+    /// A game system, which may be from a plugin, changed state.
+    System {
+        name: String,
+        change: String,
+    },
+}
+
+impl fmt::Display for JournalEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JournalEvent::Spawned { entity, kind } => write!(f, "spawned {kind} {entity:?}"),
+            JournalEvent::Despawned { entity } => write!(f, "despawned {entity:?}"),
+            JournalEvent::Damaged { entity, damage, hp } => {
+                write!(f, "{entity:?} took {damage} damage, {hp} hp left")
+            }
+            JournalEvent::ClientConnected { addr } => {
+                write!(f, "client {} connected", ClientAddr(*addr))
+            }
+            JournalEvent::ClientDisconnected { addr, reason } => {
+                write!(f, "client {} disconnected: {reason}", ClientAddr(*addr))
+            }
+            JournalEvent::Connection(state) => write!(f, "connection {state}"),
+            JournalEvent::SceneLoaded { entities } => {
+                write!(f, "scene loaded, {entities} entities")
+            }
+            JournalEvent::SceneUnloaded => write!(f, "scene unloaded"),
+            JournalEvent::System { name, change } => write!(f, "system {name} {change}"),
+        }
+    }
+}
+
+struct ClientAddr(Option<SocketAddr>);
+
+impl fmt::Display for ClientAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Some(addr) => write!(f, "{addr}"),
+            None => f.write_str("(provided)"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct JournalEntry {
+    /// Time since the journal was created.
+    pub at: Duration,
+    /// `Stats::updates` of the world at the time.
+    pub update: u64,
+    pub event: JournalEvent,
+}
+
+impl fmt::Display for JournalEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:>10.3}s update {}: {}",
+            self.at.as_secs_f64(),
+            self.update,
+            self.event
+        )
+    }
+}
+
+#[derive(Debug)]
+struct Entries {
+    entries: VecDeque<JournalEntry>,
+    capacity: usize,
+    /// Entries dropped to make room for newer ones.
+    dropped: u64,
+}
+
+impl Entries {
+    fn truncate(&mut self) {
+        while self.entries.len() > self.capacity {
+            self.entries.pop_front();
+            self.dropped += 1;
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Journal {
+    shared: Arc<Mutex<Entries>>,
+    created: Instant,
+}
+
+impl Default for Journal {
+    fn default() -> Self {
+        Journal::new(DEFAULT_CAPACITY)
+    }
+}
+
+impl Journal {
+    /// A journal keeping the last `capacity` events, none if it's 0.
+    pub fn new(capacity: usize) -> Self {
+        Journal {
+            shared: Arc::new(Mutex::new(Entries {
+                entries: VecDeque::new(),
+                capacity,
+                dropped: 0,
+            })),
+            created: Instant::now(),
+        }
+    }
+
+    fn entries(&self) -> MutexGuard<'_, Entries> {
+        // Entries are only ever pushed and popped whole.
+        self.shared.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.entries().capacity
+    }
+
+    /// Keep the last `capacity` events from now on, dropping the oldest of
+    /// those kept if there are more.
+    pub fn set_capacity(&self, capacity: usize) {
+        let mut entries = self.entries();
+        entries.capacity = capacity;
+        entries.truncate();
+    }
+
+    /// Record an event, happening on the world's update `update`.
+    pub fn record(&self, update: u64, event: JournalEvent) {
+        let entry = JournalEntry {
+            at: self.created.elapsed(),
+            update,
+            event,
+        };
+        let mut entries = self.entries();
+        entries.entries.push_back(entry);
+        entries.truncate();
+    }
+
+    /// The last `count` events, oldest first.
+    pub fn recent(&self, count: usize) -> Vec<JournalEntry> {
+        let entries = self.entries();
+        let skip = entries.entries.len().saturating_sub(count);
+        entries.entries.iter().skip(skip).cloned().collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Write every event kept, oldest first, a line each.
+    pub fn write_to(&self, writer: &mut impl Write) -> io::Result<()> {
+        // Copied, so the journal isn't locked while writing.
+        let (entries, dropped) = {
+            let entries = self.entries();
+            (entries.entries.clone(), entries.dropped)
+        };
+        writeln!(
+            writer,
+            "{} events, {dropped} older ones dropped",
+            entries.len()
+        )?;
+        for entry in entries {
+            writeln!(writer, "{entry}")?;
+        }
+        Ok(())
+    }
+
+    /// Write the journal to a new file in `dir`, named for when it was
+    /// written, returning its path.
+    pub fn dump(&self, dir: &Path, at: SystemTime) -> io::Result<PathBuf> {
+        fs::create_dir_all(dir)?;
+        let since_epoch = at
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default();
+        let path = dir.join(format!(
+            "journal-{}.{:03}.txt",
+            since_epoch.as_secs(),
+            since_epoch.subsec_millis()
+        ));
+        let mut writer = BufWriter::new(File::create(&path)?);
+        self.write_to(&mut writer)?;
+        writer.flush()?;
+        Ok(path)
+    }
+
+    /// Dump the journal to `dir` whenever a thread panics, after the panic
+    /// hook that was set before has run. Panic hooks are process-wide, so
+    /// this is for the one journal a process has to explain its crashes.
+    pub fn dump_on_panic(&self, dir: PathBuf) {
+        let journal = self.clone();
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            previous(info);
+            // The logger may be what panicked.
+            match journal.dump(&dir, SystemTime::now()) {
+                Ok(path) => eprintln!("journal written to {}", path.display()),
+                Err(err) => eprintln!("unable to write the journal to {}: {err}", dir.display()),
+            }
+        }));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_the_latest_events_and_writes_them() {
+        let journal = Journal::new(3);
+        let entity = Entity::DANGLING;
+        for hp in (0..5).rev() {
+            journal.record(
+                u64::from(5 - hp),
+                JournalEvent::Damaged {
+                    entity,
+                    damage: 1,
+                    hp,
+                },
+            );
+        }
+        assert_eq!(journal.len(), 3);
+        assert_eq!(
+            journal
+                .recent(2)
+                .iter()
+                .map(|entry| entry.update)
+                .collect::<Vec<_>>(),
+            [4, 5]
+        );
+
+        journal.set_capacity(1);
+        journal.record(
+            6,
+            JournalEvent::ClientDisconnected {
+                addr: Some("127.0.0.1:4000".parse().unwrap()),
+                reason: "timed out".to_string(),
+            },
+        );
+        let mut written = Vec::new();
+        journal.write_to(&mut written).unwrap();
+        let written = String::from_utf8(written).unwrap();
+        let lines = written.lines().collect::<Vec<_>>();
+        assert_eq!(lines[0], "1 events, 5 older ones dropped");
+        assert!(lines[1].ends_with("update 6: client 127.0.0.1:4000 disconnected: timed out"));
+        assert_eq!(lines.len(), 2);
+
+        let dir = std::env::temp_dir().join(format!("journal-test-{}", std::process::id()));
+        let path = journal.dump(&dir, SystemTime::UNIX_EPOCH).unwrap();
+        assert_eq!(path, dir.join("journal-0.000.txt"));
+        assert_eq!(fs::read_to_string(&path).unwrap(), written);
+        fs::remove_dir_all(dir).unwrap();
+
+        journal.set_capacity(0);
+        journal.record(7, JournalEvent::SceneUnloaded);
+        assert!(journal.is_empty());
+    }
+}
@@ -1,27 +1,71 @@
 //! Implements a world and entity system for the engine to mutate and render.
 
+pub mod animation;
 pub mod bundles;
+pub mod clock;
+pub mod collision;
 pub mod components;
+pub mod debug_draw;
+pub mod debug_ui;
+pub mod ecs_stats;
+pub mod focus;
 pub mod graphics;
 pub mod health;
+pub mod journal;
+pub mod limits;
+pub mod notifications;
+pub mod pool;
+pub mod preload;
+pub mod replication;
+pub mod save;
+pub mod snapshot;
 
 use std::io;
 use std::net::SocketAddr;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime};
 
+use animation::{Skeleton, Skinned};
 use async_lock::{Mutex, MutexGuardArc};
-use bundles::Player;
-use components::{GraphicPrefab, WorldTransform};
-use gfx::{DebugMesh, Graphic, Model};
+use bundles::{Player, ProjectileObject, ProjectileSpawn, StaticObject};
+use clock::ServerClock;
+use collision::CollisionGeometry;
+use components::{
+    Drawable, GraphicPrefab, Joint, Name, PhysicsBody, Projectile, ReloadedGraphic, WorldTransform,
+};
+use core_executor::progress::TaskRegistry;
+use debug_draw::DebugDraw;
+use debug_ui::DebugUi;
+use ecs_stats::{ArchetypeStats, ComponentNames, QueryStats};
+use focus::InputFocus;
+use gfx::{DebugMesh, GltfScene, GpuNeeds, Graphic, Model};
 pub use glam::{Mat4, Quat, Vec3};
 pub use hecs::Entity;
+use input::accumulate::InputAccumulator;
+use input::assignment::PlayerSlots;
+use input::haptics::{HapticsController, Rumble};
+use input::macros::InputMacros;
 use input::wire::InputState;
-use logger::{info, LogLevel, Logger};
+use interner::Symbol;
+use journal::{Journal, JournalEvent};
+use limits::{Limit, LimitWarnings, Utilization, WorldLimits};
+use logger::{error, info, warn, LogLevel, Logger};
+use network::build_info::BuildInfo;
+pub use network::compression::Compression;
+use network::compression::CompressionStats;
+use network::quality::QualityMonitor;
+use network::reconnect::ConnectionState;
 use network::{Connection, RpcError};
+use notifications::{Notifications, Severity};
+use pool::{EntityPools, PoolId, Pooled};
+use preload::{PreloadGroups, DEFAULT_PRELOAD_BUDGET};
+use replication::ReplicationPolicy;
+use save::SaveSchemas;
+use snapshot::DivergenceTracker;
 use stable_typeid::StableTypeId;
 
+use crate::components::spatial::SpatialHierarchyNode;
 use crate::components::Camera;
 
 #[repr(C)]
@@ -50,7 +94,85 @@ impl WorldLockAndControllerState {
 
 #[derive(Default)]
 pub struct AssetLoaderState {
-    pub watched: Vec<PathBuf>,
+    pub watched: Vec<WatchedAsset>,
+    /// Prefabs whose shaders changed on disk since the renderer last reloaded
+    /// shaders, taken with `take_modified_shaders`.
+    pub modified_shaders: Vec<Entity>,
+}
+
+impl AssetLoaderState {
+    /// Watch the files the model in `prefab` was loaded from, so that it can be
+    /// reloaded when they change. Prefabs that aren't models are ignored.
+    pub fn watch(&mut self, world: &World, prefab: Entity) -> Result<(), WorldError> {
+        let (paths, shader_paths) = match &world
+            .hecs_world
+            .get::<&GraphicPrefab>(prefab)
+            .map_err(WorldError::Component)?
+            .gfx
+        {
+            Graphic::Model(model) => (
+                model
+                    .source_paths()
+                    .into_iter()
+                    .map(Path::to_path_buf)
+                    .collect::<Vec<_>>(),
+                vec![
+                    model.vertex_shader_path().to_path_buf(),
+                    model.fragment_shader_path().to_path_buf(),
+                ],
+            ),
+            _ => return Ok(()),
+        };
+        let mut watched = WatchedAsset {
+            prefab,
+            paths,
+            last_modified: None,
+            shader_paths,
+            shaders_last_modified: None,
+        };
+        watched.last_modified = watched.latest_modification();
+        watched.shaders_last_modified = watched.latest_shader_modification();
+        self.watched.push(watched);
+        Ok(())
+    }
+
+    /// Prefabs whose shaders changed since this was last called, for the
+    /// renderer to reload them with `Presenter::update_resources`.
+    pub fn take_modified_shaders(&mut self) -> Vec<Entity> {
+        std::mem::take(&mut self.modified_shaders)
+    }
+}
+
+/// A set of files backing a graphic prefab.
+#[derive(Debug)]
+pub struct WatchedAsset {
+    pub prefab: Entity,
+    /// Files the model is loaded from, reloaded when they change.
+    pub paths: Vec<PathBuf>,
+    pub last_modified: Option<SystemTime>,
+    /// SPIR-V the model is drawn with, which the renderer reads itself.
+    pub shader_paths: Vec<PathBuf>,
+    pub shaders_last_modified: Option<SystemTime>,
+}
+
+impl WatchedAsset {
+    /// The most recent modification time of any of the watched files. Files
+    /// that can't be read are ignored, as they may be mid-write.
+    pub fn latest_modification(&self) -> Option<SystemTime> {
+        latest_modification(&self.paths)
+    }
+
+    /// The most recent modification time of either shader.
+    pub fn latest_shader_modification(&self) -> Option<SystemTime> {
+        latest_modification(&self.shader_paths)
+    }
+}
+
+fn latest_modification(paths: &[PathBuf]) -> Option<SystemTime> {
+    paths
+        .iter()
+        .filter_map(|path| std::fs::metadata(path).and_then(|m| m.modified()).ok())
+        .max()
 }
 
 #[repr(C)]
@@ -75,8 +197,8 @@ impl AssetLoaderStateAndWorldLock {
 
 #[derive(thiserror::Error, Debug)]
 pub enum WorldError {
-    #[error("Too many objects added to world")]
-    TooManyObjects,
+    #[error("too many {limit} in world, the limit is {max}")]
+    TooManyObjects { limit: Limit, max: usize },
 
     #[error("Network error {0:?}")]
     Network(RpcError),
@@ -109,6 +231,17 @@ pub enum WorldError {
     NoSuchEntity(hecs::NoSuchEntity),
 }
 
+/// A client of a server, see `World::clients`.
+pub struct Client {
+    /// Where the client's packets come from, None for a connection the
+    /// server was given, such as a loopback one.
+    pub addr: Option<SocketAddr>,
+    pub connection: Box<dyn Connection + Send + Sync + 'static>,
+    /// Codec the client's updates are compressed with, agreed in its
+    /// handshake.
+    pub compression: Compression,
+}
+
 pub struct World {
     pub hecs_world: hecs::World,
     pub root: Option<hecs::Entity>,
@@ -116,13 +249,98 @@ pub struct World {
     pub stats: Stats,
     pub config: Config,
 
-    // TODO: support more than one connection, for servers
     // TODO: move into networking related struct
+    /// A client's connection to the server.
     pub connection: Option<Box<dyn Connection + Send + Sync + 'static>>,
+    /// A server's clients, in the order they connected. The first controls
+    /// the client's player, the others are sent updates and watch.
+    pub clients: Vec<Client>,
 
     pub players: Vec<Entity>,
     pub client_controller_state: Option<InputState>,
     pub server_controller_state: Option<InputState>,
+    /// Controller states accumulated between sim ticks, so ticks see the
+    /// same input at any frame rate.
+    pub client_input: InputAccumulator,
+    pub server_input: InputAccumulator,
+    /// Records the local player's input each tick, or plays a macro back in
+    /// its place, see `InputMacros`.
+    pub input_macros: InputMacros,
+
+    /// Time on the server, for anything that has to happen in step across
+    /// every world in a session.
+    pub clock: ServerClock,
+
+    /// Lines drawn by systems for debugging, see `DebugDraw`.
+    pub debug_draw: DebugDraw,
+    /// Windows shown by systems for debugging, see `DebugUi`.
+    pub debug_ui: DebugUi,
+    /// Which of the UI and gameplay input goes to, see `InputFocus`.
+    pub input_focus: InputFocus,
+    /// Bumped to have the renderer capture reflection probes again, see
+    /// `World::recapture_reflection_probes`.
+    pub reflection_probe_captures: u64,
+
+    /// Quality of the slowest of `connections`, for adapting to congestion.
+    /// Changes are kept as events until drained.
+    pub connection_quality: QualityMonitor,
+    /// Time and bytes saved compressing updates, by codec.
+    pub compression_stats: CompressionStats,
+    /// Which groups of entities agree with the server's, or the first
+    /// client's, from the slice checksums exchanged with each update.
+    pub divergence: DivergenceTracker,
+    /// Versions of the components written to saves, and the migrations of
+    /// saves from older versions, see `save`.
+    pub save_schemas: SaveSchemas,
+    /// State of a client's connection to the server, None on servers. See
+    /// `World::set_connection_state`.
+    pub connection_state: Option<ConnectionState>,
+
+    /// Errors and warnings for the user, see `World::notify`.
+    pub notifications: Notifications,
+    /// Recent significant events, for post-mortems, see `World::record`.
+    pub journal: Journal,
+
+    /// Projectiles spawned on the server and not yet announced to clients,
+    /// see `World::spawn_projectile`.
+    pub new_projectiles: Vec<Entity>,
+    /// Pool projectiles are taken from, once reserved.
+    projectile_pool: Option<PoolId>,
+
+    /// Mouse look since the last update, as yaw and pitch in radians, for the
+    /// player whose camera this world is seen from, see `World::camera`.
+    pub pending_look: (f32, f32),
+    /// Rumbles for this world's own controllers, played by the platform. See
+    /// `World::rumble`.
+    pub local_rumbles: Vec<Rumble>,
+    /// Rumbles for the client's player, sent in the server's next update.
+    pub remote_rumbles: Vec<Rumble>,
+    /// Requests for this process' controllers' motors, for game code driving
+    /// them directly rather than through `World::rumble`. Not sent to
+    /// clients.
+    pub haptics: HapticsController,
+    /// Which gamepad plays which local player, and who joined or left this
+    /// frame, see `input::assignment`.
+    pub player_slots: PlayerSlots,
+
+    /// Entities recycled rather than despawned, see `World::despawn`.
+    pub pools: EntityPools,
+    /// Groups of models the scene file declares, and whether they're loaded,
+    /// see `preload`.
+    pub preload: PreloadGroups,
+    /// Graphic prefabs despawned since the renderer last released what it
+    /// uploaded for them, see `World::despawn`.
+    pub despawned_graphics: Vec<Entity>,
+    /// Caps users have been warned are filling up, see `Config::limits`.
+    limit_warnings: LimitWarnings,
+
+    /// Names of component types, for archetype stats.
+    pub component_names: ComponentNames,
+    /// Query counters for the current and last frame, see `QueryStats`.
+    pub query_stats: QueryStats,
+    /// Progress of long-running tasks, such as loads, for loading screens and
+    /// the console.
+    pub tasks: TaskRegistry,
 
     pub logger: Logger,
 }
@@ -135,9 +353,36 @@ pub struct Stats {
 
 pub struct Config {
     pub net_disabled: bool,
-    pub maybe_server_addr: Option<SocketAddr>,
+    /// Host name or address, with port, of the server to connect to as a
+    /// client.
+    pub maybe_server_addr: Option<String>,
+    /// Codec a server compresses updates with, if the client supports it.
+    pub net_compression: Compression,
+    /// What this build is, exchanged in the handshake so peers built from
+    /// different sources refuse to sync.
+    pub build_info: BuildInfo,
+    /// Where to trace every packet sent and received, for debugging the
+    /// protocol, see `network::trace`.
+    pub net_trace: Option<PathBuf>,
+    /// Caps on what can be spawned, see `limits`.
+    pub limits: WorldLimits,
+    /// How a client moves replicated entities without a `ReplicationPolicy`
+    /// of their own between updates.
+    pub replication: ReplicationPolicy,
+    /// How far behind the server entities interpolated without a delay of
+    /// their own are drawn. None leaves it to the connection's quality, see
+    /// `ConnectionQuality::interpolation_delay`.
+    pub interpolation_delay: Option<Duration>,
+    /// Scene file declaring groups of models to preload, see `preload`.
+    pub scene: Option<PathBuf>,
+    /// Bytes of models preloaded groups may take before lower priority groups
+    /// are evicted.
+    pub preload_budget: usize,
 }
 
+/// Most rumbles waiting to be sent to a client, the oldest are dropped.
+const MAX_QUEUED_RUMBLES: usize = 8;
+
 impl World {
     pub const SIM_TICK_DELAY: Duration = Duration::from_millis(8);
 
@@ -145,19 +390,31 @@ impl World {
     /// waits for a client to connect before continuing.
     ///
     /// FIXME: make this /// independent of any connecting clients.
-    pub fn new(maybe_server_addr: Option<SocketAddr>, logger: &Logger, net_disabled: bool) -> Self {
+    pub fn new(maybe_server_addr: Option<String>, logger: &Logger, net_disabled: bool) -> Self {
         let mut hecs_world = hecs::World::new();
         let root_entity = hecs_world.spawn((WorldTransform::default(),));
         Self {
             connection: None,
+            clients: Vec::new(),
 
             players: Vec::new(),
             client_controller_state: None,
             server_controller_state: None,
+            client_input: InputAccumulator::default(),
+            server_input: InputAccumulator::default(),
+            input_macros: InputMacros::default(),
 
             config: Config {
                 net_disabled,
                 maybe_server_addr,
+                net_compression: Compression::default(),
+                build_info: BuildInfo::default(),
+                net_trace: None,
+                limits: WorldLimits::default(),
+                replication: ReplicationPolicy::default(),
+                interpolation_delay: None,
+                scene: None,
+                preload_budget: DEFAULT_PRELOAD_BUDGET,
             },
 
             stats: Stats {
@@ -169,16 +426,103 @@ impl World {
             hecs_world,
             root: Some(root_entity),
 
+            clock: ServerClock::new(Instant::now()),
+
+            debug_draw: DebugDraw::default(),
+            debug_ui: DebugUi::default(),
+            input_focus: InputFocus::default(),
+            reflection_probe_captures: 0,
+            connection_quality: QualityMonitor::default(),
+            compression_stats: CompressionStats::default(),
+            divergence: DivergenceTracker::default(),
+            save_schemas: SaveSchemas::default(),
+            connection_state: None,
+            notifications: Notifications::default(),
+            journal: Journal::default(),
+
+            new_projectiles: Vec::new(),
+            projectile_pool: None,
+
+            pending_look: (0.0, 0.0),
+            local_rumbles: Vec::new(),
+            remote_rumbles: Vec::new(),
+            haptics: HapticsController::default(),
+            player_slots: PlayerSlots::default(),
+
+            pools: EntityPools::default(),
+            preload: PreloadGroups::default(),
+            despawned_graphics: Vec::new(),
+            limit_warnings: LimitWarnings::default(),
+
+            component_names: ComponentNames::default(),
+            query_stats: QueryStats::default(),
+            tasks: TaskRegistry::new(),
+
             logger: logger.sub("world"),
         }
     }
 
     pub fn set_client_controller_state(&mut self, state: InputState) {
         self.client_controller_state = Some(state);
+        self.client_input.sample(state, Instant::now());
     }
 
     pub fn set_server_controller_state(&mut self, state: InputState) {
         self.server_controller_state = Some(state);
+        self.server_input.sample(state, Instant::now());
+    }
+
+    /// Tell the user about a problem, such as a missing asset or a lost
+    /// connection. It's logged too, so it shows up without a UI.
+    pub fn notify(&mut self, severity: Severity, source: &str, message: impl Into<String>) {
+        let message = message.into();
+        let logger = self.logger.sub(source);
+        match severity {
+            Severity::Info => info!(logger, "{message}"),
+            Severity::Warning => warn!(logger, "{message}"),
+            Severity::Error => error!(logger, "{message}"),
+        }
+        self.notifications
+            .post(severity, source, message, Instant::now());
+    }
+
+    /// Journal a significant event, such as a client connecting, see
+    /// `journal`.
+    pub fn record(&self, event: JournalEvent) {
+        self.journal.record(self.stats.updates, event);
+    }
+
+    /// Entities per combination of components, see `ecs_stats`.
+    pub fn archetype_stats(&self) -> Vec<ArchetypeStats> {
+        ecs_stats::archetype_stats(&self.hecs_world, &self.component_names)
+    }
+
+    /// Record a change in the state of the connection to the server, telling
+    /// the user when it's lost or comes back.
+    pub fn set_connection_state(&mut self, state: ConnectionState) {
+        let previous = self.connection_state.replace(state);
+        if previous == Some(state) {
+            return;
+        }
+        self.record(JournalEvent::Connection(state));
+        match state {
+            ConnectionState::Reconnecting { .. } => {
+                self.notify(Severity::Warning, "connection", state.to_string())
+            }
+            ConnectionState::Connected if previous.is_some() => {
+                self.notify(Severity::Info, "connection", state.to_string())
+            }
+            _ => info!(self.logger, "connection {state}"),
+        }
+    }
+
+    /// Every connection this world syncs over: to the server, or to each
+    /// client.
+    pub fn connections(&self) -> impl Iterator<Item = &(dyn Connection + Send + Sync + 'static)> {
+        self.connection
+            .iter()
+            .chain(self.clients.iter().map(|client| &client.connection))
+            .map(|connection| &**connection)
     }
 
     pub fn is_server(&self) -> bool {
@@ -193,11 +537,168 @@ impl World {
 
     pub fn add_model(&mut self, model: Model) -> Entity {
         self.hecs_world.spawn((GraphicPrefab {
-            gfx: Graphic::Model(model),
+            gfx: Graphic::new_model(model),
         },))
     }
 
-    pub fn add_player(&mut self, player: Player) -> Entity {
+    /// Spawn the nodes of a glTF scene under `parent`, keeping their
+    /// hierarchy: each node is a `SpatialHierarchyNode` child of its parent
+    /// node, drawing the models of its mesh, with a child object for each
+    /// model when there's more than one, and a `Name` if the node has one.
+    /// The models become prefabs, shared by nodes drawing the same mesh.
+    /// Skinned nodes get a `Skeleton` of their joints, with the scene's
+    /// animations as its clips, and their drawables are `Skinned` by it.
+    /// Returns the entity of each node of `scene.nodes`, None for those
+    /// outside its scene.
+    pub fn add_gltf_scene(
+        &mut self,
+        scene: GltfScene,
+        parent: Entity,
+    ) -> Result<Vec<Option<Entity>>, WorldError> {
+        self.hecs_world
+            .entity(parent)
+            .map_err(WorldError::NoSuchEntity)?;
+
+        // Parents come before their children.
+        let mut order = Vec::new();
+        let mut pending: Vec<(usize, Option<usize>)> =
+            scene.roots.iter().rev().map(|&root| (root, None)).collect();
+        while let Some((node, parent_node)) = pending.pop() {
+            order.push((node, parent_node));
+            pending.extend(
+                scene.nodes[node]
+                    .children
+                    .iter()
+                    .rev()
+                    .map(|&child| (child, Some(node))),
+            );
+        }
+        let primitives = |node: usize| {
+            scene.nodes[node]
+                .mesh
+                .map_or(0, |mesh| scene.meshes[mesh].len())
+        };
+        let drawables = order
+            .iter()
+            .map(|&(node, _)| primitives(node))
+            .sum::<usize>();
+        let children = order
+            .iter()
+            .map(|&(node, _)| primitives(node))
+            .filter(|&primitives| primitives > 1)
+            .sum::<usize>();
+        self.check_limits(order.len() + children, drawables, 0)?;
+
+        let prefabs: Vec<Vec<Entity>> = scene
+            .meshes
+            .into_iter()
+            .map(|models| {
+                models
+                    .into_iter()
+                    .map(|model| self.add_model(model))
+                    .collect()
+            })
+            .collect();
+        let mut entities = vec![None; scene.nodes.len()];
+        let mut skinned = Vec::new();
+        for (node, parent_node) in order {
+            let gltf_node = &scene.nodes[node];
+            let parent = parent_node
+                .and_then(|parent| entities[parent])
+                .unwrap_or(parent);
+            let spatial = SpatialHierarchyNode::new_with_transform(parent, gltf_node.transform);
+            let models = gltf_node.mesh.map_or(&[][..], |mesh| &prefabs[mesh]);
+            let (entity, drawables) = match models {
+                [model] => {
+                    let entity = self.hecs_world.spawn(StaticObject::new(*model, spatial));
+                    (entity, vec![entity])
+                }
+                _ => {
+                    let entity = self.hecs_world.spawn((spatial, WorldTransform::default()));
+                    let drawables = models
+                        .iter()
+                        .map(|model| {
+                            self.hecs_world
+                                .spawn(StaticObject::new(*model, SpatialHierarchyNode::new(entity)))
+                        })
+                        .collect();
+                    (entity, drawables)
+                }
+            };
+            if let Some(skin) = gltf_node.skin {
+                skinned.push((entity, skin, drawables));
+            }
+            if let Some(name) = gltf_node.name {
+                self.hecs_world
+                    .insert_one(entity, Name(name))
+                    .expect("just spawned");
+            }
+            entities[node] = Some(entity);
+        }
+
+        let clips: Vec<_> = scene.animations.into_iter().map(Arc::new).collect();
+        for (entity, skin, drawables) in skinned {
+            let skin = &scene.skins[skin];
+            // Joints outside the scene weren't spawned, and the mesh is left
+            // in its bind pose.
+            let joints: Option<Vec<Entity>> =
+                skin.joints.iter().map(|&joint| entities[joint]).collect();
+            let joints = match joints {
+                Some(joints) => joints,
+                None => continue,
+            };
+            let skeleton = Skeleton {
+                joint_matrices: vec![Mat4::IDENTITY; joints.len()],
+                joints,
+                nodes: skin.joints.clone(),
+                names: skin
+                    .joints
+                    .iter()
+                    .map(|&joint| scene.nodes[joint].name)
+                    .collect(),
+                inverse_bind_matrices: skin.inverse_bind_matrices.clone(),
+                clips: clips.clone(),
+            };
+            self.hecs_world
+                .insert_one(entity, skeleton)
+                .expect("just spawned");
+            for drawable in drawables {
+                self.hecs_world
+                    .insert_one(drawable, Skinned { skeleton: entity })
+                    .expect("just spawned");
+            }
+        }
+        Ok(entities)
+    }
+
+    /// Give a prefab geometry to collide as, for the static objects drawing
+    /// it. Colliders are built from it when physics is set up.
+    pub fn add_collision_geometry(
+        &mut self,
+        prefab: Entity,
+        geometry: CollisionGeometry,
+    ) -> Result<(), WorldError> {
+        self.hecs_world
+            .insert_one(prefab, geometry)
+            .map_err(WorldError::NoSuchEntity)
+    }
+
+    /// Replace the model of an existing prefab, flagging it so that renderers
+    /// upload it again.
+    pub fn reload_model(&mut self, prefab: Entity, model: Model) -> Result<(), WorldError> {
+        let mut graphic = self
+            .hecs_world
+            .get::<&mut GraphicPrefab>(prefab)
+            .map_err(WorldError::Component)?;
+        graphic.gfx = Graphic::new_model(model);
+        drop(graphic);
+        self.hecs_world
+            .insert_one(prefab, ReloadedGraphic { at: Instant::now() })
+            .map_err(WorldError::NoSuchEntity)
+    }
+
+    pub fn add_player(&mut self, player: Player) -> Result<Entity, WorldError> {
+        self.check_limits(1, 1, 1)?;
         let player = self.hecs_world.spawn(player);
         let log = self.logger.sub("entity");
         info!(
@@ -208,7 +709,207 @@ impl World {
             StableTypeId::of::<Camera>()
         );
         self.players.push(player);
-        player
+        self.record(JournalEvent::Spawned {
+            entity: player,
+            kind: "player",
+        });
+        Ok(player)
+    }
+
+    /// Spawn a drawable that doesn't move on its own.
+    pub fn add_object(&mut self, object: StaticObject) -> Result<Entity, WorldError> {
+        self.check_limits(1, 1, 0)?;
+        let object = self.hecs_world.spawn(object);
+        self.record(JournalEvent::Spawned {
+            entity: object,
+            kind: "object",
+        });
+        Ok(object)
+    }
+
+    /// Spawn a joint between two entities' rigid bodies, which the world
+    /// update system creates. Despawn it to break the joint.
+    pub fn add_joint(&mut self, joint: Joint) -> Result<Entity, WorldError> {
+        for end in [joint.first, joint.second] {
+            self.hecs_world
+                .entity(end)
+                .map_err(WorldError::NoSuchEntity)?;
+        }
+        self.check_limits(1, 0, 0)?;
+        let joint = self.hecs_world.spawn((joint,));
+        self.record(JournalEvent::Spawned {
+            entity: joint,
+            kind: "joint",
+        });
+        Ok(joint)
+    }
+
+    /// Spawn a projectile, which the world update system moves, despawns when
+    /// its lifetime runs out and, on the server, uses to damage what it hits.
+    /// Projectiles spawned on the server are replicated to clients.
+    pub fn spawn_projectile(&mut self, spawn: ProjectileSpawn) -> Result<Entity, WorldError> {
+        // Reusing a pooled projectile doesn't add an entity.
+        let reused = self
+            .projectile_pool
+            .is_some_and(|pool| self.pools.pool_stats(pool).free > 0);
+        self.check_limits(usize::from(!reused), 1, 1)?;
+        let root = self.root.expect("world has no root");
+        let bundle = ProjectileObject::new(root, spawn);
+        let projectile = match self.projectile_pool {
+            Some(pool) => {
+                let projectile = self.pools.acquire(&mut self.hecs_world, pool);
+                // Same components as the pool's, so replaced in place.
+                self.hecs_world.insert(projectile, bundle).unwrap();
+                projectile
+            }
+            None => self.hecs_world.spawn(bundle),
+        };
+        if self.is_server() {
+            self.new_projectiles.push(projectile);
+        }
+        Ok(projectile)
+    }
+
+    /// Check that spawning this many more entities, drawables and replicated
+    /// entities stays within `Config::limits`, telling the user when a cap is
+    /// filling up or full.
+    fn check_limits(
+        &mut self,
+        entities: usize,
+        drawables: usize,
+        replicated: usize,
+    ) -> Result<(), WorldError> {
+        // Pooled entities waiting for reuse are hidden, and not replicated.
+        let free_pooled = self
+            .pools
+            .stats()
+            .map(|(_, stats)| stats.free)
+            .sum::<usize>();
+        let free_projectiles = self
+            .projectile_pool
+            .map_or(0, |pool| self.pools.pool_stats(pool).free);
+        let counts = [
+            (Limit::Entities, self.hecs_world.len() as usize + entities),
+            (
+                Limit::Drawables,
+                self.hecs_world
+                    .query::<&Drawable>()
+                    .iter()
+                    .len()
+                    .saturating_sub(free_pooled)
+                    + drawables,
+            ),
+            (
+                Limit::Replicated,
+                (self.hecs_world.query::<&PhysicsBody>().iter().len()
+                    + self.hecs_world.query::<&Projectile>().iter().len())
+                .saturating_sub(free_projectiles)
+                    + replicated,
+            ),
+        ];
+        let mut result = Ok(());
+        for (limit, count) in counts {
+            let max = self.config.limits.max(limit);
+            let (utilization, report) =
+                self.limit_warnings.check(&self.config.limits, limit, count);
+            match utilization {
+                Utilization::High if report => self.notify(
+                    Severity::Warning,
+                    "limits",
+                    format!("{count} of {max} {limit} in use"),
+                ),
+                Utilization::Exceeded => {
+                    if report {
+                        self.notify(
+                            Severity::Error,
+                            "limits",
+                            format!("at the limit of {max} {limit}, not spawning more"),
+                        );
+                    }
+                    result = result.and(Err(WorldError::TooManyObjects { limit, max }));
+                }
+                _ => {}
+            }
+        }
+        result
+    }
+
+    /// Take projectiles from a pool of `count` entities from now on, so firing
+    /// them doesn't churn entities.
+    pub fn reserve_projectiles(&mut self, count: usize) {
+        if self.projectile_pool.is_some() {
+            return;
+        }
+        let root = self.root.expect("world has no root");
+        let pool = self
+            .pools
+            .reserve(&mut self.hecs_world, "projectiles", count, move || {
+                ProjectileObject::new(
+                    root,
+                    ProjectileSpawn {
+                        origin: Vec3::ZERO,
+                        direction: Vec3::NEG_Z,
+                        speed: 0.0,
+                        lifetime: Duration::ZERO,
+                        gfx_prefab: Entity::DANGLING,
+                        damage: 0,
+                        owner: None,
+                    },
+                )
+            });
+        self.projectile_pool = Some(pool);
+    }
+
+    /// Despawn an entity, or return it to its pool if it's pooled, doing
+    /// nothing if it's already been returned. Despawned graphic prefabs are
+    /// kept in `despawned_graphics`, so the renderer can release what it
+    /// uploaded for them.
+    pub fn despawn(&mut self, entity: Entity) -> Result<(), WorldError> {
+        if self.pools.release(&mut self.hecs_world, entity) {
+            return Ok(());
+        }
+        // Already released, it's kept by its pool to be reused.
+        if self.hecs_world.get::<&Pooled>(entity).is_ok() {
+            return Ok(());
+        }
+        let is_graphic = self.hecs_world.get::<&GraphicPrefab>(entity).is_ok();
+        let is_projectile = self.hecs_world.get::<&Projectile>(entity).is_ok();
+        self.hecs_world
+            .despawn(entity)
+            .map_err(WorldError::NoSuchEntity)?;
+        if is_graphic {
+            self.despawned_graphics.push(entity);
+        }
+        if !is_projectile {
+            self.record(JournalEvent::Despawned { entity });
+        }
+        Ok(())
+    }
+
+    /// Recompute every world transform on the next update, rather than only
+    /// those of moved nodes, such as after loading a scene.
+    pub fn refresh_transforms(&mut self) {
+        components::spatial::mark_all_dirty(&mut self.hecs_world);
+    }
+
+    /// Whether an entity exists and isn't a pooled entity waiting for reuse.
+    pub fn is_live(&self, entity: Entity) -> bool {
+        match self.hecs_world.get::<&Pooled>(entity) {
+            Ok(pooled) => pooled.active,
+            Err(hecs::ComponentError::MissingComponent(_)) => true,
+            Err(hecs::ComponentError::NoSuchEntity) => false,
+        }
+    }
+
+    /// The first entity found with the `Name` `name`.
+    pub fn find_named(&self, name: &str) -> Option<Entity> {
+        // Nothing has a name that was never interned.
+        let name = Name(Symbol::lookup(name)?);
+        self.hecs_world
+            .query::<&Name>()
+            .iter()
+            .find(|(_, found)| **found == name)
+            .map(|(entity, _)| entity)
     }
 
     pub fn player(&self, index: usize) -> Option<Entity> {
@@ -216,6 +917,26 @@ impl World {
         Some(*entity)
     }
 
+    /// Rumble the controllers of `player`, here if it's this world's player
+    /// or, on the server, on the client it belongs to.
+    pub fn rumble(&mut self, player: Entity, rumble: Rumble) {
+        if self.camera() == Some(player) {
+            self.local_rumbles.push(rumble);
+        } else if self.is_server() && self.players.contains(&player) {
+            // Nobody may be draining these without a connection.
+            if self.remote_rumbles.len() == MAX_QUEUED_RUMBLES {
+                self.remote_rumbles.remove(0);
+            }
+            self.remote_rumbles.push(rumble);
+        }
+    }
+
+    /// Capture every `ReflectionProbe` again on the next frame, for instance
+    /// after editing the scene around them.
+    pub fn recapture_reflection_probes(&mut self) {
+        self.reflection_probe_captures += 1;
+    }
+
     pub fn camera(&self) -> Option<Entity> {
         if self.is_server() {
             self.players.get(0).copied()
@@ -223,4 +944,13 @@ impl World {
             self.players.get(1).copied()
         }
     }
+
+    /// Redraw the debug grid and axes, with the grid around the camera.
+    pub fn draw_debug_reference(&mut self) {
+        let focus = self
+            .camera()
+            .and_then(|camera| self.hecs_world.get::<&WorldTransform>(camera).ok())
+            .map_or(Vec3::ZERO, |transform| transform.world.w_axis.truncate());
+        self.debug_draw.draw_reference(focus);
+    }
 }
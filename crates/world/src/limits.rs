@@ -0,0 +1,134 @@
+//! Caps on what can be spawned into a world, so runaway spawning fails with
+//! an error rather than grinding the renderer and network to a halt. Users
+//! are warned once a cap is `WARN_UTILIZATION` used, see `World::notify`.
+
+use std::fmt;
+
+/// Fraction of a cap in use when users are warned.
+pub const WARN_UTILIZATION: f32 = 0.8;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Limit {
+    Entities,
+    /// Entities with a `Drawable`, not counting pooled ones waiting for
+    /// reuse.
+    Drawables,
+    /// Entities sent to clients: physics bodies and live projectiles.
+    Replicated,
+}
+
+impl Limit {
+    const ALL: [Limit; 3] = [Limit::Entities, Limit::Drawables, Limit::Replicated];
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Limit::Entities => f.write_str("entities"),
+            Limit::Drawables => f.write_str("drawables"),
+            Limit::Replicated => f.write_str("replicated entities"),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct WorldLimits {
+    pub max_entities: usize,
+    pub max_drawables: usize,
+    pub max_replicated: usize,
+}
+
+impl Default for WorldLimits {
+    fn default() -> Self {
+        Self {
+            max_entities: 65_536,
+            max_drawables: 16_384,
+            max_replicated: 1_024,
+        }
+    }
+}
+
+impl WorldLimits {
+    pub fn max(&self, limit: Limit) -> usize {
+        match limit {
+            Limit::Entities => self.max_entities,
+            Limit::Drawables => self.max_drawables,
+            Limit::Replicated => self.max_replicated,
+        }
+    }
+}
+
+/// How much of a cap is in use.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Utilization {
+    #[default]
+    Normal,
+    /// At least `WARN_UTILIZATION` used.
+    High,
+    /// Over the cap, whatever was being spawned wasn't.
+    Exceeded,
+}
+
+/// The most of each cap users have been told is in use, so they're told once
+/// each time a cap fills up rather than on every spawn. Forgotten once a cap
+/// is back to `Utilization::Normal`.
+#[derive(Debug, Default)]
+pub struct LimitWarnings {
+    reported: [Utilization; Limit::ALL.len()],
+}
+
+impl LimitWarnings {
+    /// How much of `limit` is used by `count`, including what's about to be
+    /// spawned, and whether users should be told.
+    pub fn check(
+        &mut self,
+        limits: &WorldLimits,
+        limit: Limit,
+        count: usize,
+    ) -> (Utilization, bool) {
+        let max = limits.max(limit);
+        let utilization = if count > max {
+            Utilization::Exceeded
+        } else if count as f32 >= max as f32 * WARN_UTILIZATION {
+            Utilization::High
+        } else {
+            Utilization::Normal
+        };
+        let reported = &mut self.reported[limit as usize];
+        let report = utilization > *reported;
+        if report || utilization == Utilization::Normal {
+            *reported = utilization;
+        }
+        (utilization, report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn warns_once_per_fill_and_errors_over_the_cap() {
+        let limits = WorldLimits {
+            max_replicated: 10,
+            ..Default::default()
+        };
+        let mut warnings = LimitWarnings::default();
+        let mut check = |count| warnings.check(&limits, Limit::Replicated, count);
+        assert_eq!(check(7), (Utilization::Normal, false));
+        assert_eq!(check(8), (Utilization::High, true));
+        assert_eq!(check(10), (Utilization::High, false));
+        assert_eq!(check(11), (Utilization::Exceeded, true));
+        assert_eq!(check(10), (Utilization::High, false));
+        assert_eq!(check(11), (Utilization::Exceeded, false));
+        // Drained below the threshold, then filled again.
+        assert_eq!(check(3), (Utilization::Normal, false));
+        assert_eq!(check(9), (Utilization::High, true));
+
+        assert_eq!(
+            warnings.check(&limits, Limit::Entities, 100),
+            (Utilization::Normal, false)
+        );
+        assert_eq!(Limit::Replicated.to_string(), "replicated entities");
+    }
+}
@@ -0,0 +1,150 @@
+//! Errors and warnings for the user, as opposed to the log. Systems post
+//! notifications such as a missing asset or a lost connection, and UI shows
+//! them until they expire or are dismissed. A notification posted again while
+//! it's still showing is counted as a repeat, so a failure that happens every
+//! frame shows up once.
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Most notifications kept, older ones are dropped first.
+const CAPACITY: usize = 32;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+impl Severity {
+    /// How long notifications of this severity show before expiring, errors
+    /// stay until dismissed.
+    pub fn lifetime(self) -> Option<Duration> {
+        match self {
+            Severity::Info => Some(Duration::from_secs(4)),
+            Severity::Warning => Some(Duration::from_secs(8)),
+            Severity::Error => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Notification {
+    pub id: u64,
+    pub severity: Severity,
+    /// What posted it, usually a system name.
+    pub source: String,
+    pub message: String,
+    pub posted_at: Instant,
+    /// Times it was posted again while showing.
+    pub repeats: u32,
+}
+
+#[derive(Debug, Default)]
+pub struct Notifications {
+    queue: VecDeque<Notification>,
+    next_id: u64,
+}
+
+impl Notifications {
+    /// Post a notification, returning its id. Posting one identical to one
+    /// that's showing counts a repeat and keeps it showing longer instead.
+    pub fn post(
+        &mut self,
+        severity: Severity,
+        source: &str,
+        message: impl Into<String>,
+        now: Instant,
+    ) -> u64 {
+        let message = message.into();
+        if let Some(existing) = self.queue.iter_mut().find(|notification| {
+            notification.severity == severity
+                && notification.source == source
+                && notification.message == message
+        }) {
+            existing.repeats += 1;
+            existing.posted_at = now;
+            return existing.id;
+        }
+
+        if self.queue.len() == CAPACITY {
+            self.queue.pop_front();
+        }
+        let id = self.next_id;
+        self.next_id += 1;
+        self.queue.push_back(Notification {
+            id,
+            severity,
+            source: source.to_string(),
+            message,
+            posted_at: now,
+            repeats: 0,
+        });
+        id
+    }
+
+    /// Remove a notification, returning false if it had already gone.
+    pub fn dismiss(&mut self, id: u64) -> bool {
+        let len = self.queue.len();
+        self.queue.retain(|notification| notification.id != id);
+        self.queue.len() != len
+    }
+
+    pub fn dismiss_all(&mut self) {
+        self.queue.clear();
+    }
+
+    /// Remove notifications that have been showing for their lifetime.
+    pub fn expire(&mut self, now: Instant) {
+        self.queue.retain(|notification| {
+            notification.severity.lifetime().map_or(true, |lifetime| {
+                now.saturating_duration_since(notification.posted_at) < lifetime
+            })
+        });
+    }
+
+    /// Notifications showing, oldest first.
+    pub fn iter(&self) -> impl Iterator<Item = &Notification> {
+        self.queue.iter()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn repeats_are_collapsed_and_notifications_expire() {
+        let start = Instant::now();
+        let mut notifications = Notifications::default();
+        let lost = notifications.post(Severity::Error, "net_sync", "connection lost", start);
+        let missing = notifications.post(Severity::Warning, "assets", "missing a.png", start);
+        assert_eq!(
+            notifications.post(Severity::Error, "net_sync", "connection lost", start),
+            lost
+        );
+        assert_eq!(notifications.iter().count(), 2);
+        assert_eq!(notifications.iter().next().unwrap().repeats, 1);
+
+        // Warnings expire, errors stay until dismissed.
+        notifications.expire(start + Duration::from_secs(60));
+        assert_eq!(
+            notifications.iter().map(|n| n.id).collect::<Vec<_>>(),
+            vec![lost]
+        );
+        assert!(!notifications.dismiss(missing));
+        assert!(notifications.dismiss(lost));
+        assert!(notifications.is_empty());
+
+        for n in 0..CAPACITY + 1 {
+            notifications.post(Severity::Info, "test", format!("{n}"), start);
+        }
+        assert_eq!(notifications.iter().count(), CAPACITY);
+        assert_eq!(notifications.iter().next().unwrap().message, "1");
+    }
+}
@@ -0,0 +1,275 @@
+//! Pools of entities for things spawned and despawned often, like
+//! projectiles, particles and debris. Released entities are kept with their
+//! components and hidden rather than despawned, and acquiring one resets its
+//! components in place, so entities stay in the same archetype and their
+//! storage isn't reallocated.
+//!
+//! Pooled entities have a `Pooled` component. Systems that move or collide
+//! entities skip the inactive ones, see `World::is_live`.
+
+use hecs::{DynamicBundle, Entity};
+
+use crate::components::RenderFlags;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct PoolId(usize);
+
+/// Present on every entity owned by a pool.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Pooled {
+    pub pool: PoolId,
+    /// Whether the entity is acquired, inactive entities are hidden and
+    /// waiting to be reused.
+    pub active: bool,
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct PoolStats {
+    /// Entities acquired, whether reused or newly spawned.
+    pub acquired: u64,
+    /// Acquisitions served by reusing a released entity.
+    pub hits: u64,
+    pub released: u64,
+    /// Entities owned by the pool, active or not.
+    pub capacity: usize,
+    /// Entities waiting to be reused.
+    pub free: usize,
+}
+
+impl PoolStats {
+    /// Fraction of acquisitions that reused an entity, 1 if none were made.
+    pub fn hit_rate(&self) -> f32 {
+        if self.acquired == 0 {
+            1.0
+        } else {
+            self.hits as f32 / self.acquired as f32
+        }
+    }
+}
+
+/// Spawns a fresh entity when given `None`, or resets the components of the
+/// given one.
+type Reset = Box<dyn Fn(&mut hecs::World, Option<Entity>) -> Entity + Send + Sync>;
+
+struct Pool {
+    name: &'static str,
+    reset: Reset,
+    free: Vec<Entity>,
+    stats: PoolStats,
+}
+
+#[derive(Default)]
+pub struct EntityPools {
+    pools: Vec<Pool>,
+}
+
+impl std::fmt::Debug for EntityPools {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_map()
+            .entries(self.pools.iter().map(|pool| (pool.name, pool.stats)))
+            .finish()
+    }
+}
+
+impl EntityPools {
+    /// Create a pool of entities made by `prefab`, spawning `count` inactive
+    /// entities up front. `prefab` is also used to reset entities when they're
+    /// reused, so it should give every component a pooled entity can have.
+    pub fn reserve<B, F>(
+        &mut self,
+        world: &mut hecs::World,
+        name: &'static str,
+        count: usize,
+        prefab: F,
+    ) -> PoolId
+    where
+        B: DynamicBundle,
+        F: Fn() -> B + Send + Sync + 'static,
+    {
+        let id = PoolId(self.pools.len());
+        let reset: Reset = Box::new(move |world, entity| match entity {
+            Some(entity) => {
+                // Every component is already there, so this replaces them in
+                // place rather than moving the entity to another archetype.
+                world.insert(entity, prefab()).unwrap();
+                entity
+            }
+            None => {
+                // Spawned with everything it'll ever have, so hiding it later
+                // doesn't move it to another archetype.
+                let mut builder = hecs::EntityBuilder::new();
+                builder.add_bundle(prefab());
+                if !builder.has::<RenderFlags>() {
+                    builder.add(RenderFlags::NONE);
+                }
+                builder.add(Pooled {
+                    pool: id,
+                    active: false,
+                });
+                world.spawn(builder.build())
+            }
+        });
+        let mut pool = Pool {
+            name,
+            reset,
+            free: Vec::with_capacity(count),
+            stats: PoolStats::default(),
+        };
+        for _ in 0..count {
+            let entity = (pool.reset)(world, None);
+            hide(world, entity, true);
+            pool.free.push(entity);
+        }
+        pool.stats.capacity = count;
+        pool.stats.free = count;
+        self.pools.push(pool);
+        id
+    }
+
+    /// Take an entity from the pool with its components reset, spawning a new
+    /// one if none are free.
+    pub fn acquire(&mut self, world: &mut hecs::World, id: PoolId) -> Entity {
+        let pool = &mut self.pools[id.0];
+        pool.stats.acquired += 1;
+        let mut reused = None;
+        while let Some(entity) = pool.free.pop() {
+            if world.contains(entity) {
+                reused = Some(entity);
+                break;
+            }
+            // Despawned from under the pool, such as by clearing the world.
+            pool.stats.capacity -= 1;
+        }
+        let entity = match reused {
+            Some(entity) => {
+                pool.stats.hits += 1;
+                (pool.reset)(world, Some(entity))
+            }
+            None => {
+                pool.stats.capacity += 1;
+                (pool.reset)(world, None)
+            }
+        };
+        pool.stats.free = pool.free.len();
+        set_active(world, entity, true);
+        entity
+    }
+
+    /// Return an entity to its pool. Returns false, doing nothing, if it isn't
+    /// an active pooled entity.
+    pub fn release(&mut self, world: &mut hecs::World, entity: Entity) -> bool {
+        let pool = match world.get::<&Pooled>(entity) {
+            Ok(pooled) if pooled.active => pooled.pool,
+            _ => return false,
+        };
+        set_active(world, entity, false);
+        let pool = &mut self.pools[pool.0];
+        pool.free.push(entity);
+        pool.stats.released += 1;
+        pool.stats.free = pool.free.len();
+        true
+    }
+
+    pub fn pool_stats(&self, id: PoolId) -> PoolStats {
+        self.pools[id.0].stats
+    }
+
+    /// Stats for each pool, by name.
+    pub fn stats(&self) -> impl Iterator<Item = (&'static str, PoolStats)> + '_ {
+        self.pools.iter().map(|pool| (pool.name, pool.stats))
+    }
+}
+
+fn set_active(world: &mut hecs::World, entity: Entity, active: bool) {
+    if let Ok(mut pooled) = world.get::<&mut Pooled>(entity) {
+        pooled.active = active;
+    }
+    hide(world, entity, !active);
+}
+
+fn hide(world: &mut hecs::World, entity: Entity, hidden: bool) {
+    if let Ok(mut flags) = world.get::<&mut RenderFlags>(entity) {
+        if hidden {
+            flags.insert(RenderFlags::HIDDEN);
+        } else {
+            flags.remove(RenderFlags::HIDDEN);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use logger::LogLevel;
+
+    use super::*;
+    use crate::components::{Lifetime, WorldTransform};
+    use crate::World;
+
+    #[test]
+    fn released_entities_are_reused_with_components_reset() {
+        let mut world = hecs::World::new();
+        let mut pools = EntityPools::default();
+        let prefab = || {
+            (
+                WorldTransform::default(),
+                Lifetime {
+                    remaining: std::time::Duration::from_secs(1),
+                },
+            )
+        };
+        let id = pools.reserve(&mut world, "debris", 1, prefab);
+        let archetypes = world.archetypes().len();
+
+        let first = pools.acquire(&mut world, id);
+        assert!(!world
+            .get::<&RenderFlags>(first)
+            .unwrap()
+            .contains(RenderFlags::HIDDEN));
+        world.get::<&mut Lifetime>(first).unwrap().remaining = Default::default();
+        assert!(pools.release(&mut world, first));
+        assert!(!pools.release(&mut world, first));
+        assert!(world
+            .get::<&RenderFlags>(first)
+            .unwrap()
+            .contains(RenderFlags::HIDDEN));
+
+        let again = pools.acquire(&mut world, id);
+        assert_eq!(again, first);
+        assert_eq!(
+            world.get::<&Lifetime>(again).unwrap().remaining,
+            std::time::Duration::from_secs(1)
+        );
+        // The pool was empty, so this one is new.
+        let second = pools.acquire(&mut world, id);
+        assert_ne!(second, first);
+        assert_eq!(world.archetypes().len(), archetypes);
+
+        let (_, stats) = pools.stats().next().unwrap();
+        assert_eq!((stats.acquired, stats.hits, stats.released), (3, 1, 1));
+        assert_eq!((stats.capacity, stats.free), (2, 0));
+        assert!((stats.hit_rate() - 1.0 / 3.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn despawned_entities_are_kept_or_replaced() {
+        let mut world = World::new(None, &LogLevel::Info.logger(), true);
+        let id = world.pools.reserve(&mut world.hecs_world, "debris", 1, || {
+            (WorldTransform::default(),)
+        });
+        let first = world.pools.acquire(&mut world.hecs_world, id);
+        // Released, then left alone once it's waiting to be reused.
+        world.despawn(first).unwrap();
+        world.despawn(first).unwrap();
+        assert!(!world.is_live(first));
+        assert_eq!(world.pools.acquire(&mut world.hecs_world, id), first);
+
+        // Despawned from under the pool, it's replaced rather than reused.
+        world.despawn(first).unwrap();
+        world.hecs_world.despawn(first).unwrap();
+        let replacement = world.pools.acquire(&mut world.hecs_world, id);
+        assert_ne!(replacement, first);
+        assert!(world.is_live(replacement));
+        let stats = world.pools.pool_stats(id);
+        assert_eq!((stats.capacity, stats.free), (1, 0));
+    }
+}
@@ -0,0 +1,423 @@
+//! Groups of models a scene file declares to be loaded ahead of being needed,
+//! such as a spawn area or an interior, and what's become of each.
+//!
+//! The asset loader loads groups by priority within `Config::preload_budget`,
+//! evicting lower priority groups to make room for higher ones. Gameplay asks
+//! whether a group `is_resident` before moving the player somewhere that needs
+//! it, and may `request` a group to have it loaded ahead of its priority.
+
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::Entity;
+
+/// Budget for preloaded groups when none is configured.
+pub const DEFAULT_PRELOAD_BUDGET: usize = 256 * 1024 * 1024;
+
+#[derive(thiserror::Error, Debug)]
+pub enum SceneFileError {
+    #[error("reading {0}: {1}")]
+    Io(PathBuf, std::io::Error),
+    #[error("parsing {0}: {1}")]
+    Parse(PathBuf, serde_yaml::Error),
+    #[error("preload group {0:?} is declared more than once")]
+    DuplicateGroup(String),
+}
+
+/// What a scene file declares, in YAML:
+///
+/// ```yaml
+/// preload_groups:
+///   - name: interior
+///     priority: 5
+///     models:
+///       - path: assets/models/static/cube.obj
+/// ```
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+pub struct SceneFile {
+    #[serde(default)]
+    pub preload_groups: Vec<PreloadGroupDecl>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct PreloadGroupDecl {
+    pub name: String,
+    /// Groups are loaded highest priority first, and evicted lowest first.
+    #[serde(default)]
+    pub priority: i32,
+    pub models: Vec<PreloadModel>,
+}
+
+/// A model of a preload group and the shaders it's drawn with.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize)]
+pub struct PreloadModel {
+    pub path: PathBuf,
+    #[serde(default = "default_vertex_shader")]
+    pub vertex_shader: PathBuf,
+    #[serde(default = "default_fragment_shader")]
+    pub fragment_shader: PathBuf,
+}
+
+fn default_vertex_shader() -> PathBuf {
+    PathBuf::from("assets/shaders/spv/default_vertex.spv")
+}
+
+fn default_fragment_shader() -> PathBuf {
+    PathBuf::from("assets/shaders/spv/default_fragment.spv")
+}
+
+impl SceneFile {
+    pub fn load(path: &Path) -> Result<Self, SceneFileError> {
+        let yaml = std::fs::read_to_string(path)
+            .map_err(|err| SceneFileError::Io(path.to_path_buf(), err))?;
+        Self::parse(path, &yaml)
+    }
+
+    fn parse(path: &Path, yaml: &str) -> Result<Self, SceneFileError> {
+        let scene: SceneFile = serde_yaml::from_str(yaml)
+            .map_err(|err| SceneFileError::Parse(path.to_path_buf(), err))?;
+        let mut names = HashSet::new();
+        for group in &scene.preload_groups {
+            if !names.insert(&group.name) {
+                return Err(SceneFileError::DuplicateGroup(group.name.clone()));
+            }
+        }
+        Ok(scene)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum GroupState {
+    /// Waiting for its turn, or for room in the budget.
+    Pending,
+    /// Loaded, its prefabs spawned.
+    Resident,
+    /// Unloaded to make room for a higher priority group, loaded again once
+    /// there's room.
+    Evicted,
+    /// A model couldn't be loaded, the group won't be tried again.
+    Failed,
+}
+
+#[derive(Debug)]
+struct PreloadGroup {
+    decl: PreloadGroupDecl,
+    state: GroupState,
+    requested: bool,
+    /// Bytes the group's models take, known once they've been loaded.
+    bytes: Option<usize>,
+    /// Prefab of each model while resident, in the order they're declared.
+    prefabs: Vec<Entity>,
+}
+
+impl PreloadGroup {
+    /// Requested groups come before any others, then higher priorities.
+    fn rank(&self) -> (bool, i32) {
+        (self.requested, self.decl.priority)
+    }
+
+    fn is_drawn(&self, drawn: &HashSet<Entity>) -> bool {
+        self.prefabs.iter().any(|prefab| drawn.contains(prefab))
+    }
+}
+
+/// The preload groups of the loaded scene file, see the module docs.
+#[derive(Debug, Default)]
+pub struct PreloadGroups {
+    groups: Vec<PreloadGroup>,
+}
+
+impl PreloadGroups {
+    /// Preload the groups `scene` declares, replacing any declared before.
+    /// Nothing is loaded until the asset loader's next update.
+    pub fn declare(&mut self, scene: SceneFile) {
+        self.groups = scene
+            .preload_groups
+            .into_iter()
+            .map(|decl| PreloadGroup {
+                decl,
+                state: GroupState::Pending,
+                requested: false,
+                bytes: None,
+                prefabs: Vec::new(),
+            })
+            .collect();
+    }
+
+    fn group(&self, name: &str) -> Option<&PreloadGroup> {
+        self.groups.iter().find(|group| group.decl.name == name)
+    }
+
+    fn group_mut(&mut self, name: &str) -> Option<&mut PreloadGroup> {
+        self.groups.iter_mut().find(|group| group.decl.name == name)
+    }
+
+    /// Names of the declared groups, highest priority first.
+    pub fn names(&self) -> Vec<&str> {
+        let mut groups = self.groups.iter().collect::<Vec<_>>();
+        groups.sort_by_key(|group| std::cmp::Reverse(group.rank()));
+        groups
+            .iter()
+            .map(|group| group.decl.name.as_str())
+            .collect()
+    }
+
+    /// State of a group, None if no group has that name.
+    pub fn state(&self, name: &str) -> Option<GroupState> {
+        self.group(name).map(|group| group.state)
+    }
+
+    /// Whether a group's prefabs are spawned, such as before teleporting the
+    /// player somewhere drawn with them.
+    pub fn is_resident(&self, name: &str) -> bool {
+        self.state(name) == Some(GroupState::Resident)
+    }
+
+    /// Prefabs of a resident group's models, in the order they're declared.
+    pub fn prefabs(&self, name: &str) -> Option<&[Entity]> {
+        self.group(name)
+            .filter(|group| group.state == GroupState::Resident)
+            .map(|group| group.prefabs.as_slice())
+    }
+
+    /// Load a group ahead of those that aren't requested, and keep it loaded
+    /// until it's released. Returns false if no group has that name.
+    pub fn request(&mut self, name: &str) -> bool {
+        self.group_mut(name)
+            .map(|group| group.requested = true)
+            .is_some()
+    }
+
+    /// Go back to loading a group by its priority.
+    pub fn release(&mut self, name: &str) {
+        if let Some(group) = self.group_mut(name) {
+            group.requested = false;
+        }
+    }
+
+    /// Bytes taken by resident groups.
+    pub fn resident_bytes(&self) -> usize {
+        self.groups
+            .iter()
+            .filter(|group| group.state == GroupState::Resident)
+            .filter_map(|group| group.bytes)
+            .sum()
+    }
+
+    /// The group to load next: the highest ranked that isn't resident and
+    /// either hasn't been loaded to find its size yet, or fits in `budget`
+    /// once lower ranked groups are evicted. Groups with prefabs in `drawn`
+    /// aren't evicted.
+    pub fn next_to_load(&self, budget: usize, drawn: &HashSet<Entity>) -> Option<&str> {
+        let mut waiting = self
+            .groups
+            .iter()
+            .filter(|group| matches!(group.state, GroupState::Pending | GroupState::Evicted))
+            .collect::<Vec<_>>();
+        waiting.sort_by_key(|group| std::cmp::Reverse(group.rank()));
+        waiting
+            .into_iter()
+            .find(|group| match group.bytes {
+                Some(bytes) => self.evictions_for(group, bytes, budget, drawn).is_some(),
+                None => true,
+            })
+            .map(|group| group.decl.name.as_str())
+    }
+
+    /// Models of a group, to load it.
+    pub fn models(&self, name: &str) -> Option<&[PreloadModel]> {
+        self.group(name).map(|group| group.decl.models.as_slice())
+    }
+
+    /// The groups to evict, lowest ranked first, for the `bytes` of group
+    /// `name` to fit in `budget`. None when it can't fit, or there's no such
+    /// group.
+    pub fn make_room(
+        &self,
+        name: &str,
+        bytes: usize,
+        budget: usize,
+        drawn: &HashSet<Entity>,
+    ) -> Option<Vec<&str>> {
+        self.evictions_for(self.group(name)?, bytes, budget, drawn)
+    }
+
+    fn evictions_for(
+        &self,
+        group: &PreloadGroup,
+        bytes: usize,
+        budget: usize,
+        drawn: &HashSet<Entity>,
+    ) -> Option<Vec<&str>> {
+        let mut free = budget.saturating_sub(self.resident_bytes());
+        let mut evictable = self
+            .groups
+            .iter()
+            .filter(|other| other.state == GroupState::Resident)
+            .filter(|other| other.rank() < group.rank() && !other.is_drawn(drawn))
+            .collect::<Vec<_>>();
+        evictable.sort_by_key(|other| other.rank());
+        let mut evictions = Vec::new();
+        for other in evictable {
+            if bytes <= free {
+                break;
+            }
+            free += other.bytes.unwrap_or(0);
+            evictions.push(other.decl.name.as_str());
+        }
+        (bytes <= free).then_some(evictions)
+    }
+
+    /// Note the bytes a group's models take, once they've been loaded.
+    pub fn set_bytes(&mut self, name: &str, bytes: usize) {
+        if let Some(group) = self.group_mut(name) {
+            group.bytes = Some(bytes);
+        }
+    }
+
+    /// Mark a group resident, with the prefab spawned for each of its models.
+    pub fn set_resident(&mut self, name: &str, prefabs: Vec<Entity>) {
+        if let Some(group) = self.group_mut(name) {
+            group.state = GroupState::Resident;
+            group.prefabs = prefabs;
+        }
+    }
+
+    /// Mark a group evicted, returning the prefabs to despawn.
+    pub fn set_evicted(&mut self, name: &str) -> Vec<Entity> {
+        match self.group_mut(name) {
+            Some(group) => {
+                group.state = GroupState::Evicted;
+                std::mem::take(&mut group.prefabs)
+            }
+            None => Vec::new(),
+        }
+    }
+
+    pub fn set_failed(&mut self, name: &str) {
+        if let Some(group) = self.group_mut(name) {
+            group.state = GroupState::Failed;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scene(groups: &[(&str, i32)]) -> PreloadGroups {
+        let mut preload = PreloadGroups::default();
+        preload.declare(SceneFile {
+            preload_groups: groups
+                .iter()
+                .map(|(name, priority)| PreloadGroupDecl {
+                    name: name.to_string(),
+                    priority: *priority,
+                    models: Vec::new(),
+                })
+                .collect(),
+        });
+        preload
+    }
+
+    fn prefab(id: u32) -> Entity {
+        Entity::from_bits((1 << 32) | u64::from(id)).unwrap()
+    }
+
+    #[test]
+    fn scene_files_declare_groups() {
+        let scene = SceneFile::parse(
+            Path::new("scene.yaml"),
+            "preload_groups:
+  - name: spawn area
+    priority: 10
+    models:
+      - path: assets/models/static/tank.obj
+  - name: interior
+    models:
+      - path: room.obj
+        fragment_shader: room.spv
+",
+        )
+        .unwrap();
+        assert_eq!(scene.preload_groups.len(), 2);
+        assert_eq!(scene.preload_groups[1].priority, 0);
+        let room = &scene.preload_groups[1].models[0];
+        assert_eq!(room.vertex_shader, default_vertex_shader());
+        assert_eq!(room.fragment_shader, PathBuf::from("room.spv"));
+
+        let duplicate = "preload_groups:
+  - {name: a, models: []}
+  - {name: a, models: []}
+";
+        assert!(matches!(
+            SceneFile::parse(Path::new("scene.yaml"), duplicate),
+            Err(SceneFileError::DuplicateGroup(name)) if name == "a"
+        ));
+    }
+
+    #[test]
+    fn groups_load_by_priority_and_requests_come_first() {
+        let mut preload = scene(&[("low", 1), ("high", 5)]);
+        let drawn = HashSet::new();
+        assert_eq!(preload.next_to_load(100, &drawn), Some("high"));
+        assert!(preload.request("low"));
+        assert_eq!(preload.next_to_load(100, &drawn), Some("low"));
+        preload.release("low");
+        assert_eq!(preload.names(), ["high", "low"]);
+        assert!(!preload.request("missing"));
+    }
+
+    #[test]
+    fn lower_priority_groups_are_evicted_under_pressure() {
+        let mut preload = scene(&[("low", 1), ("mid", 3), ("high", 5)]);
+        let mut drawn = HashSet::new();
+        preload.set_bytes("low", 40);
+        preload.set_resident("low", vec![prefab(1)]);
+        preload.set_bytes("mid", 40);
+        preload.set_resident("mid", vec![prefab(2)]);
+        assert_eq!(preload.resident_bytes(), 80);
+
+        assert_eq!(preload.make_room("high", 20, 100, &drawn), Some(vec![]));
+        assert_eq!(
+            preload.make_room("high", 50, 100, &drawn),
+            Some(vec!["low"])
+        );
+        assert_eq!(
+            preload.make_room("high", 90, 100, &drawn),
+            Some(vec!["low", "mid"])
+        );
+        assert_eq!(preload.make_room("high", 101, 100, &drawn), None);
+        // Nothing's evicted for a group of lower priority.
+        assert_eq!(preload.make_room("low", 50, 100, &drawn), None);
+
+        // Nor are groups still being drawn.
+        drawn.insert(prefab(1));
+        assert_eq!(
+            preload.make_room("high", 50, 100, &drawn),
+            Some(vec!["mid"])
+        );
+
+        assert_eq!(preload.set_evicted("mid"), [prefab(2)]);
+        assert_eq!(preload.state("mid"), Some(GroupState::Evicted));
+        assert!(preload.prefabs("mid").is_none());
+        assert_eq!(preload.resident_bytes(), 40);
+    }
+
+    #[test]
+    fn groups_that_dont_fit_are_skipped() {
+        let mut preload = scene(&[("huge", 5), ("small", 1)]);
+        let drawn = HashSet::new();
+        preload.set_bytes("huge", 1000);
+        assert_eq!(preload.next_to_load(100, &drawn), Some("small"));
+        preload.set_bytes("small", 10);
+        preload.set_resident("small", vec![prefab(1)]);
+        assert!(preload.is_resident("small"));
+        assert_eq!(preload.prefabs("small"), Some(&[prefab(1)][..]));
+        assert_eq!(preload.next_to_load(100, &drawn), None);
+        preload.set_failed("huge");
+        assert_eq!(preload.state("huge"), Some(GroupState::Failed));
+    }
+}
@@ -0,0 +1,230 @@
+//! How clients move entities replicated from the server between updates.
+//! Updates arrive late and irregularly, and not every entity should hide that
+//! the same way: players look best drawn a little in the past, blending
+//! between buffered updates, projectiles should keep moving ahead of their
+//! last update, and doors should simply jump to where they're told.
+//!
+//! Entities with a `ReplicationPolicy`, or every replicated entity when the
+//! world's `Config::replication` isn't `Snap`, have the updates received for
+//! them kept in a `ReplicationBuffer`. The world update system moves them to
+//! `ReplicationBuffer::pose_at` every frame. Other entities jump to each
+//! update as it arrives.
+
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use glam::{Quat, Vec3};
+
+/// How far ahead of the last update projectiles are extrapolated, by default.
+pub const DEFAULT_EXTRAPOLATION_CAP: Duration = Duration::from_millis(250);
+
+/// How a client moves a replicated entity between updates from the server.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum ReplicationPolicy {
+    /// Jump to each update as it arrives.
+    #[default]
+    Snap,
+    /// Draw the entity `delay` behind the server, blending between the
+    /// updates either side of that time. Without a delay, the connection's
+    /// quality decides it, see `ConnectionQuality::interpolation_delay`.
+    Interpolate { delay: Option<Duration> },
+    /// Keep moving the entity at the velocity between its last two updates,
+    /// for at most `cap` past the last one.
+    Extrapolate { cap: Duration },
+}
+
+/// An update received for a replicated entity, stamped with the server time
+/// it was sent at.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ReplicationSample {
+    pub server_time: Duration,
+    pub position: Vec3,
+    pub rotation: Quat,
+}
+
+/// Updates received for an entity with a `ReplicationPolicy`, oldest first.
+/// Added by the client when the first update for the entity arrives.
+#[derive(Debug, Default, Clone)]
+pub struct ReplicationBuffer {
+    samples: VecDeque<ReplicationSample>,
+}
+
+impl ReplicationBuffer {
+    /// Most updates kept, enough for the longest interpolation delay at the
+    /// rate the server sends.
+    pub const MAX_SAMPLES: usize = 64;
+
+    /// Keep an update, unless it's no newer than the latest one, as packets
+    /// can arrive out of order.
+    pub fn push(&mut self, sample: ReplicationSample) {
+        if self
+            .latest()
+            .is_some_and(|latest| latest.server_time >= sample.server_time)
+        {
+            return;
+        }
+        if self.samples.len() == Self::MAX_SAMPLES {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(sample);
+    }
+
+    /// The latest update received.
+    pub fn latest(&self) -> Option<&ReplicationSample> {
+        self.samples.back()
+    }
+
+    /// Where to draw the entity at `server_now` under `policy`, interpolating
+    /// by `connection_delay` if the policy doesn't give a delay. None before
+    /// any update has arrived.
+    pub fn pose_at(
+        &self,
+        policy: ReplicationPolicy,
+        server_now: Duration,
+        connection_delay: Duration,
+    ) -> Option<(Vec3, Quat)> {
+        let latest = self.latest()?;
+        match policy {
+            ReplicationPolicy::Snap => Some((latest.position, latest.rotation)),
+            ReplicationPolicy::Interpolate { delay } => {
+                let time = server_now.saturating_sub(delay.unwrap_or(connection_delay));
+                Some(self.interpolated(time))
+            }
+            ReplicationPolicy::Extrapolate { cap } => {
+                let ahead = server_now.saturating_sub(latest.server_time).min(cap);
+                let velocity = self.velocity().unwrap_or(Vec3::ZERO);
+                Some((
+                    latest.position + velocity * ahead.as_secs_f32(),
+                    latest.rotation,
+                ))
+            }
+        }
+    }
+
+    /// The pose at server `time`, held at the first or latest update outside
+    /// of the updates received.
+    fn interpolated(&self, time: Duration) -> (Vec3, Quat) {
+        let after = self
+            .samples
+            .iter()
+            .position(|sample| sample.server_time > time);
+        let (before, after) = match after {
+            Some(0) => (&self.samples[0], &self.samples[0]),
+            Some(after) => (&self.samples[after - 1], &self.samples[after]),
+            None => {
+                let latest = self.samples.back().unwrap();
+                (latest, latest)
+            }
+        };
+        let span = after.server_time.saturating_sub(before.server_time);
+        if span.is_zero() {
+            return (before.position, before.rotation);
+        }
+        let alpha =
+            (time.saturating_sub(before.server_time).as_secs_f32() / span.as_secs_f32()).min(1.0);
+        (
+            before.position.lerp(after.position, alpha),
+            before.rotation.slerp(after.rotation, alpha),
+        )
+    }
+
+    /// Velocity between the last two updates.
+    fn velocity(&self) -> Option<Vec3> {
+        let mut latest = self.samples.iter().rev();
+        let (last, previous) = (latest.next()?, latest.next()?);
+        let span = last.server_time.saturating_sub(previous.server_time);
+        Some((last.position - previous.position) / span.as_secs_f32())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(millis: u64, x: f32) -> ReplicationSample {
+        ReplicationSample {
+            server_time: Duration::from_millis(millis),
+            position: Vec3::new(x, 0.0, 0.0),
+            rotation: Quat::IDENTITY,
+        }
+    }
+
+    fn x_at(buffer: &ReplicationBuffer, policy: ReplicationPolicy, millis: u64) -> f32 {
+        let connection_delay = Duration::from_millis(100);
+        let (position, _) = buffer
+            .pose_at(policy, Duration::from_millis(millis), connection_delay)
+            .unwrap();
+        position.x
+    }
+
+    fn assert_near(x: f32, expected: f32) {
+        assert!((x - expected).abs() < 1e-3, "{x} != {expected}");
+    }
+
+    fn buffer() -> ReplicationBuffer {
+        let mut buffer = ReplicationBuffer::default();
+        buffer.push(sample(100, 0.0));
+        buffer.push(sample(200, 10.0));
+        buffer.push(sample(300, 30.0));
+        buffer
+    }
+
+    #[test]
+    fn snaps_to_the_latest_update() {
+        assert_near(x_at(&buffer(), ReplicationPolicy::Snap, 1000), 30.0);
+        assert_eq!(
+            ReplicationBuffer::default().pose_at(
+                ReplicationPolicy::Snap,
+                Duration::ZERO,
+                Duration::ZERO
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn interpolates_behind_the_server() {
+        let buffer = buffer();
+        let policy = ReplicationPolicy::Interpolate {
+            delay: Some(Duration::from_millis(50)),
+        };
+        assert_near(x_at(&buffer, policy, 200), 5.0);
+        assert_near(x_at(&buffer, policy, 300), 20.0);
+        // Held at either end of what was received.
+        assert_near(x_at(&buffer, policy, 100), 0.0);
+        assert_near(x_at(&buffer, policy, 1000), 30.0);
+        // Without a delay of its own, the connection's is used.
+        let policy = ReplicationPolicy::Interpolate { delay: None };
+        assert_near(x_at(&buffer, policy, 350), 20.0);
+    }
+
+    #[test]
+    fn extrapolates_up_to_the_cap() {
+        let buffer = buffer();
+        let policy = ReplicationPolicy::Extrapolate {
+            cap: Duration::from_millis(100),
+        };
+        assert_near(x_at(&buffer, policy, 300), 30.0);
+        assert_near(x_at(&buffer, policy, 350), 40.0);
+        assert_near(x_at(&buffer, policy, 1000), 50.0);
+
+        // A single update has no velocity to go on.
+        let mut single = ReplicationBuffer::default();
+        single.push(sample(100, 1.0));
+        assert_near(x_at(&single, policy, 200), 1.0);
+    }
+
+    #[test]
+    fn ignores_stale_updates_and_keeps_the_newest() {
+        let mut buffer = buffer();
+        buffer.push(sample(250, 100.0));
+        buffer.push(sample(300, 100.0));
+        assert_eq!(buffer.latest(), Some(&sample(300, 30.0)));
+
+        for i in 0..ReplicationBuffer::MAX_SAMPLES as u64 {
+            buffer.push(sample(400 + i, 0.0));
+        }
+        assert_eq!(buffer.samples.len(), ReplicationBuffer::MAX_SAMPLES);
+        assert_eq!(buffer.samples[0].server_time, Duration::from_millis(400));
+    }
+}
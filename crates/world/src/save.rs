@@ -0,0 +1,523 @@
+//! Save files, and keeping them loadable as components change.
+//!
+//! A save holds a `WorldSnapshot` of the world along with the schema version
+//! each of its components was saved with. Whenever the fields a component is
+//! saved with change, its version in `SaveSchemas` is bumped and a migration
+//! registered that brings values of the previous version up to date. Loading
+//! runs the migrations of every component saved with an older version, and
+//! fails listing every component that can't be migrated, rather than loading
+//! part of a save.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::time::Duration;
+
+use glam::{Mat4, Quat, Vec3};
+use serde::{Deserialize, Serialize};
+
+use crate::components::spatial::SpatialHierarchyNode;
+use crate::components::{Lifetime, PhysicsBody, Projectile, Velocity, WorldTransform};
+use crate::health::HealthFacet;
+use crate::snapshot::{ComponentValues, WorldSnapshot};
+use crate::{Entity, World};
+
+/// Version of the save file layout, as opposed to the versions of the
+/// components in it.
+pub const SAVE_FORMAT_VERSION: u32 = 1;
+
+/// Components captured by `WorldSnapshot::capture`, registered at version 1.
+pub const SNAPSHOT_COMPONENTS: [&str; 6] = [
+    "world_transform",
+    "physics_body",
+    "velocity",
+    "lifetime",
+    "projectile",
+    "health",
+];
+
+/// Brings the values of a component saved with one version up to the next.
+pub type Migration = fn(&mut ComponentValues);
+
+/// A snapshot of the world, and the schema version of each component in it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SaveFile {
+    pub format: u32,
+    /// Schema version of each component, by name.
+    #[serde(default)]
+    pub versions: BTreeMap<String, u32>,
+    pub snapshot: WorldSnapshot,
+}
+
+/// Why a component of a save can't be migrated.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Unmigratable {
+    /// No component of that name is registered.
+    Unknown,
+    /// The save has no version for it.
+    Unversioned,
+    /// Saved by a newer engine, with a version past the registered one.
+    Newer { current: u32 },
+    /// No migration is registered from this version to the next.
+    NoMigration { from: u32 },
+}
+
+/// A component of a save that can't be migrated.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnmigratableComponent {
+    pub component: String,
+    /// The version it was saved with, None if it wasn't.
+    pub saved_version: Option<u32>,
+    pub reason: Unmigratable,
+}
+
+impl fmt::Display for UnmigratableComponent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.component)?;
+        if let Some(version) = self.saved_version {
+            write!(f, " v{version}")?;
+        }
+        match self.reason {
+            Unmigratable::Unknown => write!(f, ": unknown component"),
+            Unmigratable::Unversioned => write!(f, ": saved without a version"),
+            Unmigratable::Newer { current } => {
+                write!(f, ": newer than v{current}, the version of this engine")
+            }
+            Unmigratable::NoMigration { from } => {
+                write!(f, ": no migration from v{from} to v{}", from + 1)
+            }
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug, Clone, PartialEq)]
+pub enum SaveError {
+    #[error("save format {saved} isn't supported, the newest supported is {supported}")]
+    UnsupportedFormat { saved: u32, supported: u32 },
+
+    #[error("components can't be migrated: {}", list(.0))]
+    Unmigratable(Vec<UnmigratableComponent>),
+}
+
+fn list(components: &[UnmigratableComponent]) -> String {
+    components
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[derive(Debug, Clone)]
+struct Schema {
+    version: u32,
+    /// Migrations by the version they migrate from.
+    migrations: BTreeMap<u32, Migration>,
+}
+
+/// The current schema version of each saved component, and the migrations
+/// from its older versions.
+#[derive(Debug, Clone)]
+pub struct SaveSchemas {
+    schemas: BTreeMap<String, Schema>,
+}
+
+impl Default for SaveSchemas {
+    fn default() -> Self {
+        let mut schemas = Self::empty();
+        for component in SNAPSHOT_COMPONENTS {
+            schemas.register(component, 1);
+        }
+        schemas
+    }
+}
+
+impl SaveSchemas {
+    /// Schemas with no components registered.
+    pub fn empty() -> Self {
+        Self {
+            schemas: BTreeMap::new(),
+        }
+    }
+
+    /// Register `component` as saved with `version`, keeping any migrations
+    /// already registered for it.
+    pub fn register(&mut self, component: &str, version: u32) -> &mut Self {
+        self.schemas
+            .entry(component.to_string())
+            .or_insert_with(|| Schema {
+                version,
+                migrations: BTreeMap::new(),
+            })
+            .version = version;
+        self
+    }
+
+    /// Register how values of `component` saved with version `from` become
+    /// version `from + 1`.
+    ///
+    /// Panics if `component` isn't registered.
+    pub fn migration(&mut self, component: &str, from: u32, migrate: Migration) -> &mut Self {
+        let schema = self
+            .schemas
+            .get_mut(component)
+            .unwrap_or_else(|| panic!("migration for unregistered component {component}"));
+        schema.migrations.insert(from, migrate);
+        self
+    }
+
+    /// The current version of `component`, None if it isn't registered.
+    pub fn version(&self, component: &str) -> Option<u32> {
+        self.schemas.get(component).map(|schema| schema.version)
+    }
+
+    /// Capture a save of the world, with the current version of every
+    /// registered component.
+    pub fn save(&self, world: &World) -> SaveFile {
+        SaveFile {
+            format: SAVE_FORMAT_VERSION,
+            versions: self
+                .schemas
+                .iter()
+                .map(|(name, schema)| (name.clone(), schema.version))
+                .collect(),
+            snapshot: WorldSnapshot::capture(world),
+        }
+    }
+
+    /// Bring every component of `save` up to its current version. Fails
+    /// listing every component that can't be, leaving none migrated.
+    pub fn migrate(&self, mut save: SaveFile) -> Result<SaveFile, SaveError> {
+        if save.format > SAVE_FORMAT_VERSION {
+            return Err(SaveError::UnsupportedFormat {
+                saved: save.format,
+                supported: SAVE_FORMAT_VERSION,
+            });
+        }
+
+        let saved: BTreeSet<&String> = save
+            .snapshot
+            .entities
+            .values()
+            .flat_map(|components| components.keys())
+            .collect();
+        let mut steps = BTreeMap::new();
+        let mut unmigratable = Vec::new();
+        for component in saved {
+            let saved_version = save.versions.get(component).copied();
+            match self.steps(component, saved_version) {
+                Ok(migrations) => {
+                    steps.insert(component.clone(), migrations);
+                }
+                Err(reason) => unmigratable.push(UnmigratableComponent {
+                    component: component.clone(),
+                    saved_version,
+                    reason,
+                }),
+            }
+        }
+        if !unmigratable.is_empty() {
+            return Err(SaveError::Unmigratable(unmigratable));
+        }
+
+        for components in save.snapshot.entities.values_mut() {
+            for (component, values) in components.iter_mut() {
+                for migrate in &steps[component] {
+                    migrate(values);
+                }
+            }
+        }
+        for (component, schema) in &self.schemas {
+            save.versions.insert(component.clone(), schema.version);
+        }
+        save.format = SAVE_FORMAT_VERSION;
+        Ok(save)
+    }
+
+    /// The migrations, in order, from `saved_version` of `component` to its
+    /// current version.
+    fn steps(
+        &self,
+        component: &str,
+        saved_version: Option<u32>,
+    ) -> Result<Vec<Migration>, Unmigratable> {
+        let schema = self.schemas.get(component).ok_or(Unmigratable::Unknown)?;
+        let saved_version = saved_version.ok_or(Unmigratable::Unversioned)?;
+        if saved_version > schema.version {
+            return Err(Unmigratable::Newer {
+                current: schema.version,
+            });
+        }
+        (saved_version..schema.version)
+            .map(|from| {
+                schema
+                    .migrations
+                    .get(&from)
+                    .copied()
+                    .ok_or(Unmigratable::NoMigration { from })
+            })
+            .collect()
+    }
+}
+
+/// Entities restored from a save, and those it has that the world doesn't.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct RestoreStats {
+    pub restored: usize,
+    pub missing: usize,
+}
+
+impl SaveFile {
+    /// Set the saved components of the entities that are still in the world.
+    /// Fields missing from the save keep their current values, so the save
+    /// must have been migrated first.
+    pub fn restore(&self, world: &mut World) -> RestoreStats {
+        let mut stats = RestoreStats::default();
+        for (bits, components) in &self.snapshot.entities {
+            let entity = Entity::from_bits(*bits).filter(|e| world.hecs_world.contains(*e));
+            let entity = match entity {
+                Some(entity) => entity,
+                None => {
+                    stats.missing += 1;
+                    continue;
+                }
+            };
+            for (component, values) in components {
+                restore_component(world, entity, component, values);
+            }
+            stats.restored += 1;
+        }
+        stats
+    }
+}
+
+fn restore_component(world: &mut World, entity: Entity, component: &str, values: &ComponentValues) {
+    let field = |name: &str, current: f32| values.get(name).copied().unwrap_or(current);
+    let vec3 = |prefix: &str, current: Vec3| {
+        Vec3::new(
+            field(&format!("{prefix}.x"), current.x),
+            field(&format!("{prefix}.y"), current.y),
+            field(&format!("{prefix}.z"), current.z),
+        )
+    };
+    let hecs_world = &mut world.hecs_world;
+    match component {
+        "world_transform" => {
+            let current = match hecs_world.get::<&WorldTransform>(entity) {
+                Ok(transform) => transform.world,
+                Err(_) => return,
+            };
+            let (scale, rot, pos) = current.to_scale_rotation_translation();
+            let rot = Quat::from_xyzw(
+                field("rot.x", rot.x),
+                field("rot.y", rot.y),
+                field("rot.z", rot.z),
+                field("rot.w", rot.w),
+            )
+            .normalize();
+            let saved =
+                Mat4::from_scale_rotation_translation(vec3("scale", scale), rot, vec3("pos", pos));
+            if let Ok(mut node) = hecs_world.get::<&mut SpatialHierarchyNode>(entity) {
+                let parent = hecs_world
+                    .get::<&WorldTransform>(node.parent)
+                    .map(|parent| parent.world)
+                    .unwrap_or(Mat4::IDENTITY);
+                node.transform = parent.inverse() * saved;
+                node.mark_updated();
+            }
+            if let Ok(mut transform) = hecs_world.get::<&mut WorldTransform>(entity) {
+                transform.world = saved;
+            }
+        }
+        "physics_body" => {
+            if let Ok(mut body) = hecs_world.get::<&mut PhysicsBody>(entity) {
+                body.linear_velocity = vec3("linear_velocity", body.linear_velocity);
+                body.angular_velocity = vec3("angular_velocity", body.angular_velocity);
+                body.mass = field("mass", body.mass);
+            }
+        }
+        "velocity" => {
+            if let Ok(mut velocity) = hecs_world.get::<&mut Velocity>(entity) {
+                velocity.linear = vec3("linear", velocity.linear);
+            }
+        }
+        "lifetime" => {
+            if let Ok(mut lifetime) = hecs_world.get::<&mut Lifetime>(entity) {
+                let remaining = field("remaining", lifetime.remaining.as_secs_f32());
+                lifetime.remaining = Duration::from_secs_f32(remaining.max(0.0));
+            }
+        }
+        "projectile" => {
+            if let Ok(mut projectile) = hecs_world.get::<&mut Projectile>(entity) {
+                projectile.damage = field("damage", projectile.damage as f32) as u32;
+            }
+        }
+        "health" => {
+            if let Ok(mut health) = hecs_world.get::<&mut HealthFacet>(entity) {
+                health.hp = field("hp", health.hp as f32) as u32;
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use logger::LogLevel;
+
+    use super::*;
+
+    type Fields<'a> = &'a [(&'a str, f32)];
+
+    fn save(versions: &[(&str, u32)], entities: &[(u64, &str, Fields)]) -> SaveFile {
+        let mut snapshot = WorldSnapshot::default();
+        for (entity, component, fields) in entities {
+            snapshot.entities.entry(*entity).or_default().insert(
+                component.to_string(),
+                fields
+                    .iter()
+                    .map(|(field, value)| (field.to_string(), *value))
+                    .collect(),
+            );
+        }
+        SaveFile {
+            format: SAVE_FORMAT_VERSION,
+            versions: versions
+                .iter()
+                .map(|(name, version)| (name.to_string(), *version))
+                .collect(),
+            snapshot,
+        }
+    }
+
+    fn rename_hp(values: &mut ComponentValues) {
+        if let Some(hp) = values.remove("health") {
+            values.insert("hp".to_string(), hp);
+        }
+    }
+
+    fn halve_hp(values: &mut ComponentValues) {
+        if let Some(hp) = values.get_mut("hp") {
+            *hp /= 2.0;
+        }
+    }
+
+    #[test]
+    fn runs_migrations_in_order_from_the_saved_version() {
+        let mut schemas = SaveSchemas::empty();
+        schemas
+            .register("health", 3)
+            .migration("health", 1, rename_hp)
+            .migration("health", 2, halve_hp);
+        let old = save(&[("health", 1)], &[(1, "health", &[("health", 100.0)])]);
+        let migrated = schemas.migrate(old).unwrap();
+        assert_eq!(migrated.versions["health"], 3);
+        assert_eq!(migrated.snapshot.entities[&1]["health"]["hp"], 50.0);
+
+        let current = save(&[("health", 3)], &[(1, "health", &[("hp", 10.0)])]);
+        assert_eq!(schemas.migrate(current.clone()).unwrap(), current);
+    }
+
+    #[test]
+    fn lists_every_component_that_cant_be_migrated() {
+        let mut schemas = SaveSchemas::empty();
+        schemas
+            .register("health", 3)
+            .migration("health", 2, halve_hp)
+            .register("velocity", 1)
+            .register("lifetime", 1)
+            .register("projectile", 1);
+        let old = save(
+            &[("health", 1), ("velocity", 2), ("projectile", 1)],
+            &[
+                (1, "health", &[("hp", 1.0)]),
+                (1, "velocity", &[("linear.x", 1.0)]),
+                (2, "lifetime", &[("remaining", 1.0)]),
+                (2, "projectile", &[("damage", 1.0)]),
+                (2, "shield", &[("charge", 1.0)]),
+            ],
+        );
+        let err = schemas.migrate(old).unwrap_err();
+        let unmigratable = |component: &str, saved_version, reason| UnmigratableComponent {
+            component: component.to_string(),
+            saved_version,
+            reason,
+        };
+        assert_eq!(
+            err,
+            SaveError::Unmigratable(vec![
+                unmigratable("health", Some(1), Unmigratable::NoMigration { from: 1 }),
+                unmigratable("lifetime", None, Unmigratable::Unversioned),
+                unmigratable("shield", None, Unmigratable::Unknown),
+                unmigratable("velocity", Some(2), Unmigratable::Newer { current: 1 }),
+            ])
+        );
+        assert_eq!(
+            err.to_string(),
+            "components can't be migrated: health v1: no migration from v1 to v2, \
+             lifetime: saved without a version, shield: unknown component, \
+             velocity v2: newer than v1, the version of this engine"
+        );
+    }
+
+    #[test]
+    fn rejects_newer_save_formats() {
+        let mut newer = save(&[], &[]);
+        newer.format = SAVE_FORMAT_VERSION + 1;
+        assert!(matches!(
+            SaveSchemas::default().migrate(newer),
+            Err(SaveError::UnsupportedFormat { .. })
+        ));
+    }
+
+    #[test]
+    fn restores_saved_components_of_existing_entities() {
+        let mut world = World::new(None, &LogLevel::Info.logger(), true);
+        let root = world.root.unwrap();
+        let entity = world.hecs_world.spawn((
+            SpatialHierarchyNode::new_at(root, Vec3::new(1.0, 2.0, 3.0)),
+            WorldTransform::default(),
+            HealthFacet::new(10),
+            Velocity { linear: Vec3::ZERO },
+        ));
+        let schemas = SaveSchemas::default();
+        let mut saved = schemas.save(&world);
+        let values = saved
+            .snapshot
+            .entities
+            .get_mut(&entity.to_bits().get())
+            .unwrap();
+        values
+            .get_mut("health")
+            .unwrap()
+            .insert("hp".to_string(), 4.0);
+        values.get_mut("velocity").unwrap().remove("linear.y");
+        values
+            .get_mut("velocity")
+            .unwrap()
+            .insert("linear.x".to_string(), 2.0);
+        values
+            .get_mut("world_transform")
+            .unwrap()
+            .insert("pos.y".to_string(), 5.0);
+        let restored = saved.snapshot.entities.len();
+        saved.snapshot.entities.insert(u64::MAX, BTreeMap::new());
+
+        let stats = schemas.migrate(saved).unwrap().restore(&mut world);
+        assert_eq!(
+            stats,
+            RestoreStats {
+                restored,
+                missing: 1
+            }
+        );
+        assert_eq!(world.hecs_world.get::<&HealthFacet>(entity).unwrap().hp, 4);
+        assert_eq!(
+            world.hecs_world.get::<&Velocity>(entity).unwrap().linear,
+            Vec3::new(2.0, 0.0, 0.0)
+        );
+        let node = world
+            .hecs_world
+            .get::<&SpatialHierarchyNode>(entity)
+            .unwrap();
+        assert_eq!(node.get_pos().y, 5.0);
+    }
+}
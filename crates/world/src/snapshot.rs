@@ -0,0 +1,691 @@
+//! Snapshots of the state that should agree between worlds, and diffs of
+//! them, for tracking down why a client's world has diverged from the
+//! server's.
+//!
+//! A snapshot holds the values of the components worth comparing, by entity
+//! and by field. Snapshots are serializable, so they can be written out on
+//! both ends and diffed later. When sending whole snapshots is too much, a
+//! `SnapshotChecksums` breakdown tells which entities and components differ,
+//! without their values.
+//!
+//! Comparing whole snapshots every tick is too much for a live session, so
+//! `SliceChecksum`s hash one group of entities at a time, a different group
+//! each tick, covering the world every `groups` ticks. A `DivergenceTracker`
+//! compares the slices of both ends, and tells which groups keep disagreeing.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use glam::Mat4;
+use serde::{Deserialize, Serialize};
+
+use crate::components::{Lifetime, PhysicsBody, Projectile, Velocity, WorldTransform};
+use crate::health::HealthFacet;
+use crate::World;
+
+/// Values compared before checksumming are rounded to this, so values that
+/// went through the same arithmetic in a different order still agree.
+pub const CHECKSUM_QUANTUM: f32 = 1e-3;
+
+/// Groups entities are split into for time-sliced checksums.
+pub const SLICE_GROUPS: u16 = 16;
+
+/// Checks of a group in a row that have to disagree before it's reported as
+/// diverged, so state still in flight between the ends isn't.
+pub const DIVERGENCE_CHECKS: u32 = 3;
+
+/// Values of a component's fields, by field name.
+pub type ComponentValues = BTreeMap<String, f32>;
+
+/// Component values of every entity with any compared components, by entity
+/// bits, which are the same on the server and its clients.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct WorldSnapshot {
+    /// The world's update count when the snapshot was captured.
+    pub update: u64,
+    pub entities: BTreeMap<u64, BTreeMap<String, ComponentValues>>,
+}
+
+/// A checksum for each component of each entity in a snapshot.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SnapshotChecksums {
+    pub update: u64,
+    pub entities: BTreeMap<u64, BTreeMap<String, u64>>,
+}
+
+/// Whether an entity exists on both sides of a diff.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Presence {
+    Both,
+    OnlyLocal,
+    OnlyRemote,
+}
+
+/// A field that differs, None where a side doesn't have it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldDiff {
+    pub field: String,
+    pub local: Option<f32>,
+    pub remote: Option<f32>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ComponentDiff {
+    OnlyLocal(String),
+    OnlyRemote(String),
+    Values {
+        component: String,
+        fields: Vec<FieldDiff>,
+    },
+    /// Checksums disagree, the remote values aren't known.
+    Checksum(String),
+}
+
+impl ComponentDiff {
+    pub fn component(&self) -> &str {
+        match self {
+            ComponentDiff::OnlyLocal(component)
+            | ComponentDiff::OnlyRemote(component)
+            | ComponentDiff::Values { component, .. }
+            | ComponentDiff::Checksum(component) => component,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EntityDiff {
+    pub entity: u64,
+    pub presence: Presence,
+    pub components: Vec<ComponentDiff>,
+}
+
+/// Every entity that differs between a local and a remote snapshot, by
+/// entity bits.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SnapshotDiff {
+    pub local_update: u64,
+    pub remote_update: u64,
+    pub entities: Vec<EntityDiff>,
+}
+
+impl SnapshotDiff {
+    /// Values closer than this are considered the same by `WorldSnapshot::diff`
+    /// callers that have no better idea, such as the console.
+    pub const DEFAULT_TOLERANCE: f32 = 0.01;
+
+    pub fn is_empty(&self) -> bool {
+        self.entities.is_empty()
+    }
+
+    pub fn entity(&self, entity: u64) -> Option<&EntityDiff> {
+        self.entities.iter().find(|diff| diff.entity == entity)
+    }
+}
+
+impl fmt::Display for SnapshotDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} entities differ between local update {} and remote update {}",
+            self.entities.len(),
+            self.local_update,
+            self.remote_update
+        )?;
+        for entity in &self.entities {
+            write!(f, "\nentity {:x}", entity.entity)?;
+            match entity.presence {
+                Presence::Both => {}
+                Presence::OnlyLocal => write!(f, " only local")?,
+                Presence::OnlyRemote => write!(f, " only remote")?,
+            }
+            for component in &entity.components {
+                match component {
+                    ComponentDiff::OnlyLocal(name) => write!(f, "\n  {name}: only local")?,
+                    ComponentDiff::OnlyRemote(name) => write!(f, "\n  {name}: only remote")?,
+                    ComponentDiff::Checksum(name) => write!(f, "\n  {name}: checksum differs")?,
+                    ComponentDiff::Values { component, fields } => {
+                        for field in fields {
+                            write!(
+                                f,
+                                "\n  {component}.{}: {:?} local, {:?} remote",
+                                field.field, field.local, field.remote
+                            )?;
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn transform_values(transform: &Mat4) -> ComponentValues {
+    let (scale, rot, pos) = transform.to_scale_rotation_translation();
+    let mut values = ComponentValues::new();
+    for (name, value) in ["x", "y", "z"].iter().zip(pos.to_array()) {
+        values.insert(format!("pos.{name}"), value);
+    }
+    for (name, value) in ["x", "y", "z", "w"].iter().zip(rot.to_array()) {
+        values.insert(format!("rot.{name}"), value);
+    }
+    for (name, value) in ["x", "y", "z"].iter().zip(scale.to_array()) {
+        values.insert(format!("scale.{name}"), value);
+    }
+    values
+}
+
+fn values<const N: usize>(fields: [(&str, f32); N]) -> ComponentValues {
+    fields
+        .into_iter()
+        .map(|(field, value)| (field.to_string(), value))
+        .collect()
+}
+
+impl WorldSnapshot {
+    /// Capture the compared components of every entity in the world.
+    pub fn capture(world: &World) -> Self {
+        let mut entities = BTreeMap::new();
+        for entity in world.hecs_world.iter() {
+            let mut components = BTreeMap::new();
+            if let Some(transform) = entity.get::<&WorldTransform>() {
+                components.insert(
+                    "world_transform".to_string(),
+                    transform_values(&transform.world),
+                );
+            }
+            if let Some(body) = entity.get::<&PhysicsBody>() {
+                let [lx, ly, lz] = body.linear_velocity.to_array();
+                let [ax, ay, az] = body.angular_velocity.to_array();
+                components.insert(
+                    "physics_body".to_string(),
+                    values([
+                        ("linear_velocity.x", lx),
+                        ("linear_velocity.y", ly),
+                        ("linear_velocity.z", lz),
+                        ("angular_velocity.x", ax),
+                        ("angular_velocity.y", ay),
+                        ("angular_velocity.z", az),
+                        ("mass", body.mass),
+                    ]),
+                );
+            }
+            if let Some(velocity) = entity.get::<&Velocity>() {
+                let [x, y, z] = velocity.linear.to_array();
+                components.insert(
+                    "velocity".to_string(),
+                    values([("linear.x", x), ("linear.y", y), ("linear.z", z)]),
+                );
+            }
+            if let Some(lifetime) = entity.get::<&Lifetime>() {
+                components.insert(
+                    "lifetime".to_string(),
+                    values([("remaining", lifetime.remaining.as_secs_f32())]),
+                );
+            }
+            if let Some(projectile) = entity.get::<&Projectile>() {
+                components.insert(
+                    "projectile".to_string(),
+                    values([("damage", projectile.damage as f32)]),
+                );
+            }
+            if let Some(health) = entity.get::<&HealthFacet>() {
+                components.insert("health".to_string(), values([("hp", health.hp as f32)]));
+            }
+            if !components.is_empty() {
+                entities.insert(entity.entity().to_bits().get(), components);
+            }
+        }
+        Self {
+            update: world.stats.updates,
+            entities,
+        }
+    }
+
+    /// Checksums of every component, to send in place of the snapshot.
+    pub fn checksums(&self) -> SnapshotChecksums {
+        SnapshotChecksums {
+            update: self.update,
+            entities: self
+                .entities
+                .iter()
+                .map(|(entity, components)| {
+                    let checksums = components
+                        .iter()
+                        .map(|(name, values)| (name.clone(), checksum(values)))
+                        .collect();
+                    (*entity, checksums)
+                })
+                .collect(),
+        }
+    }
+
+    /// Compare this, the local snapshot, with a remote one. Fields closer than
+    /// `tolerance` are the same.
+    pub fn diff(&self, remote: &WorldSnapshot, tolerance: f32) -> SnapshotDiff {
+        let entities = diff_entities(
+            &self.entities,
+            &remote.entities,
+            |component, local, remote| {
+                let fields = diff_fields(local, remote, tolerance);
+                (!fields.is_empty()).then(|| ComponentDiff::Values {
+                    component: component.to_string(),
+                    fields,
+                })
+            },
+        );
+        SnapshotDiff {
+            local_update: self.update,
+            remote_update: remote.update,
+            entities,
+        }
+    }
+
+    /// Compare this, the local snapshot, with a remote checksum breakdown.
+    /// Differing components are known, but not which of their fields differ.
+    pub fn diff_checksums(&self, remote: &SnapshotChecksums) -> SnapshotDiff {
+        let local = self.checksums();
+        let entities = diff_entities(
+            &local.entities,
+            &remote.entities,
+            |component, local, remote| {
+                (local != remote).then(|| ComponentDiff::Checksum(component.to_string()))
+            },
+        );
+        SnapshotDiff {
+            local_update: self.update,
+            remote_update: remote.update,
+            entities,
+        }
+    }
+}
+
+/// The group of `groups` an entity is hashed in, from its id so it stays put
+/// for the entity's life.
+pub fn slice_group(entity_bits: u64, groups: u16) -> u16 {
+    (entity_bits as u32 % u32::from(groups.max(1))) as u16
+}
+
+/// A checksum of the entities of one group, hashed from whatever state the
+/// caller compares, such as what's replicated. Entities can be added in any
+/// order.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SliceChecksum {
+    /// The update of the world the slice was hashed at, on the end that
+    /// chose the group.
+    pub update: u64,
+    pub group: u16,
+    pub groups: u16,
+    /// Entities hashed, so a missing entity can be told from a differing one.
+    pub entities: u16,
+    pub checksum: u64,
+}
+
+impl SliceChecksum {
+    pub fn new(update: u64, group: u16, groups: u16) -> Self {
+        Self {
+            update,
+            group,
+            groups: groups.max(1),
+            entities: 0,
+            checksum: 0,
+        }
+    }
+
+    pub fn contains(&self, entity_bits: u64) -> bool {
+        slice_group(entity_bits, self.groups) == self.group
+    }
+
+    /// Hash an entity's state, unless it's in another group.
+    pub fn add(&mut self, entity_bits: u64, state: &[u8]) {
+        if !self.contains(entity_bits) {
+            return;
+        }
+        let hash = fnv1a(
+            entity_bits
+                .to_le_bytes()
+                .into_iter()
+                .chain(state.iter().copied()),
+        );
+        // Summed, so the order entities are iterated in doesn't matter.
+        self.checksum = self.checksum.wrapping_add(hash);
+        self.entities = self.entities.saturating_add(1);
+    }
+
+    /// Whether the same entities hashed the same on both ends.
+    pub fn agrees(&self, other: &SliceChecksum) -> bool {
+        self.entities == other.entities && self.checksum == other.checksum
+    }
+}
+
+/// How a group compared, see `DivergenceTracker::compare`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SliceComparison {
+    Agreed,
+    Disagreed,
+    /// Disagreed for `DIVERGENCE_CHECKS` checks in a row.
+    Diverged,
+    /// Agreed after having diverged.
+    Recovered,
+}
+
+/// Checks of a group so far.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct GroupCheck {
+    /// Update of the last slice of the group compared.
+    pub checked_update: Option<u64>,
+    /// Checks in a row that disagreed.
+    pub mismatches: u32,
+    /// Update of the first of the checks in a row that disagreed.
+    pub mismatched_since: Option<u64>,
+}
+
+/// Which groups of entities agree between this end and its peer. Slices
+/// hashed here are kept until the peer's of the same group and update
+/// arrive.
+#[derive(Debug, Clone)]
+pub struct DivergenceTracker {
+    groups: u16,
+    next_group: u16,
+    local: Vec<Option<SliceChecksum>>,
+    checks: Vec<GroupCheck>,
+}
+
+impl Default for DivergenceTracker {
+    fn default() -> Self {
+        Self::new(SLICE_GROUPS)
+    }
+}
+
+impl DivergenceTracker {
+    pub fn new(groups: u16) -> Self {
+        let groups = groups.max(1);
+        Self {
+            groups,
+            next_group: 0,
+            local: vec![None; groups as usize],
+            checks: vec![GroupCheck::default(); groups as usize],
+        }
+    }
+
+    pub fn groups(&self) -> u16 {
+        self.groups
+    }
+
+    /// The group to hash next, for the end choosing them. Every group is
+    /// chosen once every `groups` calls.
+    pub fn next_group(&mut self) -> u16 {
+        let group = self.next_group;
+        self.next_group = (group + 1) % self.groups;
+        group
+    }
+
+    /// Keep a slice hashed here, to compare with the peer's.
+    pub fn hashed(&mut self, slice: SliceChecksum) {
+        if slice.groups != self.groups {
+            // The peer splits entities differently, start over its way.
+            *self = Self::new(slice.groups);
+        }
+        self.local[slice.group as usize] = Some(slice);
+    }
+
+    /// Compare the peer's slice with the one hashed here of the same group
+    /// and update, or None if there isn't one.
+    pub fn compare(&mut self, remote: &SliceChecksum) -> Option<SliceComparison> {
+        let local = self.local.get(remote.group as usize).copied().flatten()?;
+        if remote.groups != self.groups || local.update != remote.update {
+            return None;
+        }
+        let check = &mut self.checks[remote.group as usize];
+        check.checked_update = Some(remote.update);
+        if local.agrees(remote) {
+            let diverged = check.mismatches >= DIVERGENCE_CHECKS;
+            check.mismatches = 0;
+            check.mismatched_since = None;
+            return Some(if diverged {
+                SliceComparison::Recovered
+            } else {
+                SliceComparison::Agreed
+            });
+        }
+        if check.mismatches == 0 {
+            check.mismatched_since = Some(remote.update);
+        }
+        check.mismatches += 1;
+        Some(if check.mismatches == DIVERGENCE_CHECKS {
+            SliceComparison::Diverged
+        } else {
+            SliceComparison::Disagreed
+        })
+    }
+
+    pub fn check(&self, group: u16) -> Option<&GroupCheck> {
+        self.checks.get(group as usize)
+    }
+
+    /// Groups that have diverged, with how they've been checked.
+    pub fn diverged(&self) -> impl Iterator<Item = (u16, &GroupCheck)> {
+        (0..self.groups)
+            .zip(&self.checks)
+            .filter(|(_, check)| check.mismatches >= DIVERGENCE_CHECKS)
+    }
+
+    /// Whether every group has been compared at least once.
+    pub fn covered(&self) -> bool {
+        self.checks
+            .iter()
+            .all(|check| check.checked_update.is_some())
+    }
+}
+
+/// Diff the entities and components present on either side, comparing those
+/// on both with `compare`.
+fn diff_entities<T>(
+    local: &BTreeMap<u64, BTreeMap<String, T>>,
+    remote: &BTreeMap<u64, BTreeMap<String, T>>,
+    compare: impl Fn(&str, &T, &T) -> Option<ComponentDiff>,
+) -> Vec<EntityDiff> {
+    let mut entities = local
+        .keys()
+        .chain(remote.keys())
+        .copied()
+        .collect::<Vec<_>>();
+    entities.sort_unstable();
+    entities.dedup();
+    let empty = BTreeMap::new();
+    entities
+        .into_iter()
+        .filter_map(|entity| {
+            let presence = match (local.get(&entity), remote.get(&entity)) {
+                (Some(_), Some(_)) => Presence::Both,
+                (Some(_), None) => Presence::OnlyLocal,
+                _ => Presence::OnlyRemote,
+            };
+            let local = local.get(&entity).unwrap_or(&empty);
+            let remote = remote.get(&entity).unwrap_or(&empty);
+            let mut names = local.keys().chain(remote.keys()).collect::<Vec<_>>();
+            names.sort_unstable();
+            names.dedup();
+            let components = names
+                .into_iter()
+                .filter_map(|name| match (local.get(name), remote.get(name)) {
+                    (Some(local), Some(remote)) => compare(name, local, remote),
+                    (Some(_), None) => Some(ComponentDiff::OnlyLocal(name.clone())),
+                    _ => Some(ComponentDiff::OnlyRemote(name.clone())),
+                })
+                .collect::<Vec<_>>();
+            (!components.is_empty()).then_some(EntityDiff {
+                entity,
+                presence,
+                components,
+            })
+        })
+        .collect()
+}
+
+fn diff_fields(
+    local: &ComponentValues,
+    remote: &ComponentValues,
+    tolerance: f32,
+) -> Vec<FieldDiff> {
+    let mut fields = local.keys().chain(remote.keys()).collect::<Vec<_>>();
+    fields.sort_unstable();
+    fields.dedup();
+    fields
+        .into_iter()
+        .filter_map(|field| {
+            let (local, remote) = (local.get(field).copied(), remote.get(field).copied());
+            let same = matches!((local, remote), (Some(a), Some(b)) if (a - b).abs() <= tolerance);
+            (!same).then(|| FieldDiff {
+                field: field.clone(),
+                local,
+                remote,
+            })
+        })
+        .collect()
+}
+
+/// FNV-1a over the field names and quantized values, so checksums agree
+/// between builds and platforms.
+fn checksum(values: &ComponentValues) -> u64 {
+    fnv1a(values.iter().flat_map(|(field, value)| {
+        let quantized = (value / CHECKSUM_QUANTUM).round() as i64;
+        field.bytes().chain(quantized.to_le_bytes())
+    }))
+}
+
+fn fnv1a(bytes: impl IntoIterator<Item = u8>) -> u64 {
+    const OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0100_0000_01b3;
+    bytes.into_iter().fold(OFFSET, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(PRIME)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Fields<'a> = &'a [(&'a str, f32)];
+
+    fn snapshot(entities: &[(u64, &str, Fields)]) -> WorldSnapshot {
+        let mut snapshot = WorldSnapshot::default();
+        for (entity, component, fields) in entities {
+            snapshot.entities.entry(*entity).or_default().insert(
+                component.to_string(),
+                fields
+                    .iter()
+                    .map(|(field, value)| (field.to_string(), *value))
+                    .collect(),
+            );
+        }
+        snapshot
+    }
+
+    #[test]
+    fn reports_mismatching_entities_components_and_values() {
+        let local = snapshot(&[
+            (1, "velocity", &[("linear.x", 1.0), ("linear.y", 0.0)]),
+            (1, "health", &[("hp", 10.0)]),
+            (2, "velocity", &[("linear.x", 2.0)]),
+            (3, "health", &[("hp", 5.0)]),
+        ]);
+        let remote = snapshot(&[
+            (1, "velocity", &[("linear.x", 1.005), ("linear.y", 3.0)]),
+            (2, "velocity", &[("linear.x", 2.0)]),
+            (4, "health", &[("hp", 5.0)]),
+        ]);
+
+        let diff = local.diff(&remote, SnapshotDiff::DEFAULT_TOLERANCE);
+        assert_eq!(diff.entities.len(), 3);
+        assert_eq!(
+            diff.entity(1).unwrap().components,
+            vec![
+                ComponentDiff::OnlyLocal("health".to_string()),
+                ComponentDiff::Values {
+                    component: "velocity".to_string(),
+                    fields: vec![FieldDiff {
+                        field: "linear.y".to_string(),
+                        local: Some(0.0),
+                        remote: Some(3.0),
+                    }],
+                },
+            ]
+        );
+        assert!(diff.entity(2).is_none());
+        assert_eq!(diff.entity(3).unwrap().presence, Presence::OnlyLocal);
+        assert_eq!(diff.entity(4).unwrap().presence, Presence::OnlyRemote);
+        assert!(local.diff(&local, 0.0).is_empty());
+
+        let diff = local.diff_checksums(&remote.checksums());
+        assert_eq!(
+            diff.entity(1).unwrap().components[1],
+            ComponentDiff::Checksum("velocity".to_string())
+        );
+        assert!(diff.entity(2).is_none());
+        assert!(local.diff_checksums(&local.checksums()).is_empty());
+    }
+
+    /// The slice of the next group, hashing each entity's `state`.
+    fn slices(
+        tracker: &mut DivergenceTracker,
+        update: u64,
+        entities: &[u64],
+        state: impl Fn(u64) -> u64,
+    ) -> SliceChecksum {
+        let mut slice = SliceChecksum::new(update, tracker.next_group(), tracker.groups());
+        for entity in entities {
+            slice.add(*entity, &state(*entity).to_le_bytes());
+        }
+        slice
+    }
+
+    #[test]
+    fn time_sliced_checksums_cover_the_world_and_localize_divergence() {
+        let entities = (0..40).collect::<Vec<u64>>();
+        let mut server = DivergenceTracker::new(4);
+        let mut client = DivergenceTracker::new(4);
+
+        // Entity 6 is in group 2 and diverges on the client.
+        let mut diverged = Vec::new();
+        for update in 0..4 * u64::from(DIVERGENCE_CHECKS) {
+            let remote = slices(&mut server, update, &entities, |entity| entity);
+            let mut reversed = entities.clone();
+            reversed.reverse();
+            let local = slices(&mut client, update, &reversed, |entity| {
+                if entity == 6 {
+                    entity * 2
+                } else {
+                    entity
+                }
+            });
+            assert_eq!(local.group, remote.group);
+            assert_eq!(local.entities, 10);
+            client.hashed(local);
+            if client.compare(&remote) == Some(SliceComparison::Diverged) {
+                diverged.push((update, remote.group));
+            }
+            if update == 3 {
+                assert!(client.covered());
+            }
+        }
+        assert_eq!(diverged, vec![(10, 2)]);
+        let reported = client.diverged().collect::<Vec<_>>();
+        assert_eq!(reported.len(), 1);
+        assert_eq!(reported[0].0, 2);
+        assert_eq!(reported[0].1.mismatched_since, Some(2));
+        assert_eq!(slice_group(6, 4), 2);
+
+        // Slices of another update aren't compared.
+        let stale = SliceChecksum::new(1, 2, 4);
+        assert_eq!(client.compare(&stale), None);
+
+        // Once it agrees again, the group recovers.
+        let mut agreeing = SliceChecksum::new(20, 2, 4);
+        for entity in &entities {
+            agreeing.add(*entity, &entity.to_le_bytes());
+        }
+        client.hashed(agreeing);
+        assert_eq!(client.compare(&agreeing), Some(SliceComparison::Recovered));
+        assert_eq!(client.diverged().count(), 0);
+    }
+}
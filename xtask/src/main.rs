@@ -7,10 +7,30 @@ use structopt::StructOpt;
 // rust-toolchain files in the respective assets/shaders subdirs.
 const RUST_GPU_TOOLCHAIN: &str = "nightly-2022-12-18";
 
+// The benchmark suite, as (package, bench target). Each is a criterion bench
+// over a representative workload, so changes can be compared against a saved
+// baseline.
+const BENCHES: &[(&str, &str)] = &[
+    ("world", "transforms"),
+    ("net_sync_system", "snapshot"),
+    ("ash_renderer_system", "pipeline"),
+    ("obj-parser", "obj_parse"),
+    ("core_executor", "aos_bench"),
+];
+
 #[derive(StructOpt, Debug)]
 enum Command {
     FmtLint,
     BuildShaders,
+    /// Run the benchmark suite.
+    Bench {
+        /// Save the results under this name, to compare against later.
+        #[structopt(long)]
+        save_baseline: Option<String>,
+        /// Compare the results against a saved baseline.
+        #[structopt(long)]
+        baseline: Option<String>,
+    },
 }
 
 #[derive(StructOpt)]
@@ -57,6 +77,13 @@ fn dispatch(cmd: Command) -> Result<(), std::io::Error> {
             build_shaders()?;
             Ok(())
         }
+        Command::Bench {
+            save_baseline,
+            baseline,
+        } => {
+            bench(save_baseline, baseline)?;
+            Ok(())
+        }
     }
 }
 
@@ -90,3 +117,22 @@ fn build_shaders() -> Result<(), std::io::Error> {
     std::env::set_current_dir(project_root_dir)?;
     Ok(())
 }
+
+// Bench targets are run one at a time, as the libtest harness of the other
+// targets doesn't understand criterion's arguments.
+fn bench(save_baseline: Option<String>, baseline: Option<String>) -> Result<(), std::io::Error> {
+    let mut criterion_args = vec![];
+    if let Some(name) = save_baseline {
+        criterion_args.extend(["--save-baseline".to_owned(), name]);
+    }
+    if let Some(name) = baseline {
+        criterion_args.extend(["--baseline".to_owned(), name]);
+    }
+    for (package, bench) in BENCHES {
+        println!("xtask bench {package}/{bench}");
+        let mut args = vec!["bench", "-p", package, "--bench", bench, "--"];
+        args.extend(criterion_args.iter().map(String::as_str));
+        cmd("cargo", args).run()?;
+    }
+    Ok(())
+}